@@ -0,0 +1,125 @@
+/*
+ * Graphs of `netmon collectd` for luci-app-statistics, installed as
+ * /www/luci-static/resources/statistics/rrdtool/definitions/netmon.js
+ */
+
+'use strict';
+'require baseclass';
+
+return baseclass.extend({
+	title: _('Network Monitor'),
+
+	rrdargs: function(graph, host, plugin, plugin_instance, dtype) {
+		if (plugin_instance == '') {
+			var devices = {
+				title: "%H: Devices",
+				vlabel: "Devices",
+				number_format: "%5.0lf",
+				data: {
+					instances: {
+						count: [ "devices", "devices_online" ]
+					},
+					types: [ "count" ],
+					options: {
+						count_devices: { title: "Tracked", noarea: true, overlay: true },
+						count_devices_online: { title: "Online", overlay: true, color: "00ff00" }
+					}
+				}
+			};
+
+			var neighbors = {
+				title: "%H: Neighbor entries",
+				vlabel: "Entries",
+				number_format: "%5.0lf",
+				data: {
+					instances: {
+						count: [
+							"neighbors_reachable", "neighbors_stale", "neighbors_delay",
+							"neighbors_probe", "neighbors_failed", "neighbors_incomplete",
+							"neighbors_permanent", "neighbors_noarp"
+						]
+					},
+					types: [ "count" ],
+					options: {
+						count_neighbors_reachable: { title: "Reachable", color: "00ff00" },
+						count_neighbors_stale: { title: "Stale", color: "ffff00" },
+						count_neighbors_delay: { title: "Delay", color: "ff8000" },
+						count_neighbors_probe: { title: "Probe", color: "ff00ff" },
+						count_neighbors_failed: { title: "Failed", color: "ff0000" },
+						count_neighbors_incomplete: { title: "Incomplete", color: "800000" },
+						count_neighbors_permanent: { title: "Permanent", color: "0000ff" },
+						count_neighbors_noarp: { title: "No ARP", color: "808080" }
+					}
+				}
+			};
+
+			var poll = {
+				title: "%H: Poll duration",
+				vlabel: "Seconds",
+				number_format: "%5.3lf",
+				data: {
+					types: [ "duration" ],
+					options: {
+						duration_poll: { title: "Poll", noarea: true }
+					}
+				}
+			};
+
+			return [ devices, neighbors, poll ];
+		}
+
+		var online = {
+			title: "%H: Presence of %pi",
+			vlabel: "Online",
+			y_min: "0",
+			y_max: "1",
+			number_format: "%1.0lf",
+			data: {
+				types: [ "gauge" ],
+				options: {
+					gauge_online: { title: "Online", color: "00ff00" }
+				}
+			}
+		};
+
+		var traffic = {
+			title: "%H: Traffic of %pi",
+			vlabel: "Bytes/s",
+			number_format: "%5.1lf%sB/s",
+			data: {
+				types: [ "if_octets" ],
+				options: {
+					if_octets__tx: { title: "Sent", total: true, overlay: true, color: "00ff00" },
+					if_octets__rx: { title: "Received", flip: true, total: true, overlay: true, color: "0000ff" }
+				}
+			}
+		};
+
+		var signal = {
+			title: "%H: Signal of %pi",
+			vlabel: "dBm",
+			number_format: "%5.1lf dBm",
+			data: {
+				types: [ "signal_power" ],
+				options: {
+					signal_power: { title: "Signal", noarea: true, color: "0000ff" }
+				}
+			}
+		};
+
+		var bitrate = {
+			title: "%H: Bitrate of %pi",
+			vlabel: "Bit/s",
+			number_format: "%5.1lf%sBit/s",
+			data: {
+				types: [ "bitrate" ],
+				options: {
+					bitrate_rx: { title: "Receive", noarea: true, overlay: true, color: "0000ff" },
+					bitrate_tx: { title: "Transmit", noarea: true, overlay: true, color: "00ff00" }
+				}
+			}
+		};
+
+		return [ online, traffic, signal, bitrate ];
+	}
+});
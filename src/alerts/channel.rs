@@ -0,0 +1,102 @@
+use super::{Alert, Severity};
+use crate::daemon::PollReport;
+use anyhow::Result;
+use log::{error, info, warn};
+
+///
+/// Destination alerts are delivered to, e.g. a chat, a push service, or the
+/// log.
+///
+/// Channels are called on the alert sink's worker thread, so they may block
+/// on the network.
+///
+pub trait Channel: Send {
+  /// Name rules route their alerts by, e.g. "phone".
+  fn name(&self) -> &str;
+
+  ///
+  /// Delivers an alert.
+  ///
+  /// Args:
+  ///  - alert: The alert, along with what's known of its device.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the alert was delivered.
+  ///
+  fn send(&mut self, alert: &Alert) -> Result<()>;
+
+  /// Sees every poll, before its alerts are delivered, e.g. to answer
+  /// queries about the devices. Does nothing by default.
+  fn update(&mut self, _report: &PollReport) {}
+}
+
+/// Channel logging the alerts, at the level of their severity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogChannel {
+  name: String,
+}
+
+impl LogChannel {
+  pub fn new(name: &str) -> Self {
+    LogChannel {
+      name: name.to_string(),
+    }
+  }
+}
+
+impl Channel for LogChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let mac = alert.mac_addr.map(|v| v.to_string()).unwrap_or_default();
+    match alert.severity {
+      Severity::Info => info!(
+        "alert.rule" = alert.rule.as_str(), "alert.channel" = self.name.as_str(), "alert.severity" = "info", "device.mac" = mac.as_str();
+        "Alert '{}': {}", alert.rule, alert.message()
+      ),
+      Severity::Warning => warn!(
+        "alert.rule" = alert.rule.as_str(), "alert.channel" = self.name.as_str(), "alert.severity" = "warning", "device.mac" = mac.as_str();
+        "Alert '{}': {}", alert.rule, alert.message()
+      ),
+      Severity::Critical => error!(
+        "alert.rule" = alert.rule.as_str(), "alert.channel" = self.name.as_str(), "alert.severity" = "critical", "device.mac" = mac.as_str();
+        "Alert '{}': {}", alert.rule, alert.message()
+      ),
+    }
+    Ok(())
+  }
+}
+
+/// What's known of the device of an alert, labelled, e.g. ("Vendor",
+/// "Raspberry Pi Trading Ltd"), for channels to list.
+#[cfg(feature = "notify")]
+pub(super) fn details(alert: &Alert) -> Vec<(&'static str, String)> {
+  let device = &alert.device;
+  [
+    ("MAC", alert.mac_addr.map(|v| v.to_string())),
+    ("IP", alert.ip.as_ref().map(|v| v.to_string())),
+    ("Interface", alert.iface.clone()),
+    ("Vendor", device.vendor.clone()),
+    ("Hostname", device.hostname.clone()),
+    ("Owner", device.owner.clone()),
+    ("Trust", device.trust.map(|v| v.to_string())),
+    ("Ack id", alert.ack_id.map(|v| v.to_string())),
+  ]
+  .into_iter()
+  .filter_map(|(label, value)| Some((label, value?)))
+  .collect()
+}
+
+/// Cuts a text down to a length in characters, ending it with an ellipsis.
+pub(super) fn truncate(text: &str, max_len: usize) -> String {
+  match text.chars().count() > max_len {
+    true => text
+      .chars()
+      .take(max_len.saturating_sub(1))
+      .chain(['…'])
+      .collect(),
+    false => text.to_string(),
+  }
+}
@@ -0,0 +1,174 @@
+use super::channel::{details, truncate};
+use super::webhook::{deliver, DEFAULT_RETRIES};
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::{HttpRequest, DEFAULT_HTTP_TIMEOUT};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Longest title of an embed Discord takes, in characters.
+const MAX_TITLE_LEN: usize = 256;
+
+/// Longest description of an embed Discord takes, in characters.
+const MAX_DESCRIPTION_LEN: usize = 4096;
+
+/// Longest value of an embed's field Discord takes, in characters.
+const MAX_FIELD_LEN: usize = 1024;
+
+/// Longest content of a message Discord takes, in characters.
+const MAX_CONTENT_LEN: usize = 2000;
+
+/*
+  Alerts are POSTed to the channel's webhook as an embed, colored by their
+  severity, along with what's known of their device:
+
+    {"embeds":[{"title":"untrusted guest at night: Living room TV",
+      "description":"Living room TV (dc:a6:32:a3:48:b1) joined br-guest as 192.168.3.5",
+      "color":15158332,
+      "fields":[{"name":"MAC","value":"dc:a6:32:a3:48:b1","inline":true},
+                {"name":"IP","value":"192.168.3.5","inline":true},
+                {"name":"Vendor","value":"Raspberry Pi Trading Ltd","inline":true}],
+      "footer":{"text":"office-ap"},"timestamp":"2026-10-14T23:36:15Z"}]}
+
+  The content template, e.g. "<@&123456789> {{ title }}", adds text above
+  the embed, to mention a role on critical alerts. A webhook posts to a
+  single Discord channel, so rules route their alerts to one of several
+  discord channels, e.g. security alerts and presence chatter apart.
+*/
+
+///
+/// Channel posting the alerts to a Discord channel through one of its
+/// webhooks, as embeds.
+///
+#[derive(Debug, Clone)]
+pub struct DiscordChannel {
+  name: String,
+  url: String,
+  username: Option<String>,
+  avatar_url: Option<String>,
+  content: Option<Template>,
+  retries: u32,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+}
+
+impl DiscordChannel {
+  ///
+  /// Creates a channel.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - url: URL of the webhook, e.g.
+  ///    "https://discord.com/api/webhooks/123456789/abcdef...".
+  ///
+  pub fn new(name: &str, url: &str) -> Self {
+    DiscordChannel {
+      name: name.to_string(),
+      url: url.to_string(),
+      username: None,
+      avatar_url: None,
+      content: None,
+      retries: DEFAULT_RETRIES,
+      timeout: DEFAULT_HTTP_TIMEOUT,
+      ca_file: None,
+    }
+  }
+
+  /// Name the messages are posted under, instead of the webhook's.
+  pub fn username(mut self, username: &str) -> Self {
+    self.username = Some(username.to_string());
+    self
+  }
+
+  /// Avatar the messages are posted with, instead of the webhook's.
+  pub fn avatar_url(mut self, avatar_url: &str) -> Self {
+    self.avatar_url = Some(avatar_url.to_string());
+    self
+  }
+
+  /// Template of the text above the embed, e.g. mentioning a role.
+  pub fn content(mut self, content: Template) -> Self {
+    self.content = Some(content);
+    self
+  }
+
+  /// Retries of a failed delivery.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  /// Longest time a delivery attempt may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify Discord with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// The message of an alert.
+  fn message(&self, alert: &Alert) -> Value {
+    let fields: Vec<Value> = details(alert)
+      .into_iter()
+      .map(|(name, value)| {
+        json!({ "name": name, "value": truncate(&value, MAX_FIELD_LEN), "inline": true })
+      })
+      .collect();
+    let embed = json!({
+      "title": truncate(&alert.title(), MAX_TITLE_LEN),
+      "description": truncate(&alert.message(), MAX_DESCRIPTION_LEN),
+      "color": color(alert.severity),
+      "fields": fields,
+      "footer": { "text": alert.router },
+      "timestamp": humantime::format_rfc3339_seconds(alert.time).to_string(),
+    });
+
+    let mut message = Map::new();
+    message.insert("embeds".to_string(), json!([embed]));
+    if let Some(content) = &self.content {
+      let content = content.render(alert);
+      message.insert(
+        "content".to_string(),
+        json!(truncate(&content, MAX_CONTENT_LEN)),
+      );
+    }
+    if let Some(username) = &self.username {
+      message.insert("username".to_string(), json!(username));
+    }
+    if let Some(avatar_url) = &self.avatar_url {
+      message.insert("avatar_url".to_string(), json!(avatar_url));
+    }
+    Value::Object(message)
+  }
+}
+
+impl Channel for DiscordChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let mut request = HttpRequest::post(&self.url)
+      .header("Content-Type", "application/json")
+      .body(&self.message(alert).to_string())
+      .timeout(self.timeout);
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    deliver(&request, &self.url, self.retries).map(|_| ())
+  }
+}
+
+/// Color of the embeds of a severity, as Discord's RGB integer.
+fn color(severity: Severity) -> u32 {
+  match severity {
+    Severity::Info => 0x3498db,
+    Severity::Warning => 0xf1c40f,
+    Severity::Critical => 0xe74c3c,
+  }
+}
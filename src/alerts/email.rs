@@ -0,0 +1,573 @@
+use super::channel::details;
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::{encode_component, escape};
+use crate::daemon::{hostname, PollReport};
+use crate::neighbors::run_command_input;
+use anyhow::{Error, Result};
+use log::warn;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest time sending a mail may take when none is set.
+pub const DEFAULT_SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest line of an encoded body, as RFC 2045 allows.
+const MAX_LINE_LEN: usize = 76;
+
+/// Most bytes of a subject encoded in one word, keeping its lines short.
+const MAX_WORD_BYTES: usize = 45;
+
+/*
+  Mails are sent by curl, as it sends HTTP requests, along with the message
+  written to a temporary file only root can read:
+
+    url = "smtp://smtp.example.com:587/office-ap"
+    mail-from = "netmon@example.com"
+    mail-rcpt = "admin@example.com"
+    upload-file = "/tmp/netmon-mail-1234-1791953775.eml"
+    user = "netmon@example.com:s3cr3t"
+    ssl-reqd
+
+  Every alert is mailed on its own, in plain text and HTML:
+
+    Subject: [netmon] untrusted guest at night: Living room TV
+
+    Living room TV (dc:a6:32:a3:48:b1) joined br-guest as 192.168.3.5
+
+    Rule: untrusted guest at night
+    Severity: critical
+    Time: 2026-10-14T23:36:15Z
+    MAC: dc:a6:32:a3:48:b1
+    Vendor: Raspberry Pi Trading Ltd
+    Router: office-ap
+
+  except, in digest mode, those no more severe than the digest's severity,
+  which are held until the digest interval passed since the first of them,
+  then mailed together:
+
+    Subject: [netmon] 3 alerts on office-ap
+
+    3 alerts since 2026-10-14T08:00:12Z:
+
+    2026-10-14T08:00:12Z info guest joined: Living room TV
+      Living room TV (dc:a6:32:a3:48:b1) joined br-guest as 192.168.3.5
+    ...
+*/
+
+/// How the connection to the mail server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SmtpTls {
+  /// Upgrades a plain connection with STARTTLS, failing if the server
+  /// can't, on port 587 by default.
+  #[default]
+  Starttls,
+  /// Connects over TLS, on port 465 by default.
+  Tls,
+  /// Sends in the clear, on port 25 by default, e.g. to a relay on the LAN.
+  None,
+}
+
+impl SmtpTls {
+  fn default_port(self) -> u16 {
+    match self {
+      SmtpTls::Starttls => 587,
+      SmtpTls::Tls => 465,
+      SmtpTls::None => 25,
+    }
+  }
+}
+
+///
+/// Channel mailing the alerts through an SMTP server, over curl.
+///
+/// In digest mode, alerts no more severe than the digest's severity are held
+/// and mailed together once in a while, rather than one mail each.
+///
+#[derive(Debug)]
+pub struct EmailChannel {
+  name: String,
+  server: String,
+  tls: SmtpTls,
+  credentials: Option<(String, String)>,
+  from: String,
+  to: Vec<String>,
+  subject: Option<Template>,
+  body: Option<Template>,
+  html: Option<Template>,
+  digest: Option<(Duration, Severity)>,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+  /// Alerts held for the next digest.
+  pending: Vec<Alert>,
+  /// Time the next digest is mailed at, once alerts are held.
+  digest_at: Option<SystemTime>,
+}
+
+impl EmailChannel {
+  ///
+  /// Creates a channel.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - server: Mail server, e.g. "smtp.example.com" or
+  ///    "smtp.example.com:2525", on the port of its TLS mode if none is given.
+  ///  - from: Sender of the mails, e.g. "netmon <netmon@example.com>".
+  ///  - to: Recipients of the mails.
+  ///
+  pub fn new(name: &str, server: &str, from: &str, to: &[String]) -> Self {
+    EmailChannel {
+      name: name.to_string(),
+      server: server.to_string(),
+      tls: SmtpTls::default(),
+      credentials: None,
+      from: from.to_string(),
+      to: to.to_vec(),
+      subject: None,
+      body: None,
+      html: None,
+      digest: None,
+      timeout: DEFAULT_SMTP_TIMEOUT,
+      ca_file: None,
+      pending: Vec::new(),
+      digest_at: None,
+    }
+  }
+
+  /// How the connection to the server is secured.
+  pub fn tls(mut self, tls: SmtpTls) -> Self {
+    self.tls = tls;
+    self
+  }
+
+  /// Logs into the server.
+  pub fn credentials(mut self, username: &str, password: &str) -> Self {
+    self.credentials = Some((username.to_string(), password.to_string()));
+    self
+  }
+
+  /// Template of the subjects.
+  pub fn subject(mut self, subject: Template) -> Self {
+    self.subject = Some(subject);
+    self
+  }
+
+  /// Template of the plain text bodies.
+  pub fn body(mut self, body: Template) -> Self {
+    self.body = Some(body);
+    self
+  }
+
+  /// Template of the HTML bodies, whose fields are escaped for HTML.
+  pub fn html(mut self, html: Template) -> Self {
+    self.html = Some(html);
+    self
+  }
+
+  ///
+  /// Holds the alerts no more severe than a severity, mailing them together
+  /// once an interval passed since the first of them.
+  ///
+  /// Args:
+  ///  - interval: Longest time an alert is held for.
+  ///  - severity: Most severe alerts held, e.g. info.
+  ///
+  pub fn digest(mut self, interval: Duration, severity: Severity) -> Self {
+    self.digest = Some((interval, severity));
+    self
+  }
+
+  /// Longest time sending a mail may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify the server with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// URL of the server, e.g. "smtp://smtp.example.com:587/office-ap".
+  fn url(&self) -> String {
+    let scheme = match self.tls {
+      SmtpTls::Tls => "smtps",
+      SmtpTls::Starttls | SmtpTls::None => "smtp",
+    };
+    let has_port = self
+      .server
+      .rsplit_once(':')
+      .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let server = match has_port {
+      true => self.server.clone(),
+      false => format!("{}:{}", self.server, self.tls.default_port()),
+    };
+    // curl greets the server with the path, else the uploaded file's name.
+    format!("{}://{}/{}", scheme, server, encode_component(&hostname()))
+  }
+
+  ///
+  /// Mails a message to the recipients.
+  ///
+  /// Args:
+  ///  - subject: Subject of the mail.
+  ///  - text: Plain text body.
+  ///  - html: HTML body, sent along with the plain text one.
+  ///
+  fn mail(&self, subject: &str, text: &str, html: Option<&str>) -> Result<()> {
+    let message = self.message(subject, text, html);
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|v| v.as_nanos())
+      .unwrap_or_default();
+    let path =
+      std::env::temp_dir().join(format!("netmon-mail-{}-{}.eml", std::process::id(), nanos));
+    std::fs::OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .mode(0o600)
+      .open(&path)
+      .and_then(|mut file| file.write_all(message.as_bytes()))
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", path.display(), e)))?;
+
+    let mut config = String::new();
+    let mut option = |name: &str, value: &str| {
+      let _ = writeln!(config, "{} = \"{}\"", name, escape(value));
+    };
+    option("url", &self.url());
+    option("mail-from", address(&self.from));
+    for to in &self.to {
+      option("mail-rcpt", address(to));
+    }
+    option("upload-file", &path.to_string_lossy());
+    if let Some((username, password)) = &self.credentials {
+      option("user", &format!("{}:{}", username, password));
+    }
+    if let Some(ca_file) = &self.ca_file {
+      option("cacert", &ca_file.to_string_lossy());
+    }
+    option("max-time", &self.timeout.as_secs_f64().to_string());
+    option("proto", "=smtp,smtps");
+    if self.tls == SmtpTls::Starttls {
+      config.push_str("ssl-reqd\n");
+    }
+    config.push_str("silent\nshow-error\n");
+
+    let sent = run_command_input(
+      "curl",
+      &["--config", "-"],
+      config.as_bytes(),
+      // Leaves curl the time to fail on its own, with a better message.
+      self.timeout + Duration::from_secs(1),
+    );
+    let _ = std::fs::remove_file(&path);
+    sent
+      .map(|_| ())
+      .map_err(|e| Error::msg(format!("Failed to mail through {}: {}", self.server, e)))
+  }
+
+  /// The message of a mail, with its headers, as sent.
+  fn message(&self, subject: &str, text: &str, html: Option<&str>) -> String {
+    let now = SystemTime::now();
+    let nanos = now
+      .duration_since(UNIX_EPOCH)
+      .map(|v| v.as_nanos())
+      .unwrap_or_default();
+    let domain = address(&self.from)
+      .rsplit_once('@')
+      .map_or("localhost", |(_, domain)| domain);
+    let mut message = String::new();
+    let mut header = |name: &str, value: &str| {
+      let _ = write!(message, "{}: {}\r\n", name, value);
+    };
+    header("Date", &format_date(now));
+    header("From", &self.from);
+    header("To", &self.to.join(", "));
+    header("Subject", &encode_header(subject));
+    header(
+      "Message-ID",
+      &format!("<{}.{}@{}>", nanos, std::process::id(), domain),
+    );
+    header("MIME-Version", "1.0");
+    let part = |content_type: &str, body: &str| {
+      format!(
+        "Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+        content_type,
+        encode_body(body)
+      )
+    };
+    match html {
+      Some(html) => {
+        let boundary = format!("netmon-{:x}", nanos);
+        let _ = write!(
+          message,
+          "Content-Type: multipart/alternative; boundary=\"{b}\"\r\n\r\n\
+           --{b}\r\n{}--{b}\r\n{}--{b}--\r\n",
+          part("text/plain", text),
+          part("text/html", html),
+          b = boundary
+        );
+      }
+      None => message.push_str(&part("text/plain", text)),
+    }
+    message
+  }
+
+  /// Mails the alerts held for the digest.
+  fn send_digest(&mut self) -> Result<()> {
+    let alerts = std::mem::take(&mut self.pending);
+    self.digest_at = None;
+    let Some(first) = alerts.first() else {
+      return Ok(());
+    };
+    let count = match alerts.len() {
+      1 => "1 alert".to_string(),
+      n => format!("{} alerts", n),
+    };
+    let since = humantime::format_rfc3339_seconds(first.time).to_string();
+    let subject = format!("[netmon] {} on {}", count, first.router);
+
+    let mut text = format!("{} since {}:\n\n", count, since);
+    let mut html = format!(
+      "<p>{} since {}:</p>\n<table cellpadding=\"4\">\n",
+      count, since
+    );
+    for alert in &alerts {
+      let time = humantime::format_rfc3339_seconds(alert.time).to_string();
+      let _ = write!(
+        text,
+        "{} {} {}\n  {}\n\n",
+        time,
+        alert.severity,
+        alert.title(),
+        alert.message()
+      );
+      let _ = writeln!(
+        html,
+        "<tr><td>{}</td><td>{}</td><td><b>{}</b><br>{}</td></tr>",
+        time,
+        alert.severity,
+        escape_html(&alert.title()),
+        escape_html(&alert.message())
+      );
+    }
+    html.push_str("</table>\n");
+    self.mail(&subject, &text, Some(&html))
+  }
+}
+
+impl Channel for EmailChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    if let Some((interval, severity)) = self.digest {
+      if alert.severity <= severity {
+        self.pending.push(alert.clone());
+        self.digest_at.get_or_insert(alert.time + interval);
+        return Ok(());
+      }
+    }
+    let subject = match &self.subject {
+      Some(subject) => subject.render(alert),
+      None => format!("[netmon] {}", alert.title()),
+    };
+    let text = match &self.body {
+      Some(body) => body.render(alert),
+      None => default_text(alert),
+    };
+    let html = match (&self.html, &self.body) {
+      (Some(html), _) => Some(html.render_escaped(alert, escape_html)),
+      (None, None) => Some(default_html(alert)),
+      // A plain text template is sent as is.
+      (None, Some(_)) => None,
+    };
+    self.mail(&subject, &text, html.as_deref())
+  }
+
+  fn update(&mut self, report: &PollReport) {
+    let Some(digest_at) = self.digest_at else {
+      return;
+    };
+    if report.started_at < digest_at {
+      return;
+    }
+    let pending = self.pending.clone();
+    if let Err(err) = self.send_digest() {
+      warn!("Failed to mail the digest of '{}': {}", self.name, err);
+      // Tried again after another interval, rather than every poll.
+      self.pending = pending;
+      if let Some((interval, _)) = self.digest {
+        self.digest_at = Some(report.started_at + interval);
+      }
+    }
+  }
+}
+
+impl Drop for EmailChannel {
+  fn drop(&mut self) {
+    // Mailed early rather than lost, on reloads and shutdown.
+    if let Err(err) = self.send_digest() {
+      warn!("Failed to mail the digest of '{}': {}", self.name, err);
+    }
+  }
+}
+
+/// Labelled details of an alert, rule and time first, router last.
+fn summary(alert: &Alert) -> Vec<(&'static str, String)> {
+  let mut summary = vec![
+    ("Rule", alert.rule.clone()),
+    ("Severity", alert.severity.to_string()),
+    (
+      "Time",
+      humantime::format_rfc3339_seconds(alert.time).to_string(),
+    ),
+  ];
+  summary.extend(details(alert));
+  summary.push(("Router", alert.router.clone()));
+  summary
+}
+
+/// The plain text body of an alert.
+fn default_text(alert: &Alert) -> String {
+  let mut text = format!("{}\n\n", alert.message());
+  for (label, value) in summary(alert) {
+    let _ = writeln!(text, "{}: {}", label, value);
+  }
+  text
+}
+
+/// The HTML body of an alert.
+fn default_html(alert: &Alert) -> String {
+  let mut html = format!(
+    "<p>{}</p>\n<table cellpadding=\"4\">\n",
+    escape_html(&alert.message())
+  );
+  for (label, value) in summary(alert) {
+    let _ = writeln!(
+      html,
+      "<tr><th align=\"left\">{}</th><td>{}</td></tr>",
+      label,
+      escape_html(&value)
+    );
+  }
+  html.push_str("</table>\n");
+  html
+}
+
+/// Address of a mailbox, e.g. "netmon@example.com" of
+/// "netmon <netmon@example.com>".
+fn address(mailbox: &str) -> &str {
+  match (mailbox.rfind('<'), mailbox.rfind('>')) {
+    (Some(start), Some(end)) if start < end => mailbox[start + 1..end].trim(),
+    _ => mailbox.trim(),
+  }
+}
+
+/// Time as a mail's Date header has it, e.g. "Wed, 14 Oct 2026 23:36:15 +0000".
+fn format_date(time: SystemTime) -> String {
+  const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+  const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+  ];
+  let secs = time
+    .duration_since(UNIX_EPOCH)
+    .map(|v| v.as_secs() as libc::time_t)
+    .unwrap_or_default();
+  // SAFETY: tm is plain data, which gmtime_r fills in.
+  let tm = unsafe {
+    let mut tm: libc::tm = std::mem::zeroed();
+    libc::gmtime_r(&secs, &mut tm);
+    tm
+  };
+  format!(
+    "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+    DAYS[tm.tm_wday.clamp(0, 6) as usize],
+    tm.tm_mday,
+    MONTHS[tm.tm_mon.clamp(0, 11) as usize],
+    tm.tm_year + 1900,
+    tm.tm_hour,
+    tm.tm_min,
+    tm.tm_sec
+  )
+}
+
+/// A header's value, on one line, and encoded as RFC 2047 words unless
+/// it's short ASCII.
+fn encode_header(value: &str) -> String {
+  // Names of devices come off the network, and mustn't add headers.
+  let value: String = value
+    .chars()
+    .map(|c| if c.is_control() { ' ' } else { c })
+    .collect();
+  if value.is_ascii() && value.len() <= MAX_LINE_LEN - 10 {
+    return value;
+  }
+  let mut words = Vec::new();
+  let mut word = String::new();
+  for c in value.chars() {
+    if word.len() + c.len_utf8() > MAX_WORD_BYTES {
+      words.push(std::mem::take(&mut word));
+    }
+    word.push(c);
+  }
+  words.push(word);
+  words
+    .iter()
+    .map(|v| format!("=?UTF-8?B?{}?=", base64(v.as_bytes())))
+    .collect::<Vec<_>>()
+    .join("\r\n ")
+}
+
+/// A body in base64, its lines ending with CRLF as mails have them.
+fn encode_body(body: &str) -> String {
+  let body = body.replace("\r\n", "\n").replace('\n', "\r\n");
+  let encoded = base64(body.as_bytes());
+  let mut lines = String::with_capacity(encoded.len() + encoded.len() / MAX_LINE_LEN * 2 + 2);
+  for line in encoded.as_bytes().chunks(MAX_LINE_LEN) {
+    lines.push_str(std::str::from_utf8(line).unwrap_or_default());
+    lines.push_str("\r\n");
+  }
+  lines
+}
+
+/// Bytes in standard base64, padded.
+fn base64(bytes: &[u8]) -> String {
+  const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+  for chunk in bytes.chunks(3) {
+    let n = chunk
+      .iter()
+      .enumerate()
+      .fold(0u32, |acc, (i, v)| acc | (*v as u32) << (16 - 8 * i));
+    for i in 0..4 {
+      match i <= chunk.len() {
+        true => encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+        false => encoded.push('='),
+      }
+    }
+  }
+  encoded
+}
+
+/// Escapes a value for HTML, so it's shown as is.
+pub fn escape_html(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
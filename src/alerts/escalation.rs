@@ -0,0 +1,179 @@
+use super::Alert;
+use log::{info, warn};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Alerts escalating at once, the oldest being dropped for newer ones.
+pub const MAX_ESCALATING: usize = 100;
+
+/*
+  An escalating alert is delivered to the channels of its first step, then
+  to those of every later step once it went unacknowledged for the step's
+  delay since it was raised:
+
+    10:00  push            alert 17 raised, acknowledged by its id
+    10:10  mail, pushover  unacknowledged for 10m
+    10:12                  POST /api/alerts/17/ack, or /ack_17 to the bot
+
+  Acknowledging an alert ends its escalation. Escalations are kept in
+  memory, across reloads but not restarts, and their ids start over at 1
+  with the daemon.
+*/
+
+///
+/// Channels an alert is delivered to at first, then after delays without
+/// being acknowledged.
+///
+/// ```
+/// use openwrt_netmon::alerts::Escalation;
+/// use std::time::Duration;
+///
+/// let escalation = Escalation::new(vec!["push".to_string()])
+///   .then(Duration::from_secs(600), vec!["mail".to_string()]);
+/// assert_eq!(escalation.first(), ["push"]);
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Escalation {
+  /// Delay since the alert was raised, and channels, of every step.
+  steps: Vec<(Duration, Vec<String>)>,
+}
+
+impl Escalation {
+  /// Creates an escalation, delivering to channels at first.
+  pub fn new(channels: Vec<String>) -> Self {
+    Escalation {
+      steps: vec![(Duration::ZERO, channels)],
+    }
+  }
+
+  /// Adds a step, delivering to channels once an alert went unacknowledged
+  /// for a delay since it was raised.
+  pub fn then(mut self, after: Duration, channels: Vec<String>) -> Self {
+    self.steps.push((after, channels));
+    self
+  }
+
+  /// Channels alerts are delivered to at first.
+  pub fn first(&self) -> &[String] {
+    &self.steps[0].1
+  }
+}
+
+/// An alert escalating, through the steps following `next`.
+#[derive(Debug, Clone)]
+struct Pending {
+  alert: Alert,
+  escalation: Escalation,
+  next: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+  last_id: u32,
+  pending: Vec<Pending>,
+}
+
+///
+/// Alerts escalating until acknowledged, shared by the alert sink with what
+/// acknowledges them, e.g. the health server's API or a Telegram bot.
+/// Cloning them shares their state.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Escalations {
+  inner: Arc<Mutex<State>>,
+}
+
+impl Escalations {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Starts escalating an alert, returning the id acknowledging it.
+  pub(super) fn start(&self, alert: &Alert, escalation: &Escalation) -> u32 {
+    let mut state = self.inner.lock().unwrap();
+    state.last_id = state.last_id.wrapping_add(1).max(1);
+    let id = state.last_id;
+    if state.pending.len() == MAX_ESCALATING {
+      let dropped = state.pending.remove(0);
+      warn!(
+        "No longer escalating alert {} of '{}', as {} alerts escalate at most",
+        dropped.alert.ack_id.unwrap_or_default(),
+        dropped.alert.rule,
+        MAX_ESCALATING
+      );
+    }
+    let mut alert = alert.clone();
+    alert.ack_id = Some(id);
+    state.pending.push(Pending {
+      alert,
+      escalation: escalation.clone(),
+      next: 1,
+    });
+    id
+  }
+
+  /// The alerts due for a step at a time, routed to its channels.
+  pub(super) fn due(&self, time: SystemTime) -> Vec<Alert> {
+    let mut state = self.inner.lock().unwrap();
+    let mut due = Vec::new();
+    for pending in &mut state.pending {
+      let steps = &pending.escalation.steps;
+      while let Some((after, channels)) = steps.get(pending.next) {
+        if time < pending.alert.time + *after {
+          break;
+        }
+        info!(
+          "Escalating alert {} of '{}' to {}, unacknowledged for {}",
+          pending.alert.ack_id.unwrap_or_default(),
+          pending.alert.rule,
+          channels.join(", "),
+          humantime::format_duration(*after)
+        );
+        let mut alert = pending.alert.clone();
+        alert.channels = channels.clone();
+        due.push(alert);
+        pending.next += 1;
+      }
+    }
+    state.pending.retain(|v| v.next < v.escalation.steps.len());
+    due
+  }
+
+  ///
+  /// Acknowledges an alert, ending its escalation.
+  ///
+  /// Args:
+  ///  - id: Id of the alert.
+  ///  - by: What acknowledged it, for the log, e.g. "over HTTP".
+  ///
+  /// Returns:
+  ///  Whether the alert was escalating.
+  ///
+  pub fn acknowledge(&self, id: u32, by: &str) -> bool {
+    let mut state = self.inner.lock().unwrap();
+    let Some(index) = state
+      .pending
+      .iter()
+      .position(|v| v.alert.ack_id == Some(id))
+    else {
+      return false;
+    };
+    let pending = state.pending.remove(index);
+    info!(
+      "Alert {} of '{}' acknowledged {}",
+      id, pending.alert.rule, by
+    );
+    true
+  }
+
+  /// Ids and titles of the alerts escalating, oldest first.
+  pub fn pending(&self) -> Vec<(u32, String)> {
+    let state = self.inner.lock().unwrap();
+    state
+      .pending
+      .iter()
+      .map(|v| (v.alert.ack_id.unwrap_or_default(), v.alert.title()))
+      .collect()
+  }
+}
@@ -0,0 +1,152 @@
+use super::webhook::{deliver, DEFAULT_RETRIES};
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::{encode_component, HttpRequest, DEFAULT_HTTP_TIMEOUT};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/*
+  Alerts are POSTed to the server's /message, authenticated by the token of
+  an application created in Gotify, with the priority of their severity:
+
+    X-Gotify-Key: AqJj0bOQg6q.XmD
+
+    {"title":"untrusted guest at night: Living room TV",
+     "message":"Living room TV (dc:a6:32:a3:48:b1) joined br-guest as 192.168.3.5",
+     "priority":8,
+     "extras":{"client::notification":{"click":{"url":"https://grafana.lan/..."}}}}
+
+  Priorities go from 0 to 10. The Android app shows no notification for 0,
+  silent ones up to 3, plays a sound from 4, and also vibrates from 8: info
+  alerts are sent at 2, warnings at 5, and critical alerts at 8, unless
+  mapped otherwise.
+*/
+
+///
+/// Channel posting the alerts to a Gotify server, e.g. one on the LAN, so
+/// they don't depend on any cloud service.
+///
+#[derive(Debug, Clone)]
+pub struct GotifyChannel {
+  name: String,
+  server: String,
+  token: String,
+  priorities: BTreeMap<Severity, u8>,
+  click: Option<Template>,
+  template: Option<Template>,
+  retries: u32,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+}
+
+impl GotifyChannel {
+  ///
+  /// Creates a channel.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - server: URL of the server, e.g. "http://10.0.0.2:8080".
+  ///  - token: Token of the application the alerts are posted as.
+  ///
+  pub fn new(name: &str, server: &str, token: &str) -> Self {
+    GotifyChannel {
+      name: name.to_string(),
+      server: server.trim_end_matches('/').to_string(),
+      token: token.to_string(),
+      priorities: BTreeMap::new(),
+      click: None,
+      template: None,
+      retries: DEFAULT_RETRIES,
+      timeout: DEFAULT_HTTP_TIMEOUT,
+      ca_file: None,
+    }
+  }
+
+  /// Priority of the alerts of a severity, from 0 to 10.
+  pub fn priority(mut self, severity: Severity, priority: u8) -> Self {
+    self.priorities.insert(severity, priority);
+    self
+  }
+
+  /// Template of the URL opened when tapping a notification, whose fields
+  /// are percent-encoded.
+  pub fn click(mut self, click: Template) -> Self {
+    self.click = Some(click);
+    self
+  }
+
+  /// Template of the messages.
+  pub fn template(mut self, template: Template) -> Self {
+    self.template = Some(template);
+    self
+  }
+
+  /// Retries of a failed delivery.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  /// Longest time a delivery attempt may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify the server with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// The message posting an alert.
+  fn message(&self, alert: &Alert) -> Value {
+    let priority = self
+      .priorities
+      .get(&alert.severity)
+      .copied()
+      .unwrap_or(match alert.severity {
+        Severity::Info => 2,
+        Severity::Warning => 5,
+        Severity::Critical => 8,
+      });
+    let text = match &self.template {
+      Some(template) => template.render(alert),
+      None => alert.message(),
+    };
+
+    let mut message = Map::new();
+    message.insert("title".to_string(), json!(alert.title()));
+    message.insert("message".to_string(), json!(text));
+    message.insert("priority".to_string(), json!(priority));
+    if let Some(click) = &self.click {
+      let url = click.render_escaped(alert, encode_component);
+      message.insert(
+        "extras".to_string(),
+        json!({ "client::notification": { "click": { "url": url } } }),
+      );
+    }
+    Value::Object(message)
+  }
+}
+
+impl Channel for GotifyChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let url = format!("{}/message", self.server);
+    let mut request = HttpRequest::post(&url)
+      .header("Content-Type", "application/json")
+      .header("X-Gotify-Key", &self.token)
+      .body(&self.message(alert).to_string())
+      .timeout(self.timeout);
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    deliver(&request, &url, self.retries).map(|_| ())
+  }
+}
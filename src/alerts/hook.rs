@@ -0,0 +1,67 @@
+use super::template::{field_value, json_string, FIELDS};
+use super::Alert;
+use crate::neighbors::run_command_env;
+use anyhow::Result;
+use log::debug;
+use std::time::Duration;
+
+/// Longest time an on_event hook may run for, before it's killed along with
+/// what it started.
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/*
+  A rule's on_event hook is run by sh on every alert the rule raises, with
+  the fields of the alert in NETMON_* variables, set if the alert has them:
+
+    NETMON_RULE=phone home
+    NETMON_EVENT=joined
+    NETMON_MAC=3c:22:fb:10:02:7e
+    NETMON_IP=192.168.1.23
+    NETMON_NAME=Alice's phone
+
+  and as a JSON object on its stdin, fields the alert doesn't have being
+  null:
+
+    {"rule":"phone home","severity":"warning","event":"joined",...,"ack_id":null}
+
+  Hooks run before quiet hours and rate limits, which are for channels, and
+  summaries of collapsed alerts don't run them again.
+*/
+
+///
+/// Runs an on_event hook on an alert, failing if it exits with an error or
+/// times out.
+///
+/// Args:
+///  - command: Command line run by sh, e.g. "/etc/netmon/lights.sh on".
+///  - alert: Alert the hook is run on.
+///
+pub(super) fn run(command: &str, alert: &Alert) -> Result<()> {
+  let mut env = Vec::new();
+  let mut json = Vec::new();
+  for field in FIELDS {
+    let value = field_value(alert, field);
+    json.push(format!(
+      "{}:{}",
+      json_string(field),
+      value
+        .as_deref()
+        .map(json_string)
+        .as_deref()
+        .unwrap_or("null")
+    ));
+    if let Some(value) = value {
+      env.push((format!("NETMON_{}", field.to_uppercase()), value));
+    }
+  }
+  let input = format!("{{{}}}\n", json.join(","));
+  let stdout = run_command_env("sh", &["-c", command], &env, input.as_bytes(), HOOK_TIMEOUT)?;
+  if !stdout.trim().is_empty() {
+    debug!(
+      "The on_event hook of '{}' printed: {}",
+      alert.rule,
+      stdout.trim()
+    );
+  }
+  Ok(())
+}
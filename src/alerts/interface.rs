@@ -0,0 +1,158 @@
+use super::hook::HOOK_TIMEOUT;
+use super::{Change, DeviceFacts};
+use crate::neighbors::{run_command_env, MacAddr};
+use crate::registry::{DeviceCategory, TrustLevel};
+use anyhow::Result;
+use log::debug;
+
+/*
+  An interface's policy lists the devices allowed on it, e.g. a guest
+  network or an IoT VLAN, VLANs being interfaces of their own (br-lan.20):
+
+    interface   br-guest
+    trust       guest        devices the registry gives these trust levels,
+    categories  iot          or these categories,
+    devices     3c:22:...    or these MAC addresses
+
+  The devices on the interface it allows none of are policy_violation
+  changes, raised once until they leave the interface. Devices missing from
+  the registry, or without a trust level, are untrusted, as for rules, and a
+  policy allowing nothing allows no device at all.
+
+  A policy's enforce hook is run by sh on every violation, whether a rule
+  raises an alert on it or not, with NETMON_MAC, NETMON_IP, NETMON_IFACE,
+  and NETMON_NAME set if known:
+
+    enforce = "/etc/netmon/block.sh"
+*/
+
+///
+/// Devices allowed on an interface, the others violating it.
+///
+/// ```
+/// use openwrt_netmon::alerts::{DeviceFacts, InterfacePolicy};
+/// use openwrt_netmon::registry::TrustLevel;
+///
+/// let policy = InterfacePolicy::new("br-guest").trust(vec![TrustLevel::Guest]);
+/// let mac_addr = "3c:22:fb:10:02:7e".parse()?;
+/// let guest = DeviceFacts {
+///   trust: Some(TrustLevel::Guest),
+///   ..Default::default()
+/// };
+/// assert!(policy.allows(&mac_addr, &guest));
+/// assert!(!policy.allows(&mac_addr, &DeviceFacts::default()));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfacePolicy {
+  iface: String,
+  trust: Vec<TrustLevel>,
+  categories: Vec<DeviceCategory>,
+  devices: Vec<MacAddr>,
+  enforce: Option<String>,
+}
+
+impl InterfacePolicy {
+  /// Creates the policy of an interface, allowing no device until told which.
+  pub fn new(iface: &str) -> Self {
+    InterfacePolicy {
+      iface: iface.to_string(),
+      trust: Vec::new(),
+      categories: Vec::new(),
+      devices: Vec::new(),
+      enforce: None,
+    }
+  }
+
+  pub fn iface(&self) -> &str {
+    &self.iface
+  }
+
+  /// Allows the devices of these trust levels, devices without one being
+  /// untrusted.
+  pub fn trust(mut self, trust: Vec<TrustLevel>) -> Self {
+    self.trust = trust;
+    self
+  }
+
+  /// Allows the devices of these categories.
+  pub fn categories(mut self, categories: Vec<DeviceCategory>) -> Self {
+    self.categories = categories;
+    self
+  }
+
+  /// Allows these devices, whatever the registry says of them.
+  pub fn devices(mut self, devices: Vec<MacAddr>) -> Self {
+    self.devices = devices;
+    self
+  }
+
+  /// Runs a command line by sh on every violation, e.g.
+  /// "/etc/netmon/block.sh".
+  pub fn enforce(mut self, command: &str) -> Self {
+    self.enforce = Some(command.to_string());
+    self
+  }
+
+  ///
+  /// Whether the policy allows a device on its interface.
+  ///
+  /// Args:
+  ///  - mac_addr: MAC address of the device.
+  ///  - device: What's known of the device, see DeviceFacts.
+  ///
+  pub fn allows(&self, mac_addr: &MacAddr, device: &DeviceFacts) -> bool {
+    self.devices.contains(mac_addr)
+      || self
+        .trust
+        .contains(&device.trust.unwrap_or(TrustLevel::Untrusted))
+      || device
+        .category
+        .is_some_and(|v| self.categories.contains(&v))
+  }
+
+  /// What the policy allows, e.g. "guest,iot", "nothing" if it allows no
+  /// device.
+  pub(super) fn allowed(&self) -> String {
+    let allowed: Vec<String> = self
+      .trust
+      .iter()
+      .map(|v| v.to_string())
+      .chain(self.categories.iter().map(|v| v.to_string()))
+      .chain(self.devices.iter().map(|v| v.to_string()))
+      .collect();
+    match allowed.is_empty() {
+      true => "nothing".to_string(),
+      false => allowed.join(","),
+    }
+  }
+
+  ///
+  /// Runs the enforce hook, if any, on a violation of the policy, failing if
+  /// it exits with an error or times out.
+  ///
+  pub(super) fn enforce_on(&self, change: &Change) -> Result<()> {
+    let Some(command) = &self.enforce else {
+      return Ok(());
+    };
+    let env: Vec<(String, String)> = [
+      ("NETMON_MAC", change.mac_addr.map(|v| v.to_string())),
+      ("NETMON_IP", change.ip.as_ref().map(|v| v.to_string())),
+      ("NETMON_IFACE", change.iface.clone()),
+      ("NETMON_NAME", change.device.name.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some((name.to_string(), value?)))
+    .collect();
+    let stdout = run_command_env("sh", &["-c", command], &env, b"", HOOK_TIMEOUT)?;
+    if !stdout.trim().is_empty() {
+      debug!(
+        "The enforce hook of the {} policy printed: {}",
+        self.iface,
+        stdout.trim()
+      );
+    }
+    Ok(())
+  }
+}
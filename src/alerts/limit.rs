@@ -0,0 +1,200 @@
+use super::{Alert, Rule, Severity, Summary};
+use crate::neighbors::{MacAddr, ScopedIpAddr};
+use log::debug;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/*
+  Alerts a rule raises again about the same device within its dedup window
+  are collapsed: the first is delivered, and the others are summed up by the
+  last of them once the window is over:
+
+    poll    10:00   10:05   10:20   ...  11:00
+    left    deliver collapse collapse    "... left eth0, 14 times in the last 1h"
+
+  A channel's rate limit delivers at most `max` alerts per period, the
+  period starting with the first alert delivered. Those over it are held
+  back, other than critical ones, and summed up once the period is over.
+*/
+
+/// Identical alerts, by rule and device, the IP address standing in for the
+/// MAC address of alerts without one.
+type AlertKey = (String, Option<MacAddr>, Option<ScopedIpAddr>);
+
+/// Repeats of an alert within the dedup window of its rule.
+#[derive(Debug, Clone)]
+struct Repeats {
+  /// End of the window.
+  until: SystemTime,
+  window: Duration,
+  /// Times the alert was raised within the window, the first included.
+  count: u32,
+  /// Last repeat, once repeated.
+  last: Option<Alert>,
+}
+
+/// Collapses the repeated alerts of rules with a dedup window.
+#[derive(Debug, Default)]
+pub(super) struct Dedup {
+  repeats: HashMap<AlertKey, Repeats>,
+}
+
+impl Dedup {
+  ///
+  /// Collapses the alerts of a poll raised again within their window.
+  ///
+  /// Args:
+  ///  - alerts: Alerts of the poll.
+  ///  - rules: Rules that raised them.
+  ///  - time: Time of the poll.
+  ///
+  /// Returns:
+  ///  The summaries of the windows over, then the alerts to deliver.
+  ///
+  pub(super) fn collapse(
+    &mut self,
+    alerts: Vec<Alert>,
+    rules: &[Rule],
+    time: SystemTime,
+  ) -> Vec<Alert> {
+    let mut delivered = self.summaries(|v| v.until <= time);
+    for alert in alerts {
+      let window = rules
+        .iter()
+        .find(|v| v.name() == alert.rule)
+        .and_then(|v| v.dedup);
+      let Some(window) = window else {
+        delivered.push(alert);
+        continue;
+      };
+      let ip = alert.mac_addr.is_none().then(|| alert.ip.clone()).flatten();
+      let key = (alert.rule.clone(), alert.mac_addr, ip);
+      match self.repeats.get_mut(&key) {
+        Some(repeats) => {
+          debug!(
+            "Collapsing a repeat of '{}': {}",
+            alert.rule,
+            alert.message()
+          );
+          repeats.count += 1;
+          repeats.last = Some(alert);
+        }
+        None => {
+          self.repeats.insert(
+            key,
+            Repeats {
+              until: time + window,
+              window,
+              count: 1,
+              last: None,
+            },
+          );
+          delivered.push(alert);
+        }
+      }
+    }
+    delivered
+  }
+
+  /// Ends every window, returning the summaries of the alerts they
+  /// collapsed, e.g. once the daemon stops.
+  pub(super) fn close(&mut self) -> Vec<Alert> {
+    self.summaries(|_| true)
+  }
+
+  /// Ends the windows given, returning the summaries of the alerts they
+  /// collapsed, oldest first.
+  fn summaries(&mut self, over: impl Fn(&Repeats) -> bool) -> Vec<Alert> {
+    let over: Vec<AlertKey> = self
+      .repeats
+      .iter()
+      .filter(|(_, v)| over(v))
+      .map(|(k, _)| k.clone())
+      .collect();
+    let mut summaries = Vec::new();
+    for key in over {
+      let Some(repeats) = self.repeats.remove(&key) else {
+        continue;
+      };
+      if let Some(mut last) = repeats.last {
+        last.summary = Some(Summary::Repeats {
+          count: repeats.count,
+          window: repeats.window,
+        });
+        summaries.push(last);
+      }
+    }
+    summaries.sort_by_key(|v| v.time);
+    summaries
+  }
+}
+
+/// Rate limit of a channel, holding back the alerts over it.
+#[derive(Debug, Clone)]
+pub(super) struct RateLimiter {
+  max: u32,
+  per: Duration,
+  /// End of the current period, once an alert was delivered.
+  until: Option<SystemTime>,
+  /// Alerts delivered during the period.
+  sent: u32,
+  /// Alerts held back during the period.
+  over: u32,
+  /// Last alert held back, summing them up.
+  last: Option<Alert>,
+}
+
+impl RateLimiter {
+  pub(super) fn new(max: u32, per: Duration) -> Self {
+    RateLimiter {
+      max,
+      per,
+      until: None,
+      sent: 0,
+      over: 0,
+      last: None,
+    }
+  }
+
+  /// Ends the period if it's over, returning the summary of the alerts held
+  /// back during it, if any.
+  pub(super) fn expire(&mut self, time: SystemTime) -> Option<Alert> {
+    if self.until.is_none_or(|v| time < v) {
+      return None;
+    }
+    self.close()
+  }
+
+  /// Ends the period, returning the summary of the alerts held back during
+  /// it, if any, e.g. once the daemon stops.
+  pub(super) fn close(&mut self) -> Option<Alert> {
+    self.until = None;
+    self.sent = 0;
+    let count = std::mem::take(&mut self.over);
+    self.last.take().map(|mut last| {
+      last.summary = Some(Summary::RateLimited {
+        count,
+        period: self.per,
+      });
+      last
+    })
+  }
+
+  /// Whether another rate limit limits as many alerts per period.
+  pub(super) fn is_like(&self, other: &RateLimiter) -> bool {
+    self.max == other.max && self.per == other.per
+  }
+
+  /// Counts an alert delivered at a time, returning whether it's within the
+  /// limit. Critical alerts are always within it.
+  pub(super) fn admit(&mut self, alert: &Alert, time: SystemTime) -> bool {
+    self.until.get_or_insert(time + self.per);
+    if self.sent < self.max || alert.severity == Severity::Critical {
+      self.sent += 1;
+      return true;
+    }
+    self.over += 1;
+    self.last = Some(alert.clone());
+    false
+  }
+}
@@ -0,0 +1,449 @@
+use super::{transaction_id, Client};
+use crate::daemon::http::encode_component;
+use anyhow::{Error, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use vodozemac::megolm::{self, GroupSession};
+use vodozemac::olm::{self, Account, AccountPickle};
+use vodozemac::{base64_encode, Curve25519PublicKey, Ed25519PublicKey, Ed25519Signature};
+
+/// Algorithm of the to-device messages sharing the room keys.
+const OLM_ALGORITHM: &str = "m.olm.v1.curve25519-aes-sha2";
+
+/// Algorithm of the room messages.
+const MEGOLM_ALGORITHM: &str = "m.megolm.v1.aes-sha2";
+
+/// Time a room key encrypts for when the room doesn't say, a week.
+const DEFAULT_ROTATION_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Messages a room key encrypts when the room doesn't say.
+const DEFAULT_ROTATION_MESSAGES: u64 = 100;
+
+/*
+  The store holds the device's Olm account, its secret keys included, so
+  it's only readable by its owner:
+
+    {"user_id":"@netmon:matrix.org","device_id":"NETMONXYZ",
+     "account":{"signing_key":...,"diffie_hellman_key":...,...},
+     "published":true}
+
+  Before every message, the room's state tells whether it's encrypted, and
+  who's in it. The keys of their devices are queried, and the room key is
+  sent to those it wasn't yet, with an Olm session each, made from one of
+  their one-time keys. The room key is replaced once it's as old, or has
+  encrypted as many messages, as the room's settings allow, and as soon as a
+  device it was sent to is gone, so that device can't read what follows.
+*/
+
+/// The device of the access token, as kept in the store.
+#[derive(Serialize, Deserialize)]
+struct Store {
+  user_id: String,
+  device_id: String,
+  account: AccountPickle,
+  /// Whether the homeserver has the device's keys.
+  published: bool,
+}
+
+/// The device of the access token, encrypting the messages.
+struct Device {
+  user_id: String,
+  device_id: String,
+  account: Account,
+}
+
+/// A device of a member of the room, by user, device id, and Curve25519 and
+/// Ed25519 keys.
+type Recipient = (String, String, String, String);
+
+/// The room key messages are encrypted with.
+struct RoomKey {
+  session: GroupSession,
+  created: Instant,
+  /// Devices the key was sent to.
+  recipients: BTreeSet<Recipient>,
+}
+
+///
+/// End-to-end encryption of the messages of a room, as a device that only
+/// sends: it never decrypts, so it has no one-time keys of its own.
+///
+pub(super) struct Encryption {
+  store: PathBuf,
+  device: Option<Device>,
+  room_key: Option<RoomKey>,
+}
+
+impl Encryption {
+  pub(super) fn new(store: &Path) -> Self {
+    Encryption {
+      store: store.to_path_buf(),
+      device: None,
+      room_key: None,
+    }
+  }
+
+  ///
+  /// Encrypts the content of a message, sending the room key to the devices
+  /// of the room lacking it.
+  ///
+  /// Args:
+  ///  - client: API of the homeserver.
+  ///  - room_id: Room the message is sent to.
+  ///  - content: Content of the m.room.message event.
+  ///
+  /// Returns:
+  ///  Result of the content of the m.room.encrypted event, None if the room
+  ///  isn't encrypted.
+  ///
+  pub(super) fn encrypt(
+    &mut self,
+    client: &Client,
+    room_id: &str,
+    content: &Value,
+  ) -> Result<Option<Value>> {
+    let path = format!("/rooms/{}/state", encode_component(room_id));
+    let state = client.call("GET", &path, None)?;
+    let events = state.as_array().map(Vec::as_slice).unwrap_or_default();
+    let Some(settings) = events
+      .iter()
+      .find(|v| v["type"] == "m.room.encryption" && v["state_key"] == "")
+      .map(|v| &v["content"])
+    else {
+      return Ok(None);
+    };
+    if settings["algorithm"] != MEGOLM_ALGORITHM {
+      return Err(Error::msg(format!(
+        "{} is encrypted with {}, only {} is supported",
+        room_id, settings["algorithm"], MEGOLM_ALGORITHM
+      )));
+    }
+    // Invited users may read the messages sent before they join.
+    let members: BTreeSet<&str> = events
+      .iter()
+      .filter(|v| v["type"] == "m.room.member")
+      .filter(|v| matches!(v["content"]["membership"].as_str(), Some("join" | "invite")))
+      .filter_map(|v| v["state_key"].as_str())
+      .collect();
+
+    let device = match self.device.take() {
+      Some(device) => device,
+      None => self.load(client)?,
+    };
+    let device = &*self.device.insert(device);
+    let devices = query_devices(client, device, &members)?;
+
+    let rotation_period = settings["rotation_period_ms"]
+      .as_u64()
+      .map(Duration::from_millis)
+      .unwrap_or(DEFAULT_ROTATION_PERIOD);
+    let rotation_messages = settings["rotation_period_msgs"]
+      .as_u64()
+      .unwrap_or(DEFAULT_ROTATION_MESSAGES);
+    let expired = self.room_key.as_ref().is_some_and(|v| {
+      v.created.elapsed() >= rotation_period
+        || u64::from(v.session.message_index()) >= rotation_messages
+        || !v.recipients.is_subset(&devices)
+    });
+    if expired {
+      info!("Replacing the Matrix room key of {}", room_id);
+      self.room_key = None;
+    }
+    let room_key = self.room_key.get_or_insert_with(|| RoomKey {
+      session: GroupSession::new(megolm::SessionConfig::version_1()),
+      created: Instant::now(),
+      recipients: BTreeSet::new(),
+    });
+    let missing: Vec<&Recipient> = devices.difference(&room_key.recipients).collect();
+    if !missing.is_empty() {
+      let sent = share_room_key(client, device, room_id, &room_key.session, &missing)?;
+      room_key.recipients.extend(sent);
+    }
+
+    let plaintext = json!({ "type": "m.room.message", "content": content, "room_id": room_id });
+    let message = room_key.session.encrypt(plaintext.to_string());
+    Ok(Some(json!({
+      "algorithm": MEGOLM_ALGORITHM,
+      "sender_key": device.account.curve25519_key().to_base64(),
+      "ciphertext": message.to_base64(),
+      "session_id": room_key.session.session_id(),
+      "device_id": device.device_id,
+    })))
+  }
+
+  /// Loads the device of the access token off the store, making it keys and
+  /// uploading them the first time.
+  fn load(&self, client: &Client) -> Result<Device> {
+    let whoami = client.call("GET", "/account/whoami", None)?;
+    let (Some(user_id), Some(device_id)) =
+      (whoami["user_id"].as_str(), whoami["device_id"].as_str())
+    else {
+      return Err(Error::msg(format!(
+        "{} has no device for the access token, which can't encrypt",
+        client.homeserver
+      )));
+    };
+
+    let store = match std::fs::read_to_string(&self.store) {
+      Ok(content) => Some(
+        serde_json::from_str::<Store>(&content)
+          .map_err(|e| Error::msg(format!("Failed to parse {}: {}", self.store.display(), e)))?,
+      ),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+      Err(e) => {
+        return Err(Error::msg(format!(
+          "Failed to read {}: {}",
+          self.store.display(),
+          e
+        )))
+      }
+    };
+    let store = match store {
+      Some(store) if store.user_id == user_id && store.device_id == device_id => store,
+      _ => {
+        info!(
+          "Making the Matrix keys of device {} of {}",
+          device_id, user_id
+        );
+        Store {
+          user_id: user_id.to_string(),
+          device_id: device_id.to_string(),
+          account: Account::new().pickle(),
+          published: false,
+        }
+      }
+    };
+    let published = store.published;
+    let device = Device {
+      user_id: store.user_id,
+      device_id: store.device_id,
+      account: Account::from_pickle(store.account),
+    };
+    if !published {
+      // Saved first, so keys the homeserver may have got aren't lost.
+      self.save(&device, false)?;
+      upload_keys(client, &device)?;
+      self.save(&device, true)?;
+    }
+    Ok(device)
+  }
+
+  /// Writes the device to the store, atomically.
+  fn save(&self, device: &Device, published: bool) -> Result<()> {
+    let store = Store {
+      user_id: device.user_id.clone(),
+      device_id: device.device_id.clone(),
+      account: device.account.pickle(),
+      published,
+    };
+    let content = serde_json::to_string(&store)?;
+    let tmp_path = self.store.with_extension("tmp");
+    std::fs::OpenOptions::new()
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .mode(0o600)
+      .open(&tmp_path)
+      .and_then(|mut file| file.write_all(content.as_bytes()))
+      .and_then(|_| std::fs::rename(&tmp_path, &self.store))
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", self.store.display(), e)))
+  }
+}
+
+/// Uploads the identity keys of the device, signed by it.
+fn upload_keys(client: &Client, device: &Device) -> Result<()> {
+  let mut keys = json!({
+    "user_id": device.user_id,
+    "device_id": device.device_id,
+    "algorithms": [OLM_ALGORITHM, MEGOLM_ALGORITHM],
+    "keys": {
+      format!("curve25519:{}", device.device_id): device.account.curve25519_key().to_base64(),
+      format!("ed25519:{}", device.device_id): device.account.ed25519_key().to_base64(),
+    },
+  });
+  let signature = device.account.sign(canonical_json(&keys));
+  keys["signatures"] = json!({
+    &device.user_id: { format!("ed25519:{}", device.device_id): signature.to_base64() },
+  });
+  client.call(
+    "POST",
+    "/keys/upload",
+    Some(&json!({ "device_keys": keys })),
+  )?;
+  Ok(())
+}
+
+/// The devices of the members of the room, but the encrypting one, whose
+/// keys are signed by themselves.
+fn query_devices(
+  client: &Client,
+  device: &Device,
+  members: &BTreeSet<&str>,
+) -> Result<BTreeSet<Recipient>> {
+  let users: Map<String, Value> = members.iter().map(|v| (v.to_string(), json!([]))).collect();
+  let response = client.call(
+    "POST",
+    "/keys/query",
+    Some(&json!({ "device_keys": users })),
+  )?;
+
+  let mut devices = BTreeSet::new();
+  let users = response["device_keys"].as_object().into_iter().flatten();
+  for (user_id, user_devices) in users {
+    for (device_id, keys) in user_devices.as_object().into_iter().flatten() {
+      if *user_id == device.user_id && *device_id == device.device_id {
+        continue;
+      }
+      let curve25519 = keys["keys"][format!("curve25519:{}", device_id)].as_str();
+      let ed25519 = ed25519_key(keys, device_id).filter(|v| is_signed(keys, user_id, device_id, v));
+      match (curve25519, ed25519) {
+        (Some(curve25519), Some(ed25519))
+          if keys["user_id"] == json!(user_id) && keys["device_id"] == json!(device_id) =>
+        {
+          devices.insert((
+            user_id.clone(),
+            device_id.clone(),
+            curve25519.to_string(),
+            ed25519.to_base64(),
+          ));
+        }
+        _ => warn!(
+          "Ignoring Matrix device {} of {}, whose keys aren't signed",
+          device_id, user_id
+        ),
+      }
+    }
+  }
+  Ok(devices)
+}
+
+///
+/// Sends the room key to devices, each encrypted with an Olm session made
+/// from one of their one-time keys.
+///
+/// Returns:
+///  Result of the devices the key was sent to, leaving out those out of
+///  one-time keys, which are tried again with the next message.
+///
+fn share_room_key(
+  client: &Client,
+  device: &Device,
+  room_id: &str,
+  session: &GroupSession,
+  recipients: &[&Recipient],
+) -> Result<Vec<Recipient>> {
+  let mut claims = Map::new();
+  for (user_id, device_id, ..) in recipients {
+    let devices = claims.entry(user_id.clone()).or_insert_with(|| json!({}));
+    devices[device_id] = json!("signed_curve25519");
+  }
+  let claimed = client.call(
+    "POST",
+    "/keys/claim",
+    Some(&json!({ "one_time_keys": claims })),
+  )?;
+
+  let room_key = json!({
+    "algorithm": MEGOLM_ALGORITHM,
+    "room_id": room_id,
+    "session_id": session.session_id(),
+    "session_key": session.session_key().to_base64(),
+  });
+  let sender_key = device.account.curve25519_key().to_base64();
+  let mut messages = Map::new();
+  let mut sent = Vec::new();
+  for recipient in recipients {
+    let (user_id, device_id, curve25519, ed25519) = recipient;
+    let Some(one_time_key) = claimed["one_time_keys"][user_id][device_id]
+      .as_object()
+      .and_then(|v| v.values().next())
+    else {
+      warn!(
+        "Matrix device {} of {} is out of one-time keys, and won't read the alerts",
+        device_id, user_id
+      );
+      continue;
+    };
+    let signed = Ed25519PublicKey::from_base64(ed25519)
+      .is_ok_and(|v| is_signed(one_time_key, user_id, device_id, &v));
+    let keys = one_time_key["key"]
+      .as_str()
+      .and_then(|v| Curve25519PublicKey::from_base64(v).ok())
+      .zip(Curve25519PublicKey::from_base64(curve25519).ok());
+    let Some((one_time_key, identity_key)) = keys.filter(|_| signed) else {
+      warn!(
+        "Ignoring Matrix device {} of {}, whose one-time key isn't signed",
+        device_id, user_id
+      );
+      continue;
+    };
+
+    let payload = json!({
+      "type": "m.room_key",
+      "content": room_key,
+      "sender": device.user_id,
+      "sender_device": device.device_id,
+      "keys": { "ed25519": device.account.ed25519_key().to_base64() },
+      "recipient": user_id,
+      "recipient_keys": { "ed25519": ed25519 },
+    });
+    let mut olm = device.account.create_outbound_session(
+      olm::SessionConfig::version_1(),
+      identity_key,
+      one_time_key,
+    );
+    let (message_type, ciphertext) = olm.encrypt(payload.to_string()).to_parts();
+    let user_messages = messages.entry(user_id.clone()).or_insert_with(|| json!({}));
+    user_messages[device_id] = json!({
+      "algorithm": OLM_ALGORITHM,
+      "sender_key": sender_key,
+      "ciphertext": {
+        curve25519: { "type": message_type, "body": base64_encode(ciphertext) },
+      },
+    });
+    sent.push((*recipient).clone());
+  }
+
+  if !messages.is_empty() {
+    let path = format!("/sendToDevice/m.room.encrypted/{}", transaction_id());
+    client.call("PUT", &path, Some(&json!({ "messages": messages })))?;
+  }
+  Ok(sent)
+}
+
+/// The Ed25519 key of a device, off its keys.
+fn ed25519_key(keys: &Value, device_id: &str) -> Option<Ed25519PublicKey> {
+  keys["keys"][format!("ed25519:{}", device_id)]
+    .as_str()
+    .and_then(|v| Ed25519PublicKey::from_base64(v).ok())
+}
+
+/// Whether an object is signed by the Ed25519 key of a device.
+fn is_signed(object: &Value, user_id: &str, device_id: &str, key: &Ed25519PublicKey) -> bool {
+  let signature = object["signatures"][user_id][format!("ed25519:{}", device_id)]
+    .as_str()
+    .and_then(|v| Ed25519Signature::from_base64(v).ok());
+  let Some(signature) = signature else {
+    return false;
+  };
+  let mut signed = object.clone();
+  if let Some(object) = signed.as_object_mut() {
+    object.remove("signatures");
+    object.remove("unsigned");
+  }
+  key
+    .verify(canonical_json(&signed).as_bytes(), &signature)
+    .is_ok()
+}
+
+/// The canonical JSON of a value, whose signature Matrix checks: compact,
+/// with the keys of objects sorted, as serde_json maps keep them.
+fn canonical_json(value: &Value) -> String {
+  value.to_string()
+}
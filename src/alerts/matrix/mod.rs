@@ -0,0 +1,254 @@
+use super::channel::details;
+use super::email::escape_html;
+use super::webhook::{deliver, DEFAULT_RETRIES};
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::{encode_component, HttpRequest, DEFAULT_HTTP_TIMEOUT};
+use anyhow::{Error, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "matrix-e2ee")]
+mod crypto;
+
+/// File keeping the keys of the encrypting device when none is configured.
+pub const DEFAULT_STORE_PATH: &str = "/etc/netmon/matrix.json";
+
+/*
+  Alerts are sent as m.text messages, with an HTML body colored by their
+  severity, and a plain text one for the clients without HTML:
+
+    PUT /_matrix/client/v3/rooms/!qporfwt:matrix.org/send/m.room.message/netmon.1791953775.1
+
+    {"msgtype":"m.text",
+     "body":"🚨 new device: dc:a6:32:a3:48:b1\nNew device dc:a6:32:a3:48:b1 on ...",
+     "format":"org.matrix.custom.html",
+     "formatted_body":"<p><font data-mx-color=\"#e74c3c\"><strong>🚨 new device: ..."}
+
+  The transaction id makes a retried delivery idempotent: the homeserver
+  posts a message once, however many times it's PUT.
+
+  With encryption, the message is encrypted with the room's Megolm session
+  and sent as m.room.encrypted instead, once the session's key was sent to
+  the devices of the room's members, each encrypted with Olm. The device of
+  the access token uploads its identity keys the first time, and keeps them
+  in the store, which must outlive reboots: clients refuse a device whose
+  keys changed, so one that lost them needs a new access token, which logs
+  in a new device.
+*/
+
+/// Counter making the transaction ids of the process unique.
+static TRANSACTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Client-server API of the homeserver, as the user of an access token.
+#[derive(Debug, Clone)]
+struct Client {
+  homeserver: String,
+  access_token: String,
+  retries: u32,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+}
+
+impl Client {
+  ///
+  /// Calls an endpoint of the API.
+  ///
+  /// Args:
+  ///  - method: Method of the request, e.g. "PUT".
+  ///  - path: Path of the endpoint under /_matrix/client/v3, e.g.
+  ///    "/keys/query".
+  ///  - body: Body of the request, if any.
+  ///
+  /// Returns:
+  ///  Result of the response's JSON.
+  ///
+  fn call(&self, method: &'static str, path: &str, body: Option<&Value>) -> Result<Value> {
+    let url = format!("{}/_matrix/client/v3{}", self.homeserver, path);
+    let mut request = HttpRequest::new(method, &url)
+      .header("Authorization", &format!("Bearer {}", self.access_token))
+      .timeout(self.timeout);
+    if let Some(body) = body {
+      request = request
+        .header("Content-Type", "application/json")
+        .body(&body.to_string());
+    }
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    let response = deliver(&request, &url, self.retries)?;
+    serde_json::from_str(&response.body)
+      .map_err(|e| Error::msg(format!("{} answered invalid JSON: {}", self.homeserver, e)))
+  }
+
+  /// Sends an event to a room.
+  fn send(&self, room_id: &str, event_type: &str, content: &Value) -> Result<()> {
+    let path = format!(
+      "/rooms/{}/send/{}/{}",
+      encode_component(room_id),
+      event_type,
+      transaction_id()
+    );
+    self.call("PUT", &path, Some(content)).map(|_| ())
+  }
+}
+
+/// A transaction id no other request of the process has.
+fn transaction_id() -> String {
+  let time = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|v| v.as_secs())
+    .unwrap_or_default();
+  format!(
+    "netmon.{}.{}",
+    time,
+    TRANSACTIONS.fetch_add(1, Ordering::Relaxed)
+  )
+}
+
+///
+/// Channel posting the alerts to a Matrix room, as the user of an access
+/// token, end-to-end encrypted with the matrix-e2ee feature.
+///
+pub struct MatrixChannel {
+  name: String,
+  client: Client,
+  room_id: String,
+  template: Option<Template>,
+  #[cfg(feature = "matrix-e2ee")]
+  encryption: Option<crypto::Encryption>,
+}
+
+impl MatrixChannel {
+  ///
+  /// Creates a channel.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - homeserver: URL of the homeserver, e.g. "https://matrix.org".
+  ///  - access_token: Access token of the user posting the alerts, who
+  ///    joined the room.
+  ///  - room_id: Id of the room, e.g. "!qporfwt:matrix.org".
+  ///
+  pub fn new(name: &str, homeserver: &str, access_token: &str, room_id: &str) -> Self {
+    MatrixChannel {
+      name: name.to_string(),
+      client: Client {
+        homeserver: homeserver.trim_end_matches('/').to_string(),
+        access_token: access_token.to_string(),
+        retries: DEFAULT_RETRIES,
+        timeout: DEFAULT_HTTP_TIMEOUT,
+        ca_file: None,
+      },
+      room_id: room_id.to_string(),
+      template: None,
+      #[cfg(feature = "matrix-e2ee")]
+      encryption: None,
+    }
+  }
+
+  /// Template of the messages, sent as plain text rather than formatted.
+  pub fn template(mut self, template: Template) -> Self {
+    self.template = Some(template);
+    self
+  }
+
+  ///
+  /// Encrypts the alerts posted to an encrypted room.
+  ///
+  /// Args:
+  ///  - store: File keeping the keys of the access token's device, which
+  ///    must outlive reboots.
+  ///
+  #[cfg(feature = "matrix-e2ee")]
+  pub fn encryption(mut self, store: &Path) -> Self {
+    self.encryption = Some(crypto::Encryption::new(store));
+    self
+  }
+
+  /// Retries of a failed delivery.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.client.retries = retries;
+    self
+  }
+
+  /// Longest time a delivery attempt may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.client.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify the homeserver with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.client.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// The content of the message of an alert.
+  fn content(&self, alert: &Alert) -> Value {
+    if let Some(template) = &self.template {
+      return json!({ "msgtype": "m.text", "body": template.render(alert) });
+    }
+    let (icon, color) = match alert.severity {
+      Severity::Info => ("ℹ️", "#3498db"),
+      Severity::Warning => ("⚠️", "#f1c40f"),
+      Severity::Critical => ("🚨", "#e74c3c"),
+    };
+    let mut body = format!("{} {}\n{}\n", icon, alert.title(), alert.message());
+    let mut html = format!(
+      "<p><font data-mx-color=\"{}\"><strong>{} {}</strong></font><br>{}</p><ul>",
+      color,
+      icon,
+      escape_html(&alert.title()),
+      escape_html(&alert.message())
+    );
+    let details = details(alert)
+      .into_iter()
+      .chain([("Router", alert.router.clone())]);
+    for (label, value) in details {
+      body.push_str(&format!("\n{}: {}", label, value));
+      html.push_str(&format!(
+        "<li><strong>{}:</strong> {}</li>",
+        label,
+        escape_html(&value)
+      ));
+    }
+    html.push_str("</ul>");
+    json!({
+      "msgtype": "m.text",
+      "body": body,
+      "format": "org.matrix.custom.html",
+      "formatted_body": html,
+    })
+  }
+}
+
+impl std::fmt::Debug for MatrixChannel {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("MatrixChannel")
+      .field("name", &self.name)
+      .field("homeserver", &self.client.homeserver)
+      .field("room_id", &self.room_id)
+      .finish_non_exhaustive()
+  }
+}
+
+impl Channel for MatrixChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let content = self.content(alert);
+    #[cfg(feature = "matrix-e2ee")]
+    if let Some(encryption) = &mut self.encryption {
+      if let Some(encrypted) = encryption.encrypt(&self.client, &self.room_id, &content)? {
+        return self
+          .client
+          .send(&self.room_id, "m.room.encrypted", &encrypted);
+      }
+    }
+    self.client.send(&self.room_id, "m.room.message", &content)
+  }
+}
@@ -0,0 +1,1530 @@
+use crate::daemon::{hostname, PollReport, Sink};
+use crate::dhcp::rogue::RogueDhcpWatch;
+use crate::neighbors::ra::RogueRaWatch;
+use crate::neighbors::route::default_gateways;
+use crate::neighbors::{MacAddr, NudState, ScopedIpAddr};
+use crate::registry::{AlertPolicy, DeviceCategory, TrustLevel};
+use crate::storage::format_state;
+use anyhow::{Error, Result};
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub mod channel;
+#[cfg(feature = "notify")]
+pub mod discord;
+#[cfg(feature = "notify")]
+pub mod email;
+pub mod escalation;
+#[cfg(feature = "notify")]
+pub mod gotify;
+pub mod hook;
+pub mod interface;
+mod limit;
+#[cfg(feature = "notify")]
+pub mod matrix;
+#[cfg(feature = "notify")]
+pub mod ntfy;
+#[cfg(feature = "notify")]
+pub mod pushover;
+pub mod rule;
+pub mod seen;
+#[cfg(feature = "notify")]
+pub mod signal;
+#[cfg(feature = "notify")]
+pub mod slack;
+mod spoof;
+pub mod state;
+#[cfg(feature = "notify")]
+pub mod telegram;
+pub mod template;
+#[cfg(feature = "notify")]
+pub mod webhook;
+pub mod window;
+
+pub use channel::{Channel, LogChannel};
+#[cfg(feature = "notify")]
+pub use discord::DiscordChannel;
+#[cfg(feature = "notify")]
+pub use email::EmailChannel;
+pub use escalation::{Escalation, Escalations};
+#[cfg(feature = "notify")]
+pub use gotify::GotifyChannel;
+pub use interface::InterfacePolicy;
+#[cfg(feature = "notify")]
+pub use matrix::MatrixChannel;
+#[cfg(feature = "notify")]
+pub use ntfy::NtfyChannel;
+#[cfg(feature = "notify")]
+pub use pushover::PushoverChannel;
+pub use rule::Rule;
+pub use seen::SeenDevices;
+#[cfg(feature = "notify")]
+pub use signal::SignalChannel;
+#[cfg(feature = "notify")]
+pub use slack::SlackChannel;
+pub use state::AlertState;
+#[cfg(feature = "notify")]
+pub use telegram::TelegramChannel;
+pub use template::Template;
+#[cfg(feature = "notify")]
+pub use webhook::WebhookChannel;
+pub use window::{LocalTime, TimeOfDay, TimeWindow, Weekday};
+
+/*
+  Every change of a poll is matched against the rules, each match raising
+  an alert delivered to the rule's channels, or to every channel:
+
+    poll -> changes -> rules -> alerts -> channels
+
+  An alert carries what's known of its device as of the poll, the registry
+  filling in for devices that left:
+
+    rule      untrusted guest at night
+    severity  critical
+    event     joined
+    device    dc:a6:32:a3:48:b1 "Living room TV" (Raspberry Pi) on br-guest
+    message   Living room TV (dc:a6:32:a3:48:b1) joined br-guest as 192.168.3.5
+
+  The registry's policies go before the rules: devices muted for an event
+  match no rule of it, and the alerts of critical devices are critical
+  whatever their rule's severity.
+
+  Devices never seen before, neither in the seen devices nor in the
+  registry, are new_device changes, once the learning period following the
+  first device ever seen is over.
+
+  Devices gone (missing from the table, FAILED, or INCOMPLETE, or STALE for
+  longer than the rule's stale_timeout) are offline changes once they have
+  been gone for the rule's offline_after, raised once until they're back, as
+  returned changes:
+
+    poll     09:00  09:01  09:02  ...  09:06  09:07
+    NAS      REACH  STALE  gone   ...  gone   REACH
+    offline                            alert         (offline_after = 5m)
+    returned                                  alert
+
+  Alerts raised again within their rule's dedup window are collapsed, then
+  summed up (see limit.rs), and channels deliver at most as many alerts as
+  their rate limit allows.
+
+  Alerts short of critical are held during the quiet hours of their rule or
+  of a channel, and delivered once both are over, a poll closing them:
+
+    poll      22:59    23:00 ... 06:59  07:00
+    joined    deliver  hold      hold   (quiet 23:00 to 07:00)
+    held                                deliver both, oldest first
+
+  Rules with an on_event hook run it on their alerts (see hook.rs), which
+  are delivered to no channel unless the rule lists some.
+
+  Alerts of rules with an escalation are given an id, and delivered to the
+  channels of its later steps until acknowledged by it (see escalation.rs).
+*/
+
+/// Alerts a channel holds during quiet hours, the oldest being dropped for
+/// newer ones.
+pub const MAX_HELD_ALERTS: usize = 100;
+
+/// Time a rogue DHCP server or router has to go quiet for before it's
+/// raised again.
+pub const ROGUE_QUIET: Duration = Duration::from_secs(3600);
+
+/// Change between polls a rule fires on, named as
+/// registry::policy::ALERT_EVENTS lists them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AlertEvent {
+  Joined,
+  Left,
+  IpChanged,
+  MacChanged,
+  StateChanged,
+  /// A device never seen before appeared.
+  NewDevice,
+  /// A device has been gone for a while.
+  Offline,
+  /// A device is back after being gone for a while.
+  Returned,
+  /// An address answered from another MAC address while bound to one, or a
+  /// MAC address claimed a gateway's address.
+  ArpSpoofing,
+  /// A known device appeared on an interface it doesn't live on.
+  WrongInterface,
+  /// A DHCP server other than the router's own offered an address.
+  RogueDhcp,
+  /// A router other than the router itself advertised, or advertised an
+  /// unexpected prefix or DNS server.
+  RogueRa,
+  /// A device appeared on an interface whose policy doesn't allow it.
+  PolicyViolation,
+}
+
+impl AlertEvent {
+  pub fn name(self) -> &'static str {
+    match self {
+      AlertEvent::Joined => "joined",
+      AlertEvent::Left => "left",
+      AlertEvent::IpChanged => "ip_changed",
+      AlertEvent::MacChanged => "mac_changed",
+      AlertEvent::StateChanged => "state_changed",
+      AlertEvent::NewDevice => "new_device",
+      AlertEvent::Offline => "offline",
+      AlertEvent::Returned => "returned",
+      AlertEvent::ArpSpoofing => "arp_spoofing",
+      AlertEvent::WrongInterface => "wrong_interface",
+      AlertEvent::RogueDhcp => "rogue_dhcp",
+      AlertEvent::RogueRa => "rogue_ra",
+      AlertEvent::PolicyViolation => "policy_violation",
+    }
+  }
+}
+
+impl fmt::Display for AlertEvent {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// How urgent an alert is, which channels may map onto their priorities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Severity {
+  Info,
+  #[default]
+  Warning,
+  Critical,
+}
+
+impl Severity {
+  pub fn name(self) -> &'static str {
+    match self {
+      Severity::Info => "info",
+      Severity::Warning => "warning",
+      Severity::Critical => "critical",
+    }
+  }
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// What's known of a device as of a poll.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceFacts {
+  /// Best known name, see PollReport::name_of.
+  pub name: Option<String>,
+  /// Hostname, from DNS or the DHCP lease.
+  pub hostname: Option<String>,
+  /// Manufacturer, according to the OUI database.
+  pub vendor: Option<String>,
+  pub owner: Option<String>,
+  pub category: Option<DeviceCategory>,
+  pub trust: Option<TrustLevel>,
+  /// How the alerts about the device depart from the rules, as registered.
+  pub policy: Option<AlertPolicy>,
+}
+
+/// A change of a poll, as rules are matched against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Change {
+  event: AlertEvent,
+  mac_addr: Option<MacAddr>,
+  ip: Option<ScopedIpAddr>,
+  iface: Option<String>,
+  /// Interface the device was on before, for devices returned.
+  old_iface: Option<String>,
+  old_value: Option<String>,
+  new_value: Option<String>,
+  /// States of the neighbor, for state changes.
+  old_state: Option<NudState>,
+  new_state: Option<NudState>,
+  /// Time the device was last seen at, for devices gone.
+  last_seen: Option<SystemTime>,
+  device: DeviceFacts,
+}
+
+/// An alert raised by a rule, ready to be delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+  /// Name of the rule that raised it.
+  pub rule: String,
+  pub severity: Severity,
+  pub event: AlertEvent,
+  /// Time of the poll the change was seen by.
+  pub time: SystemTime,
+  /// Hostname of the router.
+  pub router: String,
+  pub mac_addr: Option<MacAddr>,
+  pub ip: Option<ScopedIpAddr>,
+  pub iface: Option<String>,
+  /// Interface the device was on before, for devices returned.
+  pub old_iface: Option<String>,
+  /// Value before the change, as the history has it, e.g. the addresses of
+  /// a device that left.
+  pub old_value: Option<String>,
+  /// Value after the change, e.g. the addresses of a device that joined.
+  pub new_value: Option<String>,
+  /// Time the device was last seen at, for devices gone.
+  pub last_seen: Option<SystemTime>,
+  pub device: DeviceFacts,
+  /// Channels the alert is delivered to, every channel if empty.
+  pub channels: Vec<String>,
+  /// Alerts the alert sums up, the last of them, rather than standing for
+  /// itself.
+  pub summary: Option<Summary>,
+  /// Id acknowledging the alert by, for alerts escalating until
+  /// acknowledged.
+  pub ack_id: Option<u32>,
+}
+
+/// Alerts collapsed into one, rather than delivered one by one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Summary {
+  /// The rule raised the alert about the device `count` times within its
+  /// dedup window.
+  Repeats { count: u32, window: Duration },
+  /// `count` alerts went over the channel's rate limit within its period.
+  RateLimited { count: u32, period: Duration },
+}
+
+impl Summary {
+  /// Alerts summed up.
+  pub fn count(&self) -> u32 {
+    match self {
+      Summary::Repeats { count, .. } | Summary::RateLimited { count, .. } => *count,
+    }
+  }
+}
+
+impl Alert {
+  /// The device's name, then hostname, then MAC address, then IP address.
+  pub fn label(&self) -> String {
+    let name = self.device.name.as_ref().or(self.device.hostname.as_ref());
+    match (name, self.mac_addr, &self.ip) {
+      (Some(name), _, _) => name.clone(),
+      (None, Some(mac_addr), _) => mac_addr.to_string(),
+      (None, None, Some(ip)) => ip.to_string(),
+      (None, None, None) => "unknown device".to_string(),
+    }
+  }
+
+  /// Time the device was gone for, e.g. "2h 5m 3s", for devices gone.
+  pub fn downtime(&self) -> Option<String> {
+    self.last_seen.map(|v| format_elapsed(v, self.time))
+  }
+
+  /// Short title of the alert, e.g. for a notification's subject.
+  pub fn title(&self) -> String {
+    format!("{}: {}", self.rule, self.label())
+  }
+
+  /// Sentence describing the change, or the alerts summed up.
+  pub fn message(&self) -> String {
+    let message = self.change_message();
+    match self.summary {
+      Some(Summary::Repeats { count, window }) => format!(
+        "{}, {} times in the last {}",
+        message,
+        count,
+        humantime::format_duration(window)
+      ),
+      Some(Summary::RateLimited { count, period }) => format!(
+        "{} alerts over the rate limit in the last {}, the last: {}",
+        count,
+        humantime::format_duration(period),
+        message
+      ),
+      None => message,
+    }
+  }
+
+  /// Sentence describing the change.
+  fn change_message(&self) -> String {
+    let mut who = self.label();
+    if let Some(mac_addr) = self.mac_addr.filter(|v| v.to_string() != who) {
+      who = format!("{} ({})", who, mac_addr);
+    }
+    let iface = self.iface.as_deref().unwrap_or("the network");
+    let value = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".to_string());
+    match self.event {
+      AlertEvent::Joined => match &self.new_value {
+        Some(ips) => format!("{} joined {} as {}", who, iface, ips),
+        None => format!("{} joined {}", who, iface),
+      },
+      AlertEvent::Left => format!("{} left {}", who, iface),
+      AlertEvent::IpChanged => format!(
+        "{} changed addresses from {} to {}",
+        who,
+        value(&self.old_value),
+        value(&self.new_value)
+      ),
+      AlertEvent::MacChanged => format!(
+        "{} moved from {} to {}",
+        self
+          .ip
+          .as_ref()
+          .map(|v| v.to_string())
+          .unwrap_or_else(|| who.clone()),
+        value(&self.old_value),
+        value(&self.new_value)
+      ),
+      AlertEvent::StateChanged => format!(
+        "{}{} went from {} to {}",
+        who,
+        self
+          .ip
+          .as_ref()
+          .map(|v| format!(" at {}", v))
+          .unwrap_or_default(),
+        value(&self.old_value),
+        value(&self.new_value)
+      ),
+      AlertEvent::NewDevice => {
+        let mut message = format!("New device {} on {}", who, iface);
+        if let Some(ips) = &self.new_value {
+          message = format!("{} as {}", message, ips);
+        }
+        let facts: Vec<String> = [
+          self.device.vendor.clone(),
+          self
+            .device
+            .hostname
+            .as_ref()
+            .filter(|v| **v != self.label())
+            .map(|v| format!("hostname {}", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        match facts.is_empty() {
+          true => message,
+          false => format!("{} ({})", message, facts.join(", ")),
+        }
+      }
+      AlertEvent::Offline => {
+        let mut message = format!("{} went offline from {}", who, iface);
+        if let Some(ips) = &self.old_value {
+          message = format!("{}, where it was {}", message, ips);
+        }
+        match self.last_seen {
+          Some(last_seen) => format!(
+            "{}, last seen {} ago",
+            message,
+            format_elapsed(last_seen, self.time)
+          ),
+          None => message,
+        }
+      }
+      AlertEvent::Returned => {
+        let mut message = format!("{} is back on {}", who, iface);
+        if let Some(ips) = &self.new_value {
+          message = format!("{} as {}", message, ips);
+        }
+        if let Some(last_seen) = self.last_seen {
+          message = format!(
+            "{} after {} offline",
+            message,
+            format_elapsed(last_seen, self.time)
+          );
+        }
+        if self.old_iface.is_some() && self.old_iface != self.iface {
+          message = format!("{}, having moved from {}", message, value(&self.old_iface));
+        }
+        if self.old_value != self.new_value {
+          message = format!(
+            "{}, its addresses having been {}",
+            message,
+            value(&self.old_value)
+          );
+        }
+        message
+      }
+      AlertEvent::ArpSpoofing => {
+        let ip = self
+          .ip
+          .as_ref()
+          .map(|v| v.to_string())
+          .unwrap_or_else(|| "an address".to_string());
+        match &self.old_value {
+          Some(old) => format!("{} took over {} from {}", who, ip, old),
+          None => format!(
+            "{} claims the gateway {}, along with {}",
+            who,
+            ip,
+            value(&self.new_value)
+          ),
+        }
+      }
+      AlertEvent::WrongInterface => {
+        let mut message = format!("{} appeared on {}", who, iface);
+        if let Some(ips) = &self.new_value {
+          message = format!("{} as {}", message, ips);
+        }
+        format!("{}, living on {}", message, value(&self.old_value))
+      }
+      AlertEvent::RogueDhcp => {
+        let mut message = format!(
+          "{} is serving DHCP on {}, offering {}",
+          who,
+          iface,
+          value(&self.new_value)
+        );
+        if let Some(router) = &self.old_value {
+          message = format!("{} with the gateway {}", message, router);
+        }
+        message
+      }
+      AlertEvent::RogueRa => format!(
+        "{} sent a rogue router advertisement on {}, {}",
+        who,
+        iface,
+        value(&self.new_value)
+      ),
+      AlertEvent::PolicyViolation => {
+        let mut message = format!("{} appeared on {}", who, iface);
+        if let Some(ips) = &self.new_value {
+          message = format!("{} as {}", message, ips);
+        }
+        format!("{}, which only allows {}", message, value(&self.old_value))
+      }
+    }
+  }
+}
+
+///
+/// Matches the changes of every poll against the rules, raising an alert
+/// for every rule a change matches.
+///
+#[derive(Debug)]
+pub struct AlertEngine {
+  rules: Vec<Rule>,
+  router: String,
+  /// Devices seen so far, along with the file they're kept in, if new
+  /// devices are told apart.
+  seen: Option<(SeenDevices, PathBuf)>,
+  learning_period: Duration,
+  /// Whether devices were seen since the file was last written.
+  unsaved: bool,
+  /// When and where the devices were last seen, for offline and returned
+  /// rules.
+  presence: HashMap<MacAddr, Presence>,
+  /// Offline alerts raised, by rule name and device, until the device is
+  /// back.
+  offline: HashSet<(String, MacAddr)>,
+  /// Addresses bound to MAC addresses, for arp_spoofing rules.
+  bindings: spoof::Bindings,
+  /// Gateways configured, besides those of the default routes.
+  gateways: Vec<IpAddr>,
+  /// Devices on an interface they don't live on, until they're back.
+  elsewhere: HashSet<MacAddr>,
+  rogue_dhcp: Option<RogueDhcpWatch>,
+  rogue_ra: Option<RogueRaWatch>,
+  /// Last time every rogue DHCP server offered, or router advertised, at.
+  rogue: HashMap<IpAddr, SystemTime>,
+  interface_policies: Vec<InterfacePolicy>,
+  /// Devices on an interface whose policy doesn't allow them, until they
+  /// leave it.
+  violations: HashSet<(String, MacAddr)>,
+}
+
+/// When a device was last seen, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Presence {
+  iface: String,
+  ips: Vec<ScopedIpAddr>,
+  /// Last poll the device was in the table at, in a valid state.
+  last_valid: SystemTime,
+  /// Last poll the device was in a valid state other than STALE at, or
+  /// first seen at.
+  last_confirmed: SystemTime,
+}
+
+impl Presence {
+  /// Time the device was last seen at, counting STALE polls for at most
+  /// stale_timeout.
+  fn last_seen(&self, stale_timeout: Option<Duration>) -> SystemTime {
+    match stale_timeout {
+      Some(timeout) => self.last_valid.min(self.last_confirmed + timeout),
+      None => self.last_valid,
+    }
+  }
+}
+
+impl AlertEngine {
+  /// Creates an engine, raising the alerts as the router's hostname.
+  pub fn new(rules: Vec<Rule>) -> Self {
+    AlertEngine {
+      rules,
+      router: hostname(),
+      seen: None,
+      learning_period: Duration::ZERO,
+      unsaved: false,
+      presence: HashMap::new(),
+      offline: HashSet::new(),
+      bindings: spoof::Bindings::default(),
+      gateways: Vec::new(),
+      elsewhere: HashSet::new(),
+      rogue_dhcp: None,
+      rogue_ra: None,
+      rogue: HashMap::new(),
+      interface_policies: Vec::new(),
+      violations: HashSet::new(),
+    }
+  }
+
+  ///
+  /// Tells the devices never seen before apart, raising new_device changes,
+  /// and learns the interfaces they live on, raising wrong_interface changes.
+  ///
+  /// Args:
+  ///  - seen: Devices seen so far, see SeenDevices::load.
+  ///  - path: File the devices are kept in, written by save.
+  ///
+  pub fn seen(mut self, seen: SeenDevices, path: &Path) -> Self {
+    self.seen = Some((seen, path.to_path_buf()));
+    self
+  }
+
+  /// Time following the first device ever seen during which devices are
+  /// learned rather than new, none by default.
+  pub fn learning_period(mut self, learning_period: Duration) -> Self {
+    self.learning_period = learning_period;
+    self
+  }
+
+  /// Gateways arp_spoofing rules watch, besides those of the host's default
+  /// routes.
+  pub fn gateways(mut self, gateways: Vec<IpAddr>) -> Self {
+    self.gateways = gateways;
+    self
+  }
+
+  /// Watches for rogue DHCP servers, raising rogue_dhcp changes. The watch
+  /// is started by the first poll.
+  pub fn rogue_dhcp(mut self, watch: RogueDhcpWatch) -> Self {
+    self.rogue_dhcp = Some(watch);
+    self
+  }
+
+  /// Watches for rogue router advertisements, raising rogue_ra changes. The
+  /// watch is started by the first poll.
+  pub fn rogue_ra(mut self, watch: RogueRaWatch) -> Self {
+    self.rogue_ra = Some(watch);
+    self
+  }
+
+  /// Policies of the interfaces, raising policy_violation changes and
+  /// running their enforce hooks.
+  pub fn interface_policies(mut self, policies: Vec<InterfacePolicy>) -> Self {
+    self.interface_policies = policies;
+    self
+  }
+
+  pub fn rules(&self) -> &[Rule] {
+    &self.rules
+  }
+
+  /// Writes the devices seen for the first time since the previous call.
+  pub fn save(&mut self) -> Result<()> {
+    match &self.seen {
+      Some((seen, path)) if self.unsaved => {
+        seen.save(path)?;
+        self.unsaved = false;
+        Ok(())
+      }
+      _ => Ok(()),
+    }
+  }
+
+  ///
+  /// Matches the changes of a poll against the rules.
+  ///
+  /// Args:
+  ///  - report: Poll to match, its changes being those since the previous.
+  ///
+  /// Returns:
+  ///  The alerts raised, in the order of the changes, then of the rules.
+  ///
+  pub fn evaluate(&mut self, report: &PollReport) -> Vec<Alert> {
+    let time = report.snapshot.taken_at;
+    let local = LocalTime::at(time);
+    let mut alerts = Vec::new();
+    let mut changes = changes(report);
+    changes.extend(self.new_devices(report));
+    changes.extend(self.wrong_interfaces(report));
+    changes.extend(self.rogue_dhcp_servers(report));
+    changes.extend(self.rogue_routers(report));
+    changes.extend(self.policy_violations(report));
+    self
+      .rogue
+      .retain(|_, v| time.duration_since(*v).unwrap_or_default() < ROGUE_QUIET);
+    if self
+      .rules
+      .iter()
+      .any(|v| v.event() == AlertEvent::ArpSpoofing)
+    {
+      let mut gateways = default_gateways();
+      gateways.extend(self.gateways.iter().map(|v| ScopedIpAddr::from(*v)));
+      changes.extend(self.bindings.changes(report, &gateways));
+    }
+    for change in changes {
+      for rule in self.rules.iter().filter(|v| v.matches(&change, local)) {
+        alerts.push(raise(rule, &change, time, &self.router));
+      }
+    }
+    alerts.extend(self.presence_alerts(report, local));
+    alerts
+  }
+
+  ///
+  /// Raises the offline alerts of the devices gone for long enough, and the
+  /// returned alerts of the devices back after being gone for long enough.
+  ///
+  fn presence_alerts(&mut self, report: &PollReport, local: LocalTime) -> Vec<Alert> {
+    let presence_rule = |v: &Rule| matches!(v.event(), AlertEvent::Offline | AlertEvent::Returned);
+    if !self.rules.iter().any(presence_rule) {
+      return Vec::new();
+    }
+    let time = report.snapshot.taken_at;
+    let confirmed = NudState::VALID.difference(NudState::STALE);
+    let mut previous = Vec::new();
+    for device in report.devices.iter().filter(|v| v.online) {
+      let presence = match self.presence.get_mut(&device.mac_addr) {
+        Some(presence) => {
+          previous.push((device.mac_addr, presence.clone()));
+          presence
+        }
+        None => self.presence.entry(device.mac_addr).or_insert(Presence {
+          iface: device.iface.clone(),
+          ips: Vec::new(),
+          last_valid: time,
+          last_confirmed: time,
+        }),
+      };
+      presence.iface = device.iface.clone();
+      presence.ips = device.ips().cloned().collect();
+      presence.last_valid = time;
+      if device.nud_state.intersects(confirmed) {
+        presence.last_confirmed = time;
+      }
+    }
+    // Follow the tracker in forgetting devices, e.g. on tiny routers.
+    self
+      .presence
+      .retain(|mac_addr, _| report.tracker.get(mac_addr).is_some());
+    self
+      .offline
+      .retain(|(_, mac_addr)| self.presence.contains_key(mac_addr));
+
+    let join = |ips: &[ScopedIpAddr]| {
+      let ips: Vec<String> = ips.iter().map(|v| v.to_string()).collect();
+      (!ips.is_empty()).then(|| ips.join(","))
+    };
+    let mut alerts = Vec::new();
+    for rule in &self.rules {
+      if rule.event() == AlertEvent::Returned {
+        for (mac_addr, before) in &previous {
+          let now = &self.presence[mac_addr];
+          let last_seen = before.last_seen(rule.stale_timeout);
+          let gone = time.duration_since(last_seen).unwrap_or_default();
+          if now.last_seen(rule.stale_timeout) < time || gone < rule.offline_after {
+            continue;
+          }
+          let change = Change {
+            event: AlertEvent::Returned,
+            mac_addr: Some(*mac_addr),
+            ip: now.ips.first().cloned(),
+            iface: Some(now.iface.clone()),
+            old_iface: Some(before.iface.clone()),
+            old_value: join(&before.ips),
+            new_value: join(&now.ips),
+            old_state: None,
+            new_state: None,
+            last_seen: Some(last_seen),
+            device: device_facts(report, mac_addr),
+          };
+          if rule.matches(&change, local) {
+            alerts.push(raise(rule, &change, time, &self.router));
+          }
+        }
+      }
+      if rule.event() != AlertEvent::Offline {
+        continue;
+      }
+      for (mac_addr, presence) in &self.presence {
+        let last_seen = presence.last_seen(rule.stale_timeout);
+        let gone = time.duration_since(last_seen).unwrap_or_default();
+        if gone < rule.offline_after {
+          self.offline.remove(&(rule.name().to_string(), *mac_addr));
+          continue;
+        }
+        let offline = (rule.name().to_string(), *mac_addr);
+        if self.offline.contains(&offline) {
+          continue;
+        }
+        let change = Change {
+          event: AlertEvent::Offline,
+          mac_addr: Some(*mac_addr),
+          ip: presence.ips.first().cloned(),
+          iface: Some(presence.iface.clone()),
+          old_iface: None,
+          old_value: join(&presence.ips),
+          new_value: None,
+          old_state: None,
+          new_state: None,
+          last_seen: Some(last_seen),
+          device: device_facts(report, mac_addr),
+        };
+        if rule.matches(&change, local) {
+          alerts.push(raise(rule, &change, time, &self.router));
+          self.offline.insert(offline);
+        }
+      }
+    }
+    alerts
+  }
+
+  /// Records the devices of a poll, returning the new ones as changes.
+  fn new_devices(&mut self, report: &PollReport) -> Vec<Change> {
+    let Some((seen, _)) = &mut self.seen else {
+      return Vec::new();
+    };
+    let time = report.snapshot.taken_at;
+    let learning = seen.is_learning(self.learning_period, time);
+    if learning && seen.is_empty() {
+      info!(
+        "Learning the devices on the network for {}",
+        humantime::format_duration(self.learning_period)
+      );
+    }
+    let mut changes = Vec::new();
+    for device in report.devices.iter().filter(|v| v.online) {
+      let new = seen.insert(device.mac_addr, time);
+      // Devices seen before interfaces were kept live where they're seen next.
+      let homeless = seen.interfaces(&device.mac_addr).is_empty();
+      if (new || learning || homeless) && seen.add_interface(device.mac_addr, &device.iface) {
+        self.unsaved = true;
+      }
+      if !new {
+        continue;
+      }
+      self.unsaved = true;
+      if learning || report.registry.get(&device.mac_addr).is_some() {
+        debug!("Learned the device {}", device.mac_addr);
+        continue;
+      }
+      let ips: Vec<String> = device.addresses.iter().map(|v| v.ip.to_string()).collect();
+      changes.push(Change {
+        event: AlertEvent::NewDevice,
+        mac_addr: Some(device.mac_addr),
+        ip: device.addresses.first().map(|v| v.ip.clone()),
+        iface: Some(device.iface.clone()),
+        old_iface: None,
+        old_value: None,
+        new_value: (!ips.is_empty()).then(|| ips.join(",")),
+        old_state: None,
+        new_state: None,
+        last_seen: None,
+        device: device_facts(report, &device.mac_addr),
+      });
+    }
+    changes
+  }
+
+  ///
+  /// Raises a wrong_interface change for every known device appearing on an
+  /// interface it doesn't live on, once until it's back on one it does.
+  ///
+  fn wrong_interfaces(&mut self, report: &PollReport) -> Vec<Change> {
+    let Some((seen, _)) = &self.seen else {
+      return Vec::new();
+    };
+    if !self
+      .rules
+      .iter()
+      .any(|v| v.event() == AlertEvent::WrongInterface)
+    {
+      return Vec::new();
+    }
+    let mut elsewhere = HashSet::new();
+    let mut changes = Vec::new();
+    for device in &report.devices {
+      let interfaces = seen.interfaces(&device.mac_addr);
+      if interfaces.is_empty() || interfaces.contains(&device.iface) {
+        continue;
+      }
+      elsewhere.insert(device.mac_addr);
+      if !device.online || self.elsewhere.contains(&device.mac_addr) {
+        continue;
+      }
+      let ips: Vec<String> = device.addresses.iter().map(|v| v.ip.to_string()).collect();
+      changes.push(Change {
+        event: AlertEvent::WrongInterface,
+        mac_addr: Some(device.mac_addr),
+        ip: device.addresses.first().map(|v| v.ip.clone()),
+        iface: Some(device.iface.clone()),
+        old_iface: interfaces.first().cloned(),
+        old_value: Some(interfaces.join(",")),
+        new_value: (!ips.is_empty()).then(|| ips.join(",")),
+        old_state: None,
+        new_state: None,
+        last_seen: None,
+        device: device_facts(report, &device.mac_addr),
+      });
+    }
+    // Offline devices keep the interface they were last on, so stay there.
+    self.elsewhere = elsewhere;
+    changes
+  }
+
+  ///
+  /// Raises a rogue_dhcp change for every rogue DHCP server offering since
+  /// the previous poll, unless it already offered within ROGUE_QUIET.
+  ///
+  fn rogue_dhcp_servers(&mut self, report: &PollReport) -> Vec<Change> {
+    let Some(watch) = &mut self.rogue_dhcp else {
+      return Vec::new();
+    };
+    watch.start();
+    let time = report.snapshot.taken_at;
+    let mut changes = Vec::new();
+    for (iface, offer) in watch.take() {
+      let server = IpAddr::V4(offer.server);
+      if !rogue_again(&mut self.rogue, server, time) {
+        continue;
+      }
+      let mac_addr = report
+        .snapshot
+        .entries
+        .iter()
+        .find(|v| v.ip == server)
+        .and_then(|v| v.mac_addr);
+      changes.push(Change {
+        event: AlertEvent::RogueDhcp,
+        mac_addr,
+        ip: Some(ScopedIpAddr::from(server)),
+        iface: Some(iface),
+        old_iface: None,
+        old_value: offer.router.map(|v| v.to_string()),
+        new_value: Some(offer.offered.to_string()),
+        old_state: None,
+        new_state: None,
+        last_seen: None,
+        device: mac_addr
+          .map(|v| device_facts(report, &v))
+          .unwrap_or_default(),
+      });
+    }
+    changes
+  }
+
+  ///
+  /// Raises a rogue_ra change for every rogue router advertisement since the
+  /// previous poll, unless its router already advertised within ROGUE_QUIET.
+  ///
+  fn rogue_routers(&mut self, report: &PollReport) -> Vec<Change> {
+    let Some(watch) = &mut self.rogue_ra else {
+      return Vec::new();
+    };
+    watch.start();
+    let time = report.snapshot.taken_at;
+    let mut changes = Vec::new();
+    for rogue in watch.take() {
+      let source = IpAddr::V6(rogue.advert.source);
+      if !rogue_again(&mut self.rogue, source, time) {
+        continue;
+      }
+      let mac_addr = rogue.advert.mac_addr.or_else(|| {
+        report
+          .snapshot
+          .entries
+          .iter()
+          .find(|v| v.ip == source && v.iface == rogue.iface)
+          .and_then(|v| v.mac_addr)
+      });
+      changes.push(Change {
+        event: AlertEvent::RogueRa,
+        mac_addr,
+        ip: Some(ScopedIpAddr::new(source, &rogue.iface)),
+        iface: Some(rogue.iface),
+        old_iface: None,
+        old_value: None,
+        new_value: Some(rogue.unexpected.join(", ")),
+        old_state: None,
+        new_state: None,
+        last_seen: None,
+        device: mac_addr
+          .map(|v| device_facts(report, &v))
+          .unwrap_or_default(),
+      });
+    }
+    changes
+  }
+
+  ///
+  /// Raises a policy_violation change for every device on an interface whose
+  /// policy doesn't allow it, once until it leaves the interface, running
+  /// the policy's enforce hook on it.
+  ///
+  fn policy_violations(&mut self, report: &PollReport) -> Vec<Change> {
+    if self.interface_policies.is_empty() {
+      return Vec::new();
+    }
+    let mut violations = HashSet::new();
+    let mut changes = Vec::new();
+    for device in &report.devices {
+      let Some(policy) = self
+        .interface_policies
+        .iter()
+        .find(|v| v.iface() == device.iface)
+      else {
+        continue;
+      };
+      let facts = device_facts(report, &device.mac_addr);
+      if policy.allows(&device.mac_addr, &facts) {
+        continue;
+      }
+      let violation = (device.iface.clone(), device.mac_addr);
+      let raised = self.violations.contains(&violation);
+      violations.insert(violation);
+      if !device.online || raised {
+        continue;
+      }
+      let ips: Vec<String> = device.addresses.iter().map(|v| v.ip.to_string()).collect();
+      let change = Change {
+        event: AlertEvent::PolicyViolation,
+        mac_addr: Some(device.mac_addr),
+        ip: device.addresses.first().map(|v| v.ip.clone()),
+        iface: Some(device.iface.clone()),
+        old_iface: None,
+        old_value: Some(policy.allowed()),
+        new_value: (!ips.is_empty()).then(|| ips.join(",")),
+        old_state: None,
+        new_state: None,
+        last_seen: None,
+        device: facts,
+      };
+      if let Err(err) = policy.enforce_on(&change) {
+        warn!(
+          "Failed to enforce the {} policy on {}: {}",
+          device.iface, device.mac_addr, err
+        );
+      }
+      changes.push(change);
+    }
+    // Offline devices keep the interface they were last on, so stay there.
+    self.violations = violations;
+    changes
+  }
+}
+
+///
+/// Sink raising alerts off every poll, and delivering them to its channels.
+///
+/// Channels are tried one after the other; one failing doesn't keep the
+/// alert from the others. Alerts held during quiet hours, and those
+/// escalating, are kept in memory, across reloads. Held alerts are delivered
+/// once the daemon stops, while escalations are lost.
+///
+pub struct AlertSink {
+  engine: AlertEngine,
+  channels: Vec<Box<dyn Channel>>,
+  /// Quiet hours of the channels, by name.
+  quiet: HashMap<String, TimeWindow>,
+  /// Alerts held during quiet hours, by channel, oldest first.
+  held: HashMap<String, VecDeque<Alert>>,
+  dedup: limit::Dedup,
+  /// Rate limits of the channels, by name.
+  limits: HashMap<String, limit::RateLimiter>,
+  escalations: Escalations,
+  /// What the sink learned, handed over to the sink replacing it.
+  state: AlertState,
+  /// Whether the sink took over what the one before it learned.
+  resumed: bool,
+}
+
+impl AlertSink {
+  /// Creates a sink, delivering to no channel until one is added.
+  pub fn new(engine: AlertEngine) -> Self {
+    AlertSink {
+      engine,
+      channels: Vec::new(),
+      quiet: HashMap::new(),
+      held: HashMap::new(),
+      dedup: limit::Dedup::default(),
+      limits: HashMap::new(),
+      escalations: Escalations::new(),
+      state: AlertState::new(),
+      resumed: false,
+    }
+  }
+
+  /// Adds a channel, which rules route their alerts to by its name.
+  pub fn channel(mut self, channel: Box<dyn Channel>) -> Self {
+    self.channels.push(channel);
+    self
+  }
+
+  ///
+  /// Holds the alerts of a channel, unless critical, during quiet hours.
+  ///
+  /// Args:
+  ///  - channel: Name of the channel.
+  ///  - quiet: Local time the alerts are held at, delivered once it's over.
+  ///
+  pub fn quiet(mut self, channel: &str, quiet: TimeWindow) -> Self {
+    self.quiet.insert(channel.to_string(), quiet);
+    self
+  }
+
+  ///
+  /// Limits the alerts a channel delivers, summing up those over the limit
+  /// once its period is over. Critical alerts are never held back.
+  ///
+  /// Args:
+  ///  - channel: Name of the channel.
+  ///  - max: Alerts delivered per period at most.
+  ///  - per: Period, starting with the first alert delivered.
+  ///
+  pub fn rate_limit(mut self, channel: &str, max: u32, per: Duration) -> Self {
+    self
+      .limits
+      .insert(channel.to_string(), limit::RateLimiter::new(max, per));
+    self
+  }
+
+  /// Escalates alerts along with others sharing them, e.g. the sink of the
+  /// configuration before a reload, and what acknowledges them.
+  pub fn escalations(mut self, escalations: Escalations) -> Self {
+    self.escalations = escalations;
+    self
+  }
+
+  /// Takes over what the sink before it learned, e.g. the sink of the
+  /// configuration before a reload, and hands what it learns over to the
+  /// next one built with the same state once flushed.
+  pub fn state(mut self, state: AlertState) -> Self {
+    self.state = state;
+    self
+  }
+
+  /// Takes over what the sink before it learned, once.
+  fn resume(&mut self) {
+    if std::mem::replace(&mut self.resumed, true) {
+      return;
+    }
+    let Some(kept) = self.state.take() else {
+      return;
+    };
+    let engine = &mut self.engine;
+    engine.presence = kept.presence;
+    engine.offline = kept.offline;
+    engine
+      .offline
+      .retain(|(rule, _)| engine.rules.iter().any(|v| v.name() == rule));
+    for (channel, held) in kept.held {
+      if self.channels.iter().any(|v| v.name() == channel) {
+        self.held.insert(channel, held);
+      } else if !held.is_empty() {
+        warn!(
+          "Dropping {} alerts held for '{}', which is no longer configured",
+          held.len(),
+          channel
+        );
+      }
+    }
+    self.dedup = kept.dedup;
+    let engine = &mut self.engine;
+    engine.violations = kept.violations;
+    engine
+      .violations
+      .retain(|(iface, _)| engine.interface_policies.iter().any(|v| v.iface() == iface));
+    engine.rogue = kept.rogue;
+    engine.rogue.retain(|ip, _| match ip {
+      IpAddr::V4(_) => engine.rogue_dhcp.is_some(),
+      IpAddr::V6(_) => engine.rogue_ra.is_some(),
+    });
+    if engine
+      .rules
+      .iter()
+      .any(|v| v.event() == AlertEvent::WrongInterface)
+    {
+      engine.elsewhere = kept.elsewhere;
+    }
+    engine.bindings = kept.bindings;
+    for (channel, limit) in kept.limits {
+      if self.limits.get(&channel).is_some_and(|v| v.is_like(&limit)) {
+        self.limits.insert(channel, limit);
+      }
+    }
+  }
+}
+
+impl Sink for AlertSink {
+  fn name(&self) -> &str {
+    "alerts"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    self.resume();
+    for channel in &mut self.channels {
+      channel.update(report);
+    }
+    let time = report.snapshot.taken_at;
+    let alerts = self.engine.evaluate(report);
+    let saved = self.engine.save();
+    let mut alerts = self.dedup.collapse(alerts, self.engine.rules(), time);
+    let mut errors = Vec::new();
+    for alert in alerts.iter_mut().filter(|v| v.summary.is_none()) {
+      let rule = self.engine.rules().iter().find(|v| v.name() == alert.rule);
+      if let Some(escalation) = rule.and_then(|v| v.escalation.as_ref()) {
+        alert.ack_id = Some(self.escalations.start(alert, escalation));
+      }
+      if let Some(on_event) = rule.and_then(|v| v.on_event.as_ref()) {
+        if let Err(err) = hook::run(on_event, alert) {
+          errors.push(format!("the on_event hook of '{}': {}", alert.rule, err));
+        }
+      }
+    }
+    alerts.extend(self.escalations.due(time));
+    let local = LocalTime::at(time);
+    let rules = self.engine.rules();
+    let is_quiet = |alert: &Alert, quiet: Option<&TimeWindow>| {
+      let rule = rules.iter().find(|v| v.name() == alert.rule);
+      alert.severity < Severity::Critical
+        && (quiet.is_some_and(|v| v.contains(local))
+          || rule
+            .and_then(|v| v.quiet.as_ref())
+            .is_some_and(|v| v.contains(local)))
+    };
+
+    for channel in &mut self.channels {
+      let mut limit = self.limits.get_mut(channel.name());
+      if let Some(summary) = limit.as_mut().and_then(|v| v.expire(time)) {
+        deliver(channel.as_mut(), None, &summary, time, &mut errors);
+      }
+      let Some(held) = self.held.get_mut(channel.name()) else {
+        continue;
+      };
+      let quiet = self.quiet.get(channel.name());
+      let (still, over): (VecDeque<Alert>, VecDeque<Alert>) =
+        held.drain(..).partition(|v| is_quiet(v, quiet));
+      *held = still;
+      if !over.is_empty() {
+        info!(
+          "Delivering {} alerts held during quiet hours to '{}'",
+          over.len(),
+          channel.name()
+        );
+      }
+      for alert in over {
+        deliver(
+          channel.as_mut(),
+          limit.as_deref_mut(),
+          &alert,
+          time,
+          &mut errors,
+        );
+      }
+    }
+    for alert in alerts {
+      debug!("Rule '{}' raised: {}", alert.rule, alert.message());
+      for channel in self
+        .channels
+        .iter_mut()
+        .filter(|v| routes(&alert, rules, v.name()))
+      {
+        if is_quiet(&alert, self.quiet.get(channel.name())) {
+          debug!(
+            "Holding the alert for '{}' during quiet hours",
+            channel.name()
+          );
+          let held = self.held.entry(channel.name().to_string()).or_default();
+          if held.len() == MAX_HELD_ALERTS {
+            warn!(
+              "Dropping the oldest alert held for '{}', which holds {} at most",
+              channel.name(),
+              MAX_HELD_ALERTS
+            );
+            held.pop_front();
+          }
+          held.push_back(alert.clone());
+          continue;
+        }
+        let limit = self.limits.get_mut(channel.name());
+        deliver(channel.as_mut(), limit, &alert, time, &mut errors);
+      }
+    }
+    match errors.is_empty() {
+      true => saved,
+      false => Err(Error::msg(format!(
+        "Failed to deliver alerts to {}",
+        errors.join("; ")
+      ))),
+    }
+  }
+
+  fn needs_thread(&self) -> bool {
+    // Channels mostly deliver over the network.
+    true
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    // Hand over what the sink before learned even if it never polled.
+    self.resume();
+    if !self.state.is_stopping() {
+      self.state.keep(state::Kept {
+        presence: std::mem::take(&mut self.engine.presence),
+        offline: std::mem::take(&mut self.engine.offline),
+        held: std::mem::take(&mut self.held),
+        dedup: std::mem::take(&mut self.dedup),
+        limits: std::mem::take(&mut self.limits),
+        violations: std::mem::take(&mut self.engine.violations),
+        rogue: std::mem::take(&mut self.engine.rogue),
+        elsewhere: std::mem::take(&mut self.engine.elsewhere),
+        bindings: std::mem::take(&mut self.engine.bindings),
+      });
+      return Ok(());
+    }
+    let time = SystemTime::now();
+    let rules = self.engine.rules();
+    let mut errors = Vec::new();
+    let summaries = self.dedup.close();
+    for channel in &mut self.channels {
+      let held = self.held.remove(channel.name()).unwrap_or_default();
+      if !held.is_empty() {
+        info!(
+          "Delivering {} alerts held during quiet hours to '{}', as the daemon stops",
+          held.len(),
+          channel.name()
+        );
+      }
+      let mut limit = self.limits.get_mut(channel.name());
+      for alert in held {
+        deliver(
+          channel.as_mut(),
+          limit.as_deref_mut(),
+          &alert,
+          time,
+          &mut errors,
+        );
+      }
+      let name = channel.name().to_string();
+      for alert in summaries.iter().filter(|v| routes(v, rules, &name)) {
+        deliver(
+          channel.as_mut(),
+          limit.as_deref_mut(),
+          alert,
+          time,
+          &mut errors,
+        );
+      }
+      if let Some(summary) = limit.and_then(|v| v.close()) {
+        deliver(channel.as_mut(), None, &summary, time, &mut errors);
+      }
+    }
+    match errors.is_empty() {
+      true => Ok(()),
+      false => Err(Error::msg(format!(
+        "Failed to deliver the alerts held back to {}",
+        errors.join("; ")
+      ))),
+    }
+  }
+}
+
+impl fmt::Debug for AlertSink {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let channels: Vec<&str> = self.channels.iter().map(|v| v.name()).collect();
+    f.debug_struct("AlertSink")
+      .field("engine", &self.engine)
+      .field("channels", &channels)
+      .field("quiet", &self.quiet)
+      .finish()
+  }
+}
+
+/// Delivers an alert to a channel, unless over its rate limit, recording
+/// the failure if it fails.
+fn deliver(
+  channel: &mut dyn Channel,
+  limit: Option<&mut limit::RateLimiter>,
+  alert: &Alert,
+  time: SystemTime,
+  errors: &mut Vec<String>,
+) {
+  if limit.is_some_and(|v| !v.admit(alert, time)) {
+    debug!(
+      "Holding back the alert for '{}', over its rate limit",
+      channel.name()
+    );
+    return;
+  }
+  if let Err(err) = channel.send(alert) {
+    errors.push(format!("{}: {}", channel.name(), err));
+  }
+}
+
+/// Raises the alert of a rule a change matched.
+fn raise(rule: &Rule, change: &Change, time: SystemTime, router: &str) -> Alert {
+  let critical = change.device.policy.as_ref().is_some_and(|v| v.critical);
+  Alert {
+    rule: rule.name().to_string(),
+    severity: match critical {
+      true => Severity::Critical,
+      false => rule.severity,
+    },
+    event: change.event,
+    time,
+    router: router.to_string(),
+    mac_addr: change.mac_addr,
+    ip: change.ip.clone(),
+    iface: change.iface.clone(),
+    old_iface: change.old_iface.clone(),
+    old_value: change.old_value.clone(),
+    new_value: change.new_value.clone(),
+    last_seen: change.last_seen,
+    device: change.device.clone(),
+    channels: rule.channels.clone(),
+    summary: None,
+    ack_id: None,
+  }
+}
+
+/// Whether an alert goes to a channel: those it's routed to, or every
+/// channel unless its rule only runs an on_event hook.
+fn routes(alert: &Alert, rules: &[Rule], channel: &str) -> bool {
+  let rule = rules.iter().find(|v| v.name() == alert.rule);
+  match alert.channels.is_empty() {
+    true => rule.is_none_or(|v| v.on_event.is_none()),
+    false => alert.channels.iter().any(|v| v == channel),
+  }
+}
+
+/// Records a rogue DHCP server or router at a time, returning whether it's
+/// raised again, having been quiet for ROGUE_QUIET.
+fn rogue_again(rogue: &mut HashMap<IpAddr, SystemTime>, ip: IpAddr, time: SystemTime) -> bool {
+  let last = rogue.insert(ip, time);
+  last.is_none_or(|v| time.duration_since(v).unwrap_or_default() >= ROGUE_QUIET)
+}
+
+/// Time elapsed between two times, to the second, e.g. "5m 3s".
+fn format_elapsed(from: SystemTime, to: SystemTime) -> String {
+  let elapsed = to.duration_since(from).unwrap_or_default();
+  humantime::format_duration(Duration::from_secs(elapsed.as_secs())).to_string()
+}
+
+/// What's known of a device as of a poll, even one that left.
+fn device_facts(report: &PollReport, mac_addr: &MacAddr) -> DeviceFacts {
+  let device = report.devices.iter().find(|v| v.mac_addr == *mac_addr);
+  let known = report.registry.get(mac_addr);
+  DeviceFacts {
+    name: report.name_of(mac_addr).map(str::to_string),
+    hostname: device.and_then(|v| {
+      v.hostname
+        .clone()
+        .or_else(|| v.lease.as_ref().and_then(|v| v.hostname.clone()))
+    }),
+    vendor: device
+      .and_then(|v| v.vendor.clone())
+      .or_else(|| mac_addr.vendor().map(str::to_string)),
+    owner: known.and_then(|v| v.owner.clone()),
+    category: known.and_then(|v| v.category),
+    trust: known.and_then(|v| v.trust),
+    policy: known.and_then(|v| v.policy.clone()),
+  }
+}
+
+/// The changes of a poll, mapped as the history maps them onto events.
+fn changes(report: &PollReport) -> Vec<Change> {
+  let join = |ips: &[ScopedIpAddr]| {
+    let ips: Vec<String> = ips.iter().map(|v| v.to_string()).collect();
+    (!ips.is_empty()).then(|| ips.join(","))
+  };
+  let iface_of = |mac_addr: Option<MacAddr>, ip: Option<&ScopedIpAddr>| {
+    let device = mac_addr.and_then(|mac| report.devices.iter().find(|v| v.mac_addr == mac));
+    let entry = ip.and_then(|ip| {
+      report
+        .snapshot
+        .entries
+        .iter()
+        .find(|v| v.scoped_ip() == *ip)
+    });
+    device
+      .map(|v| v.iface.clone())
+      .or_else(|| entry.map(|v| v.iface.clone()))
+  };
+  let change = |event, mac_addr: Option<MacAddr>| Change {
+    event,
+    mac_addr,
+    ip: None,
+    iface: None,
+    old_iface: None,
+    old_value: None,
+    new_value: None,
+    old_state: None,
+    new_state: None,
+    last_seen: None,
+    device: mac_addr
+      .map(|v| device_facts(report, &v))
+      .unwrap_or_default(),
+  };
+
+  let diff = &report.diff;
+  let mut changes = Vec::new();
+  for device in &diff.joined {
+    changes.push(Change {
+      ip: device.ips.first().cloned(),
+      iface: Some(device.iface.clone()),
+      new_value: join(&device.ips),
+      ..change(AlertEvent::Joined, Some(device.mac_addr))
+    });
+  }
+  for device in &diff.left {
+    changes.push(Change {
+      ip: device.ips.first().cloned(),
+      iface: Some(device.iface.clone()),
+      old_value: join(&device.ips),
+      ..change(AlertEvent::Left, Some(device.mac_addr))
+    });
+  }
+  for ip_change in &diff.ip_changed {
+    changes.push(Change {
+      ip: ip_change.added.first().cloned(),
+      iface: iface_of(Some(ip_change.mac_addr), None),
+      old_value: join(&ip_change.removed),
+      new_value: join(&ip_change.added),
+      ..change(AlertEvent::IpChanged, Some(ip_change.mac_addr))
+    });
+  }
+  for mac_change in &diff.mac_changed {
+    changes.push(Change {
+      ip: Some(mac_change.ip.clone()),
+      iface: iface_of(mac_change.new_mac_addr, Some(&mac_change.ip)),
+      old_value: mac_change.old_mac_addr.map(|v| v.to_string()),
+      new_value: mac_change.new_mac_addr.map(|v| v.to_string()),
+      ..change(AlertEvent::MacChanged, mac_change.new_mac_addr)
+    });
+  }
+  for state_change in &diff.state_changed {
+    changes.push(Change {
+      ip: Some(state_change.ip.clone()),
+      iface: iface_of(state_change.mac_addr, Some(&state_change.ip)),
+      old_value: Some(format_state(state_change.old_state)),
+      new_value: Some(format_state(state_change.new_state)),
+      old_state: Some(state_change.old_state),
+      new_state: Some(state_change.new_state),
+      ..change(AlertEvent::StateChanged, state_change.mac_addr)
+    });
+  }
+  changes
+}
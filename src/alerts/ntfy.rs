@@ -0,0 +1,216 @@
+use super::webhook::{deliver, DEFAULT_RETRIES};
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::{encode_component, HttpRequest, DEFAULT_HTTP_TIMEOUT};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Server the alerts are published to when none is configured.
+pub const DEFAULT_SERVER: &str = "https://ntfy.sh";
+
+/*
+  Alerts are published as JSON to the server's root, with the priority of
+  their severity, and tagged with its emoji, then the channel's tags:
+
+    {"topic":"netmon-3f9a","title":"untrusted guest at night: Living room TV",
+     "message":"Living room TV (dc:a6:32:a3:48:b1) joined br-guest as 192.168.3.5",
+     "priority":5,"tags":["rotating_light","netmon"],
+     "click":"https://grafana.lan/d/netmon?var-mac=dc%3Aa6%3A32%3Aa3%3A48%3Ab1"}
+
+  Priorities go from 1 (min) to 5 (urgent); info alerts have the default
+  one, 3, warnings 4, and critical alerts 5, unless mapped otherwise. The
+  click URL, opened when tapping the notification, is rendered from a
+  template whose fields are percent-encoded.
+
+  Escalating alerts get an Acknowledge button, given the URL of the health
+  server, which ntfy's app POSTs to from the phone, so it has to reach it:
+
+    "actions":[{"action":"http","label":"Acknowledge","method":"POST",
+                "url":"http://192.168.1.1:9101/api/alerts/17/ack","clear":true}]
+*/
+
+///
+/// Channel publishing the alerts to an ntfy topic, on ntfy.sh or a
+/// self-hosted server.
+///
+#[derive(Debug, Clone)]
+pub struct NtfyChannel {
+  name: String,
+  server: String,
+  topic: String,
+  token: Option<String>,
+  basic_auth: Option<(String, String)>,
+  priorities: BTreeMap<Severity, u8>,
+  tags: Vec<String>,
+  click: Option<Template>,
+  ack_url: Option<String>,
+  template: Option<Template>,
+  retries: u32,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+}
+
+impl NtfyChannel {
+  ///
+  /// Creates a channel.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - topic: Topic the alerts are published to, as hard to guess as a
+  ///    password on a public server.
+  ///
+  pub fn new(name: &str, topic: &str) -> Self {
+    NtfyChannel {
+      name: name.to_string(),
+      server: DEFAULT_SERVER.to_string(),
+      topic: topic.to_string(),
+      token: None,
+      basic_auth: None,
+      priorities: BTreeMap::new(),
+      tags: Vec::new(),
+      click: None,
+      ack_url: None,
+      template: None,
+      retries: DEFAULT_RETRIES,
+      timeout: DEFAULT_HTTP_TIMEOUT,
+      ca_file: None,
+    }
+  }
+
+  /// Server to publish to, e.g. "http://10.0.0.2:8090".
+  pub fn server(mut self, server: &str) -> Self {
+    self.server = server.trim_end_matches('/').to_string();
+    self
+  }
+
+  /// Access token of a server requiring one, e.g. "tk_AgQdq7mVBoFD37zQVN29RhuMzNIz2".
+  pub fn token(mut self, token: &str) -> Self {
+    self.token = Some(token.to_string());
+    self
+  }
+
+  /// User publishing to a server requiring a login.
+  pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+    self.basic_auth = Some((username.to_string(), password.to_string()));
+    self
+  }
+
+  /// Priority of the alerts of a severity, from 1 (min) to 5 (urgent).
+  pub fn priority(mut self, severity: Severity, priority: u8) -> Self {
+    self.priorities.insert(severity, priority);
+    self
+  }
+
+  /// Tags every message, after its severity's emoji, e.g. "netmon".
+  pub fn tag(mut self, tag: &str) -> Self {
+    self.tags.push(tag.to_string());
+    self
+  }
+
+  /// Template of the URL opened when tapping a notification, whose fields
+  /// are percent-encoded.
+  pub fn click(mut self, click: Template) -> Self {
+    self.click = Some(click);
+    self
+  }
+
+  /// URL of the health server acknowledging escalating alerts, e.g.
+  /// "http://192.168.1.1:9101", adding an Acknowledge button to them.
+  pub fn ack_url(mut self, ack_url: &str) -> Self {
+    self.ack_url = Some(ack_url.trim_end_matches('/').to_string());
+    self
+  }
+
+  /// Template of the messages.
+  pub fn template(mut self, template: Template) -> Self {
+    self.template = Some(template);
+    self
+  }
+
+  /// Retries of a failed delivery.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  /// Longest time a delivery attempt may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify the server with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// The message publishing an alert.
+  fn message(&self, alert: &Alert) -> Value {
+    let (emoji, priority) = match alert.severity {
+      Severity::Info => ("information_source", 3),
+      Severity::Warning => ("warning", 4),
+      Severity::Critical => ("rotating_light", 5),
+    };
+    let priority = self
+      .priorities
+      .get(&alert.severity)
+      .copied()
+      .unwrap_or(priority);
+    let mut tags = vec![emoji.to_string()];
+    tags.extend(self.tags.iter().cloned());
+    let text = match &self.template {
+      Some(template) => template.render(alert),
+      None => alert.message(),
+    };
+
+    let mut message = Map::new();
+    message.insert("topic".to_string(), json!(self.topic));
+    message.insert("title".to_string(), json!(alert.title()));
+    message.insert("message".to_string(), json!(text));
+    message.insert("priority".to_string(), json!(priority));
+    message.insert("tags".to_string(), json!(tags));
+    if let Some(click) = &self.click {
+      message.insert(
+        "click".to_string(),
+        json!(click.render_escaped(alert, encode_component)),
+      );
+    }
+    if let (Some(ack_url), Some(id)) = (&self.ack_url, alert.ack_id) {
+      let action = json!({
+        "action": "http",
+        "label": "Acknowledge",
+        "method": "POST",
+        "url": format!("{}/api/alerts/{}/ack", ack_url, id),
+        "clear": true,
+      });
+      message.insert("actions".to_string(), json!([action]));
+    }
+    Value::Object(message)
+  }
+}
+
+impl Channel for NtfyChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let mut request = HttpRequest::post(&self.server)
+      .header("Content-Type", "application/json")
+      .body(&self.message(alert).to_string())
+      .timeout(self.timeout);
+    if let Some(token) = &self.token {
+      request = request.header("Authorization", &format!("Bearer {}", token));
+    }
+    if let Some((username, password)) = &self.basic_auth {
+      request = request.basic_auth(username, password);
+    }
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    deliver(&request, &self.server, self.retries).map(|_| ())
+  }
+}
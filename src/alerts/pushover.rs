@@ -0,0 +1,230 @@
+use super::channel::truncate;
+use super::webhook::{deliver, DEFAULT_RETRIES};
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::{encode_component, HttpRequest, DEFAULT_HTTP_TIMEOUT};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// API the messages are sent through when none is configured.
+pub const DEFAULT_API_URL: &str = "https://api.pushover.net/1";
+
+/// Priority of emergency alerts, repeated until acknowledged.
+pub const EMERGENCY_PRIORITY: i8 = 2;
+
+/// Wait before repeating an unacknowledged emergency alert when none is
+/// configured.
+pub const DEFAULT_RETRY: Duration = Duration::from_secs(60);
+
+/// Time an emergency alert is repeated for when none is configured.
+pub const DEFAULT_EXPIRE: Duration = Duration::from_secs(60 * 60);
+
+/// Longest title Pushover takes, in characters.
+const MAX_TITLE_LEN: usize = 250;
+
+/// Longest message Pushover takes, in characters.
+const MAX_MESSAGE_LEN: usize = 1024;
+
+/*
+  Alerts are POSTed to messages.json, with the priority of their severity:
+
+    {"token":"azGDORePK8gMaC0QOYAMyEEuzJnyUi","user":"uQiRzpo4DXghDmr9QzzfQu27cmVRsG",
+     "title":"new device: dc:a6:32:a3:48:b1",
+     "message":"New device dc:a6:32:a3:48:b1 on br-lan as 192.168.1.50, Raspberry Pi Trading Ltd",
+     "priority":2,"retry":60,"expire":3600,"timestamp":1791953775}
+
+  Priorities go from -2 (no notification) to 2 (emergency). Info alerts are
+  sent quietly, at -1, warnings at the normal 0, and critical alerts at 1,
+  which bypasses the quiet hours of the Pushover app, unless mapped
+  otherwise. Emergency alerts also bypass the phone's do not disturb
+  setting, if the app is allowed to, and are repeated every retry until
+  acknowledged, or expired.
+*/
+
+///
+/// Channel pushing the alerts to phones through Pushover.
+///
+#[derive(Debug, Clone)]
+pub struct PushoverChannel {
+  name: String,
+  api_url: String,
+  token: String,
+  user: String,
+  devices: Vec<String>,
+  sound: Option<String>,
+  priorities: BTreeMap<Severity, i8>,
+  retry: Duration,
+  expire: Duration,
+  url: Option<Template>,
+  template: Option<Template>,
+  retries: u32,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+}
+
+impl PushoverChannel {
+  ///
+  /// Creates a channel.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - token: API token of the Pushover application.
+  ///  - user: Key of the user, or group, the alerts are pushed to.
+  ///
+  pub fn new(name: &str, token: &str, user: &str) -> Self {
+    PushoverChannel {
+      name: name.to_string(),
+      api_url: DEFAULT_API_URL.to_string(),
+      token: token.to_string(),
+      user: user.to_string(),
+      devices: Vec::new(),
+      sound: None,
+      priorities: BTreeMap::new(),
+      retry: DEFAULT_RETRY,
+      expire: DEFAULT_EXPIRE,
+      url: None,
+      template: None,
+      retries: DEFAULT_RETRIES,
+      timeout: DEFAULT_HTTP_TIMEOUT,
+      ca_file: None,
+    }
+  }
+
+  /// API to send through.
+  pub fn api_url(mut self, api_url: &str) -> Self {
+    self.api_url = api_url.trim_end_matches('/').to_string();
+    self
+  }
+
+  /// Pushes to a device of the user only, rather than all of them.
+  pub fn device(mut self, device: &str) -> Self {
+    self.devices.push(device.to_string());
+    self
+  }
+
+  /// Sound the notifications play, e.g. "siren".
+  pub fn sound(mut self, sound: &str) -> Self {
+    self.sound = Some(sound.to_string());
+    self
+  }
+
+  /// Priority of the alerts of a severity, from -2 to 2 (emergency).
+  pub fn priority(mut self, severity: Severity, priority: i8) -> Self {
+    self.priorities.insert(severity, priority);
+    self
+  }
+
+  ///
+  /// How emergency alerts are repeated until acknowledged.
+  ///
+  /// Args:
+  ///  - retry: Wait before repeating an alert, 30s at least.
+  ///  - expire: Time an alert is repeated for, 3h at most.
+  ///
+  pub fn emergency(mut self, retry: Duration, expire: Duration) -> Self {
+    self.retry = retry;
+    self.expire = expire;
+    self
+  }
+
+  /// Template of a URL shown along with the alerts, whose fields are
+  /// percent-encoded.
+  pub fn url(mut self, url: Template) -> Self {
+    self.url = Some(url);
+    self
+  }
+
+  /// Template of the messages.
+  pub fn template(mut self, template: Template) -> Self {
+    self.template = Some(template);
+    self
+  }
+
+  /// Retries of a failed delivery.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  /// Longest time a delivery attempt may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify the API with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// The message pushing an alert.
+  fn message(&self, alert: &Alert) -> Value {
+    let priority = self
+      .priorities
+      .get(&alert.severity)
+      .copied()
+      .unwrap_or(match alert.severity {
+        Severity::Info => -1,
+        Severity::Warning => 0,
+        Severity::Critical => 1,
+      });
+    let text = match &self.template {
+      Some(template) => template.render(alert),
+      None => alert.message(),
+    };
+
+    let mut message = Map::new();
+    message.insert("token".to_string(), json!(self.token));
+    message.insert("user".to_string(), json!(self.user));
+    message.insert(
+      "title".to_string(),
+      json!(truncate(&alert.title(), MAX_TITLE_LEN)),
+    );
+    message.insert(
+      "message".to_string(),
+      json!(truncate(&text, MAX_MESSAGE_LEN)),
+    );
+    message.insert("priority".to_string(), json!(priority));
+    if priority == EMERGENCY_PRIORITY {
+      message.insert("retry".to_string(), json!(self.retry.as_secs()));
+      message.insert("expire".to_string(), json!(self.expire.as_secs()));
+    }
+    if let Ok(time) = alert.time.duration_since(UNIX_EPOCH) {
+      message.insert("timestamp".to_string(), json!(time.as_secs()));
+    }
+    if !self.devices.is_empty() {
+      message.insert("device".to_string(), json!(self.devices.join(",")));
+    }
+    if let Some(sound) = &self.sound {
+      message.insert("sound".to_string(), json!(sound));
+    }
+    if let Some(url) = &self.url {
+      message.insert(
+        "url".to_string(),
+        json!(url.render_escaped(alert, encode_component)),
+      );
+    }
+    Value::Object(message)
+  }
+}
+
+impl Channel for PushoverChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let url = format!("{}/messages.json", self.api_url);
+    let mut request = HttpRequest::post(&url)
+      .header("Content-Type", "application/json")
+      .body(&self.message(alert).to_string())
+      .timeout(self.timeout);
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    deliver(&request, &url, self.retries).map(|_| ())
+  }
+}
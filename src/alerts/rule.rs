@@ -0,0 +1,228 @@
+use super::escalation::Escalation;
+use super::window::{LocalTime, TimeWindow};
+use super::{AlertEvent, Change, Severity};
+use crate::neighbors::{MacAddr, NudState};
+use crate::registry::{DeviceCategory, TrustLevel};
+use std::time::Duration;
+
+/// Time a device is gone for before offline rules fire, when unset.
+pub const DEFAULT_OFFLINE_AFTER: Duration = Duration::from_secs(300);
+
+///
+/// Rule raising an alert when a change of a poll matches every one of its
+/// conditions, conditions left empty matching any change.
+///
+/// ```
+/// use openwrt_netmon::alerts::{AlertEvent, Rule, Severity, TimeWindow};
+/// use openwrt_netmon::registry::TrustLevel;
+///
+/// let rule = Rule::new("untrusted guest at night", AlertEvent::Joined)
+///   .severity(Severity::Critical)
+///   .interfaces(vec!["br-guest".to_string()])
+///   .trust(vec![TrustLevel::Untrusted])
+///   .window(TimeWindow::new().after("23:00".parse()?).before("06:00".parse()?));
+/// assert_eq!(rule.name(), "untrusted guest at night");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+  name: String,
+  event: AlertEvent,
+  pub(super) severity: Severity,
+  devices: Vec<MacAddr>,
+  interfaces: Vec<String>,
+  vendors: Vec<String>,
+  trust: Vec<TrustLevel>,
+  categories: Vec<DeviceCategory>,
+  from_states: NudState,
+  to_states: NudState,
+  window: TimeWindow,
+  pub(super) quiet: Option<TimeWindow>,
+  pub(super) dedup: Option<Duration>,
+  pub(super) channels: Vec<String>,
+  pub(super) escalation: Option<Escalation>,
+  pub(super) on_event: Option<String>,
+  pub(super) offline_after: Duration,
+  pub(super) stale_timeout: Option<Duration>,
+}
+
+impl Rule {
+  /// Creates a rule firing on every change of a kind, at warning severity.
+  pub fn new(name: &str, event: AlertEvent) -> Self {
+    Rule {
+      name: name.to_string(),
+      event,
+      severity: Severity::default(),
+      devices: Vec::new(),
+      interfaces: Vec::new(),
+      vendors: Vec::new(),
+      trust: Vec::new(),
+      categories: Vec::new(),
+      from_states: NudState::empty(),
+      to_states: NudState::empty(),
+      window: TimeWindow::new(),
+      quiet: None,
+      dedup: None,
+      channels: Vec::new(),
+      escalation: None,
+      on_event: None,
+      offline_after: DEFAULT_OFFLINE_AFTER,
+      stale_timeout: None,
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn event(&self) -> AlertEvent {
+    self.event
+  }
+
+  /// Severity of the rule's alerts.
+  pub fn severity(mut self, severity: Severity) -> Self {
+    self.severity = severity;
+    self
+  }
+
+  /// Devices the rule applies to, by MAC address.
+  pub fn devices(mut self, devices: Vec<MacAddr>) -> Self {
+    self.devices = devices;
+    self
+  }
+
+  /// Interfaces the rule applies to.
+  pub fn interfaces(mut self, interfaces: Vec<String>) -> Self {
+    self.interfaces = interfaces;
+    self
+  }
+
+  /// Vendors the rule applies to, matching part of the vendor's name
+  /// regardless of case, e.g. "espressif".
+  pub fn vendors(mut self, vendors: Vec<String>) -> Self {
+    self.vendors = vendors.iter().map(|v| v.to_lowercase()).collect();
+    self
+  }
+
+  /// Trust levels the rule applies to, devices without one counting as
+  /// untrusted.
+  pub fn trust(mut self, trust: Vec<TrustLevel>) -> Self {
+    self.trust = trust;
+    self
+  }
+
+  /// Categories of the registry the rule applies to.
+  pub fn categories(mut self, categories: Vec<DeviceCategory>) -> Self {
+    self.categories = categories;
+    self
+  }
+
+  /// States a neighbor leaves for the rule to apply, for state changes.
+  pub fn from_states(mut self, from_states: NudState) -> Self {
+    self.from_states = from_states;
+    self
+  }
+
+  /// States a neighbor enters for the rule to apply, for state changes.
+  pub fn to_states(mut self, to_states: NudState) -> Self {
+    self.to_states = to_states;
+    self
+  }
+
+  /// Local time the rule applies at.
+  pub fn window(mut self, window: TimeWindow) -> Self {
+    self.window = window;
+    self
+  }
+
+  /// Local time the rule's alerts are held at, rather than delivered, until
+  /// the window closes, unless critical.
+  pub fn quiet(mut self, quiet: TimeWindow) -> Self {
+    self.quiet = Some(quiet);
+    self
+  }
+
+  /// Window the rule's alerts about a device are collapsed within once
+  /// delivered, summed up once it's over.
+  pub fn dedup(mut self, dedup: Duration) -> Self {
+    self.dedup = Some(dedup);
+    self
+  }
+
+  /// Channels the rule's alerts are delivered to, by name, every channel if
+  /// empty.
+  pub fn channels(mut self, channels: Vec<String>) -> Self {
+    self.channels = channels;
+    self
+  }
+
+  /// Escalates the rule's alerts until acknowledged, delivering them to the
+  /// channels of the escalation's first step, rather than the rule's.
+  pub fn escalation(mut self, escalation: Escalation) -> Self {
+    self.channels = escalation.first().to_vec();
+    self.escalation = Some(escalation);
+    self
+  }
+
+  /// Command line run by sh on every alert the rule raises, e.g.
+  /// "/etc/netmon/lights.sh on", with the alert in its environment and on
+  /// its stdin. Its alerts are delivered to no channel unless it lists some.
+  pub fn on_event(mut self, on_event: &str) -> Self {
+    self.on_event = Some(on_event.to_string());
+    self
+  }
+
+  /// Time a device is gone for before the rule fires, once, for offline
+  /// rules, or before it fires once the device is back, for returned rules.
+  pub fn offline_after(mut self, offline_after: Duration) -> Self {
+    self.offline_after = offline_after;
+    self
+  }
+
+  ///
+  /// Time a device may stay STALE without being confirmed before it counts
+  /// as gone, for offline and returned rules. STALE devices are never gone by default,
+  /// as idle devices stay STALE until traffic is sent to them.
+  ///
+  pub fn stale_timeout(mut self, stale_timeout: Duration) -> Self {
+    self.stale_timeout = Some(stale_timeout);
+    self
+  }
+
+  /// Whether a change at a local time matches the rule.
+  pub(super) fn matches(&self, change: &Change, local: LocalTime) -> bool {
+    let device = &change.device;
+    let any = |states: NudState, state: Option<NudState>| {
+      states.is_empty() || state.is_some_and(|v| v.intersects(states))
+    };
+    let muted = device
+      .policy
+      .as_ref()
+      .is_some_and(|v| v.mutes(change.event.name()));
+    change.event == self.event
+      && !muted
+      && (self.devices.is_empty() || change.mac_addr.is_some_and(|v| self.devices.contains(&v)))
+      && (self.interfaces.is_empty()
+        || change
+          .iface
+          .as_ref()
+          .is_some_and(|v| self.interfaces.contains(v)))
+      && (self.vendors.is_empty()
+        || device.vendor.as_ref().is_some_and(|vendor| {
+          let vendor = vendor.to_lowercase();
+          self.vendors.iter().any(|v| vendor.contains(v.as_str()))
+        }))
+      && (self.trust.is_empty()
+        || self
+          .trust
+          .contains(&device.trust.unwrap_or(TrustLevel::Untrusted)))
+      && (self.categories.is_empty()
+        || device
+          .category
+          .is_some_and(|v| self.categories.contains(&v)))
+      && any(self.from_states, change.old_state)
+      && any(self.to_states, change.new_state)
+      && self.window.contains(local)
+  }
+}
@@ -0,0 +1,213 @@
+use crate::neighbors::MacAddr;
+use anyhow::{Error, Result};
+use log::debug;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// File the devices seen so far are kept in when no other path is given, on
+/// the overlay so it survives reboots.
+pub const DEFAULT_SEEN_PATH: &str = "/etc/netmon/seen.tsv";
+
+/*
+  The file is tab separated, one device per line, along with the time it was
+  first seen at and the interfaces it lives on, those it was seen on during
+  the learning period or else first:
+
+    # mac	first_seen	interfaces
+    dc:a6:32:a3:48:b1	2026-10-14T04:36:15Z	br-lan
+    3c:22:fb:10:02:7e	2026-10-14T05:12:40Z	br-lan,br-guest
+
+  Files written before interfaces were kept have no third column. It's only
+  rewritten when a device or an interface is learned, to spare the flash.
+*/
+
+/// Header written at the top of the file.
+const HEADER: &str = "# mac\tfirst_seen\tinterfaces";
+
+///
+/// Every device seen on the network so far, by MAC address, telling the
+/// devices never seen before apart.
+///
+/// ```
+/// use openwrt_netmon::alerts::SeenDevices;
+/// use std::time::SystemTime;
+///
+/// let mut seen: SeenDevices = "dc:a6:32:a3:48:b1\t2026-10-14T04:36:15Z\tbr-lan\n".parse()?;
+/// assert!(seen.contains(&"dc:a6:32:a3:48:b1".parse()?));
+/// assert_eq!(seen.interfaces(&"dc:a6:32:a3:48:b1".parse()?), ["br-lan"]);
+/// assert!(seen.insert("3c:22:fb:10:02:7e".parse()?, SystemTime::now()));
+/// assert!(!seen.insert("3c:22:fb:10:02:7e".parse()?, SystemTime::now()));
+/// assert!(seen.add_interface("3c:22:fb:10:02:7e".parse()?, "br-guest"));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeenDevices {
+  devices: BTreeMap<MacAddr, SystemTime>,
+  /// Interfaces the devices live on, in the order they were learned.
+  interfaces: BTreeMap<MacAddr, Vec<String>>,
+}
+
+impl SeenDevices {
+  pub fn new() -> Self {
+    SeenDevices::default()
+  }
+
+  ///
+  /// Reads the devices seen so far.
+  ///
+  /// Args:
+  ///  - path: File of the devices.
+  ///
+  /// Returns:
+  ///  Result of the devices, none if the file doesn't exist yet.
+  ///
+  pub fn load(path: &Path) -> Result<SeenDevices> {
+    match std::fs::read_to_string(path) {
+      Ok(content) => content
+        .parse()
+        .map_err(|e| Error::msg(format!("Invalid seen devices {}: {}", path.display(), e))),
+      Err(e) if e.kind() == ErrorKind::NotFound => {
+        debug!("No seen devices at {}", path.display());
+        Ok(SeenDevices::new())
+      }
+      Err(e) => Err(Error::msg(format!(
+        "Failed to read {}: {}",
+        path.display(),
+        e
+      ))),
+    }
+  }
+
+  ///
+  /// Writes the devices atomically, through a temporary file and a rename,
+  /// creating their directory.
+  ///
+  /// Args:
+  ///  - path: File of the devices.
+  ///
+  pub fn save(&self, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, self.to_string())
+      .and_then(|_| std::fs::rename(&tmp_path, path))
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", path.display(), e)))
+  }
+
+  pub fn contains(&self, mac_addr: &MacAddr) -> bool {
+    self.devices.contains_key(mac_addr)
+  }
+
+  /// Records a device, returning whether it was never seen before.
+  pub fn insert(&mut self, mac_addr: MacAddr, time: SystemTime) -> bool {
+    let new = !self.contains(&mac_addr);
+    if new {
+      self.devices.insert(mac_addr, time);
+    }
+    new
+  }
+
+  /// Interfaces a device lives on, none if they were never learned.
+  pub fn interfaces(&self, mac_addr: &MacAddr) -> &[String] {
+    self
+      .interfaces
+      .get(mac_addr)
+      .map(|v| v.as_slice())
+      .unwrap_or_default()
+  }
+
+  /// Records an interface a device lives on, returning whether it's new.
+  pub fn add_interface(&mut self, mac_addr: MacAddr, iface: &str) -> bool {
+    let interfaces = self.interfaces.entry(mac_addr).or_default();
+    let new = !interfaces.iter().any(|v| v == iface);
+    if new {
+      interfaces.push(iface.to_string());
+    }
+    new
+  }
+
+  /// Time a device was first seen at.
+  pub fn first_seen(&self, mac_addr: &MacAddr) -> Option<SystemTime> {
+    self.devices.get(mac_addr).copied()
+  }
+
+  /// Time the first device was seen at, None if there's none yet.
+  pub fn since(&self) -> Option<SystemTime> {
+    self.devices.values().min().copied()
+  }
+
+  ///
+  /// Whether devices seen at a time are still learned, rather than new:
+  /// during the learning period following the first device ever seen.
+  ///
+  /// Args:
+  ///  - period: Learning period.
+  ///  - time: Time devices are seen at.
+  ///
+  pub fn is_learning(&self, period: Duration, time: SystemTime) -> bool {
+    let since = self.since().unwrap_or(time);
+    time < since + period
+  }
+
+  pub fn len(&self) -> usize {
+    self.devices.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.devices.is_empty()
+  }
+}
+
+impl fmt::Display for SeenDevices {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "{}", HEADER)?;
+    for (mac_addr, time) in &self.devices {
+      writeln!(
+        f,
+        "{}\t{}\t{}",
+        mac_addr,
+        humantime::format_rfc3339_seconds(*time),
+        self.interfaces(mac_addr).join(",")
+      )?;
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for SeenDevices {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let mut seen = SeenDevices::new();
+    for (index, line) in s.lines().enumerate() {
+      let line = line.trim_end_matches('\r');
+      if line.trim().is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let invalid = |what: &str| Error::msg(format!("line {}: {}", index + 1, what));
+      let mut fields = line.split('\t');
+      let (Some(mac_addr), Some(time)) = (fields.next(), fields.next()) else {
+        return Err(invalid("expected a MAC address and a time"));
+      };
+      let mac_addr = mac_addr
+        .trim()
+        .parse()
+        .map_err(|_| invalid(&format!("invalid MAC address '{}'", mac_addr)))?;
+      let time = humantime::parse_rfc3339(time.trim())
+        .map_err(|_| invalid(&format!("invalid time '{}'", time)))?;
+      seen.insert(mac_addr, time);
+      let interfaces = fields.next().unwrap_or_default().split(',');
+      for iface in interfaces.map(str::trim).filter(|v| !v.is_empty()) {
+        seen.add_interface(mac_addr, iface);
+      }
+    }
+    Ok(seen)
+  }
+}
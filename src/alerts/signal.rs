@@ -0,0 +1,254 @@
+use super::channel::details;
+use super::webhook::{deliver, DEFAULT_RETRIES};
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::HttpRequest;
+use crate::neighbors::run_command_input;
+use anyhow::{Error, Result};
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Command sending the messages when none is configured.
+pub const DEFAULT_COMMAND: &str = "signal-cli";
+
+/// Longest time a delivery may take when none is configured, as signal-cli
+/// starts a JVM every time it's run.
+pub const DEFAULT_SIGNAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/*
+  Without a url, every alert runs signal-cli, the message on its stdin:
+
+    signal-cli -a +15551234567 send --message-from-stdin +15557654321 -g 3mP1m...=
+
+  signal-cli keeps the account locked while it runs, so the CLI can't send
+  while a signal-cli daemon runs for the same account, e.g. to receive
+  messages. Such a daemon, started with --http, is sent the alerts as
+  JSON-RPC calls POSTed to its url's /api/v1/rpc instead:
+
+    {"jsonrpc":"2.0","id":1,"method":"send",
+     "params":{"account":"+15551234567","recipient":["+15557654321"],
+               "groupId":["3mP1m...="],"message":"🚨 new device: ..."}}
+
+  which also spares the router running the JVM, as the daemon may run on
+  another host.
+*/
+
+/// Counter making the JSON-RPC ids of the process unique.
+static REQUESTS: AtomicU64 = AtomicU64::new(1);
+
+///
+/// Channel sending the alerts over Signal, through signal-cli, run for every
+/// alert or as a JSON-RPC daemon.
+///
+/// Deliveries to a daemon are retried as webhook::deliver retries them; the
+/// CLI isn't retried.
+///
+#[derive(Debug, Clone)]
+pub struct SignalChannel {
+  name: String,
+  account: String,
+  recipients: Vec<String>,
+  groups: Vec<String>,
+  url: Option<String>,
+  command: String,
+  config_dir: Option<PathBuf>,
+  template: Option<Template>,
+  retries: u32,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+}
+
+impl SignalChannel {
+  ///
+  /// Creates a channel, sending with the CLI until given a daemon's url.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - account: Phone number of the account signal-cli sends as, e.g.
+  ///    "+15551234567", registered or linked beforehand.
+  ///
+  pub fn new(name: &str, account: &str) -> Self {
+    SignalChannel {
+      name: name.to_string(),
+      account: account.to_string(),
+      recipients: Vec::new(),
+      groups: Vec::new(),
+      url: None,
+      command: DEFAULT_COMMAND.to_string(),
+      config_dir: None,
+      template: None,
+      retries: DEFAULT_RETRIES,
+      timeout: DEFAULT_SIGNAL_TIMEOUT,
+      ca_file: None,
+    }
+  }
+
+  /// Sends to a user, by phone number, e.g. "+15557654321", username, or
+  /// ACI.
+  pub fn recipient(mut self, recipient: &str) -> Self {
+    self.recipients.push(recipient.to_string());
+    self
+  }
+
+  /// Sends to a group, by its base64 id, as `signal-cli listGroups` lists it.
+  pub fn group(mut self, group: &str) -> Self {
+    self.groups.push(group.to_string());
+    self
+  }
+
+  /// Sends through the JSON-RPC daemon at a URL, e.g.
+  /// "http://10.0.0.2:8080", rather than running the CLI.
+  pub fn url(mut self, url: &str) -> Self {
+    self.url = Some(url.trim_end_matches('/').to_string());
+    self
+  }
+
+  /// Command running the CLI, e.g. "/opt/signal-cli/bin/signal-cli".
+  pub fn command(mut self, command: &str) -> Self {
+    self.command = command.to_string();
+    self
+  }
+
+  /// Directory the CLI keeps its accounts in, instead of its default.
+  pub fn config_dir(mut self, config_dir: &Path) -> Self {
+    self.config_dir = Some(config_dir.to_path_buf());
+    self
+  }
+
+  /// Template of the messages.
+  pub fn template(mut self, template: Template) -> Self {
+    self.template = Some(template);
+    self
+  }
+
+  /// Retries of a failed delivery to a daemon.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  /// Longest time a delivery may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify the daemon with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// The message of an alert, in plain text.
+  fn text(&self, alert: &Alert) -> String {
+    if let Some(template) = &self.template {
+      return template.render(alert);
+    }
+    let icon = match alert.severity {
+      Severity::Info => "ℹ️",
+      Severity::Warning => "⚠️",
+      Severity::Critical => "🚨",
+    };
+    let mut text = format!("{} {}\n{}\n", icon, alert.title(), alert.message());
+    text.push('\n');
+    for (label, value) in details(alert) {
+      text.push_str(&format!("{}: {}\n", label, value));
+    }
+    text.push_str(&format!("Router: {}", alert.router));
+    text
+  }
+
+  /// Sends a message by running the CLI.
+  fn run(&self, text: &str) -> Result<()> {
+    let mut args = Vec::new();
+    if let Some(config_dir) = &self.config_dir {
+      args.push("--config".to_string());
+      args.push(config_dir.display().to_string());
+    }
+    args.extend(["-a".to_string(), self.account.clone()]);
+    args.extend(["send".to_string(), "--message-from-stdin".to_string()]);
+    args.extend(self.recipients.iter().cloned());
+    // The groups come last, as -g takes every argument after it.
+    if !self.groups.is_empty() {
+      args.push("-g".to_string());
+      args.extend(self.groups.iter().cloned());
+    }
+    run_command_input(&self.command, &args, text.as_bytes(), self.timeout)
+      .map(|_| ())
+      .map_err(|e| Error::msg(format!("Failed to send over Signal: {}", e)))
+  }
+
+  /// Sends a message through the JSON-RPC daemon.
+  fn call(&self, url: &str, text: &str) -> Result<()> {
+    let mut params = Map::new();
+    params.insert("account".to_string(), json!(self.account));
+    if !self.recipients.is_empty() {
+      params.insert("recipient".to_string(), json!(self.recipients));
+    }
+    if !self.groups.is_empty() {
+      params.insert("groupId".to_string(), json!(self.groups));
+    }
+    params.insert("message".to_string(), json!(text));
+    let call = json!({
+      "jsonrpc": "2.0",
+      "id": REQUESTS.fetch_add(1, Ordering::Relaxed),
+      "method": "send",
+      "params": params,
+    });
+
+    let url = format!("{}/api/v1/rpc", url);
+    let mut request = HttpRequest::post(&url)
+      .header("Content-Type", "application/json")
+      .body(&call.to_string())
+      .timeout(self.timeout);
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    let response = deliver(&request, &url, self.retries)?;
+    let body: Value = serde_json::from_str(&response.body).unwrap_or(Value::Null);
+    if let Some(error) = body.get("error") {
+      return Err(Error::msg(format!(
+        "signal-cli failed to send: {}",
+        error["message"].as_str().unwrap_or(response.body.trim())
+      )));
+    }
+    // A message is sent to the recipients it can be, and fails for the others.
+    let failed: Vec<String> = body["result"]["results"]
+      .as_array()
+      .into_iter()
+      .flatten()
+      .filter(|v| v["type"] != "SUCCESS")
+      .map(|v| {
+        let recipient = &v["recipientAddress"];
+        let recipient = recipient["number"]
+          .as_str()
+          .or(recipient["uuid"].as_str())
+          .or(v["groupId"].as_str())
+          .unwrap_or("?");
+        format!("{} ({})", recipient, v["type"].as_str().unwrap_or("?"))
+      })
+      .collect();
+    match failed.is_empty() {
+      true => Ok(()),
+      false => Err(Error::msg(format!(
+        "signal-cli failed to send to {}",
+        failed.join(", ")
+      ))),
+    }
+  }
+}
+
+impl Channel for SignalChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let text = self.text(alert);
+    match &self.url {
+      Some(url) => self.call(url, &text),
+      None => self.run(&text),
+    }
+  }
+}
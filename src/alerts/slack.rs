@@ -0,0 +1,350 @@
+use super::channel::{details, truncate};
+use super::webhook::{deliver, DEFAULT_RETRIES};
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::{HttpRequest, DEFAULT_HTTP_TIMEOUT};
+use crate::neighbors::MacAddr;
+use anyhow::{Error, Result};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Web API the bot posts through when none is configured.
+pub const DEFAULT_API_URL: &str = "https://slack.com/api";
+
+/// Longest time between alerts of a device threaded together, when none is
+/// configured.
+pub const DEFAULT_THREAD_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Longest text of a header block Slack takes, in characters.
+const MAX_HEADER_LEN: usize = 150;
+
+/// Longest text of a section block Slack takes, in characters.
+const MAX_SECTION_LEN: usize = 3000;
+
+/// Longest text of a section's field Slack takes, in characters.
+const MAX_FIELD_LEN: usize = 2000;
+
+/*
+  Alerts are posted as Block Kit messages, with a header, the message, what's
+  known of their device, and where and when it happened:
+
+    :rotating_light: untrusted guest at night: Living room TV
+    Living room TV (dc:a6:32:a3:48:b1) joined br-guest as 192.168.3.5
+
+    MAC                        IP
+    dc:a6:32:a3:48:b1          192.168.3.5
+    Interface                  Vendor
+    br-guest                   Raspberry Pi Trading Ltd
+
+    critical | office-ap | Oct 14th at 11:36 PM
+
+  Through an incoming webhook, they go to the webhook's channel. Through a
+  bot's token, they're posted with chat.postMessage to the channel of their
+  severity, else to the default one, and with threads, the alerts of a
+  device following one within the thread window are replied in its thread:
+
+    #security  :rotating_light: new device: dc:a6:32:a3:48:b1
+               └ :warning: NAS offline: dc:a6:32:a3:48:b1
+*/
+
+/// Where the messages are posted to.
+#[derive(Debug, Clone)]
+enum Destination {
+  /// Incoming webhook, posting to a single channel.
+  Webhook(String),
+  /// Bot posting to the channels it was invited to.
+  Bot {
+    api_url: String,
+    token: String,
+    channel: String,
+    severity_channels: BTreeMap<Severity, String>,
+  },
+}
+
+/// Latest message of a device in a channel, replied to in its thread.
+#[derive(Debug, Clone)]
+struct Thread {
+  ts: String,
+  time: SystemTime,
+}
+
+///
+/// Channel posting the alerts to Slack as Block Kit messages, through an
+/// incoming webhook or a bot.
+///
+#[derive(Debug, Clone)]
+pub struct SlackChannel {
+  name: String,
+  destination: Destination,
+  template: Option<Template>,
+  threads: bool,
+  thread_window: Duration,
+  retries: u32,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+  /// Threads of the devices, by channel and device.
+  device_threads: HashMap<(String, MacAddr), Thread>,
+}
+
+impl SlackChannel {
+  ///
+  /// Creates a channel posting through an incoming webhook.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - url: URL of the webhook, e.g.
+  ///    "https://hooks.slack.com/services/T000/B000/XXXX".
+  ///
+  pub fn webhook(name: &str, url: &str) -> Self {
+    SlackChannel::new(name, Destination::Webhook(url.to_string()))
+  }
+
+  ///
+  /// Creates a channel posting through a bot.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - token: Bot token of the Slack app, e.g. "xoxb-...", allowed to
+  ///    chat:write.
+  ///  - channel: Channel the alerts are posted to, e.g. "#alerts" or
+  ///    "C0123456789".
+  ///
+  pub fn bot(name: &str, token: &str, channel: &str) -> Self {
+    SlackChannel::new(
+      name,
+      Destination::Bot {
+        api_url: DEFAULT_API_URL.to_string(),
+        token: token.to_string(),
+        channel: channel.to_string(),
+        severity_channels: BTreeMap::new(),
+      },
+    )
+  }
+
+  fn new(name: &str, destination: Destination) -> Self {
+    SlackChannel {
+      name: name.to_string(),
+      destination,
+      template: None,
+      threads: false,
+      thread_window: DEFAULT_THREAD_WINDOW,
+      retries: DEFAULT_RETRIES,
+      timeout: DEFAULT_HTTP_TIMEOUT,
+      ca_file: None,
+      device_threads: HashMap::new(),
+    }
+  }
+
+  /// Web API a bot posts through. Ignored by webhooks.
+  pub fn api_url(mut self, url: &str) -> Self {
+    if let Destination::Bot { api_url, .. } = &mut self.destination {
+      *api_url = url.trim_end_matches('/').to_string();
+    }
+    self
+  }
+
+  /// Channel a bot posts the alerts of a severity to. Ignored by webhooks.
+  pub fn severity_channel(mut self, severity: Severity, channel: &str) -> Self {
+    if let Destination::Bot {
+      severity_channels, ..
+    } = &mut self.destination
+    {
+      severity_channels.insert(severity, channel.to_string());
+    }
+    self
+  }
+
+  /// Template of the messages, written in mrkdwn, whose fields are
+  /// escaped.
+  pub fn template(mut self, template: Template) -> Self {
+    self.template = Some(template);
+    self
+  }
+
+  /// Replies the alerts of a device in the thread of its latest alert, if
+  /// within the thread window. Only bots see their messages' threads.
+  pub fn threads(mut self, threads: bool) -> Self {
+    self.threads = threads;
+    self
+  }
+
+  /// Longest time between alerts of a device threaded together.
+  pub fn thread_window(mut self, thread_window: Duration) -> Self {
+    self.thread_window = thread_window;
+    self
+  }
+
+  /// Retries of a failed delivery.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  /// Longest time a delivery attempt may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify Slack with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// The request posting a message.
+  fn request(&self, url: &str, message: Map<String, Value>) -> HttpRequest {
+    let mut request = HttpRequest::post(url)
+      .header("Content-Type", "application/json; charset=utf-8")
+      .body(&Value::Object(message).to_string())
+      .timeout(self.timeout);
+    if let Destination::Bot { token, .. } = &self.destination {
+      request = request.header("Authorization", &format!("Bearer {}", token));
+    }
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    request
+  }
+}
+
+impl Channel for SlackChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let mut message = blocks(alert, self.template.as_ref());
+    let (api_url, channel) = match &self.destination {
+      Destination::Webhook(url) => {
+        return deliver(&self.request(url, message), url, self.retries).map(|_| ());
+      }
+      Destination::Bot {
+        api_url,
+        channel,
+        severity_channels,
+        ..
+      } => (
+        api_url,
+        severity_channels.get(&alert.severity).unwrap_or(channel),
+      ),
+    };
+    message.insert("channel".to_string(), json!(channel));
+
+    let window = self.thread_window;
+    self
+      .device_threads
+      .retain(|_, thread| alert.time < thread.time + window);
+    let key = alert
+      .mac_addr
+      .filter(|_| self.threads)
+      .map(|mac_addr| (channel.clone(), mac_addr));
+    if let Some(thread) = key.as_ref().and_then(|v| self.device_threads.get(v)) {
+      message.insert("thread_ts".to_string(), json!(thread.ts));
+    }
+
+    let url = format!("{}/chat.postMessage", api_url);
+    let response = deliver(&self.request(&url, message), &url, self.retries)?;
+    let body: Value = serde_json::from_str(&response.body).unwrap_or(Value::Null);
+    if body["ok"] != Value::Bool(true) {
+      return Err(Error::msg(format!(
+        "Slack failed to post to {}: {}",
+        channel,
+        body["error"].as_str().unwrap_or(response.body.trim())
+      )));
+    }
+    if let Some(key) = key {
+      // Replies are threaded under the thread's first message.
+      let ts = self
+        .device_threads
+        .get(&key)
+        .map(|v| v.ts.clone())
+        .or_else(|| body["ts"].as_str().map(str::to_string));
+      if let Some(ts) = ts {
+        self.device_threads.insert(
+          key,
+          Thread {
+            ts,
+            time: alert.time,
+          },
+        );
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The Block Kit message of an alert, along with its notification's text.
+fn blocks(alert: &Alert, template: Option<&Template>) -> Map<String, Value> {
+  let icon = match alert.severity {
+    Severity::Info => ":information_source:",
+    Severity::Warning => ":warning:",
+    Severity::Critical => ":rotating_light:",
+  };
+  let fields: Vec<Value> = details(alert)
+    .into_iter()
+    .map(|(name, value)| {
+      let text = format!("*{}*\n{}", name, escape_mrkdwn(&value));
+      json!({ "type": "mrkdwn", "text": truncate(&text, MAX_FIELD_LEN) })
+    })
+    .collect();
+
+  let text = match template {
+    Some(template) => template.render_escaped(alert, escape_mrkdwn),
+    None => escape_mrkdwn(&alert.message()),
+  };
+
+  let rfc3339 = humantime::format_rfc3339_seconds(alert.time).to_string();
+  let epoch = alert
+    .time
+    .duration_since(UNIX_EPOCH)
+    .map(|v| v.as_secs())
+    .unwrap_or_default();
+  // Slack shows the time in the reader's time zone.
+  let context = format!(
+    "{} | {} | <!date^{}^{{date_short_pretty}} at {{time}}|{}>",
+    alert.severity,
+    escape_mrkdwn(&alert.router),
+    epoch,
+    rfc3339
+  );
+
+  let mut blocks = vec![
+    json!({
+      "type": "header",
+      "text": {
+        "type": "plain_text",
+        "text": truncate(&format!("{} {}", icon, alert.title()), MAX_HEADER_LEN),
+        "emoji": true,
+      },
+    }),
+    json!({
+      "type": "section",
+      "text": {
+        "type": "mrkdwn",
+        "text": truncate(&text, MAX_SECTION_LEN),
+      },
+    }),
+  ];
+  if !fields.is_empty() {
+    blocks.push(json!({ "type": "section", "fields": fields }));
+  }
+  blocks.push(json!({
+    "type": "context",
+    "elements": [{ "type": "mrkdwn", "text": context }],
+  }));
+
+  let mut message = Map::new();
+  message.insert("text".to_string(), json!(alert.title()));
+  message.insert("blocks".to_string(), Value::Array(blocks));
+  message
+}
+
+/// Escapes a value for Slack's mrkdwn, so it's shown as is.
+pub fn escape_mrkdwn(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
@@ -0,0 +1,125 @@
+use super::{device_facts, AlertEvent, Change};
+use crate::daemon::PollReport;
+use crate::neighbors::{ArpTable, MacAddr, NudState, ScopedIpAddr};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+/// Time an address stays bound to its MAC address once no longer seen, a
+/// different MAC address taking it over within this time being spoofing.
+pub const BINDING_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/*
+  Every address in a valid state is bound to the MAC address it answers
+  from. Another MAC address answering for it while bound raises an
+  arp_spoofing change, unless it holds the address's DHCP lease, as when the
+  lease of a device gone went to another:
+
+    poll       10:00              10:01
+    table      192.168.1.5 aa:..  192.168.1.5 bb:..  (bb holds no lease)
+    change                        bb took over 192.168.1.5 from aa
+
+  So does a MAC address answering for a gateway's IPv4 address, those of the
+  default routes or configured, along with other addresses on its interface,
+  as a host poisoning the others' caches to sit between them and the
+  gateway does. It's raised once until the gateway is back to itself.
+*/
+
+/// MAC address an address answers from.
+#[derive(Debug, Clone)]
+struct Binding {
+  mac_addr: MacAddr,
+  last_seen: SystemTime,
+}
+
+/// Bindings of addresses to MAC addresses, for arp_spoofing rules.
+#[derive(Debug, Default)]
+pub(super) struct Bindings {
+  bound: HashMap<ScopedIpAddr, Binding>,
+  /// Gateways claimed, by the MAC address claiming them.
+  claimed: HashSet<(ScopedIpAddr, MacAddr)>,
+}
+
+impl Bindings {
+  ///
+  /// Binds the addresses of a poll, returning the spoofing seen as changes.
+  ///
+  /// Args:
+  ///  - report: Poll to bind the addresses of.
+  ///  - gateways: Addresses of the gateways.
+  ///
+  pub(super) fn changes(&mut self, report: &PollReport, gateways: &[ScopedIpAddr]) -> Vec<Change> {
+    let time = report.snapshot.taken_at;
+    let entries: Vec<(&ArpTable, MacAddr)> = report
+      .snapshot
+      .entries
+      .iter()
+      .filter(|v| v.nud_state.intersects(NudState::VALID))
+      .filter_map(|v| Some((v, v.mac_addr?)))
+      .collect();
+    let leased = |entry: &ArpTable, mac_addr: MacAddr| {
+      report.devices.iter().any(|v| {
+        v.mac_addr == mac_addr && v.lease.as_ref().is_some_and(|lease| lease.ip == entry.ip)
+      })
+    };
+    let change = |entry: &ArpTable, mac_addr: MacAddr| Change {
+      event: AlertEvent::ArpSpoofing,
+      mac_addr: Some(mac_addr),
+      ip: Some(entry.scoped_ip()),
+      iface: Some(entry.iface.clone()),
+      old_iface: None,
+      old_value: None,
+      new_value: None,
+      old_state: None,
+      new_state: None,
+      last_seen: None,
+      device: device_facts(report, &mac_addr),
+    };
+
+    let mut changes = Vec::new();
+    for (entry, mac_addr) in &entries {
+      let binding = Binding {
+        mac_addr: *mac_addr,
+        last_seen: time,
+      };
+      let Some(bound) = self.bound.get_mut(&entry.scoped_ip()) else {
+        self.bound.insert(entry.scoped_ip(), binding);
+        continue;
+      };
+      let timed_out = time.duration_since(bound.last_seen).unwrap_or_default() >= BINDING_TIMEOUT;
+      if bound.mac_addr != *mac_addr && !timed_out && !leased(entry, *mac_addr) {
+        changes.push(Change {
+          old_value: Some(bound.mac_addr.to_string()),
+          ..change(entry, *mac_addr)
+        });
+      }
+      *bound = binding;
+    }
+    self
+      .bound
+      .retain(|_, v| time.duration_since(v.last_seen).unwrap_or_default() < BINDING_TIMEOUT);
+
+    let is_gateway = |entry: &ArpTable| entry.ip.is_ipv4() && gateways.contains(&entry.scoped_ip());
+    let mut claimed = HashSet::new();
+    for (entry, mac_addr) in entries.iter().filter(|(v, _)| is_gateway(v)) {
+      let others: Vec<String> = entries
+        .iter()
+        .filter(|(v, mac)| mac == mac_addr && v.iface == entry.iface)
+        .filter(|(v, _)| v.ip.is_ipv4() && !is_gateway(v))
+        .map(|(v, _)| v.ip.to_string())
+        .collect();
+      if others.is_empty() {
+        continue;
+      }
+      let key = (entry.scoped_ip(), *mac_addr);
+      if !self.claimed.contains(&key) {
+        changes.push(Change {
+          new_value: Some(others.join(",")),
+          ..change(entry, *mac_addr)
+        });
+      }
+      claimed.insert(key);
+    }
+    self.claimed = claimed;
+    changes
+  }
+}
@@ -0,0 +1,85 @@
+use super::limit::{Dedup, RateLimiter};
+use super::spoof::Bindings;
+use super::{Alert, Presence};
+use crate::neighbors::MacAddr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/*
+  The alert sink is rebuilt out of the config on every reload. What it
+  learned along the way, rather than was configured with, is handed over to
+  the sink replacing it through the AlertState both are built with: the old
+  sink keeps it there once flushed, and the new one takes it on its first
+  poll.
+
+    presence    when and where the devices were last seen, so devices away
+                across the reload go offline, and are back, as they would
+    offline     offline alerts raised, by rule name, until the device is back
+    held        alerts held during quiet hours, by channel
+    dedup       repeats collapsed within their rule's dedup window
+    limits      alerts delivered and held back during the channels' rate
+                limit periods, unless their limit changed
+    violations  devices on an interface whose policy doesn't allow them
+    rogue       rogue DHCP servers and routers raised, until they go quiet
+    elsewhere   devices on an interface they don't live on
+    bindings    addresses bound to MAC addresses, and gateways claimed
+
+  State of rules, channels, policies, and watches gone with the reload is
+  dropped. Once the daemon stops, nothing would take it, so the sink
+  delivers the alerts it holds when flushed instead, quiet hours or not, and
+  sums up the repeats and alerts over rate limits it held back, rather than
+  losing them.
+*/
+
+/// What a sink learned, once kept.
+#[derive(Debug, Default)]
+pub(super) struct Kept {
+  pub(super) presence: HashMap<MacAddr, Presence>,
+  pub(super) offline: HashSet<(String, MacAddr)>,
+  pub(super) held: HashMap<String, VecDeque<Alert>>,
+  pub(super) dedup: Dedup,
+  pub(super) limits: HashMap<String, RateLimiter>,
+  pub(super) violations: HashSet<(String, MacAddr)>,
+  pub(super) rogue: HashMap<IpAddr, SystemTime>,
+  pub(super) elsewhere: HashSet<MacAddr>,
+  pub(super) bindings: Bindings,
+}
+
+///
+/// What the alert sink learned, handed over to the sink replacing it on a
+/// reload. Cloning it shares its state.
+///
+#[derive(Debug, Clone, Default)]
+pub struct AlertState {
+  inner: Arc<Mutex<Option<Kept>>>,
+  stopping: Arc<AtomicBool>,
+}
+
+impl AlertState {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Tells the sinks the daemon stops, so they deliver what they hold once
+  /// flushed rather than keep it.
+  pub fn stop(&self) {
+    self.stopping.store(true, Ordering::Relaxed);
+  }
+
+  pub(super) fn is_stopping(&self) -> bool {
+    self.stopping.load(Ordering::Relaxed)
+  }
+
+  /// Keeps what a sink learned for the next one to take.
+  pub(super) fn keep(&self, kept: Kept) {
+    *self.inner.lock().unwrap() = Some(kept);
+  }
+
+  /// Takes what the sink before learned, if anything.
+  pub(super) fn take(&self) -> Option<Kept> {
+    self.inner.lock().unwrap().take()
+  }
+}
@@ -0,0 +1,436 @@
+use super::channel::{details, truncate};
+use super::escalation::{Escalations, MAX_ESCALATING};
+use super::{Alert, Channel, Severity, Template};
+use crate::daemon::http::{redact, HttpRequest, DEFAULT_HTTP_TIMEOUT};
+use crate::daemon::PollReport;
+use anyhow::{Error, Result};
+use log::{debug, error, warn};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Bot API the messages are sent through when none is configured.
+pub const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+/// Longest time a request for the bot's updates waits for one.
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Wait before asking for updates again after a failure.
+const POLL_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Longest message Telegram takes, in characters.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+/*
+  Alerts are sent with sendMessage, formatted with MarkdownV2, along with
+  what's known of their device:
+
+    🚨 *untrusted guest at night: Living room TV*
+    Living room TV \(dc:a6:32:a3:48:b1\) joined br\-guest as 192\.168\.3\.5
+
+    *MAC:* dc:a6:32:a3:48:b1
+    *IP:* 192\.168\.3\.5
+    *Interface:* br\-guest
+    *Vendor:* Raspberry Pi Trading Ltd
+    *Router:* office\-ap
+
+  or as the channel's template renders them, the fields being escaped for
+  MarkdownV2. With commands, the bot long-polls getUpdates and answers
+  /devices, from the configured chat only, with the devices online as of the
+  latest poll.
+
+  Escalating alerts end with the command acknowledging them, e.g. /ack_17,
+  which the bot also takes as "/ack 17", or as "ack" or /ack replied to the
+  alert's message.
+*/
+
+///
+/// Channel sending the alerts to a Telegram chat through a bot, e.g. made
+/// with @BotFather, which can also answer /devices and acknowledge alerts.
+///
+#[derive(Debug)]
+pub struct TelegramChannel {
+  name: String,
+  api_url: String,
+  bot_token: String,
+  chat_id: String,
+  template: Option<Template>,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+  commands: bool,
+  /// Devices online as of the latest poll, one line each, for /devices.
+  devices: Arc<Mutex<Vec<String>>>,
+  escalations: Option<Escalations>,
+  /// Ids of the escalating alerts' messages, and those acknowledging them,
+  /// for replies.
+  acks: Arc<Mutex<VecDeque<(i64, u32)>>>,
+  /// Set once dropped, stopping the thread answering commands.
+  stopped: Arc<AtomicBool>,
+  started: bool,
+}
+
+impl TelegramChannel {
+  ///
+  /// Creates a channel.
+  ///
+  /// Args:
+  ///  - name: Name rules route their alerts by.
+  ///  - bot_token: Token of the bot, e.g. "123456:ABC-DEF...".
+  ///  - chat_id: Chat the alerts are sent to, e.g. "-1001234567890" or
+  ///    "@my_channel".
+  ///
+  pub fn new(name: &str, bot_token: &str, chat_id: &str) -> Self {
+    TelegramChannel {
+      name: name.to_string(),
+      api_url: DEFAULT_API_URL.to_string(),
+      bot_token: bot_token.to_string(),
+      chat_id: chat_id.to_string(),
+      template: None,
+      timeout: DEFAULT_HTTP_TIMEOUT,
+      ca_file: None,
+      commands: false,
+      devices: Arc::new(Mutex::new(Vec::new())),
+      escalations: None,
+      acks: Arc::new(Mutex::new(VecDeque::new())),
+      stopped: Arc::new(AtomicBool::new(false)),
+      started: false,
+    }
+  }
+
+  /// Bot API to send through, e.g. a local Bot API server.
+  pub fn api_url(mut self, api_url: &str) -> Self {
+    self.api_url = api_url.trim_end_matches('/').to_string();
+    self
+  }
+
+  /// Template of the messages, written in MarkdownV2.
+  pub fn template(mut self, template: Template) -> Self {
+    self.template = Some(template);
+    self
+  }
+
+  /// Longest time sending a message may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify the Bot API with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// Answers /devices in the chat, with the devices online.
+  pub fn commands(mut self, commands: bool) -> Self {
+    self.commands = commands;
+    self
+  }
+
+  /// Acknowledges escalating alerts, when answering commands, by /ack.
+  pub fn escalations(mut self, escalations: Escalations) -> Self {
+    self.escalations = Some(escalations);
+    self
+  }
+
+  /// Starts the thread answering commands.
+  fn start(&self) -> Result<()> {
+    let bot = Bot {
+      api: Api {
+        method_url: format!("{}/bot{}", self.api_url, self.bot_token),
+        ca_file: self.ca_file.clone(),
+      },
+      chat_id: self.chat_id.clone(),
+      devices: self.devices.clone(),
+      escalations: self.escalations.clone(),
+      acks: self.acks.clone(),
+      stopped: self.stopped.clone(),
+    };
+    std::thread::Builder::new()
+      .name(format!("telegram-{}", self.name))
+      .spawn(move || bot.run())
+      .map(|_| ())
+      .map_err(|e| Error::msg(format!("Failed to spawn the telegram thread: {}", e)))
+  }
+
+  /// The message of an alert, in MarkdownV2.
+  fn text(&self, alert: &Alert) -> String {
+    if let Some(template) = &self.template {
+      return template.render_escaped(alert, escape_markdown);
+    }
+    let icon = match alert.severity {
+      Severity::Info => "ℹ️",
+      Severity::Warning => "⚠️",
+      Severity::Critical => "🚨",
+    };
+    let mut text = format!(
+      "{} *{}*\n{}\n",
+      icon,
+      escape_markdown(&alert.title()),
+      escape_markdown(&alert.message())
+    );
+    text.push('\n');
+    for (label, value) in details(alert) {
+      text.push_str(&format!("*{}:* {}\n", label, escape_markdown(&value)));
+    }
+    text.push_str(&format!("*Router:* {}\n", escape_markdown(&alert.router)));
+    if let Some(id) = alert.ack_id.filter(|_| self.acknowledges()) {
+      text.push_str(&format!("\n/ack\\_{} to acknowledge\n", id));
+    }
+    text
+  }
+
+  /// Whether the bot acknowledges escalating alerts.
+  fn acknowledges(&self) -> bool {
+    self.commands && self.escalations.is_some()
+  }
+}
+
+impl Channel for TelegramChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let api = Api {
+      method_url: format!("{}/bot{}", self.api_url, self.bot_token),
+      ca_file: self.ca_file.clone(),
+    };
+    let message = json!({
+      "chat_id": self.chat_id,
+      "text": truncate(&self.text(alert), MAX_MESSAGE_LEN),
+      "parse_mode": "MarkdownV2",
+      "link_preview_options": { "is_disabled": true },
+    });
+    let sent = api.call("sendMessage", &message, self.timeout)?;
+    let message_id = sent["message_id"].as_i64();
+    let (Some(message_id), Some(id)) = (message_id, alert.ack_id) else {
+      return Ok(());
+    };
+    if let (true, Ok(mut acks)) = (self.acknowledges(), self.acks.lock()) {
+      if acks.len() == MAX_ESCALATING {
+        acks.pop_front();
+      }
+      acks.push_back((message_id, id));
+    }
+    Ok(())
+  }
+
+  fn update(&mut self, report: &PollReport) {
+    if !self.commands {
+      return;
+    }
+    let mut devices: Vec<String> = report
+      .devices
+      .iter()
+      .filter(|v| v.online)
+      .map(|device| {
+        let ips: Vec<String> = device.ips().map(|v| v.to_string()).collect();
+        let label = report
+          .name_of(&device.mac_addr)
+          .map(|v| format!("{} ({})", v, device.mac_addr))
+          .unwrap_or_else(|| device.mac_addr.to_string());
+        match &device.vendor {
+          Some(vendor) => format!(
+            "{} on {}: {} [{}]",
+            label,
+            device.iface,
+            ips.join(", "),
+            vendor
+          ),
+          None => format!("{} on {}: {}", label, device.iface, ips.join(", ")),
+        }
+      })
+      .collect();
+    devices.sort();
+    if let Ok(mut shared) = self.devices.lock() {
+      *shared = devices;
+    }
+    if !self.started {
+      self.started = true;
+      if let Err(err) = self.start() {
+        error!("Not answering commands on '{}': {}", self.name, err);
+      }
+    }
+  }
+}
+
+impl Drop for TelegramChannel {
+  fn drop(&mut self) {
+    self.stopped.store(true, Ordering::Relaxed);
+  }
+}
+
+/// Methods of the Bot API for a bot.
+#[derive(Debug, Clone)]
+struct Api {
+  /// URL of the bot's methods, holding its token.
+  method_url: String,
+  ca_file: Option<PathBuf>,
+}
+
+impl Api {
+  /// Calls a method, returning its result.
+  fn call(&self, method: &str, params: &Value, timeout: Duration) -> Result<Value> {
+    let url = format!("{}/{}", self.method_url, method);
+    let mut request = HttpRequest::post(&url)
+      .header("Content-Type", "application/json")
+      .body(&params.to_string())
+      .timeout(timeout);
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    let response = request.send()?;
+    let body: Value = serde_json::from_str(&response.body).unwrap_or(Value::Null);
+    if !response.is_success() || body["ok"] != Value::Bool(true) {
+      let description = body["description"]
+        .as_str()
+        .unwrap_or(response.body.trim())
+        .to_string();
+      return Err(Error::msg(format!(
+        "{} answered {} to {}: {}",
+        redact(&url),
+        response.status,
+        method,
+        description
+      )));
+    }
+    Ok(body["result"].clone())
+  }
+}
+
+/// Answers the commands sent to a bot, until its channel is dropped.
+struct Bot {
+  api: Api,
+  chat_id: String,
+  devices: Arc<Mutex<Vec<String>>>,
+  escalations: Option<Escalations>,
+  acks: Arc<Mutex<VecDeque<(i64, u32)>>>,
+  stopped: Arc<AtomicBool>,
+}
+
+impl Bot {
+  fn run(self) {
+    let mut offset = 0;
+    while !self.stopped.load(Ordering::Relaxed) {
+      let params = json!({
+        "offset": offset,
+        "timeout": POLL_TIMEOUT.as_secs(),
+        "allowed_updates": ["message"],
+      });
+      let updates = match self
+        .api
+        .call("getUpdates", &params, POLL_TIMEOUT + DEFAULT_HTTP_TIMEOUT)
+      {
+        Ok(updates) => updates,
+        Err(err) => {
+          // Also while a reloaded channel's bot still polls.
+          warn!("Failed to get the Telegram bot's updates: {}", err);
+          std::thread::sleep(POLL_BACKOFF);
+          continue;
+        }
+      };
+      for update in updates.as_array().into_iter().flatten() {
+        if let Some(id) = update["update_id"].as_i64() {
+          offset = offset.max(id + 1);
+        }
+        if !self.stopped.load(Ordering::Relaxed) {
+          self.answer(&update["message"]);
+        }
+      }
+    }
+  }
+
+  /// Answers a message, if it's a command from the configured chat.
+  fn answer(&self, message: &Value) {
+    let chat = &message["chat"];
+    let from_chat = [&chat["id"], &chat["username"]].iter().any(|v| match v {
+      Value::Number(id) => id.to_string() == self.chat_id,
+      Value::String(username) => format!("@{}", username) == self.chat_id,
+      _ => false,
+    });
+    let Some(text) = message["text"].as_str() else {
+      return;
+    };
+    if !from_chat {
+      debug!("Ignoring a Telegram message from chat {}", chat["id"]);
+      return;
+    }
+    // Commands may be addressed to the bot, e.g. /devices@netmon_bot.
+    let mut words = text.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let reply = match command.split('@').next().unwrap_or_default() {
+      "/devices" => {
+        let devices = self.devices.lock().map(|v| v.clone()).unwrap_or_default();
+        match devices.is_empty() {
+          true => "No device is online.".to_string(),
+          false => format!("{} devices online:\n{}", devices.len(), devices.join("\n")),
+        }
+      }
+      name @ ("/ack" | "ack") if self.escalations.is_some() => {
+        let id = match words.next() {
+          Some(id) => id.parse().ok(),
+          None => self.replied_ack(&message["reply_to_message"]),
+        };
+        match (id, name) {
+          (Some(id), _) => self.acknowledge(id),
+          (None, "ack") => return,
+          (None, _) => "Send /ack <id>, or reply /ack to an alert.".to_string(),
+        }
+      }
+      name if name.starts_with("/ack_") && self.escalations.is_some() => {
+        match name["/ack_".len()..].parse() {
+          Ok(id) => self.acknowledge(id),
+          Err(_) => return,
+        }
+      }
+      "/help" | "/start" if self.escalations.is_some() => "/devices lists the devices online.\n\
+         /ack <id> acknowledges an escalating alert, as does /ack replied to it."
+        .to_string(),
+      "/help" | "/start" => "/devices lists the devices online.".to_string(),
+      _ => return,
+    };
+    let params = json!({
+      "chat_id": chat["id"],
+      "text": truncate(&reply, MAX_MESSAGE_LEN),
+      "reply_parameters": { "message_id": message["message_id"] },
+    });
+    if let Err(err) = self.api.call("sendMessage", &params, DEFAULT_HTTP_TIMEOUT) {
+      warn!("Failed to answer {}: {}", command, err);
+    }
+  }
+
+  /// Id acknowledging the alert of a message replied to, if it escalates.
+  fn replied_ack(&self, replied: &Value) -> Option<u32> {
+    let message_id = replied["message_id"].as_i64()?;
+    let acks = self.acks.lock().ok()?;
+    acks.iter().find(|v| v.0 == message_id).map(|v| v.1)
+  }
+
+  /// Acknowledges an alert, returning the reply.
+  fn acknowledge(&self, id: u32) -> String {
+    let acknowledged = self
+      .escalations
+      .as_ref()
+      .is_some_and(|v| v.acknowledge(id, "on Telegram"));
+    match acknowledged {
+      true => format!("Acknowledged alert {}.", id),
+      false => format!("Alert {} isn't escalating.", id),
+    }
+  }
+}
+
+/// Escapes a value for MarkdownV2, so it's shown as is.
+pub fn escape_markdown(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
@@ -0,0 +1,587 @@
+use super::channel::truncate;
+use super::Alert;
+use anyhow::{Error, Result};
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+/*
+  Templates are text with placeholders naming a field of the alert, replaced
+  by its value, or left empty if the alert has none:
+
+    {{ title }}: {{ message }}
+
+  Filters transform the value, from left to right. The json filter, last if
+  any, writes it as a JSON string instead, or null, so templates can build
+  JSON bodies:
+
+    {"text": {{ message | json }}, "owner": {{ owner | default("nobody") | json }}}
+    {{ severity | upper }} {{ hostname | default(mac) | truncate(20) }}
+
+  Blocks keep their text only if a field is set, or is, or isn't, a value:
+
+    {% if hostname %}{{ hostname }} ({{ mac }}){% else %}{{ mac }}{% endif %}
+    {% if severity == "critical" %}@here {% elif not owner %}unowned {% endif %}
+
+  A dash inside a tag, as in {%- and -%}, or {{- and -}}, trims the
+  whitespace before or after it, so blocks can sit on lines of their own.
+*/
+
+/// Fields of an alert a template can name.
+pub const FIELDS: [&str; 24] = [
+  "rule",
+  "severity",
+  "event",
+  "time",
+  "router",
+  "mac",
+  "ip",
+  "iface",
+  "old_iface",
+  "old_value",
+  "new_value",
+  "last_seen",
+  "name",
+  "hostname",
+  "vendor",
+  "owner",
+  "category",
+  "trust",
+  "label",
+  "title",
+  "message",
+  "downtime",
+  "count",
+  "ack_id",
+];
+
+/// Filters a placeholder can apply.
+pub const FILTERS: [&str; 5] = ["default", "upper", "lower", "truncate", "json"];
+
+/// Transformation of a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+  /// Text, or the value of another field, standing in for an unset or empty
+  /// value.
+  Default(Operand),
+  Upper,
+  Lower,
+  /// Cuts the value down to a length in characters, ending it with an
+  /// ellipsis.
+  Truncate(usize),
+  Json,
+}
+
+/// Literal text or a field, as filters and conditions take them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+  Text(String),
+  Field(&'static str),
+}
+
+/// Test of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+  /// The field has a non-empty value.
+  Set(&'static str),
+  /// The field has no value, or an empty one.
+  Unset(&'static str),
+  Equals(&'static str, Operand),
+  Differs(&'static str, Operand),
+}
+
+/// Part of a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+  Text(String),
+  Field {
+    field: &'static str,
+    filters: Vec<Filter>,
+  },
+  /// The pieces of the first branch whose condition holds, else the
+  /// otherwise ones.
+  Block {
+    branches: Vec<(Condition, Vec<Piece>)>,
+    otherwise: Vec<Piece>,
+  },
+}
+
+/// Tag ending the pieces of a branch.
+enum Tag {
+  If(Condition),
+  Elif(Condition),
+  Else,
+  Endif,
+}
+
+///
+/// Text with placeholders replaced by the fields of an alert, and blocks
+/// kept or left out depending on them.
+///
+/// ```
+/// use openwrt_netmon::alerts::Template;
+///
+/// let template: Template = "{{ rule }}: {{ mac | json }}".parse()?;
+/// assert_eq!(template.fields().collect::<Vec<_>>(), ["rule", "mac"]);
+/// let template: Template =
+///   "{% if hostname %}{{ hostname | upper }}{% else %}{{ mac }}{% endif %}".parse()?;
+/// assert_eq!(template.fields().collect::<Vec<_>>(), ["hostname", "hostname", "mac"]);
+/// assert!("{{ colour }}".parse::<Template>().is_err());
+/// assert!("{% if owner %}{{ owner }}".parse::<Template>().is_err());
+/// assert!("{%}".parse::<Template>().is_err());
+/// assert!("{{}}".parse::<Template>().is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+  pieces: Vec<Piece>,
+}
+
+impl Template {
+  /// Fields the template names, in order.
+  pub fn fields(&self) -> impl Iterator<Item = &'static str> + '_ {
+    let mut fields = Vec::new();
+    collect_fields(&self.pieces, &mut fields);
+    fields.into_iter()
+  }
+
+  /// The template with the fields of an alert in place.
+  pub fn render(&self, alert: &Alert) -> String {
+    self.render_escaped(alert, str::to_string)
+  }
+
+  ///
+  /// The template with the fields of an alert in place, escaped for the
+  /// markup the template is written in, e.g. Markdown.
+  ///
+  /// Args:
+  ///  - alert: Alert whose fields replace the placeholders.
+  ///  - escape: Escapes a value, except those of the json filter.
+  ///
+  pub fn render_escaped(&self, alert: &Alert, escape: impl Fn(&str) -> String) -> String {
+    let mut rendered = String::new();
+    render_pieces(&self.pieces, alert, &escape, &mut rendered);
+    rendered
+  }
+}
+
+impl FromStr for Template {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let mut rest = s;
+    let (pieces, tag) = parse_pieces(&mut rest)?;
+    match tag {
+      None => Ok(Template { pieces }),
+      Some(Tag::Elif(_)) => Err(Error::msg("'{% elif %}' outside of an '{% if %}' block")),
+      Some(Tag::Else) => Err(Error::msg("'{% else %}' outside of an '{% if %}' block")),
+      Some(Tag::Endif) => Err(Error::msg("'{% endif %}' outside of an '{% if %}' block")),
+      // parse_pieces parses the blocks it meets.
+      Some(Tag::If(_)) => unreachable!(),
+    }
+  }
+}
+
+///
+/// Parses pieces up to the end of the template, or a tag ending a branch.
+///
+/// Args:
+///  - rest: Template left to parse, advanced past what was parsed.
+///
+/// Returns:
+///  Result of the pieces, and of the tag that ended them, None at the end
+///  of the template.
+///
+fn parse_pieces(rest: &mut &str) -> Result<(Vec<Piece>, Option<Tag>)> {
+  let mut pieces = Vec::new();
+  loop {
+    let start = match (rest.find("{{"), rest.find("{%")) {
+      (Some(a), Some(b)) => a.min(b),
+      (Some(a), None) | (None, Some(a)) => a,
+      (None, None) => {
+        if !rest.is_empty() {
+          pieces.push(Piece::Text(rest.to_string()));
+        }
+        *rest = "";
+        return Ok((pieces, None));
+      }
+    };
+    let is_tag = rest[start..].starts_with("{%");
+    let close = if is_tag { "%}" } else { "}}" };
+    // Past the opening, so "{%}" isn't taken for a tag closing itself.
+    let end = rest[start + 2..].find(close).ok_or_else(|| {
+      Error::msg(format!(
+        "Unterminated {}, expected '{}'",
+        if is_tag { "tag" } else { "placeholder" },
+        close
+      ))
+    })?;
+    let mut inner = &rest[start + 2..start + 2 + end];
+    let mut text = &rest[..start];
+    let mut after = &rest[start + 2 + end + 2..];
+    if let Some(trimmed) = inner.strip_prefix('-') {
+      inner = trimmed;
+      text = text.trim_end();
+    }
+    if let Some(trimmed) = inner.strip_suffix('-') {
+      inner = trimmed;
+      after = after.trim_start();
+    }
+    if !text.is_empty() {
+      pieces.push(Piece::Text(text.to_string()));
+    }
+    *rest = after;
+
+    if !is_tag {
+      pieces.push(parse_placeholder(inner)?);
+      continue;
+    }
+    match parse_tag(inner)? {
+      Tag::If(condition) => pieces.push(parse_block(rest, condition)?),
+      tag => return Ok((pieces, Some(tag))),
+    }
+  }
+}
+
+/// Parses the branches of a block, past its if tag.
+fn parse_block(rest: &mut &str, condition: Condition) -> Result<Piece> {
+  let mut branches = Vec::new();
+  let mut condition = condition;
+  loop {
+    let (pieces, tag) = parse_pieces(rest)?;
+    branches.push((condition, pieces));
+    match tag {
+      Some(Tag::Elif(next)) => condition = next,
+      Some(Tag::Else) => {
+        let (otherwise, tag) = parse_pieces(rest)?;
+        return match tag {
+          Some(Tag::Endif) => Ok(Piece::Block {
+            branches,
+            otherwise,
+          }),
+          Some(Tag::Else) => Err(Error::msg("Block with two '{% else %}'")),
+          Some(Tag::Elif(_)) => Err(Error::msg("'{% elif %}' after '{% else %}'")),
+          _ => Err(Error::msg(
+            "Unterminated '{% if %}', expected '{% endif %}'",
+          )),
+        };
+      }
+      Some(Tag::Endif) => {
+        return Ok(Piece::Block {
+          branches,
+          otherwise: Vec::new(),
+        })
+      }
+      _ => {
+        return Err(Error::msg(
+          "Unterminated '{% if %}', expected '{% endif %}'",
+        ))
+      }
+    }
+  }
+}
+
+/// Parses a placeholder, e.g. "hostname | default(mac) | upper".
+fn parse_placeholder(placeholder: &str) -> Result<Piece> {
+  let mut parts = split_unquoted(placeholder, '|').into_iter();
+  let field = parse_field(parts.next().unwrap_or_default().trim())?;
+  let mut filters = Vec::new();
+  for filter in parts {
+    if filters.last() == Some(&Filter::Json) {
+      return Err(Error::msg("The json filter must come last"));
+    }
+    filters.push(parse_filter(filter.trim())?);
+  }
+  Ok(Piece::Field { field, filters })
+}
+
+/// Parses a filter, e.g. "truncate(20)".
+fn parse_filter(filter: &str) -> Result<Filter> {
+  let (name, arg) = match filter.split_once('(') {
+    Some((name, arg)) => {
+      let arg = arg
+        .strip_suffix(')')
+        .ok_or_else(|| Error::msg(format!("Unterminated filter '{}', expected ')'", filter)))?;
+      (name.trim(), Some(arg.trim()))
+    }
+    None => (filter, None),
+  };
+  match (name, arg) {
+    ("default", Some(arg)) => Ok(Filter::Default(parse_operand(arg)?)),
+    ("truncate", Some(arg)) => arg
+      .parse()
+      .ok()
+      .filter(|v| *v > 0)
+      .map(Filter::Truncate)
+      .ok_or_else(|| Error::msg(format!("Invalid length '{}' of the truncate filter", arg))),
+    ("upper", None) => Ok(Filter::Upper),
+    ("lower", None) => Ok(Filter::Lower),
+    ("json", None) => Ok(Filter::Json),
+    ("default" | "truncate", None) => Err(Error::msg(format!(
+      "The {} filter takes an argument, e.g. {}",
+      name,
+      if name == "default" {
+        "default(\"unknown\")"
+      } else {
+        "truncate(20)"
+      }
+    ))),
+    ("upper" | "lower" | "json", Some(_)) => {
+      Err(Error::msg(format!("The {} filter takes no argument", name)))
+    }
+    _ => Err(Error::msg(format!(
+      "Unknown filter '{}', expected one of {}",
+      name,
+      FILTERS.join(", ")
+    ))),
+  }
+}
+
+/// Parses a tag, e.g. "if severity == 'critical'".
+fn parse_tag(tag: &str) -> Result<Tag> {
+  let tag = tag.trim();
+  let (keyword, rest) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+  match (keyword, rest.trim()) {
+    ("if", condition) => Ok(Tag::If(parse_condition(condition)?)),
+    ("elif", condition) => Ok(Tag::Elif(parse_condition(condition)?)),
+    ("else", "") => Ok(Tag::Else),
+    ("endif", "") => Ok(Tag::Endif),
+    _ => Err(Error::msg(format!(
+      "Unknown tag '{{% {} %}}', expected if, elif, else, or endif",
+      tag
+    ))),
+  }
+}
+
+/// Parses the condition of an if or elif tag.
+fn parse_condition(condition: &str) -> Result<Condition> {
+  if condition.is_empty() {
+    return Err(Error::msg(
+      "Tag without a condition, e.g. '{% if hostname %}'",
+    ));
+  }
+  if let Some(field) = condition.strip_prefix("not ") {
+    return Ok(Condition::Unset(parse_field(field.trim())?));
+  }
+  for (operator, equals) in [("==", true), ("!=", false)] {
+    if let Some((field, operand)) = condition.split_once(operator) {
+      let field = parse_field(field.trim())?;
+      let operand = parse_operand(operand.trim())?;
+      return Ok(match equals {
+        true => Condition::Equals(field, operand),
+        false => Condition::Differs(field, operand),
+      });
+    }
+  }
+  Ok(Condition::Set(parse_field(condition)?))
+}
+
+/// Parses a quoted text, e.g. "'critical'", or a field.
+fn parse_operand(operand: &str) -> Result<Operand> {
+  for quote in ['"', '\''] {
+    if let Some(text) = operand.strip_prefix(quote) {
+      return text
+        .strip_suffix(quote)
+        .map(|v| Operand::Text(v.to_string()))
+        .ok_or_else(|| Error::msg(format!("Unterminated text {}", operand)));
+    }
+  }
+  parse_field(operand).map(Operand::Field)
+}
+
+/// The field of a name.
+fn parse_field(name: &str) -> Result<&'static str> {
+  FIELDS.into_iter().find(|v| *v == name).ok_or_else(|| {
+    Error::msg(format!(
+      "Unknown field '{}', expected one of {}",
+      name,
+      FIELDS.join(", ")
+    ))
+  })
+}
+
+/// Splits a text at a separator, except within quotes.
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+  let mut parts = Vec::new();
+  let mut quote = None;
+  let mut start = 0;
+  for (i, c) in text.char_indices() {
+    match (quote, c) {
+      (None, '"' | '\'') => quote = Some(c),
+      (Some(q), c) if q == c => quote = None,
+      (None, c) if c == separator => {
+        parts.push(&text[start..i]);
+        start = i + c.len_utf8();
+      }
+      _ => {}
+    }
+  }
+  parts.push(&text[start..]);
+  parts
+}
+
+/// Appends the fields pieces name, in order.
+fn collect_fields(pieces: &[Piece], fields: &mut Vec<&'static str>) {
+  let operand = |operand: &Operand, fields: &mut Vec<&'static str>| {
+    if let Operand::Field(field) = operand {
+      fields.push(field);
+    }
+  };
+  for piece in pieces {
+    match piece {
+      Piece::Text(_) => {}
+      Piece::Field { field, filters } => {
+        fields.push(field);
+        for filter in filters {
+          if let Filter::Default(default) = filter {
+            operand(default, fields);
+          }
+        }
+      }
+      Piece::Block {
+        branches,
+        otherwise,
+      } => {
+        for (condition, pieces) in branches {
+          match condition {
+            Condition::Set(field) | Condition::Unset(field) => fields.push(field),
+            Condition::Equals(field, value) | Condition::Differs(field, value) => {
+              fields.push(field);
+              operand(value, fields);
+            }
+          }
+          collect_fields(pieces, fields);
+        }
+        collect_fields(otherwise, fields);
+      }
+    }
+  }
+}
+
+/// Appends the rendering of pieces.
+fn render_pieces(
+  pieces: &[Piece],
+  alert: &Alert,
+  escape: &impl Fn(&str) -> String,
+  rendered: &mut String,
+) {
+  for piece in pieces {
+    match piece {
+      Piece::Text(text) => rendered.push_str(text),
+      Piece::Field { field, filters } => {
+        let mut value = field_value(alert, field);
+        let mut json = false;
+        for filter in filters {
+          value = match filter {
+            Filter::Default(default) => match value.filter(|v| !v.is_empty()) {
+              Some(value) => Some(value),
+              None => operand_value(alert, default),
+            },
+            Filter::Upper => value.map(|v| v.to_uppercase()),
+            Filter::Lower => value.map(|v| v.to_lowercase()),
+            Filter::Truncate(max_len) => value.map(|v| truncate(&v, *max_len)),
+            Filter::Json => {
+              json = true;
+              value
+            }
+          };
+        }
+        match (value, json) {
+          (Some(value), true) => rendered.push_str(&json_string(&value)),
+          (None, true) => rendered.push_str("null"),
+          (Some(value), false) => rendered.push_str(&escape(&value)),
+          (None, false) => {}
+        }
+      }
+      Piece::Block {
+        branches,
+        otherwise,
+      } => {
+        let branch = branches
+          .iter()
+          .find(|(condition, _)| holds(condition, alert))
+          .map(|(_, pieces)| pieces)
+          .unwrap_or(otherwise);
+        render_pieces(branch, alert, escape, rendered);
+      }
+    }
+  }
+}
+
+/// Whether a condition holds for an alert.
+fn holds(condition: &Condition, alert: &Alert) -> bool {
+  let is_set = |field| field_value(alert, field).is_some_and(|v| !v.is_empty());
+  match condition {
+    Condition::Set(field) => is_set(field),
+    Condition::Unset(field) => !is_set(field),
+    Condition::Equals(field, operand) => {
+      field_value(alert, field).unwrap_or_default()
+        == operand_value(alert, operand).unwrap_or_default()
+    }
+    Condition::Differs(field, operand) => {
+      field_value(alert, field).unwrap_or_default()
+        != operand_value(alert, operand).unwrap_or_default()
+    }
+  }
+}
+
+/// Value of an operand, None for a field the alert has none of.
+fn operand_value(alert: &Alert, operand: &Operand) -> Option<String> {
+  match operand {
+    Operand::Text(text) => Some(text.clone()),
+    Operand::Field(field) => field_value(alert, field),
+  }
+}
+
+/// Value of a field of an alert, None if the alert has none.
+pub(super) fn field_value(alert: &Alert, field: &str) -> Option<String> {
+  let time = |time| humantime::format_rfc3339_seconds(time).to_string();
+  match field {
+    "rule" => Some(alert.rule.clone()),
+    "severity" => Some(alert.severity.to_string()),
+    "event" => Some(alert.event.to_string()),
+    "time" => Some(time(alert.time)),
+    "router" => Some(alert.router.clone()),
+    "mac" => alert.mac_addr.map(|v| v.to_string()),
+    "ip" => alert.ip.as_ref().map(|v| v.to_string()),
+    "iface" => alert.iface.clone(),
+    "old_iface" => alert.old_iface.clone(),
+    "old_value" => alert.old_value.clone(),
+    "new_value" => alert.new_value.clone(),
+    "last_seen" => alert.last_seen.map(time),
+    "name" => alert.device.name.clone(),
+    "hostname" => alert.device.hostname.clone(),
+    "vendor" => alert.device.vendor.clone(),
+    "owner" => alert.device.owner.clone(),
+    "category" => alert.device.category.map(|v| v.to_string()),
+    "trust" => alert.device.trust.map(|v| v.to_string()),
+    "label" => Some(alert.label()),
+    "title" => Some(alert.title()),
+    "message" => Some(alert.message()),
+    "downtime" => alert.downtime(),
+    "count" => alert.summary.map(|v| v.count().to_string()),
+    "ack_id" => alert.ack_id.map(|v| v.to_string()),
+    _ => None,
+  }
+}
+
+/// A value as a JSON string, quoted and escaped.
+pub fn json_string(value: &str) -> String {
+  let mut quoted = String::with_capacity(value.len() + 2);
+  quoted.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '\t' => quoted.push_str("\\t"),
+      c if (c as u32) < 0x20 => {
+        let _ = write!(quoted, "\\u{:04x}", c as u32);
+      }
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
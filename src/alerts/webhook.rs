@@ -0,0 +1,249 @@
+use super::{Alert, Channel, Template};
+use crate::daemon::http::{redact, HttpRequest, HttpResponse, DEFAULT_HTTP_TIMEOUT};
+use anyhow::{Error, Result};
+use hmac::{Hmac, KeyInit, Mac};
+use log::warn;
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Header holding the HMAC-SHA256 of the body, when signing.
+pub const SIGNATURE_HEADER: &str = "X-Netmon-Signature-256";
+
+/// Retries of a failed delivery when none is configured.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// Wait before the first retry, doubled before each of the others.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/*
+  Alerts are POSTed as JSON, keys sorted, leaving out what's unknown:
+
+    {"device":{"trust":"untrusted","vendor":"Raspberry Pi Trading Ltd"},
+     "event":"joined","iface":"br-guest","mac":"dc:a6:32:a3:48:b1",
+     "message":"dc:a6:32:a3:48:b1 joined br-guest as 192.168.3.5",
+     "new_value":"192.168.3.5","router":"office-ap",
+     "rule":"untrusted guest at night","severity":"critical",
+     "time":"2026-10-14T23:36:15Z",
+     "title":"untrusted guest at night: dc:a6:32:a3:48:b1"}
+
+  or as the channel's body template renders them. With a secret, the body is
+  signed as GitHub signs its webhooks, so receivers can check it came from
+  the monitor:
+
+    X-Netmon-Signature-256: sha256=5c3e2a...
+*/
+
+///
+/// Channel POSTing the alerts to a URL, e.g. an n8n or Node-RED webhook.
+///
+#[derive(Debug, Clone)]
+pub struct WebhookChannel {
+  name: String,
+  url: String,
+  headers: Vec<(String, String)>,
+  body: Option<Template>,
+  secret: Option<String>,
+  retries: u32,
+  timeout: Duration,
+  ca_file: Option<PathBuf>,
+}
+
+impl WebhookChannel {
+  pub fn new(name: &str, url: &str) -> Self {
+    WebhookChannel {
+      name: name.to_string(),
+      url: url.to_string(),
+      headers: Vec::new(),
+      body: None,
+      secret: None,
+      retries: DEFAULT_RETRIES,
+      timeout: DEFAULT_HTTP_TIMEOUT,
+      ca_file: None,
+    }
+  }
+
+  /// Adds a header to every request, e.g. an Authorization token.
+  pub fn header(mut self, name: &str, value: &str) -> Self {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+
+  /// Template of the body, instead of the alert's JSON.
+  pub fn body(mut self, body: Template) -> Self {
+    self.body = Some(body);
+    self
+  }
+
+  /// Signs the body with HMAC-SHA256, keyed by a secret shared with the
+  /// receiver.
+  pub fn secret(mut self, secret: &str) -> Self {
+    self.secret = Some(secret.to_string());
+    self
+  }
+
+  /// Retries of a failed delivery.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  /// Longest time a delivery attempt may take.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// CA certificates to verify an https URL with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// The request delivering a body.
+  fn request(&self, body: &str) -> HttpRequest {
+    let mut request = HttpRequest::post(&self.url).timeout(self.timeout);
+    if !self
+      .headers
+      .iter()
+      .any(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+    {
+      let content_type = match self.body {
+        Some(_) => "text/plain; charset=utf-8",
+        None => "application/json",
+      };
+      request = request.header("Content-Type", content_type);
+    }
+    for (name, value) in &self.headers {
+      request = request.header(name, value);
+    }
+    if let Some(secret) = &self.secret {
+      request = request.header(SIGNATURE_HEADER, &sign(secret, body));
+    }
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    request.body(body)
+  }
+}
+
+impl Channel for WebhookChannel {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn send(&mut self, alert: &Alert) -> Result<()> {
+    let body = match &self.body {
+      Some(template) => template.render(alert),
+      None => alert_json(alert).to_string(),
+    };
+    deliver(&self.request(&body), &self.url, self.retries).map(|_| ())
+  }
+}
+
+///
+/// Sends a request, retrying it when failing to connect, or answered with a
+/// 5xx or 429 status, waiting twice as long before every retry, up to the
+/// channel's `retries` (DEFAULT_RETRIES): a server restarting or rate
+/// limiting then doesn't lose the alert, while one rejecting it isn't asked
+/// again.
+///
+/// Args:
+///  - request: Request delivering an alert.
+///  - url: URL of the request, redacted in errors.
+///  - retries: Retries of a failed delivery.
+///
+/// Returns:
+///  Result of the successful response.
+///
+pub(super) fn deliver(request: &HttpRequest, url: &str, retries: u32) -> Result<HttpResponse> {
+  let mut backoff = RETRY_BACKOFF;
+  let mut attempt = 0;
+  loop {
+    let error = match request.send() {
+      Ok(response) if response.is_success() => return Ok(response),
+      Ok(response) => {
+        let error = Error::msg(format!(
+          "{} answered {}: {}",
+          redact(url),
+          response.status,
+          response.body.trim()
+        ));
+        // Retrying won't fix a rejected alert.
+        if response.status < 500 && response.status != 429 {
+          return Err(error);
+        }
+        error
+      }
+      Err(err) => err,
+    };
+    if attempt >= retries {
+      return Err(error);
+    }
+    attempt += 1;
+    warn!(
+      "{}, retrying in {} ({}/{})",
+      error,
+      humantime::format_duration(backoff),
+      attempt,
+      retries
+    );
+    std::thread::sleep(backoff);
+    backoff *= 2;
+  }
+}
+
+/// The alert as JSON, leaving out what's unknown.
+pub fn alert_json(alert: &Alert) -> Value {
+  let mut object = Map::new();
+  let field = |object: &mut Map<String, Value>, name: &str, value: Option<String>| {
+    if let Some(value) = value {
+      object.insert(name.to_string(), Value::String(value));
+    }
+  };
+  let time = |time| humantime::format_rfc3339_seconds(time).to_string();
+  field(&mut object, "rule", Some(alert.rule.clone()));
+  field(&mut object, "severity", Some(alert.severity.to_string()));
+  field(&mut object, "event", Some(alert.event.to_string()));
+  field(&mut object, "time", Some(time(alert.time)));
+  field(&mut object, "router", Some(alert.router.clone()));
+  field(&mut object, "mac", alert.mac_addr.map(|v| v.to_string()));
+  field(&mut object, "ip", alert.ip.as_ref().map(|v| v.to_string()));
+  field(&mut object, "iface", alert.iface.clone());
+  field(&mut object, "old_iface", alert.old_iface.clone());
+  field(&mut object, "old_value", alert.old_value.clone());
+  field(&mut object, "new_value", alert.new_value.clone());
+  field(&mut object, "last_seen", alert.last_seen.map(time));
+
+  let mut device = Map::new();
+  let facts = &alert.device;
+  field(&mut device, "name", facts.name.clone());
+  field(&mut device, "hostname", facts.hostname.clone());
+  field(&mut device, "vendor", facts.vendor.clone());
+  field(&mut device, "owner", facts.owner.clone());
+  field(
+    &mut device,
+    "category",
+    facts.category.map(|v| v.to_string()),
+  );
+  field(&mut device, "trust", facts.trust.map(|v| v.to_string()));
+  object.insert("device".to_string(), Value::Object(device));
+  object.insert("title".to_string(), json!(alert.title()));
+  object.insert("message".to_string(), json!(alert.message()));
+  Value::Object(object)
+}
+
+/// The signature header's value of a body, e.g. "sha256=5c3e2a...".
+fn sign(secret: &str, body: &str) -> String {
+  let mut mac =
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+  mac.update(body.as_bytes());
+  let hash: String = mac
+    .finalize()
+    .into_bytes()
+    .iter()
+    .map(|v| format!("{:02x}", v))
+    .collect();
+  format!("sha256={}", hash)
+}
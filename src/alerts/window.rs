@@ -0,0 +1,219 @@
+use anyhow::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Day of the week, in the router's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Weekday {
+  Mon,
+  Tue,
+  Wed,
+  Thu,
+  Fri,
+  Sat,
+  Sun,
+}
+
+impl Weekday {
+  pub const ALL: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Weekday::Mon => "mon",
+      Weekday::Tue => "tue",
+      Weekday::Wed => "wed",
+      Weekday::Thu => "thu",
+      Weekday::Fri => "fri",
+      Weekday::Sat => "sat",
+      Weekday::Sun => "sun",
+    }
+  }
+
+  /// The day before.
+  pub fn previous(self) -> Weekday {
+    Weekday::ALL[(self as usize + 6) % 7]
+  }
+}
+
+impl fmt::Display for Weekday {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for Weekday {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Weekday::ALL
+      .into_iter()
+      .find(|v| v.name().eq_ignore_ascii_case(s))
+      .ok_or_else(|| Error::msg(format!("Unknown day '{}'", s)))
+  }
+}
+
+///
+/// Time of the day, to the minute.
+///
+/// ```
+/// use openwrt_netmon::alerts::TimeOfDay;
+///
+/// let time: TimeOfDay = "23:05".parse()?;
+/// assert_eq!((time.hour(), time.minute()), (23, 5));
+/// assert!("24:00".parse::<TimeOfDay>().is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct TimeOfDay {
+  /// Minutes since midnight.
+  minutes: u16,
+}
+
+impl TimeOfDay {
+  pub const MIDNIGHT: TimeOfDay = TimeOfDay { minutes: 0 };
+
+  /// A time of the day, None if its hour or minute is out of range.
+  pub fn new(hour: u8, minute: u8) -> Option<Self> {
+    (hour < 24 && minute < 60).then(|| TimeOfDay {
+      minutes: hour as u16 * 60 + minute as u16,
+    })
+  }
+
+  pub fn hour(self) -> u8 {
+    (self.minutes / 60) as u8
+  }
+
+  pub fn minute(self) -> u8 {
+    (self.minutes % 60) as u8
+  }
+}
+
+impl fmt::Display for TimeOfDay {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:02}:{:02}", self.hour(), self.minute())
+  }
+}
+
+impl FromStr for TimeOfDay {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let invalid = || Error::msg(format!("Invalid time of day '{}', expected HH:MM", s));
+    let (hour, minute) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let hour = hour.parse().map_err(|_| invalid())?;
+    let minute = minute.parse().map_err(|_| invalid())?;
+    TimeOfDay::new(hour, minute).ok_or_else(invalid)
+  }
+}
+
+extern "C" {
+  /// Re-reads the timezone, from $TZ or the system's.
+  fn tzset();
+}
+
+/// Day and time of the day in the router's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+  pub weekday: Weekday,
+  pub time: TimeOfDay,
+}
+
+impl LocalTime {
+  /// The local time at a point in time, in the timezone localtime(3) goes by.
+  pub fn at(time: SystemTime) -> Self {
+    let secs = time
+      .duration_since(UNIX_EPOCH)
+      .map(|v| v.as_secs())
+      .unwrap_or_default() as libc::time_t;
+    // SAFETY: tm is plain data, which localtime_r fills in.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+      tzset();
+      libc::localtime_r(&secs, &mut tm);
+    }
+    LocalTime {
+      weekday: Weekday::ALL[(tm.tm_wday as usize + 6) % 7],
+      time: TimeOfDay::new(tm.tm_hour as u8, tm.tm_min as u8).unwrap_or_default(),
+    }
+  }
+}
+
+///
+/// Recurring window of local time, e.g. from 23:00 to 07:00 on weekdays,
+/// wrapping past midnight when it ends before it starts. Windows wrapping
+/// past midnight belong to the day they start on.
+///
+/// ```
+/// use openwrt_netmon::alerts::{LocalTime, TimeOfDay, TimeWindow, Weekday};
+///
+/// let window = TimeWindow::new()
+///   .after("23:00".parse()?)
+///   .before("07:00".parse()?)
+///   .days(vec![Weekday::Fri]);
+/// let at = |weekday, time: &str| LocalTime { weekday, time: time.parse().unwrap() };
+/// assert!(window.contains(at(Weekday::Fri, "23:30")));
+/// assert!(window.contains(at(Weekday::Sat, "06:59")));
+/// assert!(!window.contains(at(Weekday::Fri, "06:59")));
+/// assert!(!window.contains(at(Weekday::Sat, "07:00")));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TimeWindow {
+  after: Option<TimeOfDay>,
+  before: Option<TimeOfDay>,
+  days: Vec<Weekday>,
+}
+
+impl TimeWindow {
+  /// A window open at all times.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Time the window opens at, midnight if unset.
+  pub fn after(mut self, after: TimeOfDay) -> Self {
+    self.after = Some(after);
+    self
+  }
+
+  /// Time the window closes at, midnight if unset.
+  pub fn before(mut self, before: TimeOfDay) -> Self {
+    self.before = Some(before);
+    self
+  }
+
+  /// Days the window opens on, every day if empty.
+  pub fn days(mut self, days: Vec<Weekday>) -> Self {
+    self.days = days;
+    self
+  }
+
+  /// Whether the window is open at all times.
+  pub fn is_always(&self) -> bool {
+    self.after.is_none() && self.before.is_none() && self.days.is_empty()
+  }
+
+  /// Whether the window is open at a local time.
+  pub fn contains(&self, local: LocalTime) -> bool {
+    let after = self.after.unwrap_or(TimeOfDay::MIDNIGHT);
+    let (open, opened_on) = match self.before {
+      Some(before) if after < before => (local.time >= after && local.time < before, local.weekday),
+      Some(before) if before < after && local.time < before => (true, local.weekday.previous()),
+      _ => (local.time >= after, local.weekday),
+    };
+    open && (self.days.is_empty() || self.days.contains(&opened_on))
+  }
+}
@@ -0,0 +1,279 @@
+use crate::config::Config;
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+use crate::config::SinkConfig;
+use crate::neighbors::run_command_timeout;
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Longest time tar may run for, e.g. compressing a large database.
+const TAR_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Copies of a redb database attempted before giving up, in case the daemon
+/// keeps committing during the copy.
+#[cfg(feature = "redb")]
+const REDB_COPY_ATTEMPTS: usize = 3;
+
+/*
+  A backup is a gzipped tarball of the files holding the monitor's state,
+  each at its path relative to /, the same layout as `sysupgrade -b`:
+
+    etc/netmon/config.toml
+    etc/netmon/devices.tsv
+    etc/netmon/history.db
+
+  So `sysupgrade -r`, or `tar -xzf backup.tar.gz -C /`, restores it too.
+  Databases are copied consistently, even while the daemon writes to them.
+*/
+
+/// What a backed up file holds, deciding how it's copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackupKind {
+  Config,
+  Registry,
+  /// Salt of the hashes of anonymized exports, so they keep matching.
+  Salt,
+  /// Devices seen so far, so restored routers don't report them as new.
+  Seen,
+  #[cfg(feature = "sqlite")]
+  Sqlite,
+  #[cfg(feature = "redb")]
+  Redb,
+}
+
+/// A file holding part of the monitor's state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupFile {
+  pub path: PathBuf,
+  pub kind: BackupKind,
+}
+
+///
+/// Lists the files a backup bundles: the config file, the registry of known
+/// devices, the salt of anonymized exports, the devices seen so far, and the
+/// databases of the storage sinks, as far as they exist.
+///
+/// Args:
+///  - config: Config listing the files.
+///  - config_path: Config file given on the command line, if any.
+///
+/// Returns:
+///  The files, with absolute paths.
+///
+pub fn backup_files(config: &Config, config_path: Option<&Path>) -> Vec<BackupFile> {
+  let mut files = Vec::new();
+  if let Some(path) = Config::source(config_path) {
+    files.push((path, BackupKind::Config));
+  }
+  files.push((config.registry.clone(), BackupKind::Registry));
+  files.push((config.privacy.salt_file.clone(), BackupKind::Salt));
+  files.push((config.new_devices.seen_file.clone(), BackupKind::Seen));
+  #[cfg(any(feature = "sqlite", feature = "redb"))]
+  for sink in &config.sinks {
+    match sink {
+      #[cfg(feature = "sqlite")]
+      SinkConfig::Sqlite(sink) => files.push((sink.path.clone(), BackupKind::Sqlite)),
+      #[cfg(feature = "redb")]
+      SinkConfig::Redb(sink) => files.push((sink.path.clone(), BackupKind::Redb)),
+      _ => {}
+    }
+  }
+
+  let mut backup_files: Vec<BackupFile> = Vec::new();
+  for (path, kind) in files {
+    let Ok(path) = std::path::absolute(&path) else {
+      continue;
+    };
+    if !path.is_file() {
+      debug!("Not backing up {}, as it doesn't exist", path.display());
+    } else if !backup_files.iter().any(|v| v.path == path) {
+      backup_files.push(BackupFile { path, kind });
+    }
+  }
+  backup_files
+}
+
+/// Directory files are staged in, removed once dropped.
+struct StagingDir(PathBuf);
+
+impl StagingDir {
+  fn new(purpose: &str) -> Result<StagingDir> {
+    let path = std::env::temp_dir().join(format!("netmon-{}.{}", purpose, std::process::id()));
+    if path.exists() {
+      std::fs::remove_dir_all(&path)
+        .map_err(|e| Error::msg(format!("Failed to remove {}: {}", path.display(), e)))?;
+    }
+    std::fs::create_dir_all(&path)
+      .map_err(|e| Error::msg(format!("Failed to create {}: {}", path.display(), e)))?;
+    Ok(StagingDir(path))
+  }
+}
+
+impl Drop for StagingDir {
+  fn drop(&mut self) {
+    if let Err(err) = std::fs::remove_dir_all(&self.0) {
+      warn!("Failed to remove {}: {}", self.0.display(), err);
+    }
+  }
+}
+
+/// Path of an absolute path within the archive, relative to /.
+fn archive_name(path: &Path) -> PathBuf {
+  path
+    .components()
+    .filter(|v| matches!(v, Component::Normal(_)))
+    .collect()
+}
+
+/// Creates a file's parent directory.
+fn create_parent(path: &Path) -> Result<()> {
+  match path.parent() {
+    Some(parent) => std::fs::create_dir_all(parent)
+      .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e))),
+    None => Ok(()),
+  }
+}
+
+/// Copies a redb database, checking the copy wasn't torn by a commit.
+#[cfg(feature = "redb")]
+fn copy_redb(path: &Path, dest: &Path) -> Result<()> {
+  let mut result = Ok(());
+  for _ in 0..REDB_COPY_ATTEMPTS {
+    result = std::fs::copy(path, dest)
+      .map_err(|e| Error::msg(format!("Failed to copy {}: {}", path.display(), e)))
+      .and_then(|_| crate::storage::kv::check_copy(dest));
+    if result.is_ok() {
+      break;
+    }
+  }
+  result
+}
+
+///
+/// Bundles files into a gzipped tarball, through busybox or GNU tar. The
+/// archive is written to a temporary file first, so a failed backup leaves
+/// any previous one in place.
+///
+/// Args:
+///  - archive: Tarball to write, e.g. "/tmp/netmon-backup.tar.gz".
+///  - files: Files to bundle (see backup_files).
+///
+pub fn create(archive: &Path, files: &[BackupFile]) -> Result<()> {
+  if files.is_empty() {
+    return Err(Error::msg("Nothing to back up"));
+  }
+  let staging = StagingDir::new("backup")?;
+  let mut names = Vec::new();
+  for file in files {
+    let name = archive_name(&file.path);
+    let dest = staging.0.join(&name);
+    create_parent(&dest)?;
+    match file.kind {
+      #[cfg(feature = "sqlite")]
+      BackupKind::Sqlite => crate::storage::sqlite::snapshot(&file.path, &dest)?,
+      #[cfg(feature = "redb")]
+      BackupKind::Redb => copy_redb(&file.path, &dest)?,
+      BackupKind::Config | BackupKind::Registry | BackupKind::Salt | BackupKind::Seen => {
+        std::fs::copy(&file.path, &dest)
+          .map_err(|e| Error::msg(format!("Failed to copy {}: {}", file.path.display(), e)))?;
+      }
+    }
+    names.push(name.into_os_string());
+  }
+
+  let mut tmp_path = archive.as_os_str().to_owned();
+  tmp_path.push(".tmp");
+  let mut args = vec!["-czf".into(), tmp_path.clone(), "-C".into()];
+  args.push(staging.0.clone().into_os_string());
+  args.extend(names);
+  run_command_timeout("tar", &args, TAR_TIMEOUT)
+    .and_then(|_| Ok(std::fs::rename(&tmp_path, archive)?))
+    .map_err(|e| {
+      let _ = std::fs::remove_file(&tmp_path);
+      Error::msg(format!("Failed to write {}: {}", archive.display(), e))
+    })
+}
+
+/// Checks a name listed by the archive stays within the restored root.
+fn check_name(name: &str) -> Result<PathBuf> {
+  let path = Path::new(name);
+  let mut checked = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::Normal(v) => checked.push(v),
+      Component::CurDir => {}
+      _ => {
+        return Err(Error::msg(format!(
+          "The archive holds an unsafe path '{}'",
+          name
+        )))
+      }
+    }
+  }
+  Ok(checked)
+}
+
+///
+/// Restores the files of a backup in place, each atomically. A database's
+/// write-ahead log left next to the file it replaces is removed, as it
+/// belongs to the replaced database. The daemon should be stopped first, as
+/// it would keep writing to the replaced files.
+///
+/// Args:
+///  - archive: Tarball written by create, or by `sysupgrade -b`.
+///  - root: Directory the paths of the archive are relative to, "/" to
+///    restore the files in place.
+///
+/// Returns:
+///  Result of the restored files.
+///
+pub fn restore(archive: &Path, root: &Path) -> Result<Vec<PathBuf>> {
+  let archive_arg = archive.as_os_str();
+  let listing = run_command_timeout("tar", &["-tzf".as_ref(), archive_arg], TAR_TIMEOUT)
+    .map_err(|e| Error::msg(format!("Failed to read {}: {}", archive.display(), e)))?;
+  let mut names = Vec::new();
+  for name in listing.lines().filter(|v| !v.ends_with('/')) {
+    names.push(check_name(name)?);
+  }
+  if names.is_empty() {
+    return Err(Error::msg(format!("{} is empty", archive.display())));
+  }
+
+  let staging = StagingDir::new("restore")?;
+  let extract_args = [
+    "-xzf".as_ref(),
+    archive_arg,
+    "-C".as_ref(),
+    staging.0.as_os_str(),
+  ];
+  run_command_timeout("tar", &extract_args, TAR_TIMEOUT)
+    .map_err(|e| Error::msg(format!("Failed to extract {}: {}", archive.display(), e)))?;
+  for name in &names {
+    let staged = staging.0.join(name);
+    if !staged.symlink_metadata().is_ok_and(|v| v.is_file()) {
+      return Err(Error::msg(format!(
+        "{} isn't a regular file in the archive",
+        name.display()
+      )));
+    }
+  }
+
+  let mut restored = Vec::new();
+  for name in &names {
+    let dest = root.join(name);
+    let mut tmp_path = dest.as_os_str().to_owned();
+    tmp_path.push(".restore");
+    create_parent(&dest)?;
+    std::fs::copy(staging.0.join(name), &tmp_path)
+      .and_then(|_| std::fs::rename(&tmp_path, &dest))
+      .map_err(|e| Error::msg(format!("Failed to restore {}: {}", dest.display(), e)))?;
+    for suffix in ["-wal", "-shm"] {
+      let mut stale = dest.as_os_str().to_owned();
+      stale.push(suffix);
+      let _ = std::fs::remove_file(stale);
+    }
+    restored.push(dest);
+  }
+  Ok(restored)
+}
@@ -0,0 +1,220 @@
+use crate::counters;
+use crate::neighbors::netlink::{self, iface_name_from_index};
+use crate::neighbors::{ArpTable, CommandRunner, MacAddr, NeighborFlags, SystemRunner};
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::str::FromStr;
+
+/// How an entry got into the forwarding database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FdbState {
+  /// Learned from traffic, and aged out when idle.
+  Dynamic,
+  /// Added administratively ("static").
+  Static,
+  /// Address of the bridge or port itself ("permanent").
+  Permanent,
+}
+
+/*
+https://man7.org/linux/man-pages/man8/bridge.8.html
+
+  dc:a6:32:57:46:d6 dev lan1 master br-lan
+  24:4b:fe:06:f8:3c dev wlan0 master br-lan
+  aa:bb:cc:dd:ee:ff dev lan2 vlan 1 master br-lan permanent
+  00:11:22:33:44:55 dev lan3 master br-lan extern_learn offload
+  33:33:00:00:00:01 dev eth0 self permanent
+
+  So we're parsing:
+  <mac> <dev> <port> [vlan <id>] [master <bridge>] .* [<flags>] [<state>]
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FdbEntry {
+  pub mac_addr: MacAddr,
+  pub port: String,
+  pub master: Option<String>,
+  pub vlan: Option<u16>,
+  pub state: FdbState,
+  pub flags: NeighborFlags,
+  /// Entry lives in the port device's own table, rather than the bridge's.
+  pub is_self: bool,
+}
+
+impl FdbEntry {
+  ///
+  /// Parses a string into an FdbEntry instance, given the string matches a
+  /// line result from `bridge fdb show`.
+  ///
+  /// Args:
+  ///  - s: Row result as a string.
+  ///
+  /// Returns:
+  ///  Result reflecting a successful parse.
+  ///
+  pub fn parse_from_string(s: &str) -> Result<Self> {
+    let sliced_str: Vec<&str> = s.split_whitespace().collect();
+    debug!("Sliced string -> {:?}", sliced_str);
+
+    if sliced_str.len() < 3 || sliced_str[1] != "dev" {
+      return Err(Error::msg(format!("Unexpected string -> {}", s)));
+    }
+    let mac_addr = MacAddr::from_str(sliced_str[0])?;
+    let port = sliced_str[2].to_string();
+
+    let mut entry = FdbEntry {
+      mac_addr,
+      port,
+      master: None,
+      vlan: None,
+      state: FdbState::Dynamic,
+      flags: NeighborFlags::empty(),
+      is_self: false,
+    };
+
+    let mut tokens = sliced_str.iter().skip(3);
+    while let Some(token) = tokens.next() {
+      match *token {
+        "master" => entry.master = tokens.next().map(|v| v.to_string()),
+        "vlan" => entry.vlan = tokens.next().and_then(|v| v.parse::<u16>().ok()),
+        "self" => entry.is_self = true,
+        "permanent" => entry.state = FdbState::Permanent,
+        "static" => entry.state = FdbState::Static,
+        "extern_learn" => entry.flags |= NeighborFlags::EXTERN_LEARN,
+        "offload" => entry.flags |= NeighborFlags::OFFLOAD,
+        // Keywords followed by a value we don't care about.
+        "dst" | "port" | "vni" | "src_vni" | "via" | "nhid" => {
+          tokens.next();
+        }
+        _ => {}
+      }
+    }
+
+    Ok(entry)
+  }
+}
+
+/// Parses the rows of a `bridge fdb show` listing, skipping rows that failed to parse.
+pub fn parse_bridge_fdb_output(stdout: &str) -> Vec<FdbEntry> {
+  stdout
+    .lines()
+    .map(|s| s.trim())
+    .filter(|s| !s.is_empty())
+    .filter_map(|v| match FdbEntry::parse_from_string(v) {
+      Ok(entry) => Some(entry),
+      Err(e) => {
+        warn!("Skipping fdb entry: {}", e);
+        counters::record_parse_error();
+        None
+      }
+    })
+    .collect()
+}
+
+/// Generates a parsed array of FdbEntry results by running `bridge fdb show`.
+pub fn get_fdb_entries_cmd() -> Result<Vec<FdbEntry>> {
+  get_fdb_entries_with(&SystemRunner::new())
+}
+
+/// Generates a parsed array of FdbEntry results, running `bridge fdb show` through the given runner.
+pub fn get_fdb_entries_with(runner: &dyn CommandRunner) -> Result<Vec<FdbEntry>> {
+  Ok(parse_bridge_fdb_output(
+    &runner.run_args("bridge", &["fdb", "show"])?,
+  ))
+}
+
+/// Dumps the bridge forwarding databases over rtnetlink.
+pub fn get_fdb_entries_netlink() -> Result<Vec<FdbEntry>> {
+  // The bridge family's "neighbors" are its forwarding database entries.
+  let mut request = vec![0u8; netlink::NDMSG_LEN];
+  request[0] = libc::AF_BRIDGE as u8;
+
+  let entries = netlink::dump_raw_neighbors(&request)?
+    .into_iter()
+    .filter(|v| v.family == libc::AF_BRIDGE as u8)
+    .filter_map(|raw| {
+      let mac_addr = raw.lladdr.as_deref().and_then(MacAddr::from_bytes)?;
+      let state = match raw.state {
+        s if s & libc::NUD_PERMANENT != 0 => FdbState::Permanent,
+        s if s & libc::NUD_NOARP != 0 => FdbState::Static,
+        _ => FdbState::Dynamic,
+      };
+
+      Some(FdbEntry {
+        mac_addr,
+        port: iface_name_from_index(raw.ifindex),
+        master: raw.master.map(iface_name_from_index),
+        vlan: raw.vlan,
+        state,
+        flags: NeighborFlags::from_bits_truncate(raw.flags),
+        is_self: raw.flags & libc::NTF_SELF != 0,
+      })
+    })
+    .collect();
+
+  Ok(entries)
+}
+
+/// Collects the bridge forwarding databases, preferring netlink and falling back to 'bridge'.
+pub fn get_fdb_entries() -> Result<Vec<FdbEntry>> {
+  get_fdb_entries_netlink().or_else(|err| {
+    warn!(
+      "Netlink fdb dump failed, falling back to 'bridge fdb show': {}",
+      err
+    );
+    get_fdb_entries_cmd()
+  })
+}
+
+/// A neighbor along with the bridge port it was learned on.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NeighborPort {
+  pub neighbor: ArpTable,
+  /// Bridge port (e.g. lan1, wlan0), or None if the neighbor isn't behind a bridge.
+  pub port: Option<String>,
+  pub vlan: Option<u16>,
+}
+
+/// Whether a neighbor seen on `iface` sits behind the given bridge (or one of its VLAN devices).
+fn is_behind_bridge(iface: &str, bridge: &str) -> bool {
+  iface == bridge
+    || iface
+      .strip_prefix(bridge)
+      .is_some_and(|v| v.starts_with('.'))
+}
+
+///
+/// Joins neighbors with the forwarding database by MAC address, resolving
+/// which bridge port each neighbor is attached to.
+///
+/// Args:
+///  - neighbors: Neighbor table snapshot.
+///  - fdb: Forwarding database entries.
+///
+/// Returns:
+///  Every neighbor along with its port, if found.
+///
+pub fn join_neighbor_ports(neighbors: Vec<ArpTable>, fdb: &[FdbEntry]) -> Vec<NeighborPort> {
+  neighbors
+    .into_iter()
+    .map(|neighbor| {
+      // The bridge's own addresses are permanent, and never what a neighbor is behind.
+      let entry = fdb.iter().find(|v| {
+        Some(v.mac_addr) == neighbor.mac_addr
+          && v.state != FdbState::Permanent
+          && v
+            .master
+            .as_deref()
+            .is_some_and(|bridge| is_behind_bridge(&neighbor.iface, bridge))
+      });
+
+      NeighborPort {
+        port: entry.map(|v| v.port.clone()),
+        vlan: entry.and_then(|v| v.vlan),
+        neighbor,
+      }
+    })
+    .collect()
+}
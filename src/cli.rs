@@ -0,0 +1,555 @@
+use crate::logging::{Facility, LogFormat, LogTarget};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use openwrt_netmon::daemon::socket;
+use openwrt_netmon::neighbors::parse_nud_keyword;
+use openwrt_netmon::registry::{AlertPolicy, DeviceCategory, TrustLevel};
+use openwrt_netmon::{dhcp, registry, storage, vendor};
+use openwrt_netmon::{AddressFamily, MacAddr, NeighborFilter, NudState};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Monitors the neighbors (ARP/NDP) of an OpenWrt router.
+#[derive(Debug, Parser)]
+#[command(name = "netmon", version)]
+pub struct Cli {
+  /// How log records are written to stderr.
+  #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+  pub log_format: LogFormat,
+  /// Where log records are written.
+  #[arg(long, global = true, value_enum, default_value_t = LogTarget::Stderr)]
+  pub log_target: LogTarget,
+  /// Syslog facility, with --log-target syslog.
+  #[arg(long, global = true, value_enum, default_value_t = Facility::Daemon)]
+  pub syslog_facility: Facility,
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+/// Name Netdata runs the plugin as, through a symlink in its plugins.d.
+pub const NETDATA_PLUGIN_NAME: &str = "netmon.plugin";
+
+///
+/// The program's arguments, as `netmon netdata-plugin` when run as
+/// `netmon.plugin`, since Netdata passes nothing but its update_every.
+///
+pub fn args() -> Vec<OsString> {
+  let mut args: Vec<OsString> = std::env::args_os().collect();
+  let plugin = args
+    .first()
+    .and_then(|v| Path::new(v).file_name())
+    .is_some_and(|v| v == NETDATA_PLUGIN_NAME);
+  if plugin {
+    args.insert(1, OsString::from("netdata-plugin"));
+  }
+  args
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+  /// List the current neighbors (default).
+  List {
+    /// Include the neighbors' cache counters.
+    #[arg(short, long)]
+    stats: bool,
+    #[command(flatten)]
+    filter: FilterArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// Stream neighbor table changes.
+  Watch {
+    #[command(flatten)]
+    filter: FilterArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// Poll the neighbor table on a schedule, logging changes.
+  Daemon {
+    /// Stay attached to the terminal, as procd and systemd expect, instead of
+    /// detaching once the first poll succeeded.
+    #[arg(short, long)]
+    foreground: bool,
+    /// Config file, instead of $NETMON_CONFIG or /etc/netmon/config.toml.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Poll interval, e.g. "30s" or "5m", overriding the config until it's reloaded.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    interval: Option<Duration>,
+    /// Take over a pid file naming a running process that doesn't hold its
+    /// lock, e.g. when its pid was reused.
+    #[arg(long)]
+    force: bool,
+  },
+  /// Poll the neighbor table on a schedule, printing the devices' metrics as
+  /// PUTVAL lines, for collectd's exec plugin to run.
+  Collectd {
+    /// Config file, for the interfaces, names, and privacy settings, leaving
+    /// out its sinks and alerts.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Poll interval, instead of $COLLECTD_INTERVAL, or else the config's.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    interval: Option<Duration>,
+  },
+  /// Poll the neighbor table on a schedule, printing the devices' charts in
+  /// Netdata's external plugin protocol, as Netdata runs it through a
+  /// `netmon.plugin` symlink in its plugins.d.
+  NetdataPlugin {
+    /// Poll interval in seconds, as Netdata passes its update_every, or else
+    /// the config's.
+    update_every: Option<u64>,
+    /// Config file, for the interfaces, names, and privacy settings, leaving
+    /// out its sinks and alerts.
+    #[arg(long)]
+    config: Option<PathBuf>,
+  },
+  /// Poll the neighbor table once, printing the devices' current state, e.g.
+  /// for Telegraf's exec input to run.
+  #[cfg(feature = "influxdb")]
+  Snapshot {
+    /// Format of the state, the line protocol for Telegraf's "influx"
+    /// data_format.
+    #[arg(long, value_enum, default_value_t = SnapshotFormat::Influx)]
+    format: SnapshotFormat,
+    /// Config file, for the interfaces, names, and privacy settings, leaving
+    /// out its sinks and alerts.
+    #[arg(long)]
+    config: Option<PathBuf>,
+  },
+  /// Make a running daemon reload its config.
+  Reload {
+    /// The daemon's control socket.
+    #[arg(long, default_value = socket::DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
+  },
+  /// List the current devices along with their DHCP leases.
+  Devices {
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+    #[command(flatten)]
+    filter: FilterArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// Show a single device, along with its recent history if a daemon runs.
+  #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+  Device {
+    #[command(subcommand)]
+    command: Option<DeviceCommand>,
+    #[arg(required = true, value_parser = parse_mac_addr)]
+    mac_addr: Option<MacAddr>,
+    /// The daemon's control socket, asked for the device's recent history.
+    #[arg(long, default_value = socket::DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// Print a device's timeline of joins, leaves, address and state changes,
+  /// from the history of the config's storage sink, or else of the daemon.
+  History {
+    /// Device, by MAC address, IP address, or name.
+    device: String,
+    /// How far back the timeline goes, e.g. "48h".
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "24h")]
+    since: Duration,
+    /// Config file, instead of $NETMON_CONFIG or /etc/netmon/config.toml.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// The daemon's control socket, asked when no storage sink keeps the
+    /// history, instead of the config's.
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// Export the current devices, or their recorded history, one row per
+  /// device, sighting, or event.
+  Export {
+    #[command(flatten)]
+    export: ExportArgs,
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+    #[command(flatten)]
+    filter: FilterArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// List the current neighbors along with the bridge port they're behind.
+  Ports {
+    #[command(flatten)]
+    filter: FilterArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// Flush neighbors in the given states (STALE and FAILED by default).
+  Flush {
+    /// Only flush neighbors on this interface.
+    #[arg(long)]
+    dev: Option<String>,
+    /// NUD state to flush, may be repeated.
+    #[arg(long, value_parser = parse_nud_state)]
+    nud: Vec<NudState>,
+    /// List the neighbors that would be flushed, without flushing them.
+    #[arg(long)]
+    dry_run: bool,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// Parse a captured `ip neigh` listing instead of the live table.
+  Parse {
+    /// Output of `ip neigh` or `ip -s neigh`, "-" for stdin.
+    #[arg(long, default_value = "-")]
+    from_file: PathBuf,
+    /// Group the neighbors into devices, joined with their DHCP leases.
+    #[arg(long)]
+    devices: bool,
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+    #[command(flatten)]
+    filter: FilterArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+  },
+  /// Export or import the registry of known devices, e.g. to share it across
+  /// routers.
+  Registry {
+    #[command(subcommand)]
+    command: RegistryCommand,
+  },
+  /// Back up, or restore, the config, registry, and databases of the monitor.
+  Backup {
+    #[command(subcommand)]
+    command: BackupCommand,
+  },
+  /// Look up MAC address vendors.
+  Vendor {
+    #[command(subcommand)]
+    command: VendorCommand,
+  },
+  /// Integrate with Zabbix, alongside the daemon's zabbix sink.
+  #[cfg(feature = "zabbix")]
+  Zabbix {
+    #[command(subcommand)]
+    command: ZabbixCommand,
+  },
+}
+
+/// What `netmon export` writes, and where.
+#[derive(Debug, Clone, Args)]
+pub struct ExportArgs {
+  /// File to write to, instead of stdout.
+  #[arg(short = 'o', long = "output")]
+  pub path: Option<PathBuf>,
+  /// What to export: the current devices, or the history of the database.
+  #[arg(long, value_enum, default_value_t = ExportData::Devices)]
+  pub data: ExportData,
+  /// Format of the rows, JSON with --json too.
+  #[arg(long, value_enum, default_value_t = ExportFormat::Tsv)]
+  pub format: ExportFormat,
+  /// Columns to export, in order, e.g. "mac,name,vendor". Every column if
+  /// unset.
+  #[arg(long, value_delimiter = ',')]
+  pub columns: Vec<String>,
+  /// Only export the history since this long ago, e.g. "7d".
+  #[arg(long, value_parser = humantime::parse_duration)]
+  pub since: Option<Duration>,
+  /// Only export the history of this device.
+  #[arg(long, value_parser = parse_mac_addr)]
+  pub mac: Option<MacAddr>,
+  /// History database, written by the daemon's sqlite sink.
+  #[arg(long, default_value = storage::DEFAULT_SQLITE_PATH)]
+  pub db: PathBuf,
+  /// Hash MAC addresses and names with the config's salt, as the config's
+  /// `privacy.anonymize` does, so the export can be shared.
+  #[arg(long)]
+  pub anonymize: bool,
+}
+
+/// Data exported by `netmon export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportData {
+  /// The current devices.
+  Devices,
+  /// Every poll's sightings of the devices, from the history.
+  Sightings,
+  /// Every change of the devices, from the history.
+  Events,
+}
+
+/// Format of `netmon export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+  /// Tab separated values.
+  Tsv,
+  /// Comma separated values, quoted where needed, for spreadsheets.
+  Csv,
+  /// A JSON array.
+  Json,
+}
+
+/// Format of `netmon snapshot`.
+#[cfg(feature = "influxdb")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SnapshotFormat {
+  /// InfluxDB line protocol, as the influxdb sink writes it, with timestamps
+  /// in nanoseconds.
+  Influx,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DeviceCommand {
+  /// Name a device in the registry of known devices, along with its owner,
+  /// category, trust level, and alert policy. Options left out keep their
+  /// registered value.
+  SetAlias {
+    #[arg(value_parser = parse_mac_addr)]
+    mac_addr: MacAddr,
+    /// Name shown instead of the MAC address.
+    name: String,
+    #[arg(long)]
+    owner: Option<String>,
+    #[arg(long, value_parser = parse_category)]
+    category: Option<DeviceCategory>,
+    #[arg(long, value_parser = parse_trust)]
+    trust: Option<TrustLevel>,
+    /// How its alerts depart from the rules, e.g. "critical" or
+    /// "mute:left,mute:offline", "" removing it.
+    #[arg(long, value_parser = parse_policy)]
+    policy: Option<AlertPolicy>,
+    #[arg(long, default_value = registry::DEFAULT_REGISTRY_PATH)]
+    registry: PathBuf,
+    /// The daemon's control socket, told to reload the registry.
+    #[arg(long, default_value = socket::DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
+  },
+  /// Remove a device from the registry of known devices.
+  Forget {
+    #[arg(value_parser = parse_mac_addr)]
+    mac_addr: MacAddr,
+    #[arg(long, default_value = registry::DEFAULT_REGISTRY_PATH)]
+    registry: PathBuf,
+    /// The daemon's control socket, told to reload the registry.
+    #[arg(long, default_value = socket::DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
+  },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RegistryCommand {
+  /// Write every known device as YAML or JSON.
+  Export {
+    /// File to write to, instead of stdout.
+    #[arg(short = 'o', long = "output")]
+    path: Option<PathBuf>,
+    /// Format of the devices, guessed from the file's extension if unset.
+    #[arg(long, value_enum)]
+    format: Option<RegistryFormat>,
+    #[arg(long, default_value = registry::DEFAULT_REGISTRY_PATH)]
+    registry: PathBuf,
+  },
+  /// Merge devices written by `registry export` into the registry. Fields set
+  /// differently on both sides are conflicts, failing the import unless
+  /// --on-conflict says which side wins.
+  Import {
+    /// File to read, "-" for stdin.
+    path: PathBuf,
+    /// Format of the devices, guessed from the file's extension if unset.
+    #[arg(long, value_enum)]
+    format: Option<RegistryFormat>,
+    /// What to do with conflicting fields.
+    #[arg(long, value_enum, default_value_t = OnConflict::Fail)]
+    on_conflict: OnConflict,
+    /// Report what the import would change, without saving it.
+    #[arg(long)]
+    dry_run: bool,
+    #[arg(long, default_value = registry::DEFAULT_REGISTRY_PATH)]
+    registry: PathBuf,
+    /// The daemon's control socket, told to reload the registry.
+    #[arg(long, default_value = socket::DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
+  },
+}
+
+/// Format of `netmon registry export` and `import`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RegistryFormat {
+  /// YAML, the default with the 'yaml' feature.
+  Yaml,
+  Json,
+}
+
+impl RegistryFormat {
+  /// The format named by a file's extension, YAML unless it's ".json" or
+  /// YAML isn't supported.
+  pub fn guess(path: Option<&Path>) -> RegistryFormat {
+    let json = path
+      .and_then(|v| v.extension())
+      .is_some_and(|v| v.eq_ignore_ascii_case("json"));
+    match json || !cfg!(feature = "yaml") {
+      true => RegistryFormat::Json,
+      false => RegistryFormat::Yaml,
+    }
+  }
+}
+
+/// Side winning the conflicts of `netmon registry import`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+  /// Report the conflicts and import nothing.
+  Fail,
+  /// Keep the registry's values.
+  Keep,
+  /// Take the imported values.
+  Replace,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupCommand {
+  /// Bundle the files holding the monitor's state into a gzipped tarball,
+  /// copying the databases consistently even while the daemon runs.
+  Create {
+    /// Tarball to write, e.g. /tmp/netmon-backup.tar.gz.
+    path: PathBuf,
+    /// Config file, instead of $NETMON_CONFIG or /etc/netmon/config.toml.
+    #[arg(long)]
+    config: Option<PathBuf>,
+  },
+  /// Restore a tarball written by `backup create`, with the daemon stopped.
+  Restore {
+    /// Tarball to read.
+    path: PathBuf,
+    /// Directory to restore the files under, instead of in place.
+    #[arg(long, default_value = "/")]
+    root: PathBuf,
+    /// The daemon's control socket, checked for a running daemon.
+    #[arg(long, default_value = socket::DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
+  },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VendorCommand {
+  /// Print the vendor a MAC address was assigned to.
+  Lookup {
+    #[arg(value_parser = parse_mac_addr)]
+    mac_addr: MacAddr,
+  },
+  /// Download the IEEE OUI registry.
+  Update {
+    #[arg(long, default_value = vendor::IEEE_OUI_URL)]
+    url: String,
+    #[arg(long, default_value = vendor::DEFAULT_DB_PATH)]
+    path: PathBuf,
+  },
+}
+
+#[cfg(feature = "zabbix")]
+#[derive(Debug, Subcommand)]
+pub enum ZabbixCommand {
+  /// Print the current devices as low-level discovery JSON, e.g. for the
+  /// agent's `UserParameter=netmon.devices.discovery,netmon zabbix lld`.
+  Lld {
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+    /// Hash MAC addresses and names with the config's salt, as the config's
+    /// `privacy.anonymize` does, to match the keys of an anonymizing sink.
+    #[arg(long)]
+    anonymize: bool,
+  },
+}
+
+/// Restricts which neighbors a command looks at.
+#[derive(Debug, Clone, Default, Args)]
+pub struct FilterArgs {
+  /// Only include neighbors on this interface.
+  #[arg(long)]
+  pub dev: Option<String>,
+  /// Only include neighbors of this address family.
+  #[arg(long, value_enum)]
+  pub family: Option<Family>,
+  /// Only include neighbors in this NUD state, may be repeated.
+  #[arg(long, value_parser = parse_nud_state)]
+  pub nud: Vec<NudState>,
+}
+
+impl FilterArgs {
+  /// The equivalent neighbor filter.
+  pub fn to_filter(&self) -> NeighborFilter {
+    let mut filter = NeighborFilter::new();
+    if let Some(dev) = &self.dev {
+      filter = filter.iface(dev);
+    }
+    if let Some(family) = self.family {
+      filter = filter.family(family.into());
+    }
+    self.nud.iter().fold(filter, |acc, v| acc.nud_state(*v))
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Family {
+  Inet,
+  Inet6,
+}
+
+impl From<Family> for AddressFamily {
+  fn from(family: Family) -> Self {
+    match family {
+      Family::Inet => AddressFamily::Inet,
+      Family::Inet6 => AddressFamily::Inet6,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct OutputArgs {
+  /// Print JSON instead of text.
+  #[arg(long)]
+  pub json: bool,
+}
+
+/// How devices are named and joined with their leases.
+#[derive(Debug, Clone, Args)]
+pub struct DiscoveryArgs {
+  /// Resolve the devices' hostnames through reverse DNS.
+  #[arg(long)]
+  pub resolve: bool,
+  /// Browse mDNS for the names and services of unnamed devices.
+  #[arg(long)]
+  pub mdns: bool,
+  /// Probe unnamed devices over LLMNR and NetBIOS.
+  #[arg(long)]
+  pub probe_names: bool,
+  /// dnsmasq lease file.
+  #[arg(long, default_value = dhcp::DEFAULT_DNSMASQ_LEASE_PATH)]
+  pub leases: PathBuf,
+  /// odhcpd lease file.
+  #[arg(long, default_value = dhcp::DEFAULT_ODHCPD_LEASE_PATH)]
+  pub odhcpd_leases: PathBuf,
+  /// Registry of known devices, naming them.
+  #[arg(long, default_value = registry::DEFAULT_REGISTRY_PATH)]
+  pub registry: PathBuf,
+}
+
+fn parse_mac_addr(s: &str) -> Result<MacAddr, String> {
+  s.parse().map_err(|e: anyhow::Error| e.to_string())
+}
+
+fn parse_category(s: &str) -> Result<DeviceCategory, String> {
+  s.parse().map_err(|e: anyhow::Error| e.to_string())
+}
+
+fn parse_trust(s: &str) -> Result<TrustLevel, String> {
+  s.parse().map_err(|e: anyhow::Error| e.to_string())
+}
+
+fn parse_policy(s: &str) -> Result<AlertPolicy, String> {
+  s.parse().map_err(|e: anyhow::Error| e.to_string())
+}
+
+fn parse_nud_state(s: &str) -> Result<NudState, String> {
+  parse_nud_keyword(s).ok_or_else(|| format!("unknown NUD state '{}'", s))
+}
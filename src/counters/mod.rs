@@ -0,0 +1,17 @@
+//!
+//! Process-wide counters of recoverable failures, such as malformed entries
+//! skipped while parsing, for health reporting.
+//!
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static PARSE_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts an entry skipped because it couldn't be parsed.
+pub fn record_parse_error() {
+  PARSE_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Entries skipped because they couldn't be parsed, since startup.
+pub fn parse_errors() -> u64 {
+  PARSE_ERRORS.load(Ordering::Relaxed)
+}
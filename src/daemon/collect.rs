@@ -0,0 +1,194 @@
+use super::trace::Span;
+use crate::dhcp::Lease;
+use crate::neighbors::ArpTable;
+use crate::wireless::Station;
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Collectors running at once when no cap is configured.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Collectors a poll runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum CollectorKind {
+  /// The neighbor table, required for a poll to succeed.
+  Neighbor,
+  /// The DHCP lease files.
+  Lease,
+  /// The wireless stations, through `iw`.
+  Wireless,
+}
+
+impl CollectorKind {
+  pub fn name(self) -> &'static str {
+    match self {
+      CollectorKind::Neighbor => "neighbor",
+      CollectorKind::Lease => "lease",
+      CollectorKind::Wireless => "wireless",
+    }
+  }
+}
+
+/// What a single collector found.
+#[derive(Debug)]
+pub enum Collected {
+  Neighbors(Vec<ArpTable>),
+  Leases(Vec<Lease>),
+  Stations(Vec<Station>),
+}
+
+type CollectorFuture = Pin<Box<dyn Future<Output = Result<Collected>> + Send>>;
+
+struct Collector {
+  kind: CollectorKind,
+  timeout: Duration,
+  future: CollectorFuture,
+}
+
+///
+/// Everything the collectors of a poll found, merged. Collectors that failed,
+/// timed out, or weren't run hold their error.
+///
+#[derive(Debug)]
+pub struct Collection {
+  pub neighbors: Result<Vec<ArpTable>>,
+  pub leases: Result<Vec<Lease>>,
+  pub stations: Result<Vec<Station>>,
+  /// Time each collector took, whether it failed or not.
+  pub spans: Vec<Span>,
+}
+
+/// Error of a collector that never reported back.
+fn missing<T>(kind: CollectorKind) -> Result<T> {
+  Err(Error::msg(format!("No {} collector ran", kind.name())))
+}
+
+impl Default for Collection {
+  fn default() -> Self {
+    Collection {
+      neighbors: missing(CollectorKind::Neighbor),
+      leases: missing(CollectorKind::Lease),
+      stations: missing(CollectorKind::Wireless),
+      spans: Vec::new(),
+    }
+  }
+}
+
+impl Collection {
+  /// Merges a collector's result in, keeping the error if it failed.
+  fn merge(&mut self, kind: CollectorKind, result: Result<Collected>) {
+    match (kind, result) {
+      (_, Ok(Collected::Neighbors(v))) => self.neighbors = Ok(v),
+      (_, Ok(Collected::Leases(v))) => self.leases = Ok(v),
+      (_, Ok(Collected::Stations(v))) => self.stations = Ok(v),
+      (CollectorKind::Neighbor, Err(err)) => self.neighbors = Err(err),
+      (CollectorKind::Lease, Err(err)) => self.leases = Err(err),
+      (CollectorKind::Wireless, Err(err)) => self.stations = Err(err),
+    }
+  }
+}
+
+///
+/// Collectors of a single poll, run concurrently up to a cap, each bounded
+/// by its own timeout. Time spent waiting for a free slot doesn't count
+/// towards a collector's timeout.
+///
+pub struct CollectorSet {
+  concurrency: usize,
+  collectors: Vec<Collector>,
+}
+
+impl Default for CollectorSet {
+  fn default() -> Self {
+    CollectorSet::new(DEFAULT_CONCURRENCY)
+  }
+}
+
+impl CollectorSet {
+  /// Runs up to `concurrency` collectors at once, at least one.
+  pub fn new(concurrency: usize) -> Self {
+    CollectorSet {
+      concurrency: concurrency.max(1),
+      collectors: Vec::new(),
+    }
+  }
+
+  ///
+  /// Adds a collector.
+  ///
+  /// Args:
+  ///  - kind: Collector, deciding where its result is merged.
+  ///  - timeout: Longest time the collector may run for.
+  ///  - future: Collector to run.
+  ///
+  pub fn add<F>(mut self, kind: CollectorKind, timeout: Duration, future: F) -> Self
+  where
+    F: Future<Output = Result<Collected>> + Send + 'static,
+  {
+    self.collectors.push(Collector {
+      kind,
+      timeout,
+      future: Box::pin(future),
+    });
+    self
+  }
+
+  ///
+  /// Runs every collector and merges their results.
+  ///
+  /// Returns:
+  ///  What the collectors found, along with the errors of those that didn't.
+  ///
+  pub async fn run(self) -> Collection {
+    let permits = Arc::new(Semaphore::new(self.concurrency));
+    let mut tasks = JoinSet::new();
+    for collector in self.collectors {
+      let permits = permits.clone();
+      tasks.spawn(async move {
+        let _permit = permits.acquire_owned().await;
+        let name = collector.kind.name();
+        let start = SystemTime::now();
+        let started_at = Instant::now();
+        let result = match tokio::time::timeout(collector.timeout, collector.future).await {
+          Ok(result) => result,
+          Err(_) => Err(Error::msg(format!(
+            "The {} collector timed out after {}",
+            name,
+            humantime::format_duration(collector.timeout)
+          ))),
+        };
+        let duration = started_at.elapsed();
+        let duration_ms = duration.as_millis() as u64;
+        debug!(
+          "collector.name" = name, "collector.duration_ms" = duration_ms;
+          "The {} collector ran in {}ms", name, duration_ms
+        );
+        let mut span = Span::new(&format!("collect {}", name), start, duration);
+        if let Err(err) = &result {
+          span = span.error(&err.to_string());
+        }
+        (collector.kind, result, span)
+      });
+    }
+
+    let mut collection = Collection::default();
+    while let Some(joined) = tasks.join_next().await {
+      match joined {
+        Ok((kind, result, span)) => {
+          collection.merge(kind, result);
+          collection.spans.push(span);
+        }
+        Err(err) => warn!("A collector panicked: {}", err),
+      }
+    }
+    collection
+  }
+}
@@ -0,0 +1,182 @@
+use super::metrics::{device_samples, neighbor_counts, unix_secs};
+use super::{hostname, PollReport, Sink};
+use crate::privacy::Anonymizer;
+use anyhow::{Error, Result};
+use std::io::Write;
+use std::time::Duration;
+
+/// Name of the plugin the values are submitted as.
+pub const PLUGIN: &str = "netmon";
+
+/*
+  Every poll prints a PUTVAL line per value, in the plain text protocol of
+  collectd's exec plugin, with types of collectd's default types.db:
+
+    PUTVAL "office-ap/netmon/count-devices" interval=30.000 1791953775:14
+    PUTVAL "office-ap/netmon/count-devices_online" interval=30.000 1791953775:12
+    PUTVAL "office-ap/netmon/count-neighbors_reachable" interval=30.000 1791953775:12
+    PUTVAL "office-ap/netmon/duration-poll" interval=30.000 1791953775:0.042
+    PUTVAL "office-ap/netmon-dc_a6_32_a3_48_b1/gauge-online" interval=30.000 1791953775:1
+
+  Wireless stations also get the bytes received from, and sent to, them as
+  if_octets, their signal as signal_power, and their bitrates, in bits per
+  second, as bitrate-rx and bitrate-tx:
+
+    PUTVAL "office-ap/netmon-dc_a6_32_a3_48_b1/if_octets" interval=30.000 1791953775:1024:2048
+    PUTVAL "office-ap/netmon-dc_a6_32_a3_48_b1/signal_power" interval=30.000 1791953775:-52
+
+  Devices go by their MAC address, with underscores, as rrdtool takes colons
+  as separators.
+*/
+
+///
+/// Sink printing the devices' presence and traffic, and the neighbor table's
+/// state, as collectd PUTVAL lines, for collectd's exec plugin to read off
+/// `netmon collectd`.
+///
+pub struct CollectdSink {
+  out: Box<dyn Write + Send>,
+  hostname: String,
+  interval: Duration,
+  anonymizer: Option<Anonymizer>,
+}
+
+impl CollectdSink {
+  ///
+  /// Creates a sink, submitting the values as the router's hostname.
+  ///
+  /// Args:
+  ///  - out: Where to print the lines, collectd reading the exec'd
+  ///    program's stdout.
+  ///  - interval: Interval the values are polled at, for collectd to tell
+  ///    when they're missing.
+  ///
+  pub fn new(out: Box<dyn Write + Send>, interval: Duration) -> Self {
+    CollectdSink {
+      out,
+      hostname: hostname(),
+      interval,
+      anonymizer: None,
+    }
+  }
+
+  /// Hostname the values are submitted for, e.g. collectd's $COLLECTD_HOSTNAME.
+  pub fn hostname(mut self, hostname: &str) -> Self {
+    self.hostname = hostname.to_string();
+    self
+  }
+
+  /// Hashes the MAC addresses the devices' plugin instances hold.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// The lines of a poll.
+  fn lines(&self, report: &PollReport) -> Vec<String> {
+    let time = unix_secs(report.snapshot.taken_at);
+    let line = |plugin_instance: Option<&str>, kind: &str, values: &str| {
+      let plugin = match plugin_instance {
+        Some(instance) => format!("{}-{}", PLUGIN, identifier_part(instance)),
+        None => PLUGIN.to_string(),
+      };
+      format!(
+        "PUTVAL \"{}/{}/{}\" interval={:.3} {}:{}",
+        identifier_part(&self.hostname),
+        plugin,
+        kind,
+        self.interval.as_secs_f64(),
+        time,
+        values
+      )
+    };
+
+    let devices = device_samples(report, self.anonymizer.as_ref());
+    let online = devices.iter().filter(|v| v.online).count();
+    let mut lines = vec![
+      line(None, "count-devices", &devices.len().to_string()),
+      line(None, "count-devices_online", &online.to_string()),
+    ];
+    for (nud_state, count) in neighbor_counts(report) {
+      let kind = format!("count-neighbors_{}", nud_state.to_lowercase());
+      lines.push(line(None, &identifier_part(&kind), &count.to_string()));
+    }
+    lines.push(line(
+      None,
+      "duration-poll",
+      &format!("{:.3}", report.duration.as_secs_f64()),
+    ));
+
+    let value = |v: Option<u64>| v.map_or_else(|| "U".to_string(), |v| v.to_string());
+    for device in &devices {
+      let mac = device.mac.replace(':', "_");
+      let instance = Some(mac.as_str());
+      lines.push(line(
+        instance,
+        "gauge-online",
+        &(device.online as u8).to_string(),
+      ));
+      if device.rx_bytes.is_some() || device.tx_bytes.is_some() {
+        let octets = format!("{}:{}", value(device.rx_bytes), value(device.tx_bytes));
+        lines.push(line(instance, "if_octets", &octets));
+      }
+      if let Some(signal_dbm) = device.signal_dbm {
+        lines.push(line(instance, "signal_power", &signal_dbm.to_string()));
+      }
+      let bitrates = [
+        ("bitrate-rx", device.rx_bitrate_kbps),
+        ("bitrate-tx", device.tx_bitrate_kbps),
+      ];
+      for (kind, kbps) in bitrates {
+        if let Some(kbps) = kbps {
+          lines.push(line(instance, kind, &(kbps * 1000).to_string()));
+        }
+      }
+    }
+    lines
+  }
+}
+
+impl Sink for CollectdSink {
+  fn name(&self) -> &str {
+    "collectd"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let mut out = String::new();
+    for line in self.lines(report) {
+      out.push_str(&line);
+      out.push('\n');
+    }
+    self
+      .out
+      .write_all(out.as_bytes())
+      .and_then(|_| self.out.flush())
+      .map_err(|e| Error::msg(format!("Failed to write to collectd: {}", e)))
+  }
+
+  fn needs_thread(&self) -> bool {
+    // collectd may be slow to read its end of the pipe.
+    true
+  }
+}
+
+impl std::fmt::Debug for CollectdSink {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("CollectdSink")
+      .field("hostname", &self.hostname)
+      .field("interval", &self.interval)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Replaces what separates the parts of an identifier, or needs quoting, in a part.
+fn identifier_part(value: &str) -> String {
+  value
+    .chars()
+    .map(|c| match c.is_ascii_alphanumeric() || "-_.".contains(c) {
+      true => c,
+      false => '_',
+    })
+    .collect()
+}
@@ -0,0 +1,207 @@
+use anyhow::{Error, Result};
+use log::{info, warn};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Requests pending on a Control.
+#[derive(Debug, Default)]
+struct ControlState {
+  cancelled: bool,
+  reload: bool,
+}
+
+impl ControlState {
+  fn has_request(&self) -> bool {
+    self.cancelled || self.reload
+  }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+  state: Mutex<ControlState>,
+  /// Wakes threads blocked in wait_timeout.
+  condvar: Condvar,
+  /// Wakes tasks awaiting wait.
+  notify: Notify,
+}
+
+///
+/// Shutdown and reload requests shared by every part of the daemon. Cloning
+/// the control shares its state, so a request made through any clone reaches
+/// them all.
+///
+/// ```
+/// use openwrt_netmon::daemon::Control;
+/// use std::time::Duration;
+///
+/// let control = Control::new();
+/// let waiter = control.clone();
+/// std::thread::spawn(move || control.cancel());
+///
+/// waiter.wait_timeout(Duration::from_secs(5));
+/// assert!(waiter.is_cancelled());
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct Control {
+  inner: Arc<Shared>,
+}
+
+impl Control {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Wakes every waiter, once a request was made.
+  fn wake(&self) {
+    self.inner.condvar.notify_all();
+    self.inner.notify.notify_waiters();
+  }
+
+  /// Requests a shutdown, waking every waiter.
+  pub fn cancel(&self) {
+    self.inner.state.lock().unwrap().cancelled = true;
+    self.wake();
+  }
+
+  /// Whether a shutdown was requested.
+  pub fn is_cancelled(&self) -> bool {
+    self.inner.state.lock().unwrap().cancelled
+  }
+
+  /// Requests the config to be reloaded, waking every waiter.
+  pub fn request_reload(&self) {
+    self.inner.state.lock().unwrap().reload = true;
+    self.wake();
+  }
+
+  /// Whether a reload was requested since the last call, clearing the request.
+  pub fn take_reload(&self) -> bool {
+    std::mem::take(&mut self.inner.state.lock().unwrap().reload)
+  }
+
+  ///
+  /// Sleeps until the timeout elapses, or a shutdown or reload is requested.
+  ///
+  /// Args:
+  ///  - timeout: Longest time to sleep for.
+  ///
+  pub fn wait_timeout(&self, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+
+    let mut guard = self.inner.state.lock().unwrap();
+    while !guard.has_request() {
+      let now = Instant::now();
+      if now >= deadline {
+        break;
+      }
+      guard = self
+        .inner
+        .condvar
+        .wait_timeout(guard, deadline - now)
+        .unwrap()
+        .0;
+    }
+  }
+
+  ///
+  /// Like wait_timeout, but sleeps without blocking the runtime.
+  ///
+  /// Args:
+  ///  - timeout: Longest time to sleep for.
+  ///
+  pub async fn wait(&self, timeout: Duration) {
+    // Registered before checking the state, so a request made in between
+    // still wakes it.
+    let notified = self.inner.notify.notified();
+    tokio::pin!(notified);
+    notified.as_mut().enable();
+
+    if self.inner.state.lock().unwrap().has_request() {
+      return;
+    }
+    let _ = tokio::time::timeout(timeout, notified).await;
+  }
+
+  /// Resolves once a shutdown is requested, e.g. to abort a poll in flight.
+  pub async fn cancelled(&self) {
+    loop {
+      let notified = self.inner.notify.notified();
+      tokio::pin!(notified);
+      notified.as_mut().enable();
+
+      if self.is_cancelled() {
+        return;
+      }
+      notified.await;
+    }
+  }
+}
+
+/// Name of a signal, for logging.
+fn signal_name(signal: libc::c_int) -> &'static str {
+  match signal {
+    libc::SIGINT => "SIGINT",
+    libc::SIGTERM => "SIGTERM",
+    libc::SIGHUP => "SIGHUP",
+    _ => "signal",
+  }
+}
+
+///
+/// Requests a shutdown on SIGTERM or SIGINT, and a reload on SIGHUP. A second
+/// shutdown signal exits right away, for when shutting down hangs.
+///
+/// The signals are blocked and waited on by a dedicated thread, so this must
+/// be called before any other thread is spawned for them to inherit the mask.
+///
+/// Args:
+///  - control: Control to make the requests through.
+///
+/// Returns:
+///  Result reflecting whether the signals could be blocked.
+///
+pub fn handle_signals(control: Control) -> Result<()> {
+  // SAFETY: sigset_t is plain data, initialized by sigemptyset before use.
+  let mut set: libc::sigset_t = unsafe { std::mem::zeroed() };
+  let rc = unsafe {
+    libc::sigemptyset(&mut set);
+    libc::sigaddset(&mut set, libc::SIGINT);
+    libc::sigaddset(&mut set, libc::SIGTERM);
+    libc::sigaddset(&mut set, libc::SIGHUP);
+    libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut())
+  };
+  if rc != 0 {
+    return Err(Error::msg(format!(
+      "Failed to block signals: {}",
+      std::io::Error::from_raw_os_error(rc)
+    )));
+  }
+
+  std::thread::Builder::new()
+    .name("signals".to_string())
+    .spawn(move || loop {
+      let mut signal: libc::c_int = 0;
+      // SAFETY: set holds the blocked signals, signal is a valid out pointer.
+      if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+        warn!("Failed to wait for signals");
+        return;
+      }
+
+      if signal == libc::SIGHUP {
+        info!("Received SIGHUP, reloading the config");
+        control.request_reload();
+        continue;
+      }
+      if control.is_cancelled() {
+        warn!("Received {} again, exiting now", signal_name(signal));
+        std::process::exit(1);
+      }
+      info!("Received {}, shutting down", signal_name(signal));
+      control.cancel();
+    })
+    .map_err(|e| Error::msg(format!("Failed to spawn the signal thread: {}", e)))?;
+
+  Ok(())
+}
@@ -0,0 +1,114 @@
+use anyhow::{Error, Result};
+use std::os::fd::RawFd;
+use std::time::Duration;
+
+/// How long the launching process waits for the daemon to become ready.
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+///
+/// Write end of the pipe the launching process waits on, so it exits only
+/// once the detached daemon is ready (or died trying).
+///
+#[derive(Debug)]
+pub struct ReadyPipe {
+  fd: RawFd,
+}
+
+impl ReadyPipe {
+  /// Tells the launching process the daemon is ready, letting it exit.
+  pub fn notify(self) {
+    // SAFETY: fd is the open write end of the pipe, closed on drop.
+    unsafe {
+      libc::write(self.fd, b"1".as_ptr() as *const libc::c_void, 1);
+    }
+  }
+}
+
+impl Drop for ReadyPipe {
+  fn drop(&mut self) {
+    // SAFETY: fd is owned by this pipe end and not used afterwards.
+    unsafe {
+      libc::close(self.fd);
+    }
+  }
+}
+
+/// Waits in the launching process for the daemon to become ready, then exits.
+fn wait_for_ready(fd: RawFd) -> ! {
+  let mut pollfd = libc::pollfd {
+    fd,
+    events: libc::POLLIN,
+    revents: 0,
+  };
+  let mut byte = 0u8;
+  // SAFETY: pollfd and byte outlive the calls, fd is the pipe's read end.
+  let ready = unsafe {
+    libc::poll(&mut pollfd, 1, READY_TIMEOUT.as_millis() as libc::c_int) == 1
+      && libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) == 1
+  };
+
+  if !ready {
+    eprintln!("netmon didn't become ready, see its logs");
+    std::process::exit(1);
+  }
+  std::process::exit(0);
+}
+
+///
+/// Detaches from the terminal into the background: forks, starts a new
+/// session, and points stdio at /dev/null. The launching process exits once
+/// the daemon notifies the returned pipe, or fails if it never does, so init
+/// scripts know whether the daemon came up.
+///
+/// Must be called before any thread is spawned, as only the calling thread
+/// survives the fork.
+///
+/// Returns:
+///  Result of the pipe to notify once ready, in the detached process.
+///
+pub fn detach() -> Result<ReadyPipe> {
+  let mut fds = [0 as RawFd; 2];
+  // SAFETY: fds is a valid array of two descriptors.
+  if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+    return Err(Error::msg(format!(
+      "Failed to create the readiness pipe: {}",
+      std::io::Error::last_os_error()
+    )));
+  }
+  let [read_fd, write_fd] = fds;
+
+  // SAFETY: no other thread is running, see above.
+  match unsafe { libc::fork() } {
+    -1 => Err(Error::msg(format!(
+      "Failed to fork: {}",
+      std::io::Error::last_os_error()
+    ))),
+    0 => {
+      // SAFETY: the descriptors are valid, and /dev/null is opened before
+      // replacing stdio with it.
+      unsafe {
+        libc::close(read_fd);
+        libc::setsid();
+        libc::chdir(c"/".as_ptr());
+
+        let null_fd = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null_fd >= 0 {
+          for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            libc::dup2(null_fd, fd);
+          }
+          if null_fd > libc::STDERR_FILENO {
+            libc::close(null_fd);
+          }
+        }
+      }
+      Ok(ReadyPipe { fd: write_fd })
+    }
+    _ => {
+      // SAFETY: the write end belongs to the child.
+      unsafe {
+        libc::close(write_fd);
+      }
+      wait_for_ready(read_fd)
+    }
+  }
+}
@@ -0,0 +1,278 @@
+use super::{PollReport, Sink};
+use crate::neighbors::{run_command, MacAddr, NudState, ScopedIpAddr};
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Event log written when none is configured.
+pub const DEFAULT_EVENT_LOG_PATH: &str = "/var/log/netmon/events.log";
+
+/// Size past which the event log is rotated when none is configured.
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+/// Rotated event logs kept when none is configured.
+pub const DEFAULT_KEEP: usize = 5;
+
+/*
+  Every event is a line of logfmt key=value pairs, quoting values with spaces:
+
+    time=2026-10-14T04:36:15Z event=joined mac=dc:a6:32:a3:48:b1 iface=br-lan ips=192.168.0.5 name="Living room TV"
+    time=2026-10-14T04:36:45Z event=ip_changed mac=dc:a6:32:a3:48:b1 added=fe80::1%br-lan removed= name="Living room TV"
+    time=2026-10-14T04:37:15Z event=state_changed ip=192.168.0.5 mac=dc:a6:32:a3:48:b1 old_state=REACHABLE new_state=STALE name="Living room TV"
+    time=2026-10-14T04:37:45Z event=mac_changed ip=192.168.0.5 old_mac=dc:a6:32:a3:48:b1 new_mac=aa:bb:cc:dd:ee:ff
+    time=2026-10-14T04:38:15Z event=left mac=dc:a6:32:a3:48:b1 iface=br-lan ips=192.168.0.5 name="Living room TV"
+
+  Rotation shifts events.log to events.log.1.gz, events.log.1.gz to
+  events.log.2.gz, and so on, dropping the oldest.
+*/
+
+/// Appends a logfmt pair, quoting the value if needed.
+fn push_pair(line: &mut String, key: &str, value: &str) {
+  let needs_quotes = value.contains([' ', '"', '=']);
+  match needs_quotes {
+    true => {
+      let _ = write!(line, " {}=\"{}\"", key, value.replace('"', "\\\""));
+    }
+    false => {
+      let _ = write!(line, " {}={}", key, value);
+    }
+  }
+}
+
+fn join_ips(ips: &[ScopedIpAddr]) -> String {
+  let ips: Vec<String> = ips.iter().map(|v| v.to_string()).collect();
+  ips.join(",")
+}
+
+fn format_mac(mac_addr: Option<MacAddr>) -> String {
+  mac_addr.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn format_state(nud_state: NudState) -> String {
+  let names: Vec<&str> = nud_state.iter_names().map(|(name, _)| name).collect();
+  names.join("|")
+}
+
+///
+/// Formats every change of a poll as event log lines.
+///
+/// Args:
+///  - report: Poll to format.
+///
+/// Returns:
+///  The lines, each ending with a newline.
+///
+pub fn format_events(report: &PollReport) -> String {
+  let time = humantime::format_rfc3339_seconds(report.snapshot.taken_at).to_string();
+  let diff = &report.diff;
+  let mut lines = String::new();
+  let mut event = |kind: &str, pairs: &[(&str, String)]| {
+    let mut line = format!("time={} event={}", time, kind);
+    for (key, value) in pairs {
+      push_pair(&mut line, key, value);
+    }
+    lines.push_str(&line);
+    lines.push('\n');
+  };
+
+  for (kind, devices) in [("joined", &diff.joined), ("left", &diff.left)] {
+    for device in devices {
+      let mut pairs = vec![
+        ("mac", device.mac_addr.to_string()),
+        ("iface", device.iface.clone()),
+        ("ips", join_ips(&device.ips)),
+      ];
+      if let Some(name) = report.name_of(&device.mac_addr) {
+        pairs.push(("name", name.to_string()));
+      }
+      event(kind, &pairs);
+    }
+  }
+  for change in &diff.ip_changed {
+    let mut pairs = vec![
+      ("mac", change.mac_addr.to_string()),
+      ("added", join_ips(&change.added)),
+      ("removed", join_ips(&change.removed)),
+    ];
+    if let Some(name) = report.name_of(&change.mac_addr) {
+      pairs.push(("name", name.to_string()));
+    }
+    event("ip_changed", &pairs);
+  }
+  for change in &diff.mac_changed {
+    event(
+      "mac_changed",
+      &[
+        ("ip", change.ip.to_string()),
+        ("old_mac", format_mac(change.old_mac_addr)),
+        ("new_mac", format_mac(change.new_mac_addr)),
+      ],
+    );
+  }
+  for change in &diff.state_changed {
+    let mut pairs = vec![
+      ("ip", change.ip.to_string()),
+      ("mac", format_mac(change.mac_addr)),
+      ("old_state", format_state(change.old_state)),
+      ("new_state", format_state(change.new_state)),
+    ];
+    if let Some(name) = change.mac_addr.and_then(|v| report.name_of(&v)) {
+      pairs.push(("name", name.to_string()));
+    }
+    event("state_changed", &pairs);
+  }
+  lines
+}
+
+///
+/// Sink appending every device change to a log file of its own, rotated once
+/// it grows past a size, so the history survives log buffer wraparounds even
+/// without a database.
+///
+#[derive(Debug)]
+pub struct EventLogSink {
+  path: PathBuf,
+  max_size: u64,
+  keep: usize,
+  compress: bool,
+  file: Option<File>,
+}
+
+impl Default for EventLogSink {
+  fn default() -> Self {
+    EventLogSink::new(Path::new(DEFAULT_EVENT_LOG_PATH))
+  }
+}
+
+impl EventLogSink {
+  pub fn new(path: &Path) -> Self {
+    EventLogSink {
+      path: path.to_path_buf(),
+      max_size: DEFAULT_MAX_SIZE,
+      keep: DEFAULT_KEEP,
+      compress: true,
+      file: None,
+    }
+  }
+
+  /// Size past which the log is rotated.
+  pub fn max_size(mut self, max_size: u64) -> Self {
+    self.max_size = max_size;
+    self
+  }
+
+  /// Rotated logs kept, the oldest being removed. None are kept if 0.
+  pub fn keep(mut self, keep: usize) -> Self {
+    self.keep = keep;
+    self
+  }
+
+  /// Whether rotated logs are compressed with gzip.
+  pub fn compress(mut self, compress: bool) -> Self {
+    self.compress = compress;
+    self
+  }
+
+  /// Path of the n-th rotated log, compressed or not.
+  fn rotated_path(&self, n: usize, compressed: bool) -> PathBuf {
+    let mut path = self.path.clone().into_os_string();
+    path.push(format!(".{}", n));
+    if compressed {
+      path.push(".gz");
+    }
+    PathBuf::from(path)
+  }
+
+  /// Opens the log for appending, creating it along with its directory.
+  fn open(&mut self) -> Result<&mut File> {
+    if self.file.is_none() {
+      if let Some(parent) = self.path.parent() {
+        std::fs::create_dir_all(parent)
+          .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+      }
+      let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&self.path)
+        .map_err(|e| Error::msg(format!("Failed to open {}: {}", self.path.display(), e)))?;
+      self.file = Some(file);
+    }
+    Ok(self.file.as_mut().unwrap())
+  }
+
+  /// Shifts every rotated log by one, then rotates the current one.
+  fn rotate(&mut self) -> Result<()> {
+    self.file = None;
+    debug!("Rotating {}", self.path.display());
+
+    if self.keep == 0 {
+      return std::fs::remove_file(&self.path)
+        .map_err(|e| Error::msg(format!("Failed to remove {}: {}", self.path.display(), e)));
+    }
+    for compressed in [true, false] {
+      let _ = std::fs::remove_file(self.rotated_path(self.keep, compressed));
+    }
+    for n in (1..self.keep).rev() {
+      for compressed in [true, false] {
+        let from = self.rotated_path(n, compressed);
+        if from.exists() {
+          let _ = std::fs::rename(&from, self.rotated_path(n + 1, compressed));
+        }
+      }
+    }
+
+    let rotated = self.rotated_path(1, false);
+    std::fs::rename(&self.path, &rotated)
+      .map_err(|e| Error::msg(format!("Failed to rotate {}: {}", self.path.display(), e)))?;
+    if self.compress {
+      // busybox gzip, replacing the file with its .gz.
+      if let Err(err) = run_command("gzip", &[std::ffi::OsStr::new("-f"), rotated.as_os_str()]) {
+        warn!("Keeping {} uncompressed: {}", rotated.display(), err);
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Sink for EventLogSink {
+  fn name(&self) -> &str {
+    "event_log"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let lines = format_events(report);
+    if lines.is_empty() {
+      return Ok(());
+    }
+
+    let size = std::fs::metadata(&self.path).map_or(0, |v| v.len());
+    if size > 0 && size + lines.len() as u64 > self.max_size {
+      self.rotate()?;
+    }
+
+    let path = self.path.clone();
+    let file = self.open()?;
+    if let Err(e) = file.write_all(lines.as_bytes()) {
+      // Reopened on the next poll, e.g. if the file was removed.
+      self.file = None;
+      return Err(Error::msg(format!(
+        "Failed to write {}: {}",
+        path.display(),
+        e
+      )));
+    }
+    Ok(())
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    match self.file.as_mut() {
+      Some(file) => file
+        .sync_data()
+        .map_err(|e| Error::msg(format!("Failed to sync {}: {}", self.path.display(), e))),
+      None => Ok(()),
+    }
+  }
+}
@@ -0,0 +1,268 @@
+use super::metrics::{device_samples, neighbor_counts, unix_secs};
+use super::{hostname, PollReport, Sink};
+use crate::privacy::Anonymizer;
+use anyhow::{Error, Result};
+use log::info;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Prefix of the metrics' paths when none is configured.
+pub const DEFAULT_PREFIX: &str = "netmon";
+
+/// Longest time connecting to, or writing to, carbon may take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Datapoints per pickled message, as carbon caps the size of messages.
+const PICKLE_CHUNK: usize = 500;
+
+/// Wait before reconnecting after the first failure, doubled after each.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between reconnections.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/*
+  Every poll sends a datapoint per metric, at the time of the poll, e.g.
+  with the prefix "routers.{hostname}.netmon" in the plaintext protocol:
+
+    routers.office-ap.netmon.devices.dc_a6_32_a3_48_b1.online 1 1791953775
+    routers.office-ap.netmon.devices.dc_a6_32_a3_48_b1.last_seen 1791953775 1791953775
+    routers.office-ap.netmon.devices.dc_a6_32_a3_48_b1.rx_bytes 1024 1791953775
+    routers.office-ap.netmon.neighbors.reachable 12 1791953775
+    routers.office-ap.netmon.poll_duration_seconds 0.042 1791953775
+
+  Wireless stations also get tx_bytes, signal_dbm, and their bitrates. The
+  pickle protocol sends the same datapoints as pickled lists of
+  (path, (timestamp, value)) tuples, each prefixed by its length.
+*/
+
+/// Protocol carbon is sent the datapoints in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum GraphiteProtocol {
+  /// A line per datapoint, usually on port 2003.
+  #[default]
+  Plaintext,
+  /// Pickled batches of datapoints, usually on port 2004.
+  Pickle,
+}
+
+///
+/// Sink sending the presence of the devices, and the traffic of wireless
+/// stations, to a Graphite (carbon) server over TCP.
+///
+/// The sink connects on the first poll, and reconnects after a failure,
+/// waiting twice as long after each, up to 5 minutes. Polls made while
+/// disconnected are dropped.
+///
+#[derive(Debug)]
+pub struct GraphiteSink {
+  address: String,
+  protocol: GraphiteProtocol,
+  prefix: String,
+  anonymizer: Option<Anonymizer>,
+  stream: Option<TcpStream>,
+  backoff: Duration,
+  retry_at: Option<Instant>,
+}
+
+impl GraphiteSink {
+  ///
+  /// Creates a sink, without connecting yet.
+  ///
+  /// Args:
+  ///  - address: carbon's host and port, e.g. "10.0.0.1:2003".
+  ///  - protocol: Protocol carbon listens for on the port.
+  ///
+  pub fn new(address: &str, protocol: GraphiteProtocol) -> Self {
+    GraphiteSink {
+      address: address.to_string(),
+      protocol,
+      prefix: DEFAULT_PREFIX.to_string(),
+      anonymizer: None,
+      stream: None,
+      backoff: MIN_BACKOFF,
+      retry_at: None,
+    }
+  }
+
+  /// Prefix of the metrics' paths, "{hostname}" standing for the router's.
+  pub fn prefix(mut self, prefix: &str) -> Self {
+    let hostname = path_component(&hostname());
+    self.prefix = prefix.replace("{hostname}", &hostname);
+    self
+  }
+
+  /// Hashes the MAC addresses the devices' paths hold.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// The datapoints of a poll, as (path, value) pairs.
+  fn datapoints(&self, report: &PollReport) -> Vec<(String, f64)> {
+    let path = |v: &str| match self.prefix.is_empty() {
+      true => v.to_string(),
+      false => format!("{}.{}", self.prefix, v),
+    };
+    let mut datapoints = Vec::new();
+    for device in device_samples(report, self.anonymizer.as_ref()) {
+      let device_path = |v: &str| path(&format!("devices.{}.{}", path_component(&device.mac), v));
+      datapoints.push((device_path("online"), device.online as u8 as f64));
+      datapoints.push((device_path("last_seen"), unix_secs(device.last_seen) as f64));
+      let fields = [
+        ("rx_bytes", device.rx_bytes.map(|v| v as f64)),
+        ("tx_bytes", device.tx_bytes.map(|v| v as f64)),
+        ("signal_dbm", device.signal_dbm.map(f64::from)),
+        ("rx_bitrate_kbps", device.rx_bitrate_kbps.map(|v| v as f64)),
+        ("tx_bitrate_kbps", device.tx_bitrate_kbps.map(|v| v as f64)),
+      ];
+      for (field, value) in fields {
+        if let Some(value) = value {
+          datapoints.push((device_path(field), value));
+        }
+      }
+    }
+    for (nud_state, count) in neighbor_counts(report) {
+      let nud_state = path_component(&nud_state.to_lowercase());
+      datapoints.push((path(&format!("neighbors.{}", nud_state)), count as f64));
+    }
+    datapoints.push((path("poll_duration_seconds"), report.duration.as_secs_f64()));
+    datapoints
+  }
+
+  /// Connects to carbon if not connected yet.
+  fn connect(&mut self) -> Result<&mut TcpStream> {
+    if self.stream.is_none() {
+      if let Some(retry_at) = self.retry_at {
+        let wait = retry_at.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+          return Err(Error::msg(format!(
+            "Not connected to Graphite, retrying in {}",
+            humantime::format_duration(Duration::from_secs(wait.as_secs().max(1)))
+          )));
+        }
+      }
+      let stream = resolve(&self.address)
+        .and_then(|addr| {
+          let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+          stream.set_write_timeout(Some(TIMEOUT))?;
+          Ok(stream)
+        })
+        .map_err(|e| {
+          self.disconnect();
+          Error::msg(format!(
+            "Failed to connect to Graphite at {}: {}",
+            self.address, e
+          ))
+        })?;
+      info!("Connected to Graphite at {}", self.address);
+      self.backoff = MIN_BACKOFF;
+      self.retry_at = None;
+      self.stream = Some(stream);
+    }
+    Ok(self.stream.as_mut().unwrap())
+  }
+
+  /// Drops the connection, waiting longer before each reconnection.
+  fn disconnect(&mut self) {
+    self.stream = None;
+    self.retry_at = Some(Instant::now() + self.backoff);
+    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+  }
+}
+
+impl Sink for GraphiteSink {
+  fn name(&self) -> &str {
+    "graphite"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let time = unix_secs(report.snapshot.taken_at);
+    let datapoints = self.datapoints(report);
+    let messages: Vec<Vec<u8>> = match self.protocol {
+      GraphiteProtocol::Plaintext => vec![format_plaintext(&datapoints, time).into_bytes()],
+      GraphiteProtocol::Pickle => datapoints
+        .chunks(PICKLE_CHUNK)
+        .map(|v| format_pickle(v, time))
+        .collect(),
+    };
+
+    let stream = self.connect()?;
+    let written = messages.iter().try_for_each(|v| stream.write_all(v));
+    written.map_err(|e| {
+      self.disconnect();
+      Error::msg(format!("Failed to write to Graphite: {}", e))
+    })
+  }
+
+  fn needs_thread(&self) -> bool {
+    true
+  }
+}
+
+/// Resolves carbon's address, e.g. a hostname and port.
+fn resolve(address: &str) -> std::io::Result<SocketAddr> {
+  address
+    .to_socket_addrs()?
+    .next()
+    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address found"))
+}
+
+/// Replaces what Graphite takes as separators or patterns in a component of a path.
+fn path_component(value: &str) -> String {
+  value
+    .chars()
+    .map(
+      |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+        true => c,
+        false => '_',
+      },
+    )
+    .collect()
+}
+
+/// Formats datapoints in the plaintext protocol.
+fn format_plaintext(datapoints: &[(String, f64)], time: u64) -> String {
+  let mut out = String::new();
+  for (path, value) in datapoints {
+    out.push_str(&format!("{} {} {}\n", path, value, time));
+  }
+  out
+}
+
+///
+/// Formats datapoints as a message of the pickle protocol: a list of
+/// (path, (timestamp, value)) tuples in pickle protocol 2, prefixed by its
+/// length as a big-endian u32.
+///
+/// Args:
+///  - datapoints: Paths and values of the datapoints.
+///  - time: Unix time of the datapoints.
+///
+/// Returns:
+///  The message.
+///
+fn format_pickle(datapoints: &[(String, f64)], time: u64) -> Vec<u8> {
+  // PROTO 2, EMPTY_LIST, MARK.
+  let mut pickle = vec![0x80, 2, b']', b'('];
+  for (path, value) in datapoints {
+    // BINUNICODE, then the time and value as BINFLOATs, in nested TUPLE2s.
+    pickle.push(b'X');
+    pickle.extend_from_slice(&(path.len() as u32).to_le_bytes());
+    pickle.extend_from_slice(path.as_bytes());
+    pickle.push(b'G');
+    pickle.extend_from_slice(&(time as f64).to_be_bytes());
+    pickle.push(b'G');
+    pickle.extend_from_slice(&value.to_be_bytes());
+    pickle.extend_from_slice(&[0x86, 0x86]);
+  }
+  // APPENDS, STOP.
+  pickle.extend_from_slice(b"e.");
+
+  let mut message = (pickle.len() as u32).to_be_bytes().to_vec();
+  message.extend(pickle);
+  message
+}
@@ -0,0 +1,443 @@
+use super::metrics::{Metrics, PROMETHEUS_CONTENT_TYPE};
+use crate::alerts::Escalations;
+use crate::counters;
+use crate::neighbors::MacAddr;
+use crate::storage::{HistoryQuery, MemoryStorage};
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How long a client gets to send its request.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Intervals without a successful poll before the daemon counts as wedged.
+const STALE_INTERVALS: u32 = 3;
+
+/*
+  Both endpoints answer 200 when healthy and 503 otherwise, with the details
+  as plain text:
+
+    GET /healthz    Whether a poll succeeded within the last 3 intervals.
+    GET /readyz     Whether a poll succeeded yet, and every sink accepted the
+                    latest one.
+
+    HTTP/1.0 200 OK
+    Content-Type: text/plain
+
+    status=ok
+    uptime=3600s
+    last_poll=2026-10-14T04:36:15Z
+    last_success=2026-10-14T04:36:15Z
+    consecutive_failures=0
+    failed_polls=2
+    parse_errors=0
+    collector.neighbor.restarts=1
+    sink.log=ok
+    sink.state=ok
+
+  When the daemon keeps its recent history in memory, it's also served as
+  plain text, in the format of storage::RecentHistory:
+
+    GET /history/<mac>
+
+  Along with the metrics of the latest poll, for Prometheus to scrape, and
+  as JSON for scripts (see daemon::Metrics):
+
+    GET /metrics
+    GET /api/metrics.json
+
+  Alerts escalating until acknowledged are listed, one "<id> <title>" per
+  line, and acknowledged by their id, answering 404 once they no longer
+  escalate:
+
+    GET  /api/alerts
+    POST /api/alerts/<id>/ack
+*/
+
+/// Latest outcome of a sink.
+#[derive(Debug, Clone, Default)]
+struct SinkHealth {
+  last_error: Option<String>,
+  dropped: u64,
+}
+
+/// Failures the health counts, as the metrics report them.
+#[derive(Debug, Clone, Default)]
+pub struct HealthCounters {
+  pub failed_polls: u64,
+  /// Polls that failed since the latest success.
+  pub consecutive_failures: u32,
+  /// Times each collector was restarted by the watchdog.
+  pub restarts: BTreeMap<String, u64>,
+  /// Whether each sink accepted the latest poll, and the polls it dropped.
+  pub sinks: BTreeMap<String, (bool, u64)>,
+}
+
+#[derive(Debug)]
+struct HealthState {
+  started_at: SystemTime,
+  interval: Duration,
+  last_poll: Option<SystemTime>,
+  last_success: Option<SystemTime>,
+  last_error: Option<String>,
+  consecutive_failures: u32,
+  failed_polls: u64,
+  sinks: BTreeMap<String, SinkHealth>,
+  /// Times each collector was restarted by the watchdog.
+  restarts: BTreeMap<String, u64>,
+}
+
+///
+/// Outcome of the daemon's recent polls and of its sinks, shared with the
+/// health endpoint. Cloning the health shares its state.
+///
+/// ```
+/// use openwrt_netmon::daemon::Health;
+/// use std::time::Duration;
+///
+/// let health = Health::new(Duration::from_secs(30));
+/// assert!(!health.is_ready());
+///
+/// health.record_poll(&Ok(()));
+/// assert!(health.is_live() && health.is_ready());
+///
+/// health.record_sink("mqtt", &Err(anyhow::Error::msg("Connection refused")));
+/// assert!(!health.is_ready());
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct Health {
+  inner: Arc<Mutex<HealthState>>,
+}
+
+impl Default for Health {
+  fn default() -> Self {
+    Health::new(super::DEFAULT_INTERVAL)
+  }
+}
+
+impl Health {
+  pub fn new(interval: Duration) -> Self {
+    Health {
+      inner: Arc::new(Mutex::new(HealthState {
+        started_at: SystemTime::now(),
+        interval,
+        last_poll: None,
+        last_success: None,
+        last_error: None,
+        consecutive_failures: 0,
+        failed_polls: 0,
+        sinks: BTreeMap::new(),
+        restarts: BTreeMap::new(),
+      })),
+    }
+  }
+
+  /// Updates the poll interval, which decides when polls are overdue.
+  pub fn set_interval(&self, interval: Duration) {
+    self.inner.lock().unwrap().interval = interval;
+  }
+
+  /// Records the outcome of a poll.
+  pub fn record_poll(&self, result: &Result<()>) {
+    let mut state = self.inner.lock().unwrap();
+    let now = SystemTime::now();
+    state.last_poll = Some(now);
+    match result {
+      Ok(()) => {
+        state.last_success = Some(now);
+        state.last_error = None;
+        state.consecutive_failures = 0;
+      }
+      Err(err) => {
+        state.last_error = Some(err.to_string());
+        state.consecutive_failures += 1;
+        state.failed_polls += 1;
+      }
+    }
+  }
+
+  /// Records the outcome of a sink publishing a poll.
+  pub fn record_sink(&self, name: &str, result: &Result<()>) {
+    let mut state = self.inner.lock().unwrap();
+    let sink = state.sinks.entry(name.to_string()).or_default();
+    sink.last_error = result.as_ref().err().map(|e| e.to_string());
+  }
+
+  /// Records a poll dropped because the sink lagged behind.
+  pub fn record_dropped(&self, name: &str) {
+    let mut state = self.inner.lock().unwrap();
+    state.sinks.entry(name.to_string()).or_default().dropped += 1;
+  }
+
+  /// Records a stuck collector restarted by the watchdog.
+  pub fn record_restart(&self, collector: &str) {
+    let mut state = self.inner.lock().unwrap();
+    *state.restarts.entry(collector.to_string()).or_default() += 1;
+  }
+
+  /// Forgets a sink, once it's removed by a reload.
+  pub fn remove_sink(&self, name: &str) {
+    self.inner.lock().unwrap().sinks.remove(name);
+  }
+
+  /// Whether a poll succeeded recently, or the daemon only just started.
+  pub fn is_live(&self) -> bool {
+    let state = self.inner.lock().unwrap();
+    let since = state.last_success.unwrap_or(state.started_at);
+    let elapsed = SystemTime::now().duration_since(since).unwrap_or_default();
+    elapsed <= state.interval * STALE_INTERVALS
+  }
+
+  /// Whether a poll succeeded yet and every sink accepted the latest one.
+  pub fn is_ready(&self) -> bool {
+    let state = self.inner.lock().unwrap();
+    state.last_success.is_some()
+      && state.consecutive_failures == 0
+      && state.sinks.values().all(|v| v.last_error.is_none())
+  }
+
+  /// Time since the daemon started.
+  pub fn uptime(&self) -> Duration {
+    let started_at = self.inner.lock().unwrap().started_at;
+    SystemTime::now()
+      .duration_since(started_at)
+      .unwrap_or_default()
+  }
+
+  /// Failures counted so far.
+  pub fn counters(&self) -> HealthCounters {
+    let state = self.inner.lock().unwrap();
+    HealthCounters {
+      failed_polls: state.failed_polls,
+      consecutive_failures: state.consecutive_failures,
+      restarts: state.restarts.clone(),
+      sinks: state
+        .sinks
+        .iter()
+        .map(|(name, sink)| (name.clone(), (sink.last_error.is_none(), sink.dropped)))
+        .collect(),
+    }
+  }
+
+  /// Details of the health, as the endpoint reports them.
+  pub fn report(&self) -> String {
+    let state = self.inner.lock().unwrap();
+    let format_time = |time: Option<SystemTime>| {
+      time.map_or("-".to_string(), |v| {
+        humantime::format_rfc3339_seconds(v).to_string()
+      })
+    };
+    let uptime = SystemTime::now()
+      .duration_since(state.started_at)
+      .unwrap_or_default();
+
+    let mut report = format!(
+      "uptime={}s\nlast_poll={}\nlast_success={}\nconsecutive_failures={}\nfailed_polls={}\nparse_errors={}\n",
+      uptime.as_secs(),
+      format_time(state.last_poll),
+      format_time(state.last_success),
+      state.consecutive_failures,
+      state.failed_polls,
+      counters::parse_errors()
+    );
+    if let Some(err) = &state.last_error {
+      report += &format!("last_error={}\n", err);
+    }
+    for (name, restarts) in &state.restarts {
+      report += &format!("collector.{}.restarts={}\n", name, restarts);
+    }
+    for (name, sink) in &state.sinks {
+      match &sink.last_error {
+        Some(err) => report += &format!("sink.{}=error: {}\n", name, err),
+        None => report += &format!("sink.{}=ok\n", name),
+      }
+      if sink.dropped > 0 {
+        report += &format!("sink.{}.dropped={}\n", name, sink.dropped);
+      }
+    }
+    report
+  }
+}
+
+/// Answers a history request.
+fn write_history(
+  stream: &TcpStream,
+  mac_addr: &str,
+  history: Option<&MemoryStorage>,
+) -> std::io::Result<()> {
+  let Some(history) = history else {
+    return write_response(stream, "404 Not Found", "no in-memory history\n");
+  };
+  match mac_addr.parse::<MacAddr>() {
+    Ok(mac_addr) => {
+      let history = history.recent(&HistoryQuery::new().mac_addr(mac_addr));
+      write_response(stream, "200 OK", &history.to_string())
+    }
+    Err(err) => write_response(stream, "400 Bad Request", &format!("{}\n", err)),
+  }
+}
+
+/// Answers a single client.
+fn handle_client(
+  stream: TcpStream,
+  health: &Health,
+  history: Option<&MemoryStorage>,
+  metrics: &Metrics,
+  escalations: &Escalations,
+) -> std::io::Result<()> {
+  stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+  let mut request = String::new();
+  BufReader::new(&stream).take(1024).read_line(&mut request)?;
+
+  let mut parts = request.split_whitespace();
+  let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+  debug!("Health request {} {}", method, path);
+
+  let healthy = match (method, path) {
+    ("GET" | "HEAD", "/healthz") => health.is_live(),
+    ("GET" | "HEAD", "/readyz") => health.is_ready(),
+    ("GET", "/metrics") => {
+      let body = metrics.render(health);
+      return write_typed_response(&stream, "200 OK", PROMETHEUS_CONTENT_TYPE, &body);
+    }
+    #[cfg(feature = "json-metrics")]
+    ("GET", "/api/metrics.json") => {
+      let body = metrics.render_json(health);
+      return write_typed_response(&stream, "200 OK", "application/json", &body);
+    }
+    ("GET", path) if path.starts_with("/history/") => {
+      return write_history(&stream, &path["/history/".len()..], history)
+    }
+    ("GET", "/api/alerts") => {
+      let body: String = escalations
+        .pending()
+        .iter()
+        .map(|(id, title)| format!("{} {}\n", id, title))
+        .collect();
+      return write_response(&stream, "200 OK", &body);
+    }
+    ("POST", path) if path.starts_with("/api/alerts/") => {
+      return write_ack(&stream, &path["/api/alerts/".len()..], escalations)
+    }
+    ("GET" | "HEAD", _) => return write_response(&stream, "404 Not Found", "not found\n"),
+    _ => return write_response(&stream, "405 Method Not Allowed", "method not allowed\n"),
+  };
+
+  let (status, body) = match healthy {
+    true => ("200 OK", format!("status=ok\n{}", health.report())),
+    false => (
+      "503 Service Unavailable",
+      format!("status=unhealthy\n{}", health.report()),
+    ),
+  };
+  match method {
+    "HEAD" => write_response(&stream, status, ""),
+    _ => write_response(&stream, status, &body),
+  }
+}
+
+/// Acknowledges the escalating alert of an /api/alerts/<id>/ack request.
+fn write_ack(stream: &TcpStream, path: &str, escalations: &Escalations) -> std::io::Result<()> {
+  let Some(id) = path.strip_suffix("/ack") else {
+    return write_response(stream, "404 Not Found", "not found\n");
+  };
+  let Ok(id) = id.parse::<u32>() else {
+    return write_response(stream, "400 Bad Request", "invalid alert id\n");
+  };
+  match escalations.acknowledge(id, "over HTTP") {
+    true => write_response(stream, "200 OK", "acknowledged\n"),
+    false => write_response(
+      stream,
+      "404 Not Found",
+      &format!("alert {} isn't escalating\n", id),
+    ),
+  }
+}
+
+fn write_response(stream: &TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+  write_typed_response(stream, status, "text/plain", body)
+}
+
+fn write_typed_response(
+  mut stream: &TcpStream,
+  status: &str,
+  content_type: &str,
+  body: &str,
+) -> std::io::Result<()> {
+  write!(
+    stream,
+    "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    content_type,
+    body.len(),
+    body
+  )
+}
+
+///
+/// HTTP listener answering /healthz and /readyz, for blackbox probes and init
+/// scripts to detect a wedged daemon, along with /history of devices,
+/// /metrics, /api/metrics.json, and the /api/alerts escalating.
+///
+#[derive(Debug)]
+pub struct HealthServer {
+  addr: SocketAddr,
+}
+
+impl HealthServer {
+  ///
+  /// Listens for health checks on the given address.
+  ///
+  /// Args:
+  ///  - addr: Address to listen on, e.g. 127.0.0.1:9101.
+  ///  - health: Health to report.
+  ///  - history: Recent history to serve, if kept.
+  ///  - metrics: Metrics to serve, fed as a sink of the daemon.
+  ///  - escalations: Alerts escalating, to acknowledge, as the daemon's
+  ///    alert sinks share them.
+  ///
+  /// Returns:
+  ///  Result of the server, listening on a dedicated thread.
+  ///
+  pub fn bind(
+    addr: SocketAddr,
+    health: Health,
+    history: Option<MemoryStorage>,
+    metrics: Metrics,
+    escalations: Escalations,
+  ) -> Result<HealthServer> {
+    let listener =
+      TcpListener::bind(addr).map_err(|e| Error::msg(format!("Failed to bind {}: {}", addr, e)))?;
+    let addr = listener.local_addr().unwrap_or(addr);
+
+    std::thread::Builder::new()
+      .name("health".to_string())
+      .spawn(move || {
+        for stream in listener.incoming() {
+          match stream {
+            Ok(stream) => {
+              if let Err(err) =
+                handle_client(stream, &health, history.as_ref(), &metrics, &escalations)
+              {
+                debug!("Health client failed: {}", err);
+              }
+            }
+            Err(err) => warn!("Failed to accept a health client: {}", err),
+          }
+        }
+      })
+      .map_err(|e| Error::msg(format!("Failed to spawn the health thread: {}", e)))?;
+
+    Ok(HealthServer { addr })
+  }
+
+  /// Address the server listens on.
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+}
@@ -0,0 +1,223 @@
+use crate::neighbors::run_command_input;
+use anyhow::{Error, Result};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Longest time a request may take when none is set.
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/*
+  Requests are sent by curl, which OpenWrt ships with TLS support, reading
+  the whole request as a config on its stdin so neither the headers nor the
+  body, which may hold tokens, show up in the process list:
+
+    url = "https://influx.example.com/api/v2/write?org=home&bucket=netmon"
+    request = "POST"
+    header = "Authorization: Token s3cr3t"
+    data-raw = "netmon_device,mac=dc:a6:32:a3:48:b1 online=1i 1791953775\n"
+
+  curl prints the response's body, then its status on a line of its own.
+*/
+
+/// Status and body of an HTTP response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+  pub status: u16,
+  pub body: String,
+}
+
+impl HttpResponse {
+  /// Whether the status is 2xx.
+  pub fn is_success(&self) -> bool {
+    (200..300).contains(&self.status)
+  }
+}
+
+///
+/// An HTTP request, sent over curl.
+///
+/// ```no_run
+/// use openwrt_netmon::daemon::http::HttpRequest;
+///
+/// let response = HttpRequest::post("http://192.168.1.2:8086/write?db=netmon")
+///   .header("Content-Type", "text/plain")
+///   .body("netmon_device,mac=dc:a6:32:a3:48:b1 online=1i")
+///   .send()?;
+/// assert!(response.is_success());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+  method: &'static str,
+  url: String,
+  headers: Vec<(String, String)>,
+  basic_auth: Option<(String, String)>,
+  body: Option<String>,
+  body_file: Option<PathBuf>,
+  ca_file: Option<PathBuf>,
+  timeout: Duration,
+}
+
+impl HttpRequest {
+  pub fn new(method: &'static str, url: &str) -> Self {
+    HttpRequest {
+      method,
+      url: url.to_string(),
+      headers: Vec::new(),
+      basic_auth: None,
+      body: None,
+      body_file: None,
+      ca_file: None,
+      timeout: DEFAULT_HTTP_TIMEOUT,
+    }
+  }
+
+  pub fn get(url: &str) -> Self {
+    HttpRequest::new("GET", url)
+  }
+
+  pub fn post(url: &str) -> Self {
+    HttpRequest::new("POST", url)
+  }
+
+  pub fn header(mut self, name: &str, value: &str) -> Self {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+
+  /// Authenticates with a user name and password (Basic).
+  pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+    self.basic_auth = Some((username.to_string(), password.to_string()));
+    self
+  }
+
+  pub fn body(mut self, body: &str) -> Self {
+    self.body = Some(body.to_string());
+    self
+  }
+
+  /// Sends the content of a file as the body, as is, for binary bodies a
+  /// curl config can't hold.
+  pub fn body_file(mut self, path: &Path) -> Self {
+    self.body_file = Some(path.to_path_buf());
+    self
+  }
+
+  /// CA certificates to verify the server with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// Longest time the request may take, connecting included.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// The request as a curl config.
+  fn curl_config(&self) -> String {
+    let mut config = String::new();
+    let mut option = |name: &str, value: &str| {
+      let _ = writeln!(config, "{} = \"{}\"", name, escape(value));
+    };
+    option("url", &self.url);
+    option("request", self.method);
+    for (name, value) in &self.headers {
+      option("header", &format!("{}: {}", name, value));
+    }
+    if let Some((username, password)) = &self.basic_auth {
+      option("user", &format!("{}:{}", username, password));
+    }
+    if let Some(body) = &self.body {
+      option("data-raw", body);
+    }
+    if let Some(body_file) = &self.body_file {
+      option("data-binary", &format!("@{}", body_file.to_string_lossy()));
+    }
+    if let Some(ca_file) = &self.ca_file {
+      option("cacert", &ca_file.to_string_lossy());
+    }
+    option("max-time", &self.timeout.as_secs_f64().to_string());
+    option("proto", "=http,https");
+    option("write-out", "\n%{http_code}");
+    config.push_str("silent\nshow-error\ngloboff\n");
+    config
+  }
+
+  ///
+  /// Sends the request, waiting for the response.
+  ///
+  /// Returns:
+  ///  Result of the response, failing if none came, but not on error statuses.
+  ///
+  pub fn send(&self) -> Result<HttpResponse> {
+    let stdout = run_command_input(
+      "curl",
+      &["--config", "-"],
+      self.curl_config().as_bytes(),
+      // Leaves curl the time to fail on its own, with a better message.
+      self.timeout + Duration::from_secs(1),
+    )
+    .map_err(|e| Error::msg(format!("Failed to reach {}: {}", redact(&self.url), e)))?;
+    let (body, status) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+    let status = status.trim().parse().map_err(|_| {
+      Error::msg(format!(
+        "Invalid response from {}: no status",
+        redact(&self.url)
+      ))
+    })?;
+    Ok(HttpResponse {
+      status,
+      body: body.to_string(),
+    })
+  }
+}
+
+/// Escapes a quoted value of a curl config.
+pub(crate) fn escape(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    match c {
+      '\\' => escaped.push_str("\\\\"),
+      '"' => escaped.push_str("\\\""),
+      '\n' => escaped.push_str("\\n"),
+      '\r' => escaped.push_str("\\r"),
+      '\t' => escaped.push_str("\\t"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+/// Scheme and host of a URL, for messages, as its credentials, path, and
+/// query may hold secrets, e.g. webhook tokens.
+pub fn redact(url: &str) -> String {
+  let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+  let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+  let host = authority
+    .rsplit_once('@')
+    .map_or(authority, |(_, host)| host);
+  match scheme.is_empty() {
+    true => host.to_string(),
+    false => format!("{}://{}", scheme, host),
+  }
+}
+
+/// Percent-encodes a component of a URL's query.
+pub fn encode_component(value: &str) -> String {
+  let mut encoded = String::with_capacity(value.len());
+  for byte in value.bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+        encoded.push(byte as char)
+      }
+      _ => {
+        let _ = write!(encoded, "%{:02X}", byte);
+      }
+    }
+  }
+  encoded
+}
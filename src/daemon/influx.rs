@@ -0,0 +1,518 @@
+use super::http::{encode_component, redact, HttpRequest, HttpResponse};
+use super::metrics::{device_samples, unix_secs};
+use super::{PollReport, Sink};
+use crate::privacy::Anonymizer;
+use anyhow::{Error, Result};
+use log::{info, warn};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// Measurement of the devices' presence when none is configured.
+pub const DEFAULT_PRESENCE_MEASUREMENT: &str = "netmon_device";
+
+/// Measurement of the wireless stations' traffic when none is configured.
+pub const DEFAULT_TRAFFIC_MEASUREMENT: &str = "netmon_traffic";
+
+/// Longest time lines are batched before being written when none is configured.
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Lines written at once when none is configured.
+pub const DEFAULT_BATCH_SIZE: usize = 5000;
+
+/// Lines held while InfluxDB is unreachable when none is configured, past
+/// which the oldest are dropped.
+pub const DEFAULT_MAX_PENDING: usize = 10_000;
+
+/// Wait before retrying after the first failure, doubled after each.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/*
+  Every poll writes a line per device the daemon tracks, and one per
+  wireless station, at the time of the poll:
+
+    netmon_device,mac=dc:a6:32:a3:48:b1,name=Living\ room\ TV,iface=br-lan online=1i,last_seen=1791953775i 1791953775
+    netmon_traffic,mac=dc:a6:32:a3:48:b1,name=Living\ room\ TV,iface=br-lan rx_bytes=1024i,tx_bytes=2048i,signal_dbm=-52i 1791953775
+
+  The tags are those configured, along with the static ones, leaving out
+  the ones without a value. Stations only have the fields iw reported.
+  Lines printed for Telegraf's exec input, by `netmon snapshot`, have
+  timestamps in nanoseconds instead, which Telegraf expects by default.
+*/
+
+/// Tag of the devices' lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum InfluxTag {
+  Mac,
+  /// Best known name of the device, see PollReport::name_of.
+  Name,
+  Iface,
+  /// Manufacturer, according to the OUI database.
+  Vendor,
+}
+
+/// Tags of the devices' lines when none are configured.
+pub const DEFAULT_TAGS: &[InfluxTag] = &[InfluxTag::Mac, InfluxTag::Name, InfluxTag::Iface];
+
+/// Database, and credentials, lines are written to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InfluxTarget {
+  /// InfluxDB 2 bucket, authenticated with an API token.
+  V2 {
+    org: String,
+    bucket: String,
+    token: Option<String>,
+  },
+  /// InfluxDB 1 database, authenticated with a user if any.
+  V1 {
+    database: String,
+    username: Option<String>,
+    password: Option<String>,
+  },
+}
+
+///
+/// Sink pushing the presence of the devices, and the traffic of wireless
+/// stations, to InfluxDB 1 or 2 in the line protocol over HTTP.
+///
+/// Lines are batched for the flush interval, or until a batch is full. A
+/// batch InfluxDB can't be reached for is kept and retried, waiting twice as
+/// long after each failure, up to 5 minutes, dropping the oldest lines past
+/// the pending limit. Batches InfluxDB rejects as malformed are dropped.
+///
+#[derive(Debug)]
+pub struct InfluxSink {
+  url: String,
+  target: InfluxTarget,
+  ca_file: Option<PathBuf>,
+  format: LineFormat,
+  flush_interval: Duration,
+  batch_size: usize,
+  max_pending: usize,
+  anonymizer: Option<Anonymizer>,
+  pending: VecDeque<String>,
+  last_write: Instant,
+  backoff: Duration,
+  retry_at: Option<Instant>,
+}
+
+impl InfluxSink {
+  ///
+  /// Creates a sink, without connecting yet.
+  ///
+  /// Args:
+  ///  - url: Base URL of InfluxDB, e.g. "http://192.168.1.2:8086".
+  ///  - target: Database to write to.
+  ///
+  pub fn new(url: &str, target: InfluxTarget) -> Self {
+    InfluxSink {
+      url: url.trim_end_matches('/').to_string(),
+      target,
+      ca_file: None,
+      format: LineFormat::default(),
+      flush_interval: DEFAULT_FLUSH_INTERVAL,
+      batch_size: DEFAULT_BATCH_SIZE,
+      max_pending: DEFAULT_MAX_PENDING,
+      anonymizer: None,
+      pending: VecDeque::new(),
+      last_write: Instant::now(),
+      backoff: MIN_BACKOFF,
+      retry_at: None,
+    }
+  }
+
+  /// CA certificates to verify an https URL with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// Measurement of the devices' presence, left out if empty.
+  pub fn presence_measurement(mut self, measurement: &str) -> Self {
+    self.format.presence_measurement = measurement.to_string();
+    self
+  }
+
+  /// Measurement of the wireless stations' traffic, left out if empty.
+  pub fn traffic_measurement(mut self, measurement: &str) -> Self {
+    self.format.traffic_measurement = measurement.to_string();
+    self
+  }
+
+  /// Tags of every line, taken from its device.
+  pub fn tags(mut self, tags: Vec<InfluxTag>) -> Self {
+    self.format.tags = tags;
+    self
+  }
+
+  /// Tags given to every line as is, e.g. router=office-ap.
+  pub fn static_tags(mut self, static_tags: BTreeMap<String, String>) -> Self {
+    self.format.static_tags = static_tags;
+    self
+  }
+
+  /// Longest time lines are batched, every poll being written for "0s".
+  pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+    self.flush_interval = flush_interval;
+    self
+  }
+
+  /// Lines written at once, at least one.
+  pub fn batch_size(mut self, batch_size: usize) -> Self {
+    self.batch_size = batch_size.max(1);
+    self
+  }
+
+  /// Lines held while InfluxDB is unreachable, past which the oldest are dropped.
+  pub fn max_pending(mut self, max_pending: usize) -> Self {
+    self.max_pending = max_pending;
+    self
+  }
+
+  /// Hashes the MAC addresses and names tagging the lines.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// URL of the write endpoint, with the database in its query.
+  fn write_url(&self) -> String {
+    match &self.target {
+      InfluxTarget::V2 { org, bucket, .. } => format!(
+        "{}/api/v2/write?org={}&bucket={}&precision=s",
+        self.url,
+        encode_component(org),
+        encode_component(bucket)
+      ),
+      InfluxTarget::V1 { database, .. } => format!(
+        "{}/write?db={}&precision=s",
+        self.url,
+        encode_component(database)
+      ),
+    }
+  }
+
+  /// Sends a batch of lines, returning InfluxDB's response.
+  fn send(&self, lines: &[String]) -> Result<HttpResponse> {
+    let mut body = lines.join("\n");
+    body.push('\n');
+    let mut request = HttpRequest::post(&self.write_url())
+      .header("Content-Type", "text/plain; charset=utf-8")
+      .body(&body);
+    match &self.target {
+      InfluxTarget::V2 {
+        token: Some(token), ..
+      } => request = request.header("Authorization", &format!("Token {}", token)),
+      InfluxTarget::V1 {
+        username: Some(username),
+        password,
+        ..
+      } => request = request.basic_auth(username, password.as_deref().unwrap_or_default()),
+      _ => {}
+    }
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    request.send()
+  }
+
+  ///
+  /// Writes the pending lines, a batch at a time, unless waiting to retry.
+  ///
+  /// Returns:
+  ///  Result reflecting whether every pending line was written, failing if a
+  ///  batch was rejected, then dropped, or has to be retried.
+  ///
+  fn write_pending(&mut self) -> Result<()> {
+    if let Some(retry_at) = self.retry_at {
+      let wait = retry_at.saturating_duration_since(Instant::now());
+      if !wait.is_zero() {
+        return Err(Error::msg(format!(
+          "InfluxDB is unreachable, retrying in {}",
+          humantime::format_duration(Duration::from_secs(wait.as_secs().max(1)))
+        )));
+      }
+    }
+
+    self.last_write = Instant::now();
+    let retried = self.retry_at.is_some();
+    while !self.pending.is_empty() {
+      let count = self.pending.len().min(self.batch_size);
+      let batch: Vec<String> = self.pending.iter().take(count).cloned().collect();
+      let err = match self.send(&batch) {
+        Ok(response) if response.is_success() => {
+          self.pending.drain(..count);
+          continue;
+        }
+        // Malformed lines, which retrying won't fix.
+        Ok(response) if matches!(response.status, 400 | 413 | 422) => {
+          self.pending.drain(..count);
+          return Err(Error::msg(format!(
+            "InfluxDB rejected {} line(s) ({}), dropping them: {}",
+            count,
+            response.status,
+            response.body.trim()
+          )));
+        }
+        Ok(response) => Error::msg(format!(
+          "InfluxDB at {} answered {}: {}",
+          redact(&self.url),
+          response.status,
+          response.body.trim()
+        )),
+        Err(err) => err,
+      };
+      self.retry_at = Some(Instant::now() + self.backoff);
+      self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+      return Err(err);
+    }
+    if retried {
+      info!("InfluxDB is reachable again");
+    }
+    self.backoff = MIN_BACKOFF;
+    self.retry_at = None;
+    Ok(())
+  }
+}
+
+impl Sink for InfluxSink {
+  fn name(&self) -> &str {
+    "influxdb"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let lines = self
+      .format
+      .lines(report, self.anonymizer.as_ref(), Precision::Seconds);
+    self.pending.extend(lines);
+    if self.pending.len() > self.max_pending {
+      let dropped = self.pending.len() - self.max_pending;
+      self.pending.drain(..dropped);
+      warn!(
+        "Dropping the {} oldest line(s) waiting for InfluxDB",
+        dropped
+      );
+    }
+
+    let due = self.last_write.elapsed() >= self.flush_interval;
+    match due || self.pending.len() >= self.batch_size {
+      true => self.write_pending(),
+      false => Ok(()),
+    }
+  }
+
+  fn needs_thread(&self) -> bool {
+    true
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    if self.pending.is_empty() {
+      return Ok(());
+    }
+    // A last attempt, even while waiting to retry.
+    self.retry_at = None;
+    let pending = self.pending.len();
+    self
+      .write_pending()
+      .map_err(|e| Error::msg(format!("Dropping {} line(s) for InfluxDB: {}", pending, e)))
+  }
+}
+
+/// Measurements and tags of the lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LineFormat {
+  presence_measurement: String,
+  traffic_measurement: String,
+  tags: Vec<InfluxTag>,
+  static_tags: BTreeMap<String, String>,
+}
+
+impl Default for LineFormat {
+  fn default() -> Self {
+    LineFormat {
+      presence_measurement: DEFAULT_PRESENCE_MEASUREMENT.to_string(),
+      traffic_measurement: DEFAULT_TRAFFIC_MEASUREMENT.to_string(),
+      tags: DEFAULT_TAGS.to_vec(),
+      static_tags: BTreeMap::new(),
+    }
+  }
+}
+
+/// Unit of the lines' timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Precision {
+  Seconds,
+  Nanoseconds,
+}
+
+impl LineFormat {
+  /// Formats the lines of a poll.
+  fn lines(
+    &self,
+    report: &PollReport,
+    anonymizer: Option<&Anonymizer>,
+    precision: Precision,
+  ) -> Vec<String> {
+    let time = match precision {
+      Precision::Seconds => unix_secs(report.snapshot.taken_at) as u128,
+      Precision::Nanoseconds => report
+        .snapshot
+        .taken_at
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_nanos())
+        .unwrap_or_default(),
+    };
+    let mut lines = Vec::new();
+    for device in device_samples(report, anonymizer) {
+      let mut tags = String::new();
+      for tag in &self.tags {
+        let value = match tag {
+          InfluxTag::Mac => &device.mac,
+          InfluxTag::Name => &device.name,
+          InfluxTag::Iface => &device.iface,
+          InfluxTag::Vendor => device.vendor.unwrap_or_default(),
+        };
+        push_tag(&mut tags, tag_key(*tag), value);
+      }
+      for (key, value) in &self.static_tags {
+        push_tag(&mut tags, key, value);
+      }
+
+      if !self.presence_measurement.is_empty() {
+        lines.push(format!(
+          "{}{} online={}i,last_seen={}i {}",
+          escape_key(&self.presence_measurement),
+          tags,
+          device.online as u8,
+          unix_secs(device.last_seen),
+          time
+        ));
+      }
+      if !self.traffic_measurement.is_empty() && device.has_traffic() {
+        let mut fields = Vec::new();
+        let mut field = |key: &str, value: Option<i64>| {
+          if let Some(value) = value {
+            fields.push(format!("{}={}i", key, value));
+          }
+        };
+        field("rx_bytes", device.rx_bytes.map(|v| v as i64));
+        field("tx_bytes", device.tx_bytes.map(|v| v as i64));
+        field("signal_dbm", device.signal_dbm.map(i64::from));
+        field("rx_bitrate_kbps", device.rx_bitrate_kbps.map(|v| v as i64));
+        field("tx_bitrate_kbps", device.tx_bitrate_kbps.map(|v| v as i64));
+        lines.push(format!(
+          "{}{} {} {}",
+          escape_key(&self.traffic_measurement),
+          tags,
+          fields.join(","),
+          time
+        ));
+      }
+    }
+    lines
+  }
+}
+
+///
+/// Sink printing the presence of the devices, and the traffic of wireless
+/// stations, in the line protocol, for Telegraf's exec input to read off
+/// `netmon snapshot --format influx`.
+///
+pub struct InfluxLineSink {
+  out: Box<dyn Write + Send>,
+  format: LineFormat,
+  anonymizer: Option<Anonymizer>,
+}
+
+impl InfluxLineSink {
+  ///
+  /// Creates a sink, with the default measurements and tags.
+  ///
+  /// Args:
+  ///  - out: Where to print the lines, Telegraf reading the exec'd
+  ///    command's stdout.
+  ///
+  pub fn new(out: Box<dyn Write + Send>) -> Self {
+    InfluxLineSink {
+      out,
+      format: LineFormat::default(),
+      anonymizer: None,
+    }
+  }
+
+  /// Hashes the MAC addresses and names tagging the lines.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+}
+
+impl Sink for InfluxLineSink {
+  fn name(&self) -> &str {
+    "influx-lines"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let mut out = String::new();
+    for line in self
+      .format
+      .lines(report, self.anonymizer.as_ref(), Precision::Nanoseconds)
+    {
+      out.push_str(&line);
+      out.push('\n');
+    }
+    self
+      .out
+      .write_all(out.as_bytes())
+      .and_then(|_| self.out.flush())
+      .map_err(|e| Error::msg(format!("Failed to print the lines: {}", e)))
+  }
+}
+
+impl std::fmt::Debug for InfluxLineSink {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("InfluxLineSink")
+      .field("format", &self.format)
+      .finish_non_exhaustive()
+  }
+}
+
+fn tag_key(tag: InfluxTag) -> &'static str {
+  match tag {
+    InfluxTag::Mac => "mac",
+    InfluxTag::Name => "name",
+    InfluxTag::Iface => "iface",
+    InfluxTag::Vendor => "vendor",
+  }
+}
+
+/// Escapes a measurement, tag key, or tag value of the line protocol.
+fn escape_key(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    match c {
+      ',' | '=' | ' ' | '\\' => {
+        escaped.push('\\');
+        escaped.push(c);
+      }
+      // Lines can't hold line breaks, even escaped.
+      '\n' | '\r' => escaped.push(' '),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+/// Appends a tag, unless its value is empty, which the line protocol forbids.
+fn push_tag(tags: &mut String, key: &str, value: &str) {
+  if !value.is_empty() {
+    let _ = write!(tags, ",{}={}", escape_key(key), escape_key(value));
+  }
+}
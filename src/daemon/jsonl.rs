@@ -0,0 +1,219 @@
+use super::{PollReport, Sink};
+use crate::privacy::Anonymizer;
+use crate::storage::{history_events, HistoryEvent};
+use anyhow::{Error, Result};
+use log::debug;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// File written when none is configured.
+pub const DEFAULT_JSONL_PATH: &str = "/var/log/netmon/events.jsonl";
+
+///
+/// Version of the records' schema. Fields are only ever added within a
+/// version, so parsers should ignore the ones they don't know; renaming or
+/// removing a field, or changing its meaning, bumps the version.
+///
+pub const SCHEMA_VERSION: u32 = 1;
+
+/*
+  Every event is a line holding a single JSON object, e.g.:
+
+    {"schema_version":1,"time":"2026-10-14T04:36:15Z","event":"joined","mac":"dc:a6:32:a3:48:b1","iface":"br-lan","new_value":"192.168.0.5","name":"Living room TV"}
+    {"schema_version":1,"time":"2026-10-14T04:37:15Z","event":"state_changed","mac":"dc:a6:32:a3:48:b1","ip":"192.168.0.5","old_value":"REACHABLE","new_value":"STALE"}
+
+  Fields without a value are left out. See storage::HistoryEvent for what
+  each event's fields hold. When anonymizing, MAC addresses and names are
+  hashed (see privacy::Anonymizer).
+*/
+
+/// A JSON Lines record of a single event.
+#[derive(Debug, Serialize)]
+pub struct JsonlRecord<'a> {
+  pub schema_version: u32,
+  /// RFC 3339 time of the poll that found the change, in UTC.
+  pub time: String,
+  pub event: &'static str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub mac: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ip: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub iface: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub old_value: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub new_value: Option<&'a str>,
+  /// Best known name of the device, see Device::name.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub name: Option<&'a str>,
+}
+
+impl<'a> JsonlRecord<'a> {
+  pub fn new(event: &'a HistoryEvent, name: Option<&'a str>) -> Self {
+    JsonlRecord {
+      schema_version: SCHEMA_VERSION,
+      time: humantime::format_rfc3339_seconds(event.time).to_string(),
+      event: event.kind.name(),
+      mac: event.mac_addr.map(|v| v.to_string()),
+      ip: event.ip.as_ref().map(|v| v.to_string()),
+      iface: event.iface.as_deref(),
+      old_value: event.old_value.as_deref(),
+      new_value: event.new_value.as_deref(),
+      name,
+    }
+  }
+}
+
+///
+/// Formats every change of a poll as JSON Lines records.
+///
+/// Args:
+///  - report: Poll to format.
+///  - anonymizer: Hashes the identifiers of the devices, if set.
+///
+/// Returns:
+///  Result of the lines, each ending with a newline.
+///
+pub fn format_records(report: &PollReport, anonymizer: Option<&Anonymizer>) -> Result<String> {
+  let mut lines = String::new();
+  for event in history_events(report) {
+    let name = event.mac_addr.and_then(|v| report.name_of(&v));
+    let (event, name) = match anonymizer {
+      Some(anonymizer) => (anonymizer.event(&event), name.map(|v| anonymizer.name(v))),
+      None => (event, name.map(str::to_string)),
+    };
+    let line = serde_json::to_string(&JsonlRecord::new(&event, name.as_deref()))
+      .map_err(|e| Error::msg(format!("Failed to serialize an event: {}", e)))?;
+    lines.push_str(&line);
+    lines.push('\n');
+  }
+  Ok(lines)
+}
+
+///
+/// Sink appending one JSON object per device change to a file, for log
+/// shippers such as Vector or Fluent Bit to ingest. The file may also be a
+/// FIFO: events are then dropped while no reader has it open, and once its
+/// buffer is full, rather than blocking the sink.
+///
+#[derive(Debug)]
+pub struct JsonlSink {
+  path: PathBuf,
+  file: Option<File>,
+  anonymizer: Option<Anonymizer>,
+}
+
+impl Default for JsonlSink {
+  fn default() -> Self {
+    JsonlSink::new(Path::new(DEFAULT_JSONL_PATH))
+  }
+}
+
+impl JsonlSink {
+  pub fn new(path: &Path) -> Self {
+    JsonlSink {
+      path: path.to_path_buf(),
+      file: None,
+      anonymizer: None,
+    }
+  }
+
+  /// Hashes the MAC addresses and names of the devices in every record.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  fn is_fifo(&self) -> bool {
+    std::fs::metadata(&self.path).is_ok_and(|v| v.file_type().is_fifo())
+  }
+
+  ///
+  /// Opens the file for appending, creating it along with its directory
+  /// unless it's a FIFO.
+  ///
+  /// Returns:
+  ///  Result of the file, None if it's a FIFO without a reader.
+  ///
+  fn open(&mut self) -> Result<Option<&mut File>> {
+    if self.file.is_none() {
+      let fifo = self.is_fifo();
+      if !fifo {
+        if let Some(parent) = self.path.parent() {
+          std::fs::create_dir_all(parent)
+            .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+        }
+      }
+      // Non-blocking, so a FIFO without a reader fails instead of hanging.
+      let file = OpenOptions::new()
+        .create(!fifo)
+        .append(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&self.path);
+      match file {
+        Ok(file) => self.file = Some(file),
+        Err(e) if fifo && e.raw_os_error() == Some(libc::ENXIO) => return Ok(None),
+        Err(e) => {
+          return Err(Error::msg(format!(
+            "Failed to open {}: {}",
+            self.path.display(),
+            e
+          )))
+        }
+      }
+    }
+    Ok(self.file.as_mut())
+  }
+}
+
+impl Sink for JsonlSink {
+  fn name(&self) -> &str {
+    "jsonl"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let lines = format_records(report, self.anonymizer.as_ref())?;
+    if lines.is_empty() {
+      return Ok(());
+    }
+
+    let path = self.path.clone();
+    let Some(file) = self.open()? else {
+      debug!(
+        "No reader on {}, dropping the poll's events",
+        path.display()
+      );
+      return Ok(());
+    };
+    // Lines shorter than PIPE_BUF are written whole or not at all to a FIFO.
+    let written = lines
+      .split_inclusive('\n')
+      .try_for_each(|line| file.write_all(line.as_bytes()));
+    match written {
+      Ok(()) => Ok(()),
+      Err(e) if e.kind() == ErrorKind::WouldBlock => {
+        debug!("{} is full, dropping events", path.display());
+        Ok(())
+      }
+      Err(e) => {
+        // Reopened on the next poll, e.g. once a FIFO's reader is back.
+        self.file = None;
+        match e.kind() {
+          ErrorKind::BrokenPipe => {
+            debug!("The reader of {} left, dropping events", path.display());
+            Ok(())
+          }
+          _ => Err(Error::msg(format!(
+            "Failed to write {}: {}",
+            path.display(),
+            e
+          ))),
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,467 @@
+use super::{Health, PollReport, Sink};
+use crate::counters;
+use crate::privacy::Anonymizer;
+use crate::storage::format_state;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Content type of the Prometheus text format.
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/*
+  GET /metrics answers in the Prometheus text format, with a sample per
+  device the daemon tracks, online or not:
+
+    # HELP netmon_device_online Whether the device is online.
+    # TYPE netmon_device_online gauge
+    netmon_device_online{mac="dc:a6:32:a3:48:b1",alias="Living room TV",iface="br-lan"} 1
+    # HELP netmon_device_last_seen_timestamp_seconds Time the device was last seen.
+    # TYPE netmon_device_last_seen_timestamp_seconds gauge
+    netmon_device_last_seen_timestamp_seconds{mac="dc:a6:32:a3:48:b1",alias="Living room TV",iface="br-lan"} 1791953775
+    # HELP netmon_neighbors_total Neighbor entries, by NUD state.
+    # TYPE netmon_neighbors_total gauge
+    netmon_neighbors_total{nud_state="REACHABLE"} 12
+    netmon_neighbors_total{nud_state="STALE"} 3
+    # HELP netmon_poll_duration_seconds Time the latest poll took.
+    # TYPE netmon_poll_duration_seconds gauge
+    netmon_poll_duration_seconds 0.042
+
+  Along with the time of the latest poll, and the failures of the polls,
+  collectors, and sinks the health endpoints report. Devices without a name
+  have an empty alias.
+
+  GET /api/metrics.json answers with the same metrics as a JSON object, for
+  scripts, along with the devices of each interface:
+
+    {
+      "last_poll": 1791953775,
+      "poll_duration_seconds": 0.042,
+      "devices": {"total": 14, "online": 12, "wireless": 5},
+      "interfaces": {"br-lan": {"total": 14, "online": 12, "wireless": 5}},
+      "neighbors": {"REACHABLE": 12, "STALE": 3},
+      "monitor": {
+        "uptime_seconds": 3600, "live": true, "ready": true,
+        "failed_polls": 2, "consecutive_failures": 0, "parse_errors": 0,
+        "collector_restarts": {"neighbor": 1},
+        "sinks": {"log": {"up": true, "dropped": 0}}
+      }
+    }
+
+  Wireless devices are those iw reported traffic for. last_poll is null
+  until the first poll.
+*/
+
+/// A device, as the metrics sinks sample it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceSample {
+  /// MAC address, hashed when anonymizing.
+  pub mac: String,
+  /// Best known name, see PollReport::name_of, hashed when anonymizing and
+  /// empty if none.
+  pub name: String,
+  pub iface: String,
+  /// Manufacturer, according to the OUI database.
+  pub vendor: Option<&'static str>,
+  pub online: bool,
+  pub last_seen: SystemTime,
+  /// Traffic of the device if it's a wireless station, as iw reported it.
+  pub rx_bytes: Option<u64>,
+  pub tx_bytes: Option<u64>,
+  pub signal_dbm: Option<i32>,
+  pub rx_bitrate_kbps: Option<u64>,
+  pub tx_bitrate_kbps: Option<u64>,
+}
+
+impl DeviceSample {
+  /// Whether the device is a wireless station iw reported traffic for.
+  pub fn has_traffic(&self) -> bool {
+    self.rx_bytes.is_some()
+      || self.tx_bytes.is_some()
+      || self.signal_dbm.is_some()
+      || self.rx_bitrate_kbps.is_some()
+      || self.tx_bitrate_kbps.is_some()
+  }
+}
+
+///
+/// Samples every device the daemon tracks as of a poll, online or not.
+///
+/// Args:
+///  - report: The poll.
+///  - anonymizer: Hashes the MAC addresses and names, if any.
+///
+/// Returns:
+///  The samples, sorted by MAC address.
+///
+pub fn device_samples(report: &PollReport, anonymizer: Option<&Anonymizer>) -> Vec<DeviceSample> {
+  let mut samples: Vec<DeviceSample> = report
+    .tracker
+    .neighbors()
+    .map(|neighbor| {
+      let name = report.name_of(&neighbor.mac_addr).unwrap_or_default();
+      let (mac, name) = match anonymizer {
+        Some(anonymizer) => (
+          anonymizer.mac_addr(&neighbor.mac_addr).to_string(),
+          match name.is_empty() {
+            true => String::new(),
+            false => anonymizer.name(name),
+          },
+        ),
+        None => (neighbor.mac_addr.to_string(), name.to_string()),
+      };
+      let station = report
+        .devices
+        .iter()
+        .find(|v| v.mac_addr == neighbor.mac_addr)
+        .and_then(|v| v.station.as_ref());
+      DeviceSample {
+        mac,
+        name,
+        iface: neighbor.iface.clone(),
+        vendor: neighbor.mac_addr.vendor(),
+        online: neighbor.online,
+        last_seen: neighbor.last_seen,
+        rx_bytes: station.and_then(|v| v.rx_bytes),
+        tx_bytes: station.and_then(|v| v.tx_bytes),
+        signal_dbm: station.and_then(|v| v.signal_dbm),
+        rx_bitrate_kbps: station.and_then(|v| v.rx_bitrate_kbps),
+        tx_bitrate_kbps: station.and_then(|v| v.tx_bitrate_kbps),
+      }
+    })
+    .collect();
+  samples.sort_by(|a, b| a.mac.cmp(&b.mac));
+  samples
+}
+
+///
+/// Neighbor entries of a poll, by NUD state.
+///
+/// Returns:
+///  The number of entries, keyed by their NUD state names.
+///
+pub fn neighbor_counts(report: &PollReport) -> BTreeMap<String, u64> {
+  let mut neighbors = BTreeMap::new();
+  for entry in &report.snapshot.entries {
+    *neighbors.entry(format_state(entry.nud_state)).or_default() += 1;
+  }
+  neighbors
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+  last_poll: Option<SystemTime>,
+  poll_duration: Duration,
+  devices: Vec<DeviceSample>,
+  /// Neighbor entries, keyed by their NUD state names.
+  neighbors: BTreeMap<String, u64>,
+}
+
+///
+/// Sink keeping the metrics of the latest poll, for a HealthServer to serve
+/// at /metrics, and an SnmpServer. Cloning the metrics shares them.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+  inner: Arc<Mutex<MetricsState>>,
+  anonymizer: Option<Anonymizer>,
+}
+
+impl Metrics {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Hashes the MAC addresses and names labelling the devices' samples.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// The devices as of the latest poll, sorted by MAC address.
+  pub fn devices(&self) -> Vec<DeviceSample> {
+    self.inner.lock().unwrap().devices.clone()
+  }
+
+  /// Neighbor entries of the latest poll, keyed by their NUD state names.
+  pub fn neighbors(&self) -> BTreeMap<String, u64> {
+    self.inner.lock().unwrap().neighbors.clone()
+  }
+
+  /// Time of the latest poll, and how long it took, if any.
+  pub fn last_poll(&self) -> Option<(SystemTime, Duration)> {
+    let state = self.inner.lock().unwrap();
+    state.last_poll.map(|v| (v, state.poll_duration))
+  }
+
+  ///
+  /// Formats the metrics in the Prometheus text format.
+  ///
+  /// Args:
+  ///  - health: Health of the daemon, for the failures it counts.
+  ///
+  /// Returns:
+  ///  The metrics, each with its help and type.
+  ///
+  pub fn render(&self, health: &Health) -> String {
+    let state = self.inner.lock().unwrap();
+    let mut out = String::new();
+
+    header(
+      &mut out,
+      "netmon_device_online",
+      "gauge",
+      "Whether the device is online.",
+    );
+    for device in &state.devices {
+      sample(
+        &mut out,
+        "netmon_device_online",
+        &device_labels(device),
+        device.online as u8,
+      );
+    }
+    header(
+      &mut out,
+      "netmon_device_last_seen_timestamp_seconds",
+      "gauge",
+      "Time the device was last seen.",
+    );
+    for device in &state.devices {
+      sample(
+        &mut out,
+        "netmon_device_last_seen_timestamp_seconds",
+        &device_labels(device),
+        unix_secs(device.last_seen),
+      );
+    }
+
+    header(
+      &mut out,
+      "netmon_neighbors_total",
+      "gauge",
+      "Neighbor entries, by NUD state.",
+    );
+    for (nud_state, count) in &state.neighbors {
+      sample(
+        &mut out,
+        "netmon_neighbors_total",
+        &[("nud_state", nud_state)],
+        count,
+      );
+    }
+
+    header(
+      &mut out,
+      "netmon_poll_duration_seconds",
+      "gauge",
+      "Time the latest poll took.",
+    );
+    sample(
+      &mut out,
+      "netmon_poll_duration_seconds",
+      &[],
+      state.poll_duration.as_secs_f64(),
+    );
+    if let Some(last_poll) = state.last_poll {
+      header(
+        &mut out,
+        "netmon_last_poll_timestamp_seconds",
+        "gauge",
+        "Time of the latest poll.",
+      );
+      sample(
+        &mut out,
+        "netmon_last_poll_timestamp_seconds",
+        &[],
+        unix_secs(last_poll),
+      );
+    }
+    drop(state);
+
+    let counters = health.counters();
+    header(
+      &mut out,
+      "netmon_poll_failures_total",
+      "counter",
+      "Polls that failed to read the neighbor table.",
+    );
+    sample(
+      &mut out,
+      "netmon_poll_failures_total",
+      &[],
+      counters.failed_polls,
+    );
+    header(
+      &mut out,
+      "netmon_parse_errors_total",
+      "counter",
+      "Entries skipped because they couldn't be parsed.",
+    );
+    sample(
+      &mut out,
+      "netmon_parse_errors_total",
+      &[],
+      counters::parse_errors(),
+    );
+    header(
+      &mut out,
+      "netmon_collector_restarts_total",
+      "counter",
+      "Stuck collectors restarted by the watchdog.",
+    );
+    for (collector, restarts) in &counters.restarts {
+      sample(
+        &mut out,
+        "netmon_collector_restarts_total",
+        &[("collector", collector)],
+        restarts,
+      );
+    }
+    header(
+      &mut out,
+      "netmon_sink_up",
+      "gauge",
+      "Whether the sink accepted the latest poll.",
+    );
+    for (sink, (up, _)) in &counters.sinks {
+      sample(&mut out, "netmon_sink_up", &[("sink", sink)], *up as u8);
+    }
+    header(
+      &mut out,
+      "netmon_sink_dropped_total",
+      "counter",
+      "Polls dropped because the sink lagged behind.",
+    );
+    for (sink, (_, dropped)) in &counters.sinks {
+      sample(
+        &mut out,
+        "netmon_sink_dropped_total",
+        &[("sink", sink)],
+        dropped,
+      );
+    }
+    out
+  }
+}
+
+#[cfg(feature = "json-metrics")]
+impl Metrics {
+  ///
+  /// Formats the metrics as JSON, for scripts that don't parse the
+  /// Prometheus text format.
+  ///
+  /// Args:
+  ///  - health: Health of the daemon, for the failures it counts.
+  ///
+  /// Returns:
+  ///  The metrics, as a JSON object.
+  ///
+  pub fn render_json(&self, health: &Health) -> String {
+    use serde_json::{json, Map, Value};
+
+    let count = |devices: &[&DeviceSample]| {
+      json!({
+        "total": devices.len(),
+        "online": devices.iter().filter(|v| v.online).count(),
+        "wireless": devices.iter().filter(|v| v.has_traffic()).count(),
+      })
+    };
+    let state = self.inner.lock().unwrap();
+    let devices: Vec<&DeviceSample> = state.devices.iter().collect();
+    let mut interfaces: BTreeMap<&str, Vec<&DeviceSample>> = BTreeMap::new();
+    for device in &devices {
+      interfaces.entry(&device.iface).or_default().push(device);
+    }
+    let interfaces: Map<String, Value> = interfaces
+      .into_iter()
+      .map(|(iface, devices)| (iface.to_string(), count(&devices)))
+      .collect();
+
+    let counters = health.counters();
+    let sinks: Map<String, Value> = counters
+      .sinks
+      .iter()
+      .map(|(sink, (up, dropped))| (sink.clone(), json!({"up": up, "dropped": dropped})))
+      .collect();
+    json!({
+      "last_poll": state.last_poll.map(unix_secs),
+      "poll_duration_seconds": state.poll_duration.as_secs_f64(),
+      "devices": count(&devices),
+      "interfaces": interfaces,
+      "neighbors": state.neighbors,
+      "monitor": {
+        "uptime_seconds": health.uptime().as_secs(),
+        "live": health.is_live(),
+        "ready": health.is_ready(),
+        "failed_polls": counters.failed_polls,
+        "consecutive_failures": counters.consecutive_failures,
+        "parse_errors": counters::parse_errors(),
+        "collector_restarts": counters.restarts,
+        "sinks": sinks,
+      },
+    })
+    .to_string()
+  }
+}
+
+impl Sink for Metrics {
+  fn name(&self) -> &str {
+    "metrics"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let devices = device_samples(report, self.anonymizer.as_ref());
+    let neighbors = neighbor_counts(report);
+
+    let mut state = self.inner.lock().unwrap();
+    state.last_poll = Some(report.snapshot.taken_at);
+    state.poll_duration = report.duration;
+    state.devices = devices;
+    state.neighbors = neighbors;
+    Ok(())
+  }
+}
+
+/// Seconds since the Unix epoch.
+pub fn unix_secs(time: SystemTime) -> u64 {
+  time
+    .duration_since(UNIX_EPOCH)
+    .map(|v| v.as_secs())
+    .unwrap_or_default()
+}
+
+fn device_labels(device: &DeviceSample) -> [(&str, &str); 3] {
+  [
+    ("mac", &device.mac),
+    ("alias", &device.name),
+    ("iface", &device.iface),
+  ]
+}
+
+/// Appends the help and type of a metric.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+  let _ = writeln!(out, "# HELP {} {}", name, help);
+  let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Appends a sample, escaping its label values.
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+  out.push_str(name);
+  if !labels.is_empty() {
+    let labels: Vec<String> = labels
+      .iter()
+      .map(|(key, value)| {
+        let value = value
+          .replace('\\', "\\\\")
+          .replace('"', "\\\"")
+          .replace('\n', "\\n");
+        format!("{}=\"{}\"", key, value)
+      })
+      .collect();
+    let _ = write!(out, "{{{}}}", labels.join(","));
+  }
+  let _ = writeln!(out, " {}", value);
+}
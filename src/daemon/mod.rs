@@ -0,0 +1,1406 @@
+#[cfg(feature = "config")]
+use crate::alerts::{
+  AlertEngine, AlertEvent, AlertSink, Channel, Escalation, InterfacePolicy, LogChannel, Rule,
+  SeenDevices, TimeOfDay, TimeWindow, Weekday,
+};
+use crate::alerts::{AlertState, Escalations};
+#[cfg(all(feature = "config", feature = "notify"))]
+use crate::alerts::{
+  DiscordChannel, EmailChannel, GotifyChannel, MatrixChannel, NtfyChannel, PushoverChannel,
+  SignalChannel, SlackChannel, TelegramChannel, WebhookChannel,
+};
+#[cfg(feature = "config")]
+use crate::config::{AlertRule, ChannelConfig, Config, EscalationConfig, SinkConfig};
+use crate::device::Device;
+#[cfg(feature = "config")]
+use crate::dhcp::rogue::RogueDhcpWatch;
+use crate::dhcp::{self, Lease, LeaseFile};
+use crate::diff::{NeighborDiff, Snapshot};
+#[cfg(feature = "config")]
+use crate::neighbors::ra::RogueRaWatch;
+use crate::neighbors::{self, MacAddr};
+use crate::registry::Registry;
+use crate::tracker::NeighborTracker;
+use crate::wireless;
+use anyhow::{Error, Result};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Notify;
+
+pub mod collect;
+pub mod collectd;
+pub mod control;
+pub mod detach;
+pub mod eventlog;
+#[cfg(feature = "graphite")]
+pub mod graphite;
+pub mod health;
+pub mod http;
+#[cfg(feature = "influxdb")]
+pub mod influx;
+#[cfg(feature = "jsonl")]
+pub mod jsonl;
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod netdata;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod pidfile;
+pub mod profile;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+pub mod schedule;
+pub mod sink;
+pub mod snmp;
+pub mod socket;
+pub mod state;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+pub mod trace;
+pub mod watchdog;
+pub mod worker;
+#[cfg(feature = "zabbix")]
+pub mod zabbix;
+
+pub use collect::{Collected, Collection, CollectorKind, CollectorSet};
+pub use collectd::CollectdSink;
+pub use control::{handle_signals, Control};
+pub use detach::{detach, ReadyPipe};
+pub use eventlog::EventLogSink;
+#[cfg(feature = "graphite")]
+pub use graphite::{GraphiteProtocol, GraphiteSink};
+pub use health::{Health, HealthCounters, HealthServer};
+#[cfg(feature = "influxdb")]
+pub use influx::{InfluxLineSink, InfluxSink, InfluxTag, InfluxTarget};
+#[cfg(feature = "jsonl")]
+pub use jsonl::JsonlSink;
+pub use metrics::Metrics;
+#[cfg(feature = "mqtt")]
+pub use mqtt::{MqttClient, MqttOptions, MqttSink};
+pub use netdata::NetdataSink;
+#[cfg(feature = "otlp")]
+pub use otlp::OtlpSink;
+pub use pidfile::PidFile;
+pub use profile::ResourceProfile;
+#[cfg(feature = "remote-write")]
+pub use remote_write::RemoteWriteSink;
+pub use schedule::{AdaptivePolling, Schedule};
+pub use sink::{LogSink, Sink};
+pub use snmp::SnmpServer;
+pub use socket::ControlSocket;
+pub use state::{StateDir, StateSink};
+#[cfg(feature = "statsd")]
+pub use statsd::StatsdSink;
+#[cfg(feature = "stream")]
+pub use stream::{EventFormat, StreamSink, StreamTarget};
+pub use trace::Span;
+pub use watchdog::Watchdog;
+pub use worker::SinkWorker;
+#[cfg(feature = "zabbix")]
+pub use zabbix::ZabbixSink;
+
+/// Poll interval used when none is configured.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long collectors still running at shutdown are waited for.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Everything learned in a single poll, shared by every sink.
+#[derive(Debug)]
+pub struct PollReport {
+  pub snapshot: Snapshot,
+  /// Changes since the previous poll, empty on the first one.
+  pub diff: NeighborDiff,
+  pub devices: Vec<Device>,
+  /// Device state as of this poll.
+  pub tracker: NeighborTracker,
+  /// Known devices as of this poll.
+  pub registry: Arc<Registry>,
+  /// Time the poll started at.
+  pub started_at: SystemTime,
+  /// Time the poll took to collect and join everything.
+  pub duration: Duration,
+  /// Time each step of the poll took.
+  pub spans: Vec<Span>,
+}
+
+impl PollReport {
+  /// Best known name of a device, even one that left: its name as of this
+  /// poll, then its name in the registry.
+  pub fn name_of(&self, mac_addr: &MacAddr) -> Option<&str> {
+    self
+      .devices
+      .iter()
+      .find(|v| v.mac_addr == *mac_addr)
+      .and_then(|v| v.name())
+      .or_else(|| self.registry.name_of(mac_addr))
+  }
+}
+
+///
+/// Long-running monitor, polling the neighbor table on a fixed schedule and
+/// feeding every change to its sinks.
+///
+/// Each poll runs its collectors (neighbors, DHCP leases, and wireless
+/// stations) concurrently on a tokio runtime, each bounded by the command
+/// timeout, and queues the result to every sink's worker thread.
+///
+pub struct Daemon {
+  interval: Duration,
+  adaptive: Option<AdaptivePolling>,
+  jitter_percent: u32,
+  interfaces: Vec<String>,
+  aliases: HashMap<MacAddr, String>,
+  registry: Arc<Registry>,
+  lease_files: Vec<LeaseFile>,
+  command_timeout: Duration,
+  collector_timeouts: HashMap<CollectorKind, Duration>,
+  collector_concurrency: usize,
+  queue_capacity: usize,
+  profile: ResourceProfile,
+  /// Sinks waiting for their worker to be started.
+  sinks: Vec<Box<dyn Sink>>,
+  /// Sinks of the config waiting for their worker, replaced on every reload.
+  config_sinks: Vec<Box<dyn Sink>>,
+  workers: Vec<SinkWorker>,
+  config_workers: Vec<SinkWorker>,
+  ready_hooks: Vec<Box<dyn FnOnce()>>,
+  heartbeat: Option<(Duration, Box<dyn FnMut()>)>,
+  tracker: NeighborTracker,
+  previous: Option<Arc<PollReport>>,
+  control: Control,
+  health: Health,
+  watchdog: Watchdog,
+  /// Alerts escalating, kept across reloads.
+  escalations: Escalations,
+  /// What the alerts learned, kept across reloads.
+  alert_state: AlertState,
+  /// Wakes the loop to poll right away, e.g. once a rogue DHCP server is
+  /// seen.
+  poll_now: Arc<Notify>,
+  /// Config file to reload, discovered as at startup if None.
+  #[cfg(feature = "config")]
+  config_path: Option<PathBuf>,
+  #[cfg(feature = "config")]
+  alert_rules: Vec<AlertRule>,
+}
+
+impl Default for Daemon {
+  fn default() -> Self {
+    Daemon {
+      interval: DEFAULT_INTERVAL,
+      adaptive: None,
+      jitter_percent: 0,
+      interfaces: Vec::new(),
+      aliases: HashMap::new(),
+      registry: Arc::new(Registry::new()),
+      lease_files: vec![
+        LeaseFile::Dnsmasq(PathBuf::from(dhcp::DEFAULT_DNSMASQ_LEASE_PATH)),
+        LeaseFile::Odhcpd(PathBuf::from(dhcp::DEFAULT_ODHCPD_LEASE_PATH)),
+      ],
+      command_timeout: neighbors::DEFAULT_COMMAND_TIMEOUT,
+      collector_timeouts: HashMap::new(),
+      collector_concurrency: collect::DEFAULT_CONCURRENCY,
+      queue_capacity: worker::DEFAULT_QUEUE_CAPACITY,
+      profile: ResourceProfile::Standard,
+      sinks: Vec::new(),
+      config_sinks: Vec::new(),
+      workers: Vec::new(),
+      config_workers: Vec::new(),
+      ready_hooks: Vec::new(),
+      heartbeat: None,
+      tracker: NeighborTracker::new(),
+      previous: None,
+      control: Control::new(),
+      health: Health::default(),
+      watchdog: Watchdog::default(),
+      escalations: Escalations::new(),
+      alert_state: AlertState::new(),
+      poll_now: Arc::new(Notify::new()),
+      #[cfg(feature = "config")]
+      config_path: None,
+      #[cfg(feature = "config")]
+      alert_rules: Vec::new(),
+    }
+  }
+}
+
+impl Daemon {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Builds a daemon out of its config, including its sinks.
+  #[cfg(feature = "config")]
+  pub fn from_config(config: &Config) -> Self {
+    let mut daemon = Daemon::new();
+    daemon.apply_config(config);
+    daemon
+  }
+
+  ///
+  /// Applies a (re)loaded config, replacing the sinks it configured but
+  /// keeping the device state. Running sinks it replaces are stopped and
+  /// flushed first.
+  ///
+  /// Args:
+  ///  - config: Config to apply.
+  ///
+  #[cfg(feature = "config")]
+  pub fn apply_config(&mut self, config: &Config) {
+    self.interval = config.interval.max(Duration::from_secs(1));
+    self.adaptive = config.polling.adaptive.then_some(AdaptivePolling {
+      fast_interval: config.polling.fast_interval,
+      fast_window: config.polling.fast_window,
+      max_interval: config.polling.max_interval,
+    });
+    self.jitter_percent = config.polling.jitter_percent;
+    self.interfaces = config.interfaces.clone();
+    self.aliases = config.aliases.clone();
+    match Registry::load(&config.registry) {
+      Ok(registry) => self.registry = Arc::new(registry),
+      Err(err) => warn!("Keeping the known devices: {}", err),
+    }
+    self.lease_files = vec![
+      LeaseFile::Dnsmasq(config.leases.dnsmasq.clone()),
+      LeaseFile::Odhcpd(config.leases.odhcpd.clone()),
+    ];
+    self.alert_rules = config.alerts.clone();
+    self.watchdog.set_stuck_intervals(config.stuck_intervals);
+    self.command_timeout = config.collectors.timeout;
+    self.collector_timeouts = config.collectors.timeouts.clone();
+    self.collector_concurrency = config.collectors.concurrency;
+    self.set_resource_profile(config.resource_profile);
+    if let Err(err) = stop_workers(self.take_config_workers()) {
+      warn!("{}", err);
+    }
+    self.config_sinks = config
+      .sinks
+      .iter()
+      .filter_map(|sink| match build_sink(sink, config) {
+        Ok(sink) => Some(sink),
+        Err(err) => {
+          error!("Skipping a sink: {}", err);
+          None
+        }
+      })
+      .collect();
+    if !config.alerts.is_empty() || !config.interface_policies.is_empty() {
+      let sink = build_alerts(config, &self.escalations, &self.alert_state, &self.poll_now);
+      self.config_sinks.push(Box::new(sink));
+    }
+  }
+
+  /// Config file to re-read on reload, instead of discovering it again.
+  #[cfg(feature = "config")]
+  pub fn config_path(mut self, config_path: Option<PathBuf>) -> Self {
+    self.config_path = config_path;
+    self
+  }
+
+  /// Alert rules of the current config.
+  #[cfg(feature = "config")]
+  pub fn alert_rules(&self) -> &[AlertRule] {
+    &self.alert_rules
+  }
+
+  /// How often to poll the neighbor table, at most once a second.
+  pub fn interval(mut self, interval: Duration) -> Self {
+    self.interval = interval.max(Duration::from_secs(1));
+    self
+  }
+
+  /// How often the neighbor table is polled, when it's not polled adaptively.
+  pub fn poll_interval(&self) -> Duration {
+    self.interval
+  }
+
+  /// Adaptive polling settings, polling on the interval only if None.
+  pub fn adaptive(mut self, adaptive: Option<AdaptivePolling>) -> Self {
+    self.adaptive = adaptive;
+    self
+  }
+
+  /// Share of each delay polls are moved by at random, as a percentage up to 50.
+  pub fn jitter_percent(mut self, jitter_percent: u32) -> Self {
+    self.jitter_percent = jitter_percent;
+    self
+  }
+
+  /// Interfaces to watch, every interface if empty.
+  pub fn interfaces(mut self, interfaces: Vec<String>) -> Self {
+    self.interfaces = interfaces;
+    self
+  }
+
+  /// Names to give known devices, keyed by MAC address.
+  pub fn aliases(mut self, aliases: HashMap<MacAddr, String>) -> Self {
+    self.aliases = aliases;
+    self
+  }
+
+  /// Known devices, whose names take precedence over the aliases.
+  pub fn registry(mut self, registry: Registry) -> Self {
+    self.registry = Arc::new(registry);
+    self
+  }
+
+  /// Lease files to join with the devices on every poll.
+  pub fn lease_files(mut self, lease_files: Vec<LeaseFile>) -> Self {
+    self.lease_files = lease_files;
+    self
+  }
+
+  /// Longest time a collector, and each external command, may run for.
+  pub fn command_timeout(mut self, command_timeout: Duration) -> Self {
+    self.command_timeout = command_timeout;
+    self
+  }
+
+  /// Longest time the given collector may run for, instead of the command timeout.
+  pub fn collector_timeout(mut self, kind: CollectorKind, timeout: Duration) -> Self {
+    self.collector_timeouts.insert(kind, timeout);
+    self
+  }
+
+  /// Collectors run at once during a poll, at least one.
+  pub fn collector_concurrency(mut self, collector_concurrency: usize) -> Self {
+    self.collector_concurrency = collector_concurrency;
+    self
+  }
+
+  /// Poll intervals a collector may run for before the watchdog restarts it.
+  pub fn stuck_intervals(self, stuck_intervals: u32) -> Self {
+    self.watchdog.set_stuck_intervals(stuck_intervals);
+    self
+  }
+
+  /// Resource profile to run with, see ResourceProfile.
+  pub fn resource_profile(mut self, profile: ResourceProfile) -> Self {
+    self.set_resource_profile(profile);
+    self
+  }
+
+  fn set_resource_profile(&mut self, profile: ResourceProfile) {
+    self.profile = profile;
+    self.tracker.set_max_neighbors(profile.max_neighbors());
+    crate::vendor::set_embedded_only(profile.is_tiny());
+  }
+
+  /// Polls queued for each sink before newer ones are dropped.
+  pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+    self.queue_capacity = queue_capacity;
+    self
+  }
+
+  /// Adds a sink to publish every poll to, kept across reloads.
+  pub fn sink(mut self, sink: Box<dyn Sink>) -> Self {
+    self.sinks.push(sink);
+    self
+  }
+
+  /// Adds a hook called once the first poll succeeded, e.g. to notify init.
+  pub fn on_ready(mut self, hook: Box<dyn FnOnce()>) -> Self {
+    self.ready_hooks.push(hook);
+    self
+  }
+
+  ///
+  /// Sets a hook called at least once per period for as long as the polling
+  /// loop runs, e.g. to ping a watchdog. A hung poll stops the heartbeat.
+  ///
+  /// Args:
+  ///  - period: Longest time between two beats.
+  ///  - beat: Hook to call.
+  ///
+  pub fn heartbeat(mut self, period: Duration, beat: Box<dyn FnMut()>) -> Self {
+    self.heartbeat = Some((period, beat));
+    self
+  }
+
+  /// Control stopping or reloading the daemon, e.g. from handle_signals.
+  pub fn control(mut self, control: Control) -> Self {
+    self.control = control;
+    self
+  }
+
+  /// Health to record the outcome of polls and sinks in, e.g. for a
+  /// HealthServer.
+  pub fn health(mut self, health: Health) -> Self {
+    self.health = health;
+    self
+  }
+
+  /// Alerts the config's rules escalate, e.g. for a HealthServer to
+  /// acknowledge.
+  pub fn escalations(&self) -> &Escalations {
+    &self.escalations
+  }
+
+  /// The device state maintained across polls.
+  pub fn tracker(&self) -> &NeighborTracker {
+    &self.tracker
+  }
+
+  /// Starts a worker for every sink that doesn't have one yet.
+  fn start_sinks(&mut self) {
+    for (sinks, workers) in [
+      (&mut self.sinks, &mut self.workers),
+      (&mut self.config_sinks, &mut self.config_workers),
+    ] {
+      for sink in std::mem::take(sinks) {
+        if self.profile.is_tiny() && !sink.needs_thread() {
+          workers.push(SinkWorker::inline(sink, self.health.clone()));
+          continue;
+        }
+        match SinkWorker::spawn(sink, self.queue_capacity, self.health.clone()) {
+          Ok(worker) => workers.push(worker),
+          Err(err) => error!("{}", err),
+        }
+      }
+    }
+  }
+
+  /// Takes the workers of the config's sinks, forgetting their health.
+  #[cfg(feature = "config")]
+  fn take_config_workers(&mut self) -> Vec<SinkWorker> {
+    let workers = std::mem::take(&mut self.config_workers);
+    for worker in &workers {
+      self.health.remove_sink(worker.name());
+    }
+    workers
+  }
+
+  ///
+  /// Polls the neighbor table once, updating the device state and queuing
+  /// the result to every sink. The leases and wireless stations are collected
+  /// alongside the neighbors, and only the neighbors are required: the others
+  /// are skipped when they fail. Sink failures are logged by their worker,
+  /// so one broken sink doesn't starve the others.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the neighbor table could be read.
+  ///
+  pub async fn poll_once(&mut self) -> Result<()> {
+    self.start_sinks();
+    let start = SystemTime::now();
+    let started_at = Instant::now();
+
+    let watchdog = self.watchdog.clone();
+    let lease_watchdog = self.watchdog.clone();
+    let lease_files = self.lease_files.clone();
+    let stations_timeout = self.timeout_of(CollectorKind::Wireless);
+    let Collection {
+      neighbors: entries,
+      leases,
+      stations,
+      mut spans,
+    } = CollectorSet::new(
+      self
+        .profile
+        .collector_concurrency(self.collector_concurrency),
+    )
+    .add(
+      CollectorKind::Neighbor,
+      self.timeout_of(CollectorKind::Neighbor),
+      async move {
+        let entries = watchdog
+          .collect("neighbor", neighbors::collect_neighbors)
+          .await?;
+        Ok(Collected::Neighbors(entries))
+      },
+    )
+    .add(
+      CollectorKind::Lease,
+      self.timeout_of(CollectorKind::Lease),
+      async move {
+        let leases = lease_watchdog
+          .collect("lease", move || Ok(read_leases(&lease_files)))
+          .await?;
+        Ok(Collected::Leases(leases))
+      },
+    )
+    .add(CollectorKind::Wireless, stations_timeout, async move {
+      let stations = wireless::get_stations_async(stations_timeout).await?;
+      Ok(Collected::Stations(stations))
+    })
+    .run()
+    .await;
+
+    let joined_at = (SystemTime::now(), Instant::now());
+    let mut entries = entries?;
+    if !self.interfaces.is_empty() {
+      entries.retain(|v| self.interfaces.contains(&v.iface));
+    }
+    let snapshot = Snapshot::new(entries);
+    let diff = match &self.previous {
+      Some(previous) => Snapshot::diff(&previous.snapshot, &snapshot),
+      None => NeighborDiff::default(),
+    };
+
+    let mut devices = Device::group(&snapshot.entries);
+    match leases {
+      Ok(leases) => dhcp::join_leases(&mut devices, &leases),
+      Err(err) => warn!("Skipping the leases: {}", err),
+    }
+    match stations {
+      Ok(stations) => wireless::join_stations(&mut devices, &stations),
+      Err(err) => debug!("Skipping the wireless stations: {}", err),
+    }
+    for device in devices.iter_mut() {
+      device.alias = self.aliases.get(&device.mac_addr).cloned();
+    }
+    self.registry.annotate(&mut devices);
+    self.tracker.update_at(&snapshot.entries, snapshot.taken_at);
+    spans.push(Span::new("join", joined_at.0, joined_at.1.elapsed()));
+
+    let report = Arc::new(PollReport {
+      snapshot,
+      diff,
+      devices,
+      tracker: self.tracker.clone(),
+      registry: self.registry.clone(),
+      started_at: start,
+      duration: started_at.elapsed(),
+      spans,
+    });
+    for worker in self
+      .workers
+      .iter_mut()
+      .chain(self.config_workers.iter_mut())
+    {
+      worker.send(report.clone());
+    }
+
+    self.previous = Some(report);
+    Ok(())
+  }
+
+  ///
+  /// Re-reads the config, keeping the current one if it's invalid. The sinks
+  /// of the config are flushed before being replaced.
+  ///
+  #[cfg(feature = "config")]
+  pub async fn reload(&mut self) {
+    match Config::discover(self.config_path.as_deref()) {
+      Ok(config) => {
+        let workers = self.take_config_workers();
+        if let Err(err) = stop_workers_async(workers).await {
+          warn!("{}", err);
+        }
+        self.apply_config(&config);
+        info!(
+          "Reloaded the config, polling every {}",
+          humantime::format_duration(self.interval)
+        );
+      }
+      Err(err) => error!("Keeping the current config: {}", err),
+    }
+  }
+
+  #[cfg(not(feature = "config"))]
+  pub async fn reload(&mut self) {
+    warn!("Ignoring the reload, config files require the 'config' feature");
+  }
+
+  ///
+  /// Runs the polling loop (see run_async) on a new single-threaded tokio
+  /// runtime, built for the resource profile, until a shutdown is requested.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the runtime started and every sink was
+  ///  flushed.
+  ///
+  pub fn run(&mut self) -> Result<()> {
+    let runtime = self.profile.runtime()?;
+
+    let result = runtime.block_on(self.run_async());
+    // Collectors stuck past their timeout mustn't hold up the exit.
+    runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+    result
+  }
+
+  ///
+  /// Polls the neighbor table once, on a new runtime as run does, then stops
+  /// and flushes every sink, for commands printing a single poll.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the neighbor table could be read, and every
+  ///  sink was flushed.
+  ///
+  pub fn run_once(&mut self) -> Result<()> {
+    let runtime = self.profile.runtime()?;
+
+    let result = runtime.block_on(async {
+      let result = self.poll_once().await;
+      self.alert_state.stop();
+      let mut workers = std::mem::take(&mut self.workers);
+      workers.append(&mut self.config_workers);
+      result.and(stop_workers_async(workers).await)
+    });
+    runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+    result
+  }
+
+  /// Longest time a collector may run for.
+  fn timeout_of(&self, kind: CollectorKind) -> Duration {
+    self
+      .collector_timeouts
+      .get(&kind)
+      .copied()
+      .unwrap_or(self.command_timeout)
+  }
+
+  /// Schedule of the current settings, updating the intervals the health
+  /// and the watchdog go by.
+  fn schedule(&self) -> Schedule {
+    let schedule = Schedule::new(self.interval)
+      .adaptive(self.adaptive)
+      .jitter_percent(self.jitter_percent);
+    self.health.set_interval(schedule.max_delay());
+    self.watchdog.set_interval(self.interval);
+    if let Some(adaptive) = &self.adaptive {
+      info!(
+        "Polling every {} after changes, backing off up to {} while stable",
+        humantime::format_duration(adaptive.fast_interval),
+        humantime::format_duration(adaptive.max_interval)
+      );
+    }
+    schedule
+  }
+
+  ///
+  /// Polls on the schedule until a shutdown is requested, aborting the
+  /// poll in flight, then stops and flushes every sink. The ready hooks are
+  /// called after the first successful poll. Polls that overrun their delay
+  /// skip the ticks they missed rather than bunching up, and reloads poll
+  /// right away.
+  ///
+  /// Returns:
+  ///  Result reflecting whether every sink was flushed.
+  ///
+  pub async fn run_async(&mut self) -> Result<()> {
+    self.start_sinks();
+    info!(
+      "Polling every {}",
+      humantime::format_duration(self.interval)
+    );
+    if self.profile.is_tiny() {
+      info!("Running with the tiny resource profile");
+    }
+    let mut schedule = self.schedule();
+    let supervisor = tokio::spawn(self.watchdog.clone().supervise(self.health.clone()));
+
+    let mut next_tick = Instant::now();
+    let mut next_beat = Instant::now();
+    while !self.control.is_cancelled() {
+      if self.control.take_reload() {
+        self.reload().await;
+        schedule = self.schedule();
+        next_tick = Instant::now();
+      }
+
+      if Instant::now() >= next_tick {
+        let control = self.control.clone();
+        let started_at = Instant::now();
+        let result = tokio::select! {
+          result = self.poll_once() => result,
+          // Polls only update the state once done, so they can be dropped.
+          _ = control.cancelled() => break,
+        };
+        let duration_ms = started_at.elapsed().as_millis() as u64;
+        self.health.record_poll(&result);
+        let changed = result.is_ok() && self.previous.as_ref().is_some_and(|v| !v.diff.is_empty());
+        match result {
+          Ok(()) => {
+            debug!("poll.duration_ms" = duration_ms; "Polled in {}ms", duration_ms);
+            for hook in std::mem::take(&mut self.ready_hooks) {
+              hook();
+            }
+          }
+          Err(err) => error!(
+            "poll.duration_ms" = duration_ms;
+            "Poll failed: {}", err
+          ),
+        }
+
+        let delay = schedule.next_delay(changed, started_at);
+        debug!("Polling again in {:?}", delay);
+        next_tick += delay;
+        let now = Instant::now();
+        let mut skipped = 0;
+        while next_tick <= now {
+          next_tick += delay;
+          skipped += 1;
+        }
+        if skipped > 0 {
+          warn!("Poll overran the interval, skipping {} tick(s)", skipped);
+        }
+      }
+      let mut wake_at = next_tick;
+      if let Some((period, beat)) = &mut self.heartbeat {
+        if Instant::now() >= next_beat {
+          beat();
+          next_beat = Instant::now() + *period;
+        }
+        wake_at = wake_at.min(next_beat);
+      }
+      tokio::select! {
+        _ = self.control.wait(wake_at.saturating_duration_since(Instant::now())) => {}
+        _ = self.poll_now.notified() => next_tick = Instant::now(),
+      }
+    }
+
+    supervisor.abort();
+    self.alert_state.stop();
+    let mut workers = std::mem::take(&mut self.workers);
+    workers.append(&mut self.config_workers);
+    stop_workers_async(workers).await
+  }
+}
+
+/// Builds the sink raising the config's alerts, delivering them to its
+/// channels, or else to the log.
+#[cfg(feature = "config")]
+fn build_alerts(
+  config: &Config,
+  escalations: &Escalations,
+  state: &AlertState,
+  poll_now: &Arc<Notify>,
+) -> AlertSink {
+  let rules = config
+    .alerts
+    .iter()
+    .map(|v| build_rule(v, config))
+    .collect();
+  let mut engine = AlertEngine::new(rules).gateways(config.gateways.clone());
+  if config
+    .alerts
+    .iter()
+    .any(|v| matches!(v.event, AlertEvent::NewDevice | AlertEvent::WrongInterface))
+  {
+    let path = &config.new_devices.seen_file;
+    match SeenDevices::load(path) {
+      Ok(seen) => {
+        engine = engine
+          .seen(seen, path)
+          .learning_period(config.new_devices.learning_period)
+      }
+      Err(err) => error!("Not raising new device or wrong interface alerts: {}", err),
+    }
+  }
+  if config
+    .alerts
+    .iter()
+    .any(|v| v.event == AlertEvent::RogueDhcp)
+  {
+    let rogue_dhcp = &config.rogue_dhcp;
+    let poll_now = poll_now.clone();
+    let mut watch = RogueDhcpWatch::new(rogue_dhcp.interfaces.clone())
+      .servers(rogue_dhcp.servers.clone())
+      .on_offer(Box::new(move || poll_now.notify_one()));
+    if let Some(probe_interval) = rogue_dhcp.probe_interval {
+      watch = watch.probe_interval(probe_interval);
+    }
+    engine = engine.rogue_dhcp(watch);
+  }
+  if config.alerts.iter().any(|v| v.event == AlertEvent::RogueRa) {
+    let rogue_ra = &config.rogue_ra;
+    let poll_now = poll_now.clone();
+    let watch = RogueRaWatch::new(rogue_ra.interfaces.clone())
+      .routers(rogue_ra.routers.clone())
+      .prefixes(rogue_ra.prefixes.clone())
+      .dns(rogue_ra.dns.clone())
+      .on_advert(Box::new(move || poll_now.notify_one()));
+    engine = engine.rogue_ra(watch);
+  }
+  let policies = config.interface_policies.iter().map(|v| {
+    let mut policy = InterfacePolicy::new(&v.interface)
+      .trust(v.trust.clone())
+      .categories(v.categories.clone())
+      .devices(v.devices.clone());
+    if let Some(enforce) = &v.enforce {
+      policy = policy.enforce(enforce);
+    }
+    policy
+  });
+  engine = engine.interface_policies(policies.collect());
+  let mut sink = AlertSink::new(engine)
+    .escalations(escalations.clone())
+    .state(state.clone());
+  if config.channels.is_empty() {
+    sink = sink.channel(Box::new(LogChannel::new("log")));
+  }
+  for (name, channel) in &config.channels {
+    match build_channel(name, channel, config, escalations) {
+      Ok(channel) => sink = sink.channel(channel),
+      Err(err) => error!("Skipping the channel '{}': {}", name, err),
+    }
+  }
+  for (name, quiet) in &config.quiet_hours {
+    sink = sink.quiet(name, build_window(quiet.after, quiet.before, &quiet.days));
+  }
+  for (name, limit) in &config.rate_limits {
+    sink = sink.rate_limit(name, limit.max, limit.per);
+  }
+  sink
+}
+
+/// Builds a window of local time of the config.
+#[cfg(feature = "config")]
+fn build_window(
+  after: Option<TimeOfDay>,
+  before: Option<TimeOfDay>,
+  days: &[Weekday],
+) -> TimeWindow {
+  let mut window = TimeWindow::new().days(days.to_vec());
+  if let Some(after) = after {
+    window = window.after(after);
+  }
+  if let Some(before) = before {
+    window = window.before(before);
+  }
+  window
+}
+
+/// Builds a rule of the config.
+#[cfg(feature = "config")]
+fn build_rule(rule: &AlertRule, config: &Config) -> Rule {
+  let mut built = Rule::new(&rule.name, rule.event);
+  let quiet = rule.quiet_after.is_some() || rule.quiet_before.is_some();
+  if quiet || !rule.quiet_days.is_empty() {
+    built = built.quiet(build_window(
+      rule.quiet_after,
+      rule.quiet_before,
+      &rule.quiet_days,
+    ));
+  }
+  if let Some(offline_after) = rule.offline_after {
+    built = built.offline_after(offline_after);
+  }
+  if let Some(stale_timeout) = rule.stale_timeout {
+    built = built.stale_timeout(stale_timeout);
+  }
+  if let Some(dedup) = rule.dedup {
+    built = built.dedup(dedup);
+  }
+  if let Some(on_event) = &rule.on_event {
+    built = built.on_event(on_event);
+  }
+  built = built
+    .severity(rule.severity)
+    .devices(rule.devices.clone())
+    .interfaces(rule.interfaces.clone())
+    .vendors(rule.vendors.clone())
+    .trust(rule.trust.clone())
+    .categories(rule.categories.clone())
+    .from_states(rule.from_states)
+    .to_states(rule.to_states)
+    .window(build_window(rule.after, rule.before, &rule.days))
+    .channels(rule.channels.clone());
+  let escalation = rule
+    .escalation
+    .as_ref()
+    .and_then(|v| config.escalations.get(v));
+  if let Some(escalation) = escalation.and_then(build_escalation) {
+    built = built.escalation(escalation);
+  }
+  built
+}
+
+/// Builds an escalation of the config, unless it has no step.
+#[cfg(feature = "config")]
+fn build_escalation(escalation: &EscalationConfig) -> Option<Escalation> {
+  let (first, later) = escalation.steps.split_first()?;
+  Some(
+    later
+      .iter()
+      .fold(Escalation::new(first.channels.clone()), |built, step| {
+        built.then(step.after, step.channels.clone())
+      }),
+  )
+}
+
+/// Builds a channel of the config.
+#[cfg(feature = "config")]
+#[allow(unused_variables)]
+fn build_channel(
+  name: &str,
+  channel: &ChannelConfig,
+  config: &Config,
+  escalations: &Escalations,
+) -> Result<Box<dyn Channel>> {
+  Ok(match channel {
+    ChannelConfig::Log => Box::new(LogChannel::new(name)),
+    #[cfg(feature = "notify")]
+    ChannelConfig::Webhook(webhook) => {
+      let mut channel = WebhookChannel::new(name, &webhook.url)
+        .retries(webhook.retries)
+        .timeout(webhook.timeout);
+      for (header, value) in &webhook.headers {
+        channel = channel.header(header, value);
+      }
+      if let Some(body) = &webhook.body {
+        channel = channel.body(body.clone());
+      }
+      if let Some(secret) = &webhook.secret {
+        channel = channel.secret(secret);
+      }
+      if let Some(ca_file) = &webhook.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Telegram(telegram) => {
+      let mut channel = TelegramChannel::new(name, &telegram.bot_token, &telegram.chat_id)
+        .api_url(&telegram.api_url)
+        .commands(telegram.commands)
+        .escalations(escalations.clone())
+        .timeout(telegram.timeout);
+      if let Some(template) = &telegram.template {
+        channel = channel.template(template.clone());
+      }
+      if let Some(ca_file) = &telegram.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Discord(discord) => {
+      let mut channel = DiscordChannel::new(name, &discord.url)
+        .retries(discord.retries)
+        .timeout(discord.timeout);
+      if let Some(username) = &discord.username {
+        channel = channel.username(username);
+      }
+      if let Some(avatar_url) = &discord.avatar_url {
+        channel = channel.avatar_url(avatar_url);
+      }
+      if let Some(content) = &discord.content {
+        channel = channel.content(content.clone());
+      }
+      if let Some(ca_file) = &discord.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Slack(slack) => {
+      let mut channel = match (&slack.url, &slack.token, &slack.channel) {
+        (Some(url), _, _) => SlackChannel::webhook(name, url),
+        (None, Some(token), Some(channel)) => {
+          SlackChannel::bot(name, token, channel).api_url(&slack.api_url)
+        }
+        _ => return Err(Error::msg(format!("'{}' needs a url or a token", name))),
+      };
+      for (severity, slack_channel) in &slack.severity_channels {
+        channel = channel.severity_channel(*severity, slack_channel);
+      }
+      channel = channel
+        .threads(slack.threads)
+        .thread_window(slack.thread_window)
+        .retries(slack.retries)
+        .timeout(slack.timeout);
+      if let Some(template) = &slack.template {
+        channel = channel.template(template.clone());
+      }
+      if let Some(ca_file) = &slack.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Email(email) => {
+      let mut channel = EmailChannel::new(name, &email.server, &email.from, &email.to)
+        .tls(email.tls)
+        .timeout(email.timeout);
+      if let (Some(username), Some(password)) = (&email.username, &email.password) {
+        channel = channel.credentials(username, password);
+      }
+      if let Some(subject) = &email.subject {
+        channel = channel.subject(subject.clone());
+      }
+      if let Some(body) = &email.body {
+        channel = channel.body(body.clone());
+      }
+      if let Some(html) = &email.html {
+        channel = channel.html(html.clone());
+      }
+      if let Some(digest) = email.digest {
+        channel = channel.digest(digest, email.digest_severity);
+      }
+      if let Some(ca_file) = &email.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Ntfy(ntfy) => {
+      let mut channel = NtfyChannel::new(name, &ntfy.topic)
+        .server(&ntfy.server)
+        .retries(ntfy.retries)
+        .timeout(ntfy.timeout);
+      if let Some(token) = &ntfy.token {
+        channel = channel.token(token);
+      }
+      if let (Some(username), Some(password)) = (&ntfy.username, &ntfy.password) {
+        channel = channel.basic_auth(username, password);
+      }
+      for (severity, priority) in &ntfy.priorities {
+        channel = channel.priority(*severity, *priority);
+      }
+      for tag in &ntfy.tags {
+        channel = channel.tag(tag);
+      }
+      if let Some(click) = &ntfy.click {
+        channel = channel.click(click.clone());
+      }
+      if let Some(ack_url) = &ntfy.ack_url {
+        channel = channel.ack_url(ack_url);
+      }
+      if let Some(template) = &ntfy.template {
+        channel = channel.template(template.clone());
+      }
+      if let Some(ca_file) = &ntfy.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Pushover(pushover) => {
+      let mut channel = PushoverChannel::new(name, &pushover.token, &pushover.user)
+        .api_url(&pushover.api_url)
+        .emergency(pushover.retry, pushover.expire)
+        .retries(pushover.retries)
+        .timeout(pushover.timeout);
+      for device in &pushover.devices {
+        channel = channel.device(device);
+      }
+      if let Some(sound) = &pushover.sound {
+        channel = channel.sound(sound);
+      }
+      for (severity, priority) in &pushover.priorities {
+        channel = channel.priority(*severity, *priority);
+      }
+      if let Some(url) = &pushover.url {
+        channel = channel.url(url.clone());
+      }
+      if let Some(template) = &pushover.template {
+        channel = channel.template(template.clone());
+      }
+      if let Some(ca_file) = &pushover.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Gotify(gotify) => {
+      let mut channel = GotifyChannel::new(name, &gotify.server, &gotify.token)
+        .retries(gotify.retries)
+        .timeout(gotify.timeout);
+      for (severity, priority) in &gotify.priorities {
+        channel = channel.priority(*severity, *priority);
+      }
+      if let Some(click) = &gotify.click {
+        channel = channel.click(click.clone());
+      }
+      if let Some(template) = &gotify.template {
+        channel = channel.template(template.clone());
+      }
+      if let Some(ca_file) = &gotify.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Matrix(matrix) => {
+      let mut channel = MatrixChannel::new(
+        name,
+        &matrix.homeserver,
+        &matrix.access_token,
+        &matrix.room_id,
+      )
+      .retries(matrix.retries)
+      .timeout(matrix.timeout);
+      if let Some(template) = &matrix.template {
+        channel = channel.template(template.clone());
+      }
+      #[cfg(feature = "matrix-e2ee")]
+      if matrix.encryption {
+        channel = channel.encryption(&matrix.store);
+      }
+      if let Some(ca_file) = &matrix.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+    #[cfg(feature = "notify")]
+    ChannelConfig::Signal(signal) => {
+      let mut channel = SignalChannel::new(name, &signal.account)
+        .command(&signal.command)
+        .retries(signal.retries)
+        .timeout(signal.timeout);
+      for recipient in &signal.recipients {
+        channel = channel.recipient(recipient);
+      }
+      for group in &signal.groups {
+        channel = channel.group(group);
+      }
+      if let Some(url) = &signal.url {
+        channel = channel.url(url);
+      }
+      if let Some(config_dir) = &signal.config_dir {
+        channel = channel.config_dir(config_dir);
+      }
+      if let Some(template) = &signal.template {
+        channel = channel.template(template.clone());
+      }
+      if let Some(ca_file) = &signal.ca_file {
+        channel = channel.ca_file(ca_file);
+      }
+      Box::new(channel)
+    }
+  })
+}
+
+/// Builds a sink of the config, pruning the storage sinks' history.
+#[cfg(feature = "config")]
+#[cfg_attr(
+  not(any(feature = "sqlite", feature = "redb")),
+  allow(unused_variables)
+)]
+fn build_sink(sink: &SinkConfig, config: &Config) -> Result<Box<dyn Sink>> {
+  Ok(match sink {
+    SinkConfig::Log => Box::new(LogSink),
+    SinkConfig::EventLog(event_log) => Box::new(
+      EventLogSink::new(&event_log.path)
+        .max_size(event_log.max_size)
+        .keep(event_log.keep)
+        .compress(event_log.compress),
+    ),
+    #[cfg(feature = "jsonl")]
+    SinkConfig::Jsonl(jsonl) => {
+      let mut sink = JsonlSink::new(&jsonl.path);
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "sqlite")]
+    SinkConfig::Sqlite(sqlite) => Box::new(
+      crate::storage::Pruner::new(
+        crate::storage::WriteBehind::new(crate::storage::SqliteStorage::open(&sqlite.path)?)
+          .flush_interval(sqlite.flush_interval)
+          .flush_on_change(sqlite.flush_on_change),
+        config.retention.retention(),
+      )
+      .interval(config.retention.prune_interval),
+    ),
+    #[cfg(feature = "redb")]
+    SinkConfig::Redb(redb) => Box::new(
+      crate::storage::Pruner::new(
+        crate::storage::WriteBehind::new(
+          crate::storage::RedbStorage::open(&redb.path)?.sync_interval(redb.sync_interval),
+        )
+        .flush_interval(redb.flush_interval)
+        .flush_on_change(redb.flush_on_change),
+        config.retention.retention(),
+      )
+      .interval(config.retention.prune_interval),
+    ),
+    #[cfg(feature = "postgres")]
+    SinkConfig::Postgres(postgres) => {
+      let mut sink =
+        crate::storage::PostgresSink::new(&postgres.url, &postgres.spill_path, postgres.max_spill)?;
+      if let Some(router) = &postgres.router {
+        sink = sink.router(router);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "graphite")]
+    SinkConfig::Graphite(graphite) => {
+      let mut sink =
+        GraphiteSink::new(&graphite.address, graphite.protocol).prefix(&graphite.prefix);
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "statsd")]
+    SinkConfig::Statsd(statsd) => Box::new(
+      StatsdSink::new(&statsd.address)
+        .prefix(&statsd.prefix)
+        .dogstatsd(statsd.dogstatsd)
+        .tags(statsd.tags.clone()),
+    ),
+    #[cfg(feature = "mqtt")]
+    SinkConfig::Mqtt(mqtt) => {
+      let mut sink =
+        MqttSink::new(mqtt.options(), &mqtt.prefix).attributes_interval(mqtt.attributes_interval);
+      if mqtt.home_assistant {
+        sink = sink.discovery(&mqtt.discovery_prefix);
+      }
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "stream")]
+    SinkConfig::Nats(nats) => {
+      let target = StreamTarget::Nats {
+        address: nats.address.clone(),
+        subject: nats.subject.clone(),
+        username: nats.username.clone(),
+        password: nats.password.clone(),
+        token: nats.token.clone(),
+      };
+      let mut sink = StreamSink::new(target).format(nats.format);
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "stream")]
+    SinkConfig::Kafka(kafka) => {
+      let target = StreamTarget::Kafka {
+        url: kafka.url.clone(),
+        topic: kafka.topic.clone(),
+        headers: kafka.headers.clone(),
+        ca_file: kafka.ca_file.clone(),
+      };
+      let mut sink = StreamSink::new(target).format(kafka.format);
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "remote-write")]
+    SinkConfig::RemoteWrite(remote_write) => {
+      let mut sink = RemoteWriteSink::new(&remote_write.url)
+        .headers(remote_write.headers.clone())
+        .labels(remote_write.labels.clone())
+        .batch_size(remote_write.batch_size)
+        .max_pending(remote_write.max_pending);
+      if let Some(token) = &remote_write.bearer_token {
+        sink = sink.bearer_token(token);
+      }
+      if let Some(username) = &remote_write.username {
+        sink = sink.basic_auth(
+          username,
+          remote_write.password.as_deref().unwrap_or_default(),
+        );
+      }
+      if let Some(ca_file) = &remote_write.ca_file {
+        sink = sink.ca_file(ca_file);
+      }
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "zabbix")]
+    SinkConfig::Zabbix(zabbix) => {
+      let mut sink = ZabbixSink::new(&zabbix.server).discovery_interval(zabbix.discovery_interval);
+      if let Some(host) = &zabbix.host {
+        sink = sink.host(host);
+      }
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "otlp")]
+    SinkConfig::Otlp(otlp) => {
+      let mut sink = OtlpSink::new(&otlp.endpoint)
+        .headers(otlp.headers.clone())
+        .service_name(&otlp.service_name)
+        .traces(otlp.traces)
+        .metrics(otlp.metrics);
+      if let Some(ca_file) = &otlp.ca_file {
+        sink = sink.ca_file(ca_file);
+      }
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+    #[cfg(feature = "influxdb")]
+    SinkConfig::Influxdb(influx) => {
+      let target = influx
+        .target()
+        .ok_or_else(|| Error::msg("sinks: influxdb needs a bucket or a database"))?;
+      let mut sink = InfluxSink::new(&influx.url, target)
+        .presence_measurement(&influx.presence_measurement)
+        .traffic_measurement(&influx.traffic_measurement)
+        .tags(influx.tags.clone())
+        .static_tags(influx.static_tags.clone())
+        .flush_interval(influx.flush_interval)
+        .batch_size(influx.batch_size)
+        .max_pending(influx.max_pending);
+      if let Some(ca_file) = &influx.ca_file {
+        sink = sink.ca_file(ca_file);
+      }
+      if let Some(anonymizer) = config.privacy.anonymizer()? {
+        sink = sink.anonymize(anonymizer);
+      }
+      Box::new(sink)
+    }
+  })
+}
+
+/// Hostname of the router, "OpenWrt" if unknown.
+pub fn hostname() -> String {
+  std::fs::read_to_string("/proc/sys/kernel/hostname")
+    .map(|v| v.trim().to_string())
+    .ok()
+    .filter(|v| !v.is_empty())
+    .unwrap_or_else(|| "OpenWrt".into())
+}
+
+/// Reads every lease file, skipping the ones that don't exist.
+fn read_leases(lease_files: &[LeaseFile]) -> Vec<Lease> {
+  let mut leases = Vec::new();
+  for lease_file in lease_files {
+    match lease_file.read() {
+      Ok(v) => leases.extend(v),
+      Err(err) => debug!("{}", err),
+    }
+  }
+  leases
+}
+
+/// Stops every worker, flushing its sink, trying each even if one fails.
+fn stop_workers(workers: Vec<SinkWorker>) -> Result<()> {
+  let mut failed = Vec::new();
+  for worker in workers {
+    let name = worker.name().to_string();
+    if let Err(err) = worker.stop() {
+      warn!("{}", err);
+      failed.push(name);
+    }
+  }
+
+  match failed.is_empty() {
+    true => Ok(()),
+    false => Err(Error::msg(format!(
+      "Failed to flush sink(s): {}",
+      failed.join(", ")
+    ))),
+  }
+}
+
+/// Like stop_workers, without blocking the runtime while the sinks flush.
+async fn stop_workers_async(workers: Vec<SinkWorker>) -> Result<()> {
+  tokio::task::spawn_blocking(move || stop_workers(workers))
+    .await
+    .map_err(|e| Error::msg(format!("Failed to stop the sinks: {}", e)))?
+}
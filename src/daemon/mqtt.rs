@@ -0,0 +1,796 @@
+use super::{PollReport, Sink};
+use crate::privacy::Anonymizer;
+use crate::MacAddr;
+use anyhow::{Error, Result};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Port of the broker when none is configured, without TLS.
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// Port of the broker when none is configured, with TLS.
+pub const DEFAULT_MQTTS_PORT: u16 = 8883;
+
+/// Prefix of the topics when none is configured.
+pub const DEFAULT_PREFIX: &str = "netmon";
+
+/// Keep alive negotiated with the broker when none is configured.
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(300);
+
+/// Longest time between two publications of a device's attributes, keeping
+/// its last_seen current, when none is configured.
+pub const DEFAULT_ATTRIBUTES_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Prefix Home Assistant subscribes to for discovery by default.
+pub const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Longest time connecting to, or writing to, the broker may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest time the TLS tunnel may take to close once its input is.
+const TUNNEL_CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Wait before reconnecting after the first failure, doubled after each.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between reconnections.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/*
+  Every device the daemon tracks gets retained topics under the prefix,
+  republished when they change:
+
+    netmon/status                             online
+    netmon/devices/dc:a6:32:a3:48:b1/state    online
+    netmon/devices/dc:a6:32:a3:48:b1/attributes
+      {"mac":"dc:a6:32:a3:48:b1","name":"Living room TV","ip":"192.168.1.20",
+       "ips":["192.168.1.20","fe80::dea6:32ff:fea3:48b1%br-lan"],
+       "hostname":"tv","iface":"br-lan","vendor":"Raspberry Pi",
+       "signal_dbm":-52,"online":true,"last_seen":"2026-10-14T06:56:15Z"}
+
+  netmon/status is the daemon's own: "online" once connected, and "offline"
+  on shutdown, or set by the broker, as the connection's will, if the daemon
+  dies or loses its connection. Keys without a value are left out.
+
+  With Home Assistant discovery, every device also gets a retained config
+  per entity under the discovery prefix, when first published, and when its
+  name or vendor changes:
+
+    homeassistant/device_tracker/netmon/dca632a348b1/config
+      {"unique_id":"netmon_dca632a348b1","name":null,
+       "state_topic":"netmon/devices/dc:a6:32:a3:48:b1/state",
+       "payload_home":"online","payload_not_home":"offline",
+       "source_type":"router",
+       "json_attributes_topic":"netmon/devices/dc:a6:32:a3:48:b1/attributes",
+       "availability_topic":"netmon/status",
+       "device":{"identifiers":["netmon_dca632a348b1"],
+                 "connections":[["mac","dc:a6:32:a3:48:b1"]],
+                 "name":"Living room TV","manufacturer":"Raspberry Pi"},
+       ...}
+    homeassistant/binary_sensor/netmon/dca632a348b1/config
+      {"unique_id":"netmon_dca632a348b1_connectivity","name":"Connectivity",
+       "device_class":"connectivity","payload_on":"online",
+       "payload_off":"offline",...}
+
+  The entities are available as long as netmon/status is "online", so they
+  turn unavailable when the daemon stops. The node id, "netmon", is the
+  prefix with what Home Assistant doesn't allow replaced, which keeps the
+  entities of daemons publishing under different prefixes apart.
+*/
+
+/// How to reach an MQTT broker, and who to connect as.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MqttOptions {
+  pub host: String,
+  pub port: u16,
+  /// Whether to connect over TLS, through `openssl s_client`.
+  pub tls: bool,
+  /// CA certificates to verify the broker with, instead of the system's.
+  pub ca_file: Option<PathBuf>,
+  pub client_id: String,
+  pub username: Option<String>,
+  pub password: Option<String>,
+  /// Longest silence before the broker drops the connection.
+  pub keep_alive: Duration,
+  /// Topic and payload the broker publishes, retained, if the connection is lost.
+  pub will: Option<(String, String)>,
+}
+
+impl MqttOptions {
+  pub fn new(host: &str, port: u16, client_id: &str) -> Self {
+    MqttOptions {
+      host: host.to_string(),
+      port,
+      tls: false,
+      ca_file: None,
+      client_id: client_id.to_string(),
+      username: None,
+      password: None,
+      keep_alive: DEFAULT_KEEP_ALIVE,
+      will: None,
+    }
+  }
+}
+
+impl std::fmt::Debug for MqttOptions {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    // The password is left out.
+    f.debug_struct("MqttOptions")
+      .field("host", &self.host)
+      .field("port", &self.port)
+      .field("tls", &self.tls)
+      .field("client_id", &self.client_id)
+      .field("username", &self.username)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Connection to a broker, over TCP or a TLS tunnel.
+enum Transport {
+  Tcp(TcpStream),
+  /// `openssl s_client`, encrypting what's written to its stdin.
+  Tls(Child),
+}
+
+impl Transport {
+  fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+    match self {
+      Transport::Tcp(stream) => stream.write_all(bytes),
+      Transport::Tls(child) => {
+        let stdin = child.stdin.as_mut().expect("s_client's stdin is piped");
+        stdin.write_all(bytes)?;
+        stdin.flush()
+      }
+    }
+  }
+
+  fn read_fd(&self) -> RawFd {
+    match self {
+      Transport::Tcp(stream) => stream.as_raw_fd(),
+      Transport::Tls(child) => child
+        .stdout
+        .as_ref()
+        .expect("s_client's stdout is piped")
+        .as_raw_fd(),
+    }
+  }
+
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    match self {
+      Transport::Tcp(stream) => stream.read(buf),
+      Transport::Tls(child) => child
+        .stdout
+        .as_mut()
+        .expect("s_client's stdout is piped")
+        .read(buf),
+    }
+  }
+
+  /// Whether there's something to read within the timeout.
+  fn readable(&self, timeout: Duration) -> bool {
+    let mut fd = libc::pollfd {
+      fd: self.read_fd(),
+      events: libc::POLLIN,
+      revents: 0,
+    };
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+    unsafe { libc::poll(&mut fd, 1, timeout) > 0 }
+  }
+
+  /// Reads exactly enough bytes to fill the buffer, within the timeout.
+  fn read_exact(&mut self, buf: &mut [u8], timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut filled = 0;
+    while filled < buf.len() {
+      let left = deadline.saturating_duration_since(Instant::now());
+      if left.is_zero() || !self.readable(left) {
+        return Err(Error::msg("timed out"));
+      }
+      match self.read(&mut buf[filled..])? {
+        0 => return Err(Error::msg("connection closed")),
+        n => filled += n,
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Drop for Transport {
+  fn drop(&mut self) {
+    if let Transport::Tls(child) = self {
+      // Closing its input lets the tunnel forward what was written, such as
+      // a DISCONNECT, before it exits.
+      drop(child.stdin.take());
+      let deadline = Instant::now() + TUNNEL_CLOSE_TIMEOUT;
+      while matches!(child.try_wait(), Ok(None)) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+      }
+      let _ = child.kill();
+      let _ = child.wait();
+    }
+  }
+}
+
+///
+/// A minimal MQTT 3.1.1 client, publishing with QoS 0.
+///
+/// ```no_run
+/// use openwrt_netmon::daemon::mqtt::{MqttClient, MqttOptions};
+///
+/// let mut client = MqttClient::connect(&MqttOptions::new("192.168.1.2", 1883, "netmon"))?;
+/// client.publish("netmon/status", b"online", true)?;
+/// client.disconnect();
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+pub struct MqttClient {
+  transport: Transport,
+  last_write: Instant,
+}
+
+impl MqttClient {
+  ///
+  /// Connects to a broker, waiting for it to accept the connection.
+  ///
+  /// Args:
+  ///  - options: Broker to connect to, and how.
+  ///
+  /// Returns:
+  ///  Result of the client, failing if the broker is unreachable or refuses it.
+  ///
+  pub fn connect(options: &MqttOptions) -> Result<Self> {
+    let address = format!("{}:{}", options.host, options.port);
+    let transport = match options.tls {
+      true => Transport::Tls(spawn_tunnel(options, &address)?),
+      false => {
+        let addr = address
+          .to_socket_addrs()
+          .map_err(|e| Error::msg(format!("Failed to resolve {}: {}", address, e)))?
+          .next()
+          .ok_or_else(|| Error::msg(format!("Failed to resolve {}", address)))?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)
+          .and_then(|stream| stream.set_write_timeout(Some(TIMEOUT)).map(|_| stream))
+          .map_err(|e| Error::msg(format!("Failed to connect to {}: {}", address, e)))?;
+        Transport::Tcp(stream)
+      }
+    };
+    let mut client = MqttClient {
+      transport,
+      last_write: Instant::now(),
+    };
+
+    client.write(&connect_packet(options))?;
+    let mut connack = [0; 4];
+    if let Err(err) = client.transport.read_exact(&mut connack, TIMEOUT) {
+      return Err(Error::msg(format!(
+        "No answer from the broker at {}: {}{}",
+        address,
+        err,
+        client.tunnel_error()
+      )));
+    }
+    match connack {
+      [0x20, 0x02, _, 0] => Ok(client),
+      [0x20, 0x02, _, code] => Err(Error::msg(format!(
+        "The broker at {} refused the connection: {}",
+        address,
+        connack_reason(code)
+      ))),
+      _ => Err(Error::msg(format!(
+        "The broker at {} answered something other than CONNACK",
+        address
+      ))),
+    }
+  }
+
+  /// Why the TLS tunnel closed, as openssl reported it, if it did.
+  fn tunnel_error(&mut self) -> String {
+    let Transport::Tls(child) = &mut self.transport else {
+      return String::new();
+    };
+    let _ = child.kill();
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+      let _ = pipe.read_to_string(&mut stderr);
+    }
+    match stderr
+      .lines()
+      .find(|v| v.contains("error") || v.contains("verify"))
+    {
+      Some(line) => format!(" ({})", line.trim()),
+      None => String::new(),
+    }
+  }
+
+  fn write(&mut self, packet: &[u8]) -> Result<()> {
+    self.drain();
+    self.transport.write_all(packet)?;
+    self.last_write = Instant::now();
+    Ok(())
+  }
+
+  /// Discards what the broker sent since, such as PINGRESPs.
+  fn drain(&mut self) {
+    let mut buf = [0; 512];
+    while self.transport.readable(Duration::ZERO) {
+      match self.transport.read(&mut buf) {
+        Ok(0) | Err(_) => break,
+        Ok(_) => {}
+      }
+    }
+  }
+
+  /// Publishes a message, retained by the broker for new subscribers if `retain`.
+  pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+    let mut body = Vec::with_capacity(2 + topic.len() + payload.len());
+    push_string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    self.write(&packet(0x30 | retain as u8, &body))
+  }
+
+  /// Tells the broker the client is alive, if nothing was sent for a while.
+  pub fn keep_alive(&mut self, keep_alive: Duration) -> Result<()> {
+    match self.last_write.elapsed() >= keep_alive / 2 {
+      true => self.write(&[0xc0, 0x00]),
+      false => Ok(()),
+    }
+  }
+
+  /// Disconnects, without the broker publishing the will.
+  pub fn disconnect(mut self) {
+    let _ = self.transport.write_all(&[0xe0, 0x00]);
+  }
+}
+
+/// Starts `openssl s_client`, verifying the broker's certificate.
+fn spawn_tunnel(options: &MqttOptions, address: &str) -> Result<Child> {
+  let mut command = Command::new("openssl");
+  // -quiet would otherwise keep the tunnel open once its input is closed.
+  command.args(["s_client", "-quiet", "-no_ign_eof", "-verify_return_error"]);
+  command.args(["-connect", address]);
+  command.args(["-servername", &options.host]);
+  command.args(["-verify_hostname", &options.host]);
+  if let Some(ca_file) = &options.ca_file {
+    command.arg("-CAfile").arg(ca_file);
+  }
+  command
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| Error::msg(format!("Failed to run openssl for TLS: {}", e)))
+}
+
+/// An MQTT packet, from its first byte and body.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+  let mut packet = vec![header];
+  let mut length = body.len();
+  loop {
+    let mut byte = (length % 128) as u8;
+    length /= 128;
+    if length > 0 {
+      byte |= 0x80;
+    }
+    packet.push(byte);
+    if length == 0 {
+      break;
+    }
+  }
+  packet.extend_from_slice(body);
+  packet
+}
+
+/// Appends a length-prefixed string.
+fn push_string(body: &mut Vec<u8>, value: &[u8]) {
+  body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+  body.extend_from_slice(value);
+}
+
+fn connect_packet(options: &MqttOptions) -> Vec<u8> {
+  let mut flags = 0x02; // Clean session.
+  if options.will.is_some() {
+    flags |= 0x04 | 0x20; // Will, retained.
+  }
+  if options.username.is_some() {
+    flags |= 0x80;
+    if options.password.is_some() {
+      flags |= 0x40;
+    }
+  }
+  let keep_alive = options.keep_alive.as_secs().min(u16::MAX as u64) as u16;
+
+  let mut body = Vec::new();
+  push_string(&mut body, b"MQTT");
+  body.push(4);
+  body.push(flags);
+  body.extend_from_slice(&keep_alive.to_be_bytes());
+  push_string(&mut body, options.client_id.as_bytes());
+  if let Some((topic, payload)) = &options.will {
+    push_string(&mut body, topic.as_bytes());
+    push_string(&mut body, payload.as_bytes());
+  }
+  if let Some(username) = &options.username {
+    push_string(&mut body, username.as_bytes());
+    if let Some(password) = &options.password {
+      push_string(&mut body, password.as_bytes());
+    }
+  }
+  packet(0x10, &body)
+}
+
+fn connack_reason(code: u8) -> &'static str {
+  match code {
+    1 => "unacceptable protocol version",
+    2 => "client id rejected",
+    3 => "server unavailable",
+    4 => "bad user name or password",
+    5 => "not authorized",
+    _ => "unknown reason",
+  }
+}
+
+/// What was last published for a device.
+#[derive(Debug)]
+struct Published {
+  state: &'static str,
+  /// Attributes, but for last_seen, that change on every poll.
+  attributes: Value,
+  at: Instant,
+  /// Device the discovery configs were published with.
+  discovery: Option<Value>,
+}
+
+///
+/// Sink publishing the state and attributes of every device to an MQTT
+/// broker, as retained topics home automation can subscribe to.
+///
+/// The sink connects on the first poll, and reconnects after a failure,
+/// waiting twice as long after each, up to 5 minutes, then republishes
+/// every device. Polls made while disconnected are dropped.
+///
+/// With Home Assistant discovery, every device also appears in Home
+/// Assistant as a device tracker and a connectivity sensor.
+///
+pub struct MqttSink {
+  options: MqttOptions,
+  prefix: String,
+  attributes_interval: Duration,
+  discovery_prefix: Option<String>,
+  anonymizer: Option<Anonymizer>,
+  client: Option<MqttClient>,
+  published: HashMap<MacAddr, Published>,
+  backoff: Duration,
+  retry_at: Option<Instant>,
+}
+
+impl MqttSink {
+  ///
+  /// Creates a sink, without connecting yet.
+  ///
+  /// Args:
+  ///  - options: Broker to publish to. Its will is set to the daemon's status.
+  ///  - prefix: Prefix of the topics, e.g. "netmon".
+  ///
+  pub fn new(mut options: MqttOptions, prefix: &str) -> Self {
+    let prefix = prefix.trim_end_matches('/').to_string();
+    options.will = Some((format!("{}/status", prefix), "offline".into()));
+    MqttSink {
+      options,
+      prefix,
+      attributes_interval: DEFAULT_ATTRIBUTES_INTERVAL,
+      discovery_prefix: None,
+      anonymizer: None,
+      client: None,
+      published: HashMap::new(),
+      backoff: MIN_BACKOFF,
+      retry_at: None,
+    }
+  }
+
+  /// Longest time between two publications of a device's attributes.
+  pub fn attributes_interval(mut self, attributes_interval: Duration) -> Self {
+    self.attributes_interval = attributes_interval;
+    self
+  }
+
+  /// Publishes Home Assistant discovery configs under the prefix, e.g. "homeassistant".
+  pub fn discovery(mut self, discovery_prefix: &str) -> Self {
+    self.discovery_prefix = Some(discovery_prefix.trim_end_matches('/').to_string());
+    self
+  }
+
+  /// Hashes the MAC addresses, names, and EUI-64 addresses of the devices.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// Connects to the broker if not connected yet, announcing the daemon.
+  fn connect(&mut self) -> Result<&mut MqttClient> {
+    if self.client.is_none() {
+      if let Some(retry_at) = self.retry_at {
+        let wait = retry_at.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+          return Err(Error::msg(format!(
+            "Not connected to MQTT, retrying in {}",
+            humantime::format_duration(Duration::from_secs(wait.as_secs().max(1)))
+          )));
+        }
+      }
+      let status = format!("{}/status", self.prefix);
+      let client = MqttClient::connect(&self.options)
+        .and_then(|mut client| client.publish(&status, b"online", true).map(|_| client));
+      let client = client.inspect_err(|_| self.disconnect())?;
+      info!(
+        "Connected to MQTT at {}:{}",
+        self.options.host, self.options.port
+      );
+      self.backoff = MIN_BACKOFF;
+      self.retry_at = None;
+      self.published.clear();
+      self.client = Some(client);
+    }
+    Ok(self.client.as_mut().unwrap())
+  }
+
+  /// Drops the connection, waiting longer before each reconnection.
+  fn disconnect(&mut self) {
+    self.client = None;
+    self.retry_at = Some(Instant::now() + self.backoff);
+    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+  }
+
+  /// The messages of a poll that changed since they were last published.
+  fn messages(&mut self, report: &PollReport) -> Vec<(String, Vec<u8>)> {
+    let mut messages = Vec::new();
+    for neighbor in report.tracker.neighbors() {
+      let device = report
+        .devices
+        .iter()
+        .find(|v| v.mac_addr == neighbor.mac_addr);
+      let name = report.name_of(&neighbor.mac_addr);
+      let mut ips = neighbor.ips.clone();
+      let (mac_addr, name, hostname) = match &self.anonymizer {
+        Some(anonymizer) => {
+          ips = ips.iter().map(|v| anonymizer.ip(v)).collect();
+          (
+            anonymizer.mac_addr(&neighbor.mac_addr),
+            name.map(|v| anonymizer.name(v)),
+            device
+              .and_then(|v| v.hostname.as_deref())
+              .map(|v| anonymizer.name(v)),
+          )
+        }
+        None => (
+          neighbor.mac_addr,
+          name.map(str::to_string),
+          device.and_then(|v| v.hostname.clone()),
+        ),
+      };
+      let ip = ips.iter().find(|v| v.ip.is_ipv4()).or(ips.first());
+
+      let mut attributes = json!({
+        "mac": mac_addr.to_string(),
+        "ips": ips.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+        "iface": neighbor.iface,
+        "online": neighbor.online,
+      });
+      let optional = [
+        ("name", name.map(Value::from)),
+        ("ip", ip.map(|v| Value::from(v.to_string()))),
+        ("hostname", hostname.map(Value::from)),
+        ("vendor", neighbor.mac_addr.vendor().map(Value::from)),
+        (
+          "signal_dbm",
+          device
+            .and_then(|v| v.station.as_ref())
+            .and_then(|v| v.signal_dbm)
+            .map(Value::from),
+        ),
+      ];
+      for (key, value) in optional {
+        if let Some(value) = value {
+          attributes[key] = value;
+        }
+      }
+
+      let state = match neighbor.online {
+        true => "online",
+        false => "offline",
+      };
+      let topic = format!("{}/devices/{}", self.prefix, mac_addr);
+      let previous = self.published.get(&neighbor.mac_addr);
+      let discovery = self.discovery_prefix.as_ref().map(|discovery_prefix| {
+        let name = attributes["name"]
+          .as_str()
+          .or(attributes["hostname"].as_str())
+          .map_or_else(|| mac_addr.to_string(), str::to_string);
+        let mut device = json!({
+          "identifiers": [self.unique_id(&mac_addr)],
+          "connections": [["mac", mac_addr.to_string()]],
+          "name": name,
+        });
+        if let Some(vendor) = attributes.get("vendor") {
+          device["manufacturer"] = vendor.clone();
+        }
+        if previous.is_none_or(|v| v.discovery.as_ref() != Some(&device)) {
+          messages.extend(self.discovery_messages(discovery_prefix, &mac_addr, &device));
+        }
+        device
+      });
+      if previous.is_none_or(|v| v.state != state) {
+        messages.push((format!("{}/state", topic), state.as_bytes().to_vec()));
+      }
+      let stale = previous
+        .is_none_or(|v| v.attributes != attributes || v.at.elapsed() >= self.attributes_interval);
+      let at = match stale {
+        true => {
+          let mut payload = attributes.clone();
+          payload["last_seen"] = humantime::format_rfc3339_seconds(neighbor.last_seen)
+            .to_string()
+            .into();
+          messages.push((
+            format!("{}/attributes", topic),
+            payload.to_string().into_bytes(),
+          ));
+          Instant::now()
+        }
+        false => previous.map_or_else(Instant::now, |v| v.at),
+      };
+
+      self.published.insert(
+        neighbor.mac_addr,
+        Published {
+          state,
+          attributes,
+          at,
+          discovery,
+        },
+      );
+    }
+    messages
+  }
+
+  /// Id Home Assistant tells a device's entities apart by, after the node id.
+  fn unique_id(&self, mac_addr: &MacAddr) -> String {
+    format!(
+      "{}_{}",
+      node_id(&self.prefix),
+      mac_addr.to_string().replace(':', "")
+    )
+  }
+
+  ///
+  /// The Home Assistant discovery configs of a device: a device tracker,
+  /// home while the device is online, and a connectivity sensor.
+  ///
+  /// Args:
+  ///  - discovery_prefix: Prefix Home Assistant subscribes to.
+  ///  - mac_addr: The device's MAC address, as published.
+  ///  - device: The device, as Home Assistant's device registry takes it.
+  ///
+  /// Returns:
+  ///  The topics and payloads of the configs.
+  ///
+  fn discovery_messages(
+    &self,
+    discovery_prefix: &str,
+    mac_addr: &MacAddr,
+    device: &Value,
+  ) -> Vec<(String, Vec<u8>)> {
+    let topic = format!("{}/devices/{}", self.prefix, mac_addr);
+    let unique_id = self.unique_id(mac_addr);
+    let common = json!({
+      "state_topic": format!("{}/state", topic),
+      "json_attributes_topic": format!("{}/attributes", topic),
+      "availability_topic": format!("{}/status", self.prefix),
+      "payload_available": "online",
+      "payload_not_available": "offline",
+      "device": device,
+      "origin": {"name": "netmon", "sw_version": env!("CARGO_PKG_VERSION")},
+    });
+    let entities = [
+      (
+        "device_tracker",
+        json!({
+          "unique_id": unique_id,
+          "name": null,
+          "payload_home": "online",
+          "payload_not_home": "offline",
+          "source_type": "router",
+        }),
+      ),
+      (
+        "binary_sensor",
+        json!({
+          "unique_id": format!("{}_connectivity", unique_id),
+          "name": "Connectivity",
+          "device_class": "connectivity",
+          "payload_on": "online",
+          "payload_off": "offline",
+        }),
+      ),
+    ];
+    let object_id = mac_addr.to_string().replace(':', "");
+    entities
+      .into_iter()
+      .map(|(component, mut config)| {
+        for (key, value) in common.as_object().unwrap() {
+          config[key] = value.clone();
+        }
+        let topic = format!(
+          "{}/{}/{}/{}/config",
+          discovery_prefix,
+          component,
+          node_id(&self.prefix),
+          object_id
+        );
+        (topic, config.to_string().into_bytes())
+      })
+      .collect()
+  }
+}
+
+impl Sink for MqttSink {
+  fn name(&self) -> &str {
+    "mqtt"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    self.connect()?;
+    let messages = self.messages(report);
+    debug!("Publishing {} MQTT message(s)", messages.len());
+    let keep_alive = self.options.keep_alive;
+    let client = self.client.as_mut().unwrap();
+    let published = messages
+      .iter()
+      .try_for_each(|(topic, payload)| client.publish(topic, payload, true))
+      .and_then(|_| client.keep_alive(keep_alive));
+    published.map_err(|e| {
+      self.disconnect();
+      Error::msg(format!("Failed to publish to MQTT: {}", e))
+    })
+  }
+
+  fn needs_thread(&self) -> bool {
+    true
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    if let Some(mut client) = self.client.take() {
+      client.publish(&format!("{}/status", self.prefix), b"offline", true)?;
+      client.disconnect();
+    }
+    Ok(())
+  }
+}
+
+impl std::fmt::Debug for MqttSink {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("MqttSink")
+      .field("options", &self.options)
+      .field("prefix", &self.prefix)
+      .field("connected", &self.client.is_some())
+      .finish_non_exhaustive()
+  }
+}
+
+/// Node id of the discovery topics: the prefix, with what Home Assistant doesn't allow replaced.
+fn node_id(prefix: &str) -> String {
+  prefix
+    .chars()
+    .map(
+      |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+        true => c,
+        false => '_',
+      },
+    )
+    .collect()
+}
@@ -0,0 +1,354 @@
+use super::metrics::{device_samples, neighbor_counts};
+use super::{PollReport, Sink};
+use crate::privacy::Anonymizer;
+use anyhow::{Error, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::time::Duration;
+
+/// Name of the plugin the charts belong to.
+pub const PLUGIN: &str = "netmon";
+
+/// Priority of the first chart, placing the charts after Netdata's own.
+const PRIORITY: u32 = 90000;
+
+/*
+  Every poll prints Netdata's external plugin protocol, defining the charts
+  first, and again when their dimensions change:
+
+    CHART 'netmon.devices' '' 'Devices' 'devices' 'devices' 'netmon.devices' 'line' 90000 30 '' 'netmon'
+    DIMENSION 'tracked' 'tracked' 'absolute' 1 1 ''
+    DIMENSION 'online' 'online' 'absolute' 1 1 ''
+    BEGIN 'netmon.devices'
+    SET 'tracked' = 14
+    SET 'online' = 12
+    END
+
+  Along with netmon.neighbors, the entries by NUD state, netmon.poll_duration,
+  and netmon.presence, a dimension per device, 1 while it's online, named
+  after the device. Wireless stations also get a netmon.traffic_<mac> chart,
+  in kilobits per second, and a netmon.signal_<mac> one. Charts and
+  dimensions of the devices that were forgotten are marked obsolete.
+*/
+
+///
+/// Sink printing the devices' presence and traffic, and the neighbor table's
+/// state, in Netdata's external plugin protocol, for Netdata to read off
+/// `netmon netdata-plugin`.
+///
+pub struct NetdataSink {
+  out: Box<dyn Write + Send>,
+  interval: Duration,
+  anonymizer: Option<Anonymizer>,
+  /// Charts as last defined, by id.
+  charts: BTreeMap<String, Chart>,
+  /// NUD states seen so far, which keep their dimensions once seen.
+  nud_states: BTreeSet<String>,
+}
+
+/// A chart, as defined to Netdata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Chart {
+  id: String,
+  title: String,
+  units: &'static str,
+  family: &'static str,
+  context: &'static str,
+  kind: &'static str,
+  priority: u32,
+  dimensions: Vec<Dimension>,
+}
+
+/// A dimension of a chart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Dimension {
+  id: String,
+  name: String,
+  algorithm: &'static str,
+  multiplier: i64,
+  divisor: i64,
+}
+
+impl Dimension {
+  /// A dimension collected as is.
+  fn absolute(id: &str, name: &str) -> Self {
+    Dimension {
+      id: id.to_string(),
+      name: name.to_string(),
+      algorithm: "absolute",
+      multiplier: 1,
+      divisor: 1,
+    }
+  }
+}
+
+impl NetdataSink {
+  ///
+  /// Creates a sink.
+  ///
+  /// Args:
+  ///  - out: Where to print the protocol, Netdata reading the plugin's stdout.
+  ///  - interval: Interval the values are polled at, Netdata's update_every.
+  ///
+  pub fn new(out: Box<dyn Write + Send>, interval: Duration) -> Self {
+    NetdataSink {
+      out,
+      interval,
+      anonymizer: None,
+      charts: BTreeMap::new(),
+      nud_states: BTreeSet::new(),
+    }
+  }
+
+  /// Hashes the MAC addresses and names of the devices.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// A chart without dimensions yet, placed after the `index`th chart.
+  fn chart(&self, id: &str, title: &str, units: &'static str, index: u32) -> Chart {
+    Chart {
+      id: format!("{}.{}", PLUGIN, id),
+      title: title.to_string(),
+      units,
+      family: "devices",
+      context: "",
+      kind: "line",
+      priority: PRIORITY + index,
+      dimensions: Vec::new(),
+    }
+  }
+
+  /// The charts of a poll, along with their values by dimension.
+  fn charts(&mut self, report: &PollReport) -> Vec<(Chart, Vec<(String, i64)>)> {
+    let devices = device_samples(report, self.anonymizer.as_ref());
+    let online = devices.iter().filter(|v| v.online).count();
+    let mut charts = Vec::new();
+
+    let mut chart = self.chart("devices", "Devices", "devices", 0);
+    chart.context = "netmon.devices";
+    chart.dimensions = vec![
+      Dimension::absolute("tracked", "tracked"),
+      Dimension::absolute("online", "online"),
+    ];
+    let values = vec![
+      ("tracked".to_string(), devices.len() as i64),
+      ("online".to_string(), online as i64),
+    ];
+    charts.push((chart, values));
+
+    let counts: BTreeMap<String, u64> = neighbor_counts(report)
+      .into_iter()
+      .map(|(nud_state, count)| (chart_id_part(&nud_state.to_lowercase()), count))
+      .collect();
+    self.nud_states.extend(counts.keys().cloned());
+    let mut chart = self.chart("neighbors", "Neighbor entries by NUD state", "entries", 1);
+    chart.family = "neighbors";
+    chart.context = "netmon.neighbors";
+    chart.kind = "stacked";
+    chart.dimensions = self
+      .nud_states
+      .iter()
+      .map(|v| Dimension::absolute(v, v))
+      .collect();
+    let values = self
+      .nud_states
+      .iter()
+      .map(|v| (v.clone(), counts.get(v).copied().unwrap_or_default() as i64))
+      .collect();
+    charts.push((chart, values));
+
+    let mut chart = self.chart("poll_duration", "Poll duration", "milliseconds", 2);
+    chart.family = "polling";
+    chart.context = "netmon.poll_duration";
+    chart.dimensions = vec![Dimension {
+      divisor: 1000,
+      ..Dimension::absolute("duration", "duration")
+    }];
+    let values = vec![("duration".to_string(), report.duration.as_micros() as i64)];
+    charts.push((chart, values));
+
+    let mut presence = self.chart("presence", "Presence of devices", "online", 3);
+    presence.context = "netmon.presence";
+    let mut values = Vec::new();
+    let mut stations = Vec::new();
+    for device in &devices {
+      let id = chart_id_part(&device.mac);
+      let name = match device.name.is_empty() {
+        true => device.mac.as_str(),
+        false => device.name.as_str(),
+      };
+      presence.dimensions.push(Dimension::absolute(&id, name));
+      values.push((id.clone(), device.online as i64));
+      if !device.has_traffic() {
+        continue;
+      }
+
+      let mut chart = self.chart(
+        &format!("traffic_{}", id),
+        &format!("Traffic of {}", name),
+        "kilobits/s",
+        4,
+      );
+      chart.family = "traffic";
+      chart.context = "netmon.device_traffic";
+      chart.kind = "area";
+      let bytes = [
+        ("received", device.rx_bytes, 8),
+        ("sent", device.tx_bytes, -8),
+      ];
+      let mut traffic = Vec::new();
+      for (dimension, value, multiplier) in bytes {
+        if let Some(value) = value {
+          chart.dimensions.push(Dimension {
+            algorithm: "incremental",
+            multiplier,
+            divisor: 1000,
+            ..Dimension::absolute(dimension, dimension)
+          });
+          traffic.push((dimension.to_string(), value as i64));
+        }
+      }
+      stations.push((chart, traffic));
+
+      if let Some(signal_dbm) = device.signal_dbm {
+        let mut chart = self.chart(
+          &format!("signal_{}", id),
+          &format!("Signal of {}", name),
+          "dBm",
+          5,
+        );
+        chart.family = "signal";
+        chart.context = "netmon.device_signal";
+        chart.dimensions = vec![Dimension::absolute("signal", "signal")];
+        stations.push((chart, vec![("signal".to_string(), signal_dbm as i64)]));
+      }
+    }
+    charts.push((presence, values));
+    charts.extend(stations);
+    charts
+  }
+
+  /// The lines of a poll, defining the charts that changed, or went away.
+  fn lines(&mut self, report: &PollReport) -> Vec<String> {
+    let charts = self.charts(report);
+    let update_every = self.interval.as_secs().max(1);
+    let mut lines = Vec::new();
+
+    let current: BTreeSet<&str> = charts.iter().map(|(v, _)| v.id.as_str()).collect();
+    let gone: Vec<String> = self
+      .charts
+      .keys()
+      .filter(|v| !current.contains(v.as_str()))
+      .cloned()
+      .collect();
+    for id in gone {
+      let chart = self.charts.remove(&id).unwrap();
+      lines.push(chart_line(&chart, update_every, "obsolete"));
+    }
+
+    for (chart, values) in charts {
+      let defined = self.charts.get(&chart.id);
+      if defined != Some(&chart) {
+        lines.push(chart_line(&chart, update_every, ""));
+        for dimension in &chart.dimensions {
+          lines.push(dimension_line(dimension, ""));
+        }
+        let dimensions = defined.map(|v| v.dimensions.as_slice()).unwrap_or_default();
+        for dimension in dimensions {
+          if !chart.dimensions.iter().any(|v| v.id == dimension.id) {
+            lines.push(dimension_line(dimension, "obsolete"));
+          }
+        }
+      }
+
+      lines.push(format!("BEGIN {}", quote(&chart.id)));
+      for (dimension, value) in values {
+        lines.push(format!("SET {} = {}", quote(&dimension), value));
+      }
+      lines.push("END".to_string());
+      self.charts.insert(chart.id.clone(), chart);
+    }
+    lines
+  }
+}
+
+impl Sink for NetdataSink {
+  fn name(&self) -> &str {
+    "netdata"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let mut out = String::new();
+    for line in self.lines(report) {
+      out.push_str(&line);
+      out.push('\n');
+    }
+    self
+      .out
+      .write_all(out.as_bytes())
+      .and_then(|_| self.out.flush())
+      .map_err(|e| Error::msg(format!("Failed to write to Netdata: {}", e)))
+  }
+
+  fn needs_thread(&self) -> bool {
+    // Netdata may be slow to read its end of the pipe.
+    true
+  }
+}
+
+impl std::fmt::Debug for NetdataSink {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("NetdataSink")
+      .field("interval", &self.interval)
+      .field("charts", &self.charts.len())
+      .finish_non_exhaustive()
+  }
+}
+
+/// The CHART line defining a chart, with its options, e.g. "obsolete".
+fn chart_line(chart: &Chart, update_every: u64, options: &str) -> String {
+  format!(
+    "CHART {} '' {} {} {} {} {} {} {} {} {}",
+    quote(&chart.id),
+    quote(&chart.title),
+    quote(chart.units),
+    quote(chart.family),
+    quote(chart.context),
+    quote(chart.kind),
+    chart.priority,
+    update_every,
+    quote(options),
+    quote(PLUGIN)
+  )
+}
+
+/// The DIMENSION line defining a dimension, with its options.
+fn dimension_line(dimension: &Dimension, options: &str) -> String {
+  format!(
+    "DIMENSION {} {} {} {} {} {}",
+    quote(&dimension.id),
+    quote(&dimension.name),
+    quote(dimension.algorithm),
+    dimension.multiplier,
+    dimension.divisor,
+    quote(options)
+  )
+}
+
+/// Quotes a word of the protocol, which can't hold quotes or newlines.
+fn quote(value: &str) -> String {
+  format!("'{}'", value.replace(['\'', '"', '\n'], ""))
+}
+
+/// Replaces what Netdata doesn't take in the ids of charts and dimensions.
+fn chart_id_part(value: &str) -> String {
+  value
+    .chars()
+    .map(|c| match c.is_ascii_alphanumeric() || c == '_' {
+      true => c,
+      false => '_',
+    })
+    .collect()
+}
@@ -0,0 +1,396 @@
+use super::http::{redact, HttpRequest};
+use super::metrics::{device_samples, neighbor_counts, unix_secs, DeviceSample};
+use super::trace::{self, Span};
+use super::{hostname, PollReport, Sink};
+use crate::counters;
+use crate::privacy::Anonymizer;
+use anyhow::{Error, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Service the telemetry is reported under when none is configured.
+pub const DEFAULT_SERVICE_NAME: &str = "netmon";
+
+/// Kind of the spans, all internal to the daemon (SPAN_KIND_INTERNAL).
+const SPAN_KIND_INTERNAL: u8 = 1;
+
+/// Status of failed spans (STATUS_CODE_ERROR).
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// Temporality of the cumulative sums (AGGREGATION_TEMPORALITY_CUMULATIVE).
+const CUMULATIVE: u8 = 2;
+
+/*
+  Every poll is exported to an OpenTelemetry collector over OTLP/HTTP, in
+  its JSON encoding, as a trace of the poll's steps:
+
+    poll                  the collection, until the poll is handed out
+      collect neighbor    each collector, failed ones with their error
+      collect lease
+      collect wireless
+      join                grouping the entries into devices
+      sink sqlite         each sink publishing the poll
+
+  Sinks publish after the poll was handed out, so their spans are exported
+  with a later poll, in the same trace. Along with the metrics:
+
+    netmon.device.online       gauge, per device (mac, name, iface)
+    netmon.device.last_seen    gauge, in seconds since the Unix epoch
+    netmon.neighbors           gauge, per NUD state (nud_state)
+    netmon.poll.duration       gauge, in seconds
+    netmon.parse_errors        cumulative sum
+*/
+
+///
+/// Sink exporting a trace of every poll, and the devices' metrics, to an
+/// OpenTelemetry collector over OTLP/HTTP (usually on port 4318).
+///
+/// Telemetry the collector can't be reached for is dropped.
+///
+#[derive(Debug)]
+pub struct OtlpSink {
+  endpoint: String,
+  headers: BTreeMap<String, String>,
+  ca_file: Option<PathBuf>,
+  service_name: String,
+  traces: bool,
+  metrics: bool,
+  anonymizer: Option<Anonymizer>,
+  /// Random seed of the trace ids, so polls of different runs don't collide.
+  seed: [u8; 16],
+  started_at: SystemTime,
+}
+
+impl OtlpSink {
+  ///
+  /// Creates a sink exporting both traces and metrics, and starts recording
+  /// the sinks' spans.
+  ///
+  /// Args:
+  ///  - endpoint: Base URL of the collector, e.g. "http://10.0.0.1:4318".
+  ///
+  pub fn new(endpoint: &str) -> Self {
+    trace::enable();
+    let mut seed = [0; 16];
+    let random = std::fs::File::open("/dev/urandom").and_then(|mut v| v.read_exact(&mut seed));
+    if random.is_err() {
+      let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+      seed = (nanos ^ std::process::id() as u128).to_le_bytes();
+    }
+    OtlpSink {
+      endpoint: endpoint.trim_end_matches('/').to_string(),
+      headers: BTreeMap::new(),
+      ca_file: None,
+      service_name: DEFAULT_SERVICE_NAME.to_string(),
+      traces: true,
+      metrics: true,
+      anonymizer: None,
+      seed,
+      started_at: SystemTime::now(),
+    }
+  }
+
+  /// Headers of every request, e.g. an API key.
+  pub fn headers(mut self, headers: BTreeMap<String, String>) -> Self {
+    self.headers = headers;
+    self
+  }
+
+  /// CA certificates to verify an https endpoint with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// Service the telemetry is reported under.
+  pub fn service_name(mut self, service_name: &str) -> Self {
+    self.service_name = service_name.to_string();
+    self
+  }
+
+  /// Whether to export the polls' traces.
+  pub fn traces(mut self, traces: bool) -> Self {
+    self.traces = traces;
+    self
+  }
+
+  /// Whether to export the devices' metrics.
+  pub fn metrics(mut self, metrics: bool) -> Self {
+    self.metrics = metrics;
+    self
+  }
+
+  /// Hashes the MAC addresses and names the devices' metrics are labelled with.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// Trace id of a poll, told apart by the time it was taken at.
+  fn trace_id(&self, poll: SystemTime) -> [u8; 16] {
+    let nanos = poll
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_nanos();
+    let hash = Sha256::new()
+      .chain_update(self.seed)
+      .chain_update(nanos.to_le_bytes())
+      .finalize();
+    let mut trace_id = [0; 16];
+    trace_id.copy_from_slice(&hash[..16]);
+    trace_id
+  }
+
+  /// Resource the telemetry is reported under.
+  fn resource(&self) -> Value {
+    json!({
+      "attributes": [
+        attribute("service.name", &self.service_name),
+        attribute("service.version", env!("CARGO_PKG_VERSION")),
+        attribute("host.name", &hostname()),
+      ]
+    })
+  }
+
+  /// Scope of the telemetry.
+  fn scope() -> Value {
+    json!({ "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") })
+  }
+
+  /// The spans of a poll, and of the sinks that published earlier polls.
+  fn spans(&self, report: &PollReport) -> Vec<Value> {
+    let trace_id = self.trace_id(report.snapshot.taken_at);
+    let root = span_id(&trace_id, "poll");
+    let mut spans = vec![format_span(
+      &trace_id,
+      root,
+      None,
+      &Span::new("poll", report.started_at, report.duration),
+      vec![
+        int_attribute("netmon.neighbors", report.snapshot.entries.len() as u64),
+        int_attribute("netmon.devices", report.devices.len() as u64),
+      ],
+    )];
+    for span in &report.spans {
+      let id = span_id(&trace_id, &span.name);
+      spans.push(format_span(&trace_id, id, Some(root), span, Vec::new()));
+    }
+    for sink_span in trace::take_sink_spans() {
+      let trace_id = self.trace_id(sink_span.poll);
+      let id = span_id(&trace_id, &sink_span.span.name);
+      spans.push(format_span(
+        &trace_id,
+        id,
+        Some(span_id(&trace_id, "poll")),
+        &sink_span.span,
+        vec![attribute("netmon.sink", &sink_span.sink)],
+      ));
+    }
+    spans
+  }
+
+  /// The metrics of a poll.
+  fn format_metrics(&self, report: &PollReport) -> Vec<Value> {
+    let time = nanos(report.snapshot.taken_at);
+    let devices = device_samples(report, self.anonymizer.as_ref());
+    let device_attributes = |device: &DeviceSample| {
+      let mut attributes = vec![
+        attribute("mac", &device.mac),
+        attribute("iface", &device.iface),
+      ];
+      if !device.name.is_empty() {
+        attributes.push(attribute("name", &device.name));
+      }
+      attributes
+    };
+
+    let online: Vec<Value> = devices
+      .iter()
+      .map(|v| int_point(device_attributes(v), &time, v.online as u64))
+      .collect();
+    let last_seen: Vec<Value> = devices
+      .iter()
+      .map(|v| int_point(device_attributes(v), &time, unix_secs(v.last_seen)))
+      .collect();
+    let neighbors: Vec<Value> = neighbor_counts(report)
+      .into_iter()
+      .map(|(nud_state, count)| int_point(vec![attribute("nud_state", &nud_state)], &time, count))
+      .collect();
+
+    vec![
+      json!({
+        "name": "netmon.device.online",
+        "description": "Whether the device is online.",
+        "unit": "1",
+        "gauge": { "dataPoints": online },
+      }),
+      json!({
+        "name": "netmon.device.last_seen",
+        "description": "Time the device was last seen.",
+        "unit": "s",
+        "gauge": { "dataPoints": last_seen },
+      }),
+      json!({
+        "name": "netmon.neighbors",
+        "description": "Neighbor entries, by NUD state.",
+        "unit": "{entry}",
+        "gauge": { "dataPoints": neighbors },
+      }),
+      json!({
+        "name": "netmon.poll.duration",
+        "description": "Time the poll took.",
+        "unit": "s",
+        "gauge": {
+          "dataPoints": [{ "timeUnixNano": time, "asDouble": report.duration.as_secs_f64() }],
+        },
+      }),
+      json!({
+        "name": "netmon.parse_errors",
+        "description": "Entries skipped because they couldn't be parsed.",
+        "unit": "{entry}",
+        "sum": {
+          "aggregationTemporality": CUMULATIVE,
+          "isMonotonic": true,
+          "dataPoints": [{
+            "startTimeUnixNano": nanos(self.started_at),
+            "timeUnixNano": time,
+            "asInt": counters::parse_errors().to_string(),
+          }],
+        },
+      }),
+    ]
+  }
+
+  /// Posts a JSON message to a path of the collector.
+  fn send(&self, path: &str, message: &Value) -> Result<()> {
+    let url = format!("{}{}", self.endpoint, path);
+    let mut request = HttpRequest::post(&url)
+      .header("Content-Type", "application/json")
+      .body(&message.to_string());
+    for (name, value) in &self.headers {
+      request = request.header(name, value);
+    }
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    let response = request.send()?;
+    match response.is_success() {
+      true => Ok(()),
+      false => Err(Error::msg(format!(
+        "OTLP collector at {} answered {}: {}",
+        redact(&self.endpoint),
+        response.status,
+        response.body.trim()
+      ))),
+    }
+  }
+}
+
+impl Sink for OtlpSink {
+  fn name(&self) -> &str {
+    "otlp"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let traces = match self.traces {
+      true => self.send(
+        "/v1/traces",
+        &json!({
+          "resourceSpans": [{
+            "resource": self.resource(),
+            "scopeSpans": [{ "scope": Self::scope(), "spans": self.spans(report) }],
+          }]
+        }),
+      ),
+      false => Ok(()),
+    };
+    let metrics = match self.metrics {
+      true => self.send(
+        "/v1/metrics",
+        &json!({
+          "resourceMetrics": [{
+            "resource": self.resource(),
+            "scopeMetrics": [{ "scope": Self::scope(), "metrics": self.format_metrics(report) }],
+          }]
+        }),
+      ),
+      false => Ok(()),
+    };
+    traces.and(metrics)
+  }
+
+  fn needs_thread(&self) -> bool {
+    true
+  }
+}
+
+/// Nanoseconds since the Unix epoch, as a string as OTLP's JSON encodes 64-bit integers.
+fn nanos(time: SystemTime) -> String {
+  time
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos()
+    .to_string()
+}
+
+/// Id of a span, derived from its trace and name, so sinks can refer to
+/// their poll's span.
+fn span_id(trace_id: &[u8; 16], name: &str) -> [u8; 8] {
+  let hash = Sha256::new()
+    .chain_update(trace_id)
+    .chain_update(name.as_bytes())
+    .finalize();
+  let mut span_id = [0; 8];
+  span_id.copy_from_slice(&hash[..8]);
+  span_id
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+  json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn int_attribute(key: &str, value: u64) -> Value {
+  json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+/// A data point of an integer metric.
+fn int_point(attributes: Vec<Value>, time: &str, value: u64) -> Value {
+  json!({ "attributes": attributes, "timeUnixNano": time, "asInt": value.to_string() })
+}
+
+/// Formats a span, with its error as its status.
+fn format_span(
+  trace_id: &[u8; 16],
+  span_id: [u8; 8],
+  parent: Option<[u8; 8]>,
+  span: &Span,
+  attributes: Vec<Value>,
+) -> Value {
+  let mut value = json!({
+    "traceId": hex(trace_id),
+    "spanId": hex(&span_id),
+    "name": span.name,
+    "kind": SPAN_KIND_INTERNAL,
+    "startTimeUnixNano": nanos(span.start),
+    "endTimeUnixNano": nanos(span.start + span.duration),
+    "attributes": attributes,
+  });
+  if let Some(parent) = parent {
+    value["parentSpanId"] = json!(hex(&parent));
+  }
+  if let Some(error) = &span.error {
+    value["status"] = json!({ "code": STATUS_CODE_ERROR, "message": error });
+  }
+  value
+}
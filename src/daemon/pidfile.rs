@@ -0,0 +1,133 @@
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Pid file the daemon locks when none is configured.
+pub const DEFAULT_PID_FILE: &str = "/var/run/netmon.pid";
+
+/// Whether a process with the given pid exists.
+fn process_exists(pid: libc::pid_t) -> bool {
+  // SAFETY: signal 0 only checks whether the process could be signalled.
+  let rc = unsafe { libc::kill(pid, 0) };
+  rc == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+///
+/// Pid file locked with flock(2) for as long as the daemon runs, so a second
+/// instance can't start and double every notification. The lock goes away
+/// with the process, so a crashed daemon never leaves a lock behind, only a
+/// stale file that's taken over. The file is removed once dropped.
+///
+#[derive(Debug)]
+pub struct PidFile {
+  path: PathBuf,
+  file: File,
+}
+
+impl PidFile {
+  ///
+  /// Locks the pid file, failing if another daemon holds it.
+  ///
+  /// A file that isn't locked is stale and taken over, unless the pid it
+  /// names is still running, e.g. an instance that predates the lock or a
+  /// reused pid: `force` takes it over regardless.
+  ///
+  /// Args:
+  ///  - path: Pid file to lock.
+  ///  - force: Take over an unlocked pid file naming a running process.
+  ///
+  /// Returns:
+  ///  Result of the locked pid file, holding the current pid.
+  ///
+  pub fn acquire(path: &Path, force: bool) -> Result<PidFile> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+    let mut file = File::options()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .mode(0o644)
+      .open(path)
+      .map_err(|e| Error::msg(format!("Failed to open {}: {}", path.display(), e)))?;
+
+    let mut content = String::new();
+    let _ = file.read_to_string(&mut content);
+    let previous: Option<libc::pid_t> = content.trim().parse().ok();
+
+    // SAFETY: the descriptor is open for as long as file lives.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+      let err = std::io::Error::last_os_error();
+      return Err(match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Error::msg(format!(
+          "netmon is already running (pid {}), stop it first: {} is locked",
+          previous.map_or("unknown".to_string(), |v| v.to_string()),
+          path.display()
+        )),
+        _ => Error::msg(format!("Failed to lock {}: {}", path.display(), err)),
+      });
+    }
+
+    match previous {
+      Some(pid) if pid as u32 != std::process::id() && process_exists(pid) => {
+        if !force {
+          return Err(Error::msg(format!(
+            "{} names pid {}, which is still running but doesn't hold the lock. \
+             If it isn't netmon, the file is stale: remove it or pass --force",
+            path.display(),
+            pid
+          )));
+        }
+        warn!(
+          "Taking over {} from running pid {}, as forced",
+          path.display(),
+          pid
+        );
+      }
+      Some(pid) => debug!("Taking over {} from stale pid {}", path.display(), pid),
+      None => {}
+    }
+
+    let mut pid_file = PidFile {
+      path: path.to_path_buf(),
+      file,
+    };
+    pid_file.write_pid()?;
+    Ok(pid_file)
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  ///
+  /// Writes the current pid, e.g. again once detached, as the lock carries
+  /// over to the forked process but the pid doesn't.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the pid was written.
+  ///
+  pub fn write_pid(&mut self) -> Result<()> {
+    let pid = std::process::id();
+    self
+      .file
+      .set_len(0)
+      .and_then(|_| self.file.rewind())
+      .and_then(|_| writeln!(self.file, "{}", pid))
+      .and_then(|_| self.file.flush())
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", self.path.display(), e)))
+  }
+}
+
+impl Drop for PidFile {
+  fn drop(&mut self) {
+    // Removed while still locked, so no other daemon locks the file meanwhile.
+    let _ = std::fs::remove_file(&self.path);
+  }
+}
@@ -0,0 +1,87 @@
+use anyhow::{Error, Result};
+use std::time::Duration;
+
+/// Devices the tiny profile tracks before forgetting the offline ones.
+pub const TINY_MAX_NEIGHBORS: usize = 256;
+
+/// Events, and sightings, the tiny profile keeps in memory at most.
+pub const TINY_MAX_HISTORY_RECORDS: usize = 2_000;
+
+/// Blocking threads the tiny profile's runtime may run at once.
+const TINY_BLOCKING_THREADS: usize = 2;
+
+/// How long the tiny profile's idle blocking threads are kept around.
+const TINY_THREAD_KEEP_ALIVE: Duration = Duration::from_secs(1);
+
+///
+/// Trades features for memory. The tiny profile targets 64 to 128 MB
+/// routers: it caps the devices tracked, keeps the vendor lookups to the embedded snapshot,
+/// publishes to the sinks on the polling thread instead of one thread each,
+/// runs one collector at a time, bounds the runtime's blocking threads, and
+/// keeps less recent history in memory.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ResourceProfile {
+  #[default]
+  Standard,
+  Tiny,
+}
+
+impl ResourceProfile {
+  pub fn name(self) -> &'static str {
+    match self {
+      ResourceProfile::Standard => "standard",
+      ResourceProfile::Tiny => "tiny",
+    }
+  }
+
+  pub fn is_tiny(self) -> bool {
+    self == ResourceProfile::Tiny
+  }
+
+  /// Devices tracked at most, unlimited if None.
+  pub fn max_neighbors(self) -> Option<usize> {
+    match self {
+      ResourceProfile::Standard => None,
+      ResourceProfile::Tiny => Some(TINY_MAX_NEIGHBORS),
+    }
+  }
+
+  /// Collectors run at once, capping the configured concurrency.
+  pub fn collector_concurrency(self, configured: usize) -> usize {
+    match self {
+      ResourceProfile::Standard => configured,
+      ResourceProfile::Tiny => configured.min(1),
+    }
+  }
+
+  /// Recent history records kept in memory, capping the configured limit.
+  pub fn max_history_records(self, configured: usize) -> usize {
+    match self {
+      ResourceProfile::Standard => configured,
+      ResourceProfile::Tiny => configured.min(TINY_MAX_HISTORY_RECORDS),
+    }
+  }
+
+  ///
+  /// Builds the single-threaded runtime the daemon polls on.
+  ///
+  /// Returns:
+  ///  Result of the runtime, its blocking pool bounded by the profile.
+  ///
+  pub fn runtime(self) -> Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    builder.enable_all();
+    if self.is_tiny() {
+      // The watchdog abandons stuck collectors, so one thread is kept spare.
+      builder
+        .max_blocking_threads(TINY_BLOCKING_THREADS)
+        .thread_keep_alive(TINY_THREAD_KEEP_ALIVE);
+    }
+    builder
+      .build()
+      .map_err(|e| Error::msg(format!("Failed to start the runtime: {}", e)))
+  }
+}
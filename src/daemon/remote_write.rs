@@ -0,0 +1,408 @@
+use super::http::{redact, HttpRequest, HttpResponse};
+use super::metrics::{device_samples, neighbor_counts, unix_secs};
+use super::{hostname, PollReport, Sink};
+use crate::counters;
+use crate::privacy::Anonymizer;
+use anyhow::{Error, Result};
+use log::{info, warn};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// Samples sent at once when none is configured.
+pub const DEFAULT_BATCH_SIZE: usize = 2000;
+
+/// Samples held while the endpoint is unreachable when none is configured,
+/// past which the oldest are dropped.
+pub const DEFAULT_MAX_PENDING: usize = 10_000;
+
+/// Job label of the series when none is configured.
+pub const DEFAULT_JOB: &str = "netmon";
+
+/// Wait before retrying after the first failure, doubled after each.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/*
+  Every poll sends the series /metrics serves, as a scrape would collect
+  them, labelled with job="netmon" and instance="<hostname>" along with the
+  configured labels:
+
+    netmon_device_online{mac="dc:a6:32:a3:48:b1",alias="Living room TV",iface="br-lan"} 1
+    netmon_device_last_seen_timestamp_seconds{mac=...} 1791953775
+    netmon_neighbors_total{nud_state="REACHABLE"} 12
+    netmon_poll_duration_seconds 0.042
+    netmon_parse_errors_total 0
+
+  in a remote write 1.0 WriteRequest, a protobuf message compressed with
+  snappy's block format:
+
+    WriteRequest { repeated TimeSeries timeseries = 1; }
+    TimeSeries   { repeated Label labels = 1; repeated Sample samples = 2; }
+    Label        { string name = 1; string value = 2; }
+    Sample       { double value = 1; int64 timestamp = 2; }
+
+  Samples are timestamped in milliseconds with the time of their poll, so
+  those held during an outage keep theirs once sent.
+*/
+
+/// A sample waiting to be sent.
+#[derive(Debug, Clone, PartialEq)]
+struct PendingSample {
+  /// Labels of the series, __name__ included, sorted by name.
+  labels: Vec<(String, String)>,
+  value: f64,
+  timestamp_ms: i64,
+}
+
+///
+/// Sink pushing the devices' presence and the neighbor table's state to a
+/// Prometheus remote write endpoint, e.g. of VictoriaMetrics or Mimir, for
+/// routers Prometheus can't scrape.
+///
+/// Samples the endpoint can't be reached for are kept, and retried oldest
+/// first, waiting twice as long after each failure, up to 5 minutes, dropping
+/// the oldest samples past the pending limit. Batches the endpoint rejects
+/// with a client error, other than 429, are dropped, as retrying them won't
+/// help.
+///
+#[derive(Debug)]
+pub struct RemoteWriteSink {
+  url: String,
+  bearer_token: Option<String>,
+  basic_auth: Option<(String, String)>,
+  headers: BTreeMap<String, String>,
+  ca_file: Option<PathBuf>,
+  labels: BTreeMap<String, String>,
+  batch_size: usize,
+  max_pending: usize,
+  anonymizer: Option<Anonymizer>,
+  pending: VecDeque<PendingSample>,
+  backoff: Duration,
+  retry_at: Option<Instant>,
+}
+
+impl RemoteWriteSink {
+  ///
+  /// Creates a sink, without connecting yet.
+  ///
+  /// Args:
+  ///  - url: URL of the remote write endpoint, e.g.
+  ///    "https://mimir.example.com/api/v1/push".
+  ///
+  pub fn new(url: &str) -> Self {
+    let labels = BTreeMap::from([
+      ("job".to_string(), DEFAULT_JOB.to_string()),
+      ("instance".to_string(), hostname()),
+    ]);
+    RemoteWriteSink {
+      url: url.to_string(),
+      bearer_token: None,
+      basic_auth: None,
+      headers: BTreeMap::new(),
+      ca_file: None,
+      labels,
+      batch_size: DEFAULT_BATCH_SIZE,
+      max_pending: DEFAULT_MAX_PENDING,
+      anonymizer: None,
+      pending: VecDeque::new(),
+      backoff: MIN_BACKOFF,
+      retry_at: None,
+    }
+  }
+
+  /// Authenticates with a bearer token.
+  pub fn bearer_token(mut self, token: &str) -> Self {
+    self.bearer_token = Some(token.to_string());
+    self
+  }
+
+  /// Authenticates with a user name and password (Basic).
+  pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+    self.basic_auth = Some((username.to_string(), password.to_string()));
+    self
+  }
+
+  /// Headers of every request, e.g. Mimir's X-Scope-OrgID.
+  pub fn headers(mut self, headers: BTreeMap<String, String>) -> Self {
+    self.headers = headers;
+    self
+  }
+
+  /// CA certificates to verify an https URL with, instead of the system's.
+  pub fn ca_file(mut self, ca_file: &Path) -> Self {
+    self.ca_file = Some(ca_file.to_path_buf());
+    self
+  }
+
+  /// Labels of every series, on top of, or replacing, job and instance.
+  pub fn labels(mut self, labels: BTreeMap<String, String>) -> Self {
+    self.labels.extend(labels);
+    self
+  }
+
+  /// Samples sent at once, at least one.
+  pub fn batch_size(mut self, batch_size: usize) -> Self {
+    self.batch_size = batch_size.max(1);
+    self
+  }
+
+  /// Samples held while the endpoint is unreachable, past which the oldest
+  /// are dropped.
+  pub fn max_pending(mut self, max_pending: usize) -> Self {
+    self.max_pending = max_pending;
+    self
+  }
+
+  /// Hashes the MAC addresses and names labelling the devices' series.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// The samples of a poll.
+  fn samples(&self, report: &PollReport) -> Vec<PendingSample> {
+    let timestamp_ms = report
+      .snapshot
+      .taken_at
+      .duration_since(UNIX_EPOCH)
+      .map(|v| v.as_millis() as i64)
+      .unwrap_or_default();
+    let sample = |name: &str, labels: &[(&str, &str)], value: f64| {
+      let mut all: BTreeMap<String, String> = self.labels.clone();
+      for (key, value) in labels {
+        all.insert(key.to_string(), value.to_string());
+      }
+      all.insert("__name__".to_string(), name.to_string());
+      PendingSample {
+        labels: all.into_iter().filter(|(_, v)| !v.is_empty()).collect(),
+        value,
+        timestamp_ms,
+      }
+    };
+
+    let mut samples = Vec::new();
+    for device in device_samples(report, self.anonymizer.as_ref()) {
+      let labels = [
+        ("mac", device.mac.as_str()),
+        ("alias", device.name.as_str()),
+        ("iface", device.iface.as_str()),
+      ];
+      samples.push(sample(
+        "netmon_device_online",
+        &labels,
+        device.online as u8 as f64,
+      ));
+      samples.push(sample(
+        "netmon_device_last_seen_timestamp_seconds",
+        &labels,
+        unix_secs(device.last_seen) as f64,
+      ));
+    }
+    for (nud_state, count) in neighbor_counts(report) {
+      samples.push(sample(
+        "netmon_neighbors_total",
+        &[("nud_state", &nud_state)],
+        count as f64,
+      ));
+    }
+    samples.push(sample(
+      "netmon_poll_duration_seconds",
+      &[],
+      report.duration.as_secs_f64(),
+    ));
+    samples.push(sample(
+      "netmon_parse_errors_total",
+      &[],
+      counters::parse_errors() as f64,
+    ));
+    samples
+  }
+
+  /// Sends a batch of samples, returning the endpoint's response.
+  fn send(&self, samples: &[PendingSample]) -> Result<HttpResponse> {
+    let body = snap::raw::Encoder::new()
+      .compress_vec(&write_request(samples))
+      .map_err(|e| Error::msg(format!("Failed to compress the samples: {}", e)))?;
+    // curl can't read a binary body off its config, so it's handed a file.
+    let path = std::env::temp_dir().join(format!("netmon-remote-write.{}", std::process::id()));
+    std::fs::OpenOptions::new()
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .mode(0o600)
+      .open(&path)
+      .and_then(|mut file| file.write_all(&body))
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", path.display(), e)))?;
+
+    let mut request = HttpRequest::post(&self.url)
+      .header("Content-Type", "application/x-protobuf")
+      .header("Content-Encoding", "snappy")
+      .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+      .body_file(&path);
+    for (name, value) in &self.headers {
+      request = request.header(name, value);
+    }
+    if let Some(token) = &self.bearer_token {
+      request = request.header("Authorization", &format!("Bearer {}", token));
+    }
+    if let Some((username, password)) = &self.basic_auth {
+      request = request.basic_auth(username, password);
+    }
+    if let Some(ca_file) = &self.ca_file {
+      request = request.ca_file(ca_file);
+    }
+    let response = request.send();
+    let _ = std::fs::remove_file(&path);
+    response
+  }
+
+  ///
+  /// Sends the pending samples, a batch at a time, unless waiting to retry.
+  ///
+  /// Returns:
+  ///  Result reflecting whether every pending sample was sent, failing if a
+  ///  batch was rejected, then dropped, or has to be retried.
+  ///
+  fn write_pending(&mut self) -> Result<()> {
+    if let Some(retry_at) = self.retry_at {
+      let wait = retry_at.saturating_duration_since(Instant::now());
+      if !wait.is_zero() {
+        return Err(Error::msg(format!(
+          "The remote write endpoint is unreachable, retrying in {}",
+          humantime::format_duration(Duration::from_secs(wait.as_secs().max(1)))
+        )));
+      }
+    }
+
+    let retried = self.retry_at.is_some();
+    while !self.pending.is_empty() {
+      let count = self.pending.len().min(self.batch_size);
+      let batch: Vec<PendingSample> = self.pending.iter().take(count).cloned().collect();
+      let err = match self.send(&batch) {
+        Ok(response) if response.is_success() => {
+          self.pending.drain(..count);
+          continue;
+        }
+        // Samples the endpoint refuses, e.g. out of order, which retrying won't fix.
+        Ok(response) if (400..500).contains(&response.status) && response.status != 429 => {
+          self.pending.drain(..count);
+          return Err(Error::msg(format!(
+            "{} rejected {} sample(s) ({}), dropping them: {}",
+            redact(&self.url),
+            count,
+            response.status,
+            response.body.trim()
+          )));
+        }
+        Ok(response) => Error::msg(format!(
+          "{} answered {}: {}",
+          redact(&self.url),
+          response.status,
+          response.body.trim()
+        )),
+        Err(err) => err,
+      };
+      self.retry_at = Some(Instant::now() + self.backoff);
+      self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+      return Err(err);
+    }
+    if retried {
+      info!("The remote write endpoint is reachable again");
+    }
+    self.backoff = MIN_BACKOFF;
+    self.retry_at = None;
+    Ok(())
+  }
+}
+
+impl Sink for RemoteWriteSink {
+  fn name(&self) -> &str {
+    "remote_write"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    self.pending.extend(self.samples(report));
+    if self.pending.len() > self.max_pending {
+      let dropped = self.pending.len() - self.max_pending;
+      self.pending.drain(..dropped);
+      warn!(
+        "Dropping the {} oldest sample(s) waiting for the remote write endpoint",
+        dropped
+      );
+    }
+    self.write_pending()
+  }
+
+  fn needs_thread(&self) -> bool {
+    true
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    if self.pending.is_empty() {
+      return Ok(());
+    }
+    // A last attempt, even while waiting to retry.
+    self.retry_at = None;
+    let pending = self.pending.len();
+    self.write_pending().map_err(|e| {
+      Error::msg(format!(
+        "Dropping {} sample(s) for the remote write endpoint: {}",
+        pending, e
+      ))
+    })
+  }
+}
+
+/// Encodes samples as a WriteRequest, a series per set of labels.
+fn write_request(samples: &[PendingSample]) -> Vec<u8> {
+  let mut series: BTreeMap<&[(String, String)], Vec<&PendingSample>> = BTreeMap::new();
+  for sample in samples {
+    series.entry(&sample.labels).or_default().push(sample);
+  }
+
+  let mut request = Vec::new();
+  for (labels, samples) in series {
+    let mut timeseries = Vec::new();
+    for (name, value) in labels {
+      let mut label = Vec::new();
+      push_bytes(&mut label, 1, name.as_bytes());
+      push_bytes(&mut label, 2, value.as_bytes());
+      push_bytes(&mut timeseries, 1, &label);
+    }
+    for sample in samples {
+      let mut encoded = Vec::new();
+      push_key(&mut encoded, 1, 1);
+      encoded.extend_from_slice(&sample.value.to_le_bytes());
+      push_key(&mut encoded, 2, 0);
+      push_varint(&mut encoded, sample.timestamp_ms as u64);
+      push_bytes(&mut timeseries, 2, &encoded);
+    }
+    push_bytes(&mut request, 1, &timeseries);
+  }
+  request
+}
+
+/// Appends a length-delimited field.
+fn push_bytes(out: &mut Vec<u8>, field: u32, value: &[u8]) {
+  push_key(out, field, 2);
+  push_varint(out, value.len() as u64);
+  out.extend_from_slice(value);
+}
+
+/// Appends the key of a field, its number and wire type.
+fn push_key(out: &mut Vec<u8>, field: u32, wire_type: u8) {
+  push_varint(out, ((field as u64) << 3) | wire_type as u64);
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+  while value >= 0x80 {
+    out.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  out.push(value as u8);
+}
@@ -0,0 +1,158 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/// Interval polled on right after a change, when none is configured.
+pub const DEFAULT_FAST_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long polls stay fast after a change, when none is configured.
+pub const DEFAULT_FAST_WINDOW: Duration = Duration::from_secs(60);
+
+/// Longest interval a stable table backs off to, when none is configured.
+pub const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Largest jitter accepted, as a percentage of the delay.
+pub const MAX_JITTER_PERCENT: u32 = 50;
+
+/*
+  With a 30s interval, fast polls every 5s for 1m after a change, and a 5m
+  cap, a change followed by a quiet table polls:
+
+    change, 5s, 5s, ... (1m), 30s, 1m, 2m, 4m, 5m, 5m, ...
+
+  Each delay is then moved by up to the jitter, e.g. 30s ± 10% polls after
+  27s to 33s.
+*/
+
+/// Adaptive polling settings, speeding up after changes and backing off
+/// while the table is stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptivePolling {
+  pub fast_interval: Duration,
+  pub fast_window: Duration,
+  pub max_interval: Duration,
+}
+
+impl Default for AdaptivePolling {
+  fn default() -> Self {
+    AdaptivePolling {
+      fast_interval: DEFAULT_FAST_INTERVAL,
+      fast_window: DEFAULT_FAST_WINDOW,
+      max_interval: DEFAULT_MAX_INTERVAL,
+    }
+  }
+}
+
+/// A random fraction in [0, 1), from the process' random hashing keys.
+fn random_fraction() -> f64 {
+  let mut hasher = RandomState::new().build_hasher();
+  hasher.write_u64(0);
+  (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+///
+/// Decides how long to wait before each poll. Without adaptive polling, the
+/// delay is the interval; with it, polls speed up to the fast interval for a
+/// while after a change, then double their delay after every stable poll up
+/// to the max interval. Either way, the delay is jittered so routers (and
+/// collectors) started together don't poll in lockstep.
+///
+/// ```
+/// use openwrt_netmon::daemon::schedule::{AdaptivePolling, Schedule};
+/// use std::time::{Duration, Instant};
+///
+/// let adaptive = AdaptivePolling {
+///   fast_interval: Duration::from_secs(5),
+///   fast_window: Duration::from_secs(10),
+///   max_interval: Duration::from_secs(60),
+/// };
+/// let mut schedule = Schedule::new(Duration::from_secs(30)).adaptive(Some(adaptive));
+/// let now = Instant::now();
+///
+/// assert_eq!(schedule.next_delay(true, now), Duration::from_secs(5));
+/// assert_eq!(schedule.next_delay(false, now + Duration::from_secs(5)), Duration::from_secs(5));
+/// assert_eq!(schedule.next_delay(false, now + Duration::from_secs(10)), Duration::from_secs(30));
+/// assert_eq!(schedule.next_delay(false, now + Duration::from_secs(40)), Duration::from_secs(60));
+/// assert_eq!(schedule.next_delay(false, now + Duration::from_secs(100)), Duration::from_secs(60));
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct Schedule {
+  interval: Duration,
+  adaptive: Option<AdaptivePolling>,
+  jitter: f64,
+  /// Delay of the next stable poll, while backing off.
+  backoff: Duration,
+  fast_until: Option<Instant>,
+}
+
+impl Schedule {
+  pub fn new(interval: Duration) -> Self {
+    Schedule {
+      interval,
+      adaptive: None,
+      jitter: 0.0,
+      backoff: interval,
+      fast_until: None,
+    }
+  }
+
+  /// Adaptive polling settings, polling on the interval only if None.
+  pub fn adaptive(mut self, adaptive: Option<AdaptivePolling>) -> Self {
+    self.adaptive = adaptive;
+    self
+  }
+
+  /// Largest share of the delay it's moved by, as a percentage up to 50.
+  pub fn jitter_percent(mut self, jitter_percent: u32) -> Self {
+    self.jitter = jitter_percent.min(MAX_JITTER_PERCENT) as f64 / 100.0;
+    self
+  }
+
+  /// Longest delay between two polls, jitter included.
+  pub fn max_delay(&self) -> Duration {
+    let max = match &self.adaptive {
+      Some(adaptive) => adaptive.max_interval.max(self.interval),
+      None => self.interval,
+    };
+    max.mul_f64(1.0 + self.jitter)
+  }
+
+  ///
+  /// Decides the delay until the next poll.
+  ///
+  /// Args:
+  ///  - changed: Whether the latest poll found changes.
+  ///  - now: When the latest poll started.
+  ///
+  /// Returns:
+  ///  The delay, jittered.
+  ///
+  pub fn next_delay(&mut self, changed: bool, now: Instant) -> Duration {
+    let delay = match &self.adaptive {
+      None => self.interval,
+      Some(adaptive) => {
+        if changed {
+          self.fast_until = Some(now + adaptive.fast_window);
+        }
+        match self.fast_until {
+          Some(until) if now < until => {
+            self.backoff = self.interval;
+            adaptive.fast_interval.min(self.interval)
+          }
+          _ => {
+            self.fast_until = None;
+            let delay = self.backoff;
+            self.backoff = (self.backoff * 2).min(adaptive.max_interval.max(self.interval));
+            delay
+          }
+        }
+      }
+    };
+
+    match self.jitter > 0.0 {
+      true => delay.mul_f64(1.0 + self.jitter * (random_fraction() * 2.0 - 1.0)),
+      false => delay,
+    }
+  }
+}
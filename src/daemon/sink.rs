@@ -0,0 +1,113 @@
+use super::PollReport;
+use crate::neighbors::MacAddr;
+use anyhow::Result;
+use log::info;
+
+///
+/// Downstream consumer of the daemon's polls, e.g. a metrics exporter, a
+/// database, or an alerting pipeline.
+///
+/// Each sink runs on its own worker thread (see SinkWorker), hence Send.
+///
+pub trait Sink: Send {
+  /// Name used when logging the sink's failures.
+  fn name(&self) -> &str;
+
+  ///
+  /// Handles the result of a single poll.
+  ///
+  /// Args:
+  ///  - report: Snapshot, changes, and device state of the poll.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the report was handled.
+  ///
+  fn publish(&mut self, report: &PollReport) -> Result<()>;
+
+  /// Whether the sink blocks for long, e.g. on the network, so it gets its
+  /// own thread even where sinks otherwise run on the polling thread.
+  fn needs_thread(&self) -> bool {
+    false
+  }
+
+  ///
+  /// Writes out anything the sink buffered, called once the daemon stops.
+  ///
+  /// Returns:
+  ///  Result reflecting whether everything was written.
+  ///
+  fn flush(&mut self) -> Result<()> {
+    Ok(())
+  }
+}
+
+/// Logs every change between polls.
+#[derive(Debug, Default)]
+pub struct LogSink;
+
+impl Sink for LogSink {
+  fn name(&self) -> &str {
+    "log"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let diff = &report.diff;
+    for device in &diff.joined {
+      info!(
+        "device.mac":% = device.mac_addr,
+        "device.iface" = device.iface.as_str(),
+        "event.kind" = "joined";
+        "{} joined on {} {:?}",
+        label(report, &device.mac_addr), device.iface, device.ips
+      );
+    }
+    for device in &diff.left {
+      info!(
+        "device.mac":% = device.mac_addr,
+        "device.iface" = device.iface.as_str(),
+        "event.kind" = "left";
+        "{} left {}", label(report, &device.mac_addr), device.iface
+      );
+    }
+    for change in &diff.ip_changed {
+      info!(
+        "device.mac":% = change.mac_addr, "event.kind" = "ip_changed";
+        "{} addresses +{:?} -{:?}",
+        label(report, &change.mac_addr), change.added, change.removed
+      );
+    }
+    for change in &diff.mac_changed {
+      info!(
+        "device.ip":% = change.ip, "event.kind" = "mac_changed";
+        "{} moved {} -> {}",
+        change.ip,
+        optional_label(report, change.old_mac_addr),
+        optional_label(report, change.new_mac_addr)
+      );
+    }
+    for change in &diff.state_changed {
+      info!(
+        "device.ip":% = change.ip, "event.kind" = "state_changed";
+        "{} {:?} -> {:?}",
+        change.ip, change.old_state, change.new_state
+      );
+    }
+    Ok(())
+  }
+}
+
+/// Name of a device in the log, its MAC address if it has none.
+fn label(report: &PollReport, mac_addr: &MacAddr) -> String {
+  match report.name_of(mac_addr) {
+    Some(name) => name.to_string(),
+    None => mac_addr.to_string(),
+  }
+}
+
+/// Name of a device that may be unknown, "none" if so.
+fn optional_label(report: &PollReport, mac_addr: Option<MacAddr>) -> String {
+  match mac_addr {
+    Some(mac_addr) => label(report, &mac_addr),
+    None => "none".into(),
+  }
+}
@@ -0,0 +1,613 @@
+use super::metrics::{unix_secs, Metrics};
+use super::{hostname, Health};
+use crate::counters;
+use crate::neighbors::MacAddr;
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Instant;
+
+/// Community the responder answers when none is configured.
+pub const DEFAULT_COMMUNITY: &str = "public";
+
+/// Root of the NETMON-MIB, netSnmpPlaypen.1, as netmon has no enterprise number.
+pub const NETMON_MIB: &[u32] = &[1, 3, 6, 1, 4, 1, 8072, 9999, 1];
+
+/// The system group of SNMPv2-MIB.
+const SYSTEM: &[u32] = &[1, 3, 6, 1, 2, 1, 1];
+
+/// Largest response, keeping clear of fragmentation on a 1500 MTU.
+const MAX_MESSAGE: usize = 1472;
+
+/// SNMPv2c, as the message's version field holds it.
+const VERSION_2C: i64 = 1;
+
+/// Tags of the PDUs.
+const GET_REQUEST: u8 = 0xa0;
+const GET_NEXT_REQUEST: u8 = 0xa1;
+const RESPONSE: u8 = 0xa2;
+const SET_REQUEST: u8 = 0xa3;
+const GET_BULK_REQUEST: u8 = 0xa5;
+
+/// Error statuses of a response.
+const TOO_BIG: i64 = 1;
+const NOT_WRITABLE: i64 = 17;
+
+/*
+  The responder answers SNMPv2c get, get-next, and get-bulk requests, with
+  the system group and the NETMON-MIB (files/NETMON-MIB.txt), as of the
+  latest poll:
+
+    SNMPv2-MIB::sysDescr.0                       netmon 0.1.0 on office-ap
+    SNMPv2-MIB::sysObjectID.0                    NETMON-MIB::netmonMIB
+    SNMPv2-MIB::sysUpTime.0                      Timeticks: (360000) 1:00:00.00
+    SNMPv2-MIB::sysName.0                        office-ap
+
+    NETMON-MIB::netmonLive.0                     true(1)
+    NETMON-MIB::netmonReady.0                    true(1)
+    NETMON-MIB::netmonLastPoll.0                 1791953775
+    NETMON-MIB::netmonPollDuration.0             42
+    NETMON-MIB::netmonFailedPolls.0              2
+    NETMON-MIB::netmonParseErrors.0              0
+    NETMON-MIB::netmonDevices.0                  14
+    NETMON-MIB::netmonDevicesOnline.0            12
+
+    NETMON-MIB::netmonDeviceName.220.166.50.163.72.177      Living room TV
+    NETMON-MIB::netmonDeviceIface.220.166.50.163.72.177     br-lan
+    NETMON-MIB::netmonDeviceVendor.220.166.50.163.72.177    Raspberry Pi
+    NETMON-MIB::netmonDeviceOnline.220.166.50.163.72.177    true(1)
+    NETMON-MIB::netmonDeviceLastSeen.220.166.50.163.72.177  1791953775
+    NETMON-MIB::netmonDeviceRxBytes.220.166.50.163.72.177   1024
+    NETMON-MIB::netmonDeviceTxBytes.220.166.50.163.72.177   2048
+    NETMON-MIB::netmonDeviceSignal.220.166.50.163.72.177    -52
+
+    NETMON-MIB::netmonNeighborCount."REACHABLE"  12
+    NETMON-MIB::netmonSinkUp."influxdb"          true(1)
+    NETMON-MIB::netmonSinkDropped."influxdb"     0
+
+  Devices are indexed by their MAC address, neighbors by their NUD state,
+  and sinks by their name. Only wireless stations have traffic and signal
+  columns. Set requests are refused as not writable, and requests with
+  another community, or SNMPv1, are dropped.
+*/
+
+/// A value of a variable binding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+  Integer(i64),
+  OctetString(Vec<u8>),
+  ObjectId(Vec<u32>),
+  Counter32(u32),
+  Gauge32(u32),
+  TimeTicks(u32),
+  Counter64(u64),
+  NoSuchObject,
+  NoSuchInstance,
+  EndOfMibView,
+}
+
+impl Value {
+  fn string(value: &str) -> Self {
+    Value::OctetString(value.as_bytes().to_vec())
+  }
+
+  fn truth(value: bool) -> Self {
+    Value::Integer(match value {
+      true => 1,
+      false => 2,
+    })
+  }
+
+  fn encode(&self, out: &mut Vec<u8>) {
+    match self {
+      Value::Integer(v) => encode_tlv(out, 0x02, &integer_bytes(*v)),
+      Value::OctetString(v) => encode_tlv(out, 0x04, v),
+      Value::ObjectId(v) => encode_tlv(out, 0x06, &oid_bytes(v)),
+      Value::Counter32(v) => encode_tlv(out, 0x41, &unsigned_bytes(u64::from(*v))),
+      Value::Gauge32(v) => encode_tlv(out, 0x42, &unsigned_bytes(u64::from(*v))),
+      Value::TimeTicks(v) => encode_tlv(out, 0x43, &unsigned_bytes(u64::from(*v))),
+      Value::Counter64(v) => encode_tlv(out, 0x46, &unsigned_bytes(*v)),
+      Value::NoSuchObject => encode_tlv(out, 0x80, &[]),
+      Value::NoSuchInstance => encode_tlv(out, 0x81, &[]),
+      Value::EndOfMibView => encode_tlv(out, 0x82, &[]),
+    }
+  }
+}
+
+///
+/// UDP listener answering SNMPv2c requests for the devices the daemon
+/// tracks and its health, for network management systems that only speak
+/// SNMP.
+///
+#[derive(Debug)]
+pub struct SnmpServer {
+  addr: SocketAddr,
+}
+
+impl SnmpServer {
+  ///
+  /// Listens for SNMP requests on the given address.
+  ///
+  /// Args:
+  ///  - addr: Address to listen on, e.g. 0.0.0.0:161.
+  ///  - community: Community requests must come with.
+  ///  - health: Health to report.
+  ///  - metrics: Devices to report, fed as a sink of the daemon.
+  ///
+  /// Returns:
+  ///  Result of the server, listening on a dedicated thread.
+  ///
+  pub fn bind(
+    addr: SocketAddr,
+    community: &str,
+    health: Health,
+    metrics: Metrics,
+  ) -> Result<SnmpServer> {
+    let socket =
+      UdpSocket::bind(addr).map_err(|e| Error::msg(format!("Failed to bind {}: {}", addr, e)))?;
+    let addr = socket.local_addr().unwrap_or(addr);
+    let agent = Agent {
+      community: community.as_bytes().to_vec(),
+      started_at: Instant::now(),
+      health,
+      metrics,
+    };
+
+    std::thread::Builder::new()
+      .name("snmp".to_string())
+      .spawn(move || {
+        let mut buf = vec![0; 65535];
+        loop {
+          let (len, peer) = match socket.recv_from(&mut buf) {
+            Ok(v) => v,
+            Err(err) => {
+              warn!("Failed to receive an SNMP request: {}", err);
+              continue;
+            }
+          };
+          match agent.respond(&buf[..len]) {
+            Ok(Some(response)) => {
+              if let Err(err) = socket.send_to(&response, peer) {
+                debug!("Failed to answer {}: {}", peer, err);
+              }
+            }
+            Ok(None) => {}
+            Err(err) => debug!("Dropped an SNMP request from {}: {}", peer, err),
+          }
+        }
+      })
+      .map_err(|e| Error::msg(format!("Failed to spawn the snmp thread: {}", e)))?;
+
+    Ok(SnmpServer { addr })
+  }
+
+  /// Address the server listens on.
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+}
+
+/// What the responder answers with.
+struct Agent {
+  community: Vec<u8>,
+  started_at: Instant,
+  health: Health,
+  metrics: Metrics,
+}
+
+impl Agent {
+  ///
+  /// Answers a request.
+  ///
+  /// Args:
+  ///  - request: The request's message.
+  ///
+  /// Returns:
+  ///  The response's message, None if the request is to be dropped, or an
+  ///  error if it's malformed.
+  ///
+  fn respond(&self, request: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut message = Reader::new(request).expect(0x30)?;
+    let version = message.integer()?;
+    let community = message.octet_string()?;
+    if version != VERSION_2C {
+      debug!("Dropped an SNMP request of version {}", version);
+      return Ok(None);
+    }
+    if community != self.community.as_slice() {
+      debug!("Dropped an SNMP request with another community");
+      return Ok(None);
+    }
+
+    let (tag, pdu) = message.tlv()?;
+    let mut pdu = Reader::new(pdu);
+    let request_id = pdu.integer()?;
+    let first = pdu.integer()?;
+    let second = pdu.integer()?;
+    let mut bindings = pdu.expect(0x30)?;
+    let mut oids = Vec::new();
+    while !bindings.is_empty() {
+      let mut binding = bindings.expect(0x30)?;
+      oids.push(binding.oid()?);
+    }
+
+    let (error_status, error_index, bindings) = match tag {
+      GET_REQUEST => {
+        let mib = self.mib();
+        let bindings: Vec<_> = oids.into_iter().map(|v| get(&mib, v)).collect();
+        (0, 0, bindings)
+      }
+      GET_NEXT_REQUEST => {
+        let mib = self.mib();
+        let bindings: Vec<_> = oids.iter().map(|v| get_next(&mib, v)).collect();
+        (0, 0, bindings)
+      }
+      GET_BULK_REQUEST => {
+        let non_repeaters = first.clamp(0, oids.len() as i64) as usize;
+        let max_repetitions = second.max(0) as usize;
+        let bindings = get_bulk(&self.mib(), &oids, non_repeaters, max_repetitions);
+        (0, 0, bindings)
+      }
+      SET_REQUEST => {
+        let bindings: Vec<_> = oids.into_iter().map(|v| (v, Value::NoSuchObject)).collect();
+        (NOT_WRITABLE, 1, bindings)
+      }
+      other => {
+        return Err(Error::msg(format!("unsupported PDU type {:#x}", other)));
+      }
+    };
+
+    let encode = |error_status: i64, error_index: i64, bindings: &[(Vec<u32>, Value)]| {
+      let mut inner = Vec::new();
+      encode_tlv(&mut inner, 0x02, &integer_bytes(request_id));
+      encode_tlv(&mut inner, 0x02, &integer_bytes(error_status));
+      encode_tlv(&mut inner, 0x02, &integer_bytes(error_index));
+      encode_tlv(&mut inner, 0x30, &encode_bindings(bindings));
+      let mut message = Vec::new();
+      encode_tlv(&mut message, 0x02, &integer_bytes(version));
+      encode_tlv(&mut message, 0x04, &self.community);
+      encode_tlv(&mut message, RESPONSE, &inner);
+      let mut out = Vec::new();
+      encode_tlv(&mut out, 0x30, &message);
+      out
+    };
+
+    let mut response = encode(error_status, error_index, &bindings);
+    if response.len() > MAX_MESSAGE {
+      // Get-bulk responses are cut short, others can't be.
+      response = match tag {
+        GET_BULK_REQUEST => {
+          let mut len = bindings.len();
+          while len > 0 && response.len() > MAX_MESSAGE {
+            len -= 1;
+            response = encode(0, 0, &bindings[..len]);
+          }
+          response
+        }
+        _ => encode(TOO_BIG, 0, &[]),
+      };
+    }
+    Ok(Some(response))
+  }
+
+  /// Every object the responder serves, sorted by OID.
+  fn mib(&self) -> Vec<(Vec<u32>, Value)> {
+    let oid = |base: &[u32], suffix: &[u32]| [base, suffix].concat();
+    let mut mib = Vec::new();
+
+    let hostname = hostname();
+    let uptime = self.started_at.elapsed().as_millis() / 10;
+    mib.push((
+      oid(SYSTEM, &[1, 0]),
+      Value::string(&format!(
+        "netmon {} on {}",
+        env!("CARGO_PKG_VERSION"),
+        hostname
+      )),
+    ));
+    mib.push((oid(SYSTEM, &[2, 0]), Value::ObjectId(NETMON_MIB.to_vec())));
+    mib.push((oid(SYSTEM, &[3, 0]), Value::TimeTicks(uptime as u32)));
+    mib.push((oid(SYSTEM, &[5, 0]), Value::string(&hostname)));
+
+    let devices = self.metrics.devices();
+    let counters = self.health.counters();
+    let (last_poll, poll_duration) = self.metrics.last_poll().unzip();
+    let online = devices.iter().filter(|v| v.online).count();
+    let health = oid(NETMON_MIB, &[1]);
+    let scalars = [
+      Value::truth(self.health.is_live()),
+      Value::truth(self.health.is_ready()),
+      Value::Gauge32(last_poll.map_or(0, |v| unix_secs(v) as u32)),
+      Value::Gauge32(poll_duration.map_or(0, |v| v.as_millis() as u32)),
+      Value::Counter32(counters.failed_polls as u32),
+      Value::Counter32(counters::parse_errors() as u32),
+      Value::Gauge32(devices.len() as u32),
+      Value::Gauge32(online as u32),
+    ];
+    for (i, value) in scalars.into_iter().enumerate() {
+      mib.push((oid(&health, &[i as u32 + 1, 0]), value));
+    }
+
+    let device_entry = oid(NETMON_MIB, &[2, 1]);
+    for device in &devices {
+      let Ok(mac_addr) = device.mac.parse::<MacAddr>() else {
+        continue;
+      };
+      let index: Vec<u32> = mac_addr.octets().iter().map(|v| u32::from(*v)).collect();
+      let columns = [
+        (2, Some(Value::string(&device.name))),
+        (3, Some(Value::string(&device.iface))),
+        (4, Some(Value::string(device.vendor.unwrap_or_default()))),
+        (5, Some(Value::truth(device.online))),
+        (6, Some(Value::Gauge32(unix_secs(device.last_seen) as u32))),
+        (7, device.rx_bytes.map(Value::Counter64)),
+        (8, device.tx_bytes.map(Value::Counter64)),
+        (9, device.signal_dbm.map(|v| Value::Integer(v.into()))),
+      ];
+      for (column, value) in columns {
+        if let Some(value) = value {
+          mib.push((
+            oid(&device_entry, &[[column].as_slice(), &index].concat()),
+            value,
+          ));
+        }
+      }
+    }
+
+    let neighbor_entry = oid(NETMON_MIB, &[3, 1]);
+    for (nud_state, count) in self.metrics.neighbors() {
+      let index = string_index(&nud_state);
+      mib.push((
+        oid(&neighbor_entry, &[[2].as_slice(), &index].concat()),
+        Value::Gauge32(count as u32),
+      ));
+    }
+
+    let sink_entry = oid(NETMON_MIB, &[4, 1]);
+    for (sink, (up, dropped)) in &counters.sinks {
+      let index = string_index(sink);
+      mib.push((
+        oid(&sink_entry, &[[2].as_slice(), &index].concat()),
+        Value::truth(*up),
+      ));
+      mib.push((
+        oid(&sink_entry, &[[3].as_slice(), &index].concat()),
+        Value::Counter32(*dropped as u32),
+      ));
+    }
+
+    mib.sort_by(|a, b| a.0.cmp(&b.0));
+    mib
+  }
+}
+
+/// The instance of an object, or why there's none.
+fn get(mib: &[(Vec<u32>, Value)], oid: Vec<u32>) -> (Vec<u32>, Value) {
+  match mib.binary_search_by(|v| v.0.cmp(&oid)) {
+    Ok(i) => (oid, mib[i].1.clone()),
+    Err(_) => {
+      // Instances of a known object are one sub-identifier longer than the
+      // object, e.g. sysName.0, but for the tables' multi-part indexes.
+      let known = !oid.is_empty() && mib.iter().any(|v| v.0.starts_with(&oid[..oid.len() - 1]));
+      match known {
+        true => (oid, Value::NoSuchInstance),
+        false => (oid, Value::NoSuchObject),
+      }
+    }
+  }
+}
+
+/// The first instance after an OID, in lexicographic order.
+fn get_next(mib: &[(Vec<u32>, Value)], oid: &[u32]) -> (Vec<u32>, Value) {
+  let i = mib.partition_point(|v| v.0.as_slice() <= oid);
+  match mib.get(i) {
+    Some((oid, value)) => (oid.clone(), value.clone()),
+    None => (oid.to_vec(), Value::EndOfMibView),
+  }
+}
+
+///
+/// Answers a get-bulk request, as in RFC 3416 4.2.3.
+///
+/// Args:
+///  - mib: Every object, sorted by OID.
+///  - oids: The request's OIDs.
+///  - non_repeaters: Leading OIDs answered once, as by get-next.
+///  - max_repetitions: Times the other OIDs are walked on for.
+///
+/// Returns:
+///  The variable bindings, the repeated ones interleaved.
+///
+fn get_bulk(
+  mib: &[(Vec<u32>, Value)],
+  oids: &[Vec<u32>],
+  non_repeaters: usize,
+  max_repetitions: usize,
+) -> Vec<(Vec<u32>, Value)> {
+  let (non_repeating, repeating) = oids.split_at(non_repeaters);
+  let mut bindings: Vec<_> = non_repeating.iter().map(|v| get_next(mib, v)).collect();
+  let mut cursors: Vec<Vec<u32>> = repeating.to_vec();
+  for _ in 0..max_repetitions {
+    if cursors.is_empty() {
+      break;
+    }
+    let mut ended = true;
+    for cursor in cursors.iter_mut() {
+      let (oid, value) = get_next(mib, cursor);
+      ended &= value == Value::EndOfMibView;
+      *cursor = oid.clone();
+      bindings.push((oid, value));
+    }
+    if ended {
+      break;
+    }
+  }
+  bindings
+}
+
+/// The index of a table by a variable-length string: its length, then its bytes.
+fn string_index(value: &str) -> Vec<u32> {
+  std::iter::once(value.len() as u32)
+    .chain(value.bytes().map(u32::from))
+    .collect()
+}
+
+fn encode_bindings(bindings: &[(Vec<u32>, Value)]) -> Vec<u8> {
+  let mut out = Vec::new();
+  for (oid, value) in bindings {
+    let mut binding = Vec::new();
+    encode_tlv(&mut binding, 0x06, &oid_bytes(oid));
+    value.encode(&mut binding);
+    encode_tlv(&mut out, 0x30, &binding);
+  }
+  out
+}
+
+/// Appends a BER type, length, and value.
+fn encode_tlv(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+  out.push(tag);
+  match value.len() {
+    len @ 0..=0x7f => out.push(len as u8),
+    len => {
+      let bytes = len.to_be_bytes();
+      let skip = bytes.iter().take_while(|v| **v == 0).count();
+      out.push(0x80 | (bytes.len() - skip) as u8);
+      out.extend_from_slice(&bytes[skip..]);
+    }
+  }
+  out.extend_from_slice(value);
+}
+
+/// A signed integer in as few two's complement bytes as hold it.
+fn integer_bytes(value: i64) -> Vec<u8> {
+  let bytes = value.to_be_bytes();
+  let mut skip = 0;
+  while skip < 7 {
+    let redundant = (bytes[skip] == 0x00 && bytes[skip + 1] & 0x80 == 0)
+      || (bytes[skip] == 0xff && bytes[skip + 1] & 0x80 != 0);
+    if !redundant {
+      break;
+    }
+    skip += 1;
+  }
+  bytes[skip..].to_vec()
+}
+
+/// An unsigned integer, with a leading zero byte if its top bit is set.
+fn unsigned_bytes(value: u64) -> Vec<u8> {
+  let bytes = value.to_be_bytes();
+  let skip = bytes.iter().take(7).take_while(|v| **v == 0).count();
+  let mut out = Vec::with_capacity(9);
+  if bytes[skip] & 0x80 != 0 {
+    out.push(0);
+  }
+  out.extend_from_slice(&bytes[skip..]);
+  out
+}
+
+/// An OID, its first two sub-identifiers combined, the others in base 128.
+fn oid_bytes(oid: &[u32]) -> Vec<u8> {
+  let mut out = Vec::new();
+  let (first, rest) = match oid {
+    [a, b, rest @ ..] => (a * 40 + b, rest),
+    [a] => (a * 40, &[][..]),
+    [] => return out,
+  };
+  for sub_id in std::iter::once(&first).chain(rest) {
+    let mut chunk = Vec::new();
+    let mut v = *sub_id;
+    chunk.push((v & 0x7f) as u8);
+    v >>= 7;
+    while v > 0 {
+      chunk.push(0x80 | (v & 0x7f) as u8);
+      v >>= 7;
+    }
+    out.extend(chunk.iter().rev());
+  }
+  out
+}
+
+/// Reads BER values out of a message.
+struct Reader<'a> {
+  buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn new(buf: &'a [u8]) -> Self {
+    Reader { buf }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.buf.is_empty()
+  }
+
+  /// Reads the next value, as its type and content.
+  fn tlv(&mut self) -> Result<(u8, &'a [u8])> {
+    let truncated = || Error::msg("truncated message");
+    let (&tag, rest) = self.buf.split_first().ok_or_else(truncated)?;
+    let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+    let len = match first {
+      0..=0x7f => first as usize,
+      0x81..=0x84 => {
+        let count = (first & 0x7f) as usize;
+        let bytes = rest.get(..count).ok_or_else(truncated)?;
+        rest = &rest[count..];
+        bytes.iter().fold(0, |len, v| (len << 8) | *v as usize)
+      }
+      _ => return Err(Error::msg("unsupported length")),
+    };
+    let value = rest.get(..len).ok_or_else(truncated)?;
+    self.buf = &rest[len..];
+    Ok((tag, value))
+  }
+
+  /// Reads the next value, which must be of the given type.
+  fn expect(&mut self, tag: u8) -> Result<Reader<'a>> {
+    match self.tlv()? {
+      (found, value) if found == tag => Ok(Reader::new(value)),
+      (found, _) => Err(Error::msg(format!(
+        "expected type {:#x}, got {:#x}",
+        tag, found
+      ))),
+    }
+  }
+
+  fn integer(&mut self) -> Result<i64> {
+    let value = self.expect(0x02)?.buf;
+    if value.is_empty() || value.len() > 8 {
+      return Err(Error::msg("invalid integer"));
+    }
+    let sign = match value[0] & 0x80 != 0 {
+      true => -1i64,
+      false => 0,
+    };
+    Ok(value.iter().fold(sign, |v, b| (v << 8) | i64::from(*b)))
+  }
+
+  fn octet_string(&mut self) -> Result<&'a [u8]> {
+    Ok(self.expect(0x04)?.buf)
+  }
+
+  fn oid(&mut self) -> Result<Vec<u32>> {
+    let value = self.expect(0x06)?.buf;
+    let mut sub_ids = Vec::new();
+    let mut sub_id: u32 = 0;
+    for byte in value {
+      sub_id = sub_id
+        .checked_mul(128)
+        .ok_or_else(|| Error::msg("invalid OID"))?
+        | u32::from(byte & 0x7f);
+      if byte & 0x80 == 0 {
+        match sub_ids.is_empty() {
+          true => sub_ids.extend([(sub_id / 40).min(2), sub_id - (sub_id / 40).min(2) * 40]),
+          false => sub_ids.push(sub_id),
+        }
+        sub_id = 0;
+      }
+    }
+    if value.is_empty() || value.last().is_some_and(|v| v & 0x80 != 0) {
+      return Err(Error::msg("invalid OID"));
+    }
+    Ok(sub_ids)
+  }
+}
@@ -0,0 +1,206 @@
+use super::Control;
+use crate::neighbors::MacAddr;
+use crate::storage::{HistoryQuery, MemoryStorage, RecentHistory};
+use anyhow::{Error, Result};
+use log::{debug, info, warn};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Control socket the daemon listens on when none is configured.
+pub const DEFAULT_SOCKET_PATH: &str = "/var/run/netmon/netmon.sock";
+
+/// How long a client gets to send its command.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/*
+  The control socket takes a single newline terminated command per connection
+  and answers with a single line:
+
+    reload         -> ok        Reloads the config, as SIGHUP does.
+    shutdown       -> ok        Stops the daemon, as SIGTERM does.
+    ping           -> pong
+    <other>        -> error: unknown command '<other>'
+
+  Except for history, answered with a line per record of the device's recent
+  history, see storage::RecentHistory, until the daemon closes the connection:
+
+    history <mac>  -> event\t1791946800\tjoined\t...
+*/
+
+///
+/// Unix socket taking commands for a running daemon. The socket file is
+/// removed once dropped.
+///
+#[derive(Debug)]
+pub struct ControlSocket {
+  path: PathBuf,
+}
+
+/// Recent history of a device, as the reply to a history command.
+fn history_reply(mac_addr: &str, history: Option<&MemoryStorage>) -> String {
+  let Some(history) = history else {
+    return "error: the daemon keeps no in-memory history".to_string();
+  };
+  match mac_addr.parse::<MacAddr>() {
+    Ok(mac_addr) => history
+      .recent(&HistoryQuery::new().mac_addr(mac_addr))
+      .to_string(),
+    Err(err) => format!("error: {}", err),
+  }
+}
+
+/// Answers a single client.
+fn handle_client(
+  stream: UnixStream,
+  control: &Control,
+  history: Option<&MemoryStorage>,
+) -> std::io::Result<()> {
+  stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+  let mut command = String::new();
+  BufReader::new(&stream).take(256).read_line(&mut command)?;
+
+  let command = command.trim();
+  debug!("Control socket command '{}'", command);
+  let reply = match command {
+    "reload" => {
+      info!("Reloading the config, as requested over the control socket");
+      control.request_reload();
+      "ok".to_string()
+    }
+    "shutdown" => {
+      info!("Shutting down, as requested over the control socket");
+      control.cancel();
+      "ok".to_string()
+    }
+    "ping" => "pong".to_string(),
+    other => match other.strip_prefix("history ") {
+      Some(mac_addr) => return write!(&stream, "{}", history_reply(mac_addr.trim(), history)),
+      None => format!("error: unknown command '{}'", other),
+    },
+  };
+  writeln!(&stream, "{}", reply)
+}
+
+impl ControlSocket {
+  ///
+  /// Listens for commands on the given path, replacing a stale socket left
+  /// behind by a previous run. Only the owner may connect.
+  ///
+  /// Args:
+  ///  - path: Socket file to create.
+  ///  - control: Control to make the requests through.
+  ///  - history: Recent history to answer history commands from, if kept.
+  ///
+  /// Returns:
+  ///  Result of the socket, listening on a dedicated thread.
+  ///
+  pub fn bind(
+    path: &Path,
+    control: Control,
+    history: Option<MemoryStorage>,
+  ) -> Result<ControlSocket> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+    if UnixStream::connect(path).is_ok() {
+      return Err(Error::msg(format!(
+        "Another daemon is listening on {}",
+        path.display()
+      )));
+    }
+    let _ = std::fs::remove_file(path);
+
+    let listener = UnixListener::bind(path)
+      .map_err(|e| Error::msg(format!("Failed to bind {}: {}", path.display(), e)))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+      .map_err(|e| Error::msg(format!("Failed to restrict {}: {}", path.display(), e)))?;
+
+    std::thread::Builder::new()
+      .name("control".to_string())
+      .spawn(move || {
+        for stream in listener.incoming() {
+          match stream {
+            Ok(stream) => {
+              if let Err(err) = handle_client(stream, &control, history.as_ref()) {
+                debug!("Control socket client failed: {}", err);
+              }
+            }
+            Err(err) => warn!("Failed to accept a control socket client: {}", err),
+          }
+        }
+      })
+      .map_err(|e| Error::msg(format!("Failed to spawn the control thread: {}", e)))?;
+
+    Ok(ControlSocket {
+      path: path.to_path_buf(),
+    })
+  }
+}
+
+impl Drop for ControlSocket {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.path);
+  }
+}
+
+///
+/// Sends a command to a running daemon.
+///
+/// Args:
+///  - path: Daemon's control socket.
+///  - command: Command, e.g. "reload".
+///
+/// Returns:
+///  Result of the daemon's reply.
+///
+pub fn send_command(path: &Path, command: &str) -> Result<String> {
+  let mut stream = UnixStream::connect(path)
+    .map_err(|e| Error::msg(format!("Failed to connect to {}: {}", path.display(), e)))?;
+  stream
+    .set_read_timeout(Some(CLIENT_TIMEOUT))
+    .and_then(|_| writeln!(stream, "{}", command))
+    .map_err(|e| Error::msg(format!("Failed to send '{}': {}", command, e)))?;
+
+  let mut reply = String::new();
+  BufReader::new(&stream)
+    .read_line(&mut reply)
+    .map_err(|e| Error::msg(format!("Failed to read the reply: {}", e)))?;
+
+  let reply = reply.trim().to_string();
+  match reply.strip_prefix("error: ") {
+    Some(err) => Err(Error::msg(err.to_string())),
+    None => Ok(reply),
+  }
+}
+
+///
+/// Reads the recent history of a device from a running daemon.
+///
+/// Args:
+///  - path: Daemon's control socket.
+///  - mac_addr: Device to read the history of.
+///
+/// Returns:
+///  Result of the history, failing if the daemon keeps none.
+///
+pub fn query_history(path: &Path, mac_addr: MacAddr) -> Result<RecentHistory> {
+  let mut stream = UnixStream::connect(path)
+    .map_err(|e| Error::msg(format!("Failed to connect to {}: {}", path.display(), e)))?;
+  stream
+    .set_read_timeout(Some(CLIENT_TIMEOUT))
+    .and_then(|_| writeln!(stream, "history {}", mac_addr))
+    .map_err(|e| Error::msg(format!("Failed to ask for the history: {}", e)))?;
+
+  let mut reply = String::new();
+  stream
+    .read_to_string(&mut reply)
+    .map_err(|e| Error::msg(format!("Failed to read the history: {}", e)))?;
+  match reply.strip_prefix("error: ") {
+    Some(err) => Err(Error::msg(err.trim().to_string())),
+    None => reply.parse(),
+  }
+}
@@ -0,0 +1,112 @@
+use super::{PollReport, Sink};
+use anyhow::{Error, Result};
+use std::path::{Path, PathBuf};
+
+/// Directory the daemon keeps its runtime state in when none is configured.
+pub const DEFAULT_STATE_DIR: &str = "/var/run/netmon";
+
+/// File holding the status of the latest poll.
+pub const STATUS_FILE: &str = "status";
+
+/// File created once the first poll succeeded.
+pub const READY_FILE: &str = "ready";
+
+/*
+  The status file is rewritten after every poll, e.g.:
+
+    pid=1234
+    last_poll=2026-10-14T04:36:15Z
+    neighbors=12
+    devices=9
+    online=7
+*/
+
+/// Writes a file atomically, through a temporary file and a rename.
+fn write_atomic(path: &Path, content: &str) -> Result<()> {
+  let tmp_path = path.with_extension("tmp");
+  std::fs::write(&tmp_path, content)
+    .and_then(|_| std::fs::rename(&tmp_path, path))
+    .map_err(|e| Error::msg(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+///
+/// Keeps the daemon's runtime state, such as /var/run/netmon, for init
+/// scripts and status commands to inspect.
+///
+#[derive(Debug, Clone)]
+pub struct StateDir {
+  path: PathBuf,
+}
+
+impl Default for StateDir {
+  fn default() -> Self {
+    StateDir::new(Path::new(DEFAULT_STATE_DIR))
+  }
+}
+
+impl StateDir {
+  pub fn new(path: &Path) -> Self {
+    StateDir {
+      path: path.to_path_buf(),
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Creates the directory and clears the state of a previous run.
+  pub fn create(&self) -> Result<()> {
+    std::fs::create_dir_all(&self.path)
+      .map_err(|e| Error::msg(format!("Failed to create {}: {}", self.path.display(), e)))?;
+    for file in [STATUS_FILE, READY_FILE] {
+      let _ = std::fs::remove_file(self.path.join(file));
+    }
+    Ok(())
+  }
+
+  /// Marks the daemon as ready.
+  pub fn mark_ready(&self) -> Result<()> {
+    write_atomic(
+      &self.path.join(READY_FILE),
+      &format!("{}\n", std::process::id()),
+    )
+  }
+
+  /// Clears the state, once the daemon stopped.
+  pub fn remove(&self) {
+    for file in [STATUS_FILE, READY_FILE] {
+      let _ = std::fs::remove_file(self.path.join(file));
+    }
+  }
+}
+
+/// Sink rewriting the status file of a state directory after every poll.
+#[derive(Debug, Clone, Default)]
+pub struct StateSink {
+  state_dir: StateDir,
+}
+
+impl StateSink {
+  pub fn new(state_dir: StateDir) -> Self {
+    StateSink { state_dir }
+  }
+}
+
+impl Sink for StateSink {
+  fn name(&self) -> &str {
+    "state"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let status = format!(
+      "pid={}\nlast_poll={}\nneighbors={}\ndevices={}\nonline={}\n",
+      std::process::id(),
+      humantime::format_rfc3339_seconds(report.snapshot.taken_at),
+      report.snapshot.entries.len(),
+      report.devices.len(),
+      report.devices.iter().filter(|v| v.online).count()
+    );
+    write_atomic(&self.state_dir.path().join(STATUS_FILE), &status)
+  }
+}
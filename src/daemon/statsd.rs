@@ -0,0 +1,213 @@
+use super::metrics::neighbor_counts;
+use super::{PollReport, Sink};
+use crate::counters;
+use anyhow::{Error, Result};
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+
+/// Address of the StatsD agent when none is configured.
+pub const DEFAULT_STATSD_ADDRESS: &str = "127.0.0.1:8125";
+
+/// Prefix of the metrics' names when none is configured.
+pub const DEFAULT_PREFIX: &str = "netmon";
+
+/// Largest datagram sent, keeping clear of fragmentation on a 1500 MTU.
+const MAX_DATAGRAM: usize = 1432;
+
+/*
+  Every poll sends the changes since the previous one as counters, and the
+  state of the network as gauges, batched into datagrams:
+
+    netmon.devices.joined:2|c
+    netmon.devices.left:1|c
+    netmon.parse_errors:0|c
+    netmon.poll_duration:42|ms
+    netmon.devices.online:12|g
+    netmon.neighbors.reachable:12|g
+
+  In the DogStatsD format, joins and leaves are counted per interface, and
+  neighbors per NUD state, as tags, along with the static ones:
+
+    netmon.devices.joined:2|c|#iface:br-lan,router:office-ap
+    netmon.neighbors:12|g|#nud_state:reachable,router:office-ap
+*/
+
+///
+/// Sink sending counters of the devices joining and leaving, and gauges of
+/// the network's state, to a StatsD agent over UDP.
+///
+/// Datagrams the agent doesn't receive are lost, as UDP goes, so the sink
+/// only fails if the agent's address doesn't resolve.
+///
+#[derive(Debug)]
+pub struct StatsdSink {
+  address: String,
+  prefix: String,
+  dogstatsd: bool,
+  tags: BTreeMap<String, String>,
+  socket: Option<UdpSocket>,
+  /// Parse errors counted as of the previous poll.
+  parse_errors: u64,
+}
+
+impl StatsdSink {
+  ///
+  /// Creates a sink, without resolving the agent's address yet.
+  ///
+  /// Args:
+  ///  - address: The agent's host and port, e.g. "127.0.0.1:8125".
+  ///
+  pub fn new(address: &str) -> Self {
+    StatsdSink {
+      address: address.to_string(),
+      prefix: DEFAULT_PREFIX.to_string(),
+      dogstatsd: false,
+      tags: BTreeMap::new(),
+      socket: None,
+      parse_errors: counters::parse_errors(),
+    }
+  }
+
+  /// Prefix of the metrics' names, left out if empty.
+  pub fn prefix(mut self, prefix: &str) -> Self {
+    self.prefix = prefix.to_string();
+    self
+  }
+
+  /// Tags the metrics in the DogStatsD format.
+  pub fn dogstatsd(mut self, dogstatsd: bool) -> Self {
+    self.dogstatsd = dogstatsd;
+    self
+  }
+
+  /// Tags given to every metric, in the DogStatsD format only.
+  pub fn tags(mut self, tags: BTreeMap<String, String>) -> Self {
+    self.tags = tags;
+    self
+  }
+
+  /// Formats a metric, with its tags in the DogStatsD format.
+  fn metric(
+    &self,
+    name: &str,
+    value: impl std::fmt::Display,
+    kind: &str,
+    tags: &[(&str, &str)],
+  ) -> String {
+    let mut metric = match self.prefix.is_empty() {
+      true => format!("{}:{}|{}", name, value, kind),
+      false => format!("{}.{}:{}|{}", self.prefix, name, value, kind),
+    };
+    if self.dogstatsd {
+      let tags: Vec<String> = tags
+        .iter()
+        .copied()
+        .chain(self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+        .map(|(k, v)| format!("{}:{}", tag_value(k), tag_value(v)))
+        .collect();
+      if !tags.is_empty() {
+        metric.push_str("|#");
+        metric.push_str(&tags.join(","));
+      }
+    }
+    metric
+  }
+
+  /// The metrics of a poll.
+  fn metrics(&mut self, report: &PollReport) -> Vec<String> {
+    let mut metrics = Vec::new();
+    let presences = [("joined", &report.diff.joined), ("left", &report.diff.left)];
+    for (name, changes) in presences {
+      let name = format!("devices.{}", name);
+      match self.dogstatsd {
+        true => {
+          let mut by_iface: BTreeMap<&str, u64> = BTreeMap::new();
+          for change in changes {
+            *by_iface.entry(&change.iface).or_default() += 1;
+          }
+          for (iface, count) in by_iface {
+            metrics.push(self.metric(&name, count, "c", &[("iface", iface)]));
+          }
+        }
+        false => metrics.push(self.metric(&name, changes.len(), "c", &[])),
+      }
+    }
+
+    let parse_errors = counters::parse_errors();
+    let new_errors = parse_errors.saturating_sub(self.parse_errors);
+    self.parse_errors = parse_errors;
+    metrics.push(self.metric("parse_errors", new_errors, "c", &[]));
+    metrics.push(self.metric("poll_duration", report.duration.as_millis(), "ms", &[]));
+
+    let online = report.tracker.neighbors().filter(|v| v.online).count();
+    metrics.push(self.metric("devices.online", online, "g", &[]));
+    for (nud_state, count) in neighbor_counts(report) {
+      let nud_state = nud_state.to_lowercase();
+      match self.dogstatsd {
+        true => metrics.push(self.metric("neighbors", count, "g", &[("nud_state", &nud_state)])),
+        false => {
+          let name = format!("neighbors.{}", nud_state.replace('|', "_"));
+          metrics.push(self.metric(&name, count, "g", &[]));
+        }
+      }
+    }
+    metrics
+  }
+
+  /// Binds the socket, connected to the agent, if not bound yet.
+  fn connect(&mut self) -> Result<&UdpSocket> {
+    if self.socket.is_none() {
+      let bind = match self.address.starts_with('[') {
+        true => "[::]:0",
+        false => "0.0.0.0:0",
+      };
+      let socket = UdpSocket::bind(bind)
+        .and_then(|socket| socket.connect(&self.address).map(|_| socket))
+        .map_err(|e| Error::msg(format!("Failed to reach StatsD at {}: {}", self.address, e)))?;
+      self.socket = Some(socket);
+    }
+    Ok(self.socket.as_ref().unwrap())
+  }
+}
+
+impl Sink for StatsdSink {
+  fn name(&self) -> &str {
+    "statsd"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let metrics = self.metrics(report);
+    let socket = self.connect()?;
+    for datagram in datagrams(&metrics) {
+      // The agent not listening is reported as a refused send, and isn't
+      // worth failing the poll over.
+      let _ = socket.send(datagram.as_bytes());
+    }
+    Ok(())
+  }
+
+  fn needs_thread(&self) -> bool {
+    // Resolving the agent's hostname may block.
+    true
+  }
+}
+
+/// Replaces what separates tags, or a tag's key from its value, in the DogStatsD format.
+fn tag_value(value: &str) -> String {
+  value.replace([',', '|', '#', ' '], "_")
+}
+
+/// Joins metrics into datagrams, with a metric per line, as few as fit.
+fn datagrams(metrics: &[String]) -> Vec<String> {
+  let mut datagrams: Vec<String> = Vec::new();
+  for metric in metrics {
+    match datagrams.last_mut() {
+      Some(datagram) if datagram.len() + 1 + metric.len() <= MAX_DATAGRAM => {
+        datagram.push('\n');
+        datagram.push_str(metric);
+      }
+      _ => datagrams.push(metric.clone()),
+    }
+  }
+  datagrams
+}
@@ -0,0 +1,477 @@
+use super::http::{encode_component, redact, HttpRequest};
+use super::jsonl::JsonlRecord;
+use super::{hostname, PollReport, Sink};
+use crate::privacy::Anonymizer;
+use crate::storage::history_events;
+use anyhow::{Error, Result};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// Port of the NATS server when none is given.
+pub const DEFAULT_NATS_PORT: u16 = 4222;
+
+/// Subject of the events when none is configured.
+pub const DEFAULT_SUBJECT: &str = "netmon.{hostname}.{event}";
+
+/// Kafka topic of the events when none is configured.
+pub const DEFAULT_TOPIC: &str = "netmon-events";
+
+/// Prefix of the CloudEvents' types, followed by the event.
+pub const CLOUDEVENTS_TYPE_PREFIX: &str = "com.github.ciaxur.netmon.";
+
+/// Longest time connecting to, writing to, or reading from the server may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait before reconnecting after the first failure, doubled after each.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between reconnections.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/*
+  Every change of a poll is published as a message of its own, holding the
+  record the jsonl sink writes:
+
+    {"schema_version":1,"time":"2026-10-14T04:36:15Z","event":"joined","mac":"dc:a6:32:a3:48:b1","iface":"br-lan","new_value":"192.168.0.5","name":"Living room TV"}
+
+  or that record as the data of a CloudEvent, in its structured JSON mode:
+
+    {"specversion":"1.0","id":"office-ap-1791953775000-0","source":"/netmon/office-ap",
+     "type":"com.github.ciaxur.netmon.joined","subject":"dc:a6:32:a3:48:b1",
+     "time":"2026-10-14T04:36:15Z","datacontenttype":"application/json","data":{...}}
+
+  To NATS, over its text protocol, on the subject with "{hostname}" and
+  "{event}" replaced, e.g. netmon.office-ap.joined:
+
+    PUB netmon.office-ap.joined 164
+    {"schema_version":1,...}
+
+  followed by a PING, the server's PONG confirming it processed them. To
+  Kafka, through a REST proxy (Confluent's, or Redpanda's), as its v2 JSON
+  records, keyed by the MAC address so a device's events stay in order:
+
+    POST /topics/netmon-events
+    Content-Type: application/vnd.kafka.json.v2+json
+
+    {"records":[{"key":"dc:a6:32:a3:48:b1","value":{"schema_version":1,...}}]}
+*/
+
+/// Shape of the published events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EventFormat {
+  /// The jsonl sink's records.
+  #[default]
+  Json,
+  /// CloudEvents 1.0, in structured JSON mode, holding the records.
+  Cloudevents,
+}
+
+/// Where the events are streamed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamTarget {
+  /// A NATS server, on a subject per event.
+  Nats {
+    /// Host, and port if not 4222, of the server.
+    address: String,
+    /// Subject, "{hostname}" and "{event}" standing for the router's and the event's.
+    subject: String,
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+  },
+  /// A Kafka topic, through a REST proxy.
+  Kafka {
+    /// Base URL of the REST proxy, e.g. "http://10.0.0.1:8082".
+    url: String,
+    topic: String,
+    /// Headers of every request, e.g. an Authorization.
+    headers: BTreeMap<String, String>,
+    ca_file: Option<PathBuf>,
+  },
+}
+
+/// An event, ready to be published.
+#[derive(Debug, Clone, PartialEq)]
+struct StreamEvent {
+  event: &'static str,
+  mac: Option<String>,
+  payload: Value,
+}
+
+///
+/// Sink publishing every device change to a NATS subject, or a Kafka topic,
+/// as JSON or CloudEvents, for an event pipeline to consume.
+///
+/// The connection to NATS is kept open, and reopened once lost, waiting
+/// twice as long after each failure, up to 5 minutes. Events the server
+/// can't be reached for are dropped.
+///
+#[derive(Debug)]
+pub struct StreamSink {
+  target: StreamTarget,
+  format: EventFormat,
+  hostname: String,
+  anonymizer: Option<Anonymizer>,
+  nats: Option<NatsConnection>,
+  backoff: Duration,
+  retry_at: Option<Instant>,
+}
+
+impl StreamSink {
+  /// Creates a sink, without connecting yet.
+  pub fn new(target: StreamTarget) -> Self {
+    StreamSink {
+      target,
+      format: EventFormat::default(),
+      hostname: hostname(),
+      anonymizer: None,
+      nats: None,
+      backoff: MIN_BACKOFF,
+      retry_at: None,
+    }
+  }
+
+  /// Shape of the published events.
+  pub fn format(mut self, format: EventFormat) -> Self {
+    self.format = format;
+    self
+  }
+
+  /// Hashes the MAC addresses and names of the devices.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// The events of a poll.
+  fn events(&self, report: &PollReport) -> Result<Vec<StreamEvent>> {
+    let millis = report
+      .snapshot
+      .taken_at
+      .duration_since(UNIX_EPOCH)
+      .map(|v| v.as_millis())
+      .unwrap_or_default();
+    let mut events = Vec::new();
+    for (index, event) in history_events(report).into_iter().enumerate() {
+      let name = event.mac_addr.and_then(|v| report.name_of(&v));
+      let (event, name) = match &self.anonymizer {
+        Some(anonymizer) => (anonymizer.event(&event), name.map(|v| anonymizer.name(v))),
+        None => (event, name.map(str::to_string)),
+      };
+      let record = serde_json::to_value(JsonlRecord::new(&event, name.as_deref()))
+        .map_err(|e| Error::msg(format!("Failed to serialize an event: {}", e)))?;
+      let mac = event.mac_addr.map(|v| v.to_string());
+      let payload = match self.format {
+        EventFormat::Json => record,
+        EventFormat::Cloudevents => {
+          let mut cloud_event = json!({
+            "specversion": "1.0",
+            "id": format!("{}-{}-{}", self.hostname, millis, index),
+            "source": format!("/netmon/{}", self.hostname),
+            "type": format!("{}{}", CLOUDEVENTS_TYPE_PREFIX, event.kind.name()),
+            "time": record["time"],
+            "datacontenttype": "application/json",
+          });
+          if let Some(mac) = &mac {
+            cloud_event["subject"] = Value::from(mac.as_str());
+          }
+          cloud_event["data"] = record;
+          cloud_event
+        }
+      };
+      events.push(StreamEvent {
+        event: event.kind.name(),
+        mac,
+        payload,
+      });
+    }
+    Ok(events)
+  }
+
+  /// Connects to NATS if not connected yet.
+  fn connect_nats(&mut self) -> Result<&mut NatsConnection> {
+    if self.nats.is_none() {
+      if let Some(retry_at) = self.retry_at {
+        let wait = retry_at.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+          return Err(Error::msg(format!(
+            "Not connected to NATS, retrying in {}",
+            humantime::format_duration(Duration::from_secs(wait.as_secs().max(1)))
+          )));
+        }
+      }
+      let StreamTarget::Nats {
+        address,
+        username,
+        password,
+        token,
+        ..
+      } = &self.target
+      else {
+        unreachable!("Not a NATS target");
+      };
+      let address = address.clone();
+      let connection = NatsConnection::connect(&address, username, password, token);
+      let connection = connection.inspect_err(|_| self.disconnect())?;
+      info!("Connected to NATS at {}", address);
+      self.backoff = MIN_BACKOFF;
+      self.retry_at = None;
+      self.nats = Some(connection);
+    }
+    Ok(self.nats.as_mut().unwrap())
+  }
+
+  /// Drops the connection, waiting longer before each reconnection.
+  fn disconnect(&mut self) {
+    self.nats = None;
+    self.retry_at = Some(Instant::now() + self.backoff);
+    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+  }
+
+  /// Publishes events to NATS, on the subject of each.
+  fn publish_nats(&mut self, events: &[StreamEvent]) -> Result<()> {
+    let StreamTarget::Nats { subject, .. } = &self.target else {
+      unreachable!("Not a NATS target");
+    };
+    let hostname = self.hostname.replace(['.', ' '], "_");
+    let messages: Vec<(String, String)> = events
+      .iter()
+      .map(|v| {
+        let subject = subject
+          .replace("{hostname}", &hostname)
+          .replace("{event}", v.event);
+        (subject, v.payload.to_string())
+      })
+      .collect();
+    let connection = self.connect_nats()?;
+    connection.publish(&messages).map_err(|e| {
+      self.disconnect();
+      Error::msg(format!("Failed to publish to NATS: {}", e))
+    })
+  }
+
+  /// Produces events to the Kafka topic, in a single request.
+  fn publish_kafka(&self, events: &[StreamEvent]) -> Result<()> {
+    let StreamTarget::Kafka {
+      url,
+      topic,
+      headers,
+      ca_file,
+    } = &self.target
+    else {
+      unreachable!("Not a Kafka target");
+    };
+    let records: Vec<Value> = events
+      .iter()
+      .map(|v| json!({"key": v.mac, "value": v.payload}))
+      .collect();
+    let url = format!(
+      "{}/topics/{}",
+      url.trim_end_matches('/'),
+      encode_component(topic)
+    );
+    let mut request = HttpRequest::post(&url)
+      .header("Content-Type", "application/vnd.kafka.json.v2+json")
+      .header("Accept", "application/vnd.kafka.v2+json")
+      .body(&json!({ "records": records }).to_string());
+    for (name, value) in headers {
+      request = request.header(name, value);
+    }
+    if let Some(ca_file) = ca_file {
+      request = request.ca_file(ca_file);
+    }
+    let response = request.send()?;
+    match response.is_success() {
+      true => Ok(()),
+      false => Err(Error::msg(format!(
+        "Kafka REST proxy at {} answered {}: {}",
+        redact(&url),
+        response.status,
+        response.body.trim()
+      ))),
+    }
+  }
+}
+
+impl Sink for StreamSink {
+  fn name(&self) -> &str {
+    match self.target {
+      StreamTarget::Nats { .. } => "nats",
+      StreamTarget::Kafka { .. } => "kafka",
+    }
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let events = self.events(report)?;
+    if events.is_empty() {
+      // Still notices a lost connection, and answers the server's pings.
+      return match self.nats.as_mut().map(|v| v.keep_alive()) {
+        Some(Err(e)) => {
+          self.disconnect();
+          Err(Error::msg(format!("Lost the connection to NATS: {}", e)))
+        }
+        _ => Ok(()),
+      };
+    }
+    debug!("Streaming {} event(s)", events.len());
+    match self.target {
+      StreamTarget::Nats { .. } => self.publish_nats(&events),
+      StreamTarget::Kafka { .. } => self.publish_kafka(&events),
+    }
+  }
+
+  fn needs_thread(&self) -> bool {
+    true
+  }
+}
+
+/// Connection to a NATS server.
+#[derive(Debug)]
+struct NatsConnection {
+  reader: BufReader<TcpStream>,
+  writer: TcpStream,
+}
+
+impl NatsConnection {
+  ///
+  /// Connects to a server, authenticating if asked, and waits for it to
+  /// accept the connection.
+  ///
+  /// Args:
+  ///  - address: The server's host, and port if not 4222.
+  ///
+  fn connect(
+    address: &str,
+    username: &Option<String>,
+    password: &Option<String>,
+    token: &Option<String>,
+  ) -> Result<Self> {
+    let stream = resolve(address)
+      .and_then(|addr| {
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        Ok(stream)
+      })
+      .map_err(|e| Error::msg(format!("Failed to connect to NATS at {}: {}", address, e)))?;
+    let mut connection = NatsConnection {
+      reader: BufReader::new(stream.try_clone()?),
+      writer: stream,
+    };
+
+    let info = connection.read_line()?;
+    if !info.starts_with("INFO ") {
+      return Err(Error::msg(format!(
+        "NATS at {} sent no INFO: {}",
+        address, info
+      )));
+    }
+    let mut options = json!({
+      "verbose": false,
+      "pedantic": false,
+      "name": format!("netmon-{}", hostname()),
+      "lang": "rust",
+      "version": env!("CARGO_PKG_VERSION"),
+      "protocol": 0,
+    });
+    if let Some(username) = username {
+      options["user"] = Value::from(username.as_str());
+      options["pass"] = Value::from(password.as_deref().unwrap_or_default());
+    }
+    if let Some(token) = token {
+      options["auth_token"] = Value::from(token.as_str());
+    }
+    let connect = format!("CONNECT {}\r\nPING\r\n", options);
+    connection.writer.write_all(connect.as_bytes())?;
+    connection.wait_pong()?;
+    Ok(connection)
+  }
+
+  /// Publishes messages, as (subject, payload), until the server confirms them.
+  fn publish(&mut self, messages: &[(String, String)]) -> Result<()> {
+    self.answer_pings()?;
+    let mut out = Vec::new();
+    for (subject, payload) in messages {
+      out.extend_from_slice(format!("PUB {} {}\r\n", subject, payload.len()).as_bytes());
+      out.extend_from_slice(payload.as_bytes());
+      out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"PING\r\n");
+    self.writer.write_all(&out)?;
+    self.wait_pong()
+  }
+
+  /// Checks the connection, answering the pings the server sent meanwhile.
+  fn keep_alive(&mut self) -> Result<()> {
+    self.answer_pings()
+  }
+
+  /// Reads what the server sent since, without waiting, answering its pings.
+  fn answer_pings(&mut self) -> Result<()> {
+    loop {
+      self.reader.get_ref().set_nonblocking(true)?;
+      let pending = match self.reader.fill_buf() {
+        Ok(buf) => Ok(!buf.is_empty()),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+      };
+      self.reader.get_ref().set_nonblocking(false)?;
+      if !pending? {
+        return Ok(());
+      }
+      let line = self.read_line()?;
+      self.handle(&line)?;
+    }
+  }
+
+  /// Waits for the server's PONG, answering its pings meanwhile.
+  fn wait_pong(&mut self) -> Result<()> {
+    loop {
+      let line = self.read_line()?;
+      if line == "PONG" {
+        return Ok(());
+      }
+      self.handle(&line)?;
+    }
+  }
+
+  /// Handles a line from the server other than a PONG.
+  fn handle(&mut self, line: &str) -> Result<()> {
+    match line {
+      "PING" => Ok(self.writer.write_all(b"PONG\r\n")?),
+      line if line.starts_with("-ERR") => Err(Error::msg(format!(
+        "NATS refused: {}",
+        line["-ERR".len()..].trim().trim_matches('\'')
+      ))),
+      _ => Ok(()),
+    }
+  }
+
+  /// Reads a line from the server, without its CRLF.
+  fn read_line(&mut self) -> Result<String> {
+    let mut line = String::new();
+    let read = (&mut self.reader).take(64 * 1024).read_line(&mut line)?;
+    if read == 0 {
+      return Err(Error::msg("Connection closed by the server"));
+    }
+    Ok(line.trim_end().to_string())
+  }
+}
+
+/// Resolves the server's address, with the default port if it has none.
+fn resolve(address: &str) -> std::io::Result<SocketAddr> {
+  let mut addrs = match address.to_socket_addrs() {
+    Ok(addrs) => addrs,
+    Err(_) => (address, DEFAULT_NATS_PORT).to_socket_addrs()?,
+  };
+  addrs
+    .next()
+    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address found"))
+}
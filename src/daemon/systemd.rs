@@ -0,0 +1,74 @@
+use anyhow::{Error, Result};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+/// Socket systemd listens for notifications on, for Type=notify services.
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/*
+  https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html
+
+  Notifications are newline separated assignments sent as a single datagram
+  to $NOTIFY_SOCKET, a socket path or, when starting with '@', an abstract
+  socket name:
+
+    READY=1       Startup finished.
+    WATCHDOG=1    Keep-alive ping, due every $WATCHDOG_USEC microseconds.
+    STOPPING=1    Shutdown started.
+*/
+
+/// Whether the service manager asked for notifications.
+pub fn notify_enabled() -> bool {
+  std::env::var_os(NOTIFY_SOCKET_ENV).is_some()
+}
+
+///
+/// Sends a notification to systemd, like sd_notify(3).
+///
+/// Args:
+///  - state: Notification, e.g. "READY=1".
+///
+/// Returns:
+///  Result of whether it was sent, false when not running under systemd.
+///
+pub fn notify(state: &str) -> Result<bool> {
+  let Some(socket_path) = std::env::var_os(NOTIFY_SOCKET_ENV) else {
+    return Ok(false);
+  };
+  let socket_path = socket_path.to_string_lossy().into_owned();
+
+  let addr = match socket_path.strip_prefix('@') {
+    Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+    None => SocketAddr::from_pathname(&socket_path),
+  }
+  .map_err(|e| {
+    Error::msg(format!(
+      "Invalid {} '{}': {}",
+      NOTIFY_SOCKET_ENV, socket_path, e
+    ))
+  })?;
+
+  UnixDatagram::unbound()
+    .and_then(|socket| socket.send_to_addr(state.as_bytes(), &addr))
+    .map_err(|e| Error::msg(format!("Failed to notify systemd of '{}': {}", state, e)))?;
+  Ok(true)
+}
+
+///
+/// How often systemd expects watchdog pings, like sd_watchdog_enabled(3).
+///
+/// Returns:
+///  The watchdog timeout, or None if the watchdog is disabled or meant for
+///  another process.
+///
+pub fn watchdog_timeout() -> Option<Duration> {
+  if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+      return None;
+    }
+  }
+
+  let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+  (usec > 0).then(|| Duration::from_micros(usec))
+}
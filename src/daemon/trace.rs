@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Sink spans kept for the exporter, past which the oldest are dropped.
+const MAX_SINK_SPANS: usize = 1024;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SINK_SPANS: Mutex<VecDeque<SinkSpan>> = Mutex::new(VecDeque::new());
+
+/*
+  Timings of the steps of each poll, for tracing exporters such as the otlp
+  sink to report where a slow poll spends its time:
+
+    poll                 |=======|
+      collect neighbor   |====|
+      collect wireless   |======|
+      sink sqlite                 |===|
+
+  The collectors' spans travel with their poll, in PollReport::spans. The
+  sinks' spans end after their poll was handed out, so the workers record
+  them process-wide instead, once a tracing exporter enabled it, for the
+  exporter to take along with a later poll.
+*/
+
+/// A timed step of a poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+  /// Name of the step, e.g. "collect neighbor".
+  pub name: String,
+  pub start: SystemTime,
+  pub duration: Duration,
+  /// Why the step failed, if it did.
+  pub error: Option<String>,
+}
+
+impl Span {
+  pub fn new(name: &str, start: SystemTime, duration: Duration) -> Self {
+    Span {
+      name: name.to_string(),
+      start,
+      duration,
+      error: None,
+    }
+  }
+
+  /// Marks the step as failed.
+  pub fn error(mut self, error: &str) -> Self {
+    self.error = Some(error.to_string());
+    self
+  }
+}
+
+/// A sink publishing a poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkSpan {
+  /// Time the poll was taken at, telling which poll it was.
+  pub poll: SystemTime,
+  pub sink: String,
+  pub span: Span,
+}
+
+/// Starts recording the sinks' spans, until the process exits.
+pub fn enable() {
+  ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the sinks' spans are recorded.
+pub fn is_enabled() -> bool {
+  ENABLED.load(Ordering::Relaxed)
+}
+
+/// Records a sink's span, if enabled.
+pub fn record_sink_span(span: SinkSpan) {
+  if !is_enabled() {
+    return;
+  }
+  let mut spans = SINK_SPANS.lock().unwrap();
+  if spans.len() >= MAX_SINK_SPANS {
+    spans.pop_front();
+  }
+  spans.push_back(span);
+}
+
+/// Takes the sinks' spans recorded since the last call, oldest first.
+pub fn take_sink_spans() -> Vec<SinkSpan> {
+  SINK_SPANS.lock().unwrap().drain(..).collect()
+}
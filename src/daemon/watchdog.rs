@@ -0,0 +1,250 @@
+use super::Health;
+use crate::neighbors;
+use anyhow::{Error, Result};
+use log::error;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Poll intervals a collector may run for before it's considered stuck.
+pub const DEFAULT_STUCK_INTERVALS: u32 = 3;
+
+/*
+  A stuck collector is logged along with what its thread and commands are
+  blocked on, as read from /proc, e.g.:
+
+    The neighbor collector is stuck for 1m 30s, restarting it (thread 1234: state=S wchan=netlink_recvmsg)
+    Killing 'ip' (pid 5678) of the neighbor collector, running for 1m 30s (state=D wchan=rtnl_lock)
+*/
+
+/// A collector run that hasn't returned yet.
+#[derive(Debug, Clone, Copy)]
+struct CollectorRun {
+  generation: u64,
+  started_at: Instant,
+  /// Kernel id of the collector's thread, once it started.
+  thread_id: Option<i64>,
+}
+
+#[derive(Debug)]
+struct WatchdogState {
+  interval: Duration,
+  stuck_intervals: u32,
+  running: HashMap<String, CollectorRun>,
+  next_generation: u64,
+}
+
+/// State and kernel wait channel of a thread or process, read from /proc.
+fn describe_task(path: &str) -> String {
+  let state = std::fs::read_to_string(format!("{}/stat", path))
+    .ok()
+    .and_then(|stat| {
+      // The command name may hold spaces, the state follows its parenthesis.
+      let (_, rest) = stat.rsplit_once(')')?;
+      rest.split_whitespace().next().map(|v| v.to_string())
+    })
+    .unwrap_or_else(|| "?".to_string());
+  let wchan = std::fs::read_to_string(format!("{}/wchan", path))
+    .ok()
+    .filter(|v| !v.is_empty() && v != "0")
+    .unwrap_or_else(|| "-".to_string());
+  format!("state={} wchan={}", state, wchan)
+}
+
+///
+/// Supervises the blocking collectors, which can't be cancelled once started.
+/// A collector still running from an earlier poll isn't started again, so
+/// hung collectors don't pile up threads, until it's been running for a few
+/// poll intervals: it's then considered stuck, its commands are killed, and
+/// a fresh collector is started in its place. Cloning the watchdog shares
+/// its state.
+///
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+  state: Arc<Mutex<WatchdogState>>,
+}
+
+impl Default for Watchdog {
+  fn default() -> Self {
+    Watchdog::new(super::DEFAULT_INTERVAL)
+  }
+}
+
+impl Watchdog {
+  pub fn new(interval: Duration) -> Self {
+    Watchdog {
+      state: Arc::new(Mutex::new(WatchdogState {
+        interval,
+        stuck_intervals: DEFAULT_STUCK_INTERVALS,
+        running: HashMap::new(),
+        next_generation: 0,
+      })),
+    }
+  }
+
+  /// Updates the poll interval, which decides when collectors are stuck.
+  pub fn set_interval(&self, interval: Duration) {
+    self.state.lock().unwrap().interval = interval;
+  }
+
+  /// Updates the poll intervals a collector may run for, at least one.
+  pub fn set_stuck_intervals(&self, stuck_intervals: u32) {
+    self.state.lock().unwrap().stuck_intervals = stuck_intervals.max(1);
+  }
+
+  /// Marks a collector as started, unless a run of it is still in flight.
+  fn start(&self, name: &str) -> Result<u64> {
+    let mut state = self.state.lock().unwrap();
+    if let Some(run) = state.running.get(name) {
+      return Err(Error::msg(format!(
+        "The {} collector is still running since {} ago",
+        name,
+        humantime::format_duration(Duration::from_secs(run.started_at.elapsed().as_secs()))
+      )));
+    }
+
+    state.next_generation += 1;
+    let generation = state.next_generation;
+    state.running.insert(
+      name.to_string(),
+      CollectorRun {
+        generation,
+        started_at: Instant::now(),
+        thread_id: None,
+      },
+    );
+    Ok(generation)
+  }
+
+  /// Records the thread a collector runs on.
+  fn attach(&self, name: &str, generation: u64) {
+    let mut state = self.state.lock().unwrap();
+    if let Some(run) = state.running.get_mut(name) {
+      if run.generation == generation {
+        run.thread_id = Some(neighbors::current_thread_id());
+      }
+    }
+  }
+
+  /// Marks a collector as returned, unless it was abandoned meanwhile.
+  fn finish(&self, name: &str, generation: u64) {
+    let mut state = self.state.lock().unwrap();
+    if state.running.get(name).map(|v| v.generation) == Some(generation) {
+      state.running.remove(name);
+    }
+  }
+
+  ///
+  /// Runs a blocking collector on the runtime's blocking pool, unless a run
+  /// of it is still in flight.
+  ///
+  /// Args:
+  ///  - name: Name of the collector, for errors.
+  ///  - collect: Collector to run.
+  ///
+  /// Returns:
+  ///  Result of the collector, failing if it's still running. A collector
+  ///  that's no longer waited for, e.g. as it timed out, keeps its thread
+  ///  until it returns or is abandoned as stuck.
+  ///
+  pub async fn collect<T, F>(&self, name: &str, collect: F) -> Result<T>
+  where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+  {
+    let generation = self.start(name)?;
+    let watchdog = self.clone();
+    let collector = name.to_string();
+    let task = tokio::task::spawn_blocking(move || {
+      watchdog.attach(&collector, generation);
+      let result = collect();
+      watchdog.finish(&collector, generation);
+      result
+    });
+
+    match task.await {
+      Ok(result) => result,
+      Err(e) => {
+        self.finish(name, generation);
+        Err(Error::msg(format!("The {} collector failed: {}", name, e)))
+      }
+    }
+  }
+
+  ///
+  /// Restarts every collector running for longer than allowed: logs what
+  /// it's blocked on, kills the commands it's waiting for, and abandons its
+  /// thread so the next poll starts it afresh.
+  ///
+  /// Returns:
+  ///  Names of the collectors restarted.
+  ///
+  pub fn check(&self) -> Vec<String> {
+    let stuck: Vec<(String, CollectorRun)> = {
+      let mut state = self.state.lock().unwrap();
+      let stuck_after = state.interval * state.stuck_intervals;
+      let names: Vec<String> = state
+        .running
+        .iter()
+        .filter(|(_, run)| run.started_at.elapsed() >= stuck_after)
+        .map(|(name, _)| name.clone())
+        .collect();
+      names
+        .into_iter()
+        .filter_map(|name| state.running.remove(&name).map(|run| (name, run)))
+        .collect()
+    };
+
+    for (name, run) in &stuck {
+      let elapsed = Duration::from_secs(run.started_at.elapsed().as_secs());
+      let thread = match run.thread_id {
+        Some(tid) => format!(
+          "thread {}: {}",
+          tid,
+          describe_task(&format!("/proc/self/task/{}", tid))
+        ),
+        None => "never started".to_string(),
+      };
+      error!(
+        "collector.name" = name.as_str();
+        "The {} collector is stuck for {}, restarting it ({})",
+        name,
+        humantime::format_duration(elapsed),
+        thread
+      );
+
+      let commands = neighbors::running_commands();
+      for command in commands
+        .iter()
+        .filter(|v| Some(v.thread_id) == run.thread_id)
+      {
+        error!(
+          "Killing '{}' (pid {}) of the {} collector, running for {} ({})",
+          command.program,
+          command.pid,
+          name,
+          humantime::format_duration(Duration::from_secs(command.started_at.elapsed().as_secs())),
+          describe_task(&format!("/proc/{}", command.pid))
+        );
+        neighbors::kill_running_command(command.pid);
+      }
+    }
+    stuck.into_iter().map(|(name, _)| name).collect()
+  }
+
+  ///
+  /// Checks for stuck collectors once every poll interval, until aborted.
+  ///
+  /// Args:
+  ///  - health: Health to record the restarts in.
+  ///
+  pub async fn supervise(self, health: Health) {
+    loop {
+      let interval = self.state.lock().unwrap().interval;
+      tokio::time::sleep(interval).await;
+      for name in self.check() {
+        health.record_restart(&name);
+      }
+    }
+  }
+}
@@ -0,0 +1,152 @@
+use super::trace::{self, SinkSpan, Span};
+use super::{Health, PollReport, Sink};
+use anyhow::{Error, Result};
+use log::warn;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Instant, SystemTime};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Polls queued for a sink before newer ones are dropped.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 16;
+
+/// Where a worker's sink runs.
+enum Runner {
+  Thread {
+    tx: mpsc::Sender<Arc<PollReport>>,
+    handle: JoinHandle<Result<()>>,
+  },
+  Inline(Box<dyn Sink>),
+}
+
+///
+/// Runs a sink on its own thread, fed through a bounded queue, so a slow sink
+/// (e.g. a remote database) neither delays polling nor the other sinks. When
+/// the queue is full, new polls are dropped for that sink only.
+///
+/// Inline workers publish right away on the polling thread instead, sparing
+/// a thread per sink on small routers.
+///
+pub struct SinkWorker {
+  name: String,
+  health: Health,
+  runner: Runner,
+}
+
+/// Publishes a poll, recording the outcome.
+fn publish(sink: &mut dyn Sink, report: &PollReport, health: &Health) {
+  let start = SystemTime::now();
+  let started_at = Instant::now();
+  let result = sink.publish(report);
+  if let Err(err) = &result {
+    warn!("Sink '{}' failed: {}", sink.name(), err);
+  }
+  health.record_sink(sink.name(), &result);
+
+  if trace::is_enabled() {
+    let mut span = Span::new(
+      &format!("sink {}", sink.name()),
+      start,
+      started_at.elapsed(),
+    );
+    if let Err(err) = &result {
+      span = span.error(&err.to_string());
+    }
+    trace::record_sink_span(SinkSpan {
+      poll: report.snapshot.taken_at,
+      sink: sink.name().to_string(),
+      span,
+    });
+  }
+}
+
+impl SinkWorker {
+  ///
+  /// Starts a thread publishing every queued poll to the sink, flushing it
+  /// once the worker is stopped.
+  ///
+  /// Args:
+  ///  - sink: Sink to run.
+  ///  - capacity: Polls queued before newer ones are dropped.
+  ///  - health: Health to record the sink's outcomes in.
+  ///
+  /// Returns:
+  ///  Result of the worker, failing if its thread couldn't be spawned.
+  ///
+  pub fn spawn(mut sink: Box<dyn Sink>, capacity: usize, health: Health) -> Result<Self> {
+    let name = sink.name().to_string();
+    let (tx, mut rx) = mpsc::channel::<Arc<PollReport>>(capacity.max(1));
+
+    let sink_health = health.clone();
+    let handle = std::thread::Builder::new()
+      .name(format!("sink-{}", name))
+      .spawn(move || {
+        while let Some(report) = rx.blocking_recv() {
+          publish(sink.as_mut(), &report, &sink_health);
+        }
+        sink.flush()
+      })
+      .map_err(|e| Error::msg(format!("Failed to spawn sink '{}': {}", name, e)))?;
+
+    Ok(SinkWorker {
+      name,
+      health,
+      runner: Runner::Thread { tx, handle },
+    })
+  }
+
+  ///
+  /// Wraps a sink published to on the calling thread, without a queue.
+  ///
+  /// Args:
+  ///  - sink: Sink to run.
+  ///  - health: Health to record the sink's outcomes in.
+  ///
+  pub fn inline(sink: Box<dyn Sink>, health: Health) -> Self {
+    SinkWorker {
+      name: sink.name().to_string(),
+      health,
+      runner: Runner::Inline(sink),
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Queues a poll, dropping it if the sink is lagging behind.
+  pub fn send(&mut self, report: Arc<PollReport>) {
+    let tx = match &mut self.runner {
+      Runner::Inline(sink) => return publish(sink.as_mut(), &report, &self.health),
+      Runner::Thread { tx, .. } => tx,
+    };
+    match tx.try_send(report) {
+      Ok(()) => {}
+      Err(TrySendError::Full(_)) => {
+        warn!("Sink '{}' is lagging behind, dropping a poll", self.name);
+        self.health.record_dropped(&self.name);
+      }
+      Err(TrySendError::Closed(_)) => warn!("Sink '{}' stopped", self.name),
+    }
+  }
+
+  ///
+  /// Stops the worker once it handled every queued poll, then flushes the sink.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the sink was flushed.
+  ///
+  pub fn stop(self) -> Result<()> {
+    let SinkWorker { name, runner, .. } = self;
+    let result = match runner {
+      Runner::Inline(mut sink) => sink.flush(),
+      Runner::Thread { tx, handle } => {
+        drop(tx);
+        handle
+          .join()
+          .map_err(|_| Error::msg(format!("Sink '{}' panicked", name)))?
+      }
+    };
+    result.map_err(|e| Error::msg(format!("Failed to flush sink '{}': {}", name, e)))
+  }
+}
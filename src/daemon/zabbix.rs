@@ -0,0 +1,298 @@
+use super::metrics::{device_samples, neighbor_counts, unix_secs, DeviceSample};
+use super::{hostname, PollReport, Sink};
+use crate::privacy::Anonymizer;
+use anyhow::{Error, Result};
+use log::debug;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Port of the Zabbix server's trapper when none is given.
+pub const DEFAULT_ZABBIX_PORT: u16 = 10051;
+
+/// Longest time between two discoveries of the devices, as Zabbix deletes
+/// the items of devices it stops discovering.
+pub const DEFAULT_DISCOVERY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Key of the discovery rule the devices are discovered by.
+pub const DISCOVERY_KEY: &str = "netmon.devices.discovery";
+
+/// Longest time connecting to, writing to, or reading from the server may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest response read from the server.
+const MAX_RESPONSE: u64 = 1 << 20;
+
+/*
+  Every poll sends the values of trapper items in a single "sender data"
+  request, as zabbix_sender does, for the host of the router:
+
+    netmon.devices                                    14
+    netmon.devices.online                             12
+    netmon.neighbors[reachable]                       12
+    netmon.poll.duration                              0.042
+    netmon.device.online[dc:a6:32:a3:48:b1]           1
+    netmon.device.last_seen[dc:a6:32:a3:48:b1]        1791953775
+
+  Wireless stations also get netmon.device.rx_bytes, tx_bytes, signal_dbm,
+  rx_bitrate, and tx_bitrate, the bitrates in bits per second. The devices
+  are discovered through the netmon.devices.discovery trapper, as
+  low-level discovery JSON, when they change, and every hour:
+
+    [{"{#MAC}":"dc:a6:32:a3:48:b1","{#NAME}":"Living room TV",
+      "{#IFACE}":"br-lan","{#VENDOR}":"Raspberry Pi"}]
+
+  Devices without a name go by their MAC address, and those of an unknown
+  vendor have an empty one.
+*/
+
+///
+/// Low-level discovery JSON of devices, for the netmon.devices.discovery
+/// rule's item prototypes to be created from.
+///
+/// Args:
+///  - devices: The devices, as (mac, name, iface, vendor).
+///
+/// Returns:
+///  The JSON, an array of the devices' macros.
+///
+pub fn discovery<'a>(
+  devices: impl IntoIterator<Item = (&'a str, &'a str, &'a str, Option<&'a str>)>,
+) -> String {
+  let devices: Vec<Value> = devices
+    .into_iter()
+    .map(|(mac, name, iface, vendor)| {
+      json!({
+        "{#MAC}": mac,
+        "{#NAME}": match name.is_empty() {
+          true => mac,
+          false => name,
+        },
+        "{#IFACE}": iface,
+        "{#VENDOR}": vendor.unwrap_or_default(),
+      })
+    })
+    .collect();
+  Value::from(devices).to_string()
+}
+
+///
+/// Sink sending the presence of the devices, the traffic of wireless
+/// stations, and the neighbor table's state, to a Zabbix server or proxy
+/// through the zabbix_sender protocol.
+///
+/// Each poll opens its own connection, as the server closes it once it
+/// answered; polls the server can't be reached for are dropped. Values of
+/// items Zabbix doesn't know of yet, before it discovered their device, are
+/// ignored by the server.
+///
+#[derive(Debug)]
+pub struct ZabbixSink {
+  server: String,
+  host: String,
+  discovery_interval: Duration,
+  anonymizer: Option<Anonymizer>,
+  /// Devices last discovered, and when.
+  discovered: Option<(BTreeSet<String>, Instant)>,
+}
+
+impl ZabbixSink {
+  ///
+  /// Creates a sink, without connecting yet.
+  ///
+  /// Args:
+  ///  - server: The server's or proxy's host, and port if not 10051, e.g.
+  ///    "10.0.0.1:10051".
+  ///
+  pub fn new(server: &str) -> Self {
+    ZabbixSink {
+      server: server.to_string(),
+      host: hostname(),
+      discovery_interval: DEFAULT_DISCOVERY_INTERVAL,
+      anonymizer: None,
+      discovered: None,
+    }
+  }
+
+  /// Host the items belong to in Zabbix, the router's hostname by default.
+  pub fn host(mut self, host: &str) -> Self {
+    self.host = host.to_string();
+    self
+  }
+
+  /// Longest time between two discoveries of the devices.
+  pub fn discovery_interval(mut self, discovery_interval: Duration) -> Self {
+    self.discovery_interval = discovery_interval;
+    self
+  }
+
+  /// Hashes the MAC addresses and names of the devices.
+  pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+    self.anonymizer = Some(anonymizer);
+    self
+  }
+
+  /// The values of a poll, as (key, value) pairs.
+  fn values(&self, devices: &[DeviceSample], report: &PollReport) -> Vec<(String, Value)> {
+    let online = devices.iter().filter(|v| v.online).count();
+    let mut values = vec![
+      ("netmon.devices".to_string(), Value::from(devices.len())),
+      ("netmon.devices.online".to_string(), Value::from(online)),
+    ];
+    for (nud_state, count) in neighbor_counts(report) {
+      let key = format!(
+        "netmon.neighbors[{}]",
+        key_parameter(&nud_state.to_lowercase())
+      );
+      values.push((key, Value::from(count)));
+    }
+    values.push((
+      "netmon.poll.duration".to_string(),
+      Value::from(report.duration.as_secs_f64()),
+    ));
+
+    for device in devices {
+      let mac = key_parameter(&device.mac);
+      let fields = [
+        ("online", Some(Value::from(device.online as u8))),
+        ("last_seen", Some(Value::from(unix_secs(device.last_seen)))),
+        ("rx_bytes", device.rx_bytes.map(Value::from)),
+        ("tx_bytes", device.tx_bytes.map(Value::from)),
+        ("signal_dbm", device.signal_dbm.map(Value::from)),
+        (
+          "rx_bitrate",
+          device.rx_bitrate_kbps.map(|v| Value::from(v * 1000)),
+        ),
+        (
+          "tx_bitrate",
+          device.tx_bitrate_kbps.map(|v| Value::from(v * 1000)),
+        ),
+      ];
+      for (field, value) in fields {
+        if let Some(value) = value {
+          values.push((format!("netmon.device.{}[{}]", field, mac), value));
+        }
+      }
+    }
+    values
+  }
+
+  /// The discovery of the devices, if they changed, or it's due.
+  fn discovery(&self, devices: &[DeviceSample]) -> Option<(BTreeSet<String>, String)> {
+    let macs: BTreeSet<String> = devices.iter().map(|v| v.mac.clone()).collect();
+    let due = match &self.discovered {
+      Some((discovered, at)) => *discovered != macs || at.elapsed() >= self.discovery_interval,
+      None => true,
+    };
+    due.then(|| {
+      let devices = devices
+        .iter()
+        .map(|v| (v.mac.as_str(), v.name.as_str(), v.iface.as_str(), v.vendor));
+      (macs, discovery(devices))
+    })
+  }
+
+  ///
+  /// Sends a "sender data" request, and reads the server's answer.
+  ///
+  /// Args:
+  ///  - data: The items' values, each with its host, key, and clock.
+  ///
+  /// Returns:
+  ///  Result of the server's summary, e.g. "processed: 12; failed: 0".
+  ///
+  fn send(&self, data: Vec<Value>) -> Result<String> {
+    let request = json!({"request": "sender data", "data": data}).to_string();
+    let mut stream = resolve(&self.server)
+      .and_then(|addr| {
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(stream)
+      })
+      .map_err(|e| {
+        Error::msg(format!(
+          "Failed to connect to Zabbix at {}: {}",
+          self.server, e
+        ))
+      })?;
+
+    let mut message = b"ZBXD\x01".to_vec();
+    message.extend_from_slice(&(request.len() as u64).to_le_bytes());
+    message.extend_from_slice(request.as_bytes());
+    let mut header = [0; 13];
+    let mut body = Vec::new();
+    stream
+      .write_all(&message)
+      .and_then(|_| stream.read_exact(&mut header))
+      .map_err(|e| Error::msg(format!("Failed to send to Zabbix: {}", e)))?;
+    if &header[..4] != b"ZBXD" {
+      return Err(Error::msg("Zabbix answered with an unknown protocol"));
+    }
+    let len = u64::from_le_bytes(header[5..].try_into().unwrap()).min(MAX_RESPONSE);
+    stream
+      .take(len)
+      .read_to_end(&mut body)
+      .map_err(|e| Error::msg(format!("Failed to read Zabbix's answer: {}", e)))?;
+
+    let response: Value = serde_json::from_slice(&body)
+      .map_err(|e| Error::msg(format!("Zabbix answered with invalid JSON: {}", e)))?;
+    let info = response["info"].as_str().unwrap_or_default().to_string();
+    match response["response"].as_str() {
+      Some("success") => Ok(info),
+      _ => Err(Error::msg(format!("Zabbix refused the values: {}", info))),
+    }
+  }
+}
+
+impl Sink for ZabbixSink {
+  fn name(&self) -> &str {
+    "zabbix"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let devices = device_samples(report, self.anonymizer.as_ref());
+    let clock = unix_secs(report.snapshot.taken_at);
+    let item = |key: &str, value: Value| json!({"host": self.host, "key": key, "value": value, "clock": clock});
+    let discovery = self.discovery(&devices);
+    let mut data = Vec::new();
+    if let Some((_, json)) = &discovery {
+      data.push(item(DISCOVERY_KEY, Value::from(json.as_str())));
+    }
+    for (key, value) in self.values(&devices, report) {
+      data.push(item(&key, value));
+    }
+
+    let info = self.send(data)?;
+    debug!("Zabbix processed the values: {}", info);
+    if let Some((macs, _)) = discovery {
+      self.discovered = Some((macs, Instant::now()));
+    }
+    Ok(())
+  }
+
+  fn needs_thread(&self) -> bool {
+    true
+  }
+}
+
+/// Resolves the server's address, with the default port if it has none.
+fn resolve(server: &str) -> std::io::Result<SocketAddr> {
+  let mut addrs = match server.to_socket_addrs() {
+    Ok(addrs) => addrs,
+    Err(_) => (server, DEFAULT_ZABBIX_PORT).to_socket_addrs()?,
+  };
+  addrs
+    .next()
+    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address found"))
+}
+
+/// Quotes a parameter of an item's key if it holds what separates parameters.
+fn key_parameter(value: &str) -> String {
+  match value.contains([',', ']', '"', ' ', '[']) {
+    true => format!("\"{}\"", value.replace('"', "\\\"")),
+    false => value.to_string(),
+  }
+}
@@ -0,0 +1,125 @@
+use super::Device;
+use crate::neighbors::MacAddr;
+use std::collections::{BTreeMap, HashMap};
+
+/// What DHCP (and DHCPv6) told us about the owner of a MAC address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceIdentity {
+  /// Hostname sent in the DHCP request.
+  pub hostname: Option<String>,
+  /// DHCPv6 client DUID, hex encoded.
+  pub duid: Option<String>,
+}
+
+/// What a logical device was recognized by, from strongest to weakest.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IdentityKey {
+  Mac(MacAddr),
+  Duid(String),
+  Hostname(String),
+}
+
+/// One or more devices believed to be the same physical machine, behind rotating MACs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogicalDevice {
+  pub key: IdentityKey,
+  pub devices: Vec<Device>,
+}
+
+impl LogicalDevice {
+  /// Every MAC address the device was seen with.
+  pub fn mac_addrs(&self) -> impl Iterator<Item = MacAddr> + '_ {
+    self.devices.iter().map(|v| v.mac_addr)
+  }
+
+  /// Whether any of the device's MAC addresses is online.
+  pub fn online(&self) -> bool {
+    self.devices.iter().any(|v| v.online)
+  }
+}
+
+/// Normalizes a hostname, dropping the placeholders DHCP servers use for clients that sent none.
+fn normalize_hostname(hostname: &str) -> Option<String> {
+  let hostname = hostname.trim().trim_end_matches('.').to_lowercase();
+  match hostname.as_str() {
+    "" | "*" => None,
+    _ => Some(hostname),
+  }
+}
+
+/// Picks the key a device is linked by.
+fn identity_key(device: &Device, identity: Option<&DeviceIdentity>) -> IdentityKey {
+  // Vendor assigned addresses are stable, and are the best key we could ask for.
+  if !device.randomized_mac {
+    return IdentityKey::Mac(device.mac_addr);
+  }
+
+  let Some(identity) = identity else {
+    return IdentityKey::Mac(device.mac_addr);
+  };
+  if let Some(duid) = identity.duid.as_deref().filter(|v| !v.is_empty()) {
+    return IdentityKey::Duid(duid.to_lowercase());
+  }
+  if let Some(hostname) = identity.hostname.as_deref().and_then(normalize_hostname) {
+    return IdentityKey::Hostname(hostname);
+  }
+  IdentityKey::Mac(device.mac_addr)
+}
+
+///
+/// Links devices behind rotating randomized MACs into logical devices, using
+/// the DHCPv6 DUID first and the DHCP hostname second.
+///
+/// Devices with vendor assigned MACs, or without any identifying hints, are
+/// kept on their own.
+///
+/// Args:
+///  - devices: Devices grouped by MAC.
+///  - identities: DHCP hints, keyed by MAC.
+///
+/// Returns:
+///  The logical devices, sorted by key.
+///
+/// ```
+/// use openwrt_netmon::{ArpTable, Device, DeviceIdentity, MacAddr};
+/// use openwrt_netmon::device::link_devices;
+/// use std::collections::HashMap;
+///
+/// let devices = Device::group(&[
+///   ArpTable::parse_from_string("192.168.0.5 dev br-lan lladdr 5a:11:22:33:44:55 STALE")?,
+///   ArpTable::parse_from_string("192.168.0.9 dev br-lan lladdr 7e:66:77:88:99:aa REACHABLE")?,
+/// ]);
+///
+/// let identity = DeviceIdentity {
+///   hostname: Some("Pixel-7".to_string()),
+///   duid: None,
+/// };
+/// let identities = HashMap::from([
+///   ("5a:11:22:33:44:55".parse::<MacAddr>()?, identity.clone()),
+///   ("7e:66:77:88:99:aa".parse::<MacAddr>()?, identity),
+/// ]);
+///
+/// let linked = link_devices(devices, &identities);
+/// assert_eq!(linked.len(), 1);
+/// assert!(linked[0].online());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+pub fn link_devices(
+  devices: Vec<Device>,
+  identities: &HashMap<MacAddr, DeviceIdentity>,
+) -> Vec<LogicalDevice> {
+  let mut linked: BTreeMap<IdentityKey, Vec<Device>> = BTreeMap::new();
+  for device in devices {
+    let key = identity_key(&device, identities.get(&device.mac_addr));
+    linked.entry(key).or_default().push(device);
+  }
+
+  linked
+    .into_iter()
+    .map(|(key, devices)| LogicalDevice { key, devices })
+    .collect()
+}
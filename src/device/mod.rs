@@ -0,0 +1,191 @@
+use crate::dhcp::Lease;
+use crate::neighbors::{ArpTable, MacAddr, NudState, ScopedIpAddr};
+use crate::registry::{DeviceCategory, TrustLevel};
+use crate::wireless::Station;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+pub mod identity;
+
+pub use identity::{link_devices, DeviceIdentity, IdentityKey, LogicalDevice};
+
+/// NUD states from most to least alive, used to pick a device's overall state.
+const NUD_STATE_PRIORITY: [NudState; 8] = [
+  NudState::REACHABLE,
+  NudState::PERMANENT,
+  NudState::NOARP,
+  NudState::DELAY,
+  NudState::PROBE,
+  NudState::STALE,
+  NudState::INCOMPLETE,
+  NudState::FAILED,
+];
+
+/// Ranks a set of NUD states by its most alive member, higher being more alive.
+fn nud_state_rank(nud_state: &NudState) -> usize {
+  NUD_STATE_PRIORITY
+    .iter()
+    .position(|v| nud_state.contains(*v))
+    .map(|v| NUD_STATE_PRIORITY.len() - v)
+    .unwrap_or(0)
+}
+
+/// A single address a device currently answers on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceAddress {
+  pub ip: ScopedIpAddr,
+  pub nud_state: NudState,
+}
+
+///
+/// Every neighbor entry of a single device, keyed by its MAC address, so a
+/// phone with one IPv4 and several IPv6 addresses shows up once.
+///
+/// ```
+/// use openwrt_netmon::{ArpTable, Device};
+///
+/// let entries = vec![
+///   ArpTable::parse_from_string("192.168.0.5 dev br-lan lladdr dc:a6:32:a3:48:b1 STALE")?,
+///   ArpTable::parse_from_string("fe80::dea6:32ff:fea3:48b1 dev br-lan lladdr dc:a6:32:a3:48:b1 REACHABLE")?,
+///   ArpTable::parse_from_string("192.168.0.8 dev br-lan FAILED")?,
+/// ];
+///
+/// let devices = Device::group(&entries);
+/// assert_eq!(devices.len(), 1);
+/// assert_eq!(devices[0].addresses.len(), 2);
+/// assert_eq!(devices[0].ipv4_addrs().count(), 1);
+/// assert!(devices[0].online);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Device {
+  pub mac_addr: MacAddr,
+  /// Interface of the device's most alive entry.
+  pub iface: String,
+  pub addresses: Vec<DeviceAddress>,
+  /// State of the device's most alive entry.
+  pub nud_state: NudState,
+  pub online: bool,
+  /// Whether the MAC address looks randomized, so it may rotate over time.
+  pub randomized_mac: bool,
+  /// Manufacturer, according to the OUI database.
+  pub vendor: Option<String>,
+  /// Hostname, once resolved (see resolver::HostnameResolver).
+  pub hostname: Option<String>,
+  /// Most recent DHCP lease, once joined (see dhcp::join_leases).
+  pub lease: Option<Lease>,
+  /// DHCPv6 DUID, a stable identifier across MAC randomization.
+  pub duid: Option<String>,
+  /// ".local" name advertised over mDNS.
+  pub mdns_hostname: Option<String>,
+  /// Service types advertised over mDNS, e.g. "_ipp._tcp".
+  pub services: Vec<String>,
+  /// Name the device answered an LLMNR or NetBIOS probe with.
+  pub probed_name: Option<String>,
+  /// Name the user gave the device, in its config or registry.
+  pub alias: Option<String>,
+  /// Owner the device is registered to (see registry::Registry).
+  pub owner: Option<String>,
+  pub category: Option<DeviceCategory>,
+  pub trust: Option<TrustLevel>,
+  /// Wireless association, once joined (see wireless::join_stations).
+  pub station: Option<Station>,
+}
+
+impl Device {
+  ///
+  /// Groups neighbor entries into devices by MAC address.
+  ///
+  /// Entries without a MAC address can't be attributed to a device and are
+  /// skipped.
+  ///
+  /// Args:
+  ///  - entries: Neighbor table snapshot.
+  ///
+  /// Returns:
+  ///  The devices, sorted by MAC address.
+  ///
+  pub fn group(entries: &[ArpTable]) -> Vec<Device> {
+    let mut grouped: HashMap<MacAddr, Vec<&ArpTable>> = HashMap::new();
+    for entry in entries {
+      if let Some(mac_addr) = entry.mac_addr {
+        grouped.entry(mac_addr).or_default().push(entry);
+      }
+    }
+
+    let mut devices: Vec<Device> = grouped
+      .into_iter()
+      .map(|(mac_addr, device_entries)| Device::from_entries(mac_addr, &device_entries))
+      .collect();
+    devices.sort_by_key(|v| v.mac_addr);
+    devices
+  }
+
+  /// Builds a device out of its (non-empty) entries.
+  fn from_entries(mac_addr: MacAddr, entries: &[&ArpTable]) -> Device {
+    let best = entries
+      .iter()
+      .max_by_key(|v| nud_state_rank(&v.nud_state))
+      .unwrap();
+
+    let mut addresses: Vec<DeviceAddress> = entries
+      .iter()
+      .map(|v| DeviceAddress {
+        ip: v.scoped_ip(),
+        nud_state: v.nud_state,
+      })
+      .collect();
+    addresses.sort_by(|a, b| a.ip.cmp(&b.ip));
+
+    Device {
+      mac_addr,
+      iface: best.iface.clone(),
+      addresses,
+      nud_state: best.nud_state,
+      online: entries.iter().any(|v| v.nud_state.is_valid()),
+      randomized_mac: mac_addr.is_randomized(),
+      vendor: mac_addr.vendor().map(String::from),
+      hostname: None,
+      lease: None,
+      duid: None,
+      mdns_hostname: None,
+      services: Vec::new(),
+      probed_name: None,
+      alias: None,
+      owner: None,
+      category: None,
+      trust: None,
+      station: None,
+    }
+  }
+
+  /// Best known name of the device: its alias, then DNS name, then DHCP
+  /// hostname, then mDNS name, then probed name.
+  pub fn name(&self) -> Option<&str> {
+    self
+      .alias
+      .as_deref()
+      .or(self.hostname.as_deref())
+      .or(self.lease.as_ref().and_then(|v| v.hostname.as_deref()))
+      .or(self.mdns_hostname.as_deref())
+      .or(self.probed_name.as_deref())
+  }
+
+  /// Every address of the device.
+  pub fn ips(&self) -> impl Iterator<Item = &ScopedIpAddr> {
+    self.addresses.iter().map(|v| &v.ip)
+  }
+
+  /// The device's IPv4 addresses.
+  pub fn ipv4_addrs(&self) -> impl Iterator<Item = &ScopedIpAddr> {
+    self.ips().filter(|v| matches!(v.ip, IpAddr::V4(_)))
+  }
+
+  /// The device's IPv6 addresses.
+  pub fn ipv6_addrs(&self) -> impl Iterator<Item = &ScopedIpAddr> {
+    self.ips().filter(|v| matches!(v.ip, IpAddr::V6(_)))
+  }
+}
@@ -0,0 +1,127 @@
+use super::{mac_from_duid, normalize_duid, Lease};
+use crate::counters;
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Where OpenWrt's dnsmasq keeps its leases by default.
+pub const DEFAULT_DNSMASQ_LEASE_PATH: &str = "/tmp/dhcp.leases";
+
+/*
+  dnsmasq writes one lease per line:
+  1718049305 dc:a6:32:57:46:d6 192.168.0.33 raspberrypi 01:dc:a6:32:57:46:d6
+  0 24:4b:fe:06:f8:3c 192.168.0.10 nas *
+
+  Followed by its DHCPv6 leases, if any, after the server's DUID:
+  duid 00:01:00:01:2a:2b:7c:9d:dc:a6:32:57:46:d6
+  1718049305 1337 fd00::33 raspberrypi 00:04:4b:1b:30:9e:4c:af:88:52:79:cf:e4:7e:4f:2f:77:98
+
+  So we're parsing:
+  <expiry> <mac|iaid> <ip> <hostname|*> <client id|*>
+
+  Where an expiry of 0 means the lease never runs out.
+*/
+
+/// Maps dnsmasq's '*' placeholder to None.
+fn optional_field(field: &str) -> Option<String> {
+  match field {
+    "*" | "" => None,
+    _ => Some(field.to_string()),
+  }
+}
+
+impl Lease {
+  ///
+  /// Parses a string into a Lease instance, given the string matches a line
+  /// of dnsmasq's lease file.
+  ///
+  /// Args:
+  ///  - s: Row as a string.
+  ///
+  /// Returns:
+  ///  Result reflecting a successful parse.
+  ///
+  /// ```
+  /// use openwrt_netmon::{Lease, MacAddr};
+  ///
+  /// let lease = Lease::parse_dnsmasq_line("0 24:4b:fe:06:f8:3c 192.168.0.10 nas *")?;
+  /// assert_eq!(lease.mac_addr, Some("24:4b:fe:06:f8:3c".parse::<MacAddr>()?));
+  /// assert_eq!(lease.hostname.as_deref(), Some("nas"));
+  /// assert!(lease.expires.is_none());
+  /// # Ok::<(), anyhow::Error>(())
+  /// ```
+  ///
+  pub fn parse_dnsmasq_line(s: &str) -> Result<Self> {
+    let sliced_str: Vec<&str> = s.split_whitespace().collect();
+    debug!("Sliced string -> {:?}", sliced_str);
+
+    if sliced_str.len() < 4 {
+      return Err(Error::msg(format!("Unexpected string -> {}", s)));
+    }
+
+    let expiry = u64::from_str(sliced_str[0])
+      .map_err(|e| Error::msg(format!("Invalid lease expiry '{}': {}", sliced_str[0], e)))?;
+    let expires = match expiry {
+      0 => None,
+      secs => Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+    };
+    let ip = IpAddr::from_str(sliced_str[2])?;
+
+    // DHCPv6 leases carry the IAID where DHCPv4 ones carry the MAC, and the
+    // client's DUID as its identifier.
+    let client_id = sliced_str.get(4).and_then(|v| optional_field(v));
+    let (mac_addr, iaid, client_id) = match ip {
+      IpAddr::V4(_) => (Some(sliced_str[1].parse()?), None, client_id),
+      IpAddr::V6(_) => {
+        let duid = client_id.map(|v| normalize_duid(&v));
+        (
+          duid.as_deref().and_then(mac_from_duid),
+          sliced_str[1].parse::<u32>().ok(),
+          duid,
+        )
+      }
+    };
+
+    Ok(Lease {
+      ip,
+      mac_addr,
+      hostname: optional_field(sliced_str[3]),
+      client_id,
+      iaid,
+      expires,
+      is_static: false,
+    })
+  }
+}
+
+/// Parses a dnsmasq lease file, skipping lines that failed to parse.
+pub fn parse_dnsmasq_leases(contents: &str) -> Vec<Lease> {
+  contents
+    .lines()
+    .map(|s| s.trim())
+    .filter(|s| !s.is_empty() && !s.starts_with("duid "))
+    .filter_map(|v| match Lease::parse_dnsmasq_line(v) {
+      Ok(lease) => Some(lease),
+      Err(e) => {
+        warn!("Skipping lease: {}", e);
+        counters::record_parse_error();
+        None
+      }
+    })
+    .collect()
+}
+
+/// Reads dnsmasq's leases from the given lease file.
+pub fn read_dnsmasq_leases(path: &Path) -> Result<Vec<Lease>> {
+  let contents = std::fs::read_to_string(path).map_err(|e| {
+    Error::msg(format!(
+      "Failed to read lease file '{}': {}",
+      path.display(),
+      e
+    ))
+  })?;
+  Ok(parse_dnsmasq_leases(&contents))
+}
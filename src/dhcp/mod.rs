@@ -0,0 +1,242 @@
+use crate::device::{Device, DeviceIdentity};
+use crate::neighbors::{CommandRunner, MacAddr, SystemRunner};
+use anyhow::Result;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+pub mod dnsmasq;
+pub mod odhcpd;
+pub mod rogue;
+
+pub use dnsmasq::{read_dnsmasq_leases, DEFAULT_DNSMASQ_LEASE_PATH};
+pub use odhcpd::{mac_from_duid, read_odhcpd_leases, DEFAULT_ODHCPD_LEASE_PATH};
+
+/// A single address handed out by the DHCP (or DHCPv6) server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lease {
+  pub ip: IpAddr,
+  /// Client's MAC address. For DHCPv6 leases, only known when the DUID embeds it.
+  pub mac_addr: Option<MacAddr>,
+  pub hostname: Option<String>,
+  /// DHCP client identifier, or the client's DUID for DHCPv6 leases.
+  pub client_id: Option<String>,
+  /// DHCPv6 identity association the address belongs to.
+  pub iaid: Option<u32>,
+  /// When the lease runs out, or None if it never does.
+  pub expires: Option<SystemTime>,
+  /// Whether the lease was configured as a static host reservation.
+  pub is_static: bool,
+}
+
+impl Lease {
+  /// Whether the lease ran out before the given time.
+  pub fn is_expired_at(&self, now: SystemTime) -> bool {
+    self.expires.is_some_and(|v| v <= now)
+  }
+}
+
+/// A lease file, along with the server that wrote it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LeaseFile {
+  Dnsmasq(PathBuf),
+  Odhcpd(PathBuf),
+}
+
+impl LeaseFile {
+  /// Reads the leases out of the file.
+  pub fn read(&self) -> Result<Vec<Lease>> {
+    match self {
+      LeaseFile::Dnsmasq(path) => read_dnsmasq_leases(path),
+      LeaseFile::Odhcpd(path) => read_odhcpd_leases(path),
+    }
+  }
+}
+
+/// Normalizes a DUID into the bare lowercase hex form used by UCI.
+pub fn normalize_duid(duid: &str) -> String {
+  duid
+    .chars()
+    .filter(|v| *v != ':' && *v != '-')
+    .collect::<String>()
+    .to_lowercase()
+}
+
+/*
+  `uci -q show dhcp` lists the static host reservations as:
+
+  dhcp.@host[0]=host
+  dhcp.@host[0].name='nas'
+  dhcp.@host[0].mac='dc:a6:32:57:46:d6'
+  dhcp.@host[0].ip='192.168.0.10'
+  dhcp.@host[0].duid='000100012a2b7c9ddca6325746d6'
+
+  Where 'mac' may hold several space separated addresses.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StaticHost {
+  pub name: Option<String>,
+  pub mac_addrs: Vec<MacAddr>,
+  pub ip: Option<IpAddr>,
+  pub duid: Option<String>,
+}
+
+///
+/// Parses the static host reservations out of `uci show dhcp` output.
+///
+/// Args:
+///  - stdout: Output of `uci -q show dhcp`.
+///
+/// Returns:
+///  The reservations, in configuration order.
+///
+pub fn parse_static_hosts(stdout: &str) -> Vec<StaticHost> {
+  let mut hosts: Vec<(String, StaticHost)> = Vec::new();
+
+  for line in stdout.lines() {
+    let Some((key, value)) = line.split_once('=') else {
+      continue;
+    };
+    let value = value.trim().trim_matches('\'');
+
+    let mut parts = key.splitn(3, '.');
+    let (Some("dhcp"), Some(section)) = (parts.next(), parts.next()) else {
+      continue;
+    };
+    let option = parts.next();
+
+    // Section declaration, e.g. "dhcp.@host[0]=host".
+    if option.is_none() {
+      if value == "host" {
+        hosts.push((section.to_string(), StaticHost::default()));
+      }
+      continue;
+    }
+
+    let Some((_, host)) = hosts.iter_mut().find(|(name, _)| name == section) else {
+      continue;
+    };
+    match option {
+      Some("name") => host.name = Some(value.to_string()),
+      Some("ip") => host.ip = IpAddr::from_str(value).ok(),
+      Some("duid") => host.duid = Some(normalize_duid(value)),
+      Some("mac") => {
+        host.mac_addrs = value
+          .split_whitespace()
+          .filter_map(|v| match MacAddr::from_str(v) {
+            Ok(mac_addr) => Some(mac_addr),
+            Err(e) => {
+              warn!("Skipping static host MAC '{}': {}", v, e);
+              None
+            }
+          })
+          .collect()
+      }
+      _ => {}
+    }
+  }
+
+  hosts.into_iter().map(|(_, host)| host).collect()
+}
+
+/// Reads the static host reservations from UCI.
+pub fn get_static_hosts() -> Result<Vec<StaticHost>> {
+  get_static_hosts_with(&SystemRunner::new())
+}
+
+/// Reads the static host reservations from UCI, running `uci` through the given runner.
+pub fn get_static_hosts_with(runner: &dyn CommandRunner) -> Result<Vec<StaticHost>> {
+  Ok(parse_static_hosts(
+    &runner.run_args("uci", &["-q", "show", "dhcp"])?,
+  ))
+}
+
+///
+/// Flags the leases matching a static host reservation, by MAC address,
+/// DUID, or reserved address.
+///
+/// Args:
+///  - leases: Leases to flag.
+///  - hosts: Static host reservations.
+///
+pub fn mark_static_leases(leases: &mut [Lease], hosts: &[StaticHost]) {
+  for lease in leases.iter_mut() {
+    lease.is_static = hosts.iter().any(|host| {
+      let by_mac = lease.mac_addr.is_some_and(|v| host.mac_addrs.contains(&v));
+      let by_duid = lease.iaid.is_some() && host.duid.is_some() && lease.client_id == host.duid;
+      let by_ip = host.ip == Some(lease.ip);
+      by_mac || by_duid || by_ip
+    });
+  }
+}
+
+/// Whether a lease belongs to the device, by MAC address or by one of its addresses.
+fn lease_matches(device: &Device, lease: &Lease) -> bool {
+  lease.mac_addr == Some(device.mac_addr) || device.ips().any(|v| v.ip == lease.ip)
+}
+
+///
+/// Attaches each device's most recent DHCP lease, and its DHCPv6 DUID.
+///
+/// Leases are matched by MAC address, or for DHCPv6 leases (which don't
+/// always reveal the MAC) by one of the device's addresses.
+///
+/// Args:
+///  - devices: Devices to attach leases to.
+///  - leases: DHCP and DHCPv6 leases.
+///
+pub fn join_leases(devices: &mut [Device], leases: &[Lease]) {
+  for device in devices.iter_mut() {
+    let matched: Vec<&Lease> = leases.iter().filter(|v| lease_matches(device, v)).collect();
+
+    // A never expiring lease outlives any other.
+    let lease = matched
+      .iter()
+      .max_by_key(|v| v.expires.map_or((1, SystemTime::UNIX_EPOCH), |t| (0, t)));
+    if let Some(lease) = lease {
+      debug!("{} has lease {:?}", device.mac_addr, lease);
+      device.lease = Some((*lease).clone());
+    }
+
+    device.duid = matched
+      .iter()
+      .filter(|v| v.iaid.is_some())
+      .find_map(|v| v.client_id.clone());
+  }
+}
+
+///
+/// Collects the DHCP hints (hostname and DUID) of every device, for linking
+/// rotating MACs.
+///
+/// Args:
+///  - devices: Devices grouped by MAC.
+///  - leases: DHCP and DHCPv6 leases.
+///
+/// Returns:
+///  The hints, keyed by MAC.
+///
+pub fn identities_from_leases(
+  devices: &[Device],
+  leases: &[Lease],
+) -> HashMap<MacAddr, DeviceIdentity> {
+  let mut identities: HashMap<MacAddr, DeviceIdentity> = HashMap::new();
+  for device in devices {
+    for lease in leases.iter().filter(|v| lease_matches(device, v)) {
+      let identity = identities.entry(device.mac_addr).or_default();
+      if identity.hostname.is_none() {
+        identity.hostname = lease.hostname.clone();
+      }
+      if identity.duid.is_none() && lease.iaid.is_some() {
+        identity.duid = lease.client_id.clone();
+      }
+    }
+  }
+  identities
+}
@@ -0,0 +1,159 @@
+use super::{normalize_duid, Lease};
+use crate::counters;
+use crate::neighbors::MacAddr;
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Where OpenWrt's odhcpd keeps its leases by default ('leasefile' in /etc/config/dhcp).
+pub const DEFAULT_ODHCPD_LEASE_PATH: &str = "/tmp/hosts/odhcpd";
+
+/*
+  odhcpd writes one lease per line, prefixed by '#' so the file doubles as a
+  hosts file, followed by an "<address> <hostname>" line per leased address:
+
+  # br-lan 000100012a2b7c9ddca6325746d6 5746d6 raspberrypi 1718049305 3c1 128 fd00::3c1/128 2001:db8::3c1/128
+  # br-lan 244bfe06f83c ipv4 nas -1 a 32 192.168.0.10/32
+  fd00::3c1	raspberrypi
+
+  So we're parsing:
+  # <iface> <duid|mac> <iaid|ipv4> <hostname|-> <expiry> <assigned> <length> <address/prefix>...
+
+  Where an expiry of -1 means the lease never runs out.
+*/
+
+///
+/// Extracts the link layer address out of a DUID-LLT (type 1) or DUID-LL
+/// (type 3) DUID of an ethernet client.
+///
+/// Args:
+///  - duid: Bare hex DUID.
+///
+/// Returns:
+///  The MAC address, or None if the DUID doesn't embed one.
+///
+pub fn mac_from_duid(duid: &str) -> Option<MacAddr> {
+  let duid = normalize_duid(duid);
+  let prefix_len = match (duid.get(0..4)?, duid.get(4..8)?) {
+    // <type> <hardware type> <time> <mac>
+    ("0001", "0001") => 16,
+    // <type> <hardware type> <mac>
+    ("0003", "0001") => 8,
+    _ => return None,
+  };
+  if duid.len() != prefix_len + 12 {
+    return None;
+  }
+  MacAddr::from_str(&duid[prefix_len..]).ok()
+}
+
+///
+/// Parses a lease line of odhcpd's lease file, into one lease per address.
+///
+/// Args:
+///  - s: Row as a string.
+///
+/// Returns:
+///  Result of the leases.
+///
+/// ```
+/// use openwrt_netmon::dhcp::odhcpd::parse_odhcpd_line;
+///
+/// let leases = parse_odhcpd_line(
+///   "# br-lan 000100012a2b7c9ddca6325746d6 5746d6 raspberrypi -1 3c1 128 fd00::3c1/128 2001:db8::3c1/128",
+/// )?;
+/// assert_eq!(leases.len(), 2);
+/// assert_eq!(leases[0].client_id.as_deref(), Some("000100012a2b7c9ddca6325746d6"));
+/// assert_eq!(leases[0].mac_addr.map(|v| v.to_string()).as_deref(), Some("dc:a6:32:57:46:d6"));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+pub fn parse_odhcpd_line(s: &str) -> Result<Vec<Lease>> {
+  let sliced_str: Vec<&str> = s.split_whitespace().collect();
+  debug!("Sliced string -> {:?}", sliced_str);
+
+  if sliced_str.len() < 9 || sliced_str[0] != "#" {
+    return Err(Error::msg(format!("Unexpected string -> {}", s)));
+  }
+
+  let expiry = i64::from_str(sliced_str[5])
+    .map_err(|e| Error::msg(format!("Invalid lease expiry '{}': {}", sliced_str[5], e)))?;
+  let expires = match expiry {
+    e if e < 0 => None,
+    secs => Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64)),
+  };
+  let hostname = match sliced_str[4] {
+    "-" | "" => None,
+    v => Some(v.to_string()),
+  };
+
+  // DHCPv4 leases carry the client's MAC where DHCPv6 ones carry its DUID.
+  let (mac_addr, client_id, iaid) = match sliced_str[3] {
+    "ipv4" => (MacAddr::from_str(sliced_str[2]).ok(), None, None),
+    iaid => {
+      let duid = normalize_duid(sliced_str[2]);
+      (
+        mac_from_duid(&duid),
+        Some(duid),
+        u32::from_str_radix(iaid, 16).ok(),
+      )
+    }
+  };
+
+  let leases = sliced_str[8..]
+    .iter()
+    .filter_map(|v| {
+      let ip_str = v.split('/').next()?;
+      match IpAddr::from_str(ip_str) {
+        Ok(ip) => Some(Lease {
+          ip,
+          mac_addr,
+          hostname: hostname.clone(),
+          client_id: client_id.clone(),
+          iaid,
+          expires,
+          is_static: false,
+        }),
+        Err(e) => {
+          warn!("Skipping lease address '{}': {}", v, e);
+          None
+        }
+      }
+    })
+    .collect();
+
+  Ok(leases)
+}
+
+/// Parses an odhcpd lease file, skipping lines that failed to parse.
+pub fn parse_odhcpd_leases(contents: &str) -> Vec<Lease> {
+  contents
+    .lines()
+    .map(|s| s.trim())
+    // Only the '#' lines are leases, the rest is the hosts file half.
+    .filter(|s| s.starts_with('#'))
+    .flat_map(|v| match parse_odhcpd_line(v) {
+      Ok(leases) => leases,
+      Err(e) => {
+        warn!("Skipping lease: {}", e);
+        counters::record_parse_error();
+        Vec::new()
+      }
+    })
+    .collect()
+}
+
+/// Reads odhcpd's leases from the given lease file.
+pub fn read_odhcpd_leases(path: &Path) -> Result<Vec<Lease>> {
+  let contents = std::fs::read_to_string(path).map_err(|e| {
+    Error::msg(format!(
+      "Failed to read lease file '{}': {}",
+      path.display(),
+      e
+    ))
+  })?;
+  Ok(parse_odhcpd_leases(&contents))
+}
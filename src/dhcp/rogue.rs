@@ -0,0 +1,441 @@
+use crate::neighbors::route::local_addresses;
+use crate::neighbors::MacAddr;
+use anyhow::{Error, Result};
+use log::{debug, info, warn};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Port DHCP servers listen on.
+pub const DHCP_SERVER_PORT: u16 = 67;
+
+/// Port DHCP clients listen on, offers being sent to it.
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Offers kept between polls at most, the oldest being dropped for newer
+/// ones.
+pub const MAX_OFFERS: usize = 100;
+
+/// Longest time a receive blocks for, between checks of whether the watch
+/// was stopped and probes are due.
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Fixed part of a BOOTP message, up to the magic cookie.
+const BOOTP_LEN: usize = 236;
+
+/// Smallest message BOOTP relays and servers have to accept.
+const MIN_MESSAGE_LEN: usize = 300;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+
+/// Ethernet hardware type.
+const HTYPE_ETHERNET: u8 = 1;
+
+/// Flag asking servers to broadcast their answers.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+
+/*
+  https://datatracker.ietf.org/doc/html/rfc2131#section-2
+
+  DHCP messages are BOOTP messages, their options following a magic cookie:
+
+    u8 op; u8 htype; u8 hlen; u8 hops; u32 xid; u16 secs; u16 flags;
+    u32 ciaddr; u32 yiaddr; u32 siaddr; u32 giaddr; u8 chaddr[16];
+    u8 sname[64]; u8 file[128]; u8 cookie[4]; { u8 code; u8 len; ... }
+
+  Offers are sent to the client port, broadcast when the client set the
+  broadcast flag, or else unicast to its MAC address, which the router only
+  sees through its own ports. The watch hears the broadcast ones, and probes
+  ask every server for one with a DHCPDISCOVER of the router's own:
+
+    router  -> 255.255.255.255:67  DHCPDISCOVER, broadcast flag
+    server  -> 255.255.255.255:68  DHCPOFFER, server identifier 192.168.1.50
+
+  Offers of the router's own addresses, i.e. of its own server, are dropped.
+*/
+
+/// An offer of a DHCP server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpOffer {
+  /// Server identifier, or else the address the offer came from.
+  pub server: Ipv4Addr,
+  /// Address offered to the client.
+  pub offered: Ipv4Addr,
+  /// Gateway the server hands out, if any.
+  pub router: Option<Ipv4Addr>,
+  /// Client the offer is for.
+  pub client: MacAddr,
+}
+
+///
+/// Builds a DHCPDISCOVER asking for a broadcast answer.
+///
+/// Args:
+///  - xid: Transaction id answers repeat.
+///  - mac_addr: Client's MAC address.
+///
+pub fn encode_discover(xid: u32, mac_addr: MacAddr) -> Vec<u8> {
+  let mut msg = vec![0u8; BOOTP_LEN];
+  msg[0] = BOOTREQUEST;
+  msg[1] = HTYPE_ETHERNET;
+  msg[2] = 6;
+  msg[4..8].copy_from_slice(&xid.to_be_bytes());
+  msg[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+  msg[28..34].copy_from_slice(&mac_addr.octets());
+  msg.extend_from_slice(&MAGIC_COOKIE);
+  msg.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER]);
+  msg.extend_from_slice(&[OPTION_PARAMETERS, 2, OPTION_ROUTER, OPTION_SERVER_ID]);
+  msg.push(OPTION_END);
+  msg.resize(MIN_MESSAGE_LEN, OPTION_PAD);
+  msg
+}
+
+///
+/// Parses a DHCPOFFER.
+///
+/// ```
+/// use openwrt_netmon::dhcp::rogue::{encode_discover, parse_offer};
+/// use std::net::Ipv4Addr;
+///
+/// // Turn a discover into the offer a server would answer it with.
+/// let mut msg = encode_discover(7, "02:00:00:00:00:01".parse()?);
+/// msg[0] = 2;
+/// msg[16..20].copy_from_slice(&[192, 168, 1, 123]);
+/// msg[242] = 2;
+/// msg[243..249].copy_from_slice(&[54, 4, 192, 168, 1, 50]);
+/// msg[249] = 255;
+///
+/// let offer = parse_offer(&msg, Ipv4Addr::new(192, 168, 1, 50))?;
+/// assert_eq!(offer.server, Ipv4Addr::new(192, 168, 1, 50));
+/// assert_eq!(offer.offered, Ipv4Addr::new(192, 168, 1, 123));
+/// assert!(parse_offer(&encode_discover(7, offer.client), offer.server).is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Args:
+///  - buf: Received datagram.
+///  - source: Address it came from, the server unless it names another.
+///
+/// Returns:
+///  Result of the offer, failing for other messages.
+///
+pub fn parse_offer(buf: &[u8], source: Ipv4Addr) -> Result<DhcpOffer> {
+  let not_offer = || Error::msg("Not a DHCP offer");
+  if buf.len() < BOOTP_LEN + MAGIC_COOKIE.len() || buf[0] != BOOTREPLY {
+    return Err(not_offer());
+  }
+  if buf[BOOTP_LEN..BOOTP_LEN + 4] != MAGIC_COOKIE {
+    return Err(not_offer());
+  }
+  let ipv4 = |v: &[u8]| Ipv4Addr::new(v[0], v[1], v[2], v[3]);
+  let mut message_type = None;
+  let mut server = None;
+  let mut router = None;
+  let mut offset = BOOTP_LEN + 4;
+  while let Some(&code) = buf.get(offset) {
+    match code {
+      OPTION_PAD => {
+        offset += 1;
+        continue;
+      }
+      OPTION_END => break,
+      _ => {}
+    }
+    let Some(&len) = buf.get(offset + 1) else {
+      break;
+    };
+    let Some(value) = buf.get(offset + 2..offset + 2 + len as usize) else {
+      return Err(Error::msg("Truncated DHCP option"));
+    };
+    match code {
+      OPTION_MESSAGE_TYPE if len == 1 => message_type = Some(value[0]),
+      OPTION_SERVER_ID if len == 4 => server = Some(ipv4(value)),
+      OPTION_ROUTER if len >= 4 => router = Some(ipv4(value)),
+      _ => {}
+    }
+    offset += 2 + len as usize;
+  }
+  if message_type != Some(DHCPOFFER) {
+    return Err(not_offer());
+  }
+  Ok(DhcpOffer {
+    server: server.unwrap_or(source),
+    offered: ipv4(&buf[16..20]),
+    router,
+    client: MacAddr::from_bytes(&buf[28..34]).ok_or_else(not_offer)?,
+  })
+}
+
+///
+/// Listens for the offers of DHCP servers other than the router's own, on
+/// a thread per interface, and optionally probes for them.
+///
+/// ```no_run
+/// use openwrt_netmon::dhcp::rogue::RogueDhcpWatch;
+/// use std::time::Duration;
+///
+/// let mut watch = RogueDhcpWatch::new(vec!["br-lan".to_string()])
+///   .probe_interval(Duration::from_secs(300));
+/// watch.start();
+/// std::thread::sleep(Duration::from_secs(5));
+/// for (iface, offer) in watch.take() {
+///   println!("{} offers {} on {}", offer.server, offer.offered, iface);
+/// }
+/// ```
+///
+pub struct RogueDhcpWatch {
+  interfaces: Vec<String>,
+  /// Servers besides the router's own, that aren't rogue.
+  servers: Vec<Ipv4Addr>,
+  probe_interval: Option<Duration>,
+  on_offer: Option<Arc<dyn Fn() + Send + Sync>>,
+  /// Offers received since the last take, along with their interface.
+  offers: Arc<Mutex<Vec<(String, DhcpOffer)>>>,
+  stopped: Arc<AtomicBool>,
+  started: bool,
+}
+
+impl RogueDhcpWatch {
+  /// Creates a watch, listening on interfaces such as "br-lan" once
+  /// started.
+  pub fn new(interfaces: Vec<String>) -> Self {
+    RogueDhcpWatch {
+      interfaces,
+      servers: Vec::new(),
+      probe_interval: None,
+      on_offer: None,
+      offers: Arc::new(Mutex::new(Vec::new())),
+      stopped: Arc::new(AtomicBool::new(false)),
+      started: false,
+    }
+  }
+
+  /// DHCP servers besides the router's own whose offers are dropped, e.g.
+  /// of a Pi-hole serving DHCP.
+  pub fn servers(mut self, servers: Vec<Ipv4Addr>) -> Self {
+    self.servers = servers;
+    self
+  }
+
+  /// Sends a DHCPDISCOVER on every interface this often, none by default.
+  pub fn probe_interval(mut self, probe_interval: Duration) -> Self {
+    self.probe_interval = Some(probe_interval);
+    self
+  }
+
+  /// Called from the watch's threads on every offer of a rogue server, e.g.
+  /// to poll right away.
+  pub fn on_offer(mut self, on_offer: Box<dyn Fn() + Send + Sync>) -> Self {
+    self.on_offer = Some(Arc::from(on_offer));
+    self
+  }
+
+  /// Starts the threads listening on every interface, unless they were
+  /// already. Interfaces failing to be listened on are logged and skipped.
+  pub fn start(&mut self) {
+    if std::mem::replace(&mut self.started, true) {
+      return;
+    }
+    for iface in &self.interfaces {
+      let listener = Listener {
+        iface: iface.clone(),
+        servers: self.servers.clone(),
+        probe_interval: self.probe_interval,
+        on_offer: self.on_offer.clone(),
+        offers: self.offers.clone(),
+        stopped: self.stopped.clone(),
+      };
+      let spawned = listener.open().and_then(|socket| {
+        std::thread::Builder::new()
+          .name(format!("dhcp-{}", iface))
+          .spawn(move || listener.run(socket))
+          .map_err(|e| Error::msg(format!("Failed to spawn the thread: {}", e)))
+      });
+      match spawned {
+        Ok(_) => info!("Watching for rogue DHCP servers on {}", iface),
+        Err(err) => warn!("Not watching for rogue DHCP servers on {}: {}", iface, err),
+      }
+    }
+  }
+
+  /// Takes the offers of rogue servers received since the last call, along
+  /// with the interfaces they were received on, oldest first.
+  pub fn take(&self) -> Vec<(String, DhcpOffer)> {
+    std::mem::take(&mut *self.offers.lock().unwrap())
+  }
+}
+
+impl Drop for RogueDhcpWatch {
+  fn drop(&mut self) {
+    self.stopped.store(true, Ordering::Relaxed);
+  }
+}
+
+impl std::fmt::Debug for RogueDhcpWatch {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("RogueDhcpWatch")
+      .field("interfaces", &self.interfaces)
+      .field("servers", &self.servers)
+      .field("probe_interval", &self.probe_interval)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Thread of a watch listening on an interface.
+struct Listener {
+  iface: String,
+  servers: Vec<Ipv4Addr>,
+  probe_interval: Option<Duration>,
+  on_offer: Option<Arc<dyn Fn() + Send + Sync>>,
+  offers: Arc<Mutex<Vec<(String, DhcpOffer)>>>,
+  stopped: Arc<AtomicBool>,
+}
+
+impl Listener {
+  /// Opens the client port on the interface, shared with its DHCP client
+  /// should it run one.
+  fn open(&self) -> Result<UdpSocket> {
+    let failed = |what: &str| {
+      Error::msg(format!(
+        "Failed to {}: {}",
+        what,
+        std::io::Error::last_os_error()
+      ))
+    };
+    let raw_fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if raw_fd < 0 {
+      return Err(failed("open a socket"));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+    let set = |name: libc::c_int, value: &[u8]| unsafe {
+      libc::setsockopt(
+        fd.as_raw_fd(),
+        libc::SOL_SOCKET,
+        name,
+        value.as_ptr() as *const libc::c_void,
+        value.len() as libc::socklen_t,
+      ) == 0
+    };
+    let on = 1 as libc::c_int;
+    if !set(libc::SO_REUSEADDR, &on.to_ne_bytes()) {
+      return Err(failed("reuse the client port"));
+    }
+    if !set(libc::SO_BINDTODEVICE, self.iface.as_bytes()) {
+      return Err(failed("bind to the interface"));
+    }
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_port = DHCP_CLIENT_PORT.to_be();
+    let ret = unsafe {
+      libc::bind(
+        fd.as_raw_fd(),
+        &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+        std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+      )
+    };
+    if ret < 0 {
+      return Err(failed("bind the client port"));
+    }
+    let socket = UdpSocket::from(fd);
+    socket
+      .set_broadcast(true)
+      .and_then(|_| socket.set_read_timeout(Some(RECV_TIMEOUT)))
+      .map_err(|e| Error::msg(format!("Failed to configure the socket: {}", e)))?;
+    Ok(socket)
+  }
+
+  /// Receives offers until the watch is stopped, probing when due.
+  fn run(self, socket: UdpSocket) {
+    let mut next_probe = Instant::now();
+    let mut buf = [0u8; 1500];
+    while !self.stopped.load(Ordering::Relaxed) {
+      if let Some(interval) = self.probe_interval.filter(|_| Instant::now() >= next_probe) {
+        next_probe = Instant::now() + interval;
+        if let Err(err) = self.probe(&socket) {
+          warn!(
+            "Failed to probe for DHCP servers on {}: {}",
+            self.iface, err
+          );
+        }
+      }
+      let (len, src) = match socket.recv_from(&mut buf) {
+        Ok(v) => v,
+        Err(e)
+          if matches!(
+            e.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+          ) =>
+        {
+          continue
+        }
+        Err(e) => {
+          warn!("Failed to receive on {}: {}", self.iface, e);
+          std::thread::sleep(RECV_TIMEOUT);
+          continue;
+        }
+      };
+      let SocketAddr::V4(src) = src else {
+        continue;
+      };
+      let offer = match parse_offer(&buf[..len], *src.ip()) {
+        Ok(offer) => offer,
+        Err(e) => {
+          debug!("Skipping a DHCP message from {}: {}", src, e);
+          continue;
+        }
+      };
+      let local = local_addresses().contains(&IpAddr::V4(offer.server));
+      if self.servers.contains(&offer.server) || local {
+        debug!(
+          "Offer of {} from {} on {}",
+          offer.offered, offer.server, self.iface
+        );
+        continue;
+      }
+      {
+        let mut offers = self.offers.lock().unwrap();
+        if offers.len() == MAX_OFFERS {
+          offers.remove(0);
+        }
+        offers.push((self.iface.clone(), offer));
+      }
+      if let Some(on_offer) = &self.on_offer {
+        on_offer();
+      }
+    }
+  }
+
+  /// Broadcasts a DHCPDISCOVER from the interface's MAC address.
+  fn probe(&self, socket: &UdpSocket) -> Result<()> {
+    let path = format!("/sys/class/net/{}/address", self.iface);
+    let mac_addr: MacAddr = std::fs::read_to_string(&path)
+      .map_err(|e| Error::msg(format!("Failed to read {}: {}", path, e)))?
+      .trim()
+      .parse()?;
+    let xid = SystemTime::now()
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .unwrap_or_default()
+      .subsec_nanos();
+    let dest = SocketAddr::from((Ipv4Addr::BROADCAST, DHCP_SERVER_PORT));
+    socket
+      .send_to(&encode_discover(xid, mac_addr), dest)
+      .map_err(|e| Error::msg(format!("Failed to send: {}", e)))?;
+    debug!("Probed for DHCP servers on {}", self.iface);
+    Ok(())
+  }
+}
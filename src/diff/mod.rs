@@ -0,0 +1,206 @@
+use crate::neighbors::{self, ArpTable, MacAddr, NudState, ScopedIpAddr};
+use anyhow::Result;
+use std::collections::{BTreeSet, HashMap};
+use std::time::SystemTime;
+
+///
+/// Neighbor table as seen at a single point in time.
+///
+/// ```
+/// use openwrt_netmon::{ArpTable, Snapshot};
+///
+/// let old = Snapshot::new(vec![ArpTable::parse_from_string(
+///   "192.168.0.5 dev br-lan lladdr dc:a6:32:a3:48:b1 REACHABLE",
+/// )?]);
+/// let new = Snapshot::new(vec![
+///   ArpTable::parse_from_string("192.168.0.5 dev br-lan lladdr dc:a6:32:a3:48:b1 STALE")?,
+///   ArpTable::parse_from_string("192.168.0.8 dev br-lan lladdr 88:66:5a:49:16:b3 REACHABLE")?,
+/// ]);
+///
+/// let diff = Snapshot::diff(&old, &new);
+/// assert_eq!(diff.joined.len(), 1);
+/// assert_eq!(diff.state_changed.len(), 1);
+/// assert!(diff.left.is_empty());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+  pub taken_at: SystemTime,
+  pub entries: Vec<ArpTable>,
+}
+
+/// A device (MAC address) appearing in or disappearing from the table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DevicePresence {
+  pub mac_addr: MacAddr,
+  pub iface: String,
+  pub ips: Vec<ScopedIpAddr>,
+}
+
+/// A device whose set of addresses changed between polls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IpChange {
+  pub mac_addr: MacAddr,
+  pub added: Vec<ScopedIpAddr>,
+  pub removed: Vec<ScopedIpAddr>,
+}
+
+/// An address now answering from a different MAC address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MacChange {
+  pub ip: ScopedIpAddr,
+  pub old_mac_addr: Option<MacAddr>,
+  pub new_mac_addr: Option<MacAddr>,
+}
+
+/// An entry whose NUD state changed between polls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateChange {
+  pub ip: ScopedIpAddr,
+  pub mac_addr: Option<MacAddr>,
+  pub old_state: NudState,
+  pub new_state: NudState,
+}
+
+/// Every change between two snapshots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NeighborDiff {
+  pub joined: Vec<DevicePresence>,
+  pub left: Vec<DevicePresence>,
+  pub ip_changed: Vec<IpChange>,
+  pub mac_changed: Vec<MacChange>,
+  pub state_changed: Vec<StateChange>,
+}
+
+impl NeighborDiff {
+  /// Whether nothing changed.
+  pub fn is_empty(&self) -> bool {
+    self.joined.is_empty()
+      && self.left.is_empty()
+      && self.ip_changed.is_empty()
+      && self.mac_changed.is_empty()
+      && self.state_changed.is_empty()
+  }
+}
+
+/// Groups entries by MAC address, skipping entries that were never resolved.
+fn group_by_mac(entries: &[ArpTable]) -> HashMap<MacAddr, Vec<&ArpTable>> {
+  let mut devices: HashMap<MacAddr, Vec<&ArpTable>> = HashMap::new();
+  for entry in entries {
+    if let Some(mac_addr) = entry.mac_addr {
+      devices.entry(mac_addr).or_default().push(entry);
+    }
+  }
+  devices
+}
+
+/// Indexes entries by their interface scoped address.
+fn index_by_ip(entries: &[ArpTable]) -> HashMap<ScopedIpAddr, &ArpTable> {
+  entries.iter().map(|v| (v.scoped_ip(), v)).collect()
+}
+
+fn device_presence(mac_addr: MacAddr, entries: &[&ArpTable]) -> DevicePresence {
+  let ips: BTreeSet<ScopedIpAddr> = entries.iter().map(|v| v.scoped_ip()).collect();
+  DevicePresence {
+    mac_addr,
+    iface: entries[0].iface.clone(),
+    ips: ips.into_iter().collect(),
+  }
+}
+
+impl Snapshot {
+  pub fn new(entries: Vec<ArpTable>) -> Self {
+    Snapshot {
+      taken_at: SystemTime::now(),
+      entries,
+    }
+  }
+
+  /// Takes a snapshot of the host's current neighbor table.
+  pub fn capture() -> Result<Self> {
+    Ok(Snapshot::new(neighbors::collect_neighbors()?))
+  }
+
+  ///
+  /// Computes the changes needed to go from one snapshot to the next.
+  ///
+  /// Devices are matched by MAC address for joins, leaves, and address
+  /// changes, while individual entries are matched by interface scoped
+  /// address for MAC and state changes.
+  ///
+  /// Args:
+  ///  - old: Earlier snapshot.
+  ///  - new: Later snapshot.
+  ///
+  /// Returns:
+  ///  The differences, sorted for stable output.
+  ///
+  pub fn diff(old: &Snapshot, new: &Snapshot) -> NeighborDiff {
+    let mut diff = NeighborDiff::default();
+
+    let old_devices = group_by_mac(&old.entries);
+    let new_devices = group_by_mac(&new.entries);
+    for (mac_addr, entries) in &new_devices {
+      match old_devices.get(mac_addr) {
+        None => diff.joined.push(device_presence(*mac_addr, entries)),
+        Some(old_entries) => {
+          let old_ips: BTreeSet<ScopedIpAddr> = old_entries.iter().map(|v| v.scoped_ip()).collect();
+          let new_ips: BTreeSet<ScopedIpAddr> = entries.iter().map(|v| v.scoped_ip()).collect();
+          if old_ips != new_ips {
+            diff.ip_changed.push(IpChange {
+              mac_addr: *mac_addr,
+              added: new_ips.difference(&old_ips).cloned().collect(),
+              removed: old_ips.difference(&new_ips).cloned().collect(),
+            });
+          }
+        }
+      }
+    }
+    for (mac_addr, entries) in &old_devices {
+      if !new_devices.contains_key(mac_addr) {
+        diff.left.push(device_presence(*mac_addr, entries));
+      }
+    }
+
+    let old_ips = index_by_ip(&old.entries);
+    for (ip, entry) in index_by_ip(&new.entries) {
+      let Some(old_entry) = old_ips.get(&ip) else {
+        continue;
+      };
+
+      // An unresolved entry losing its address isn't a different device answering.
+      if old_entry.mac_addr != entry.mac_addr
+        && old_entry.mac_addr.is_some()
+        && entry.mac_addr.is_some()
+      {
+        diff.mac_changed.push(MacChange {
+          ip: ip.clone(),
+          old_mac_addr: old_entry.mac_addr,
+          new_mac_addr: entry.mac_addr,
+        });
+      }
+      if old_entry.nud_state != entry.nud_state {
+        diff.state_changed.push(StateChange {
+          ip,
+          mac_addr: entry.mac_addr,
+          old_state: old_entry.nud_state,
+          new_state: entry.nud_state,
+        });
+      }
+    }
+
+    diff.joined.sort_by_key(|v| v.mac_addr);
+    diff.left.sort_by_key(|v| v.mac_addr);
+    diff.ip_changed.sort_by_key(|v| v.mac_addr);
+    diff.mac_changed.sort_by(|a, b| a.ip.cmp(&b.ip));
+    diff.state_changed.sort_by(|a, b| a.ip.cmp(&b.ip));
+    diff
+  }
+}
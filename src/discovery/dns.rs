@@ -0,0 +1,304 @@
+use anyhow::{Error, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/*
+  https://datatracker.ietf.org/doc/html/rfc1035#section-4
+
+  DNS (and mDNS/LLMNR/NBNS) messages are a 12 byte header followed by the
+  question, answer, authority, and additional sections:
+
+    struct header { u16 id; u16 flags; u16 qdcount; u16 ancount; u16 nscount; u16 arcount; }
+    question      { <name> u16 type; u16 class; }
+    record        { <name> u16 type; u16 class; u32 ttl; u16 rdlength; <rdata> }
+
+  Names are a sequence of length prefixed labels ending in a 0 length label,
+  or a 2 byte pointer (top bits set) back to a name earlier in the message.
+  Every integer is big endian.
+*/
+const HEADER_LEN: usize = 12;
+
+/// Longest chain of compression pointers followed before giving up on a name.
+const MAX_POINTER_HOPS: usize = 32;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const CLASS_IN: u16 = 1;
+
+/// Record payloads we know how to decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+  A(Ipv4Addr),
+  Aaaa(Ipv6Addr),
+  Ptr(String),
+  Srv {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+  },
+  Txt(Vec<String>),
+  Other(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuestion {
+  pub name: String,
+  pub qtype: u16,
+  pub qclass: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+  pub name: String,
+  pub rtype: u16,
+  pub class: u16,
+  pub ttl: u32,
+  pub data: RecordData,
+}
+
+/// A parsed message, with the answer, authority, and additional sections flattened into `records`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsMessage {
+  pub id: u16,
+  pub flags: u16,
+  pub questions: Vec<DnsQuestion>,
+  pub records: Vec<DnsRecord>,
+}
+
+pub(crate) fn read_u16(buf: &[u8], offset: usize) -> Result<u16> {
+  buf
+    .get(offset..offset + 2)
+    .map(|v| u16::from_be_bytes([v[0], v[1]]))
+    .ok_or_else(|| Error::msg("Truncated DNS message"))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32> {
+  buf
+    .get(offset..offset + 4)
+    .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+    .ok_or_else(|| Error::msg("Truncated DNS message"))
+}
+
+///
+/// Appends a dotted name as a sequence of labels.
+///
+/// Args:
+///  - buf: Message being built.
+///  - name: Dotted name, e.g. "_services._dns-sd._udp.local".
+///
+pub fn push_name(buf: &mut Vec<u8>, name: &str) {
+  for label in name
+    .trim_end_matches('.')
+    .split('.')
+    .filter(|v| !v.is_empty())
+  {
+    let label = &label.as_bytes()[..label.len().min(63)];
+    buf.push(label.len() as u8);
+    buf.extend_from_slice(label);
+  }
+  buf.push(0);
+}
+
+///
+/// Reads a (possibly compressed) name.
+///
+/// Args:
+///  - buf: Whole message, as pointers are relative to its start.
+///  - offset: Where the name starts.
+///
+/// Returns:
+///  Result of the dotted name, and the offset right after it.
+///
+pub fn read_name(buf: &[u8], offset: usize) -> Result<(String, usize)> {
+  let mut labels: Vec<String> = Vec::new();
+  let mut pos = offset;
+  let mut end = None;
+
+  for _ in 0..MAX_POINTER_HOPS {
+    loop {
+      let len = *buf
+        .get(pos)
+        .ok_or_else(|| Error::msg("Truncated DNS name"))? as usize;
+
+      if len & 0xc0 == 0xc0 {
+        let pointer = (read_u16(buf, pos)? & 0x3fff) as usize;
+        end.get_or_insert(pos + 2);
+        pos = pointer;
+        break;
+      }
+      if len == 0 {
+        let name = labels.join(".");
+        return Ok((name, end.unwrap_or(pos + 1)));
+      }
+
+      let label = buf
+        .get(pos + 1..pos + 1 + len)
+        .ok_or_else(|| Error::msg("Truncated DNS label"))?;
+      labels.push(String::from_utf8_lossy(label).into_owned());
+      pos += 1 + len;
+    }
+  }
+
+  Err(Error::msg("Too many DNS name compression pointers"))
+}
+
+///
+/// Builds a query message.
+///
+/// Args:
+///  - id: Transaction ID.
+///  - flags: Header flags (e.g. 0x0100 to request recursion, 0 for mDNS).
+///  - questions: Questions to ask.
+///
+/// Returns:
+///  The encoded message.
+///
+pub fn encode_query(id: u16, flags: u16, questions: &[DnsQuestion]) -> Vec<u8> {
+  let mut buf = Vec::with_capacity(HEADER_LEN + questions.len() * 32);
+  buf.extend_from_slice(&id.to_be_bytes());
+  buf.extend_from_slice(&flags.to_be_bytes());
+  buf.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+  buf.extend_from_slice(&[0; 6]);
+
+  for question in questions {
+    push_name(&mut buf, &question.name);
+    buf.extend_from_slice(&question.qtype.to_be_bytes());
+    buf.extend_from_slice(&question.qclass.to_be_bytes());
+  }
+  buf
+}
+
+/// Decodes a record's payload.
+fn parse_record_data(buf: &[u8], rtype: u16, offset: usize, len: usize) -> Result<RecordData> {
+  let rdata = buf
+    .get(offset..offset + len)
+    .ok_or_else(|| Error::msg("Truncated DNS record"))?;
+
+  let data = match rtype {
+    TYPE_A if len == 4 => RecordData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+    TYPE_AAAA if len == 16 => {
+      let mut octets = [0u8; 16];
+      octets.copy_from_slice(rdata);
+      RecordData::Aaaa(Ipv6Addr::from(octets))
+    }
+    TYPE_PTR => RecordData::Ptr(read_name(buf, offset)?.0),
+    TYPE_SRV if len >= 7 => RecordData::Srv {
+      priority: read_u16(buf, offset)?,
+      weight: read_u16(buf, offset + 2)?,
+      port: read_u16(buf, offset + 4)?,
+      target: read_name(buf, offset + 6)?.0,
+    },
+    TYPE_TXT => {
+      let mut strings = Vec::new();
+      let mut pos = 0;
+      while pos < rdata.len() {
+        let str_len = rdata[pos] as usize;
+        let Some(s) = rdata.get(pos + 1..pos + 1 + str_len) else {
+          break;
+        };
+        strings.push(String::from_utf8_lossy(s).into_owned());
+        pos += 1 + str_len;
+      }
+      RecordData::Txt(strings)
+    }
+    _ => RecordData::Other(rdata.to_vec()),
+  };
+  Ok(data)
+}
+
+impl DnsMessage {
+  ///
+  /// Parses a message off the wire.
+  ///
+  /// Args:
+  ///  - buf: Received datagram.
+  ///
+  /// Returns:
+  ///  Result of the parsed message.
+  ///
+  pub fn parse(buf: &[u8]) -> Result<Self> {
+    if buf.len() < HEADER_LEN {
+      return Err(Error::msg("Truncated DNS header"));
+    }
+    let id = read_u16(buf, 0)?;
+    let flags = read_u16(buf, 2)?;
+    let qdcount = read_u16(buf, 4)?;
+    let record_count =
+      read_u16(buf, 6)? as usize + read_u16(buf, 8)? as usize + read_u16(buf, 10)? as usize;
+
+    let mut offset = HEADER_LEN;
+    let mut questions = Vec::new();
+    for _ in 0..qdcount {
+      let (name, next) = read_name(buf, offset)?;
+      questions.push(DnsQuestion {
+        name,
+        qtype: read_u16(buf, next)?,
+        qclass: read_u16(buf, next + 2)?,
+      });
+      offset = next + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..record_count {
+      let (name, next) = read_name(buf, offset)?;
+      let rtype = read_u16(buf, next)?;
+      let class = read_u16(buf, next + 2)?;
+      let ttl = read_u32(buf, next + 4)?;
+      let rdlength = read_u16(buf, next + 8)? as usize;
+      let data = parse_record_data(buf, rtype, next + 10, rdlength)?;
+
+      records.push(DnsRecord {
+        name,
+        rtype,
+        class,
+        ttl,
+        data,
+      });
+      offset = next + 10 + rdlength;
+    }
+
+    Ok(DnsMessage {
+      id,
+      flags,
+      questions,
+      records,
+    })
+  }
+
+  /// Whether this is a response, rather than a query.
+  pub fn is_response(&self) -> bool {
+    self.flags & 0x8000 != 0
+  }
+}
+
+///
+/// Builds the reverse lookup name of an address.
+///
+/// ```
+/// use openwrt_netmon::discovery::dns::reverse_name;
+///
+/// assert_eq!(reverse_name(&"192.168.0.5".parse()?), "5.0.168.192.in-addr.arpa");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+pub fn reverse_name(ip: &IpAddr) -> String {
+  match ip {
+    IpAddr::V4(v4) => {
+      let o = v4.octets();
+      format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+    }
+    IpAddr::V6(v6) => {
+      let nibbles: Vec<String> = v6
+        .octets()
+        .iter()
+        .rev()
+        .flat_map(|v| [v & 0x0f, v >> 4])
+        .map(|v| format!("{:x}", v))
+        .collect();
+      format!("{}.ip6.arpa", nibbles.join("."))
+    }
+  }
+}
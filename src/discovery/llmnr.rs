@@ -0,0 +1,99 @@
+use super::dns::{self, DnsMessage, DnsQuestion, RecordData, CLASS_IN, TYPE_PTR};
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// LLMNR multicast group and port.
+const LLMNR_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 252)), 5355);
+
+/*
+  https://datatracker.ietf.org/doc/html/rfc4795
+
+  LLMNR uses the DNS message format verbatim, with a single question per
+  query. Reverse "in-addr.arpa PTR?" queries are sent to the multicast group
+  and answered (unicast) by the host owning the address.
+*/
+
+///
+/// Sends a reverse LLMNR query for every address, collecting the answers.
+///
+/// Args:
+///  - ips: IPv4 addresses to query.
+///  - timeout: How long to wait for answers.
+///
+/// Returns:
+///  Result of the hostnames, keyed by address.
+///
+pub fn query_llmnr_names(ips: &[IpAddr], timeout: Duration) -> Result<HashMap<IpAddr, String>> {
+  let socket = UdpSocket::bind("0.0.0.0:0")
+    .map_err(|e| Error::msg(format!("Failed to open LLMNR socket: {}", e)))?;
+  socket
+    .set_multicast_ttl_v4(1)
+    .and_then(|_| socket.set_read_timeout(Some(Duration::from_millis(100))))
+    .map_err(|e| Error::msg(format!("Failed to configure LLMNR socket: {}", e)))?;
+
+  // Answers are matched back to their address by the reverse name.
+  let mut pending: HashMap<String, IpAddr> = HashMap::new();
+  for (i, ip) in ips.iter().filter(|v| v.is_ipv4()).enumerate() {
+    let name = dns::reverse_name(ip);
+    let query = dns::encode_query(
+      i as u16,
+      0,
+      &[DnsQuestion {
+        name: name.clone(),
+        qtype: TYPE_PTR,
+        qclass: CLASS_IN,
+      }],
+    );
+    match socket.send_to(&query, LLMNR_ADDR) {
+      Ok(_) => {
+        pending.insert(name, *ip);
+      }
+      Err(e) => warn!("Failed to send LLMNR query for {}: {}", ip, e),
+    }
+  }
+
+  let mut names = HashMap::new();
+  let mut buf = [0u8; 1500];
+  let deadline = Instant::now() + timeout;
+  while Instant::now() < deadline && !pending.is_empty() {
+    let (len, src) = match socket.recv_from(&mut buf) {
+      Ok(v) => v,
+      Err(e)
+        if matches!(
+          e.kind(),
+          std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ) =>
+      {
+        continue
+      }
+      Err(e) => {
+        warn!("Failed to receive LLMNR answer: {}", e);
+        break;
+      }
+    };
+
+    let msg = match DnsMessage::parse(&buf[..len]) {
+      Ok(msg) if msg.is_response() => msg,
+      Ok(_) => continue,
+      Err(e) => {
+        debug!("Skipping malformed LLMNR answer from {}: {}", src, e);
+        continue;
+      }
+    };
+
+    for record in msg.records {
+      let RecordData::Ptr(hostname) = record.data else {
+        continue;
+      };
+      if let Some(ip) = pending.remove(&record.name) {
+        debug!("LLMNR name of {} -> {}", ip, hostname);
+        names.insert(ip, hostname);
+      }
+    }
+  }
+
+  Ok(names)
+}
@@ -0,0 +1,220 @@
+use super::dns::{self, DnsMessage, DnsQuestion, RecordData, CLASS_IN, TYPE_PTR};
+use crate::device::Device;
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// mDNS multicast group and port.
+const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+/// DNS-SD meta query, answered with every service type a responder advertises.
+const SERVICES_NAME: &str = "_services._dns-sd._udp.local";
+
+/// Questions packed into a single query, keeping it well under the 1500 byte MTU.
+const MAX_QUESTIONS_PER_QUERY: usize = 16;
+
+/// How long to listen for answers after each round of queries.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a single responder advertised about itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MdnsInfo {
+  /// ".local" hostname, e.g. "printer.local".
+  pub hostname: Option<String>,
+  /// Advertised service types, e.g. "_ipp._tcp".
+  pub services: BTreeSet<String>,
+}
+
+/*
+  Queries are sent from an ephemeral port rather than 5353, which makes them
+  "legacy unicast" queries (RFC 6762, section 6.7): responders answer straight
+  back to us, and only about themselves. So the responder's source address is
+  what every record in its answer gets attributed to.
+
+  Browsing takes two rounds:
+    1. "_services._dns-sd._udp.local PTR?" (plus reverse "in-addr.arpa PTR?"
+       queries for the addresses we want names of), answered with service
+       types and hostnames.
+    2. "<service type> PTR?" for every type found, answered with the service
+       instances along with their SRV and A/AAAA records.
+*/
+#[derive(Debug, Clone)]
+pub struct MdnsBrowser {
+  timeout: Duration,
+}
+
+impl Default for MdnsBrowser {
+  fn default() -> Self {
+    MdnsBrowser {
+      timeout: DEFAULT_TIMEOUT,
+    }
+  }
+}
+
+/// Strips the ".local" domain off a service type, e.g. "_ipp._tcp.local" -> "_ipp._tcp".
+fn service_type(name: &str) -> String {
+  name
+    .trim_end_matches('.')
+    .trim_end_matches(".local")
+    .to_string()
+}
+
+impl MdnsBrowser {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// How long to listen for answers after each round of queries.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Sends the questions, split over as many queries as needed.
+  fn send_questions(&self, socket: &UdpSocket, questions: &[DnsQuestion]) -> Result<()> {
+    for chunk in questions.chunks(MAX_QUESTIONS_PER_QUERY) {
+      let query = dns::encode_query(0, 0, chunk);
+      socket
+        .send_to(&query, MDNS_ADDR)
+        .map_err(|e| Error::msg(format!("Failed to send mDNS query: {}", e)))?;
+    }
+    Ok(())
+  }
+
+  /// Collects answers until the timeout, merging them by responder.
+  fn collect(&self, socket: &UdpSocket, found: &mut HashMap<IpAddr, MdnsInfo>) {
+    let deadline = Instant::now() + self.timeout;
+    let mut buf = [0u8; 9000];
+
+    while Instant::now() < deadline {
+      let (len, src) = match socket.recv_from(&mut buf) {
+        Ok(v) => v,
+        Err(e)
+          if matches!(
+            e.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+          ) =>
+        {
+          continue
+        }
+        Err(e) => {
+          warn!("Failed to receive mDNS answer: {}", e);
+          break;
+        }
+      };
+
+      let msg = match DnsMessage::parse(&buf[..len]) {
+        Ok(msg) if msg.is_response() => msg,
+        Ok(_) => continue,
+        Err(e) => {
+          debug!("Skipping malformed mDNS answer from {}: {}", src, e);
+          continue;
+        }
+      };
+      debug!("mDNS answer from {} -> {:?}", src, msg.records);
+
+      let info = found.entry(src.ip()).or_default();
+      for record in msg.records {
+        match record.data {
+          // Service type (or, for the meta query, another service type to browse).
+          RecordData::Ptr(target) if record.name == SERVICES_NAME => {
+            info.services.insert(service_type(&target));
+          }
+          RecordData::Ptr(target) if record.name.ends_with(".arpa") => {
+            info.hostname.get_or_insert(target);
+          }
+          RecordData::Ptr(_) => {
+            info.services.insert(service_type(&record.name));
+          }
+          RecordData::A(ip) if IpAddr::V4(ip) == src.ip() => {
+            info.hostname.get_or_insert(record.name);
+          }
+          RecordData::Aaaa(ip) if IpAddr::V6(ip) == src.ip() => {
+            info.hostname.get_or_insert(record.name);
+          }
+          RecordData::Srv { target, .. } => {
+            info.hostname.get_or_insert(target);
+          }
+          _ => {}
+        }
+      }
+    }
+  }
+
+  ///
+  /// Browses the local network for mDNS responders.
+  ///
+  /// Args:
+  ///  - ips: Addresses to also send reverse lookups for, e.g. unnamed neighbors.
+  ///
+  /// Returns:
+  ///  Result of what every responder advertised, keyed by its address.
+  ///
+  pub fn browse(&self, ips: &[IpAddr]) -> Result<HashMap<IpAddr, MdnsInfo>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+      .map_err(|e| Error::msg(format!("Failed to open mDNS socket: {}", e)))?;
+    socket
+      .set_multicast_ttl_v4(255)
+      .and_then(|_| socket.set_read_timeout(Some(Duration::from_millis(100))))
+      .map_err(|e| Error::msg(format!("Failed to configure mDNS socket: {}", e)))?;
+
+    let mut questions = vec![DnsQuestion {
+      name: SERVICES_NAME.to_string(),
+      qtype: TYPE_PTR,
+      qclass: CLASS_IN,
+    }];
+    questions.extend(ips.iter().filter(|v| v.is_ipv4()).map(|ip| DnsQuestion {
+      name: dns::reverse_name(ip),
+      qtype: TYPE_PTR,
+      qclass: CLASS_IN,
+    }));
+
+    let mut found = HashMap::new();
+    self.send_questions(&socket, &questions)?;
+    self.collect(&socket, &mut found);
+
+    let service_types: BTreeSet<String> = found
+      .values()
+      .flat_map(|v| v.services.iter().cloned())
+      .collect();
+    if !service_types.is_empty() {
+      let questions: Vec<DnsQuestion> = service_types
+        .iter()
+        .map(|v| DnsQuestion {
+          name: format!("{}.local", v),
+          qtype: TYPE_PTR,
+          qclass: CLASS_IN,
+        })
+        .collect();
+      self.send_questions(&socket, &questions)?;
+      self.collect(&socket, &mut found);
+    }
+
+    found.retain(|_, v| v.hostname.is_some() || !v.services.is_empty());
+    Ok(found)
+  }
+}
+
+///
+/// Merges what mDNS responders advertised into the devices they answered from.
+///
+/// Args:
+///  - devices: Devices to merge into.
+///  - found: Browse results, keyed by responder address.
+///
+pub fn merge_mdns(devices: &mut [Device], found: &HashMap<IpAddr, MdnsInfo>) {
+  for device in devices.iter_mut() {
+    let mut services: BTreeSet<String> = device.services.iter().cloned().collect();
+    let ips: Vec<IpAddr> = device.ips().map(|v| v.ip).collect();
+
+    for info in ips.iter().filter_map(|v| found.get(v)) {
+      if device.mdns_hostname.is_none() {
+        device.mdns_hostname = info.hostname.clone();
+      }
+      services.extend(info.services.iter().cloned());
+    }
+    device.services = services.into_iter().collect();
+  }
+}
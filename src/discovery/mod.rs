@@ -0,0 +1,8 @@
+pub mod dns;
+pub mod llmnr;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+pub mod netbios;
+pub mod probe;
+
+pub use probe::NameProber;
@@ -0,0 +1,168 @@
+use super::dns::{self, read_u16, CLASS_IN};
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// NetBIOS name service port.
+const NBNS_PORT: u16 = 137;
+
+/// NBSTAT (node status) record type. Shares its value with SRV, so it's decoded here.
+const TYPE_NBSTAT: u16 = 0x21;
+
+/// Length of a node status name entry: 15 byte name, suffix, and 2 bytes of flags.
+const NAME_ENTRY_LEN: usize = 18;
+
+/// Suffix of the workstation (computer) name.
+const SUFFIX_WORKSTATION: u8 = 0x00;
+
+/// Group name flag of a node status name entry.
+const FLAG_GROUP: u16 = 0x8000;
+
+/*
+  https://datatracker.ietf.org/doc/html/rfc1002#section-4.2.17
+
+  A node status request asks a host for every name it registered, using the
+  DNS message format with the wildcard name "*" as the question:
+
+    "*" padded to 16 bytes, with every byte split into two nibbles encoded
+    as 'A' + nibble, so "*\0\0..." -> "CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".
+
+  The answer's NBSTAT record lists the host's names:
+
+    u8 num_names; { u8 name[15]; u8 suffix; u16 flags; } names[num_names]; ...
+*/
+
+/// First level encodes a NetBIOS name into its 32 character label.
+fn encode_netbios_name(name: &[u8]) -> String {
+  let mut padded = [0u8; 16];
+  let len = name.len().min(16);
+  padded[..len].copy_from_slice(&name[..len]);
+
+  padded
+    .iter()
+    .flat_map(|v| [b'A' + (v >> 4), b'A' + (v & 0x0f)])
+    .map(char::from)
+    .collect()
+}
+
+/// Builds a node status request.
+pub fn encode_node_status_request(id: u16) -> Vec<u8> {
+  dns::encode_query(
+    id,
+    0,
+    &[dns::DnsQuestion {
+      name: encode_netbios_name(b"*"),
+      qtype: TYPE_NBSTAT,
+      qclass: CLASS_IN,
+    }],
+  )
+}
+
+///
+/// Parses the workstation name out of a node status response.
+///
+/// Args:
+///  - buf: Received datagram.
+///
+/// Returns:
+///  Result of the name, or None if the host didn't register one.
+///
+pub fn parse_node_status_response(buf: &[u8]) -> Result<Option<String>> {
+  let flags = read_u16(buf, 2)?;
+  let ancount = read_u16(buf, 6)?;
+  if flags & 0x8000 == 0 || ancount == 0 {
+    return Err(Error::msg("Not a node status response"));
+  }
+
+  // Skip the question, should the response repeat it.
+  let mut offset = 12;
+  for _ in 0..read_u16(buf, 4)? {
+    offset = dns::read_name(buf, offset)?.1 + 4;
+  }
+  let (_, next) = dns::read_name(buf, offset)?;
+  if read_u16(buf, next)? != TYPE_NBSTAT {
+    return Err(Error::msg("Not a node status response"));
+  }
+
+  let rdata_start = next + 10;
+  let num_names = *buf
+    .get(rdata_start)
+    .ok_or_else(|| Error::msg("Truncated NBNS message"))? as usize;
+
+  for i in 0..num_names {
+    let entry_start = rdata_start + 1 + i * NAME_ENTRY_LEN;
+    let Some(entry) = buf.get(entry_start..entry_start + NAME_ENTRY_LEN) else {
+      break;
+    };
+
+    let entry_flags = u16::from_be_bytes([entry[16], entry[17]]);
+    if entry[15] == SUFFIX_WORKSTATION && entry_flags & FLAG_GROUP == 0 {
+      let name = String::from_utf8_lossy(&entry[..15]).trim_end().to_string();
+      if !name.is_empty() {
+        return Ok(Some(name));
+      }
+    }
+  }
+  Ok(None)
+}
+
+///
+/// Sends a node status request to every address, collecting the answers.
+///
+/// Args:
+///  - ips: IPv4 addresses to query.
+///  - timeout: How long to wait for answers.
+///
+/// Returns:
+///  Result of the workstation names, keyed by address.
+///
+pub fn query_netbios_names(ips: &[IpAddr], timeout: Duration) -> Result<HashMap<IpAddr, String>> {
+  let socket = UdpSocket::bind("0.0.0.0:0")
+    .map_err(|e| Error::msg(format!("Failed to open NBNS socket: {}", e)))?;
+  socket
+    .set_read_timeout(Some(Duration::from_millis(100)))
+    .map_err(|e| Error::msg(format!("Failed to configure NBNS socket: {}", e)))?;
+
+  for (i, ip) in ips.iter().filter(|v| v.is_ipv4()).enumerate() {
+    let request = encode_node_status_request(i as u16);
+    if let Err(e) = socket.send_to(&request, SocketAddr::new(*ip, NBNS_PORT)) {
+      warn!("Failed to send NBNS request to {}: {}", ip, e);
+    }
+  }
+
+  let mut names = HashMap::new();
+  let mut buf = [0u8; 1500];
+  let deadline = Instant::now() + timeout;
+  while Instant::now() < deadline && names.len() < ips.len() {
+    let (len, src) = match socket.recv_from(&mut buf) {
+      Ok(v) => v,
+      Err(e)
+        if matches!(
+          e.kind(),
+          std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ) =>
+      {
+        continue
+      }
+      // Closed ports bounce back as ICMP errors on some kernels.
+      Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => continue,
+      Err(e) => {
+        warn!("Failed to receive NBNS answer: {}", e);
+        break;
+      }
+    };
+
+    match parse_node_status_response(&buf[..len]) {
+      Ok(Some(name)) => {
+        debug!("NetBIOS name of {} -> {}", src.ip(), name);
+        names.insert(src.ip(), name);
+      }
+      Ok(None) => {}
+      Err(e) => debug!("Skipping NBNS answer from {}: {}", src, e),
+    }
+  }
+
+  Ok(names)
+}
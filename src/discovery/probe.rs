@@ -0,0 +1,106 @@
+use super::llmnr::query_llmnr_names;
+use super::netbios::query_netbios_names;
+use crate::device::Device;
+use log::warn;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// How long to wait for answers to each kind of probe.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+///
+/// Actively asks unnamed IPv4 neighbors for their names over LLMNR and
+/// NetBIOS, which is often all Windows machines answer to.
+///
+/// Probing sends traffic to every unnamed device, so callers should keep it
+/// opt-in.
+///
+#[derive(Debug, Clone)]
+pub struct NameProber {
+  llmnr: bool,
+  netbios: bool,
+  timeout: Duration,
+}
+
+impl Default for NameProber {
+  fn default() -> Self {
+    NameProber {
+      llmnr: true,
+      netbios: true,
+      timeout: DEFAULT_TIMEOUT,
+    }
+  }
+}
+
+impl NameProber {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Whether to send reverse LLMNR queries.
+  pub fn llmnr(mut self, enabled: bool) -> Self {
+    self.llmnr = enabled;
+    self
+  }
+
+  /// Whether to send NetBIOS node status requests.
+  pub fn netbios(mut self, enabled: bool) -> Self {
+    self.netbios = enabled;
+    self
+  }
+
+  /// How long to wait for answers to each kind of probe.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  ///
+  /// Probes the given addresses, preferring LLMNR names over NetBIOS ones.
+  ///
+  /// Args:
+  ///  - ips: IPv4 addresses to probe.
+  ///
+  /// Returns:
+  ///  The names found, keyed by address.
+  ///
+  pub fn probe(&self, ips: &[IpAddr]) -> HashMap<IpAddr, String> {
+    let mut names = HashMap::new();
+
+    if self.netbios {
+      match query_netbios_names(ips, self.timeout) {
+        Ok(found) => names.extend(found),
+        Err(err) => warn!("NetBIOS probe failed: {}", err),
+      }
+    }
+    if self.llmnr {
+      match query_llmnr_names(ips, self.timeout) {
+        Ok(found) => names.extend(found),
+        Err(err) => warn!("LLMNR probe failed: {}", err),
+      }
+    }
+
+    names
+  }
+
+  /// Probes every device that has no name yet, storing what it answered with.
+  pub fn probe_devices(&self, devices: &mut [Device]) {
+    let ips: Vec<IpAddr> = devices
+      .iter()
+      .filter(|v| v.name().is_none())
+      .flat_map(|v| v.ipv4_addrs().map(|ip| ip.ip))
+      .collect();
+    if ips.is_empty() {
+      return;
+    }
+
+    let names = self.probe(&ips);
+    for device in devices.iter_mut() {
+      if device.probed_name.is_none() {
+        let probed_name = device.ipv4_addrs().find_map(|v| names.get(&v.ip).cloned());
+        device.probed_name = probed_name;
+      }
+    }
+  }
+}
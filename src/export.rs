@@ -0,0 +1,332 @@
+use crate::cli::{ExportArgs, ExportData, ExportFormat};
+use anyhow::{Error, Result};
+#[cfg(feature = "config")]
+use openwrt_netmon::config::Config;
+use openwrt_netmon::privacy::Anonymizer;
+#[cfg(feature = "sqlite")]
+use openwrt_netmon::storage::SqliteStorage;
+use openwrt_netmon::storage::{HistoryEvent, HistoryQuery, Sighting, Storage};
+use openwrt_netmon::Device;
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::SystemTime;
+
+/*
+  `netmon export --data events --format csv --since 7d` writes:
+
+    time,event,mac,ip,dev,old_value,new_value
+    2026-10-07 04:36:15,joined,dc:a6:32:a3:48:b1,,br-lan,,192.168.0.5
+    2026-10-07 04:37:15,state_changed,dc:a6:32:a3:48:b1,192.168.0.5,,REACHABLE,STALE
+    2026-10-07 04:38:45,ip_changed,dc:a6:32:a3:48:b1,,,,"192.168.0.5,fe80::1%br-lan"
+
+  Times are UTC, in a format spreadsheets parse as dates.
+*/
+
+/// A column of an export, formatting one field of a row.
+struct Column<T> {
+  name: &'static str,
+  value: fn(&T) -> String,
+}
+
+/// Shorthand for a column.
+fn column<T>(name: &'static str, value: fn(&T) -> String) -> Column<T> {
+  Column { name, value }
+}
+
+fn device_columns() -> Vec<Column<Device>> {
+  vec![
+    column("mac", |v| v.mac_addr.to_string()),
+    column("name", |v| v.name().unwrap_or_default().to_string()),
+    column("dev", |v| v.iface.clone()),
+    column("vendor", |v| v.vendor.clone().unwrap_or_default()),
+    column("state", |v| crate::nud_state_names(v.nud_state)),
+    column("online", |v| v.online.to_string()),
+    column("addresses", |v| {
+      let ips: Vec<String> = v.ips().map(|v| v.to_string()).collect();
+      ips.join(",")
+    }),
+  ]
+}
+
+fn sighting_columns() -> Vec<Column<Sighting>> {
+  vec![
+    column("time", |v| format_time(v.time)),
+    column("mac", |v| v.mac_addr.to_string()),
+    column("dev", |v| v.iface.clone()),
+    column("state", |v| crate::nud_state_names(v.nud_state)),
+    column("online", |v| v.online.to_string()),
+    column("signal", |v| {
+      v.signal_dbm.map(|v| v.to_string()).unwrap_or_default()
+    }),
+    column("rx_bytes", |v| {
+      v.rx_bytes.map(|v| v.to_string()).unwrap_or_default()
+    }),
+    column("tx_bytes", |v| {
+      v.tx_bytes.map(|v| v.to_string()).unwrap_or_default()
+    }),
+  ]
+}
+
+fn event_columns() -> Vec<Column<HistoryEvent>> {
+  vec![
+    column("time", |v| format_time(v.time)),
+    column("event", |v| v.kind.name().to_string()),
+    column("mac", |v| {
+      v.mac_addr.map(|v| v.to_string()).unwrap_or_default()
+    }),
+    column("ip", |v| {
+      v.ip.as_ref().map(|v| v.to_string()).unwrap_or_default()
+    }),
+    column("dev", |v| v.iface.clone().unwrap_or_default()),
+    column("old_value", |v| v.old_value.clone().unwrap_or_default()),
+    column("new_value", |v| v.new_value.clone().unwrap_or_default()),
+  ]
+}
+
+/// UTC time as "2026-10-14 04:36:15", which spreadsheets parse as a date.
+pub fn format_time(time: SystemTime) -> String {
+  let time = humantime::format_rfc3339_seconds(time).to_string();
+  time.trim_end_matches('Z').replacen('T', " ", 1)
+}
+
+///
+/// Picks the requested columns, in the requested order.
+///
+/// Args:
+///  - columns: Every column of the data.
+///  - names: Names of the columns to keep, every column if empty.
+///
+/// Returns:
+///  Result of the columns, failing on unknown names.
+///
+fn select<T>(columns: Vec<Column<T>>, names: &[String]) -> Result<Vec<Column<T>>> {
+  if names.is_empty() {
+    return Ok(columns);
+  }
+
+  let mut columns: Vec<Option<Column<T>>> = columns.into_iter().map(Some).collect();
+  let mut selected = Vec::new();
+  for name in names {
+    let name = name.trim();
+    let index = columns
+      .iter()
+      .position(|v| v.as_ref().is_some_and(|v| v.name == name));
+    match index.and_then(|i| columns[i].take()) {
+      Some(column) => selected.push(column),
+      None => {
+        let known: Vec<&str> = columns.iter().flatten().map(|v| v.name).collect();
+        return Err(Error::msg(format!(
+          "Unknown or repeated column '{}', expected one of: {}",
+          name,
+          known.join(", ")
+        )));
+      }
+    }
+  }
+  Ok(selected)
+}
+
+/// Quotes a CSV field holding separators, quotes or line breaks.
+fn csv_field(value: &str) -> Cow<'_, str> {
+  match value.contains([',', '"', '\n', '\r']) {
+    true => Cow::Owned(format!("\"{}\"", value.replace('"', "\"\""))),
+    false => Cow::Borrowed(value),
+  }
+}
+
+/// Keeps a TSV field on its row, as TSV can't quote.
+fn tsv_field(value: &str) -> Cow<'_, str> {
+  match value.contains(['\t', '\n', '\r']) {
+    true => Cow::Owned(value.replace(['\t', '\n', '\r'], " ")),
+    false => Cow::Borrowed(value),
+  }
+}
+
+///
+/// Writes rows one at a time, as TSV, CSV, or a JSON array of objects.
+///
+/// Args:
+///  - out: Where to write the rows.
+///  - columns: Columns of every row.
+///  - rows: Rows to write.
+///  - format: Format of the rows.
+///
+fn write_rows<T>(
+  out: &mut dyn Write,
+  columns: &[Column<T>],
+  rows: &[T],
+  format: ExportFormat,
+) -> std::io::Result<()> {
+  let (separator, field): (&str, fn(&str) -> Cow<'_, str>) = match format {
+    ExportFormat::Tsv => ("\t", tsv_field),
+    ExportFormat::Csv => (",", csv_field),
+    ExportFormat::Json => {
+      writeln!(out, "[")?;
+      for (i, row) in rows.iter().enumerate() {
+        // Written by hand, as serde_json's maps don't keep the column order.
+        let fields: Vec<String> = columns
+          .iter()
+          .map(|c| {
+            format!(
+              "{:?}: {}",
+              c.name,
+              serde_json::Value::String((c.value)(row))
+            )
+          })
+          .collect();
+        let comma = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(out, "  {{{}}}{}", fields.join(", "), comma)?;
+      }
+      return writeln!(out, "]");
+    }
+  };
+
+  let names: Vec<&str> = columns.iter().map(|v| v.name).collect();
+  writeln!(out, "{}", names.join(separator))?;
+  for row in rows {
+    for (i, column) in columns.iter().enumerate() {
+      if i > 0 {
+        out.write_all(separator.as_bytes())?;
+      }
+      out.write_all(field(&(column.value)(row)).as_bytes())?;
+    }
+    writeln!(out)?;
+  }
+  Ok(())
+}
+
+///
+/// Opens the daemon's history database for reading.
+///
+/// Args:
+///  - args: Export naming the database.
+///  - read: Reads the rows out of the history.
+///
+/// Returns:
+///  Result of the rows read.
+///
+#[cfg(feature = "sqlite")]
+fn read_history<T>(args: &ExportArgs, read: impl FnOnce(&dyn Storage) -> Result<T>) -> Result<T> {
+  if !args.db.exists() {
+    return Err(Error::msg(format!(
+      "No history at {}, is the daemon's sqlite sink enabled?",
+      args.db.display()
+    )));
+  }
+  read(&SqliteStorage::open(&args.db)?)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn read_history<T>(_args: &ExportArgs, _read: impl FnOnce(&dyn Storage) -> Result<T>) -> Result<T> {
+  Err(Error::msg(
+    "Exporting the history requires the 'sqlite' feature",
+  ))
+}
+
+///
+/// Finds whether the export hashes identifiers, as asked by --anonymize or
+/// by the config, along with the config's salt.
+///
+/// Args:
+///  - anonymize: Whether --anonymize was passed.
+///
+/// Returns:
+///  Result of the anonymizer, None unless anonymizing.
+///
+#[cfg(feature = "config")]
+pub fn anonymizer(anonymize: bool) -> Result<Option<Anonymizer>> {
+  let privacy = Config::discover(None)?.privacy;
+  match anonymize || privacy.anonymize {
+    true => Anonymizer::load(&privacy.salt_file).map(Some),
+    false => Ok(None),
+  }
+}
+
+#[cfg(not(feature = "config"))]
+pub fn anonymizer(anonymize: bool) -> Result<Option<Anonymizer>> {
+  match anonymize {
+    true => Anonymizer::load(std::path::Path::new(
+      openwrt_netmon::privacy::DEFAULT_SALT_PATH,
+    ))
+    .map(Some),
+    false => Ok(None),
+  }
+}
+
+/// Query of the history matching the export.
+fn history_query(args: &ExportArgs) -> HistoryQuery {
+  let mut query = HistoryQuery::new();
+  if let Some(since) = args.since {
+    query = query.since(SystemTime::now() - since);
+  }
+  if let Some(mac_addr) = args.mac {
+    query = query.mac_addr(mac_addr);
+  }
+  query
+}
+
+///
+/// Writes an export of the current devices, or of the history, along with
+/// the selected columns.
+///
+/// Args:
+///  - out: Where to write the export.
+///  - args: What to export.
+///  - json: Whether --json was passed, overriding the format.
+///  - anonymizer: Hashes the identifiers of the devices, if set.
+///  - devices: Collects the current devices, when exporting them.
+///
+/// Returns:
+///  Result reflecting whether the export was read and written.
+///
+pub fn export(
+  out: &mut dyn Write,
+  args: &ExportArgs,
+  json: bool,
+  anonymizer: Option<&Anonymizer>,
+  devices: impl FnOnce() -> Vec<Device>,
+) -> Result<()> {
+  let format = if json {
+    ExportFormat::Json
+  } else {
+    args.format
+  };
+  let write_error = |e: std::io::Error| Error::msg(format!("Failed to write the export: {}", e));
+
+  match args.data {
+    ExportData::Devices => {
+      if args.since.is_some() || args.mac.is_some() {
+        return Err(Error::msg(
+          "--since and --mac only apply to the history, e.g. --data events",
+        ));
+      }
+      let columns = select(device_columns(), &args.columns)?;
+      let mut devices = devices();
+      if let Some(anonymizer) = anonymizer {
+        devices = devices.iter().map(|v| anonymizer.device(v)).collect();
+      }
+      // Without columns, the devices are exported whole.
+      if format == ExportFormat::Json && args.columns.is_empty() {
+        serde_json::to_writer_pretty(&mut *out, &devices)?;
+        return writeln!(out).map_err(write_error);
+      }
+      write_rows(out, &columns, &devices, format).map_err(write_error)
+    }
+    ExportData::Sightings => {
+      let columns = select(sighting_columns(), &args.columns)?;
+      let mut sightings = read_history(args, |v| v.sightings(&history_query(args)))?;
+      if let Some(anonymizer) = anonymizer {
+        sightings = sightings.iter().map(|v| anonymizer.sighting(v)).collect();
+      }
+      write_rows(out, &columns, &sightings, format).map_err(write_error)
+    }
+    ExportData::Events => {
+      let columns = select(event_columns(), &args.columns)?;
+      let mut events = read_history(args, |v| v.events(&history_query(args)))?;
+      if let Some(anonymizer) = anonymizer {
+        events = events.iter().map(|v| anonymizer.event(v)).collect();
+      }
+      write_rows(out, &columns, &events, format).map_err(write_error)
+    }
+  }
+}
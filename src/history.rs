@@ -0,0 +1,324 @@
+use crate::export::format_time;
+use anyhow::{Error, Result};
+use log::debug;
+#[cfg(feature = "config")]
+use openwrt_netmon::config::Config;
+#[cfg(all(feature = "config", any(feature = "sqlite", feature = "redb")))]
+use openwrt_netmon::config::SinkConfig;
+use openwrt_netmon::daemon::socket;
+use openwrt_netmon::neighbors;
+#[cfg(feature = "redb")]
+use openwrt_netmon::storage::RedbStorage;
+#[cfg(feature = "sqlite")]
+use openwrt_netmon::storage::SqliteStorage;
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+use openwrt_netmon::storage::{AddressRecord, HistoryQuery, Storage};
+use openwrt_netmon::storage::{EventKind, HistoryEvent};
+use openwrt_netmon::{MacAddr, Registry, ScopedIpAddr};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Where `netmon history` reads the history from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistorySource {
+  /// Database of a sqlite sink.
+  #[cfg(feature = "sqlite")]
+  Sqlite(PathBuf),
+  /// Database of a redb sink, only readable while the daemon is stopped.
+  #[cfg(feature = "redb")]
+  Redb(PathBuf),
+  /// In-memory history of the running daemon, over its control socket.
+  Daemon(PathBuf),
+}
+
+/// What a device can be looked up by, along with the history.
+pub struct HistoryContext {
+  pub source: HistorySource,
+  pub registry: Registry,
+  pub aliases: HashMap<MacAddr, String>,
+}
+
+impl HistoryContext {
+  ///
+  /// Finds the history the daemon keeps: the database of the config's first
+  /// storage sink, or else the daemon's in-memory history.
+  ///
+  /// Args:
+  ///  - config_path: Config file given on the command line, if any.
+  ///  - socket_path: The daemon's control socket, instead of the config's.
+  ///
+  /// Returns:
+  ///  Result of the context, failing if the config is invalid.
+  ///
+  #[cfg(feature = "config")]
+  pub fn load(config_path: Option<&Path>, socket_path: Option<&Path>) -> Result<Self> {
+    let config = Config::discover(config_path)?;
+    let socket_path = socket_path.unwrap_or(&config.control_socket);
+    let source =
+      storage_source(&config).unwrap_or_else(|| HistorySource::Daemon(socket_path.to_path_buf()));
+    debug!("Reading the history from {:?}", source);
+    Ok(HistoryContext {
+      source,
+      registry: Registry::load(&config.registry)?,
+      aliases: config.aliases,
+    })
+  }
+
+  #[cfg(not(feature = "config"))]
+  pub fn load(_config_path: Option<&Path>, socket_path: Option<&Path>) -> Result<Self> {
+    let socket_path = socket_path.unwrap_or(Path::new(socket::DEFAULT_SOCKET_PATH));
+    #[cfg(feature = "sqlite")]
+    let default_db = Path::new(openwrt_netmon::storage::DEFAULT_SQLITE_PATH);
+    #[cfg(feature = "sqlite")]
+    let source = match default_db.exists() {
+      true => HistorySource::Sqlite(default_db.to_path_buf()),
+      false => HistorySource::Daemon(socket_path.to_path_buf()),
+    };
+    #[cfg(not(feature = "sqlite"))]
+    let source = HistorySource::Daemon(socket_path.to_path_buf());
+    Ok(HistoryContext {
+      source,
+      registry: Registry::load(Path::new(openwrt_netmon::registry::DEFAULT_REGISTRY_PATH))?,
+      aliases: HashMap::new(),
+    })
+  }
+
+  /// Name of a device, as the registry or the config's aliases give it.
+  pub fn name_of(&self, mac_addr: &MacAddr) -> Option<&str> {
+    self
+      .registry
+      .name_of(mac_addr)
+      .or_else(|| self.aliases.get(mac_addr).map(String::as_str))
+  }
+
+  ///
+  /// Finds the device a target designates: a MAC address, an address the
+  /// device has now or had in the history, or its name.
+  ///
+  /// Args:
+  ///  - target: e.g. "dc:a6:32:a3:48:b1", "192.168.1.20", or "Living room TV".
+  ///
+  /// Returns:
+  ///  Result of the MAC address, failing if no device or several match.
+  ///
+  pub fn resolve(&self, target: &str) -> Result<MacAddr> {
+    if let Ok(mac_addr) = target.parse::<MacAddr>() {
+      return Ok(mac_addr);
+    }
+    if let Ok(ip) = target.parse::<ScopedIpAddr>() {
+      return self.resolve_ip(&ip);
+    }
+
+    let mut matches: Vec<MacAddr> = self
+      .registry
+      .iter()
+      .filter(|v| {
+        v.name
+          .as_deref()
+          .is_some_and(|v| v.eq_ignore_ascii_case(target))
+      })
+      .map(|v| v.mac_addr)
+      .collect();
+    for (mac_addr, alias) in &self.aliases {
+      if alias.eq_ignore_ascii_case(target) && !matches.contains(mac_addr) {
+        matches.push(*mac_addr);
+      }
+    }
+    match matches.as_slice() {
+      [] => Err(Error::msg(format!("No device is named '{}'", target))),
+      [mac_addr] => Ok(*mac_addr),
+      _ => {
+        let macs: Vec<String> = matches.iter().map(|v| v.to_string()).collect();
+        Err(Error::msg(format!(
+          "Several devices are named '{}': {}",
+          target,
+          macs.join(", ")
+        )))
+      }
+    }
+  }
+
+  /// Finds the device holding an address now, or the last one that did.
+  fn resolve_ip(&self, ip: &ScopedIpAddr) -> Result<MacAddr> {
+    let matches_ip = |v: &ScopedIpAddr| v.ip == ip.ip && (ip.zone.is_none() || v.zone == ip.zone);
+    match neighbors::collect_neighbors() {
+      Ok(entries) => {
+        if let Some(mac_addr) = entries
+          .iter()
+          .filter(|v| matches_ip(&v.scoped_ip()))
+          .find_map(|v| v.mac_addr)
+        {
+          return Ok(mac_addr);
+        }
+      }
+      Err(err) => debug!("Failed to collect neighbors: {}", err),
+    }
+    self
+      .recorded_addresses()?
+      .into_iter()
+      .find(|v| matches_ip(&v.ip))
+      .map(|v| v.mac_addr)
+      .ok_or_else(|| Error::msg(format!("No device has used {}", ip)))
+  }
+
+  ///
+  /// Reads a device's events from the history.
+  ///
+  /// Args:
+  ///  - mac_addr: Device to read the events of.
+  ///  - since: Time the events start at.
+  ///
+  /// Returns:
+  ///  Result of the events, oldest first.
+  ///
+  pub fn events(&self, mac_addr: MacAddr, since: SystemTime) -> Result<Vec<HistoryEvent>> {
+    match &self.source {
+      HistorySource::Daemon(socket_path) => {
+        let history = socket::query_history(socket_path, mac_addr)?;
+        Ok(
+          history
+            .events
+            .into_iter()
+            .filter(|v| v.time >= since)
+            .collect(),
+        )
+      }
+      #[cfg(any(feature = "sqlite", feature = "redb"))]
+      _ => {
+        self.read(|storage| storage.events(&HistoryQuery::new().mac_addr(mac_addr).since(since)))
+      }
+    }
+  }
+
+  /// Every address recorded in the history, the most recently seen first.
+  #[cfg(any(feature = "sqlite", feature = "redb"))]
+  fn recorded_addresses(&self) -> Result<Vec<AddressRecord>> {
+    self.read(|storage| storage.addresses(&HistoryQuery::new()))
+  }
+
+  #[cfg(not(any(feature = "sqlite", feature = "redb")))]
+  fn recorded_addresses(&self) -> Result<Vec<openwrt_netmon::storage::AddressRecord>> {
+    Err(Error::msg(
+      "The daemon's in-memory history records no addresses",
+    ))
+  }
+
+  /// Opens the database of the history, to read it.
+  #[cfg(any(feature = "sqlite", feature = "redb"))]
+  fn read<T>(&self, read: impl FnOnce(&dyn Storage) -> Result<T>) -> Result<T> {
+    match &self.source {
+      #[cfg(feature = "sqlite")]
+      HistorySource::Sqlite(path) => read(&SqliteStorage::open(existing(path)?)?),
+      #[cfg(feature = "redb")]
+      HistorySource::Redb(path) => {
+        let storage = RedbStorage::open(existing(path)?).map_err(|e| {
+          Error::msg(format!(
+            "{} (the daemon locks its redb history while it runs)",
+            e
+          ))
+        })?;
+        read(&storage)
+      }
+      HistorySource::Daemon(_) => Err(Error::msg(
+        "The daemon's in-memory history records no addresses",
+      )),
+    }
+  }
+}
+
+/// Database of the config's first storage sink.
+#[cfg(all(feature = "config", any(feature = "sqlite", feature = "redb")))]
+fn storage_source(config: &Config) -> Option<HistorySource> {
+  config.sinks.iter().find_map(|sink| match sink {
+    #[cfg(feature = "sqlite")]
+    SinkConfig::Sqlite(sqlite) => Some(HistorySource::Sqlite(sqlite.path.clone())),
+    #[cfg(feature = "redb")]
+    SinkConfig::Redb(redb) => Some(HistorySource::Redb(redb.path.clone())),
+    _ => None,
+  })
+}
+
+#[cfg(all(feature = "config", not(any(feature = "sqlite", feature = "redb"))))]
+fn storage_source(_config: &Config) -> Option<HistorySource> {
+  None
+}
+
+/// Checks a database exists, rather than creating an empty one.
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+fn existing(path: &Path) -> Result<&Path> {
+  match path.exists() {
+    true => Ok(path),
+    false => Err(Error::msg(format!("No history at {}", path.display()))),
+  }
+}
+
+/// An event of the timeline, as printed with --json.
+#[derive(Debug, Serialize)]
+pub struct TimelineEntry<'a> {
+  /// RFC 3339 time of the poll that found the change, in UTC.
+  pub time: String,
+  pub event: &'static str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub mac: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ip: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub iface: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub old_value: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub new_value: Option<&'a str>,
+}
+
+impl<'a> TimelineEntry<'a> {
+  pub fn new(event: &'a HistoryEvent) -> Self {
+    TimelineEntry {
+      time: humantime::format_rfc3339_seconds(event.time).to_string(),
+      event: event.kind.name(),
+      mac: event.mac_addr.map(|v| v.to_string()),
+      ip: event.ip.as_ref().map(|v| v.to_string()),
+      iface: event.iface.as_deref(),
+      old_value: event.old_value.as_deref(),
+      new_value: event.new_value.as_deref(),
+    }
+  }
+}
+
+/// An event of the timeline, as a line of text.
+pub fn timeline_line(event: &HistoryEvent) -> String {
+  let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".into());
+  let ip = event.ip.as_ref().map_or("-".into(), |v| v.to_string());
+  let what = match event.kind {
+    EventKind::Joined => format!(
+      "joined {} {}",
+      value(&event.iface),
+      event.new_value.as_deref().unwrap_or_default()
+    ),
+    EventKind::Left => format!("left {}", value(&event.iface)),
+    EventKind::IpChanged => {
+      let mut what = "addresses".to_string();
+      if let Some(added) = &event.new_value {
+        what += &format!(" +{}", added);
+      }
+      if let Some(removed) = &event.old_value {
+        what += &format!(" -{}", removed);
+      }
+      what
+    }
+    EventKind::MacChanged => format!(
+      "{} moved {} -> {}",
+      ip,
+      value(&event.old_value),
+      value(&event.new_value)
+    ),
+    EventKind::StateChanged => format!(
+      "{} {} -> {}",
+      ip,
+      value(&event.old_value),
+      value(&event.new_value)
+    ),
+  };
+  format!("{}  {}", format_time(event.time), what.trim_end())
+}
@@ -0,0 +1,72 @@
+//!
+//! Neighbor (ARP/NDP) table collection and tracking for linux-based routers,
+//! such as OpenWrt.
+//!
+//! Neighbors can be collected either natively over rtnetlink or by parsing
+//! the output of `ip neigh`:
+//!
+//! ```no_run
+//! use openwrt_netmon::neighbors;
+//!
+//! for neigh in neighbors::netlink::get_ip_neighbors()? {
+//!   println!("{} dev {} {:?}", neigh.ip, neigh.iface, neigh.nud_state);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Successive snapshots can be merged into per-device history:
+//!
+//! ```
+//! use openwrt_netmon::{ArpTable, MacAddr, NeighborTracker};
+//!
+//! let entry = ArpTable::parse_from_string(
+//!   "192.168.0.5 dev br-lan lladdr dc:a6:32:a3:48:b1 ref 1 used 0/0/0 probes 1 REACHABLE",
+//! )?;
+//!
+//! let mut tracker = NeighborTracker::new();
+//! tracker.update(&[entry]);
+//!
+//! let mac_addr: MacAddr = "dc:a6:32:a3:48:b1".parse()?;
+//! assert!(tracker.get(&mac_addr).unwrap().online);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+#[cfg(feature = "daemon")]
+pub mod alerts;
+#[cfg(feature = "config")]
+pub mod backup;
+pub mod bridge_fdb;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod counters;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+pub mod device;
+pub mod dhcp;
+pub mod diff;
+pub mod discovery;
+pub mod neighbors;
+pub mod privacy;
+pub mod registry;
+pub mod resolver;
+#[cfg(feature = "daemon")]
+pub mod storage;
+pub mod tracker;
+pub mod vendor;
+pub mod wireless;
+
+pub use bridge_fdb::{FdbEntry, FdbState, NeighborPort};
+#[cfg(feature = "daemon")]
+pub use daemon::{Daemon, Sink};
+pub use device::{Device, DeviceAddress, DeviceIdentity, LogicalDevice};
+pub use dhcp::Lease;
+pub use diff::{NeighborDiff, Snapshot};
+pub use neighbors::netlink::{NeighborEvent, NeighborSubscription};
+pub use neighbors::{
+  AddressFamily, ArpTable, CommandRunner, MacAddr, NeighborFilter, NeighborFlags, NudState,
+  ScopedIpAddr,
+};
+pub use registry::{KnownDevice, Registry};
+pub use resolver::HostnameResolver;
+pub use tracker::{NeighborTracker, TrackedNeighbor};
+pub use wireless::Station;
@@ -0,0 +1,244 @@
+use clap::ValueEnum;
+use env_logger::Env;
+use log::kv::{Key, Value, VisitSource};
+use serde_json::{Map, Value as Json};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Socket the local syslog daemon (logd on OpenWrt) listens on.
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Tag syslog messages are logged under.
+const SYSLOG_TAG: &str = "netmon";
+
+/*
+  JSON logs carry one event per line, with the event's fields alongside the
+  message, e.g.:
+
+    {"device.iface":"br-lan","device.mac":"dc:a6:32:a3:48:b1","event.kind":"joined",
+     "level":"INFO","message":"dc:a6:32:a3:48:b1 joined on br-lan [192.168.0.5]",
+     "target":"openwrt_netmon::daemon::sink","timestamp":"2026-10-14T04:36:15.123Z"}
+*/
+
+/// How log records are written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+  /// Human readable lines.
+  #[default]
+  Text,
+  /// One JSON object per line, for Loki or Elasticsearch.
+  Json,
+}
+
+/// Where log records are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogTarget {
+  #[default]
+  Stderr,
+  /// The local syslog socket, shown by `logread` on OpenWrt.
+  Syslog,
+}
+
+/// Syslog facility records are logged under.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Facility {
+  User,
+  #[default]
+  Daemon,
+  Local0,
+  Local1,
+  Local2,
+  Local3,
+  Local4,
+  Local5,
+  Local6,
+  Local7,
+}
+
+impl Facility {
+  /// Facility code, as defined by RFC 5424.
+  fn code(self) -> u8 {
+    match self {
+      Facility::User => 1,
+      Facility::Daemon => 3,
+      Facility::Local0 => 16,
+      Facility::Local1 => 17,
+      Facility::Local2 => 18,
+      Facility::Local3 => 19,
+      Facility::Local4 => 20,
+      Facility::Local5 => 21,
+      Facility::Local6 => 22,
+      Facility::Local7 => 23,
+    }
+  }
+}
+
+/// Syslog severity of a log level.
+fn severity(level: log::Level) -> u8 {
+  match level {
+    log::Level::Error => 3,
+    log::Level::Warn => 4,
+    log::Level::Info => 6,
+    log::Level::Debug | log::Level::Trace => 7,
+  }
+}
+
+/// Collects a record's key-values as JSON fields.
+struct JsonFields<'a>(&'a mut Map<String, Json>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+  fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+    let value = if let Some(v) = value.to_bool() {
+      Json::Bool(v)
+    } else if let Some(v) = value.to_u64() {
+      Json::from(v)
+    } else if let Some(v) = value.to_i64() {
+      Json::from(v)
+    } else if let Some(v) = value.to_f64() {
+      Json::from(v)
+    } else {
+      Json::String(value.to_string())
+    };
+    self.0.insert(key.to_string(), value);
+    Ok(())
+  }
+}
+
+/// Collects a record's key-values as " key=value" pairs.
+struct TextFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for TextFields<'_> {
+  fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+    let _ = write!(self.0, " {}={}", key, value);
+    Ok(())
+  }
+}
+
+/// A record as a JSON object, along with its fields.
+fn json_event(record: &log::Record) -> Json {
+  let mut event = Map::new();
+  event.insert(
+    "timestamp".into(),
+    Json::String(humantime::format_rfc3339_millis(SystemTime::now()).to_string()),
+  );
+  event.insert("level".into(), Json::String(record.level().to_string()));
+  event.insert("target".into(), Json::String(record.target().to_string()));
+  event.insert("message".into(), Json::String(record.args().to_string()));
+  let _ = record.key_values().visit(&mut JsonFields(&mut event));
+  Json::Object(event)
+}
+
+/// Formats a record as a single JSON line.
+fn format_json(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> std::io::Result<()> {
+  writeln!(buf, "{}", json_event(record))
+}
+
+/*
+  Syslog messages follow RFC 3164, leaving the timestamp to the syslog daemon:
+
+    <30>netmon[1234]: dc:a6:32:a3:48:b1 joined on br-lan [192.168.0.5] device.mac=...
+*/
+
+///
+/// Logger sending every record to the local syslog socket, filtered the same
+/// way as the stderr logger.
+///
+struct SyslogLogger {
+  filter: env_logger::Logger,
+  facility: Facility,
+  format: LogFormat,
+  socket: Mutex<Option<UnixDatagram>>,
+}
+
+impl SyslogLogger {
+  /// Connects to the syslog socket, e.g. again after logd restarted.
+  fn connect() -> std::io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(SYSLOG_SOCKET)?;
+    Ok(socket)
+  }
+
+  /// The record's message, along with its fields.
+  fn message(&self, record: &log::Record) -> String {
+    let mut message = String::new();
+    match self.format {
+      LogFormat::Json => {
+        let _ = write!(message, "{}", json_event(record));
+      }
+      LogFormat::Text => {
+        let _ = write!(message, "{}", record.args());
+        let _ = record.key_values().visit(&mut TextFields(&mut message));
+      }
+    }
+    message
+  }
+}
+
+impl log::Log for SyslogLogger {
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    self.filter.enabled(metadata)
+  }
+
+  fn log(&self, record: &log::Record) {
+    if !self.filter.matches(record) {
+      return;
+    }
+    let message = self.message(record);
+    let packet = format!(
+      "<{}>{}[{}]: {}",
+      self.facility.code() * 8 + severity(record.level()),
+      SYSLOG_TAG,
+      std::process::id(),
+      message
+    );
+
+    let mut socket = self.socket.lock().unwrap();
+    for _ in 0..2 {
+      if socket.is_none() {
+        *socket = Self::connect().ok();
+      }
+      match socket.as_ref().map(|v| v.send(packet.as_bytes())) {
+        Some(Ok(_)) => return,
+        // Reconnect once, as the syslog daemon may have restarted.
+        _ => *socket = None,
+      }
+    }
+    eprintln!("[{}] {}", record.level(), message);
+  }
+
+  fn flush(&self) {}
+}
+
+///
+/// Sets up logging, filtered by $RUST_LOG (info by default). Records meant for
+/// syslog fall back to stderr while its socket is unavailable.
+///
+/// Args:
+///  - format: How records are written.
+///  - target: Where records are written.
+///  - facility: Syslog facility, when logging to syslog.
+///
+pub fn init(format: LogFormat, target: LogTarget, facility: Facility) {
+  let mut builder = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
+  if format == LogFormat::Json {
+    builder.format(format_json);
+  }
+  if target == LogTarget::Stderr {
+    return builder.init();
+  }
+
+  let filter = builder.build();
+  log::set_max_level(filter.filter());
+  let logger = SyslogLogger {
+    filter,
+    facility,
+    format,
+    socket: Mutex::new(SyslogLogger::connect().ok()),
+  };
+  if let Err(err) = log::set_boxed_logger(Box::new(logger)) {
+    eprintln!("Failed to set up logging: {}", err);
+  }
+}
@@ -1,11 +1,1255 @@
-mod net_util;
-use env_logger::Env;
-use log::info;
+use clap::Parser;
+#[cfg(feature = "influxdb")]
+use cli::SnapshotFormat;
+#[cfg(feature = "zabbix")]
+use cli::ZabbixCommand;
+use cli::{
+  BackupCommand, Cli, Command, DeviceCommand, DiscoveryArgs, ExportArgs, FilterArgs, OnConflict,
+  OutputArgs, RegistryCommand, RegistryFormat, VendorCommand,
+};
+use log::{debug, error, info, warn};
+#[cfg(feature = "config")]
+use openwrt_netmon::backup;
+#[cfg(feature = "config")]
+use openwrt_netmon::config::Config;
+#[cfg(feature = "zabbix")]
+use openwrt_netmon::daemon::zabbix;
+#[cfg(feature = "influxdb")]
+use openwrt_netmon::daemon::InfluxLineSink;
+use openwrt_netmon::daemon::{
+  self, CollectdSink, Control, ControlSocket, Health, HealthServer, Metrics, NetdataSink, PidFile,
+  SnmpServer, StateDir, StateSink,
+};
+#[cfg(feature = "mdns")]
+use openwrt_netmon::discovery::mdns::{self, MdnsBrowser};
+use openwrt_netmon::discovery::NameProber;
+use openwrt_netmon::neighbors;
+use openwrt_netmon::privacy::Anonymizer;
+#[cfg(not(feature = "config"))]
+use openwrt_netmon::registry;
+use openwrt_netmon::registry::MergePolicy;
+use openwrt_netmon::storage::{MemoryStorage, RecentHistory};
+use openwrt_netmon::{bridge_fdb, counters, dhcp, vendor, wireless};
+use openwrt_netmon::{
+  ArpTable, Daemon, Device, HostnameResolver, KnownDevice, MacAddr, NeighborEvent,
+  NeighborSubscription, NudState, Registry,
+};
+use std::io::Write;
+#[cfg(feature = "mdns")]
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
+
+mod cli;
+mod export;
+mod history;
+mod logging;
+
+/// Prints a value as pretty JSON.
+fn print_json<T: serde::Serialize + ?Sized>(value: &T) {
+  match serde_json::to_string_pretty(value) {
+    Ok(json) => println!("{}", json),
+    Err(err) => {
+      error!("Failed to serialize output: {}", err);
+      exit(1);
+    }
+  }
+}
+
+/// Prints the current neighbor table.
+fn list_neighbors(stats: bool, filter: &FilterArgs, output: &OutputArgs) {
+  let filter = filter.to_filter();
+  let mut ip_neigh_vec = match stats {
+    true => neighbors::get_ip_neighbors_detailed(&filter),
+    false => neighbors::collect_neighbors(),
+  }
+  .unwrap_or_else(|err| {
+    error!("Failed to collect neighbors: {}", err);
+    exit(1);
+  });
+  ip_neigh_vec.retain(|v| filter.matches(v));
+  print_neighbors(&ip_neigh_vec, stats, output);
+}
+
+/// Prints neighbors as `ip neigh` does, along with their counters if `stats`.
+fn print_neighbors(ip_neigh_vec: &[ArpTable], stats: bool, output: &OutputArgs) {
+  if output.json {
+    return print_json(ip_neigh_vec);
+  }
+  for neigh in ip_neigh_vec {
+    println!(
+      "{} dev {} lladdr {} {:?} {:?}",
+      neigh.scoped_ip(),
+      neigh.iface,
+      neigh.mac_addr.map(|v| v.to_string()).unwrap_or_default(),
+      neigh.flags,
+      neigh.nud_state
+    );
+    if let Some(stats) = neigh.stats.filter(|_| stats) {
+      println!("  {:?}", stats);
+    }
+  }
+}
+
+/// Prints the current neighbor table, resolving bridge ports through the FDB.
+fn list_neighbor_ports(filter: &FilterArgs, output: &OutputArgs) {
+  let filter = filter.to_filter();
+  let mut ip_neigh_vec = neighbors::collect_neighbors().unwrap_or_else(|err| {
+    error!("Failed to collect neighbors: {}", err);
+    exit(1);
+  });
+  ip_neigh_vec.retain(|v| filter.matches(v));
+  let fdb = bridge_fdb::get_fdb_entries().unwrap_or_else(|err| {
+    error!("Failed to read the bridge fdb: {}", err);
+    Vec::new()
+  });
+
+  let ports = bridge_fdb::join_neighbor_ports(ip_neigh_vec, &fdb);
+  if output.json {
+    return print_json(&ports);
+  }
+  for entry in &ports {
+    println!(
+      "{} dev {} lladdr {} port {} vlan {}",
+      entry.neighbor.scoped_ip(),
+      entry.neighbor.iface,
+      entry
+        .neighbor
+        .mac_addr
+        .map(|v| v.to_string())
+        .unwrap_or_default(),
+      entry.port.as_deref().unwrap_or(&entry.neighbor.iface),
+      entry.vlan.map(|v| v.to_string()).unwrap_or_default()
+    );
+  }
+}
+
+/// Merges mDNS names and services into the devices.
+#[cfg(feature = "mdns")]
+fn browse_mdns(devices: &mut [Device]) {
+  let ips: Vec<IpAddr> = devices
+    .iter()
+    .filter(|v| v.name().is_none())
+    .flat_map(|v| v.ips().map(|ip| ip.ip))
+    .collect();
+  match MdnsBrowser::new().browse(&ips) {
+    Ok(found) => mdns::merge_mdns(devices, &found),
+    Err(err) => error!("mDNS browse failed: {}", err),
+  }
+}
+
+#[cfg(not(feature = "mdns"))]
+fn browse_mdns(_devices: &mut [Device]) {
+  error!("Built without mDNS support, rebuild with the 'mdns' feature");
+  exit(2);
+}
+
+/// Collects the current devices, joined with their leases and names.
+fn collect_devices(filter: &FilterArgs, discovery: &DiscoveryArgs) -> Vec<Device> {
+  let filter = filter.to_filter();
+  let mut entries = neighbors::collect_neighbors().unwrap_or_else(|err| {
+    error!("Failed to collect neighbors: {}", err);
+    exit(1);
+  });
+  entries.retain(|v| filter.matches(v));
+
+  let mut devices = enrich_devices(&entries, discovery);
+  match wireless::get_stations() {
+    Ok(stations) => wireless::join_stations(&mut devices, &stations),
+    Err(err) => debug!("No wireless stations: {}", err),
+  }
+  devices
+}
+
+/// Groups neighbors into devices, joined with their leases and names.
+fn enrich_devices(entries: &[ArpTable], discovery: &DiscoveryArgs) -> Vec<Device> {
+  let mut devices = Device::group(entries);
+  if discovery.resolve {
+    HostnameResolver::new().resolve_devices(&mut devices);
+  }
+
+  let mut leases = Vec::new();
+  match dhcp::read_dnsmasq_leases(&discovery.leases) {
+    Ok(v) => leases.extend(v),
+    Err(err) => debug!("No dnsmasq leases: {}", err),
+  }
+  match dhcp::read_odhcpd_leases(&discovery.odhcpd_leases) {
+    Ok(v) => leases.extend(v),
+    Err(err) => debug!("No odhcpd leases: {}", err),
+  }
+  match dhcp::get_static_hosts() {
+    Ok(hosts) => dhcp::mark_static_leases(&mut leases, &hosts),
+    Err(err) => debug!("No static hosts: {}", err),
+  }
+  dhcp::join_leases(&mut devices, &leases);
+
+  if discovery.mdns {
+    browse_mdns(&mut devices);
+  }
+  if discovery.probe_names {
+    NameProber::new().probe_devices(&mut devices);
+  }
+  match Registry::load(&discovery.registry) {
+    Ok(registry) => registry.annotate(&mut devices),
+    Err(err) => warn!("Skipping the known devices: {}", err),
+  }
+  devices
+}
+
+/// Prints the current neighbor table grouped by device.
+fn list_devices(discovery: &DiscoveryArgs, filter: &FilterArgs, output: &OutputArgs) {
+  print_devices(&collect_devices(filter, discovery), output);
+}
+
+/// Prints one line per device.
+fn print_devices(devices: &[Device], output: &OutputArgs) {
+  if output.json {
+    return print_json(devices);
+  }
+
+  for device in devices {
+    let ips: Vec<String> = device.ips().map(|v| v.to_string()).collect();
+    println!(
+      "{} {} dev {} {} {:?}{} [{}] {}",
+      device.mac_addr,
+      device.name().unwrap_or("-"),
+      device.iface,
+      device.vendor.as_deref().unwrap_or("-"),
+      device.nud_state,
+      if device.lease.as_ref().is_some_and(|v| v.is_static) {
+        " static"
+      } else {
+        ""
+      },
+      ips.join(", "),
+      device.services.join(" ")
+    );
+  }
+}
+
+/// Prints everything known about a single device.
+fn show_device(
+  mac_addr: MacAddr,
+  socket_path: &Path,
+  discovery: &DiscoveryArgs,
+  output: &OutputArgs,
+) {
+  let devices = collect_devices(&FilterArgs::default(), discovery);
+  let Some(device) = devices.iter().find(|v| v.mac_addr == mac_addr) else {
+    error!("No device found for {}", mac_addr);
+    exit(1);
+  };
+  if output.json {
+    return print_json(device);
+  }
+
+  println!("{}", device.mac_addr);
+  println!("  name: {}", device.name().unwrap_or("-"));
+  if let Some(owner) = &device.owner {
+    println!("  owner: {}", owner);
+  }
+  if let Some(category) = device.category {
+    println!("  category: {}", category.name());
+  }
+  if let Some(trust) = device.trust {
+    println!("  trust: {}", trust.name());
+  }
+  println!("  dev: {}", device.iface);
+  println!("  vendor: {}", device.vendor.as_deref().unwrap_or("-"));
+  println!("  state: {:?}", device.nud_state);
+  println!("  online: {}", device.online);
+  println!("  randomized mac: {}", device.randomized_mac);
+  for address in &device.addresses {
+    println!("  address: {} {:?}", address.ip, address.nud_state);
+  }
+  if let Some(lease) = &device.lease {
+    println!(
+      "  lease: {}{}",
+      lease.ip,
+      if lease.is_static { " static" } else { "" }
+    );
+  }
+  if let Some(duid) = &device.duid {
+    println!("  duid: {}", duid);
+  }
+  if let Some(station) = &device.station {
+    println!(
+      "  wireless: {} signal {}",
+      station.iface,
+      station
+        .signal_dbm
+        .map_or("-".to_string(), |v| format!("{} dBm", v))
+    );
+  }
+  if !device.services.is_empty() {
+    println!("  services: {}", device.services.join(" "));
+  }
+  match daemon::socket::query_history(socket_path, mac_addr) {
+    Ok(history) => print_history(&history),
+    Err(err) => debug!("No recent history: {}", err),
+  }
+}
+
+/// Events shown by `netmon device`, the most recent ones.
+const DEVICE_HISTORY_EVENTS: usize = 10;
+
+/// Prints the recent history of a device, as kept by the daemon.
+fn print_history(history: &RecentHistory) {
+  if let (Some(first), Some(last)) = (history.sightings.first(), history.sightings.last()) {
+    let online = history.sightings.iter().filter(|v| v.online).count();
+    let signals: Vec<i32> = history
+      .sightings
+      .iter()
+      .filter_map(|v| v.signal_dbm)
+      .collect();
+    let signal = match signals.is_empty() {
+      true => String::new(),
+      false => format!(
+        ", signal {} dBm on average",
+        signals.iter().sum::<i32>() / signals.len() as i32
+      ),
+    };
+    println!(
+      "  seen: online in {} of {} poll(s) from {} to {}{}",
+      online,
+      history.sightings.len(),
+      export::format_time(first.time),
+      export::format_time(last.time),
+      signal
+    );
+  }
+
+  let skip = history.events.len().saturating_sub(DEVICE_HISTORY_EVENTS);
+  for event in history.events.iter().skip(skip) {
+    let mut line = format!(
+      "  event: {} {}",
+      export::format_time(event.time),
+      event.kind.name()
+    );
+    if let Some(ip) = &event.ip {
+      line += &format!(" {}", ip);
+    }
+    match (&event.old_value, &event.new_value) {
+      (Some(old_value), Some(new_value)) => line += &format!(" {} -> {}", old_value, new_value),
+      (Some(value), None) | (None, Some(value)) => line += &format!(" {}", value),
+      (None, None) => {}
+    }
+    println!("{}", line);
+  }
+}
+
+/// Prints the timeline of a device, from the history the daemon keeps.
+fn print_timeline(
+  target: &str,
+  since: Duration,
+  config_path: Option<&Path>,
+  socket_path: Option<&Path>,
+  output: &OutputArgs,
+) {
+  let timeline = (|| {
+    let context = history::HistoryContext::load(config_path, socket_path)?;
+    let mac_addr = context.resolve(target)?;
+    let events = context.events(mac_addr, std::time::SystemTime::now() - since)?;
+    let name = context.name_of(&mac_addr).map(String::from);
+    anyhow::Ok((mac_addr, name, events))
+  })();
+  let (mac_addr, name, events) = timeline.unwrap_or_else(|err| {
+    error!("{}", err);
+    exit(1);
+  });
+  if output.json {
+    let entries: Vec<history::TimelineEntry> =
+      events.iter().map(history::TimelineEntry::new).collect();
+    return print_json(&entries);
+  }
+
+  match name {
+    Some(name) => println!("{} ({})", name, mac_addr),
+    None => println!("{}", mac_addr),
+  }
+  if events.is_empty() {
+    println!(
+      "  nothing in the last {}",
+      humantime::format_duration(since)
+    );
+  }
+  for event in &events {
+    println!("  {}", history::timeline_line(event));
+  }
+}
+
+/// Names of the set NUD states, e.g. "REACHABLE".
+pub(crate) fn nud_state_names(nud_state: NudState) -> String {
+  let names: Vec<&str> = nud_state.iter_names().map(|(name, _)| name).collect();
+  names.join(" ")
+}
+
+///
+/// Exports the current devices, or the history, streaming the rows to the
+/// file or stdout rather than formatting the export in memory.
+///
+fn export(
+  export: &ExportArgs,
+  discovery: &DiscoveryArgs,
+  filter: &FilterArgs,
+  output: &OutputArgs,
+) {
+  let devices = || collect_devices(filter, discovery);
+  let anonymizer = export::anonymizer(export.anonymize).unwrap_or_else(|err| {
+    error!("{}", err);
+    exit(1);
+  });
+  let anonymizer = anonymizer.as_ref();
+  let written = match &export.path {
+    Some(path) => std::fs::File::create(path)
+      .map_err(|e| anyhow::Error::msg(format!("Failed to create {}: {}", path.display(), e)))
+      .and_then(|file| {
+        let mut out = std::io::BufWriter::new(file);
+        export::export(&mut out, export, output.json, anonymizer, devices)?;
+        Ok(out.flush()?)
+      }),
+    None => {
+      let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+      export::export(&mut out, export, output.json, anonymizer, devices)
+        .and_then(|_| Ok(out.flush()?))
+    }
+  };
+  if let Err(err) = written {
+    error!("{}", err);
+    exit(1);
+  }
+}
+
+///
+/// Runs a captured `ip neigh` listing through the same parsing, filtering,
+/// and device grouping as a live one, to reproduce parser issues from other
+/// routers. Rows that fail to parse are logged, along with a summary.
+///
+fn parse_capture(
+  path: &Path,
+  devices: bool,
+  discovery: &DiscoveryArgs,
+  filter: &FilterArgs,
+  output: &OutputArgs,
+) {
+  let content = match path.to_str() {
+    Some("-") => std::io::read_to_string(std::io::stdin()),
+    _ => std::fs::read_to_string(path),
+  }
+  .unwrap_or_else(|err| {
+    error!("Failed to read {}: {}", path.display(), err);
+    exit(1);
+  });
+
+  let rows = content.lines().filter(|v| !v.trim().is_empty()).count();
+  let parse_errors = counters::parse_errors();
+  let mut entries = neighbors::parse_ip_neigh_output(&content).unwrap_or_else(|err| {
+    error!("Failed to parse {}: {}", path.display(), err);
+    exit(1);
+  });
+  info!(
+    "Parsed {} of {} rows, skipped {}",
+    entries.len(),
+    rows,
+    counters::parse_errors() - parse_errors
+  );
+
+  let filter = filter.to_filter();
+  entries.retain(|v| filter.matches(v));
+  match devices {
+    true => print_devices(&enrich_devices(&entries, discovery), output),
+    false => print_neighbors(&entries, true, output),
+  }
+}
+
+/// Streams neighbor table changes until the subscription fails.
+fn watch_neighbors(filter: &FilterArgs, output: &OutputArgs) {
+  let filter = filter.to_filter();
+  let subscription = NeighborSubscription::new().unwrap_or_else(|err| {
+    error!("Failed to subscribe to neighbor changes: {}", err);
+    exit(1);
+  });
+
+  for event in subscription {
+    let event = match event {
+      Ok(event) => event,
+      Err(err) => {
+        error!("Neighbor subscription failed: {}", err);
+        break;
+      }
+    };
+    let (kind, neigh) = match &event {
+      NeighborEvent::Added(neigh) => ("added", neigh),
+      NeighborEvent::Updated(neigh) => ("updated", neigh),
+      NeighborEvent::Removed(neigh) => ("removed", neigh),
+    };
+    if !filter.matches(neigh) {
+      continue;
+    }
+
+    if output.json {
+      match serde_json::to_string(&event) {
+        Ok(json) => println!("{}", json),
+        Err(err) => error!("Failed to serialize event: {}", err),
+      }
+    } else {
+      println!("{} -> {:?}", kind, neigh);
+    }
+  }
+}
+
+///
+/// Polls the neighbor table on a schedule, as the daemon does, printing the
+/// devices' metrics as collectd PUTVAL lines on stdout, until collectd stops
+/// it. Its interval and hostname default to the ones collectd's exec plugin
+/// passes in $COLLECTD_INTERVAL and $COLLECTD_HOSTNAME.
+///
+fn run_collectd(config_path: Option<&Path>, interval: Option<Duration>) {
+  let interval = interval.or_else(|| {
+    let interval = std::env::var("COLLECTD_INTERVAL").ok()?;
+    let interval = interval.parse::<f64>().ok().filter(|v| *v >= 1.0)?;
+    Some(Duration::from_secs_f64(interval))
+  });
+  let (daemon, anonymizer) = plugin_daemon(config_path);
+  let daemon = match interval {
+    Some(interval) => daemon.interval(interval),
+    None => daemon,
+  };
+  let mut sink = CollectdSink::new(Box::new(std::io::stdout()), daemon.poll_interval());
+  if let Ok(hostname) = std::env::var("COLLECTD_HOSTNAME") {
+    sink = sink.hostname(&hostname);
+  }
+  if let Some(anonymizer) = anonymizer {
+    sink = sink.anonymize(anonymizer);
+  }
+
+  let control = Control::new();
+  if let Err(err) = daemon::handle_signals(control.clone()) {
+    error!("{}", err);
+    exit(1);
+  }
+  let mut daemon = daemon.control(control).sink(Box::new(sink));
+  if let Err(err) = daemon.run() {
+    error!("Polling failed: {}", err);
+    exit(1);
+  }
+}
+
+///
+/// Polls the neighbor table on a schedule, as the daemon does, printing the
+/// devices' charts in Netdata's external plugin protocol on stdout, until
+/// Netdata stops it. Fractions of a second aren't supported by Netdata, so
+/// the config's interval is rounded up.
+///
+fn run_netdata_plugin(config_path: Option<&Path>, update_every: Option<u64>) {
+  let (daemon, anonymizer) = plugin_daemon(config_path);
+  let interval = match update_every {
+    Some(update_every) => Duration::from_secs(update_every.max(1)),
+    None => Duration::from_secs(daemon.poll_interval().as_secs_f64().ceil().max(1.0) as u64),
+  };
+  let daemon = daemon.interval(interval);
+  let mut sink = NetdataSink::new(Box::new(std::io::stdout()), interval);
+  if let Some(anonymizer) = anonymizer {
+    sink = sink.anonymize(anonymizer);
+  }
+
+  let control = Control::new();
+  if let Err(err) = daemon::handle_signals(control.clone()) {
+    error!("{}", err);
+    exit(1);
+  }
+  let mut daemon = daemon.control(control).sink(Box::new(sink));
+  if let Err(err) = daemon.run() {
+    error!("Polling failed: {}", err);
+    exit(1);
+  }
+}
+
+///
+/// Polls the neighbor table once, as the daemon does, printing the devices'
+/// state on stdout, e.g. as the line protocol for Telegraf's exec input.
+///
+#[cfg(feature = "influxdb")]
+fn print_snapshot(config_path: Option<&Path>, format: SnapshotFormat) {
+  let (daemon, anonymizer) = plugin_daemon(config_path);
+  let sink = match format {
+    SnapshotFormat::Influx => {
+      let mut sink = InfluxLineSink::new(Box::new(std::io::stdout()));
+      if let Some(anonymizer) = anonymizer {
+        sink = sink.anonymize(anonymizer);
+      }
+      sink
+    }
+  };
+  let mut daemon = daemon.sink(Box::new(sink));
+  if let Err(err) = daemon.run_once() {
+    error!("Polling failed: {}", err);
+    exit(1);
+  }
+}
+
+/// Builds the poller of `netmon collectd`, `netmon netdata-plugin`, and
+/// `netmon snapshot` out of the config, without its sinks and alerts, along
+/// with its anonymizer.
+#[cfg(feature = "config")]
+fn plugin_daemon(config_path: Option<&Path>) -> (Daemon, Option<Anonymizer>) {
+  let mut config = Config::discover(config_path).unwrap_or_else(|err| {
+    error!("{}", err);
+    exit(1);
+  });
+  config.sinks.clear();
+  config.alerts.clear();
+  config.interface_policies.clear();
+  // collectd and Netdata expect a value every interval.
+  config.polling.adaptive = false;
+  let anonymizer = config.privacy.anonymizer().unwrap_or_else(|err| {
+    error!("{}", err);
+    exit(1);
+  });
+  (Daemon::from_config(&config), anonymizer)
+}
+
+/// Builds the poller of `netmon collectd`, `netmon netdata-plugin`, and
+/// `netmon snapshot` with its defaults, as config files aren't supported.
+#[cfg(not(feature = "config"))]
+fn plugin_daemon(config_path: Option<&Path>) -> (Daemon, Option<Anonymizer>) {
+  if config_path.is_some() {
+    error!("Config files require the 'config' feature");
+    exit(2);
+  }
+  let registry = Registry::load(Path::new(registry::DEFAULT_REGISTRY_PATH)).unwrap_or_else(|err| {
+    warn!("Skipping the known devices: {}", err);
+    Registry::new()
+  });
+  (Daemon::new().registry(registry), None)
+}
+
+/// Daemon along with the runtime paths of its config.
+struct DaemonSetup {
+  daemon: Daemon,
+  socket_path: PathBuf,
+  state_dir: StateDir,
+  pid_file: PathBuf,
+  health_listen: Option<SocketAddr>,
+  /// Address answering SNMP requests, and the community they come with.
+  snmp: Option<(SocketAddr, String)>,
+  /// Recent history kept in memory, if no storage sink keeps it.
+  history: Option<MemoryStorage>,
+  /// Metrics served along with the health checks, and over SNMP.
+  metrics: Metrics,
+}
+
+///
+/// Polls the neighbor table until SIGTERM or SIGINT, reloading on SIGHUP.
+/// Unless kept in the foreground, the daemon detaches once its config is
+/// loaded, with the launching process exiting once the first poll succeeded.
+/// Only one daemon runs at a time, holding the lock of its pid file.
+///
+fn run_daemon(
+  config_path: Option<&Path>,
+  interval: Option<Duration>,
+  foreground: bool,
+  force: bool,
+) {
+  let DaemonSetup {
+    daemon,
+    socket_path,
+    state_dir,
+    pid_file,
+    health_listen,
+    snmp,
+    history,
+    metrics,
+  } = build_daemon(config_path);
+
+  // Locked before detaching, so a second instance fails in the terminal.
+  let mut pid_file = PidFile::acquire(&pid_file, force).unwrap_or_else(|err| {
+    error!("{}", err);
+    exit(1);
+  });
+
+  // Detaching has to happen before any thread is spawned.
+  let ready_pipe = match foreground {
+    true => None,
+    false => {
+      let ready_pipe = daemon::detach().unwrap_or_else(|err| {
+        error!("{}", err);
+        exit(1);
+      });
+      if let Err(err) = pid_file.write_pid() {
+        warn!("{}", err);
+      }
+      Some(ready_pipe)
+    }
+  };
+
+  let control = Control::new();
+  if let Err(err) = daemon::handle_signals(control.clone()) {
+    error!("{}", err);
+    exit(1);
+  }
+
+  if let Err(err) = state_dir.create() {
+    warn!("State directory disabled: {}", err);
+  }
+  let ready_state_dir = state_dir.clone();
+  let health = Health::default();
+  let mut daemon = daemon
+    .control(control.clone())
+    .health(health.clone())
+    .sink(Box::new(StateSink::new(state_dir.clone())))
+    .on_ready(Box::new(move || {
+      debug!("Ready");
+      if let Err(err) = ready_state_dir.mark_ready() {
+        warn!("{}", err);
+      }
+      if let Some(ready_pipe) = ready_pipe {
+        ready_pipe.notify();
+      }
+    }));
+  if let Some(interval) = interval {
+    daemon = daemon.interval(interval);
+  }
+  if let Some(history) = &history {
+    daemon = daemon.sink(Box::new(history.clone()));
+  }
+  if health_listen.is_some() || snmp.is_some() {
+    daemon = daemon.sink(Box::new(metrics.clone()));
+  }
+  #[cfg(feature = "systemd")]
+  let mut daemon = with_systemd(daemon);
+
+  let socket = match ControlSocket::bind(&socket_path, control, history.clone()) {
+    Ok(socket) => Some(socket),
+    Err(err) => {
+      warn!("Control socket disabled: {}", err);
+      None
+    }
+  };
+  if let Some((addr, community)) = snmp {
+    match SnmpServer::bind(addr, &community, health.clone(), metrics.clone()) {
+      Ok(server) => info!("Answering SNMP requests on {}", server.addr()),
+      Err(err) => warn!("SNMP disabled: {}", err),
+    }
+  }
+  if let Some(addr) = health_listen {
+    let escalations = daemon.escalations().clone();
+    match HealthServer::bind(addr, health, history, metrics, escalations) {
+      Ok(server) => info!("Serving health checks on {}", server.addr()),
+      Err(err) => warn!("Health checks disabled: {}", err),
+    }
+  }
+  let result = daemon.run();
+  #[cfg(feature = "systemd")]
+  notify_systemd("STOPPING=1");
+  drop(socket);
+  state_dir.remove();
+  drop(pid_file);
+
+  if let Err(err) = result {
+    error!("Daemon failed: {}", err);
+    exit(1);
+  }
+  info!("Stopped");
+}
+
+/// Sends a systemd notification, logging failures.
+#[cfg(feature = "systemd")]
+fn notify_systemd(state: &str) {
+  if let Err(err) = daemon::systemd::notify(state) {
+    warn!("{}", err);
+  }
+}
+
+/// Notifies systemd once ready, and pings its watchdog from the polling loop.
+#[cfg(feature = "systemd")]
+fn with_systemd(mut daemon: Daemon) -> Daemon {
+  if !daemon::systemd::notify_enabled() {
+    return daemon;
+  }
+
+  daemon = daemon.on_ready(Box::new(|| notify_systemd("READY=1")));
+  if let Some(timeout) = daemon::systemd::watchdog_timeout() {
+    debug!("Pinging the systemd watchdog every {:?}", timeout / 2);
+    daemon = daemon.heartbeat(timeout / 2, Box::new(|| notify_systemd("WATCHDOG=1")));
+  }
+  daemon
+}
+
+/// Builds the daemon out of its config file.
+#[cfg(feature = "config")]
+fn build_daemon(config_path: Option<&Path>) -> DaemonSetup {
+  match Config::discover(config_path) {
+    Ok(config) => {
+      let metrics = match config.privacy.anonymizer() {
+        Ok(Some(anonymizer)) => Metrics::new().anonymize(anonymizer),
+        Ok(None) => Metrics::new(),
+        Err(err) => {
+          error!("{}", err);
+          exit(1);
+        }
+      };
+      DaemonSetup {
+        daemon: Daemon::from_config(&config).config_path(config_path.map(Path::to_path_buf)),
+        history: config.memory_history(),
+        metrics,
+        socket_path: config.control_socket,
+        state_dir: StateDir::new(&config.state_dir),
+        pid_file: config.pid_file,
+        health_listen: config.health_listen,
+        snmp: config
+          .snmp
+          .listen
+          .map(|v| (v, config.snmp.community.clone())),
+      }
+    }
+    Err(err) => {
+      error!("{}", err);
+      exit(1);
+    }
+  }
+}
+
+/// Builds the daemon with its defaults, as config files aren't supported.
+#[cfg(not(feature = "config"))]
+fn build_daemon(config_path: Option<&Path>) -> DaemonSetup {
+  if config_path.is_some() {
+    error!("Config files require the 'config' feature");
+    exit(2);
+  }
+  let registry = Registry::load(Path::new(registry::DEFAULT_REGISTRY_PATH)).unwrap_or_else(|err| {
+    warn!("Skipping the known devices: {}", err);
+    Registry::new()
+  });
+  DaemonSetup {
+    daemon: Daemon::new()
+      .registry(registry)
+      .sink(Box::new(daemon::LogSink)),
+    socket_path: PathBuf::from(daemon::socket::DEFAULT_SOCKET_PATH),
+    state_dir: StateDir::default(),
+    pid_file: PathBuf::from(daemon::pidfile::DEFAULT_PID_FILE),
+    health_listen: None,
+    snmp: None,
+    history: Some(MemoryStorage::default()),
+    metrics: Metrics::new(),
+  }
+}
+
+/// Edits the registry of known devices, then has a running daemon reload it.
+fn device_command(command: DeviceCommand) {
+  let (registry_path, socket_path) = match &command {
+    DeviceCommand::SetAlias {
+      registry, socket, ..
+    }
+    | DeviceCommand::Forget {
+      registry, socket, ..
+    } => (registry.clone(), socket.clone()),
+  };
+  let mut registry = Registry::load(&registry_path).unwrap_or_else(|err| {
+    error!("{}", err);
+    exit(1);
+  });
+  match command {
+    DeviceCommand::SetAlias {
+      mac_addr,
+      name,
+      owner,
+      category,
+      trust,
+      policy,
+      ..
+    } => {
+      if name.trim().is_empty() {
+        error!("The name of {} can't be empty", mac_addr);
+        exit(2);
+      }
+      let mut device = registry
+        .remove(&mac_addr)
+        .unwrap_or_else(|| KnownDevice::new(mac_addr));
+      device.name = Some(name);
+      device.owner = owner.or(device.owner);
+      device.category = category.or(device.category);
+      device.trust = trust.or(device.trust);
+      if let Some(policy) = policy {
+        device.policy = (!policy.is_empty()).then_some(policy);
+      }
+      registry.insert(device);
+    }
+    DeviceCommand::Forget { mac_addr, .. } => {
+      if registry.remove(&mac_addr).is_none() {
+        error!("{} isn't a known device", mac_addr);
+        exit(1);
+      }
+    }
+  }
+  if let Err(err) = registry.save(&registry_path) {
+    error!("{}", err);
+    exit(1);
+  }
+  match daemon::socket::send_command(&socket_path, "reload") {
+    Ok(reply) => debug!("Daemon replied '{}'", reply),
+    Err(err) => debug!("No daemon reloaded: {}", err),
+  }
+}
+
+/// Formats the registry as YAML or JSON.
+fn format_registry(registry: &Registry, format: RegistryFormat) -> anyhow::Result<String> {
+  match format {
+    RegistryFormat::Json => serde_json::to_string_pretty(registry)
+      .map(|v| v + "\n")
+      .map_err(|e| anyhow::Error::msg(format!("Failed to write JSON: {}", e))),
+    #[cfg(feature = "yaml")]
+    RegistryFormat::Yaml => serde_yaml::to_string(registry)
+      .map_err(|e| anyhow::Error::msg(format!("Failed to write YAML: {}", e))),
+    #[cfg(not(feature = "yaml"))]
+    RegistryFormat::Yaml => Err(anyhow::Error::msg(
+      "Built without YAML support, rebuild with the 'yaml' feature",
+    )),
+  }
+}
+
+/// Parses a registry written as YAML or JSON.
+fn parse_registry(content: &str, format: RegistryFormat) -> anyhow::Result<Registry> {
+  match format {
+    RegistryFormat::Json => {
+      serde_json::from_str(content).map_err(|e| anyhow::Error::msg(format!("Invalid JSON: {}", e)))
+    }
+    // An empty document has no devices, rather than no list of them.
+    #[cfg(feature = "yaml")]
+    RegistryFormat::Yaml if content.trim().is_empty() => Ok(Registry::new()),
+    #[cfg(feature = "yaml")]
+    RegistryFormat::Yaml => {
+      serde_yaml::from_str(content).map_err(|e| anyhow::Error::msg(format!("Invalid YAML: {}", e)))
+    }
+    #[cfg(not(feature = "yaml"))]
+    RegistryFormat::Yaml => Err(anyhow::Error::msg(
+      "Built without YAML support, rebuild with the 'yaml' feature",
+    )),
+  }
+}
+
+/// Exports or imports the registry of known devices.
+fn registry_command(command: &RegistryCommand) {
+  match command {
+    RegistryCommand::Export {
+      path,
+      format,
+      registry,
+    } => {
+      let format = format.unwrap_or_else(|| RegistryFormat::guess(path.as_deref()));
+      let written = Registry::load(registry)
+        .and_then(|v| format_registry(&v, format))
+        .and_then(|content| match path {
+          Some(path) => std::fs::write(path, content)
+            .map_err(|e| anyhow::Error::msg(format!("Failed to write {}: {}", path.display(), e))),
+          None => std::io::stdout()
+            .write_all(content.as_bytes())
+            .map_err(|e| anyhow::Error::msg(format!("Failed to write the registry: {}", e))),
+        });
+      if let Err(err) = written {
+        error!("{}", err);
+        exit(1);
+      }
+    }
+    RegistryCommand::Import {
+      path,
+      format,
+      on_conflict,
+      dry_run,
+      registry: registry_path,
+      socket,
+    } => {
+      let stdin = path.to_str() == Some("-");
+      let format = format.unwrap_or_else(|| RegistryFormat::guess((!stdin).then_some(path)));
+      let content = match stdin {
+        true => std::io::read_to_string(std::io::stdin()),
+        false => std::fs::read_to_string(path),
+      }
+      .unwrap_or_else(|err| {
+        error!("Failed to read {}: {}", path.display(), err);
+        exit(1);
+      });
+      let (mut registry, incoming) = match Registry::load(registry_path)
+        .and_then(|registry| Ok((registry, parse_registry(&content, format)?)))
+      {
+        Ok(v) => v,
+        Err(err) => {
+          error!("{}", err);
+          exit(1);
+        }
+      };
+
+      let policy = match on_conflict {
+        OnConflict::Replace => MergePolicy::TakeIncoming,
+        OnConflict::Fail | OnConflict::Keep => MergePolicy::KeepCurrent,
+      };
+      let report = registry.merge(&incoming, policy);
+      for conflict in &report.conflicts {
+        warn!("Conflict on {}", conflict);
+      }
+      info!(
+        "{} {} device(s), {} updated, {} unchanged, {} conflict(s)",
+        if *dry_run { "Would add" } else { "Added" },
+        report.added.len(),
+        report.updated.len(),
+        report.unchanged,
+        report.conflicts.len()
+      );
+      if *on_conflict == OnConflict::Fail && !report.conflicts.is_empty() {
+        error!("Imported nothing, pass --on-conflict keep or replace to resolve the conflicts");
+        exit(1);
+      }
+      if *dry_run || (report.added.is_empty() && report.updated.is_empty()) {
+        return;
+      }
+      if let Err(err) = registry.save(registry_path) {
+        error!("{}", err);
+        exit(1);
+      }
+      match daemon::socket::send_command(socket, "reload") {
+        Ok(reply) => debug!("Daemon replied '{}'", reply),
+        Err(err) => debug!("No daemon reloaded: {}", err),
+      }
+    }
+  }
+}
+
+/// Backs up, or restores, the monitor's state.
+#[cfg(feature = "config")]
+fn backup_command(command: &BackupCommand) {
+  match command {
+    BackupCommand::Create { path, config } => {
+      let loaded = Config::discover(config.as_deref()).unwrap_or_else(|err| {
+        error!("{}", err);
+        exit(1);
+      });
+      let files = backup::backup_files(&loaded, config.as_deref());
+      if let Err(err) = backup::create(path, &files) {
+        error!("{}", err);
+        exit(1);
+      }
+      for file in &files {
+        debug!("Backed up {}", file.path.display());
+      }
+      info!("Backed up {} file(s) to {}", files.len(), path.display());
+    }
+    BackupCommand::Restore { path, root, socket } => {
+      if daemon::socket::send_command(socket, "ping").is_ok() {
+        error!("The daemon is running, stop it first (e.g. 'service netmon stop')");
+        exit(1);
+      }
+      match backup::restore(path, root) {
+        Ok(restored) => {
+          for path in &restored {
+            info!("Restored {}", path.display());
+          }
+        }
+        Err(err) => {
+          error!("{}", err);
+          exit(1);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(not(feature = "config"))]
+fn backup_command(_command: &BackupCommand) {
+  error!("Backups require the 'config' feature");
+  exit(2);
+}
+
+/// Asks a running daemon to reload its config.
+fn reload_daemon(socket_path: &Path) {
+  match daemon::socket::send_command(socket_path, "reload") {
+    Ok(reply) => debug!("Daemon replied '{}'", reply),
+    Err(err) => {
+      error!("Failed to reload the daemon: {}", err);
+      exit(1);
+    }
+  }
+}
+
+/// Flushes neighbors matching the command line filters.
+fn flush_neighbors(
+  iface: Option<&str>,
+  nud_states: &[NudState],
+  dry_run: bool,
+  output: &OutputArgs,
+) {
+  let filter = match nud_states.is_empty() {
+    true => neighbors::stale_or_failed_filter(iface),
+    false => {
+      let filter = nud_states
+        .iter()
+        .fold(neighbors::NeighborFilter::new(), |acc, v| acc.nud_state(*v));
+      match iface {
+        Some(iface) => filter.iface(iface),
+        None => filter,
+      }
+    }
+  };
+
+  match neighbors::flush_neighbors(&filter, dry_run) {
+    Ok(flushed) => {
+      if output.json {
+        return print_json(&flushed);
+      }
+      for neigh in &flushed {
+        println!(
+          "{} dev {} {:?}",
+          neigh.scoped_ip(),
+          neigh.iface,
+          neigh.nud_state
+        );
+      }
+      info!(
+        "{} {} neighbor(s)",
+        if dry_run { "Would flush" } else { "Flushed" },
+        flushed.len()
+      );
+    }
+    Err(err) => {
+      error!("Failed to flush neighbors: {}", err);
+      exit(1);
+    }
+  }
+}
+
+/// Looks up vendors, or refreshes the on-disk OUI database.
+fn vendor_command(command: &VendorCommand) {
+  match command {
+    VendorCommand::Lookup { mac_addr } => match mac_addr.vendor() {
+      Some(vendor) => println!("{}", vendor),
+      None => {
+        error!("No vendor found for {}", mac_addr);
+        exit(1);
+      }
+    },
+    VendorCommand::Update { url, path } => {
+      if let Err(err) = vendor::update_database(url, path) {
+        error!("Failed to update the OUI database: {}", err);
+        exit(1);
+      }
+    }
+  }
+}
+
+///
+/// Prints the current devices as Zabbix low-level discovery JSON, hashed as
+/// the zabbix sink hashes them so the discovered keys match its values.
+///
+#[cfg(feature = "zabbix")]
+fn zabbix_command(command: &ZabbixCommand) {
+  match command {
+    ZabbixCommand::Lld {
+      discovery,
+      anonymize,
+    } => {
+      let anonymizer = export::anonymizer(*anonymize).unwrap_or_else(|err| {
+        error!("{}", err);
+        exit(1);
+      });
+      let mut devices = collect_devices(&FilterArgs::default(), discovery);
+      if let Some(anonymizer) = anonymizer {
+        devices = devices.iter().map(|v| anonymizer.device(v)).collect();
+      }
+      let macs: Vec<String> = devices.iter().map(|v| v.mac_addr.to_string()).collect();
+      let devices = devices.iter().zip(&macs).map(|(device, mac)| {
+        (
+          mac.as_str(),
+          device.name().unwrap_or_default(),
+          device.iface.as_str(),
+          device.vendor.as_deref(),
+        )
+      });
+      println!("{}", zabbix::discovery(devices));
+    }
+  }
+}
 
 fn main() {
-  // Initialize global logger. Logger value can be set via the 'RUST_LOG' environment variable.
-  env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+  let cli = Cli::parse_from(cli::args());
 
-  let ip_neigh_vec = net_util::get_ip_neighbors().unwrap();
-  info!("them neighhhs -> {:#?}", ip_neigh_vec);
+  // Initialize global logger. Logger value can be set via the 'RUST_LOG' environment variable.
+  logging::init(cli.log_format, cli.log_target, cli.syslog_facility);
+  match cli.command {
+    None => list_neighbors(false, &FilterArgs::default(), &OutputArgs::default()),
+    Some(Command::List {
+      stats,
+      filter,
+      output,
+    }) => list_neighbors(stats, &filter, &output),
+    Some(Command::Watch { filter, output }) => watch_neighbors(&filter, &output),
+    Some(Command::Daemon {
+      config,
+      interval,
+      foreground,
+      force,
+    }) => run_daemon(config.as_deref(), interval, foreground, force),
+    Some(Command::Collectd { config, interval }) => run_collectd(config.as_deref(), interval),
+    Some(Command::NetdataPlugin {
+      update_every,
+      config,
+    }) => run_netdata_plugin(config.as_deref(), update_every),
+    #[cfg(feature = "influxdb")]
+    Some(Command::Snapshot { format, config }) => print_snapshot(config.as_deref(), format),
+    Some(Command::Reload { socket }) => reload_daemon(&socket),
+    Some(Command::Devices {
+      discovery,
+      filter,
+      output,
+    }) => list_devices(&discovery, &filter, &output),
+    Some(Command::Device {
+      command: Some(command),
+      ..
+    }) => device_command(command),
+    Some(Command::Device {
+      mac_addr,
+      socket,
+      discovery,
+      output,
+      ..
+    }) => show_device(mac_addr.unwrap(), &socket, &discovery, &output),
+    Some(Command::History {
+      device,
+      since,
+      config,
+      socket,
+      output,
+    }) => print_timeline(
+      &device,
+      since,
+      config.as_deref(),
+      socket.as_deref(),
+      &output,
+    ),
+    Some(Command::Export {
+      export: args,
+      discovery,
+      filter,
+      output,
+    }) => export(&args, &discovery, &filter, &output),
+    Some(Command::Ports { filter, output }) => list_neighbor_ports(&filter, &output),
+    Some(Command::Flush {
+      dev,
+      nud,
+      dry_run,
+      output,
+    }) => flush_neighbors(dev.as_deref(), &nud, dry_run, &output),
+    Some(Command::Parse {
+      from_file,
+      devices,
+      discovery,
+      filter,
+      output,
+    }) => parse_capture(&from_file, devices, &discovery, &filter, &output),
+    Some(Command::Registry { command }) => registry_command(&command),
+    Some(Command::Backup { command }) => backup_command(&command),
+    Some(Command::Vendor { command }) => vendor_command(&command),
+    #[cfg(feature = "zabbix")]
+    Some(Command::Zabbix { command }) => zabbix_command(&command),
+  }
 }
@@ -0,0 +1,121 @@
+use super::{ArpTable, NudState};
+use std::net::IpAddr;
+
+/// Address family of a neighbor entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AddressFamily {
+  /// IPv4 (ARP) neighbors.
+  Inet,
+  /// IPv6 (NDP) neighbors.
+  Inet6,
+}
+
+impl AddressFamily {
+  /// Returns the family of the given address.
+  pub fn of(ip: &IpAddr) -> Self {
+    match ip {
+      IpAddr::V4(_) => AddressFamily::Inet,
+      IpAddr::V6(_) => AddressFamily::Inet6,
+    }
+  }
+}
+
+///
+/// Restricts which neighbors are collected. Filters are pushed down to the
+/// collector (`ip neigh show ...` arguments or the netlink dump request), so
+/// only matching entries are returned by the kernel wherever possible.
+///
+/// ```
+/// use openwrt_netmon::{AddressFamily, NeighborFilter, NudState};
+///
+/// let filter = NeighborFilter::new()
+///   .iface("br-lan")
+///   .family(AddressFamily::Inet)
+///   .nud_state(NudState::REACHABLE)
+///   .nud_state(NudState::STALE);
+///
+/// assert_eq!(
+///   filter.to_ip_args().join(" "),
+///   "-4 neigh show dev br-lan nud reachable nud stale"
+/// );
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NeighborFilter {
+  pub iface: Option<String>,
+  pub family: Option<AddressFamily>,
+  pub nud_states: NudState,
+  pub vrf: Option<String>,
+}
+
+impl NeighborFilter {
+  /// Creates a filter matching every neighbor `ip neigh` would list.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Restricts results to a single device.
+  pub fn iface(mut self, iface: &str) -> Self {
+    self.iface = Some(iface.to_string());
+    self
+  }
+
+  /// Restricts results to the devices enslaved to a VRF.
+  pub fn vrf(mut self, vrf: &str) -> Self {
+    self.vrf = Some(vrf.to_string());
+    self
+  }
+
+  /// Restricts results to a single address family.
+  pub fn family(mut self, family: AddressFamily) -> Self {
+    self.family = Some(family);
+    self
+  }
+
+  /// Adds NUD states to match. Entries matching any of the states are kept.
+  pub fn nud_state(mut self, nud_state: NudState) -> Self {
+    self.nud_states |= nud_state;
+    self
+  }
+
+  ///
+  /// Builds the `ip` arguments applying this filter.
+  ///
+  /// Returns:
+  ///  Arguments such as ["-4", "neigh", "show", "dev", "br-lan", "nud", "reachable"].
+  ///
+  pub fn to_ip_args(&self) -> Vec<String> {
+    let mut args = Vec::new();
+    match self.family {
+      Some(AddressFamily::Inet) => args.push("-4".to_string()),
+      Some(AddressFamily::Inet6) => args.push("-6".to_string()),
+      None => {}
+    }
+    args.extend(["neigh".to_string(), "show".to_string()]);
+
+    if let Some(iface) = &self.iface {
+      args.extend(["dev".to_string(), iface.clone()]);
+    }
+    if let Some(vrf) = &self.vrf {
+      args.extend(["vrf".to_string(), vrf.clone()]);
+    }
+
+    for (name, _) in self.nud_states.iter_names() {
+      args.extend(["nud".to_string(), name.to_lowercase()]);
+    }
+
+    args
+  }
+
+  /// Whether the given neighbor satisfies every part of the filter. VRF
+  /// membership is only known to the kernel, so it isn't checked here.
+  pub fn matches(&self, neigh: &ArpTable) -> bool {
+    self.iface.as_ref().is_none_or(|v| *v == neigh.iface)
+      && self
+        .family
+        .is_none_or(|v| v == AddressFamily::of(&neigh.ip))
+      && (self.nud_states.is_empty() || self.nud_states.intersects(neigh.nud_state))
+  }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+///
+/// 48-bit link layer (MAC) address.
+///
+/// Parsing accepts the common notations ("dc:a6:32:57:46:d6",
+/// "DC-A6-32-57-46-D6", "dca6.3257.46d6" and "dca6325746d6"), and always
+/// displays the normalized lowercase ':' separated form used by `ip neigh`.
+///
+/// ```
+/// use openwrt_netmon::MacAddr;
+///
+/// let mac_addr: MacAddr = "DC-A6-32-57-46-D6".parse()?;
+/// assert_eq!(mac_addr.to_string(), "dc:a6:32:57:46:d6");
+/// assert_eq!(mac_addr.oui(), [0xdc, 0xa6, 0x32]);
+/// assert!(!mac_addr.is_locally_administered());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+  ///
+  /// Builds a MacAddr from raw link layer address bytes.
+  ///
+  /// Args:
+  ///  - bytes: Address bytes, as reported by netlink.
+  ///
+  /// Returns:
+  ///  The address, or None if it isn't exactly 6 bytes long.
+  ///
+  pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+    let octets: [u8; 6] = bytes.try_into().ok()?;
+    Some(MacAddr(octets))
+  }
+
+  /// Raw address bytes.
+  pub fn octets(&self) -> [u8; 6] {
+    self.0
+  }
+
+  /// Organizationally Unique Identifier, the first three octets assigned to the vendor.
+  pub fn oui(&self) -> [u8; 3] {
+    [self.0[0], self.0[1], self.0[2]]
+  }
+
+  /// Whether the U/L bit is set, meaning the address wasn't assigned by the vendor
+  /// (e.g. randomized private addresses, or virtual interfaces).
+  pub fn is_locally_administered(&self) -> bool {
+    self.0[0] & 0x02 != 0
+  }
+
+  /// Name of the vendor the address was assigned to, according to the OUI database.
+  pub fn vendor(&self) -> Option<&'static str> {
+    crate::vendor::database().lookup(self)
+  }
+
+  /// Whether this looks like a randomized (private) address, as rotated by modern
+  /// phones and laptops: a locally administered unicast address.
+  pub fn is_randomized(&self) -> bool {
+    self.is_locally_administered() && self.is_unicast() && !self.is_zero()
+  }
+
+  /// Whether the I/G bit is set, meaning this is a group (multicast/broadcast) address.
+  pub fn is_multicast(&self) -> bool {
+    self.0[0] & 0x01 != 0
+  }
+
+  /// Whether this is a single station address.
+  pub fn is_unicast(&self) -> bool {
+    !self.is_multicast()
+  }
+
+  /// Whether this is the ff:ff:ff:ff:ff:ff broadcast address.
+  pub fn is_broadcast(&self) -> bool {
+    self.0 == [0xff; 6]
+  }
+
+  /// Whether this is the all zeroes address, which some drivers report for unresolved entries.
+  pub fn is_zero(&self) -> bool {
+    self.0 == [0; 6]
+  }
+}
+
+///
+/// Splits an address string into its hex digit groups.
+///
+/// Args:
+///  - s: Address in one of the supported notations.
+///
+/// Returns:
+///  The groups along with the number of hex digits each should have, or None
+///  for an unknown notation.
+///
+fn split_mac_groups(s: &str) -> Option<(Vec<&str>, usize)> {
+  if s.contains(':') {
+    Some((s.split(':').collect(), 2))
+  } else if s.contains('-') {
+    Some((s.split('-').collect(), 2))
+  } else if s.contains('.') {
+    Some((s.split('.').collect(), 4))
+  } else if s.len() == 12 {
+    Some((vec![s], 12))
+  } else {
+    None
+  }
+}
+
+impl FromStr for MacAddr {
+  type Err = Error;
+
+  /// Parses an address in any of the supported notations, such as "dc:a6:32:57:46:d6".
+  fn from_str(s: &str) -> Result<Self> {
+    let invalid = || Error::msg(format!("Invalid mac address '{}'", s));
+    let (groups, digits_per_group) = split_mac_groups(s.trim()).ok_or_else(invalid)?;
+
+    // ':' and '-' separated octets may omit leading zeroes (e.g. "0:1:5c:68:3c:46").
+    let mut hex = String::with_capacity(12);
+    for group in &groups {
+      let valid_len = match digits_per_group {
+        2 => (1..=2).contains(&group.len()),
+        len => group.len() == len,
+      };
+      if !valid_len || !group.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+      }
+      hex.push_str(&format!("{:0>width$}", group, width = digits_per_group));
+    }
+    if hex.len() != 12 {
+      return Err(invalid());
+    }
+
+    let mut octets = [0u8; 6];
+    for (i, octet) in octets.iter_mut().enumerate() {
+      *octet = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+
+    Ok(MacAddr(octets))
+  }
+}
+
+/// Serialized as its string form, e.g. "dc:a6:32:57:46:d6".
+#[cfg(feature = "serde")]
+impl serde::Serialize for MacAddr {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MacAddr {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> std::result::Result<Self, D::Error> {
+    let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+    MacAddr::from_str(&s).map_err(serde::de::Error::custom)
+  }
+}
+
+impl fmt::Display for MacAddr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let [a, b, c, d, e, g] = self.0;
+    write!(
+      f,
+      "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+      a, b, c, d, e, g
+    )
+  }
+}
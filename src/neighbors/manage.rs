@@ -0,0 +1,111 @@
+use super::{
+  get_ip_neighbors_filtered, netlink, run_ip_command, ArpTable, MacAddr, NeighborFilter, NudState,
+};
+use anyhow::Result;
+use log::{debug, info, warn};
+use std::net::IpAddr;
+
+///
+/// Pins a permanent (static) neighbor entry, e.g. to protect critical hosts
+/// against ARP spoofing. Existing entries for the address are replaced.
+///
+/// The entry is written over netlink, falling back to `ip neigh replace`.
+///
+/// Args:
+///  - ip: Neighbor address.
+///  - mac_addr: Link layer address the address should always resolve to.
+///  - iface: Device the entry belongs to.
+///
+/// Returns:
+///  Result reflecting whether the entry was written.
+///
+pub fn add_static_neighbor(ip: &IpAddr, mac_addr: &MacAddr, iface: &str) -> Result<()> {
+  netlink::replace_neighbor(ip, mac_addr, iface, NudState::PERMANENT).or_else(|err| {
+    warn!("{}, falling back to 'ip neigh replace'", err);
+
+    let ip_str = ip.to_string();
+    let mac_str = mac_addr.to_string();
+    run_ip_command(&[
+      "neigh",
+      "replace",
+      &ip_str,
+      "lladdr",
+      &mac_str,
+      "dev",
+      iface,
+      "nud",
+      "permanent",
+    ])
+    .map(|_| ())
+  })?;
+
+  info!("Pinned {} dev {} lladdr {}", ip, iface, mac_addr);
+  Ok(())
+}
+
+///
+/// Deletes a neighbor entry.
+///
+/// The entry is removed over netlink, falling back to `ip neigh del`.
+///
+/// Args:
+///  - ip: Neighbor address.
+///  - iface: Device the entry belongs to.
+///
+/// Returns:
+///  Result reflecting whether the entry was removed.
+///
+pub fn delete_neighbor(ip: &IpAddr, iface: &str) -> Result<()> {
+  netlink::delete_neighbor(ip, iface).or_else(|err| {
+    warn!("{}, falling back to 'ip neigh del'", err);
+
+    let ip_str = ip.to_string();
+    run_ip_command(&["neigh", "del", &ip_str, "dev", iface]).map(|_| ())
+  })?;
+
+  info!("Deleted {} dev {}", ip, iface);
+  Ok(())
+}
+
+///
+/// Removes every neighbor matching the filter, like `ip neigh flush`.
+///
+/// Args:
+///  - filter: Entries to remove. Use `stale_or_failed_filter` for the usual
+///    maintenance flush.
+///  - dry_run: Only report what would be removed, without touching the table.
+///
+/// Returns:
+///  Result of the entries that were (or would have been) removed.
+///
+pub fn flush_neighbors(filter: &NeighborFilter, dry_run: bool) -> Result<Vec<ArpTable>> {
+  let entries = match netlink::get_ip_neighbors_filtered(filter) {
+    Ok(entries) => entries,
+    Err(err) => {
+      warn!("{}, falling back to 'ip neigh show'", err);
+      get_ip_neighbors_filtered(filter)?
+    }
+  };
+
+  for entry in &entries {
+    if dry_run {
+      debug!("Would flush {} dev {}", entry.scoped_ip(), entry.iface);
+      continue;
+    }
+    delete_neighbor(&entry.ip, &entry.iface)?;
+  }
+
+  Ok(entries)
+}
+
+/// Filter matching STALE and FAILED entries, optionally restricted to one interface.
+pub fn stale_or_failed_filter(iface: Option<&str>) -> NeighborFilter {
+  let filter = NeighborFilter::new()
+    .nud_state(NudState::STALE)
+    .nud_state(NudState::FAILED);
+
+  match iface {
+    Some(iface) => filter.iface(iface),
+    None => filter,
+  }
+}
@@ -0,0 +1,709 @@
+use crate::counters;
+use anyhow::{Error, Result};
+use bitflags::bitflags;
+use log::{debug, warn};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{net::IpAddr, str::FromStr};
+
+pub mod filter;
+pub mod mac;
+pub mod manage;
+pub mod netlink;
+pub mod ra;
+pub mod route;
+pub mod runner;
+pub mod scoped_ip;
+pub mod stats;
+pub mod vrf;
+
+pub use filter::{AddressFamily, NeighborFilter};
+pub use mac::MacAddr;
+pub use manage::{add_static_neighbor, delete_neighbor, flush_neighbors, stale_or_failed_filter};
+pub use runner::{CommandRunner, FixtureRunner, SystemRunner};
+pub use scoped_ip::ScopedIpAddr;
+pub use stats::NeighborStats;
+pub use vrf::VrfDevice;
+
+/*
+https://man7.org/linux/man-pages/man8/ip-neighbour.8.html
+   PERMANENT
+    the neighbour entry is valid forever and can
+    be only be removed administratively.
+
+   NOARP
+    the neighbour entry is valid. No attempts to
+    validate this entry will be made but it can
+    be removed when its lifetime expires.
+
+   REACHABLE
+    the neighbour entry is valid until the
+    reachability timeout expires.
+
+   STALE
+    the neighbour entry is valid but suspicious.
+    This option to ip neigh does not change the
+    neighbour state if it was valid and the
+    address is not changed by this command.
+
+   NONE
+    this is a pseudo state used when initially
+    creating a neighbour entry or after trying
+    to remove it before it becomes free to do
+    so.
+
+   INCOMPLETE
+    the neighbour entry has not (yet) been
+    validated/resolved.
+
+   DELAY
+    neighbor entry validation is currently
+    delayed.
+
+   PROBE
+    neighbor is being probed.
+
+   FAILED
+      max number of probes exceeded without
+      success, neighbor validation has ultimately
+      failed.
+*/
+bitflags! {
+  ///
+  /// Set of NUD states, using the kernel's NUD_* values. The kernel can report
+  /// several states at once (e.g. "NOARP PERMANENT"), so this is a set rather
+  /// than a single state. NONE is the empty set.
+  ///
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct NudState: u16 {
+    const INCOMPLETE = libc::NUD_INCOMPLETE;
+    const REACHABLE = libc::NUD_REACHABLE;
+    const STALE = libc::NUD_STALE;
+    const DELAY = libc::NUD_DELAY;
+    const PROBE = libc::NUD_PROBE;
+    const FAILED = libc::NUD_FAILED;
+    const NOARP = libc::NUD_NOARP;
+    const PERMANENT = libc::NUD_PERMANENT;
+  }
+}
+
+impl NudState {
+  /// Pseudo state of a freshly created entry, with no state bits set.
+  pub const NONE: NudState = NudState::empty();
+
+  /// States in which the entry holds a usable link layer address (the kernel's NUD_VALID).
+  pub const VALID: NudState = NudState::PERMANENT
+    .union(NudState::NOARP)
+    .union(NudState::REACHABLE)
+    .union(NudState::PROBE)
+    .union(NudState::STALE)
+    .union(NudState::DELAY);
+
+  /// Whether any of the states hold a usable link layer address.
+  pub fn is_valid(&self) -> bool {
+    self.intersects(NudState::VALID)
+  }
+}
+
+/// Parses a single NUD state keyword, returning None if it isn't one.
+pub fn parse_nud_keyword(nud_state_str: &str) -> Option<NudState> {
+  match nud_state_str.to_uppercase().as_str() {
+    "PERMANENT" => Some(NudState::PERMANENT),
+    "NOARP" => Some(NudState::NOARP),
+    "REACHABLE" => Some(NudState::REACHABLE),
+    "STALE" => Some(NudState::STALE),
+    "NONE" => Some(NudState::NONE),
+    "INCOMPLETE" => Some(NudState::INCOMPLETE),
+    "DELAY" => Some(NudState::DELAY),
+    "PROBE" => Some(NudState::PROBE),
+    "FAILED" => Some(NudState::FAILED),
+    _ => None,
+  }
+}
+
+/// Parses a whitespace separated list of NUD state keywords, such as "NOARP PERMANENT".
+pub fn parse_nud_from_str(nud_state_str: &str) -> NudState {
+  nud_state_str
+    .split_whitespace()
+    .fold(NudState::NONE, |acc, v| match parse_nud_keyword(v) {
+      Some(nud_state) => acc | nud_state,
+      None => {
+        warn!("Ignoring unknown NUD state '{}'", v);
+        acc
+      }
+    })
+}
+
+bitflags! {
+  /// Extra neighbor entry flags, using the kernel's NTF_* values.
+  #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct NeighborFlags: u8 {
+    /// Entry is a proxy (ARP/NDP proxy) entry.
+    const PROXY = 0x08;
+    /// Entry was learned externally, e.g. by a switchdev driver.
+    const EXTERN_LEARN = 0x10;
+    /// Entry is offloaded to hardware.
+    const OFFLOAD = 0x20;
+    /// Neighbor is an IPv6 router.
+    const ROUTER = 0x80;
+  }
+}
+
+/// Parses a single `ip neigh` flag keyword, returning None if it isn't one.
+pub fn parse_neighbor_flag_from_str(flag_str: &str) -> Option<NeighborFlags> {
+  match flag_str.to_lowercase().as_str() {
+    "proxy" => Some(NeighborFlags::PROXY),
+    "extern_learn" => Some(NeighborFlags::EXTERN_LEARN),
+    "offload" => Some(NeighborFlags::OFFLOAD),
+    "router" => Some(NeighborFlags::ROUTER),
+    _ => None,
+  }
+}
+
+/*
+  192.168.0.33 dev br-lan lladdr dc:a6:32:57:46:d6 ref 1 used 0/0/0 probes 1 REACHABLE
+  192.168.0.5 dev br-lan lladdr dc:a6:32:a3:48:b1 ref 1 used 0/0/0 probes 1 REACHABLE
+  192.168.0.2 dev br-lan  used 0/0/0 probes 6 FAILED
+  192.168.0.147 dev br-lan lladdr 24:4b:fe:06:f8:3c ref 1 used 0/0/0 probes 1 REACHABLE
+  192.168.0.200 dev br-lan lladdr 0a:99:ad:f6:ce:e6 used 0/0/0 probes 1 STALE
+  172.119.56.1 dev eth1 lladdr 00:01:5c:68:3c:46 ref 1 used 0/0/0 probes 1 REACHABLE
+  192.168.0.100 dev br-lan lladdr 1a:42:85:a2:22:fb ref 1 used 0/0/0 probes 1 REACHABLE
+  192.168.0.11 dev br-lan lladdr 54:af:97:06:5d:7c ref 1 used 0/0/0 probes 1 REACHABLE
+  192.168.0.8 dev br-lan lladdr 88:66:5a:49:16:b3 used 0/0/0 probes 1 STALE
+  fd35:e227:2f15::169 dev br-lan lladdr 24:4b:fe:06:f8:3c used 0/0/0 probes 1 STALE
+  fe80::e132:56de:1eac:d560 dev br-lan lladdr 24:4b:fe:06:f8:3c used 0/0/0 probes 1 STALE
+  fe80::1866:4ccf:140e:95b0 dev br-lan lladdr 1a:42:85:a2:22:fb used 0/0/0 probes 4 STALE
+
+  Newer iproute2 releases drop the trailing counters, and entries that were
+  never resolved (FAILED/INCOMPLETE) have no link layer address at all:
+  192.168.0.2 dev br-lan FAILED
+
+  Flags are printed right after the link layer address:
+  fe80::1 dev eth1 lladdr 00:01:5c:68:3c:46 router REACHABLE
+  192.168.0.50 dev br-lan lladdr 0a:99:ad:f6:ce:e6 extern_learn offload REACHABLE
+
+  So we're parsing:
+  <ipv(4|6) address> <dev> <iface> [<lladdr> <mac>] [<flags>] .* [<nud states>]
+*/
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArpTable {
+  pub ip: IpAddr,
+  pub iface: String,
+  pub mac_addr: Option<MacAddr>,
+  pub flags: NeighborFlags,
+  pub nud_state: NudState,
+  pub stats: Option<NeighborStats>,
+  pub vrf: Option<String>,
+}
+
+impl ArpTable {
+  /// Neighbor address scoped to its interface, e.g. "fe80::1%br-lan" for link-local addresses.
+  pub fn scoped_ip(&self) -> ScopedIpAddr {
+    ScopedIpAddr::new(self.ip, &self.iface)
+  }
+
+  /// Whether the neighbor address is an IPv6 link-local address, only meaningful on its interface.
+  pub fn is_link_local(&self) -> bool {
+    scoped_ip::is_ipv6_link_local(&self.ip)
+  }
+
+  ///
+  /// Parses a string into an IpNeighbor instance, given the string matches
+  /// a line result from:
+  /// https://man7.org/linux/man-pages/man8/ip-neighbour.8.html.
+  ///
+  /// Entries without a (valid) link layer address are still parsed, leaving
+  /// mac_addr empty.
+  ///
+  /// Args:
+  ///  - s: Row result as a string.
+  ///
+  /// Returns:
+  ///  Result reflecting a successful parse.
+  ///
+  pub fn parse_from_string(s: &str) -> Result<Self> {
+    // <ipv(4|6) address> <dev> <iface> [<lladdr> <mac>] [<flags>] .* [<nud states>]
+    let sliced_str: Vec<&str> = s.split_whitespace().collect();
+    debug!("Sliced string -> {:?}", sliced_str);
+
+    // Expect to have at least the address and device.
+    if sliced_str.len() < 3 {
+      return Err(Error::msg(format!("Unexpected string -> {}", s)));
+    }
+
+    // Attempt to parse the ip.
+    let ip_addr_str = sliced_str.first().unwrap();
+    let ip_addr = IpAddr::from_str(ip_addr_str)
+      .map_err(|e| Error::msg(format!("Failed to parse {}: {:?}", ip_addr_str, e)))?;
+    debug!("Parsed ip address -> {:?}", ip_addr);
+
+    // Extract the device name.
+    let dev_str = sliced_str.get(1).unwrap().to_lowercase();
+    if dev_str != "dev" {
+      return Err(Error::msg(format!(
+        "No device name found, expected 'dev' but got '{}'",
+        dev_str,
+      )));
+    }
+    let dev_name = sliced_str.get(2).unwrap().to_string();
+    debug!("Extracted device name -> {:?}", dev_name);
+
+    // Extract the device's mac address, if the entry was ever resolved.
+    let mac_address = match sliced_str.get(3).map(|v| v.to_lowercase()) {
+      Some(lladdr_str) if lladdr_str == "lladdr" => {
+        sliced_str.get(4).and_then(|v| match MacAddr::from_str(v) {
+          Ok(mac) => Some(mac),
+          Err(e) => {
+            warn!("Ignoring link layer address: {}", e);
+            None
+          }
+        })
+      }
+      _ => {
+        debug!("No link layer address found -> {}", s);
+        None
+      }
+    };
+    debug!("Extracted device mac address -> {:?}", mac_address);
+
+    // Collect any flag keywords following the device.
+    let flags = sliced_str
+      .iter()
+      .skip(3)
+      .filter_map(|v| parse_neighbor_flag_from_str(v))
+      .fold(NeighborFlags::empty(), |acc, flag| acc | flag);
+    debug!("Parsed neighbor flags -> {:?}", flags);
+
+    // Parse the cache counters, if the row carries them.
+    let stats = NeighborStats::parse_from_tokens(&sliced_str[3..]);
+    debug!("Parsed neighbor stats -> {:?}", stats);
+
+    // Collect every trailing nud state keyword, which are absent on bare entries.
+    let nud_state = sliced_str
+      .iter()
+      .skip(3)
+      .rev()
+      .map_while(|v| parse_nud_keyword(v))
+      .fold(NudState::NONE, |acc, v| acc | v);
+    debug!("Parsed NUD State -> {:?}", nud_state);
+
+    Ok(ArpTable {
+      ip: ip_addr,
+      iface: dev_name,
+      mac_addr: mac_address,
+      flags,
+      nud_state,
+      stats,
+      vrf: None,
+    })
+  }
+}
+
+/// Collects the host's neighbors, preferring to query the kernel directly and
+/// falling back to the 'ip' command.
+pub fn collect_neighbors() -> Result<Vec<ArpTable>> {
+  netlink::get_ip_neighbors().or_else(|err| {
+    warn!(
+      "Netlink neighbor dump failed, falling back to 'ip neigh': {}",
+      err
+    );
+    get_ip_neighbors()
+  })
+}
+
+/// Generates a parsed array of ArpTable results from the host.
+pub fn get_ip_neighbors() -> Result<Vec<ArpTable>> {
+  get_ip_neighbors_with(&SystemRunner::new())
+}
+
+/// Generates a parsed array of ArpTable results, running 'ip' through the given runner.
+pub fn get_ip_neighbors_with(runner: &dyn CommandRunner) -> Result<Vec<ArpTable>> {
+  get_ip_neighbors_filtered_with(runner, &NeighborFilter::new())
+}
+
+/// Generates a parsed array of ArpTable results for a single device, like `ip neigh show dev <iface>`.
+pub fn get_ip_neighbors_for_iface(iface: &str) -> Result<Vec<ArpTable>> {
+  get_ip_neighbors_filtered(&NeighborFilter::new().iface(iface))
+}
+
+///
+/// Generates a parsed array of ArpTable results along with their cache
+/// counters, by running `ip -s neigh`.
+///
+/// Args:
+///  - filter: Interface, address family, and NUD state restrictions.
+///
+/// Returns:
+///  Result of the matching neighbors, with stats filled in.
+///
+pub fn get_ip_neighbors_detailed(filter: &NeighborFilter) -> Result<Vec<ArpTable>> {
+  get_ip_neighbors_detailed_with(&SystemRunner::new(), filter)
+}
+
+///
+/// Generates a parsed array of ArpTable results along with their cache
+/// counters, running `ip -s neigh` through the given runner.
+///
+/// Args:
+///  - runner: Runs the 'ip' command.
+///  - filter: Interface, address family, and NUD state restrictions.
+///
+/// Returns:
+///  Result of the matching neighbors, with stats filled in.
+///
+pub fn get_ip_neighbors_detailed_with(
+  runner: &dyn CommandRunner,
+  filter: &NeighborFilter,
+) -> Result<Vec<ArpTable>> {
+  let mut args = vec!["-s".to_string()];
+  args.extend(filter.to_ip_args());
+  parse_ip_neigh_output(&runner.run_args("ip", &args)?)
+}
+
+///
+/// Generates a parsed array of ArpTable results from the host, letting 'ip'
+/// apply the given filter.
+///
+/// Args:
+///  - filter: Interface, address family, and NUD state restrictions.
+///
+/// Returns:
+///  Result of the matching neighbors.
+///
+pub fn get_ip_neighbors_filtered(filter: &NeighborFilter) -> Result<Vec<ArpTable>> {
+  get_ip_neighbors_filtered_with(&SystemRunner::new(), filter)
+}
+
+///
+/// Generates a parsed array of ArpTable results, running 'ip' through the
+/// given runner and letting it apply the filter.
+///
+/// Args:
+///  - runner: Runs the 'ip' command.
+///  - filter: Interface, address family, and NUD state restrictions.
+///
+/// Returns:
+///  Result of the matching neighbors.
+///
+pub fn get_ip_neighbors_filtered_with(
+  runner: &dyn CommandRunner,
+  filter: &NeighborFilter,
+) -> Result<Vec<ArpTable>> {
+  parse_ip_neigh_output(&runner.run_args("ip", &filter.to_ip_args())?)
+}
+
+///
+/// Parses the rows of an `ip neigh` listing.
+///
+/// Args:
+///  - stdout: Output of the command.
+///
+/// Returns:
+///  Result of the parsed neighbors, skipping rows that failed to parse.
+///
+pub fn parse_ip_neigh_output(stdout: &str) -> Result<Vec<ArpTable>> {
+  let stdout_filtered: Vec<&str> = stdout
+    .split('\n')
+    .map(|s| s.trim())
+    .filter(|s| !s.is_empty())
+    .collect();
+
+  // Skip rows we can't make sense of rather than failing the whole table.
+  let neighbors = stdout_filtered
+    .iter()
+    .filter_map(|v| match ArpTable::parse_from_string(v) {
+      Ok(neigh) => Some(neigh),
+      Err(e) => {
+        warn!("Skipping neighbor entry: {}", e);
+        counters::record_parse_error();
+        None
+      }
+    })
+    .collect();
+
+  Ok(neighbors)
+}
+
+/// Runs the 'ip' command with the given arguments, returning its stdout.
+pub(crate) fn run_ip_command<S: AsRef<std::ffi::OsStr>>(args: &[S]) -> Result<String> {
+  run_command("ip", args)
+}
+
+/// How long external commands may run for before they're killed.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Unblocks every signal in the child, as the daemon blocks its shutdown
+/// signals and children inherit the mask, and puts it in its own process
+/// group, so a timed out command is killed along with its own children.
+fn prepare_command(command: &mut Command) -> &mut Command {
+  command.process_group(0);
+  // SAFETY: sigemptyset and pthread_sigmask are async-signal-safe.
+  unsafe {
+    command.pre_exec(|| {
+      let mut set: libc::sigset_t = std::mem::zeroed();
+      libc::sigemptyset(&mut set);
+      libc::pthread_sigmask(libc::SIG_SETMASK, &set, std::ptr::null_mut());
+      Ok(())
+    })
+  }
+}
+
+/// Kills a timed out command, along with every process it spawned.
+fn kill_process_group(pid: u32) {
+  // SAFETY: the group was created for the child by prepare_command.
+  unsafe {
+    libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+  }
+}
+
+/// A command started by run_command_timeout that hasn't completed yet.
+#[derive(Debug, Clone)]
+pub struct RunningCommand {
+  pub pid: u32,
+  pub program: String,
+  /// Kernel id of the thread waiting for the command.
+  pub thread_id: i64,
+  pub started_at: Instant,
+}
+
+/// Commands currently running, for the watchdog to inspect and kill.
+static RUNNING_COMMANDS: Mutex<Vec<RunningCommand>> = Mutex::new(Vec::new());
+
+/// Kernel id of the calling thread, as listed in /proc/self/task.
+pub fn current_thread_id() -> i64 {
+  // SAFETY: gettid has no arguments and can't fail.
+  unsafe { libc::syscall(libc::SYS_gettid) }
+}
+
+/// Every command started by run_command_timeout still running.
+pub fn running_commands() -> Vec<RunningCommand> {
+  RUNNING_COMMANDS.lock().unwrap().clone()
+}
+
+/// Kills a running command along with every process it spawned.
+pub fn kill_running_command(pid: u32) {
+  let mut running = RUNNING_COMMANDS.lock().unwrap();
+  if let Some(index) = running.iter().position(|v| v.pid == pid) {
+    running.remove(index);
+    kill_process_group(pid);
+  }
+}
+
+/// Kills a command's process group when dropped, unless it completed.
+#[cfg(feature = "daemon")]
+struct ProcessGroupGuard(Option<u32>);
+
+#[cfg(feature = "daemon")]
+impl Drop for ProcessGroupGuard {
+  fn drop(&mut self) {
+    if let Some(pid) = self.0 {
+      kill_process_group(pid);
+    }
+  }
+}
+
+/// Checks a command's exit status, returning its stdout.
+fn command_stdout(output: Output) -> Result<String> {
+  if !output.status.success() {
+    return Err(Error::msg(format!(
+      "Command failed {:?}",
+      String::from_utf8_lossy(&output.stderr).trim()
+    )));
+  }
+
+  String::from_utf8(output.stdout)
+    .map_err(|e| Error::msg(format!("Failed to convert output to string: {}", e)))
+}
+
+///
+/// Runs a command with the given arguments, killing it after
+/// DEFAULT_COMMAND_TIMEOUT.
+///
+/// Args:
+///  - program: Command to run, looked up in PATH.
+///  - args: Arguments passed to the command.
+///
+/// Returns:
+///  Result of the command's stdout.
+///
+pub(crate) fn run_command<S: AsRef<std::ffi::OsStr>>(program: &str, args: &[S]) -> Result<String> {
+  run_command_timeout(program, args, DEFAULT_COMMAND_TIMEOUT)
+}
+
+///
+/// Runs a command with the given arguments, killing it once it runs for too
+/// long.
+///
+/// Args:
+///  - program: Command to run, looked up in PATH.
+///  - args: Arguments passed to the command.
+///  - timeout: Longest time the command may run for.
+///
+/// Returns:
+///  Result of the command's stdout.
+///
+pub(crate) fn run_command_timeout<S: AsRef<std::ffi::OsStr>>(
+  program: &str,
+  args: &[S],
+  timeout: Duration,
+) -> Result<String> {
+  run_command_with(program, args, &[], None, timeout)
+}
+
+///
+/// Runs a command with the given arguments and input, killing it once it
+/// runs for too long. The input keeps secrets, such as tokens, out of the
+/// process list.
+///
+/// Args:
+///  - program: Command to run, looked up in PATH.
+///  - args: Arguments passed to the command.
+///  - input: Written to the command's stdin, which is then closed.
+///  - timeout: Longest time the command may run for.
+///
+/// Returns:
+///  Result of the command's stdout.
+///
+#[cfg(feature = "daemon")]
+pub(crate) fn run_command_input<S: AsRef<std::ffi::OsStr>>(
+  program: &str,
+  args: &[S],
+  input: &[u8],
+  timeout: Duration,
+) -> Result<String> {
+  run_command_with(program, args, &[], Some(input.to_vec()), timeout)
+}
+
+///
+/// Runs a command with the given arguments, environment, and input, killing
+/// it once it runs for too long.
+///
+/// Args:
+///  - program: Command to run, looked up in PATH.
+///  - args: Arguments passed to the command.
+///  - env: Variables added to the command's environment.
+///  - input: Written to the command's stdin, which is then closed.
+///  - timeout: Longest time the command may run for.
+///
+/// Returns:
+///  Result of the command's stdout.
+///
+#[cfg(feature = "daemon")]
+pub(crate) fn run_command_env<S: AsRef<std::ffi::OsStr>>(
+  program: &str,
+  args: &[S],
+  env: &[(String, String)],
+  input: &[u8],
+  timeout: Duration,
+) -> Result<String> {
+  run_command_with(program, args, env, Some(input.to_vec()), timeout)
+}
+
+fn run_command_with<S: AsRef<std::ffi::OsStr>>(
+  program: &str,
+  args: &[S],
+  env: &[(String, String)],
+  input: Option<Vec<u8>>,
+  timeout: Duration,
+) -> Result<String> {
+  let stdin = match input {
+    Some(_) => Stdio::piped(),
+    None => Stdio::null(),
+  };
+  let mut command = Command::new(program);
+  command.args(args).envs(env.iter().map(|(k, v)| (k, v)));
+  let mut child = prepare_command(&mut command)
+    .stdin(stdin)
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|err| Error::msg(format!("Failed to execute '{}' command: {}", program, err)))?;
+  // Written on its own thread, so a command not reading it can't block.
+  if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+    std::thread::spawn(move || {
+      let _ = std::io::Write::write_all(&mut stdin, &input);
+    });
+  }
+  let pid = child.id();
+  RUNNING_COMMANDS.lock().unwrap().push(RunningCommand {
+    pid,
+    program: program.to_string(),
+    thread_id: current_thread_id(),
+    started_at: Instant::now(),
+  });
+
+  // The output is collected on its own thread, so the wait can time out.
+  let (tx, rx) = std::sync::mpsc::channel();
+  std::thread::spawn(move || {
+    let _ = tx.send(child.wait_with_output());
+  });
+
+  let output = rx.recv_timeout(timeout);
+  RUNNING_COMMANDS.lock().unwrap().retain(|v| v.pid != pid);
+  match output {
+    Ok(output) => command_stdout(
+      output
+        .map_err(|err| Error::msg(format!("Failed to execute '{}' command: {}", program, err)))?,
+    ),
+    Err(_) => {
+      kill_process_group(pid);
+      Err(Error::msg(format!(
+        "Command '{}' timed out after {:?}",
+        program, timeout
+      )))
+    }
+  }
+}
+
+///
+/// Runs a command with the given arguments without blocking the runtime,
+/// killing it once it runs for too long.
+///
+/// Args:
+///  - program: Command to run, looked up in PATH.
+///  - args: Arguments passed to the command.
+///  - timeout: Longest time the command may run for.
+///
+/// Returns:
+///  Result of the command's stdout.
+///
+#[cfg(feature = "daemon")]
+pub(crate) async fn run_command_async<S: AsRef<std::ffi::OsStr>>(
+  program: &str,
+  args: &[S],
+  timeout: Duration,
+) -> Result<String> {
+  let mut command = Command::new(program);
+  prepare_command(command.args(args))
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+  let child = tokio::process::Command::from(command)
+    .kill_on_drop(true)
+    .spawn()
+    .map_err(|err| Error::msg(format!("Failed to execute '{}' command: {}", program, err)))?;
+  // Also kills the command when the poll is dropped, e.g. on shutdown.
+  let mut guard = ProcessGroupGuard(child.id());
+
+  match tokio::time::timeout(timeout, child.wait_with_output()).await {
+    Ok(output) => {
+      guard.0 = None;
+      command_stdout(
+        output
+          .map_err(|err| Error::msg(format!("Failed to execute '{}' command: {}", program, err)))?,
+      )
+    }
+    Err(_) => {
+      drop(guard);
+      Err(Error::msg(format!(
+        "Command '{}' timed out after {:?}",
+        program, timeout
+      )))
+    }
+  }
+}
@@ -0,0 +1,756 @@
+use super::{
+  AddressFamily, ArpTable, MacAddr, NeighborFilter, NeighborFlags, NeighborStats, NudState,
+};
+use crate::counters;
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::collections::{HashSet, VecDeque};
+use std::ffi::{CStr, CString};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+/*
+https://man7.org/linux/man-pages/man7/rtnetlink.7.html
+
+  Every netlink message starts with a 16 byte header, followed by the
+  family specific payload, padded to a 4 byte boundary:
+
+    struct nlmsghdr { u32 len; u16 type; u16 flags; u32 seq; u32 pid; }
+
+  For neighbor messages (RTM_NEWNEIGH/RTM_DELNEIGH), the payload is an
+  ndmsg followed by a list of route attributes:
+
+    struct ndmsg  { u8 family; u8 pad1; u16 pad2; i32 ifindex; u16 state; u8 flags; u8 type; }
+    struct rtattr { u16 len; u16 type; <data> }
+
+  Cache counters come in as NDA_CACHEINFO, in clock ticks (USER_HZ):
+
+    struct nda_cacheinfo { u32 confirmed; u32 used; u32 updated; u32 refcnt; }
+*/
+const NLMSG_HDR_LEN: usize = 16;
+pub(crate) const NDMSG_LEN: usize = 12;
+const RTATTR_HDR_LEN: usize = 4;
+const RECV_BUFFER_LEN: usize = 32 * 1024;
+
+// Not exported by libc, see include/uapi/linux/neighbour.h.
+const NDA_MASTER: u16 = 9;
+
+/// Rounds a length up to the 4 byte alignment used by netlink.
+fn nl_align(len: usize) -> usize {
+  (len + 3) & !3
+}
+
+/// Thin wrapper around a NETLINK_ROUTE socket.
+pub(crate) struct NetlinkSocket {
+  fd: OwnedFd,
+  seq: u32,
+}
+
+impl NetlinkSocket {
+  ///
+  /// Opens and binds a NETLINK_ROUTE socket.
+  ///
+  /// Args:
+  ///  - groups: Multicast group mask to subscribe to (0 for none).
+  ///
+  /// Returns:
+  ///  Result of the bound socket.
+  ///
+  pub(crate) fn open(groups: u32) -> Result<Self> {
+    let raw_fd = unsafe {
+      libc::socket(
+        libc::AF_NETLINK,
+        libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+        libc::NETLINK_ROUTE,
+      )
+    };
+    if raw_fd < 0 {
+      return Err(Error::msg(format!(
+        "Failed to open netlink socket: {}",
+        std::io::Error::last_os_error()
+      )));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    addr.nl_groups = groups;
+    let ret = unsafe {
+      libc::bind(
+        fd.as_raw_fd(),
+        &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+        std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+      )
+    };
+    if ret < 0 {
+      return Err(Error::msg(format!(
+        "Failed to bind netlink socket: {}",
+        std::io::Error::last_os_error()
+      )));
+    }
+
+    Ok(NetlinkSocket { fd, seq: 0 })
+  }
+
+  ///
+  /// Sends a single netlink request to the kernel.
+  ///
+  /// Args:
+  ///  - msg_type: Netlink message type (e.g. RTM_GETNEIGH).
+  ///  - flags: Netlink header flags.
+  ///  - payload: Family specific payload following the header.
+  ///
+  /// Returns:
+  ///  Result of the sequence number used for the request.
+  ///
+  pub(crate) fn send(&mut self, msg_type: u16, flags: u16, payload: &[u8]) -> Result<u32> {
+    self.seq = self.seq.wrapping_add(1);
+    let len = NLMSG_HDR_LEN + payload.len();
+
+    let mut msg = Vec::with_capacity(nl_align(len));
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&msg_type.to_ne_bytes());
+    msg.extend_from_slice(&flags.to_ne_bytes());
+    msg.extend_from_slice(&self.seq.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(payload);
+    msg.resize(nl_align(len), 0);
+
+    let ret = unsafe {
+      libc::send(
+        self.fd.as_raw_fd(),
+        msg.as_ptr() as *const libc::c_void,
+        msg.len(),
+        0,
+      )
+    };
+    if ret < 0 {
+      return Err(Error::msg(format!(
+        "Failed to send netlink request: {}",
+        std::io::Error::last_os_error()
+      )));
+    }
+
+    Ok(self.seq)
+  }
+
+  /// Blocks until a datagram is received, returning the number of bytes read.
+  pub(crate) fn recv(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+      let ret = unsafe {
+        libc::recv(
+          self.fd.as_raw_fd(),
+          buf.as_mut_ptr() as *mut libc::c_void,
+          buf.len(),
+          0,
+        )
+      };
+      if ret >= 0 {
+        return Ok(ret as usize);
+      }
+
+      let err = std::io::Error::last_os_error();
+      if err.kind() != std::io::ErrorKind::Interrupted {
+        return Err(err);
+      }
+    }
+  }
+
+  ///
+  /// Sends a request asking the kernel for an acknowledgement, and waits for it.
+  ///
+  /// Args:
+  ///  - msg_type: Netlink message type (e.g. RTM_NEWNEIGH).
+  ///  - flags: Extra netlink header flags, on top of NLM_F_REQUEST | NLM_F_ACK.
+  ///  - payload: Family specific payload following the header.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the kernel accepted the request.
+  ///
+  pub(crate) fn request_ack(&mut self, msg_type: u16, flags: u16, payload: &[u8]) -> Result<()> {
+    let flags = flags | (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16;
+    let seq = self.send(msg_type, flags, payload)?;
+
+    let mut buf = vec![0u8; RECV_BUFFER_LEN];
+    loop {
+      let len = self
+        .recv(&mut buf)
+        .map_err(|e| Error::msg(format!("Failed to receive netlink message: {}", e)))?;
+
+      for msg in parse_messages(&buf[..len]) {
+        if msg.seq != seq || msg.msg_type as libc::c_int != libc::NLMSG_ERROR {
+          continue;
+        }
+        return match parse_nlmsg_errno(msg.payload) {
+          Some(0) => Ok(()),
+          Some(errno) => Err(Error::from(std::io::Error::from_raw_os_error(-errno))),
+          None => Err(Error::msg("Truncated netlink acknowledgement")),
+        };
+      }
+    }
+  }
+}
+
+/// Appends a route attribute to a netlink payload.
+fn push_rtattr(payload: &mut Vec<u8>, attr_type: u16, data: &[u8]) {
+  let len = RTATTR_HDR_LEN + data.len();
+  payload.extend_from_slice(&(len as u16).to_ne_bytes());
+  payload.extend_from_slice(&attr_type.to_ne_bytes());
+  payload.extend_from_slice(data);
+  payload.resize(nl_align(payload.len()), 0);
+}
+
+///
+/// Builds an ndmsg payload addressing a single neighbor entry.
+///
+/// Args:
+///  - ip: Neighbor address.
+///  - iface: Device the entry belongs to.
+///  - nud_state: State of the entry.
+///  - mac_addr: Link layer address of the entry, if any.
+///
+/// Returns:
+///  Result of the payload.
+///
+fn neighbor_payload(
+  ip: &IpAddr,
+  iface: &str,
+  nud_state: NudState,
+  mac_addr: Option<&MacAddr>,
+) -> Result<Vec<u8>> {
+  let (family, dst) = match ip {
+    IpAddr::V4(v4) => (libc::AF_INET as u8, v4.octets().to_vec()),
+    IpAddr::V6(v6) => (libc::AF_INET6 as u8, v6.octets().to_vec()),
+  };
+
+  let mut payload = vec![0u8; NDMSG_LEN];
+  payload[0] = family;
+  payload[4..8].copy_from_slice(&iface_index_from_name(iface)?.to_ne_bytes());
+  payload[8..10].copy_from_slice(&nud_state.bits().to_ne_bytes());
+
+  push_rtattr(&mut payload, libc::NDA_DST, &dst);
+  if let Some(mac_addr) = mac_addr {
+    push_rtattr(&mut payload, libc::NDA_LLADDR, &mac_addr.octets());
+  }
+
+  Ok(payload)
+}
+
+///
+/// Creates or replaces a neighbor entry, like `ip neigh replace`.
+///
+/// Args:
+///  - ip: Neighbor address.
+///  - mac_addr: Link layer address the address should resolve to.
+///  - iface: Device the entry belongs to.
+///  - nud_state: State of the entry (e.g. PERMANENT for a static entry).
+///
+/// Returns:
+///  Result reflecting whether the kernel accepted the entry.
+///
+pub fn replace_neighbor(
+  ip: &IpAddr,
+  mac_addr: &MacAddr,
+  iface: &str,
+  nud_state: NudState,
+) -> Result<()> {
+  let payload = neighbor_payload(ip, iface, nud_state, Some(mac_addr))?;
+  let flags = (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16;
+
+  NetlinkSocket::open(0)?
+    .request_ack(libc::RTM_NEWNEIGH, flags, &payload)
+    .map_err(|e| {
+      Error::msg(format!(
+        "Failed to replace neighbor {} dev {}: {}",
+        ip, iface, e
+      ))
+    })
+}
+
+///
+/// Deletes a neighbor entry, like `ip neigh del`.
+///
+/// Args:
+///  - ip: Neighbor address.
+///  - iface: Device the entry belongs to.
+///
+/// Returns:
+///  Result reflecting whether the kernel removed the entry.
+///
+pub fn delete_neighbor(ip: &IpAddr, iface: &str) -> Result<()> {
+  let payload = neighbor_payload(ip, iface, NudState::NONE, None)?;
+
+  NetlinkSocket::open(0)?
+    .request_ack(libc::RTM_DELNEIGH, 0, &payload)
+    .map_err(|e| {
+      Error::msg(format!(
+        "Failed to delete neighbor {} dev {}: {}",
+        ip, iface, e
+      ))
+    })
+}
+
+/// A single message sliced out of a netlink datagram.
+#[derive(Debug)]
+pub(crate) struct NetlinkMessage<'a> {
+  pub msg_type: u16,
+  pub seq: u32,
+  pub payload: &'a [u8],
+}
+
+/// Splits a received datagram into its netlink messages.
+pub(crate) fn parse_messages(buf: &[u8]) -> Vec<NetlinkMessage<'_>> {
+  let mut messages = Vec::new();
+  let mut offset = 0;
+
+  while offset + NLMSG_HDR_LEN <= buf.len() {
+    let len = read_u32(buf, offset) as usize;
+    if len < NLMSG_HDR_LEN || offset + len > buf.len() {
+      warn!(
+        "Truncated netlink message of length {} at offset {}",
+        len, offset
+      );
+      break;
+    }
+
+    messages.push(NetlinkMessage {
+      msg_type: read_u16(buf, offset + 4),
+      seq: read_u32(buf, offset + 8),
+      payload: &buf[offset + NLMSG_HDR_LEN..offset + len],
+    });
+    offset += nl_align(len);
+  }
+
+  messages
+}
+
+/// Extracts the (negated) errno from an NLMSG_ERROR payload, 0 being an ACK.
+pub(crate) fn parse_nlmsg_errno(payload: &[u8]) -> Option<i32> {
+  // struct nlmsgerr { i32 error; struct nlmsghdr msg; }
+  payload.get(0..4).map(|b| read_u32(b, 0) as i32)
+}
+
+/// Decoded contents of an RTM_NEWNEIGH/RTM_DELNEIGH payload.
+#[derive(Debug)]
+pub(crate) struct RawNeighbor {
+  pub family: u8,
+  pub ifindex: u32,
+  pub state: u16,
+  pub flags: u8,
+  pub ip: Option<IpAddr>,
+  pub lladdr: Option<Vec<u8>>,
+  pub cacheinfo: Option<[u32; 4]>,
+  pub probes: Option<u32>,
+  pub master: Option<u32>,
+  pub vlan: Option<u16>,
+}
+
+impl RawNeighbor {
+  ///
+  /// Parses an ndmsg payload and its trailing route attributes.
+  ///
+  /// Args:
+  ///  - payload: Message payload following the netlink header.
+  ///
+  /// Returns:
+  ///  The parsed neighbor, or None if the payload is too short.
+  ///
+  pub(crate) fn parse(payload: &[u8]) -> Option<Self> {
+    if payload.len() < NDMSG_LEN {
+      return None;
+    }
+
+    let family = payload[0];
+    let ifindex = read_u32(payload, 4);
+    let state = read_u16(payload, 8);
+    let flags = payload[10];
+    let mut ip = None;
+    let mut lladdr = None;
+    let mut cacheinfo = None;
+    let mut probes = None;
+    let mut master = None;
+    let mut vlan = None;
+
+    let mut offset = NDMSG_LEN;
+    while offset + RTATTR_HDR_LEN <= payload.len() {
+      let attr_len = read_u16(payload, offset) as usize;
+      let attr_type = read_u16(payload, offset + 2);
+      if attr_len < RTATTR_HDR_LEN || offset + attr_len > payload.len() {
+        break;
+      }
+      let data = &payload[offset + RTATTR_HDR_LEN..offset + attr_len];
+
+      match attr_type {
+        libc::NDA_DST => {
+          ip = match data.len() {
+            4 => Some(IpAddr::V4(Ipv4Addr::new(
+              data[0], data[1], data[2], data[3],
+            ))),
+            16 => {
+              let mut octets = [0u8; 16];
+              octets.copy_from_slice(data);
+              Some(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => None,
+          }
+        }
+        libc::NDA_LLADDR => lladdr = Some(data.to_vec()),
+        libc::NDA_CACHEINFO if data.len() >= 16 => {
+          cacheinfo = Some([
+            read_u32(data, 0),
+            read_u32(data, 4),
+            read_u32(data, 8),
+            read_u32(data, 12),
+          ])
+        }
+        libc::NDA_PROBES if data.len() >= 4 => probes = Some(read_u32(data, 0)),
+        libc::NDA_VLAN if data.len() >= 2 => vlan = Some(read_u16(data, 0)),
+        NDA_MASTER if data.len() >= 4 => master = Some(read_u32(data, 0)),
+        _ => {}
+      }
+      offset += nl_align(attr_len);
+    }
+
+    Some(RawNeighbor {
+      family,
+      ifindex,
+      state,
+      flags,
+      ip,
+      lladdr,
+      cacheinfo,
+      probes,
+      master,
+      vlan,
+    })
+  }
+
+  /// Whether the entry belongs to the IPv4 (ARP) or IPv6 (NDP) tables.
+  pub(crate) fn is_inet(&self) -> bool {
+    self.family == libc::AF_INET as u8 || self.family == libc::AF_INET6 as u8
+  }
+
+  /// Whether `ip neigh` would list the entry, which skips NOARP and NONE entries.
+  pub(crate) fn is_visible(&self) -> bool {
+    self.is_inet() && (self.state & !libc::NUD_NOARP) != 0
+  }
+
+  /// Converts the kernel's cache info into counters, mirroring `ip -s neigh`.
+  fn stats(&self) -> Option<NeighborStats> {
+    if self.cacheinfo.is_none() && self.probes.is_none() {
+      return None;
+    }
+
+    let hz = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+      hz if hz > 0 => hz as u64,
+      _ => 100,
+    };
+    let ticks = |v: u32| Duration::from_secs(v as u64 / hz);
+
+    Some(NeighborStats {
+      refcnt: self.cacheinfo.map(|v| v[3]),
+      used: self.cacheinfo.map(|v| ticks(v[1])),
+      confirmed: self.cacheinfo.map(|v| ticks(v[0])),
+      updated: self.cacheinfo.map(|v| ticks(v[2])),
+      probes: self.probes,
+    })
+  }
+
+  /// Converts the raw kernel entry into an ArpTable, resolving the interface name.
+  pub(crate) fn into_arp_table(self) -> Option<ArpTable> {
+    let stats = self.stats();
+    Some(ArpTable {
+      ip: self.ip?,
+      iface: iface_name_from_index(self.ifindex),
+      mac_addr: self.lladdr.and_then(|v| MacAddr::from_bytes(&v)),
+      flags: NeighborFlags::from_bits_truncate(self.flags),
+      nud_state: parse_nud_from_kernel(self.state),
+      stats,
+      vrf: None,
+    })
+  }
+}
+
+/// Maps a kernel NUD_* state value onto NudState, dropping any unknown bits.
+pub fn parse_nud_from_kernel(state: u16) -> NudState {
+  NudState::from_bits_truncate(state)
+}
+
+/// Resolves an interface index into its name, falling back to "if<index>".
+pub(crate) fn iface_name_from_index(ifindex: u32) -> String {
+  let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+  let ret = unsafe { libc::if_indextoname(ifindex, buf.as_mut_ptr()) };
+  if ret.is_null() {
+    warn!("Failed to resolve interface index {}", ifindex);
+    return format!("if{}", ifindex);
+  }
+
+  unsafe { CStr::from_ptr(buf.as_ptr()) }
+    .to_string_lossy()
+    .into_owned()
+}
+
+/// Resolves an interface name into its index.
+pub(crate) fn iface_index_from_name(iface: &str) -> Result<u32> {
+  let name = CString::new(iface)
+    .map_err(|e| Error::msg(format!("Invalid interface name '{}': {}", iface, e)))?;
+  match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+    0 => Err(Error::msg(format!(
+      "Unknown interface '{}': {}",
+      iface,
+      std::io::Error::last_os_error()
+    ))),
+    ifindex => Ok(ifindex),
+  }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+  u16::from_ne_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+  u32::from_ne_bytes([
+    buf[offset],
+    buf[offset + 1],
+    buf[offset + 2],
+    buf[offset + 3],
+  ])
+}
+
+///
+/// Generates a parsed array of ArpTable results by dumping the kernel's
+/// neighbor tables over rtnetlink, without spawning any subprocess.
+///
+/// Like `ip neigh`, NOARP and NONE entries (e.g. multicast addresses) are
+/// omitted.
+///
+/// Returns:
+///  Result of the collected neighbors.
+///
+pub fn get_ip_neighbors() -> Result<Vec<ArpTable>> {
+  get_ip_neighbors_filtered(&NeighborFilter::new())
+}
+
+/// Dumps the neighbors of a single device over rtnetlink.
+pub fn get_ip_neighbors_for_iface(iface: &str) -> Result<Vec<ArpTable>> {
+  get_ip_neighbors_filtered(&NeighborFilter::new().iface(iface))
+}
+
+///
+/// Dumps the kernel's neighbor tables over rtnetlink, asking the kernel to
+/// only return the filter's family and device. NUD states are matched
+/// locally, since the kernel doesn't filter on them.
+///
+/// Args:
+///  - filter: Interface, address family, and NUD state restrictions.
+///
+/// Returns:
+///  Result of the matching neighbors.
+///
+pub fn get_ip_neighbors_filtered(filter: &NeighborFilter) -> Result<Vec<ArpTable>> {
+  // Request a dump of the filtered family's (or every family's) neighbor table.
+  let mut request = vec![0u8; NDMSG_LEN];
+  request[0] = match filter.family {
+    Some(AddressFamily::Inet) => libc::AF_INET as u8,
+    Some(AddressFamily::Inet6) => libc::AF_INET6 as u8,
+    None => libc::AF_UNSPEC as u8,
+  };
+  if let Some(iface) = &filter.iface {
+    let ifindex = iface_index_from_name(iface)?;
+    push_rtattr(&mut request, libc::NDA_IFINDEX, &ifindex.to_ne_bytes());
+  }
+  if let Some(vrf) = &filter.vrf {
+    let ifindex = iface_index_from_name(vrf)?;
+    push_rtattr(&mut request, NDA_MASTER, &ifindex.to_ne_bytes());
+  }
+
+  let mut neighbors = Vec::new();
+  for raw in dump_raw_neighbors(&request)? {
+    // Explicitly requested states override the default visibility rules.
+    if !raw.is_inet() || (filter.nud_states.is_empty() && !raw.is_visible()) {
+      continue;
+    }
+
+    if let Some(entry) = raw.into_arp_table().filter(|v| filter.matches(v)) {
+      debug!("Parsed netlink neighbor -> {:?}", entry);
+      neighbors.push(entry);
+    }
+  }
+
+  Ok(neighbors)
+}
+
+///
+/// Sends an RTM_GETNEIGH dump request and collects every returned entry.
+///
+/// Args:
+///  - request: ndmsg payload (and attributes) selecting what to dump.
+///
+/// Returns:
+///  Result of the raw entries.
+///
+pub(crate) fn dump_raw_neighbors(request: &[u8]) -> Result<Vec<RawNeighbor>> {
+  let mut socket = NetlinkSocket::open(0)?;
+  let flags = (libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16;
+  let seq = socket.send(libc::RTM_GETNEIGH, flags, request)?;
+
+  let mut neighbors = Vec::new();
+  let mut buf = vec![0u8; RECV_BUFFER_LEN];
+  loop {
+    let len = socket
+      .recv(&mut buf)
+      .map_err(|e| Error::msg(format!("Failed to receive netlink message: {}", e)))?;
+
+    for msg in parse_messages(&buf[..len]) {
+      if msg.seq != seq {
+        continue;
+      }
+
+      match msg.msg_type as libc::c_int {
+        libc::NLMSG_DONE => return Ok(neighbors),
+        libc::NLMSG_ERROR => {
+          if let Some(errno) = parse_nlmsg_errno(msg.payload).filter(|e| *e != 0) {
+            return Err(Error::msg(format!(
+              "Netlink neighbor dump failed: {}",
+              std::io::Error::from_raw_os_error(-errno)
+            )));
+          }
+        }
+        _ if msg.msg_type == libc::RTM_NEWNEIGH => match RawNeighbor::parse(msg.payload) {
+          Some(raw) => neighbors.push(raw),
+          None => {
+            warn!("Skipping malformed neighbor message");
+            counters::record_parse_error();
+          }
+        },
+        _ => {}
+      }
+    }
+  }
+}
+
+/// A change to the kernel's neighbor tables.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NeighborEvent {
+  Added(ArpTable),
+  Updated(ArpTable),
+  Removed(ArpTable),
+}
+
+///
+/// Live subscription to the RTNLGRP_NEIGH multicast group, yielding
+/// neighbor changes as the kernel reports them.
+///
+/// The current tables are dumped when subscribing so that the first
+/// notification for an already known neighbor is reported as an update
+/// rather than an addition.
+///
+pub struct NeighborSubscription {
+  socket: NetlinkSocket,
+  buf: Vec<u8>,
+  known: HashSet<(String, IpAddr)>,
+  pending: VecDeque<NeighborEvent>,
+}
+
+impl NeighborSubscription {
+  /// Subscribes to neighbor notifications and seeds the set of known neighbors.
+  pub fn new() -> Result<Self> {
+    // Subscribe before dumping so that no change can slip in between.
+    let socket = NetlinkSocket::open(libc::RTMGRP_NEIGH as u32)?;
+    let mut subscription = NeighborSubscription {
+      socket,
+      buf: vec![0u8; RECV_BUFFER_LEN],
+      known: HashSet::new(),
+      pending: VecDeque::new(),
+    };
+    subscription.resync()?;
+
+    Ok(subscription)
+  }
+
+  /// Rebuilds the set of known neighbors from a fresh table dump.
+  fn resync(&mut self) -> Result<()> {
+    self.known = get_ip_neighbors()?
+      .into_iter()
+      .map(|v| (v.iface, v.ip))
+      .collect();
+    debug!("Seeded {} known neighbors", self.known.len());
+
+    Ok(())
+  }
+
+  ///
+  /// Blocks until the next neighbor event is received.
+  ///
+  /// Returns:
+  ///  Result of the next event.
+  ///
+  pub fn next_event(&mut self) -> Result<NeighborEvent> {
+    loop {
+      if let Some(event) = self.pending.pop_front() {
+        return Ok(event);
+      }
+
+      let len = match self.socket.recv(&mut self.buf) {
+        Ok(len) => len,
+        Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+          // The kernel dropped notifications, so our view may be out of date.
+          warn!("Netlink receive buffer overran, resyncing known neighbors");
+          self.resync()?;
+          continue;
+        }
+        Err(e) => {
+          return Err(Error::msg(format!(
+            "Failed to receive netlink message: {}",
+            e
+          )))
+        }
+      };
+
+      let mut events = Vec::new();
+      for msg in parse_messages(&self.buf[..len]) {
+        if msg.msg_type != libc::RTM_NEWNEIGH && msg.msg_type != libc::RTM_DELNEIGH {
+          continue;
+        }
+        let Some(raw) = RawNeighbor::parse(msg.payload) else {
+          warn!("Skipping malformed neighbor notification");
+          counters::record_parse_error();
+          continue;
+        };
+        if !raw.is_inet() {
+          continue;
+        }
+
+        let visible = raw.is_visible() && msg.msg_type == libc::RTM_NEWNEIGH;
+        let Some(entry) = raw.into_arp_table() else {
+          continue;
+        };
+        let key = (entry.iface.clone(), entry.ip);
+
+        let event = if visible {
+          match self.known.insert(key) {
+            true => NeighborEvent::Added(entry),
+            false => NeighborEvent::Updated(entry),
+          }
+        } else if self.known.remove(&key) {
+          NeighborEvent::Removed(entry)
+        } else {
+          continue;
+        };
+        debug!("Neighbor event -> {:?}", event);
+        events.push(event);
+      }
+      self.pending.extend(events);
+    }
+  }
+}
+
+impl Iterator for NeighborSubscription {
+  type Item = Result<NeighborEvent>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    Some(self.next_event())
+  }
+}
@@ -0,0 +1,499 @@
+use super::route::local_addresses;
+use super::MacAddr;
+use anyhow::{Error, Result};
+use log::{debug, info, warn};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Advertisements kept between polls at most, the oldest being dropped for
+/// newer ones.
+pub const MAX_ADVERTS: usize = 100;
+
+/// Longest time a receive blocks for, between checks of whether the watch
+/// was stopped.
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// ICMPv6 type of router advertisements.
+const ND_ROUTER_ADVERT: u8 = 134;
+
+/// Socket option filtering the ICMPv6 types a raw socket receives.
+const ICMP6_FILTER: libc::c_int = 1;
+
+/// Fixed part of a router advertisement, up to its options.
+const ADVERT_LEN: usize = 16;
+
+const OPTION_SOURCE_LLADDR: u8 = 1;
+const OPTION_PREFIX_INFO: u8 = 3;
+const OPTION_RDNSS: u8 = 25;
+
+/*
+  https://datatracker.ietf.org/doc/html/rfc4861#section-4.2
+
+  Routers advertise themselves to ff02::1 from their link-local address,
+  along with the prefixes hosts configure addresses in, and since RFC 8106
+  their DNS servers:
+
+    u8 type; u8 code; u16 checksum; u8 hop_limit; u8 flags; u16 lifetime;
+    u32 reachable_time; u32 retrans_timer; { u8 type; u8 len; ... }
+
+  Options are len * 8 bytes long, type and len included:
+
+    1   source link-layer address   u8 lladdr[6]
+    3   prefix information          u8 prefix_len; u8 flags; u32 valid;
+                                    u32 preferred; u32 reserved; u8 prefix[16]
+    25  recursive DNS servers       u16 reserved; u32 lifetime; u8 dns[16][]
+
+  Hosts take any router's word for it, so a host advertising itself sends
+  their traffic its way. Advertisements of the router's own addresses, i.e.
+  of odhcpd, are dropped.
+*/
+
+///
+/// IPv6 prefix, such as "2001:db8::/64", the bits past its length cleared.
+///
+/// ```
+/// use openwrt_netmon::neighbors::ra::Ipv6Prefix;
+///
+/// let prefix: Ipv6Prefix = "2001:db8:0:1::5/48".parse()?;
+/// assert_eq!(prefix.to_string(), "2001:db8::/48");
+/// assert!(prefix.contains(&"2001:db8:0:1::/64".parse()?));
+/// assert!(!prefix.contains(&"2001:db9::/64".parse()?));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv6Prefix {
+  addr: Ipv6Addr,
+  len: u8,
+}
+
+impl Ipv6Prefix {
+  ///
+  /// Creates a prefix.
+  ///
+  /// Args:
+  ///  - addr: Address in the prefix.
+  ///  - len: Length of the prefix, at most 128.
+  ///
+  pub fn new(addr: Ipv6Addr, len: u8) -> Self {
+    let len = len.min(128);
+    let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+    Ipv6Prefix {
+      addr: Ipv6Addr::from(u128::from(addr) & mask),
+      len,
+    }
+  }
+
+  pub fn addr(&self) -> Ipv6Addr {
+    self.addr
+  }
+
+  pub fn prefix_len(&self) -> u8 {
+    self.len
+  }
+
+  /// Whether another prefix is within this one.
+  pub fn contains(&self, other: &Ipv6Prefix) -> bool {
+    other.len >= self.len && Ipv6Prefix::new(other.addr, self.len) == *self
+  }
+}
+
+impl fmt::Display for Ipv6Prefix {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.addr, self.len)
+  }
+}
+
+impl FromStr for Ipv6Prefix {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let invalid = || Error::msg(format!("Invalid IPv6 prefix '{}'", s));
+    let (addr, len) = s.split_once('/').ok_or_else(invalid)?;
+    let addr = addr.parse().map_err(|_| invalid())?;
+    let len = len.parse().ok().filter(|v| *v <= 128).ok_or_else(invalid)?;
+    Ok(Ipv6Prefix::new(addr, len))
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Ipv6Prefix {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> std::result::Result<Self, D::Error> {
+    let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+    Ipv6Prefix::from_str(&s).map_err(serde::de::Error::custom)
+  }
+}
+
+/// A router advertisement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterAdvert {
+  /// Link-local address the router advertised from.
+  pub source: Ipv6Addr,
+  /// MAC address the router gave, if any.
+  pub mac_addr: Option<MacAddr>,
+  /// Time the router is a default router for, none if it isn't one.
+  pub lifetime: Duration,
+  pub prefixes: Vec<Ipv6Prefix>,
+  pub dns: Vec<Ipv6Addr>,
+}
+
+///
+/// Parses a router advertisement, as a raw ICMPv6 socket receives it.
+///
+/// ```
+/// use openwrt_netmon::neighbors::ra::parse_router_advert;
+///
+/// let mut msg = vec![134, 0, 0, 0, 64, 0, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+/// msg.extend_from_slice(&[1, 1, 0x02, 0, 0, 0, 0, 0x0b]);
+/// msg.extend_from_slice(&[3, 4, 64, 0xc0, 0, 0, 0x1c, 0x20, 0, 0, 0x0e, 0x10, 0, 0, 0, 0]);
+/// msg.extend_from_slice(&"2001:db8:1::".parse::<std::net::Ipv6Addr>()?.octets());
+///
+/// let advert = parse_router_advert(&msg, "fe80::b".parse()?)?;
+/// assert_eq!(advert.mac_addr.unwrap().to_string(), "02:00:00:00:00:0b");
+/// assert_eq!(advert.lifetime.as_secs(), 1800);
+/// assert_eq!(advert.prefixes[0].to_string(), "2001:db8:1::/64");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Args:
+///  - buf: Received ICMPv6 message.
+///  - source: Address it came from.
+///
+/// Returns:
+///  Result of the advertisement, failing for other messages.
+///
+pub fn parse_router_advert(buf: &[u8], source: Ipv6Addr) -> Result<RouterAdvert> {
+  if buf.len() < ADVERT_LEN || buf[0] != ND_ROUTER_ADVERT || buf[1] != 0 {
+    return Err(Error::msg("Not a router advertisement"));
+  }
+  let ipv6 = |v: &[u8]| Ipv6Addr::from(<[u8; 16]>::try_from(v).unwrap_or_default());
+  let mut advert = RouterAdvert {
+    source,
+    mac_addr: None,
+    lifetime: Duration::from_secs(u16::from_be_bytes([buf[6], buf[7]]) as u64),
+    prefixes: Vec::new(),
+    dns: Vec::new(),
+  };
+  let mut offset = ADVERT_LEN;
+  while let Some(&[code, len]) = buf.get(offset..offset + 2) {
+    let Some(option) = buf
+      .get(offset..offset + len as usize * 8)
+      .filter(|_| len > 0)
+    else {
+      return Err(Error::msg("Truncated router advertisement option"));
+    };
+    match code {
+      OPTION_SOURCE_LLADDR => advert.mac_addr = MacAddr::from_bytes(&option[2..8]),
+      OPTION_PREFIX_INFO if option.len() == 32 => {
+        advert
+          .prefixes
+          .push(Ipv6Prefix::new(ipv6(&option[16..32]), option[2]));
+      }
+      OPTION_RDNSS => advert.dns.extend(option[8..].chunks_exact(16).map(ipv6)),
+      _ => {}
+    }
+    offset += option.len();
+  }
+  Ok(advert)
+}
+
+/// An advertisement of a rogue router, or of a router advertising what it
+/// shouldn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RogueAdvert {
+  /// Interface the advertisement was received on.
+  pub iface: String,
+  pub advert: RouterAdvert,
+  /// What's unexpected about it, e.g. "advertising 2001:db8::/64".
+  pub unexpected: Vec<String>,
+}
+
+///
+/// Listens for router advertisements other than the router's own, on a
+/// thread per interface, keeping those of unexpected routers, or
+/// advertising unexpected prefixes or DNS servers.
+///
+/// ```no_run
+/// use openwrt_netmon::neighbors::ra::RogueRaWatch;
+/// use std::time::Duration;
+///
+/// let mut watch = RogueRaWatch::new(vec!["br-lan".to_string()]);
+/// watch.start();
+/// std::thread::sleep(Duration::from_secs(5));
+/// for rogue in watch.take() {
+///   println!("{} on {}: {}", rogue.advert.source, rogue.iface, rogue.unexpected.join(", "));
+/// }
+/// ```
+///
+pub struct RogueRaWatch {
+  interfaces: Vec<String>,
+  expected: Arc<Expected>,
+  on_advert: Option<Arc<dyn Fn() + Send + Sync>>,
+  /// Advertisements received since the last take.
+  adverts: Arc<Mutex<Vec<RogueAdvert>>>,
+  stopped: Arc<AtomicBool>,
+  started: bool,
+}
+
+/// What routers may advertise, besides the router itself.
+#[derive(Debug, Clone, Default)]
+struct Expected {
+  routers: Vec<Ipv6Addr>,
+  prefixes: Vec<Ipv6Prefix>,
+  dns: Vec<Ipv6Addr>,
+}
+
+impl Expected {
+  /// What's unexpected about an advertisement, nothing if it's expected.
+  fn unexpected(&self, advert: &RouterAdvert) -> Vec<String> {
+    let known = self.routers.contains(&advert.source);
+    let mut unexpected = Vec::new();
+    if !known {
+      unexpected.push("from an unknown router".to_string());
+    }
+    let covered = |v: &Ipv6Prefix| self.prefixes.iter().any(|prefix| prefix.contains(v));
+    for prefix in &advert.prefixes {
+      if !known || !(self.prefixes.is_empty() || covered(prefix)) {
+        unexpected.push(format!("advertising {}", prefix));
+      }
+    }
+    for dns in &advert.dns {
+      if !known || !(self.dns.is_empty() || self.dns.contains(dns)) {
+        unexpected.push(format!("advertising the DNS server {}", dns));
+      }
+    }
+    unexpected
+  }
+}
+
+impl RogueRaWatch {
+  /// Creates a watch, listening on interfaces such as "br-lan" once
+  /// started.
+  pub fn new(interfaces: Vec<String>) -> Self {
+    RogueRaWatch {
+      interfaces,
+      expected: Arc::new(Expected::default()),
+      on_advert: None,
+      adverts: Arc::new(Mutex::new(Vec::new())),
+      stopped: Arc::new(AtomicBool::new(false)),
+      started: false,
+    }
+  }
+
+  /// Link-local addresses of the routers besides the router itself that may
+  /// advertise, e.g. of a second router on the LAN.
+  pub fn routers(mut self, routers: Vec<Ipv6Addr>) -> Self {
+    Arc::make_mut(&mut self.expected).routers = routers;
+    self
+  }
+
+  /// Prefixes those routers may advertise, any if empty.
+  pub fn prefixes(mut self, prefixes: Vec<Ipv6Prefix>) -> Self {
+    Arc::make_mut(&mut self.expected).prefixes = prefixes;
+    self
+  }
+
+  /// DNS servers those routers may advertise, any if empty.
+  pub fn dns(mut self, dns: Vec<Ipv6Addr>) -> Self {
+    Arc::make_mut(&mut self.expected).dns = dns;
+    self
+  }
+
+  /// Called from the watch's threads on every rogue advertisement, e.g. to
+  /// poll right away.
+  pub fn on_advert(mut self, on_advert: Box<dyn Fn() + Send + Sync>) -> Self {
+    self.on_advert = Some(Arc::from(on_advert));
+    self
+  }
+
+  /// Starts the threads listening on every interface, unless they were
+  /// already. Interfaces failing to be listened on are logged and skipped.
+  pub fn start(&mut self) {
+    if std::mem::replace(&mut self.started, true) {
+      return;
+    }
+    for iface in &self.interfaces {
+      let listener = Listener {
+        iface: iface.clone(),
+        expected: self.expected.clone(),
+        on_advert: self.on_advert.clone(),
+        adverts: self.adverts.clone(),
+        stopped: self.stopped.clone(),
+      };
+      let spawned = listener.open().and_then(|fd| {
+        std::thread::Builder::new()
+          .name(format!("ra-{}", iface))
+          .spawn(move || listener.run(fd))
+          .map_err(|e| Error::msg(format!("Failed to spawn the thread: {}", e)))
+      });
+      match spawned {
+        Ok(_) => info!("Watching for rogue router advertisements on {}", iface),
+        Err(err) => warn!(
+          "Not watching for rogue router advertisements on {}: {}",
+          iface, err
+        ),
+      }
+    }
+  }
+
+  /// Takes the rogue advertisements received since the last call, oldest
+  /// first.
+  pub fn take(&self) -> Vec<RogueAdvert> {
+    std::mem::take(&mut *self.adverts.lock().unwrap())
+  }
+}
+
+impl Drop for RogueRaWatch {
+  fn drop(&mut self) {
+    self.stopped.store(true, Ordering::Relaxed);
+  }
+}
+
+impl std::fmt::Debug for RogueRaWatch {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("RogueRaWatch")
+      .field("interfaces", &self.interfaces)
+      .field("expected", &self.expected)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Thread of a watch listening on an interface.
+struct Listener {
+  iface: String,
+  expected: Arc<Expected>,
+  on_advert: Option<Arc<dyn Fn() + Send + Sync>>,
+  adverts: Arc<Mutex<Vec<RogueAdvert>>>,
+  stopped: Arc<AtomicBool>,
+}
+
+impl Listener {
+  /// Opens a raw ICMPv6 socket on the interface, receiving router
+  /// advertisements only.
+  fn open(&self) -> Result<OwnedFd> {
+    let failed = |what: &str| {
+      Error::msg(format!(
+        "Failed to {}: {}",
+        what,
+        std::io::Error::last_os_error()
+      ))
+    };
+    let raw_fd = unsafe {
+      libc::socket(
+        libc::AF_INET6,
+        libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+        libc::IPPROTO_ICMPV6,
+      )
+    };
+    if raw_fd < 0 {
+      return Err(failed("open a raw ICMPv6 socket"));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+    let set = |level: libc::c_int, name: libc::c_int, value: &[u8]| unsafe {
+      libc::setsockopt(
+        fd.as_raw_fd(),
+        level,
+        name,
+        value.as_ptr() as *const libc::c_void,
+        value.len() as libc::socklen_t,
+      ) == 0
+    };
+    // Set bits block their type.
+    let mut filter = [u32::MAX; 8];
+    filter[ND_ROUTER_ADVERT as usize >> 5] &= !(1 << (ND_ROUTER_ADVERT & 31));
+    let filter: Vec<u8> = filter.iter().flat_map(|v| v.to_ne_bytes()).collect();
+    if !set(libc::IPPROTO_ICMPV6, ICMP6_FILTER, &filter) {
+      return Err(failed("filter the socket"));
+    }
+    if !set(
+      libc::SOL_SOCKET,
+      libc::SO_BINDTODEVICE,
+      self.iface.as_bytes(),
+    ) {
+      return Err(failed("bind to the interface"));
+    }
+    let timeout = libc::timeval {
+      tv_sec: RECV_TIMEOUT.as_secs() as libc::time_t,
+      tv_usec: 0,
+    };
+    let timeout = unsafe {
+      std::slice::from_raw_parts(
+        &timeout as *const libc::timeval as *const u8,
+        std::mem::size_of::<libc::timeval>(),
+      )
+    };
+    if !set(libc::SOL_SOCKET, libc::SO_RCVTIMEO, timeout) {
+      return Err(failed("configure the socket"));
+    }
+    Ok(fd)
+  }
+
+  /// Receives advertisements until the watch is stopped.
+  fn run(self, fd: OwnedFd) {
+    let mut buf = [0u8; 1500];
+    while !self.stopped.load(Ordering::Relaxed) {
+      let mut addr: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+      let mut addr_len = std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+      let ret = unsafe {
+        libc::recvfrom(
+          fd.as_raw_fd(),
+          buf.as_mut_ptr() as *mut libc::c_void,
+          buf.len(),
+          0,
+          &mut addr as *mut libc::sockaddr_in6 as *mut libc::sockaddr,
+          &mut addr_len,
+        )
+      };
+      if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if !matches!(
+          err.kind(),
+          std::io::ErrorKind::WouldBlock
+            | std::io::ErrorKind::TimedOut
+            | std::io::ErrorKind::Interrupted
+        ) {
+          warn!("Failed to receive on {}: {}", self.iface, err);
+          std::thread::sleep(RECV_TIMEOUT);
+        }
+        continue;
+      }
+      let source = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+      let advert = match parse_router_advert(&buf[..ret as usize], source) {
+        Ok(advert) => advert,
+        Err(e) => {
+          debug!("Skipping an ICMPv6 message from {}: {}", source, e);
+          continue;
+        }
+      };
+      if local_addresses().contains(&IpAddr::V6(source)) {
+        continue;
+      }
+      let unexpected = self.expected.unexpected(&advert);
+      if unexpected.is_empty() {
+        debug!("Router advertisement of {} on {}", source, self.iface);
+        continue;
+      }
+      {
+        let mut adverts = self.adverts.lock().unwrap();
+        if adverts.len() == MAX_ADVERTS {
+          adverts.remove(0);
+        }
+        adverts.push(RogueAdvert {
+          iface: self.iface.clone(),
+          advert,
+          unexpected,
+        });
+      }
+      if let Some(on_advert) = &self.on_advert {
+        on_advert();
+      }
+    }
+  }
+}
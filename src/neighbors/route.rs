@@ -0,0 +1,141 @@
+use super::ScopedIpAddr;
+use log::{debug, warn};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// IPv4 routes of the main table, as the kernel lists them.
+pub const PROC_ROUTE_PATH: &str = "/proc/net/route";
+
+/// IPv6 routes of the main table, as the kernel lists them.
+pub const PROC_IPV6_ROUTE_PATH: &str = "/proc/net/ipv6_route";
+
+/// Flag of the routes through a gateway (the kernel's RTF_GATEWAY).
+const RTF_GATEWAY: u32 = 0x2;
+
+/*
+  /proc/net/route prints a header, then a row per IPv4 route, its addresses
+  in hex, in host (little endian) byte order:
+
+    Iface  Destination  Gateway   Flags  RefCnt  Use  Metric  Mask      MTU ...
+    wan    00000000     0100A8C0  0003   0       0    0       00000000  0   ...
+
+  /proc/net/ipv6_route prints no header, and its addresses in network order:
+
+    00000000000000000000000000000000 00 00000000000000000000000000000000 00
+    fe800000000000000000000000000001 00000400 00000001 00000000 00000003 wan
+
+  which are the destination and its prefix length, the source and its prefix
+  length, the next hop, metric, reference count, use count, flags, and
+  interface. Default routes go to 0.0.0.0/0 and ::/0.
+*/
+
+///
+/// Parses the gateways of the default routes out of /proc/net/route.
+///
+/// ```
+/// use openwrt_netmon::neighbors::route::parse_proc_route;
+///
+/// let gateways = parse_proc_route(
+///   "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\tMTU\tWindow\tIRTT\n\
+///    wan\t00000000\t0100A8C0\t0003\t0\t0\t0\t00000000\t0\t0\t0\n\
+///    br-lan\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n",
+/// );
+/// assert_eq!(gateways.len(), 1);
+/// assert_eq!(gateways[0].to_string(), "192.168.0.1");
+/// ```
+///
+pub fn parse_proc_route(s: &str) -> Vec<ScopedIpAddr> {
+  s.lines()
+    .skip(1)
+    .filter_map(|line| {
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      let hex = |index: usize| u32::from_str_radix(fields.get(index)?, 16).ok();
+      let (iface, flags) = (fields.first()?, hex(3)?);
+      if hex(1)? != 0 || hex(7)? != 0 || flags & RTF_GATEWAY == 0 {
+        return None;
+      }
+      let gateway = Ipv4Addr::from(hex(2)?.to_le_bytes());
+      Some(ScopedIpAddr::new(IpAddr::V4(gateway), iface))
+    })
+    .collect()
+}
+
+///
+/// Parses the gateways of the default routes out of /proc/net/ipv6_route.
+///
+/// ```
+/// use openwrt_netmon::neighbors::route::parse_proc_ipv6_route;
+///
+/// let gateways = parse_proc_ipv6_route(
+///   "00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+///    fe800000000000000000000000000001 00000400 00000001 00000000 00000003 wan\n\
+///    fd000000000000000000000000000000 40 00000000000000000000000000000000 00 \
+///    00000000000000000000000000000000 00000100 00000001 00000000 00000001 br-lan\n",
+/// );
+/// assert_eq!(gateways.len(), 1);
+/// assert_eq!(gateways[0].to_string(), "fe80::1%wan");
+/// ```
+///
+pub fn parse_proc_ipv6_route(s: &str) -> Vec<ScopedIpAddr> {
+  s.lines()
+    .filter_map(|line| {
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      let (iface, flags) = (
+        fields.get(9)?,
+        u32::from_str_radix(fields.get(8)?, 16).ok()?,
+      );
+      let destination = u128::from_str_radix(fields.first()?, 16).ok()?;
+      let prefix_len = u8::from_str_radix(fields.get(1)?, 16).ok()?;
+      if destination != 0 || prefix_len != 0 || flags & RTF_GATEWAY == 0 {
+        return None;
+      }
+      let gateway = Ipv6Addr::from(u128::from_str_radix(fields.get(4)?, 16).ok()?);
+      Some(ScopedIpAddr::new(IpAddr::V6(gateway), iface))
+    })
+    .collect()
+}
+
+/// Gateways of the host's default routes, IPv4 then IPv6, none if the
+/// routes can't be read.
+pub fn default_gateways() -> Vec<ScopedIpAddr> {
+  let read = |path: &str| {
+    std::fs::read_to_string(path)
+      .map_err(|e| debug!("Failed to read {}: {}", path, e))
+      .unwrap_or_default()
+  };
+  let mut gateways = parse_proc_route(&read(PROC_ROUTE_PATH));
+  gateways.extend(parse_proc_ipv6_route(&read(PROC_IPV6_ROUTE_PATH)));
+  gateways
+}
+
+/// Addresses of the host's interfaces, IPv6 ones without their zone, none
+/// if they can't be listed.
+pub fn local_addresses() -> Vec<IpAddr> {
+  let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+  if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+    warn!(
+      "Failed to list the local addresses: {}",
+      std::io::Error::last_os_error()
+    );
+    return Vec::new();
+  }
+  let mut addresses = Vec::new();
+  let mut next = ifaddrs;
+  while let Some(ifaddr) = unsafe { next.as_ref() } {
+    let addr = ifaddr.ifa_addr;
+    match unsafe { addr.as_ref() }.map(|v| v.sa_family as libc::c_int) {
+      Some(libc::AF_INET) => {
+        let addr = unsafe { &*(addr as *const libc::sockaddr_in) };
+        let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+        addresses.push(IpAddr::V4(ip));
+      }
+      Some(libc::AF_INET6) => {
+        let addr = unsafe { &*(addr as *const libc::sockaddr_in6) };
+        addresses.push(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+      }
+      _ => {}
+    }
+    next = ifaddr.ifa_next;
+  }
+  unsafe { libc::freeifaddrs(ifaddrs) };
+  addresses
+}
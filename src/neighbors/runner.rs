@@ -0,0 +1,117 @@
+use super::{run_command_timeout, DEFAULT_COMMAND_TIMEOUT};
+use anyhow::{Error, Result};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::time::Duration;
+
+///
+/// Runs the external commands collectors parse the output of, such as `ip`,
+/// `bridge`, `iw` and `uci`. Substituting the runner lets collectors work
+/// off recorded fixtures, or another host, e.g. by prefixing every command
+/// with `ssh`, instead of the local processes.
+///
+pub trait CommandRunner: Send + Sync {
+  ///
+  /// Runs a command to completion.
+  ///
+  /// Args:
+  ///  - program: Command to run, e.g. "ip".
+  ///  - args: Arguments passed to the command.
+  ///
+  /// Returns:
+  ///  Result of the command's stdout, failing if it didn't exit successfully.
+  ///
+  fn run(&self, program: &str, args: &[&OsStr]) -> Result<String>;
+}
+
+/// Runs commands as local processes, killing those running for too long.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemRunner {
+  timeout: Duration,
+}
+
+impl Default for SystemRunner {
+  fn default() -> Self {
+    SystemRunner::new()
+  }
+}
+
+impl SystemRunner {
+  pub fn new() -> Self {
+    SystemRunner {
+      timeout: DEFAULT_COMMAND_TIMEOUT,
+    }
+  }
+
+  /// Longest time a command may run for before it's killed.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+}
+
+impl CommandRunner for SystemRunner {
+  fn run(&self, program: &str, args: &[&OsStr]) -> Result<String> {
+    run_command_timeout(program, args, self.timeout)
+  }
+}
+
+///
+/// Answers commands with recorded outputs, keyed by their command line, and
+/// fails any other command.
+///
+/// ```
+/// use openwrt_netmon::neighbors::{self, FixtureRunner};
+///
+/// let runner = FixtureRunner::new()
+///   .output("ip neigh show", "192.168.0.5 dev br-lan lladdr dc:a6:32:a3:48:b1 REACHABLE\n");
+///
+/// let entries = neighbors::get_ip_neighbors_with(&runner)?;
+/// assert_eq!(entries[0].iface, "br-lan");
+///
+/// assert!(neighbors::get_ip_neighbors_detailed_with(&runner, &Default::default()).is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct FixtureRunner {
+  outputs: HashMap<String, String>,
+}
+
+impl FixtureRunner {
+  pub fn new() -> Self {
+    FixtureRunner::default()
+  }
+
+  /// Records the stdout of a command line, e.g. "iw dev wlan0 station dump".
+  pub fn output(mut self, command_line: &str, stdout: &str) -> Self {
+    let command_line: Vec<&str> = command_line.split_whitespace().collect();
+    self
+      .outputs
+      .insert(command_line.join(" "), stdout.to_string());
+    self
+  }
+}
+
+impl CommandRunner for FixtureRunner {
+  fn run(&self, program: &str, args: &[&OsStr]) -> Result<String> {
+    let mut command_line = program.to_string();
+    for arg in args {
+      command_line.push(' ');
+      command_line.push_str(&arg.to_string_lossy());
+    }
+    self
+      .outputs
+      .get(&command_line)
+      .cloned()
+      .ok_or_else(|| Error::msg(format!("No recorded output for '{}'", command_line)))
+  }
+}
+
+impl dyn CommandRunner + '_ {
+  /// Runs a command, for arguments of any string type.
+  pub fn run_args<S: AsRef<OsStr>>(&self, program: &str, args: &[S]) -> Result<String> {
+    let args: Vec<&OsStr> = args.iter().map(|v| v.as_ref()).collect();
+    self.run(program, &args)
+  }
+}
@@ -0,0 +1,110 @@
+use super::netlink::iface_index_from_name;
+use anyhow::{Error, Result};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+
+/// Whether the address is an IPv6 link-local (fe80::/10) address.
+pub fn is_ipv6_link_local(ip: &IpAddr) -> bool {
+  match ip {
+    IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+    IpAddr::V4(_) => false,
+  }
+}
+
+///
+/// IP address along with its zone (scope id), as needed for IPv6 link-local
+/// addresses which are only unique per interface.
+///
+/// ```
+/// use openwrt_netmon::ScopedIpAddr;
+///
+/// let scoped: ScopedIpAddr = "fe80::1%br-lan".parse()?;
+/// assert_eq!(scoped.zone.as_deref(), Some("br-lan"));
+/// assert_eq!(scoped.to_string(), "fe80::1%br-lan");
+///
+/// // Globally scoped addresses never carry a zone.
+/// let global = ScopedIpAddr::new("192.168.0.5".parse()?, "br-lan");
+/// assert_eq!(global.to_string(), "192.168.0.5");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScopedIpAddr {
+  pub ip: IpAddr,
+  pub zone: Option<String>,
+}
+
+impl ScopedIpAddr {
+  ///
+  /// Scopes an address seen on the given interface.
+  ///
+  /// Args:
+  ///  - ip: Neighbor address.
+  ///  - iface: Interface the address was seen on, only kept for link-local addresses.
+  ///
+  /// Returns:
+  ///  The scoped address.
+  ///
+  pub fn new(ip: IpAddr, iface: &str) -> Self {
+    let zone = is_ipv6_link_local(&ip).then(|| iface.to_string());
+    ScopedIpAddr { ip, zone }
+  }
+
+  /// Resolves the zone into the numeric scope id used by sockets.
+  pub fn scope_id(&self) -> Result<u32> {
+    match &self.zone {
+      Some(zone) => iface_index_from_name(zone),
+      None => Ok(0),
+    }
+  }
+
+  /// Builds a socket address for the given port, carrying the scope id for link-local addresses.
+  pub fn to_socket_addr(&self, port: u16) -> Result<SocketAddr> {
+    match self.ip {
+      IpAddr::V6(v6) => Ok(SocketAddr::V6(SocketAddrV6::new(
+        v6,
+        port,
+        0,
+        self.scope_id()?,
+      ))),
+      IpAddr::V4(_) => Ok(SocketAddr::new(self.ip, port)),
+    }
+  }
+}
+
+impl From<IpAddr> for ScopedIpAddr {
+  fn from(ip: IpAddr) -> Self {
+    ScopedIpAddr { ip, zone: None }
+  }
+}
+
+impl FromStr for ScopedIpAddr {
+  type Err = Error;
+
+  /// Parses "<ip>[%<zone>]", such as "fe80::1%br-lan".
+  fn from_str(s: &str) -> Result<Self> {
+    let (ip_str, zone) = match s.split_once('%') {
+      Some((ip_str, zone)) => (ip_str, Some(zone.to_string())),
+      None => (s, None),
+    };
+
+    let ip = match zone {
+      Some(_) => Ipv6Addr::from_str(ip_str).map(IpAddr::V6),
+      None => IpAddr::from_str(ip_str),
+    }
+    .map_err(|e| Error::msg(format!("Failed to parse {}: {:?}", s, e)))?;
+
+    Ok(ScopedIpAddr { ip, zone })
+  }
+}
+
+impl fmt::Display for ScopedIpAddr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.zone {
+      Some(zone) => write!(f, "{}%{}", self.ip, zone),
+      None => write!(f, "{}", self.ip),
+    }
+  }
+}
@@ -0,0 +1,90 @@
+use log::warn;
+use std::time::Duration;
+
+/*
+  `ip -s neigh` (and BusyBox's 'ip neigh') print the kernel's cache info:
+  192.168.0.33 dev br-lan lladdr dc:a6:32:57:46:d6 ref 1 used 11/11/5 probes 1 REACHABLE
+
+  Where:
+    ref      - Number of references held on the entry.
+    used     - Seconds since the entry was last used/confirmed/updated.
+    probes   - Number of probes sent while resolving the entry.
+
+  Some iproute2 releases forget the space before 'probes':
+  192.0.2.1 dev eth0 lladdr 02:fc:00:00:00:05  used 372/352/327probes 1 STALE
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NeighborStats {
+  pub refcnt: Option<u32>,
+  pub used: Option<Duration>,
+  pub confirmed: Option<Duration>,
+  pub updated: Option<Duration>,
+  pub probes: Option<u32>,
+}
+
+impl NeighborStats {
+  ///
+  /// Parses the counters out of the tokens of an `ip neigh` row.
+  ///
+  /// Args:
+  ///  - tokens: Whitespace separated tokens following the device name.
+  ///
+  /// Returns:
+  ///  The counters, or None if the row didn't carry any.
+  ///
+  pub fn parse_from_tokens(tokens: &[&str]) -> Option<Self> {
+    let mut stats = NeighborStats::default();
+    let mut found = false;
+
+    // Split tokens such as "327probes" back apart.
+    let tokens: Vec<&str> = tokens
+      .iter()
+      .flat_map(|v| match v.strip_suffix("probes") {
+        Some(prefix) if !prefix.is_empty() => vec![prefix, "probes"],
+        _ => vec![*v],
+      })
+      .collect();
+
+    for (i, token) in tokens.iter().enumerate() {
+      let Some(value) = tokens.get(i + 1) else {
+        break;
+      };
+
+      match *token {
+        "ref" => {
+          stats.refcnt = parse_counter(token, value);
+          found = true;
+        }
+        "probes" => {
+          stats.probes = parse_counter(token, value);
+          found = true;
+        }
+        "used" => {
+          // <used>/<confirmed>/<updated>
+          let times: Vec<Option<Duration>> = value
+            .split('/')
+            .map(|v| parse_counter(token, v).map(|v| Duration::from_secs(v as u64)))
+            .collect();
+          stats.used = times.first().copied().flatten();
+          stats.confirmed = times.get(1).copied().flatten();
+          stats.updated = times.get(2).copied().flatten();
+          found = true;
+        }
+        _ => {}
+      }
+    }
+
+    found.then_some(stats)
+  }
+}
+
+fn parse_counter(name: &str, value: &str) -> Option<u32> {
+  match value.parse::<u32>() {
+    Ok(v) => Some(v),
+    Err(e) => {
+      warn!("Failed to parse '{}' counter '{}': {}", name, value, e);
+      None
+    }
+  }
+}
@@ -0,0 +1,106 @@
+use super::{netlink, run_ip_command, ArpTable, NeighborFilter};
+use anyhow::Result;
+use log::{debug, warn};
+
+/*
+  `ip -o -d link show type vrf` prints a single line per VRF device:
+  9: blue: <NOARP,MASTER,UP,LOWER_UP> mtu 65575 qdisc noqueue state UP ... \    vrf table 10 addrgenmode eui64 ...
+
+  So we're parsing:
+  <index>: <name>: .* vrf table <table> .*
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VrfDevice {
+  pub name: String,
+  pub table: Option<u32>,
+}
+
+impl VrfDevice {
+  ///
+  /// Parses a VRF device out of a one-line `ip -o -d link` row.
+  ///
+  /// Args:
+  ///  - s: Row result as a string.
+  ///
+  /// Returns:
+  ///  The device, or None if the row isn't a VRF device.
+  ///
+  pub fn parse_from_string(s: &str) -> Option<Self> {
+    let sliced_str: Vec<&str> = s.split_whitespace().collect();
+
+    // Names of stacked devices are printed as "<name>@<parent>".
+    let name = sliced_str.get(1)?.trim_end_matches(':');
+    let name = name.split('@').next()?.to_string();
+
+    let vrf_idx = sliced_str
+      .windows(2)
+      .position(|v| v[0] == "vrf" && v[1] == "table")?;
+    let table = sliced_str
+      .get(vrf_idx + 2)
+      .and_then(|v| v.parse::<u32>().ok());
+
+    Some(VrfDevice { name, table })
+  }
+}
+
+/// Enumerates the host's VRF devices.
+pub fn get_vrf_devices() -> Result<Vec<VrfDevice>> {
+  let stdout = run_ip_command(&["-o", "-d", "link", "show", "type", "vrf"])?;
+  let vrfs: Vec<VrfDevice> = stdout
+    .lines()
+    .filter_map(VrfDevice::parse_from_string)
+    .collect();
+  debug!("Found VRF devices -> {:?}", vrfs);
+
+  Ok(vrfs)
+}
+
+///
+/// Collects the neighbors of a single VRF, like `ip neigh show vrf <vrf>`,
+/// tagging every entry with the VRF's name.
+///
+/// Args:
+///  - vrf: Name of the VRF device.
+///  - filter: Further interface, address family, and NUD state restrictions.
+///
+/// Returns:
+///  Result of the VRF's neighbors.
+///
+pub fn get_ip_neighbors_for_vrf(vrf: &str, filter: &NeighborFilter) -> Result<Vec<ArpTable>> {
+  let filter = filter.clone().vrf(vrf);
+  let mut neighbors = netlink::get_ip_neighbors_filtered(&filter).or_else(|err| {
+    warn!("{}, falling back to 'ip neigh show vrf {}'", err, vrf);
+    super::get_ip_neighbors_filtered(&filter)
+  })?;
+
+  for neigh in neighbors.iter_mut() {
+    neigh.vrf = Some(vrf.to_string());
+  }
+  Ok(neighbors)
+}
+
+///
+/// Collects the neighbors of every table, tagging entries that belong to a
+/// VRF with its name. Entries of the default table are left untagged.
+///
+/// Returns:
+///  Result of every neighbor.
+///
+pub fn get_ip_neighbors_by_vrf() -> Result<Vec<ArpTable>> {
+  let mut neighbors = super::collect_neighbors()?;
+
+  for vrf in get_vrf_devices()? {
+    let vrf_neighbors = get_ip_neighbors_for_vrf(&vrf.name, &NeighborFilter::new())?;
+    for neigh in neighbors.iter_mut() {
+      let in_vrf = vrf_neighbors
+        .iter()
+        .any(|v| v.iface == neigh.iface && v.ip == neigh.ip);
+      if in_vrf {
+        neigh.vrf = Some(vrf.name.clone());
+      }
+    }
+  }
+
+  Ok(neighbors)
+}
@@ -0,0 +1,253 @@
+use crate::device::Device;
+use crate::neighbors::{MacAddr, ScopedIpAddr};
+use anyhow::{Error, Result};
+use hmac::{Hmac, KeyInit, Mac};
+use log::info;
+use sha2::Sha256;
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv6Addr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+#[cfg(feature = "daemon")]
+use crate::storage::{EventKind, HistoryEvent, Sighting};
+
+/// File holding the salt when none is configured.
+pub const DEFAULT_SALT_PATH: &str = "/etc/netmon/privacy.salt";
+
+/// Size of a generated salt, in bytes.
+const SALT_SIZE: usize = 32;
+
+/*
+  Identifiers are replaced by a keyed hash (HMAC-SHA256) of their value,
+  the salt being the key, so they still tell devices apart and join across
+  exports, but can't be reversed by hashing every MAC address of a vendor:
+
+    dc:a6:32:a3:48:b1   ->  5e:0b:93:d1:7c:24
+    Living room TV      ->  device-3a91f0c2b7e4
+    fe80::dea6:32ff:fea3:48b1%br-lan  ->  fe80::5c0b:93ff:fed1:7c24%br-lan
+
+  MAC addresses hash to locally administered unicast addresses, so parsers
+  still accept them. Other addresses are kept, but for IPv6 interface ids
+  derived from a MAC address (EUI-64), which get the hashed address's.
+*/
+
+///
+/// Salted hashing of the identifiers of devices, MAC addresses and names,
+/// for exports to be shared without revealing which devices a household
+/// owns. The same salt always gives the same hashes.
+///
+/// ```
+/// use openwrt_netmon::privacy::Anonymizer;
+/// use openwrt_netmon::MacAddr;
+///
+/// let anonymizer = Anonymizer::new(b"salt");
+/// let mac_addr: MacAddr = "dc:a6:32:a3:48:b1".parse()?;
+/// let hashed = anonymizer.mac_addr(&mac_addr);
+/// assert_ne!(hashed, mac_addr);
+/// assert!(hashed.is_locally_administered() && hashed.is_unicast());
+/// assert_eq!(hashed, anonymizer.mac_addr(&mac_addr));
+///
+/// assert!(anonymizer.name("Living room TV").starts_with("device-"));
+/// assert_ne!(Anonymizer::new(b"pepper").mac_addr(&mac_addr), hashed);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Clone)]
+pub struct Anonymizer {
+  salt: Vec<u8>,
+}
+
+impl fmt::Debug for Anonymizer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    // The salt is left out, as it undoes the hashing.
+    f.debug_struct("Anonymizer").finish_non_exhaustive()
+  }
+}
+
+impl Anonymizer {
+  pub fn new(salt: &[u8]) -> Self {
+    Anonymizer {
+      salt: salt.to_vec(),
+    }
+  }
+
+  ///
+  /// Reads the salt from a file, generating a random one on first use so
+  /// the hashes stay the same across restarts.
+  ///
+  /// Args:
+  ///  - path: File holding the salt, only readable by its owner once created.
+  ///
+  /// Returns:
+  ///  Result of the anonymizer, failing if the salt is unreadable or empty.
+  ///
+  pub fn load(path: &Path) -> Result<Self> {
+    let salt = match std::fs::read(path) {
+      Ok(v) => v,
+      Err(e) if e.kind() == ErrorKind::NotFound => generate_salt(path)?,
+      Err(e) => {
+        return Err(Error::msg(format!(
+          "Failed to read {}: {}",
+          path.display(),
+          e
+        )))
+      }
+    };
+    if salt.is_empty() {
+      return Err(Error::msg(format!("The salt {} is empty", path.display())));
+    }
+    Ok(Anonymizer::new(&salt))
+  }
+
+  /// Keyed hash of a value, its kind keeping equal values of different kinds apart.
+  fn hash(&self, kind: &str, value: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&self.salt).expect("HMAC takes keys of any size");
+    mac.update(kind.as_bytes());
+    mac.update(&[0]);
+    mac.update(value);
+    mac.finalize().into_bytes().into()
+  }
+
+  /// Hashes a MAC address into a locally administered unicast one.
+  pub fn mac_addr(&self, mac_addr: &MacAddr) -> MacAddr {
+    let hash = self.hash("mac", &mac_addr.octets());
+    let mut octets = [0; 6];
+    octets.copy_from_slice(&hash[..6]);
+    octets[0] = (octets[0] | 0x02) & !0x01;
+    MacAddr(octets)
+  }
+
+  /// Hashes a name, or any other identifier such as a DUID, e.g. into "device-3a91f0c2b7e4".
+  pub fn name(&self, name: &str) -> String {
+    let hash = self.hash("name", name.as_bytes());
+    let hex: String = hash[..6].iter().map(|v| format!("{:02x}", v)).collect();
+    format!("device-{}", hex)
+  }
+
+  /// Replaces the interface id of an IPv6 address derived from a MAC address.
+  pub fn ip(&self, ip: &ScopedIpAddr) -> ScopedIpAddr {
+    let IpAddr::V6(v6) = ip.ip else {
+      return ip.clone();
+    };
+    let mut octets = v6.octets();
+    if octets[11] != 0xff || octets[12] != 0xfe {
+      return ip.clone();
+    }
+    let mac_addr = MacAddr([
+      octets[8] ^ 0x02,
+      octets[9],
+      octets[10],
+      octets[13],
+      octets[14],
+      octets[15],
+    ]);
+    let hashed = self.mac_addr(&mac_addr).octets();
+    octets[8] = hashed[0] ^ 0x02;
+    octets[9..11].copy_from_slice(&hashed[1..3]);
+    octets[13..16].copy_from_slice(&hashed[3..6]);
+    ScopedIpAddr {
+      ip: IpAddr::V6(Ipv6Addr::from(octets)),
+      zone: ip.zone.clone(),
+    }
+  }
+
+  /// A device with its identifiers hashed, keeping what describes it, such as its vendor.
+  pub fn device(&self, device: &Device) -> Device {
+    let name = |v: &Option<String>| v.as_deref().map(|v| self.name(v));
+    let mut device = device.clone();
+    device.mac_addr = self.mac_addr(&device.mac_addr);
+    for address in &mut device.addresses {
+      address.ip = self.ip(&address.ip);
+    }
+    device.hostname = name(&device.hostname);
+    device.duid = name(&device.duid);
+    device.mdns_hostname = name(&device.mdns_hostname);
+    device.probed_name = name(&device.probed_name);
+    device.alias = name(&device.alias);
+    device.owner = name(&device.owner);
+    if let Some(lease) = &mut device.lease {
+      lease.ip = self.ip(&lease.ip.into()).ip;
+      lease.mac_addr = lease.mac_addr.map(|v| self.mac_addr(&v));
+      lease.hostname = name(&lease.hostname);
+      lease.client_id = name(&lease.client_id);
+    }
+    if let Some(station) = &mut device.station {
+      station.mac_addr = self.mac_addr(&station.mac_addr);
+    }
+    device
+  }
+
+  ///
+  /// An event with its MAC addresses, and the addresses derived from them,
+  /// hashed.
+  ///
+  /// Args:
+  ///  - event: Event to anonymize.
+  ///
+  /// Returns:
+  ///  The anonymized event.
+  ///
+  #[cfg(feature = "daemon")]
+  pub fn event(&self, event: &HistoryEvent) -> HistoryEvent {
+    let value = |v: &Option<String>| -> Option<String> {
+      let v = v.as_deref()?;
+      Some(match event.kind {
+        EventKind::Joined | EventKind::Left | EventKind::IpChanged => {
+          let ips: Vec<String> = v
+            .split(',')
+            .map(|ip| match ip.parse::<ScopedIpAddr>() {
+              Ok(ip) => self.ip(&ip).to_string(),
+              Err(_) => ip.to_string(),
+            })
+            .collect();
+          ips.join(",")
+        }
+        EventKind::MacChanged => match v.parse::<MacAddr>() {
+          Ok(mac_addr) => self.mac_addr(&mac_addr).to_string(),
+          Err(_) => self.name(v),
+        },
+        EventKind::StateChanged => v.to_string(),
+      })
+    };
+    HistoryEvent {
+      mac_addr: event.mac_addr.map(|v| self.mac_addr(&v)),
+      ip: event.ip.as_ref().map(|v| self.ip(v)),
+      old_value: value(&event.old_value),
+      new_value: value(&event.new_value),
+      ..event.clone()
+    }
+  }
+
+  /// A sighting with its MAC address hashed.
+  #[cfg(feature = "daemon")]
+  pub fn sighting(&self, sighting: &Sighting) -> Sighting {
+    Sighting {
+      mac_addr: self.mac_addr(&sighting.mac_addr),
+      ..sighting.clone()
+    }
+  }
+}
+
+/// Writes a random salt to a new file, only readable by its owner.
+fn generate_salt(path: &Path) -> Result<Vec<u8>> {
+  let mut salt = vec![0; SALT_SIZE];
+  std::fs::File::open("/dev/urandom")
+    .and_then(|mut file| file.read_exact(&mut salt))
+    .map_err(|e| Error::msg(format!("Failed to generate a salt: {}", e)))?;
+
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)
+      .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+  }
+  std::fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .mode(0o600)
+    .open(path)
+    .and_then(|mut file| file.write_all(&salt))
+    .map_err(|e| Error::msg(format!("Failed to write {}: {}", path.display(), e)))?;
+  info!("Generated the salt {}", path.display());
+  Ok(salt)
+}
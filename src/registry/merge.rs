@@ -0,0 +1,190 @@
+use super::Registry;
+use crate::neighbors::MacAddr;
+use std::fmt;
+
+/// Which value a field keeps when both registries set it differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergePolicy {
+  /// Keep the current value.
+  KeepCurrent,
+  /// Take the incoming value.
+  TakeIncoming,
+}
+
+/// A field both registries set, to different values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+  pub mac_addr: MacAddr,
+  /// Field in conflict, e.g. "name".
+  pub field: &'static str,
+  pub current: String,
+  pub incoming: String,
+}
+
+impl fmt::Display for Conflict {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} {}: '{}' here, '{}' incoming",
+      self.mac_addr, self.field, self.current, self.incoming
+    )
+  }
+}
+
+/// What merging a registry into another changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+  /// Devices that weren't known yet.
+  pub added: Vec<MacAddr>,
+  /// Known devices that got a field set or replaced.
+  pub updated: Vec<MacAddr>,
+  /// Known devices the merge left as they were.
+  pub unchanged: usize,
+  /// Fields set differently on both sides, resolved by the policy.
+  pub conflicts: Vec<Conflict>,
+}
+
+/// Merges a single field, recording a conflict if both sides set it.
+fn merge_field<T: Clone + PartialEq + fmt::Display>(
+  mac_addr: MacAddr,
+  field: &'static str,
+  current: &mut Option<T>,
+  incoming: &Option<T>,
+  policy: MergePolicy,
+  conflicts: &mut Vec<Conflict>,
+) {
+  match (current.as_ref(), incoming) {
+    (_, None) => {}
+    (None, Some(_)) => *current = incoming.clone(),
+    (Some(a), Some(b)) if a == b => {}
+    (Some(a), Some(b)) => {
+      conflicts.push(Conflict {
+        mac_addr,
+        field,
+        current: a.to_string(),
+        incoming: b.to_string(),
+      });
+      if policy == MergePolicy::TakeIncoming {
+        *current = incoming.clone();
+      }
+    }
+  }
+}
+
+impl Registry {
+  ///
+  /// Merges another registry into this one, field by field: devices and
+  /// fields only the other one knows are added, and fields both set
+  /// differently are conflicts, resolved by the policy.
+  ///
+  /// ```
+  /// use openwrt_netmon::registry::{MergePolicy, Registry};
+  ///
+  /// let mut registry: Registry = "aa:bb:cc:dd:ee:ff\tPrinter\t\t\t\n".parse()?;
+  /// let incoming: Registry = concat!(
+  ///   "aa:bb:cc:dd:ee:ff\tOffice printer\tbob\t\t\n",
+  ///   "dc:a6:32:a3:48:b1\tLiving room TV\t\tmedia\t\n",
+  /// ).parse()?;
+  ///
+  /// let report = registry.merge(&incoming, MergePolicy::KeepCurrent);
+  /// assert_eq!(report.added.len(), 1);
+  /// assert_eq!(report.conflicts[0].field, "name");
+  /// assert_eq!(registry.name_of(&"aa:bb:cc:dd:ee:ff".parse()?), Some("Printer"));
+  /// # Ok::<(), anyhow::Error>(())
+  /// ```
+  ///
+  /// Args:
+  ///  - other: Registry to merge in.
+  ///  - policy: Which side wins conflicts.
+  ///
+  /// Returns:
+  ///  What the merge added, updated, and found in conflict.
+  ///
+  pub fn merge(&mut self, other: &Registry, policy: MergePolicy) -> MergeReport {
+    let mut report = MergeReport::default();
+    for incoming in other.iter() {
+      let Some(current) = self.devices.get_mut(&incoming.mac_addr) else {
+        report.added.push(incoming.mac_addr);
+        self.insert(incoming.clone());
+        continue;
+      };
+
+      let before = current.clone();
+      let mac_addr = incoming.mac_addr;
+      let conflicts = &mut report.conflicts;
+      merge_field(
+        mac_addr,
+        "name",
+        &mut current.name,
+        &incoming.name,
+        policy,
+        conflicts,
+      );
+      merge_field(
+        mac_addr,
+        "owner",
+        &mut current.owner,
+        &incoming.owner,
+        policy,
+        conflicts,
+      );
+      merge_field(
+        mac_addr,
+        "category",
+        &mut current.category,
+        &incoming.category,
+        policy,
+        conflicts,
+      );
+      merge_field(
+        mac_addr,
+        "trust",
+        &mut current.trust,
+        &incoming.trust,
+        policy,
+        conflicts,
+      );
+      merge_field(
+        mac_addr,
+        "policy",
+        &mut current.policy,
+        &incoming.policy,
+        policy,
+        conflicts,
+      );
+      match *current == before {
+        true => report.unchanged += 1,
+        false => report.updated.push(mac_addr),
+      }
+    }
+    report
+  }
+}
+
+/// Devices of a registry, as written to and read from YAML or JSON.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Registry {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(self.iter())
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Registry {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> std::result::Result<Self, D::Error> {
+    let devices: Vec<super::KnownDevice> = serde::Deserialize::deserialize(deserializer)?;
+    let mut registry = Registry::new();
+    for device in devices {
+      let mac_addr = device.mac_addr;
+      if registry.insert(device).is_some() {
+        return Err(serde::de::Error::custom(format!(
+          "{} is listed more than once",
+          mac_addr
+        )));
+      }
+    }
+    Ok(registry)
+  }
+}
@@ -0,0 +1,389 @@
+use crate::device::Device;
+use crate::neighbors::MacAddr;
+use anyhow::{Error, Result};
+use log::debug;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::str::FromStr;
+
+pub mod merge;
+pub mod policy;
+
+pub use merge::{Conflict, MergePolicy, MergeReport};
+pub use policy::AlertPolicy;
+
+/// Registry read and written when no other path is given, on the overlay so
+/// it survives reboots.
+pub const DEFAULT_REGISTRY_PATH: &str = "/etc/netmon/devices.tsv";
+
+/// Kind of device, as the user categorized it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DeviceCategory {
+  Phone,
+  Computer,
+  Iot,
+  Server,
+  Network,
+  Media,
+  Other,
+}
+
+impl DeviceCategory {
+  pub const ALL: [DeviceCategory; 7] = [
+    DeviceCategory::Phone,
+    DeviceCategory::Computer,
+    DeviceCategory::Iot,
+    DeviceCategory::Server,
+    DeviceCategory::Network,
+    DeviceCategory::Media,
+    DeviceCategory::Other,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      DeviceCategory::Phone => "phone",
+      DeviceCategory::Computer => "computer",
+      DeviceCategory::Iot => "iot",
+      DeviceCategory::Server => "server",
+      DeviceCategory::Network => "network",
+      DeviceCategory::Media => "media",
+      DeviceCategory::Other => "other",
+    }
+  }
+}
+
+impl fmt::Display for DeviceCategory {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for DeviceCategory {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    DeviceCategory::ALL
+      .into_iter()
+      .find(|v| v.name().eq_ignore_ascii_case(s))
+      .ok_or_else(|| Error::msg(format!("Unknown device category '{}'", s)))
+  }
+}
+
+/// How much the user trusts a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TrustLevel {
+  Trusted,
+  Guest,
+  Untrusted,
+}
+
+impl TrustLevel {
+  pub const ALL: [TrustLevel; 3] = [
+    TrustLevel::Trusted,
+    TrustLevel::Guest,
+    TrustLevel::Untrusted,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      TrustLevel::Trusted => "trusted",
+      TrustLevel::Guest => "guest",
+      TrustLevel::Untrusted => "untrusted",
+    }
+  }
+}
+
+impl fmt::Display for TrustLevel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for TrustLevel {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    TrustLevel::ALL
+      .into_iter()
+      .find(|v| v.name().eq_ignore_ascii_case(s))
+      .ok_or_else(|| Error::msg(format!("Unknown trust level '{}'", s)))
+  }
+}
+
+/// A device the user told the monitor about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct KnownDevice {
+  pub mac_addr: MacAddr,
+  /// Friendly name, shown instead of the MAC address.
+  #[cfg_attr(
+    feature = "serde",
+    serde(default, skip_serializing_if = "Option::is_none")
+  )]
+  pub name: Option<String>,
+  #[cfg_attr(
+    feature = "serde",
+    serde(default, skip_serializing_if = "Option::is_none")
+  )]
+  pub owner: Option<String>,
+  #[cfg_attr(
+    feature = "serde",
+    serde(default, skip_serializing_if = "Option::is_none")
+  )]
+  pub category: Option<DeviceCategory>,
+  #[cfg_attr(
+    feature = "serde",
+    serde(default, skip_serializing_if = "Option::is_none")
+  )]
+  pub trust: Option<TrustLevel>,
+  /// How the alerts about the device depart from the rules.
+  #[cfg_attr(
+    feature = "serde",
+    serde(default, skip_serializing_if = "Option::is_none")
+  )]
+  pub policy: Option<AlertPolicy>,
+}
+
+impl KnownDevice {
+  pub fn new(mac_addr: MacAddr) -> Self {
+    KnownDevice {
+      mac_addr,
+      name: None,
+      owner: None,
+      category: None,
+      trust: None,
+      policy: None,
+    }
+  }
+}
+
+/*
+  The registry is a tab separated file, one device per line, with empty
+  fields for what's unknown:
+
+    # mac	name	owner	category	trust	policy
+    dc:a6:32:a3:48:b1	Living room TV	alice	media	trusted
+    3c:22:fb:10:02:7e	Thermostat		iot	untrusted	mute:left,mute:offline
+    00:11:32:4e:8a:0c	NAS		server	trusted	critical
+
+  Lines starting with '#' are comments, and lines written before policies
+  existed lack the last field. Names and owners can't hold tabs or line
+  breaks, which are replaced with spaces.
+*/
+
+/// Header written at the top of the registry.
+const HEADER: &str = "# mac\tname\towner\tcategory\ttrust\tpolicy";
+
+///
+/// Known devices keyed by MAC address, with the names, owners, categories,
+/// trust levels, and alert policies the user gave them. The daemon and the command line both
+/// read it, so every output and alert names devices the way the user does.
+///
+/// ```
+/// use openwrt_netmon::registry::{DeviceCategory, Registry};
+///
+/// let registry: Registry = "dc:a6:32:a3:48:b1\tLiving room TV\t\tmedia\t\n".parse()?;
+/// let mac_addr = "dc:a6:32:a3:48:b1".parse()?;
+/// assert_eq!(registry.name_of(&mac_addr), Some("Living room TV"));
+/// assert_eq!(registry.get(&mac_addr).unwrap().category, Some(DeviceCategory::Media));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Registry {
+  devices: BTreeMap<MacAddr, KnownDevice>,
+}
+
+impl Registry {
+  pub fn new() -> Self {
+    Registry::default()
+  }
+
+  ///
+  /// Reads a registry file.
+  ///
+  /// Args:
+  ///  - path: Registry file.
+  ///
+  /// Returns:
+  ///  Result of the registry, empty if the file doesn't exist yet.
+  ///
+  pub fn load(path: &Path) -> Result<Registry> {
+    match std::fs::read_to_string(path) {
+      Ok(content) => content
+        .parse()
+        .map_err(|e| Error::msg(format!("Invalid registry {}: {}", path.display(), e))),
+      Err(e) if e.kind() == ErrorKind::NotFound => {
+        debug!("No registry at {}", path.display());
+        Ok(Registry::new())
+      }
+      Err(e) => Err(Error::msg(format!(
+        "Failed to read {}: {}",
+        path.display(),
+        e
+      ))),
+    }
+  }
+
+  ///
+  /// Writes the registry atomically, through a temporary file and a rename,
+  /// creating its directory.
+  ///
+  /// Args:
+  ///  - path: Registry file.
+  ///
+  pub fn save(&self, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, self.to_string())
+      .and_then(|_| std::fs::rename(&tmp_path, path))
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", path.display(), e)))
+  }
+
+  pub fn get(&self, mac_addr: &MacAddr) -> Option<&KnownDevice> {
+    self.devices.get(mac_addr)
+  }
+
+  /// Adds a device, returning the entry it replaced.
+  pub fn insert(&mut self, device: KnownDevice) -> Option<KnownDevice> {
+    self.devices.insert(device.mac_addr, device)
+  }
+
+  pub fn remove(&mut self, mac_addr: &MacAddr) -> Option<KnownDevice> {
+    self.devices.remove(mac_addr)
+  }
+
+  /// Every known device, by MAC address.
+  pub fn iter(&self) -> impl Iterator<Item = &KnownDevice> {
+    self.devices.values()
+  }
+
+  pub fn len(&self) -> usize {
+    self.devices.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.devices.is_empty()
+  }
+
+  /// Name the user gave a device.
+  pub fn name_of(&self, mac_addr: &MacAddr) -> Option<&str> {
+    self.get(mac_addr).and_then(|v| v.name.as_deref())
+  }
+
+  ///
+  /// Gives the known devices the name, owner, category, and trust level
+  /// they were registered with. Names replace the aliases of the config.
+  ///
+  /// Args:
+  ///  - devices: Devices to annotate.
+  ///
+  pub fn annotate(&self, devices: &mut [Device]) {
+    for device in devices {
+      let Some(known) = self.get(&device.mac_addr) else {
+        continue;
+      };
+      if known.name.is_some() {
+        device.alias = known.name.clone();
+      }
+      device.owner = known.owner.clone();
+      device.category = known.category;
+      device.trust = known.trust;
+    }
+  }
+}
+
+/// Keeps a field on its line, as the registry can't quote.
+fn clean_field(value: &Option<String>) -> String {
+  value
+    .as_deref()
+    .unwrap_or_default()
+    .replace(['\t', '\n', '\r'], " ")
+}
+
+impl fmt::Display for Registry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "{}", HEADER)?;
+    for device in self.iter() {
+      writeln!(
+        f,
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        device.mac_addr,
+        clean_field(&device.name),
+        clean_field(&device.owner),
+        device.category.map(|v| v.name()).unwrap_or_default(),
+        device.trust.map(|v| v.name()).unwrap_or_default(),
+        device
+          .policy
+          .as_ref()
+          .map(|v| v.to_string())
+          .unwrap_or_default()
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Optional text field, None if empty.
+fn text_field(value: Option<&str>) -> Option<String> {
+  value
+    .map(str::trim)
+    .filter(|v| !v.is_empty())
+    .map(String::from)
+}
+
+/// Parses a single device line.
+fn parse_device(line: &str) -> Result<KnownDevice> {
+  let mut fields = line.split('\t');
+  let mac_addr: MacAddr = fields.next().unwrap_or_default().trim().parse()?;
+  let name = text_field(fields.next());
+  let owner = text_field(fields.next());
+  let category = text_field(fields.next()).map(|v| v.parse()).transpose()?;
+  let trust = text_field(fields.next()).map(|v| v.parse()).transpose()?;
+  let policy = text_field(fields.next())
+    .map(|v| v.parse::<AlertPolicy>())
+    .transpose()?
+    .filter(|v| !v.is_empty());
+  if fields.next().is_some() {
+    return Err(Error::msg("Too many fields"));
+  }
+  Ok(KnownDevice {
+    mac_addr,
+    name,
+    owner,
+    category,
+    trust,
+    policy,
+  })
+}
+
+impl FromStr for Registry {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let mut registry = Registry::new();
+    for (i, line) in s.lines().enumerate() {
+      if line.trim().is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let device = parse_device(line).map_err(|e| Error::msg(format!("line {}: {}", i + 1, e)))?;
+      if registry.insert(device).is_some() {
+        return Err(Error::msg(format!(
+          "line {}: device listed more than once",
+          i + 1
+        )));
+      }
+    }
+    Ok(registry)
+  }
+}
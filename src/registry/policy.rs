@@ -0,0 +1,136 @@
+use anyhow::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Events alert rules fire on, as policies mute them, e.g. "offline".
+pub const ALERT_EVENTS: [&str; 13] = [
+  "joined",
+  "left",
+  "ip_changed",
+  "mac_changed",
+  "state_changed",
+  "new_device",
+  "offline",
+  "returned",
+  "arp_spoofing",
+  "wrong_interface",
+  "rogue_dhcp",
+  "rogue_ra",
+  "policy_violation",
+];
+
+/*
+  A policy is a comma separated list of what it overrides:
+
+    critical                every alert about the device is critical
+    mute                    no alert is raised about the device
+    mute:left,mute:offline  none about it leaving or going offline
+
+  e.g. "critical,mute:state_changed" for a NAS whose NUD states flap.
+*/
+
+///
+/// How the alerts about a device depart from what the rules raise, e.g. so
+/// a flaky sensor's disconnects are muted, or the NAS always pages. Policies
+/// go before the rules: a muted change matches none of them.
+///
+/// ```
+/// use openwrt_netmon::registry::AlertPolicy;
+///
+/// let policy: AlertPolicy = "critical, mute:offline".parse()?;
+/// assert!(policy.critical);
+/// assert!(policy.mutes("offline"));
+/// assert!(!policy.mutes("joined"));
+/// assert_eq!(policy.to_string(), "critical,mute:offline");
+///
+/// assert!("mute:sneezed".parse::<AlertPolicy>().is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AlertPolicy {
+  /// Raises the device's alerts as critical, whatever their rule's
+  /// severity, so they're never held during quiet hours.
+  pub critical: bool,
+  /// Events no alert is raised about, one of ALERT_EVENTS each.
+  pub muted: Vec<String>,
+}
+
+impl AlertPolicy {
+  /// Whether the policy overrides nothing.
+  pub fn is_empty(&self) -> bool {
+    !self.critical && self.muted.is_empty()
+  }
+
+  /// Whether no alert is raised about an event of the device.
+  pub fn mutes(&self, event: &str) -> bool {
+    self.muted.iter().any(|v| v == event)
+  }
+}
+
+impl fmt::Display for AlertPolicy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut overrides = Vec::new();
+    if self.critical {
+      overrides.push("critical".to_string());
+    }
+    match ALERT_EVENTS.iter().all(|v| self.mutes(v)) {
+      true => overrides.push("mute".to_string()),
+      false => overrides.extend(self.muted.iter().map(|v| format!("mute:{}", v))),
+    }
+    f.write_str(&overrides.join(","))
+  }
+}
+
+impl FromStr for AlertPolicy {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let mut policy = AlertPolicy::default();
+    let mut mute = |event: &str| {
+      if !policy.mutes(event) {
+        policy.muted.push(event.to_string());
+      }
+    };
+    let mut critical = false;
+    for item in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+      match item.split_once(':') {
+        None if item == "critical" => critical = true,
+        None if item == "mute" => ALERT_EVENTS.iter().for_each(|v| mute(v)),
+        Some(("mute", event)) if ALERT_EVENTS.contains(&event.trim()) => mute(event.trim()),
+        Some(("mute", event)) => {
+          return Err(Error::msg(format!(
+            "Unknown event '{}', expected one of {}",
+            event.trim(),
+            ALERT_EVENTS.join(", ")
+          )))
+        }
+        _ => {
+          return Err(Error::msg(format!(
+            "Unknown policy '{}', expected critical, mute, or mute:<event>",
+            item
+          )))
+        }
+      }
+    }
+    policy.critical = critical;
+    Ok(policy)
+  }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AlertPolicy {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for AlertPolicy {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> std::result::Result<Self, D::Error> {
+    let s: String = serde::Deserialize::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+  }
+}
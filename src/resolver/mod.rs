@@ -0,0 +1,228 @@
+use crate::device::Device;
+use crate::neighbors::scoped_ip::is_ipv6_link_local;
+use anyhow::{Error, Result};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a resolved hostname is trusted before querying again.
+const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long a failed lookup is remembered, so silent addresses aren't queried every poll.
+const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(60);
+
+/// Number of lookups in flight at once.
+const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+///
+/// PTR-queries an address through the system resolver.
+///
+/// Args:
+///  - ip: Address to resolve.
+///
+/// Returns:
+///  Result of the hostname, or None if the address has no PTR record.
+///
+pub fn reverse_lookup(ip: &IpAddr) -> Result<Option<String>> {
+  // SAFETY: Zeroed sockaddr structs are valid, and getnameinfo only reads
+  // `addr_len` bytes of the address while writing at most `host.len()` bytes.
+  let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+  let addr_len = match ip {
+    IpAddr::V4(v4) => {
+      let addr = &mut storage as *mut _ as *mut libc::sockaddr_in;
+      unsafe {
+        (*addr).sin_family = libc::AF_INET as libc::sa_family_t;
+        (*addr).sin_addr.s_addr = u32::from_ne_bytes(v4.octets());
+      }
+      std::mem::size_of::<libc::sockaddr_in>()
+    }
+    IpAddr::V6(v6) => {
+      let addr = &mut storage as *mut _ as *mut libc::sockaddr_in6;
+      unsafe {
+        (*addr).sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        (*addr).sin6_addr.s6_addr = v6.octets();
+      }
+      std::mem::size_of::<libc::sockaddr_in6>()
+    }
+  };
+
+  let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+  let rc = unsafe {
+    libc::getnameinfo(
+      &storage as *const _ as *const libc::sockaddr,
+      addr_len as libc::socklen_t,
+      host.as_mut_ptr(),
+      host.len() as libc::socklen_t,
+      std::ptr::null_mut(),
+      0,
+      libc::NI_NAMEREQD,
+    )
+  };
+
+  match rc {
+    0 => {
+      let hostname = unsafe { CStr::from_ptr(host.as_ptr()) };
+      Ok(Some(hostname.to_string_lossy().into_owned()))
+    }
+    libc::EAI_NONAME => Ok(None),
+    _ => {
+      let reason = unsafe { CStr::from_ptr(libc::gai_strerror(rc)) };
+      Err(Error::msg(format!(
+        "Reverse lookup of {} failed: {}",
+        ip,
+        reason.to_string_lossy()
+      )))
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+  hostname: Option<String>,
+  expires_at: Instant,
+}
+
+///
+/// Resolves neighbor addresses into hostnames, caching the results and
+/// capping the number of lookups in flight.
+///
+#[derive(Debug)]
+pub struct HostnameResolver {
+  cache: Mutex<HashMap<IpAddr, CacheEntry>>,
+  ttl: Duration,
+  negative_ttl: Duration,
+  max_concurrency: usize,
+}
+
+impl Default for HostnameResolver {
+  fn default() -> Self {
+    HostnameResolver {
+      cache: Mutex::new(HashMap::new()),
+      ttl: DEFAULT_TTL,
+      negative_ttl: DEFAULT_NEGATIVE_TTL,
+      max_concurrency: DEFAULT_MAX_CONCURRENCY,
+    }
+  }
+}
+
+impl HostnameResolver {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// How long resolved hostnames are cached.
+  pub fn ttl(mut self, ttl: Duration) -> Self {
+    self.ttl = ttl;
+    self
+  }
+
+  /// How long failed lookups are cached.
+  pub fn negative_ttl(mut self, negative_ttl: Duration) -> Self {
+    self.negative_ttl = negative_ttl;
+    self
+  }
+
+  /// Number of lookups in flight at once.
+  pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+    self.max_concurrency = max_concurrency.max(1);
+    self
+  }
+
+  /// Returns the cached hostname, or None if the address needs to be queried (again).
+  fn cached(&self, ip: &IpAddr, now: Instant) -> Option<Option<String>> {
+    let cache = self.cache.lock().unwrap();
+    cache
+      .get(ip)
+      .filter(|v| v.expires_at > now)
+      .map(|v| v.hostname.clone())
+  }
+
+  /// Resolves a single address, going through the cache.
+  pub fn resolve(&self, ip: &IpAddr) -> Option<String> {
+    let now = Instant::now();
+    if let Some(hostname) = self.cached(ip, now) {
+      return hostname;
+    }
+
+    let hostname = reverse_lookup(ip).unwrap_or_else(|err| {
+      warn!("{}", err);
+      None
+    });
+    debug!("Resolved {} -> {:?}", ip, hostname);
+
+    let ttl = match hostname {
+      Some(_) => self.ttl,
+      None => self.negative_ttl,
+    };
+    self.cache.lock().unwrap().insert(
+      *ip,
+      CacheEntry {
+        hostname: hostname.clone(),
+        expires_at: now + ttl,
+      },
+    );
+    hostname
+  }
+
+  ///
+  /// Resolves several addresses concurrently, with at most `max_concurrency`
+  /// lookups in flight.
+  ///
+  /// Args:
+  ///  - ips: Addresses to resolve.
+  ///
+  /// Returns:
+  ///  The hostname of every address.
+  ///
+  pub fn resolve_all(&self, ips: &[IpAddr]) -> HashMap<IpAddr, Option<String>> {
+    let queue = Mutex::new(ips.iter());
+    let results = Mutex::new(HashMap::new());
+
+    let workers = self.max_concurrency.min(ips.len());
+    std::thread::scope(|scope| {
+      for _ in 0..workers {
+        scope.spawn(|| loop {
+          let Some(ip) = queue.lock().unwrap().next() else {
+            break;
+          };
+          let hostname = self.resolve(ip);
+          results.lock().unwrap().insert(*ip, hostname);
+        });
+      }
+    });
+
+    results.into_inner().unwrap()
+  }
+
+  ///
+  /// Resolves the hostname of every device, preferring the PTR record of its
+  /// IPv4 addresses over its IPv6 ones. Link-local addresses are skipped as
+  /// they're never in DNS.
+  ///
+  /// Args:
+  ///  - devices: Devices to attach hostnames to.
+  ///
+  pub fn resolve_devices(&self, devices: &mut [Device]) {
+    let resolvable = |device: &Device| -> Vec<IpAddr> {
+      let mut ips: Vec<IpAddr> = device
+        .ips()
+        .map(|v| v.ip)
+        .filter(|v| !is_ipv6_link_local(v))
+        .collect();
+      ips.sort_by_key(|v| v.is_ipv6());
+      ips
+    };
+
+    let ips: Vec<IpAddr> = devices.iter().flat_map(resolvable).collect();
+    let hostnames = self.resolve_all(&ips);
+
+    for device in devices.iter_mut() {
+      device.hostname = resolvable(device)
+        .iter()
+        .find_map(|v| hostnames.get(v).cloned().flatten());
+    }
+  }
+}
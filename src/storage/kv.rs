@@ -0,0 +1,439 @@
+use super::memory::{event_line, parse_line, sighting_line};
+use super::{
+  from_unix_secs, migrate, unix_secs, AddressRecord, HistoryEvent, HistoryQuery, PollRecords,
+  PollWriter, PruneStats, RecentHistory, Retention, Sighting, Storage,
+};
+use crate::daemon::{PollReport, Sink};
+use anyhow::{Error, Result};
+use log::debug;
+use redb::{
+  Database, Durability, ReadableDatabase, ReadableTable, TableDefinition, TableError,
+  WriteTransaction,
+};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Database written when none is configured, on the overlay so it survives
+/// reboots.
+pub const DEFAULT_REDB_PATH: &str = "/etc/netmon/history.redb";
+
+/// Longest time polls are committed without syncing them to flash, when no
+/// interval is configured.
+pub const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Page cache of the database, kept small for the router's memory.
+const CACHE_SIZE: usize = 4 * 1024 * 1024;
+
+/*
+  Records are keyed by time, in seconds since the epoch, and a sequence
+  number unique across the database, and hold the lines of
+  storage::RecentHistory. Each device's records are indexed by MAC address
+  too, so reading a single device doesn't scan the whole history:
+
+    events            (time, seq)       -> "event\t..."
+    events_by_mac     (mac, time, seq)  -> ()
+    sightings         (time, seq)       -> "sighting\t..."
+    sightings_by_mac  (mac, time, seq)  -> ()
+    addresses         (mac, ip)         -> (first_seen, last_seen)
+    meta              "seq"             -> next sequence number
+                      "schema_version"  -> version of the layout (see storage::migrate)
+*/
+type Records = TableDefinition<'static, (i64, u64), &'static str>;
+type Index = TableDefinition<'static, (&'static str, i64, u64), ()>;
+
+const EVENTS: Records = TableDefinition::new("events");
+const EVENTS_BY_MAC: Index = TableDefinition::new("events_by_mac");
+const SIGHTINGS: Records = TableDefinition::new("sightings");
+const SIGHTINGS_BY_MAC: Index = TableDefinition::new("sightings_by_mac");
+const ADDRESSES: TableDefinition<(&str, &str), (i64, i64)> = TableDefinition::new("addresses");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+
+/// Key of the meta table holding the version of the layout.
+const SCHEMA_VERSION: &str = "schema_version";
+
+/// Migrations of the layout, the first bringing it to version 1.
+const MIGRATIONS: &[fn(&WriteTransaction) -> Result<()>] = &[create_tables];
+
+/// Creates the tables of the first layout.
+fn create_tables(tx: &WriteTransaction) -> Result<()> {
+  tx.open_table(EVENTS)?;
+  tx.open_table(EVENTS_BY_MAC)?;
+  tx.open_table(SIGHTINGS)?;
+  tx.open_table(SIGHTINGS_BY_MAC)?;
+  tx.open_table(ADDRESSES)?;
+  tx.open_table(META)?;
+  Ok(())
+}
+
+/// Version of a database's layout, 0 if it has none.
+fn schema_version(db: &Database) -> Result<u32> {
+  let tx = db.begin_read()?;
+  let version = match tx.open_table(META) {
+    Ok(meta) => meta.get(SCHEMA_VERSION)?.map_or(0, |v| v.value()),
+    Err(TableError::TableDoesNotExist(_)) => 0,
+    Err(e) => return Err(e.into()),
+  };
+  Ok(u32::try_from(version).unwrap_or(u32::MAX))
+}
+
+/// Runs migrations in a single transaction, recording the latest version.
+fn migrate(db: &Database, versions: RangeInclusive<u32>) -> Result<()> {
+  let tx = db.begin_write()?;
+  for version in versions.clone() {
+    MIGRATIONS[version as usize - 1](&tx)?;
+  }
+  tx.open_table(META)?
+    .insert(SCHEMA_VERSION, *versions.end() as u64)?;
+  tx.commit()?;
+  Ok(())
+}
+
+///
+/// Storage backend recording the device history in a redb database, an
+/// embedded key-value store, as an alternative to SQLite's write
+/// amplification on NAND and eMMC flash. Every poll is committed as it's
+/// published, but synced to flash at most once per sync interval, so a
+/// crash or power loss loses at most the polls of the last interval.
+///
+/// The database is locked while open, so only the daemon reads it back.
+///
+pub struct RedbStorage {
+  path: PathBuf,
+  db: Database,
+  sync_interval: Duration,
+  synced_at: Instant,
+  /// Whether commits since the last sync are still to be synced.
+  unsynced: bool,
+}
+
+impl RedbStorage {
+  ///
+  /// Opens a database, creating it along with its directory and tables.
+  ///
+  /// Args:
+  ///  - path: Database file.
+  ///
+  /// Returns:
+  ///  Result of the storage, failing if the database can't be opened, e.g.
+  ///  while another process has it open.
+  ///
+  pub fn open(path: &Path) -> Result<Self> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+    let db = Database::builder()
+      .set_cache_size(CACHE_SIZE)
+      .create(path)
+      .map_err(|e| Error::msg(format!("Failed to open {}: {}", path.display(), e)))?;
+
+    let version = schema_version(&db)
+      .map_err(|e| Error::msg(format!("Failed to read {}: {}", path.display(), e)))?;
+    let versions = migrate::plan(
+      &path.display().to_string(),
+      version,
+      MIGRATIONS.len() as u32,
+    )?;
+    if !versions.is_empty() {
+      migrate(&db, versions)
+        .map_err(|e| Error::msg(format!("Failed to migrate {}: {}", path.display(), e)))?;
+    }
+    debug!("Opened {}", path.display());
+
+    Ok(RedbStorage {
+      path: path.to_path_buf(),
+      db,
+      sync_interval: DEFAULT_SYNC_INTERVAL,
+      synced_at: Instant::now(),
+      unsynced: false,
+    })
+  }
+
+  /// Longest time polls are committed without syncing them, every poll
+  /// being synced if zero.
+  pub fn sync_interval(mut self, sync_interval: Duration) -> Self {
+    self.sync_interval = sync_interval;
+    self
+  }
+
+  /// Maps a database error onto one naming the database.
+  fn error(&self, action: &str, e: Error) -> Error {
+    Error::msg(format!(
+      "Failed to {} {}: {}",
+      action,
+      self.path.display(),
+      e
+    ))
+  }
+
+  /// Whether the next commit is synced to flash.
+  fn sync_due(&self) -> bool {
+    self.synced_at.elapsed() >= self.sync_interval
+  }
+
+  /// Records that a commit went through, synced or not.
+  fn committed(&mut self, synced: bool) {
+    match synced {
+      true => {
+        self.synced_at = Instant::now();
+        self.unsynced = false;
+      }
+      false => self.unsynced = true,
+    }
+  }
+
+  ///
+  /// Records a poll in a single transaction: its sightings, its changes,
+  /// and the addresses of its devices.
+  ///
+  /// Args:
+  ///  - report: Poll to record.
+  ///
+  pub fn record(&mut self, report: &PollReport) -> Result<()> {
+    self.write_polls(&[PollRecords::new(report)])
+  }
+
+  /// Reads the records of a table matching a query, oldest first.
+  fn read(&self, records: Records, index: Index, query: &HistoryQuery) -> Result<RecentHistory> {
+    let from = query.since.map_or(i64::MIN, unix_secs);
+    let to = query.until.map_or(i64::MAX, unix_secs);
+    let read = || -> Result<RecentHistory> {
+      let tx = self.db.begin_read()?;
+      let records = tx.open_table(records)?;
+      let mut history = RecentHistory::default();
+      match query.mac_addr {
+        Some(mac_addr) => {
+          let mac_addr = mac_addr.to_string();
+          let index = tx.open_table(index)?;
+          for entry in index.range((mac_addr.as_str(), from, 0)..(mac_addr.as_str(), to, 0))? {
+            let (_, time, seq) = entry?.0.value();
+            if let Some(line) = records.get((time, seq))? {
+              parse_line(line.value(), &mut history)?;
+            }
+          }
+        }
+        None => {
+          for entry in records.range((from, 0)..(to, 0))? {
+            parse_line(entry?.1.value(), &mut history)?;
+          }
+        }
+      }
+      Ok(history)
+    };
+    read().map_err(|e| self.error("read", e))
+  }
+}
+
+///
+/// Removes the records of a table before a time, along with their index
+/// entries.
+///
+/// Args:
+///  - tx: Transaction to remove them in.
+///  - records: Table of the records.
+///  - index: Index of the records by MAC address.
+///  - before: Time before which records are removed.
+///
+/// Returns:
+///  Result of the number of records removed.
+///
+fn remove_before(
+  tx: &redb::WriteTransaction,
+  records: Records,
+  index: Index,
+  before: i64,
+) -> Result<usize> {
+  let mut records = tx.open_table(records)?;
+  let mut index = tx.open_table(index)?;
+  let mut removed = Vec::new();
+  for entry in records.extract_from_if(..(before, 0), |_, _| true)? {
+    let (key, line) = entry?;
+    removed.push((key.value(), line.value().to_string()));
+  }
+
+  for ((time, seq), line) in &removed {
+    let mut history = RecentHistory::default();
+    parse_line(line, &mut history)?;
+    let mac_addr = match (history.events.first(), history.sightings.first()) {
+      (Some(event), _) => event.mac_addr,
+      (_, Some(sighting)) => Some(sighting.mac_addr),
+      _ => None,
+    };
+    if let Some(mac_addr) = mac_addr {
+      index.remove((mac_addr.to_string().as_str(), *time, *seq))?;
+    }
+  }
+  Ok(removed.len())
+}
+
+///
+/// Checks a copy of a database, made while the daemon may have been writing
+/// to it, rolling back the commit it was copied in the middle of, if any.
+///
+/// Args:
+///  - path: Copy to check.
+///
+/// Returns:
+///  Result reflecting whether the copy is usable.
+///
+pub fn check_copy(path: &Path) -> Result<()> {
+  let mut db = Database::open(path)
+    .map_err(|e| Error::msg(format!("Failed to open {}: {}", path.display(), e)))?;
+  match db.check_integrity() {
+    Ok(true) => Ok(()),
+    Ok(false) => {
+      debug!("Repaired {}", path.display());
+      Ok(())
+    }
+    Err(e) => Err(Error::msg(format!(
+      "{} is corrupted: {}",
+      path.display(),
+      e
+    ))),
+  }
+}
+
+impl PollWriter for RedbStorage {
+  fn write_polls(&mut self, polls: &[PollRecords]) -> Result<()> {
+    let sync = self.sync_due();
+    let written = (|| -> Result<()> {
+      let mut tx = self.db.begin_write()?;
+      if !sync {
+        tx.set_durability(Durability::None)?;
+      }
+      {
+        let mut meta = tx.open_table(META)?;
+        let mut seq = meta.get("seq")?.map_or(0, |v| v.value());
+        let mut events = tx.open_table(EVENTS)?;
+        let mut events_by_mac = tx.open_table(EVENTS_BY_MAC)?;
+        let mut sightings = tx.open_table(SIGHTINGS)?;
+        let mut sightings_by_mac = tx.open_table(SIGHTINGS_BY_MAC)?;
+        let mut addresses = tx.open_table(ADDRESSES)?;
+
+        for poll in polls {
+          let time = unix_secs(poll.time);
+          for event in &poll.events {
+            events.insert((time, seq), event_line(event).as_str())?;
+            if let Some(mac_addr) = event.mac_addr {
+              events_by_mac.insert((mac_addr.to_string().as_str(), time, seq), ())?;
+            }
+            seq += 1;
+          }
+          for sighting in &poll.sightings {
+            sightings.insert((time, seq), sighting_line(sighting).as_str())?;
+            sightings_by_mac.insert((sighting.mac_addr.to_string().as_str(), time, seq), ())?;
+            seq += 1;
+          }
+          for (mac_addr, ip) in &poll.addresses {
+            let mac_addr = mac_addr.to_string();
+            let ip = ip.to_string();
+            let key = (mac_addr.as_str(), ip.as_str());
+            let first_seen = addresses.get(key)?.map_or(time, |v| v.value().0);
+            addresses.insert(key, (first_seen, time))?;
+          }
+        }
+        meta.insert("seq", seq)?;
+      }
+      tx.commit()?;
+      Ok(())
+    })();
+
+    written.map_err(|e| self.error("write", e))?;
+    self.committed(sync);
+    Ok(())
+  }
+}
+
+impl Sink for RedbStorage {
+  fn name(&self) -> &str {
+    "redb"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    self.record(report)
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    if !self.unsynced {
+      return Ok(());
+    }
+    // An empty durable commit syncs the commits before it.
+    let synced = (|| -> Result<()> {
+      self.db.begin_write()?.commit()?;
+      Ok(())
+    })();
+    synced.map_err(|e| self.error("sync", e))?;
+    self.committed(true);
+    Ok(())
+  }
+}
+
+impl Storage for RedbStorage {
+  fn events(&self, query: &HistoryQuery) -> Result<Vec<HistoryEvent>> {
+    Ok(self.read(EVENTS, EVENTS_BY_MAC, query)?.events)
+  }
+
+  fn sightings(&self, query: &HistoryQuery) -> Result<Vec<Sighting>> {
+    Ok(self.read(SIGHTINGS, SIGHTINGS_BY_MAC, query)?.sightings)
+  }
+
+  fn addresses(&self, query: &HistoryQuery) -> Result<Vec<AddressRecord>> {
+    let from = query.since.map_or(i64::MIN, unix_secs);
+    let to = query.until.map_or(i64::MAX, unix_secs);
+    let read = || -> Result<Vec<AddressRecord>> {
+      let tx = self.db.begin_read()?;
+      let table = tx.open_table(ADDRESSES)?;
+      let mut addresses = Vec::new();
+      for entry in table.iter()? {
+        let (key, value) = entry?;
+        let ((mac_addr, ip), (first_seen, last_seen)) = (key.value(), value.value());
+        let mac_addr = mac_addr.parse()?;
+        if query.mac_addr.is_some_and(|v| v != mac_addr) || last_seen < from || first_seen >= to {
+          continue;
+        }
+        addresses.push(AddressRecord {
+          mac_addr,
+          ip: ip.parse()?,
+          first_seen: from_unix_secs(first_seen),
+          last_seen: from_unix_secs(last_seen),
+        });
+      }
+      addresses.sort_by_key(|v| std::cmp::Reverse(v.last_seen));
+      Ok(addresses)
+    };
+    read().map_err(|e| self.error("read", e))
+  }
+
+  fn prune(&mut self, retention: &Retention, now: SystemTime) -> Result<PruneStats> {
+    let events_before = retention.events_before(now).map(unix_secs);
+    let raw_polls_before = retention.raw_polls_before(now).map(unix_secs);
+    let sync = self.sync_due();
+    let pruned = (|| -> Result<PruneStats> {
+      let mut tx = self.db.begin_write()?;
+      if !sync {
+        tx.set_durability(Durability::None)?;
+      }
+      let mut stats = PruneStats::default();
+      // Freed pages are reused by later polls, so the file stops growing.
+      if let Some(before) = events_before {
+        stats.events = remove_before(&tx, EVENTS, EVENTS_BY_MAC, before)?;
+        let mut addresses = tx.open_table(ADDRESSES)?;
+        addresses.retain(|_, (_, last_seen)| {
+          let keep = last_seen >= before;
+          stats.addresses += usize::from(!keep);
+          keep
+        })?;
+      }
+      if let Some(before) = raw_polls_before {
+        stats.sightings = remove_before(&tx, SIGHTINGS, SIGHTINGS_BY_MAC, before)?;
+      }
+      tx.commit()?;
+      Ok(stats)
+    })();
+
+    let stats = pruned.map_err(|e| self.error("prune", e))?;
+    self.committed(sync);
+    Ok(stats)
+  }
+}
@@ -0,0 +1,341 @@
+use super::{
+  format_state, from_unix_secs, history_events, parse_state, sightings, unix_secs, AddressRecord,
+  EventKind, HistoryEvent, HistoryQuery, PruneStats, Retention, Sighting, Storage,
+};
+use crate::daemon::{PollReport, Sink};
+use crate::neighbors::{MacAddr, ScopedIpAddr};
+use anyhow::{Error, Result};
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How long the in-memory history is kept when none is configured.
+pub const DEFAULT_MEMORY_KEEP: Duration = Duration::from_secs(6 * 3600);
+
+/// Events, and sightings, kept in memory when no limit is configured.
+pub const DEFAULT_MEMORY_MAX_RECORDS: usize = 10_000;
+
+#[derive(Debug)]
+struct MemoryHistory {
+  keep: Duration,
+  max_records: usize,
+  events: VecDeque<HistoryEvent>,
+  sightings: VecDeque<Sighting>,
+  addresses: HashMap<(MacAddr, ScopedIpAddr), AddressRecord>,
+}
+
+impl MemoryHistory {
+  /// Drops what's older than the retention, then the oldest past the limits.
+  fn trim(&mut self, now: SystemTime) {
+    if let Some(before) = now.checked_sub(self.keep) {
+      self.remove_before(Some(before), Some(before));
+    }
+    while self.events.len() > self.max_records {
+      self.events.pop_front();
+    }
+    while self.sightings.len() > self.max_records {
+      self.sightings.pop_front();
+    }
+  }
+
+  fn remove_before(
+    &mut self,
+    events_before: Option<SystemTime>,
+    sightings_before: Option<SystemTime>,
+  ) -> PruneStats {
+    let mut stats = PruneStats::default();
+    if let Some(before) = events_before {
+      while self.events.front().is_some_and(|v| v.time < before) {
+        self.events.pop_front();
+        stats.events += 1;
+      }
+      let addresses = self.addresses.len();
+      self.addresses.retain(|_, v| v.last_seen >= before);
+      stats.addresses = addresses - self.addresses.len();
+    }
+    if let Some(before) = sightings_before {
+      while self.sightings.front().is_some_and(|v| v.time < before) {
+        self.sightings.pop_front();
+        stats.sightings += 1;
+      }
+    }
+    stats
+  }
+}
+
+/// Whether a record of a device over a time range matches a query.
+fn matches(
+  query: &HistoryQuery,
+  mac_addr: Option<MacAddr>,
+  from: SystemTime,
+  to: SystemTime,
+) -> bool {
+  query.mac_addr.is_none_or(|v| mac_addr == Some(v))
+    && query.since.is_none_or(|v| to >= v)
+    && query.until.is_none_or(|v| from < v)
+}
+
+///
+/// Storage backend keeping the last hours of history in memory, bounded both
+/// in time and in records, so recent history is at hand without a database.
+/// Nothing survives a restart. Cloning the storage shares its history, e.g.
+/// to publish polls to it as a sink while the control socket reads it back.
+///
+/// ```
+/// use openwrt_netmon::storage::{HistoryQuery, MemoryStorage, Storage};
+/// use std::time::Duration;
+///
+/// let storage = MemoryStorage::new(Duration::from_secs(3600), 1000);
+/// assert!(storage.events(&HistoryQuery::new())?.is_empty());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct MemoryStorage {
+  inner: Arc<Mutex<MemoryHistory>>,
+}
+
+impl Default for MemoryStorage {
+  fn default() -> Self {
+    MemoryStorage::new(DEFAULT_MEMORY_KEEP, DEFAULT_MEMORY_MAX_RECORDS)
+  }
+}
+
+impl MemoryStorage {
+  ///
+  /// Creates an empty history.
+  ///
+  /// Args:
+  ///  - keep: How long events and sightings are kept.
+  ///  - max_records: Most events, and most sightings, kept at once; the
+  ///    oldest are dropped first.
+  ///
+  pub fn new(keep: Duration, max_records: usize) -> Self {
+    MemoryStorage {
+      inner: Arc::new(Mutex::new(MemoryHistory {
+        keep,
+        max_records,
+        events: VecDeque::new(),
+        sightings: VecDeque::new(),
+        addresses: HashMap::new(),
+      })),
+    }
+  }
+
+  /// The events and sightings matching a query, oldest first.
+  pub fn recent(&self, query: &HistoryQuery) -> RecentHistory {
+    let history = self.inner.lock().unwrap();
+    RecentHistory {
+      events: history
+        .events
+        .iter()
+        .filter(|v| matches(query, v.mac_addr, v.time, v.time))
+        .cloned()
+        .collect(),
+      sightings: history
+        .sightings
+        .iter()
+        .filter(|v| matches(query, Some(v.mac_addr), v.time, v.time))
+        .cloned()
+        .collect(),
+    }
+  }
+}
+
+impl Sink for MemoryStorage {
+  fn name(&self) -> &str {
+    "memory"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let time = report.snapshot.taken_at;
+    let mut history = self.inner.lock().unwrap();
+    history.events.extend(history_events(report));
+    history.sightings.extend(sightings(report));
+    for device in &report.devices {
+      for ip in device.ips() {
+        history
+          .addresses
+          .entry((device.mac_addr, ip.clone()))
+          .and_modify(|v| v.last_seen = time)
+          .or_insert_with(|| AddressRecord {
+            mac_addr: device.mac_addr,
+            ip: ip.clone(),
+            first_seen: time,
+            last_seen: time,
+          });
+      }
+    }
+    history.trim(time);
+    Ok(())
+  }
+}
+
+impl Storage for MemoryStorage {
+  fn events(&self, query: &HistoryQuery) -> Result<Vec<HistoryEvent>> {
+    Ok(self.recent(query).events)
+  }
+
+  fn sightings(&self, query: &HistoryQuery) -> Result<Vec<Sighting>> {
+    Ok(self.recent(query).sightings)
+  }
+
+  fn addresses(&self, query: &HistoryQuery) -> Result<Vec<AddressRecord>> {
+    let history = self.inner.lock().unwrap();
+    let mut addresses: Vec<AddressRecord> = history
+      .addresses
+      .values()
+      .filter(|v| matches(query, Some(v.mac_addr), v.first_seen, v.last_seen))
+      .cloned()
+      .collect();
+    addresses.sort_by_key(|v| Reverse(v.last_seen));
+    Ok(addresses)
+  }
+
+  fn prune(&mut self, retention: &Retention, now: SystemTime) -> Result<PruneStats> {
+    let mut history = self.inner.lock().unwrap();
+    Ok(history.remove_before(
+      retention.events_before(now),
+      retention.raw_polls_before(now),
+    ))
+  }
+}
+
+/*
+  Recent history is sent over the control socket and the health listener as
+  tab separated lines, with empty fields for missing values:
+
+    event     <time>  <kind>   <mac>  <ip>     <iface>  <old_value>  <new_value>
+    sighting  <time>  <mac>    <iface>  <state>  <online>  <signal>  <rx_bytes>  <tx_bytes>
+
+  Times are seconds since the epoch, states and values are as stored by the
+  sqlite sink, and online is 0 or 1.
+*/
+
+/// Events and sightings of the recent history, e.g. of a single device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecentHistory {
+  pub events: Vec<HistoryEvent>,
+  pub sightings: Vec<Sighting>,
+}
+
+/// Field of a line, empty if None.
+fn field<T: ToString>(value: &Option<T>) -> String {
+  value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// An event as a line, without its newline.
+pub(super) fn event_line(event: &HistoryEvent) -> String {
+  format!(
+    "event\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+    unix_secs(event.time),
+    event.kind.name(),
+    field(&event.mac_addr),
+    field(&event.ip),
+    field(&event.iface),
+    field(&event.old_value),
+    field(&event.new_value)
+  )
+}
+
+/// A sighting as a line, without its newline.
+pub(super) fn sighting_line(sighting: &Sighting) -> String {
+  format!(
+    "sighting\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+    unix_secs(sighting.time),
+    sighting.mac_addr,
+    sighting.iface,
+    format_state(sighting.nud_state),
+    sighting.online as u8,
+    field(&sighting.signal_dbm),
+    field(&sighting.rx_bytes),
+    field(&sighting.tx_bytes)
+  )
+}
+
+impl fmt::Display for RecentHistory {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for event in &self.events {
+      writeln!(f, "{}", event_line(event))?;
+    }
+    for sighting in &self.sightings {
+      writeln!(f, "{}", sighting_line(sighting))?;
+    }
+    Ok(())
+  }
+}
+
+/// Parses an optional field, None if empty.
+fn parse_field<T: FromStr>(value: &str) -> Result<Option<T>>
+where
+  T::Err: fmt::Display,
+{
+  match value.is_empty() {
+    true => Ok(None),
+    false => value
+      .parse()
+      .map(Some)
+      .map_err(|e| Error::msg(format!("Invalid field '{}': {}", value, e))),
+  }
+}
+
+/// Parses a field that can't be empty.
+fn parse_required<T: FromStr>(value: &str) -> Result<T>
+where
+  T::Err: fmt::Display,
+{
+  parse_field(value)?.ok_or_else(|| Error::msg("Missing a required field"))
+}
+
+/// Optional text field, None if empty.
+fn text_field(value: &str) -> Option<String> {
+  (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Parses a single line, adding its record to the history.
+pub(super) fn parse_line(line: &str, history: &mut RecentHistory) -> Result<()> {
+  let fields: Vec<&str> = line.split('\t').collect();
+  match fields[..] {
+    ["event", time, kind, mac_addr, ip, iface, old_value, new_value] => {
+      history.events.push(HistoryEvent {
+        time: from_unix_secs(parse_required(time)?),
+        kind: EventKind::from_str(kind)?,
+        mac_addr: parse_field(mac_addr)?,
+        ip: parse_field(ip)?,
+        iface: text_field(iface),
+        old_value: text_field(old_value),
+        new_value: text_field(new_value),
+      });
+    }
+    ["sighting", time, mac_addr, iface, state, online, signal_dbm, rx_bytes, tx_bytes] => {
+      history.sightings.push(Sighting {
+        time: from_unix_secs(parse_required(time)?),
+        mac_addr: parse_required(mac_addr)?,
+        iface: iface.to_string(),
+        nud_state: parse_state(state),
+        online: online == "1",
+        signal_dbm: parse_field(signal_dbm)?,
+        rx_bytes: parse_field(rx_bytes)?,
+        tx_bytes: parse_field(tx_bytes)?,
+      });
+    }
+    _ => return Err(Error::msg("Unknown record")),
+  }
+  Ok(())
+}
+
+impl FromStr for RecentHistory {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let mut history = RecentHistory::default();
+    for line in s.lines().filter(|v| !v.is_empty()) {
+      parse_line(line, &mut history)
+        .map_err(|e| Error::msg(format!("Invalid history line '{}': {}", line, e)))?;
+    }
+    Ok(history)
+  }
+}
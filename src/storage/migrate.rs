@@ -0,0 +1,45 @@
+use anyhow::{Error, Result};
+use log::info;
+use std::ops::RangeInclusive;
+
+/*
+  Every backend embeds its migrations in order, the first creating the
+  tables, and records the version of its schema alongside the history:
+
+    sqlite    PRAGMA user_version
+    redb      meta table, "schema_version" key
+    postgres  schema_version table, a single row
+
+  A database without a version predates the migrations; the first one
+  creates what's missing, so it's migrated like a new one. Migrations are
+  only ever appended: a release changing the schema adds one, never edits a
+  released one.
+*/
+
+///
+/// Plans the migrations bringing a database up to date, refusing databases
+/// written by a newer release, which this one would corrupt.
+///
+/// Args:
+///  - database: Database being opened, for the messages, e.g. its path.
+///  - version: Version of the database's schema, 0 if it has none.
+///  - latest: Version the migrations bring the schema to.
+///
+/// Returns:
+///  Result of the versions to migrate to, in order, empty if up to date.
+///
+pub fn plan(database: &str, version: u32, latest: u32) -> Result<RangeInclusive<u32>> {
+  if version > latest {
+    return Err(Error::msg(format!(
+      "{} has schema version {}, newer than the {} this netmon supports; upgrade netmon, or restore a backup written by this version",
+      database, version, latest
+    )));
+  }
+  if version < latest {
+    info!(
+      "Migrating {} from schema version {} to {}",
+      database, version, latest
+    );
+  }
+  Ok(version + 1..=latest)
+}
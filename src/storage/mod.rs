@@ -0,0 +1,446 @@
+use crate::daemon::PollReport;
+use crate::neighbors::{MacAddr, NudState, ScopedIpAddr};
+use anyhow::{Error, Result};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "redb")]
+pub mod kv;
+pub mod memory;
+pub mod migrate;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod retention;
+pub mod spill;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod writebehind;
+
+#[cfg(feature = "redb")]
+pub use kv::RedbStorage;
+pub use memory::{MemoryStorage, RecentHistory};
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresSink;
+pub use retention::{PruneStats, Pruner, Retention};
+pub use spill::SpillQueue;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+pub use writebehind::WriteBehind;
+
+/// SQLite database written when none is configured, on the overlay so it
+/// survives reboots.
+pub const DEFAULT_SQLITE_PATH: &str = "/etc/netmon/history.db";
+
+/// Kind of change recorded in the history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+  Joined,
+  Left,
+  IpChanged,
+  MacChanged,
+  StateChanged,
+}
+
+impl EventKind {
+  pub fn name(self) -> &'static str {
+    match self {
+      EventKind::Joined => "joined",
+      EventKind::Left => "left",
+      EventKind::IpChanged => "ip_changed",
+      EventKind::MacChanged => "mac_changed",
+      EventKind::StateChanged => "state_changed",
+    }
+  }
+}
+
+impl FromStr for EventKind {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "joined" => Ok(EventKind::Joined),
+      "left" => Ok(EventKind::Left),
+      "ip_changed" => Ok(EventKind::IpChanged),
+      "mac_changed" => Ok(EventKind::MacChanged),
+      "state_changed" => Ok(EventKind::StateChanged),
+      _ => Err(Error::msg(format!("Unknown event kind '{}'", s))),
+    }
+  }
+}
+
+/*
+  Each change of a poll maps onto a single event:
+
+    kind           mac      ip        iface  old_value           new_value
+    joined         device   -         iface  -                   ips
+    left           device   -         iface  ips                 -
+    ip_changed     device   -         -      removed ips         added ips
+    mac_changed    new mac  address   -      old mac             new mac
+    state_changed  device   address   -      old state           new state
+
+  Lists of addresses are comma separated, and states are NUD names joined
+  with '|', e.g. "REACHABLE" or "STALE|PERMANENT".
+*/
+
+/// A change of a device, as recorded in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEvent {
+  pub time: SystemTime,
+  pub kind: EventKind,
+  pub mac_addr: Option<MacAddr>,
+  pub ip: Option<ScopedIpAddr>,
+  pub iface: Option<String>,
+  pub old_value: Option<String>,
+  pub new_value: Option<String>,
+}
+
+/// A device seen by a single poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sighting {
+  pub time: SystemTime,
+  pub mac_addr: MacAddr,
+  pub iface: String,
+  pub nud_state: NudState,
+  pub online: bool,
+  /// Signal strength of a wireless station, in dBm.
+  pub signal_dbm: Option<i32>,
+  /// Bytes a wireless station received since it associated.
+  pub rx_bytes: Option<u64>,
+  /// Bytes a wireless station sent since it associated.
+  pub tx_bytes: Option<u64>,
+}
+
+/// Resolution the sightings are rolled up to as they age.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+  FiveMinutes,
+  Hourly,
+}
+
+impl Resolution {
+  /// Length of each bucket.
+  pub fn duration(self) -> Duration {
+    match self {
+      Resolution::FiveMinutes => Duration::from_secs(300),
+      Resolution::Hourly => Duration::from_secs(3600),
+    }
+  }
+}
+
+/// Sightings of a device over a bucket of time, rolled up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceSample {
+  /// Start of the bucket.
+  pub time: SystemTime,
+  pub resolution: Resolution,
+  pub mac_addr: MacAddr,
+  /// Polls that saw the device.
+  pub samples: u64,
+  /// Polls that saw the device online.
+  pub online_samples: u64,
+  pub signal_min_dbm: Option<i32>,
+  pub signal_avg_dbm: Option<f64>,
+  pub signal_max_dbm: Option<i32>,
+  /// Bytes a wireless station received during the bucket.
+  pub rx_bytes: Option<u64>,
+  /// Bytes a wireless station sent during the bucket.
+  pub tx_bytes: Option<u64>,
+}
+
+/// An address a device used, and when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressRecord {
+  pub mac_addr: MacAddr,
+  pub ip: ScopedIpAddr,
+  pub first_seen: SystemTime,
+  pub last_seen: SystemTime,
+}
+
+/// What a single poll adds to the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollRecords {
+  pub time: SystemTime,
+  pub events: Vec<HistoryEvent>,
+  pub sightings: Vec<Sighting>,
+  /// Addresses the poll's devices answered on.
+  pub addresses: Vec<(MacAddr, ScopedIpAddr)>,
+}
+
+impl PollRecords {
+  /// The records of a poll.
+  pub fn new(report: &PollReport) -> Self {
+    PollRecords {
+      time: report.snapshot.taken_at,
+      events: history_events(report),
+      sightings: sightings(report),
+      addresses: report
+        .devices
+        .iter()
+        .flat_map(|device| device.ips().map(|ip| (device.mac_addr, ip.clone())))
+        .collect(),
+    }
+  }
+
+  /// Events, sightings, and addresses recorded.
+  pub fn len(&self) -> usize {
+    self.events.len() + self.sightings.len() + self.addresses.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+/// Which part of the history to read, every device and all time by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryQuery {
+  pub mac_addr: Option<MacAddr>,
+  pub since: Option<SystemTime>,
+  pub until: Option<SystemTime>,
+}
+
+impl HistoryQuery {
+  pub fn new() -> Self {
+    HistoryQuery::default()
+  }
+
+  /// Keeps to a single device.
+  pub fn mac_addr(mut self, mac_addr: MacAddr) -> Self {
+    self.mac_addr = Some(mac_addr);
+    self
+  }
+
+  /// Keeps to what happened at or after the given time.
+  pub fn since(mut self, since: SystemTime) -> Self {
+    self.since = Some(since);
+    self
+  }
+
+  /// Keeps to what happened before the given time.
+  pub fn until(mut self, until: SystemTime) -> Self {
+    self.until = Some(until);
+    self
+  }
+}
+
+///
+/// Device history recorded across polls, and restarts, by a storage backend.
+/// Backends record the polls as sinks; this reads them back.
+///
+pub trait Storage {
+  ///
+  /// Reads the changes matching a query.
+  ///
+  /// Args:
+  ///  - query: Device and time range to read.
+  ///
+  /// Returns:
+  ///  Result of the events, oldest first.
+  ///
+  fn events(&self, query: &HistoryQuery) -> Result<Vec<HistoryEvent>>;
+
+  ///
+  /// Reads the polls' sightings of devices matching a query.
+  ///
+  /// Args:
+  ///  - query: Device and time range to read.
+  ///
+  /// Returns:
+  ///  Result of the sightings, oldest first.
+  ///
+  fn sightings(&self, query: &HistoryQuery) -> Result<Vec<Sighting>>;
+
+  ///
+  /// Reads the addresses used by devices matching a query, during its time
+  /// range.
+  ///
+  /// Args:
+  ///  - query: Device and time range to read.
+  ///
+  /// Returns:
+  ///  Result of the addresses, the most recently seen first.
+  ///
+  fn addresses(&self, query: &HistoryQuery) -> Result<Vec<AddressRecord>>;
+
+  ///
+  /// Removes the history past its retention.
+  ///
+  /// Args:
+  ///  - retention: How long each part of the history is kept.
+  ///  - now: Time the retention is counted back from.
+  ///
+  /// Returns:
+  ///  Result of what was removed.
+  ///
+  fn prune(&mut self, retention: &Retention, now: SystemTime) -> Result<PruneStats>;
+
+  ///
+  /// Rolls the sightings up into 5-minute buckets, and those into hourly
+  /// ones, as far as the buckets are complete. Backends that keep no
+  /// rollups don't roll anything up.
+  ///
+  /// Args:
+  ///  - now: Time up to which buckets are complete.
+  ///
+  /// Returns:
+  ///  Result of the number of buckets written.
+  ///
+  fn downsample(&mut self, _now: SystemTime) -> Result<usize> {
+    Ok(0)
+  }
+
+  ///
+  /// Reads the rolled up sightings of devices matching a query.
+  ///
+  /// Args:
+  ///  - query: Device and time range to read.
+  ///  - resolution: Buckets to read.
+  ///
+  /// Returns:
+  ///  Result of the samples, oldest first, failing if the backend keeps no
+  ///  rollups.
+  ///
+  fn presence(
+    &self,
+    _query: &HistoryQuery,
+    _resolution: Resolution,
+  ) -> Result<Vec<PresenceSample>> {
+    Err(Error::msg("This storage keeps no rolled up history"))
+  }
+}
+
+///
+/// Backends writing several polls at once, in a single transaction, so their
+/// writes can be batched (see WriteBehind).
+///
+pub trait PollWriter {
+  ///
+  /// Records polls in a single transaction, all of them or none.
+  ///
+  /// Args:
+  ///  - polls: Records of the polls, oldest first.
+  ///
+  fn write_polls(&mut self, polls: &[PollRecords]) -> Result<()>;
+}
+
+/// Seconds since the epoch, as stored by the backends.
+pub fn unix_secs(time: SystemTime) -> i64 {
+  match time.duration_since(UNIX_EPOCH) {
+    Ok(v) => v.as_secs() as i64,
+    Err(e) => -(e.duration().as_secs() as i64),
+  }
+}
+
+/// Time of a number of seconds since the epoch.
+pub fn from_unix_secs(secs: i64) -> SystemTime {
+  match secs >= 0 {
+    true => UNIX_EPOCH + Duration::from_secs(secs as u64),
+    false => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+  }
+}
+
+/// Comma separated addresses, None if empty.
+fn join_ips(ips: &[ScopedIpAddr]) -> Option<String> {
+  let ips: Vec<String> = ips.iter().map(|v| v.to_string()).collect();
+  (!ips.is_empty()).then(|| ips.join(","))
+}
+
+/// NUD state names joined with '|'.
+pub fn format_state(nud_state: NudState) -> String {
+  let names: Vec<&str> = nud_state.iter_names().map(|(name, _)| name).collect();
+  names.join("|")
+}
+
+/// Parses NUD state names joined with '|', skipping unknown ones.
+pub fn parse_state(names: &str) -> NudState {
+  names
+    .split('|')
+    .filter_map(NudState::from_name)
+    .fold(NudState::empty(), |acc, v| acc | v)
+}
+
+///
+/// Turns the changes of a poll into history events.
+///
+/// Args:
+///  - report: Poll to record.
+///
+/// Returns:
+///  The events, all at the time of the poll.
+///
+pub fn history_events(report: &PollReport) -> Vec<HistoryEvent> {
+  let time = report.snapshot.taken_at;
+  let event = |kind| HistoryEvent {
+    time,
+    kind,
+    mac_addr: None,
+    ip: None,
+    iface: None,
+    old_value: None,
+    new_value: None,
+  };
+
+  let diff = &report.diff;
+  let mut events = Vec::new();
+  for device in &diff.joined {
+    events.push(HistoryEvent {
+      mac_addr: Some(device.mac_addr),
+      iface: Some(device.iface.clone()),
+      new_value: join_ips(&device.ips),
+      ..event(EventKind::Joined)
+    });
+  }
+  for device in &diff.left {
+    events.push(HistoryEvent {
+      mac_addr: Some(device.mac_addr),
+      iface: Some(device.iface.clone()),
+      old_value: join_ips(&device.ips),
+      ..event(EventKind::Left)
+    });
+  }
+  for change in &diff.ip_changed {
+    events.push(HistoryEvent {
+      mac_addr: Some(change.mac_addr),
+      old_value: join_ips(&change.removed),
+      new_value: join_ips(&change.added),
+      ..event(EventKind::IpChanged)
+    });
+  }
+  for change in &diff.mac_changed {
+    events.push(HistoryEvent {
+      mac_addr: change.new_mac_addr,
+      ip: Some(change.ip.clone()),
+      old_value: change.old_mac_addr.map(|v| v.to_string()),
+      new_value: change.new_mac_addr.map(|v| v.to_string()),
+      ..event(EventKind::MacChanged)
+    });
+  }
+  for change in &diff.state_changed {
+    events.push(HistoryEvent {
+      mac_addr: change.mac_addr,
+      ip: Some(change.ip.clone()),
+      old_value: Some(format_state(change.old_state)),
+      new_value: Some(format_state(change.new_state)),
+      ..event(EventKind::StateChanged)
+    });
+  }
+  events
+}
+
+/// The devices seen by a poll.
+pub fn sightings(report: &PollReport) -> Vec<Sighting> {
+  report
+    .devices
+    .iter()
+    .map(|device| Sighting {
+      time: report.snapshot.taken_at,
+      mac_addr: device.mac_addr,
+      iface: device.iface.clone(),
+      nud_state: device.nud_state,
+      online: device.online,
+      signal_dbm: device.station.as_ref().and_then(|v| v.signal_dbm),
+      rx_bytes: device.station.as_ref().and_then(|v| v.rx_bytes),
+      tx_bytes: device.station.as_ref().and_then(|v| v.tx_bytes),
+    })
+    .collect()
+}
@@ -0,0 +1,175 @@
+use super::{Resolution, Storage};
+use crate::daemon::{PollReport, Sink};
+use anyhow::Result;
+use log::{debug, info, warn};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long events and addresses are kept when no retention is configured.
+pub const DEFAULT_KEEP_EVENTS: Duration = Duration::from_secs(90 * 24 * 3600);
+
+/// How long the polls' sightings are kept when no retention is configured.
+pub const DEFAULT_KEEP_RAW_POLLS: Duration = Duration::from_secs(24 * 3600);
+
+/// How long 5-minute rollups are kept when no retention is configured.
+pub const DEFAULT_KEEP_5M: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// How long hourly rollups are kept when no retention is configured.
+pub const DEFAULT_KEEP_HOURLY: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// How often the history is pruned when no interval is configured.
+pub const DEFAULT_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How long each part of the history is kept, forever if None.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+  /// Changes, and the addresses devices used.
+  pub keep_events: Option<Duration>,
+  /// Every poll's sightings, by far the largest part.
+  pub keep_raw_polls: Option<Duration>,
+  /// Sightings rolled up into 5-minute buckets.
+  pub keep_5m: Option<Duration>,
+  /// Sightings rolled up into hourly buckets.
+  pub keep_hourly: Option<Duration>,
+}
+
+impl Default for Retention {
+  fn default() -> Self {
+    Retention {
+      keep_events: Some(DEFAULT_KEEP_EVENTS),
+      keep_raw_polls: Some(DEFAULT_KEEP_RAW_POLLS),
+      keep_5m: Some(DEFAULT_KEEP_5M),
+      keep_hourly: Some(DEFAULT_KEEP_HOURLY),
+    }
+  }
+}
+
+impl Retention {
+  /// Time before which events are removed, as of now.
+  pub fn events_before(&self, now: SystemTime) -> Option<SystemTime> {
+    self.keep_events.and_then(|v| now.checked_sub(v))
+  }
+
+  /// Time before which sightings are removed, as of now.
+  pub fn raw_polls_before(&self, now: SystemTime) -> Option<SystemTime> {
+    self.keep_raw_polls.and_then(|v| now.checked_sub(v))
+  }
+
+  /// Time before which rollups of a resolution are removed, as of now.
+  pub fn rollups_before(&self, resolution: Resolution, now: SystemTime) -> Option<SystemTime> {
+    let keep = match resolution {
+      Resolution::FiveMinutes => self.keep_5m,
+      Resolution::Hourly => self.keep_hourly,
+    };
+    keep.and_then(|v| now.checked_sub(v))
+  }
+}
+
+/// What a single pruning removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneStats {
+  pub events: usize,
+  pub sightings: usize,
+  pub addresses: usize,
+  pub rollups: usize,
+}
+
+impl PruneStats {
+  pub fn total(&self) -> usize {
+    self.events + self.sightings + self.addresses + self.rollups
+  }
+}
+
+///
+/// Wraps a storage backend's sink, pruning its history past the retention
+/// on the sink's own thread, so the database doesn't grow unbounded on
+/// flash. The history is pruned with the first poll, catching up on what
+/// expired while the daemon was down, then once every interval; the
+/// sightings are rolled up right before, so they're kept at a coarser
+/// resolution once the raw polls expire.
+///
+pub struct Pruner<S> {
+  storage: S,
+  retention: Retention,
+  interval: Duration,
+  next_prune: Option<Instant>,
+}
+
+impl<S: Sink + Storage> Pruner<S> {
+  pub fn new(storage: S, retention: Retention) -> Self {
+    Pruner {
+      storage,
+      retention,
+      interval: DEFAULT_PRUNE_INTERVAL,
+      next_prune: None,
+    }
+  }
+
+  /// How often the history is pruned.
+  pub fn interval(mut self, interval: Duration) -> Self {
+    self.interval = interval;
+    self
+  }
+
+  /// The wrapped storage, e.g. to read the history back.
+  pub fn storage(&self) -> &S {
+    &self.storage
+  }
+
+  /// Prunes the history if it's due.
+  fn prune_if_due(&mut self) {
+    let now = Instant::now();
+    if self.next_prune.is_some_and(|v| now < v) {
+      return;
+    }
+    self.next_prune = Some(now + self.interval);
+
+    let started_at = Instant::now();
+    let now = SystemTime::now();
+    match self.storage.downsample(now) {
+      Ok(0) => {}
+      Ok(buckets) => debug!(
+        "Rolled up {} bucket(s) of the {} history",
+        buckets,
+        self.storage.name()
+      ),
+      Err(err) => warn!(
+        "Failed to roll up the {} history: {}",
+        self.storage.name(),
+        err
+      ),
+    }
+    match self.storage.prune(&self.retention, now) {
+      Ok(stats) if stats.total() > 0 => info!(
+        "Pruned {} event(s), {} sighting(s), {} rollup(s), and {} address(es) from the {} history in {}ms",
+        stats.events,
+        stats.sightings,
+        stats.rollups,
+        stats.addresses,
+        self.storage.name(),
+        started_at.elapsed().as_millis()
+      ),
+      Ok(_) => debug!("Nothing to prune from the {} history", self.storage.name()),
+      Err(err) => warn!(
+        "Failed to prune the {} history: {}",
+        self.storage.name(),
+        err
+      ),
+    }
+  }
+}
+
+impl<S: Sink + Storage> Sink for Pruner<S> {
+  fn name(&self) -> &str {
+    self.storage.name()
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    let result = self.storage.publish(report);
+    self.prune_if_due();
+    result
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    self.storage.flush()
+  }
+}
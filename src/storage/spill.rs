@@ -0,0 +1,285 @@
+use super::{
+  format_state, from_unix_secs, parse_state, unix_secs, EventKind, HistoryEvent, PollRecords,
+  Sighting,
+};
+use crate::neighbors::{MacAddr, ScopedIpAddr};
+use anyhow::{Error, Result};
+use log::warn;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Largest spill queue when none is configured.
+pub const DEFAULT_MAX_SPILL: u64 = 1024 * 1024;
+
+/*
+  The spill queue is a tab separated file of the polls, each starting with
+  its time, followed by its events, sightings, and addresses, with empty
+  fields for what's unknown:
+
+    poll	1791953775
+    event	joined	dc:a6:32:a3:48:b1		br-lan		192.168.0.5
+    sighting	dc:a6:32:a3:48:b1	br-lan	REACHABLE	1	-52	1024	2048
+    address	dc:a6:32:a3:48:b1	192.168.0.5
+
+  Events and sightings are at their poll's time.
+*/
+
+///
+/// Polls a remote backend couldn't be written to yet, queued on disk so they
+/// survive restarts, and written once the backend is back. Once the queue
+/// reaches its size, newer polls are dropped.
+///
+pub struct SpillQueue {
+  path: PathBuf,
+  max_size: u64,
+  size: u64,
+}
+
+impl SpillQueue {
+  ///
+  /// Opens the queue, keeping the polls a previous run left in it, but for
+  /// a line torn by a crash while appending.
+  ///
+  /// Args:
+  ///  - path: File holding the queue.
+  ///  - max_size: Size in bytes past which polls are dropped.
+  ///
+  pub fn new(path: &Path, max_size: u64) -> Self {
+    let mut size = 0;
+    if let Ok(content) = std::fs::read(path) {
+      size = content
+        .iter()
+        .rposition(|&v| v == b'\n')
+        .map_or(0, |v| v + 1) as u64;
+      if size < content.len() as u64 {
+        warn!("Dropping a torn line at the end of {}", path.display());
+        if let Err(err) = OpenOptions::new()
+          .write(true)
+          .open(path)
+          .and_then(|file| file.set_len(size))
+        {
+          warn!("Failed to truncate {}: {}", path.display(), err);
+        }
+      }
+    }
+    SpillQueue {
+      path: path.to_path_buf(),
+      max_size,
+      size,
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Size of the queue in bytes.
+  pub fn size(&self) -> u64 {
+    self.size
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.size == 0
+  }
+
+  ///
+  /// Appends polls to the queue, creating its directory.
+  ///
+  /// Args:
+  ///  - polls: Records of the polls.
+  ///
+  /// Returns:
+  ///  Result reflecting whether the polls were queued, failing if the queue
+  ///  is full.
+  ///
+  pub fn push(&mut self, polls: &[PollRecords]) -> Result<()> {
+    let mut lines = String::new();
+    for poll in polls {
+      format_poll(poll, &mut lines);
+    }
+    if self.size + lines.len() as u64 > self.max_size {
+      return Err(Error::msg(format!(
+        "The spill queue {} is full, dropping {} poll(s)",
+        self.path.display(),
+        polls.len()
+      )));
+    }
+    if let Some(parent) = self.path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+    OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&self.path)
+      .and_then(|mut file| file.write_all(lines.as_bytes()))
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", self.path.display(), e)))?;
+    self.size += lines.len() as u64;
+    Ok(())
+  }
+
+  ///
+  /// Reads every queued poll, leaving them in the queue.
+  ///
+  /// Returns:
+  ///  Result of the polls, oldest first.
+  ///
+  pub fn read(&self) -> Result<Vec<PollRecords>> {
+    let content = match std::fs::read_to_string(&self.path) {
+      Ok(v) => v,
+      Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(e) => {
+        return Err(Error::msg(format!(
+          "Failed to read {}: {}",
+          self.path.display(),
+          e
+        )))
+      }
+    };
+    parse_polls(&content).map_err(|e| {
+      Error::msg(format!(
+        "Invalid spill queue {}: {}",
+        self.path.display(),
+        e
+      ))
+    })
+  }
+
+  /// Empties the queue, once its polls were written.
+  pub fn clear(&mut self) -> Result<()> {
+    match std::fs::remove_file(&self.path) {
+      Ok(()) => {}
+      Err(e) if e.kind() == ErrorKind::NotFound => {}
+      Err(e) => {
+        return Err(Error::msg(format!(
+          "Failed to remove {}: {}",
+          self.path.display(),
+          e
+        )))
+      }
+    }
+    self.size = 0;
+    Ok(())
+  }
+}
+
+/// Field of an optional value, empty if None.
+fn field<T: ToString>(value: &Option<T>) -> String {
+  value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Appends the lines of a poll.
+fn format_poll(poll: &PollRecords, lines: &mut String) {
+  lines.push_str(&format!("poll\t{}\n", unix_secs(poll.time)));
+  for event in &poll.events {
+    lines.push_str(&format!(
+      "event\t{}\t{}\t{}\t{}\t{}\t{}\n",
+      event.kind.name(),
+      field(&event.mac_addr),
+      field(&event.ip),
+      field(&event.iface),
+      field(&event.old_value),
+      field(&event.new_value)
+    ));
+  }
+  for sighting in &poll.sightings {
+    lines.push_str(&format!(
+      "sighting\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+      sighting.mac_addr,
+      sighting.iface,
+      format_state(sighting.nud_state),
+      sighting.online as u8,
+      field(&sighting.signal_dbm),
+      field(&sighting.rx_bytes),
+      field(&sighting.tx_bytes)
+    ));
+  }
+  for (mac_addr, ip) in &poll.addresses {
+    lines.push_str(&format!("address\t{}\t{}\n", mac_addr, ip));
+  }
+}
+
+/// Parses an optional field, None if empty.
+fn parse_field<T: FromStr>(value: &str) -> Result<Option<T>>
+where
+  T::Err: std::fmt::Display,
+{
+  match value.is_empty() {
+    true => Ok(None),
+    false => value
+      .parse()
+      .map(Some)
+      .map_err(|e| Error::msg(format!("Invalid field '{}': {}", value, e))),
+  }
+}
+
+/// Parses a required field.
+fn parse_required<T: FromStr>(value: &str) -> Result<T>
+where
+  T::Err: std::fmt::Display,
+{
+  parse_field(value)?.ok_or_else(|| Error::msg("Missing field"))
+}
+
+/// Parses the lines of the queue.
+fn parse_polls(content: &str) -> Result<Vec<PollRecords>> {
+  let mut polls: Vec<PollRecords> = Vec::new();
+  for (i, line) in content.lines().enumerate() {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields[0] == "poll" && fields.len() == 2 {
+      let secs =
+        parse_required(fields[1]).map_err(|e| Error::msg(format!("line {}: {}", i + 1, e)))?;
+      polls.push(PollRecords {
+        time: from_unix_secs(secs),
+        events: Vec::new(),
+        sightings: Vec::new(),
+        addresses: Vec::new(),
+      });
+      continue;
+    }
+    let parsed = match (fields[0], polls.last_mut()) {
+      ("event", Some(poll)) if fields.len() == 7 => parse_event(poll, &fields),
+      ("sighting", Some(poll)) if fields.len() == 8 => parse_sighting(poll, &fields),
+      ("address", Some(poll)) if fields.len() == 3 => parse_address(poll, &fields),
+      _ => Err(Error::msg("Unexpected line")),
+    };
+    parsed.map_err(|e| Error::msg(format!("line {}: {}", i + 1, e)))?;
+  }
+  Ok(polls)
+}
+
+fn parse_event(poll: &mut PollRecords, fields: &[&str]) -> Result<()> {
+  poll.events.push(HistoryEvent {
+    time: poll.time,
+    kind: EventKind::from_str(fields[1])?,
+    mac_addr: parse_field(fields[2])?,
+    ip: parse_field(fields[3])?,
+    iface: parse_field(fields[4])?,
+    old_value: parse_field(fields[5])?,
+    new_value: parse_field(fields[6])?,
+  });
+  Ok(())
+}
+
+fn parse_sighting(poll: &mut PollRecords, fields: &[&str]) -> Result<()> {
+  poll.sightings.push(Sighting {
+    time: poll.time,
+    mac_addr: parse_required(fields[1])?,
+    iface: parse_required(fields[2])?,
+    nud_state: parse_state(fields[3]),
+    online: fields[4] == "1",
+    signal_dbm: parse_field(fields[5])?,
+    rx_bytes: parse_field(fields[6])?,
+    tx_bytes: parse_field(fields[7])?,
+  });
+  Ok(())
+}
+
+fn parse_address(poll: &mut PollRecords, fields: &[&str]) -> Result<()> {
+  let mac_addr: MacAddr = parse_required(fields[1])?;
+  let ip: ScopedIpAddr = parse_required(fields[2])?;
+  poll.addresses.push((mac_addr, ip));
+  Ok(())
+}
@@ -0,0 +1,594 @@
+use super::{
+  format_state, from_unix_secs, migrate, parse_state, unix_secs, AddressRecord, HistoryEvent,
+  HistoryQuery, PollRecords, PollWriter, PresenceSample, PruneStats, Resolution, Retention,
+  Sighting, Storage,
+};
+use crate::daemon::{PollReport, Sink};
+use anyhow::{Error, Result};
+use log::debug;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, Row};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/*
+  Times are seconds since the epoch, MAC addresses are lowercase and colon
+  separated, and addresses keep their scope, e.g. "fe80::1%br-lan". See
+  storage::HistoryEvent for what the events' columns hold.
+*/
+const SCHEMA_V1: &str = "
+  CREATE TABLE IF NOT EXISTS sightings (
+    time INTEGER NOT NULL,
+    mac TEXT NOT NULL,
+    iface TEXT NOT NULL,
+    state TEXT NOT NULL,
+    online INTEGER NOT NULL,
+    signal_dbm INTEGER,
+    rx_bytes INTEGER,
+    tx_bytes INTEGER
+  );
+  CREATE INDEX IF NOT EXISTS sightings_mac_time ON sightings (mac, time);
+  CREATE INDEX IF NOT EXISTS sightings_time ON sightings (time);
+
+  CREATE TABLE IF NOT EXISTS events (
+    time INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    mac TEXT,
+    ip TEXT,
+    iface TEXT,
+    old_value TEXT,
+    new_value TEXT
+  );
+  CREATE INDEX IF NOT EXISTS events_mac_time ON events (mac, time);
+  CREATE INDEX IF NOT EXISTS events_time ON events (time);
+
+  CREATE TABLE IF NOT EXISTS addresses (
+    mac TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    PRIMARY KEY (mac, ip)
+  );
+  CREATE INDEX IF NOT EXISTS addresses_last_seen ON addresses (last_seen);
+
+  CREATE TABLE IF NOT EXISTS rollups (
+    resolution INTEGER NOT NULL,
+    bucket INTEGER NOT NULL,
+    mac TEXT NOT NULL,
+    samples INTEGER NOT NULL,
+    online_samples INTEGER NOT NULL,
+    signal_min INTEGER,
+    signal_max INTEGER,
+    signal_sum INTEGER,
+    signal_samples INTEGER NOT NULL,
+    rx_bytes INTEGER,
+    tx_bytes INTEGER,
+    PRIMARY KEY (resolution, bucket, mac)
+  );
+  CREATE INDEX IF NOT EXISTS rollups_mac_bucket ON rollups (mac, resolution, bucket);
+";
+
+/// Migrations of the schema, the first bringing it to version 1 (see
+/// storage::migrate).
+const MIGRATIONS: &[&str] = &[SCHEMA_V1];
+
+/// Columns added to the sightings before the schema was versioned.
+const SIGHTING_COLUMNS: [&str; 3] = ["signal_dbm", "rx_bytes", "tx_bytes"];
+
+/*
+  Rollups sum up what's needed to merge buckets further: 5-minute buckets
+  are rolled up from the sightings, and hourly ones from the 5-minute
+  buckets. The traffic of a bucket is how much the station's counters grew
+  since the device's previous sighting, up to an hour earlier; counters
+  going back mean the station associated again, and counted from zero.
+*/
+const ROLLUP_5M: &str = "
+  INSERT OR REPLACE INTO rollups (resolution, bucket, mac, samples, online_samples, signal_min,
+    signal_max, signal_sum, signal_samples, rx_bytes, tx_bytes)
+  SELECT 300, time - time % 300, mac, COUNT(*), SUM(online), MIN(signal_dbm), MAX(signal_dbm),
+    SUM(signal_dbm), COUNT(signal_dbm),
+    SUM(CASE WHEN rx_bytes < prev_rx THEN rx_bytes ELSE rx_bytes - prev_rx END),
+    SUM(CASE WHEN tx_bytes < prev_tx THEN tx_bytes ELSE tx_bytes - prev_tx END)
+  FROM (
+    SELECT *,
+      LAG(rx_bytes) OVER (PARTITION BY mac ORDER BY time, rowid) AS prev_rx,
+      LAG(tx_bytes) OVER (PARTITION BY mac ORDER BY time, rowid) AS prev_tx
+    FROM sightings WHERE time >= ?1 - 3600 AND time < ?2
+  )
+  WHERE time >= ?1
+  GROUP BY time - time % 300, mac
+";
+
+const ROLLUP_HOURLY: &str = "
+  INSERT OR REPLACE INTO rollups (resolution, bucket, mac, samples, online_samples, signal_min,
+    signal_max, signal_sum, signal_samples, rx_bytes, tx_bytes)
+  SELECT 3600, bucket - bucket % 3600, mac, SUM(samples), SUM(online_samples), MIN(signal_min),
+    MAX(signal_max), SUM(signal_sum), SUM(signal_samples), SUM(rx_bytes), SUM(tx_bytes)
+  FROM rollups WHERE resolution = 300 AND bucket >= ?1 AND bucket < ?2
+  GROUP BY bucket - bucket % 3600, mac
+";
+
+/// Conditions of a query on the given columns, along with their parameters.
+fn where_clause(query: &HistoryQuery, from: &str, to: &str) -> (String, Vec<Value>) {
+  let mut conditions = Vec::new();
+  let mut values = Vec::new();
+  if let Some(mac_addr) = &query.mac_addr {
+    conditions.push("mac = ?".to_string());
+    values.push(Value::Text(mac_addr.to_string()));
+  }
+  if let Some(since) = query.since {
+    conditions.push(format!("{} >= ?", to));
+    values.push(Value::Integer(unix_secs(since)));
+  }
+  if let Some(until) = query.until {
+    conditions.push(format!("{} < ?", from));
+    values.push(Value::Integer(unix_secs(until)));
+  }
+
+  match conditions.is_empty() {
+    true => (String::new(), values),
+    false => (format!("WHERE {}", conditions.join(" AND ")), values),
+  }
+}
+
+/// Adds the sightings' newer columns to unversioned databases created
+/// before them.
+fn add_sighting_columns(conn: &Connection) -> rusqlite::Result<()> {
+  let mut statement = conn.prepare("SELECT name FROM pragma_table_info('sightings')")?;
+  let columns = statement
+    .query_map([], |row| row.get::<_, String>(0))?
+    .collect::<rusqlite::Result<Vec<String>>>()?;
+  for column in SIGHTING_COLUMNS {
+    if !columns.iter().any(|v| v == column) {
+      conn.execute_batch(&format!(
+        "ALTER TABLE sightings ADD COLUMN {} INTEGER",
+        column
+      ))?;
+    }
+  }
+  Ok(())
+}
+
+/// Brings the schema of a database up to date, in a single transaction.
+fn migrate(conn: &mut Connection, path: &Path) -> Result<()> {
+  let version: u32 = conn
+    .query_row("PRAGMA user_version", [], |row| row.get(0))
+    .map_err(|e| {
+      Error::msg(format!(
+        "Failed to read the schema of {}: {}",
+        path.display(),
+        e
+      ))
+    })?;
+  let versions = migrate::plan(
+    &path.display().to_string(),
+    version,
+    MIGRATIONS.len() as u32,
+  )?;
+  if versions.is_empty() {
+    return Ok(());
+  }
+  let latest = *versions.end();
+  let migrated = (|| -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    for version in versions {
+      tx.execute_batch(MIGRATIONS[version as usize - 1])?;
+      if version == 1 {
+        add_sighting_columns(&tx)?;
+      }
+    }
+    tx.execute_batch(&format!("PRAGMA user_version = {}", latest))?;
+    tx.commit()
+  })();
+  migrated.map_err(|e| Error::msg(format!("Failed to migrate {}: {}", path.display(), e)))
+}
+
+/// Start of the bucket holding a time, in seconds since the epoch.
+fn bucket_of(secs: i64, resolution: Resolution) -> i64 {
+  secs - secs.rem_euclid(resolution_secs(resolution))
+}
+
+/// Resolution stored in the rollups' resolution column, in seconds.
+fn resolution_secs(resolution: Resolution) -> i64 {
+  resolution.duration().as_secs() as i64
+}
+
+/// Parses an optional column, failing the row if it's malformed.
+fn parse_column<T: std::str::FromStr>(row: &Row, index: usize) -> rusqlite::Result<Option<T>> {
+  let value: Option<String> = row.get(index)?;
+  value
+    .map(|v| {
+      v.parse().map_err(|_| {
+        rusqlite::Error::FromSqlConversionFailure(
+          index,
+          rusqlite::types::Type::Text,
+          format!("Malformed value '{}'", v).into(),
+        )
+      })
+    })
+    .transpose()
+}
+
+/// Parses a required column, failing the row if it's missing or malformed.
+fn parse_required<T: std::str::FromStr>(row: &Row, index: usize) -> rusqlite::Result<T> {
+  parse_column(row, index)?.ok_or(rusqlite::Error::InvalidColumnType(
+    index,
+    "NULL".to_string(),
+    rusqlite::types::Type::Null,
+  ))
+}
+
+///
+/// Storage backend recording the device history in a SQLite database: every
+/// poll's sightings, every change as an event, and the addresses each
+/// device used, indexed by MAC address and time so a device's history stays
+/// quick to read back.
+///
+/// ```
+/// use openwrt_netmon::storage::{HistoryQuery, SqliteStorage, Storage};
+///
+/// let storage = SqliteStorage::open_in_memory()?;
+/// assert!(storage.events(&HistoryQuery::new())?.is_empty());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+#[derive(Debug)]
+pub struct SqliteStorage {
+  path: PathBuf,
+  conn: Connection,
+}
+
+impl SqliteStorage {
+  ///
+  /// Opens a database, creating it along with its directory and tables.
+  ///
+  /// Args:
+  ///  - path: Database file.
+  ///
+  /// Returns:
+  ///  Result of the storage, failing if the database can't be opened.
+  ///
+  pub fn open(path: &Path) -> Result<Self> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| Error::msg(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+    let conn = Connection::open(path)
+      .map_err(|e| Error::msg(format!("Failed to open {}: {}", path.display(), e)))?;
+    SqliteStorage::init(conn, path)
+  }
+
+  /// Opens a database held in memory, e.g. for tests.
+  pub fn open_in_memory() -> Result<Self> {
+    let conn = Connection::open_in_memory()
+      .map_err(|e| Error::msg(format!("Failed to open the database: {}", e)))?;
+    SqliteStorage::init(conn, Path::new(":memory:"))
+  }
+
+  fn init(mut conn: Connection, path: &Path) -> Result<Self> {
+    // The write-ahead log spares flash a journal rewrite on every poll.
+    let journal_mode: String = conn
+      .query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))
+      .map_err(|e| Error::msg(format!("Failed to set up {}: {}", path.display(), e)))?;
+    migrate(&mut conn, path)?;
+    debug!("Opened {} (journal mode {})", path.display(), journal_mode);
+
+    Ok(SqliteStorage {
+      path: path.to_path_buf(),
+      conn,
+    })
+  }
+
+  /// Maps a database error onto one naming the database.
+  fn error(&self, action: &str, e: rusqlite::Error) -> Error {
+    Error::msg(format!(
+      "Failed to {} {}: {}",
+      action,
+      self.path.display(),
+      e
+    ))
+  }
+
+  ///
+  /// Records a poll in a single transaction: its sightings, its changes,
+  /// and the addresses of its devices.
+  ///
+  /// Args:
+  ///  - report: Poll to record.
+  ///
+  pub fn record(&mut self, report: &PollReport) -> Result<()> {
+    self.write_polls(&[PollRecords::new(report)])
+  }
+}
+
+impl PollWriter for SqliteStorage {
+  fn write_polls(&mut self, polls: &[PollRecords]) -> Result<()> {
+    let tx = self
+      .conn
+      .transaction()
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", self.path.display(), e)))?;
+    let written = (|| {
+      let mut insert_sighting = tx.prepare_cached(
+        "INSERT INTO sightings (time, mac, iface, state, online, signal_dbm, rx_bytes, tx_bytes)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+      )?;
+      let mut insert_event = tx.prepare_cached(
+        "INSERT INTO events (time, kind, mac, ip, iface, old_value, new_value)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+      )?;
+      let mut upsert_address = tx.prepare_cached(
+        "INSERT INTO addresses (mac, ip, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+         ON CONFLICT (mac, ip) DO UPDATE SET last_seen = excluded.last_seen",
+      )?;
+      for poll in polls {
+        let time = unix_secs(poll.time);
+        for sighting in &poll.sightings {
+          insert_sighting.execute(params![
+            time,
+            sighting.mac_addr.to_string(),
+            sighting.iface,
+            format_state(sighting.nud_state),
+            sighting.online,
+            sighting.signal_dbm,
+            sighting.rx_bytes.map(|v| v as i64),
+            sighting.tx_bytes.map(|v| v as i64),
+          ])?;
+        }
+        for event in &poll.events {
+          insert_event.execute(params![
+            time,
+            event.kind.name(),
+            event.mac_addr.map(|v| v.to_string()),
+            event.ip.as_ref().map(|v| v.to_string()),
+            event.iface,
+            event.old_value,
+            event.new_value,
+          ])?;
+        }
+        for (mac_addr, ip) in &poll.addresses {
+          upsert_address.execute(params![mac_addr.to_string(), ip.to_string(), time])?;
+        }
+      }
+      Ok(())
+    })();
+
+    written
+      .and_then(|_| tx.commit())
+      .map_err(|e| Error::msg(format!("Failed to write {}: {}", self.path.display(), e)))
+  }
+}
+
+///
+/// Copies a database into a new file, consistently even while the daemon
+/// writes to it, e.g. for a backup.
+///
+/// Args:
+///  - path: Database to copy.
+///  - dest: File to create, which must not exist yet.
+///
+pub fn snapshot(path: &Path, dest: &Path) -> Result<()> {
+  let dest_str = dest
+    .to_str()
+    .ok_or_else(|| Error::msg(format!("Invalid path {}", dest.display())))?;
+  // Not read-only, as reading a WAL database may create its shared memory.
+  let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+    .map_err(|e| Error::msg(format!("Failed to open {}: {}", path.display(), e)))?;
+  conn
+    .execute("VACUUM INTO ?1", [dest_str])
+    .map_err(|e| Error::msg(format!("Failed to copy {}: {}", path.display(), e)))?;
+  Ok(())
+}
+
+impl Sink for SqliteStorage {
+  fn name(&self) -> &str {
+    "sqlite"
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    self.record(report)
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    // Folds the write-ahead log back into the database file.
+    self
+      .conn
+      .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+      .map_err(|e| self.error("checkpoint", e))
+  }
+}
+
+impl Storage for SqliteStorage {
+  fn events(&self, query: &HistoryQuery) -> Result<Vec<HistoryEvent>> {
+    let (clause, values) = where_clause(query, "time", "time");
+    let sql = format!(
+      "SELECT time, kind, mac, ip, iface, old_value, new_value FROM events {} ORDER BY time, rowid",
+      clause
+    );
+    let read = || -> rusqlite::Result<Vec<HistoryEvent>> {
+      let mut statement = self.conn.prepare(&sql)?;
+      let rows = statement.query_map(params_from_iter(values), |row| {
+        Ok(HistoryEvent {
+          time: from_unix_secs(row.get(0)?),
+          kind: parse_required(row, 1)?,
+          mac_addr: parse_column(row, 2)?,
+          ip: parse_column(row, 3)?,
+          iface: row.get(4)?,
+          old_value: row.get(5)?,
+          new_value: row.get(6)?,
+        })
+      })?;
+      rows.collect()
+    };
+    read().map_err(|e| self.error("read", e))
+  }
+
+  fn sightings(&self, query: &HistoryQuery) -> Result<Vec<Sighting>> {
+    let (clause, values) = where_clause(query, "time", "time");
+    let sql = format!(
+      "SELECT time, mac, iface, state, online, signal_dbm, rx_bytes, tx_bytes FROM sightings {}
+       ORDER BY time, rowid",
+      clause
+    );
+    let read = || -> rusqlite::Result<Vec<Sighting>> {
+      let mut statement = self.conn.prepare(&sql)?;
+      let rows = statement.query_map(params_from_iter(values), |row| {
+        Ok(Sighting {
+          time: from_unix_secs(row.get(0)?),
+          mac_addr: parse_required(row, 1)?,
+          iface: row.get(2)?,
+          nud_state: parse_state(&row.get::<_, String>(3)?),
+          online: row.get(4)?,
+          signal_dbm: row.get(5)?,
+          rx_bytes: row.get::<_, Option<i64>>(6)?.map(|v| v as u64),
+          tx_bytes: row.get::<_, Option<i64>>(7)?.map(|v| v as u64),
+        })
+      })?;
+      rows.collect()
+    };
+    read().map_err(|e| self.error("read", e))
+  }
+
+  fn addresses(&self, query: &HistoryQuery) -> Result<Vec<AddressRecord>> {
+    let (clause, values) = where_clause(query, "first_seen", "last_seen");
+    let sql = format!(
+      "SELECT mac, ip, first_seen, last_seen FROM addresses {} ORDER BY last_seen DESC",
+      clause
+    );
+    let read = || -> rusqlite::Result<Vec<AddressRecord>> {
+      let mut statement = self.conn.prepare(&sql)?;
+      let rows = statement.query_map(params_from_iter(values), |row| {
+        Ok(AddressRecord {
+          mac_addr: parse_required(row, 0)?,
+          ip: parse_required(row, 1)?,
+          first_seen: from_unix_secs(row.get(2)?),
+          last_seen: from_unix_secs(row.get(3)?),
+        })
+      })?;
+      rows.collect()
+    };
+    read().map_err(|e| self.error("read", e))
+  }
+
+  fn prune(&mut self, retention: &Retention, now: SystemTime) -> Result<PruneStats> {
+    let events_before = retention.events_before(now).map(unix_secs);
+    let raw_polls_before = retention.raw_polls_before(now).map(unix_secs);
+    let tx = self
+      .conn
+      .transaction()
+      .map_err(|e| Error::msg(format!("Failed to prune {}: {}", self.path.display(), e)))?;
+    let pruned = (|| {
+      let mut stats = PruneStats::default();
+      // Deleted pages are reused by later polls, so the file stops growing.
+      if let Some(before) = events_before {
+        stats.events = tx.execute("DELETE FROM events WHERE time < ?1", [before])?;
+        stats.addresses = tx.execute("DELETE FROM addresses WHERE last_seen < ?1", [before])?;
+      }
+      // What's not rolled up yet is kept, however old.
+      if let Some(before) = raw_polls_before {
+        stats.sightings = tx.execute(
+          "DELETE FROM sightings WHERE time < MIN(?1, COALESCE(
+             (SELECT MAX(bucket) + 300 FROM rollups WHERE resolution = 300),
+             (SELECT MIN(time) FROM sightings)))",
+          [before],
+        )?;
+      }
+      if let Some(before) = retention.rollups_before(Resolution::FiveMinutes, now) {
+        stats.rollups += tx.execute(
+          "DELETE FROM rollups WHERE resolution = 300 AND bucket < MIN(?1, COALESCE(
+             (SELECT MAX(bucket) + 3600 FROM rollups WHERE resolution = 3600),
+             (SELECT MIN(bucket) FROM rollups WHERE resolution = 300)))",
+          [unix_secs(before)],
+        )?;
+      }
+      if let Some(before) = retention.rollups_before(Resolution::Hourly, now) {
+        stats.rollups += tx.execute(
+          "DELETE FROM rollups WHERE resolution = 3600 AND bucket < ?1",
+          [unix_secs(before)],
+        )?;
+      }
+      Ok(stats)
+    })();
+
+    pruned
+      .and_then(|stats| tx.commit().map(|_| stats))
+      .map_err(|e| Error::msg(format!("Failed to prune {}: {}", self.path.display(), e)))
+  }
+
+  fn downsample(&mut self, now: SystemTime) -> Result<usize> {
+    let tx = self
+      .conn
+      .transaction()
+      .map_err(|e| Error::msg(format!("Failed to roll up {}: {}", self.path.display(), e)))?;
+    let rolled_up = (|| {
+      // Buckets before `to` are complete, and those from `from` not rolled
+      // up yet, picking up where the previous run stopped.
+      let to = bucket_of(unix_secs(now), Resolution::FiveMinutes);
+      let from: Option<i64> = tx.query_row(
+        "SELECT COALESCE(
+           (SELECT MAX(bucket) + 300 FROM rollups WHERE resolution = 300),
+           (SELECT MIN(time) - MIN(time) % 300 FROM sightings))",
+        [],
+        |row| row.get(0),
+      )?;
+      let mut buckets = 0;
+      if let Some(from) = from.filter(|v| *v < to) {
+        buckets += tx.execute(ROLLUP_5M, [from, to])?;
+      }
+
+      let to = bucket_of(to, Resolution::Hourly);
+      let from: Option<i64> = tx.query_row(
+        "SELECT COALESCE(
+           (SELECT MAX(bucket) + 3600 FROM rollups WHERE resolution = 3600),
+           (SELECT MIN(bucket) - MIN(bucket) % 3600 FROM rollups WHERE resolution = 300))",
+        [],
+        |row| row.get(0),
+      )?;
+      if let Some(from) = from.filter(|v| *v < to) {
+        buckets += tx.execute(ROLLUP_HOURLY, [from, to])?;
+      }
+      Ok(buckets)
+    })();
+
+    rolled_up
+      .and_then(|buckets| tx.commit().map(|_| buckets))
+      .map_err(|e| Error::msg(format!("Failed to roll up {}: {}", self.path.display(), e)))
+  }
+
+  fn presence(&self, query: &HistoryQuery, resolution: Resolution) -> Result<Vec<PresenceSample>> {
+    let (clause, mut values) = where_clause(query, "bucket", "bucket");
+    let clause = match clause.is_empty() {
+      true => "WHERE resolution = ?".to_string(),
+      false => format!("{} AND resolution = ?", clause),
+    };
+    values.push(Value::Integer(resolution_secs(resolution)));
+    let sql = format!(
+      "SELECT bucket, mac, samples, online_samples, signal_min, signal_max, signal_sum,
+         signal_samples, rx_bytes, tx_bytes
+       FROM rollups {} ORDER BY bucket, mac",
+      clause
+    );
+    let read = || -> rusqlite::Result<Vec<PresenceSample>> {
+      let mut statement = self.conn.prepare(&sql)?;
+      let rows = statement.query_map(params_from_iter(values), |row| {
+        let signal_sum: Option<i64> = row.get(6)?;
+        let signal_samples: i64 = row.get(7)?;
+        Ok(PresenceSample {
+          time: from_unix_secs(row.get(0)?),
+          resolution,
+          mac_addr: parse_required(row, 1)?,
+          samples: row.get::<_, i64>(2)? as u64,
+          online_samples: row.get::<_, i64>(3)? as u64,
+          signal_min_dbm: row.get(4)?,
+          signal_avg_dbm: signal_sum
+            .filter(|_| signal_samples > 0)
+            .map(|v| v as f64 / signal_samples as f64),
+          signal_max_dbm: row.get(5)?,
+          rx_bytes: row.get::<_, Option<i64>>(8)?.map(|v| v as u64),
+          tx_bytes: row.get::<_, Option<i64>>(9)?.map(|v| v as u64),
+        })
+      })?;
+      rows.collect()
+    };
+    read().map_err(|e| self.error("read", e))
+  }
+}
@@ -0,0 +1,164 @@
+use super::{
+  AddressRecord, HistoryEvent, HistoryQuery, PollRecords, PollWriter, PresenceSample, PruneStats,
+  Resolution, Retention, Sighting, Storage,
+};
+use crate::daemon::{PollReport, Sink};
+use anyhow::Result;
+use log::{debug, warn};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long polls are held in memory before being written, when none is
+/// configured.
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Polls held in memory before they're written regardless of the flush
+/// interval, and while the backend can't be written to, before the oldest
+/// are dropped.
+pub const DEFAULT_MAX_PENDING: usize = 1000;
+
+///
+/// Wraps a storage backend's sink, holding the polls in memory and writing
+/// them in a single transaction once per flush interval, rather than on every
+/// poll, to spare the router's flash. Polls with a device joining, leaving,
+/// or changing its MAC address are flushed right away, as is everything held
+/// when the sink is flushed on shutdown or reload.
+///
+/// Each flush lands whole or not at all, so a crash or power loss leaves
+/// the history consistent, losing at most the polls of the last flush
+/// interval. A failed flush is retried with the next poll.
+///
+pub struct WriteBehind<S> {
+  storage: S,
+  pending: Vec<PollRecords>,
+  flush_interval: Duration,
+  flush_on_change: bool,
+  max_pending: usize,
+  flushed_at: Instant,
+}
+
+impl<S: Sink + Storage + PollWriter> WriteBehind<S> {
+  pub fn new(storage: S) -> Self {
+    WriteBehind {
+      storage,
+      pending: Vec::new(),
+      flush_interval: DEFAULT_FLUSH_INTERVAL,
+      flush_on_change: true,
+      max_pending: DEFAULT_MAX_PENDING,
+      flushed_at: Instant::now(),
+    }
+  }
+
+  /// Longest time polls are held before being written, every poll being
+  /// written right away if zero.
+  pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+    self.flush_interval = flush_interval;
+    self
+  }
+
+  /// Whether polls with a device joining, leaving, or changing its MAC
+  /// address are written right away.
+  pub fn flush_on_change(mut self, flush_on_change: bool) -> Self {
+    self.flush_on_change = flush_on_change;
+    self
+  }
+
+  /// Most polls held, written once reached, and kept while the backend
+  /// can't be written to, the oldest being dropped first.
+  pub fn max_pending(mut self, max_pending: usize) -> Self {
+    self.max_pending = max_pending.max(1);
+    self
+  }
+
+  /// The wrapped storage, e.g. to read the history back.
+  pub fn storage(&self) -> &S {
+    &self.storage
+  }
+
+  /// Writes every held poll, keeping them on failure to retry later.
+  fn write_pending(&mut self) -> Result<()> {
+    if self.pending.is_empty() {
+      return Ok(());
+    }
+    match self.storage.write_polls(&self.pending) {
+      Ok(()) => {
+        debug!(
+          "Wrote {} poll(s) to the {} history",
+          self.pending.len(),
+          self.storage.name()
+        );
+        self.pending.clear();
+        self.flushed_at = Instant::now();
+        Ok(())
+      }
+      Err(err) => {
+        if self.pending.len() > self.max_pending {
+          let dropped = self.pending.len() - self.max_pending;
+          warn!(
+            "Dropping {} poll(s) the {} history couldn't be written with",
+            dropped,
+            self.storage.name()
+          );
+          self.pending.drain(..dropped);
+        }
+        Err(err)
+      }
+    }
+  }
+}
+
+/// Whether a poll changed which devices are around.
+fn is_significant(report: &PollReport) -> bool {
+  let diff = &report.diff;
+  !diff.joined.is_empty() || !diff.left.is_empty() || !diff.mac_changed.is_empty()
+}
+
+impl<S: Sink + Storage + PollWriter> Sink for WriteBehind<S> {
+  fn name(&self) -> &str {
+    self.storage.name()
+  }
+
+  fn publish(&mut self, report: &PollReport) -> Result<()> {
+    self.pending.push(PollRecords::new(report));
+    let due = self.flushed_at.elapsed() >= self.flush_interval
+      || self.pending.len() >= self.max_pending
+      || (self.flush_on_change && is_significant(report));
+    match due {
+      true => self.write_pending(),
+      false => Ok(()),
+    }
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    self.write_pending()?;
+    self.storage.flush()
+  }
+}
+
+/// Reads see the written history only, without the polls still held.
+impl<S: Sink + Storage + PollWriter> Storage for WriteBehind<S> {
+  fn events(&self, query: &HistoryQuery) -> Result<Vec<HistoryEvent>> {
+    self.storage.events(query)
+  }
+
+  fn sightings(&self, query: &HistoryQuery) -> Result<Vec<Sighting>> {
+    self.storage.sightings(query)
+  }
+
+  fn addresses(&self, query: &HistoryQuery) -> Result<Vec<AddressRecord>> {
+    self.storage.addresses(query)
+  }
+
+  fn prune(&mut self, retention: &Retention, now: SystemTime) -> Result<PruneStats> {
+    self.storage.prune(retention, now)
+  }
+
+  fn downsample(&mut self, now: SystemTime) -> Result<usize> {
+    // Buckets rolled up aren't rolled up again, so the polls held go first.
+    self.write_pending()?;
+    self.storage.downsample(now)
+  }
+
+  fn presence(&self, query: &HistoryQuery, resolution: Resolution) -> Result<Vec<PresenceSample>> {
+    self.storage.presence(query, resolution)
+  }
+}
@@ -0,0 +1,164 @@
+use crate::device::Device;
+use crate::neighbors::{ArpTable, MacAddr, NudState, ScopedIpAddr};
+use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+/// History of a single device, keyed by its MAC address.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackedNeighbor {
+  pub mac_addr: MacAddr,
+  pub ips: Vec<ScopedIpAddr>,
+  pub iface: String,
+  pub nud_state: NudState,
+  pub online: bool,
+  pub first_seen: SystemTime,
+  pub last_seen: SystemTime,
+  pub last_state_change: SystemTime,
+}
+
+///
+/// Merges successive neighbor table snapshots, keeping track of when each
+/// device was first and last seen, and when its state last changed.
+///
+#[derive(Debug, Clone, Default)]
+pub struct NeighborTracker {
+  neighbors: HashMap<MacAddr, TrackedNeighbor>,
+  /// Devices kept, evicting the offline ones seen the longest ago.
+  max_neighbors: Option<usize>,
+}
+
+impl NeighborTracker {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Caps the devices kept, unlimited if None. Online devices are never evicted.
+  pub fn set_max_neighbors(&mut self, max_neighbors: Option<usize>) {
+    self.max_neighbors = max_neighbors;
+    self.evict();
+  }
+
+  /// Forgets the offline devices seen the longest ago, down to the cap.
+  fn evict(&mut self) {
+    let Some(max_neighbors) = self.max_neighbors else {
+      return;
+    };
+    if self.neighbors.len() <= max_neighbors {
+      return;
+    }
+
+    let mut offline: Vec<(SystemTime, MacAddr)> = self
+      .neighbors
+      .values()
+      .filter(|v| !v.online)
+      .map(|v| (v.last_seen, v.mac_addr))
+      .collect();
+    offline.sort();
+    let excess = self.neighbors.len() - max_neighbors;
+    for (_, mac_addr) in offline.into_iter().take(excess) {
+      debug!(
+        "Forgetting {}, as {} devices are tracked",
+        mac_addr, max_neighbors
+      );
+      self.neighbors.remove(&mac_addr);
+    }
+  }
+
+  /// Merges a snapshot taken just now.
+  pub fn update(&mut self, entries: &[ArpTable]) {
+    self.update_at(entries, SystemTime::now())
+  }
+
+  ///
+  /// Merges a snapshot into the tracked state.
+  ///
+  /// Entries without a MAC address can't be attributed to a device and are
+  /// skipped. Devices missing from the snapshot are marked offline.
+  ///
+  /// Args:
+  ///  - entries: Neighbor table snapshot.
+  ///  - now: Time the snapshot was taken.
+  ///
+  pub fn update_at(&mut self, entries: &[ArpTable], now: SystemTime) {
+    let devices = Device::group(entries);
+    let snapshot: HashSet<MacAddr> = devices.iter().map(|v| v.mac_addr).collect();
+
+    for device in devices {
+      let mac_addr = &device.mac_addr;
+      let online = device.online;
+      let ips: Vec<ScopedIpAddr> = device.ips().cloned().collect();
+
+      match self.neighbors.get_mut(mac_addr) {
+        Some(tracked) => {
+          if tracked.nud_state != device.nud_state || tracked.online != online {
+            debug!(
+              "{} changed state {:?} -> {:?}",
+              mac_addr, tracked.nud_state, device.nud_state
+            );
+            if tracked.online != online {
+              info!(
+                "device.mac":% = mac_addr,
+                "event.kind" = if online { "online" } else { "offline" };
+                "{} is now {}",
+                mac_addr,
+                if online { "online" } else { "offline" }
+              );
+            }
+            tracked.last_state_change = now;
+          }
+          if online {
+            tracked.last_seen = now;
+          }
+          tracked.ips = ips;
+          tracked.iface = device.iface.clone();
+          tracked.nud_state = device.nud_state;
+          tracked.online = online;
+        }
+        None => {
+          debug!(
+            "device.mac":% = mac_addr, "event.kind" = "joined";
+            "{} joined on {}", mac_addr, device.iface
+          );
+          self.neighbors.insert(
+            *mac_addr,
+            TrackedNeighbor {
+              mac_addr: *mac_addr,
+              ips,
+              iface: device.iface.clone(),
+              nud_state: device.nud_state,
+              online,
+              first_seen: now,
+              last_seen: now,
+              last_state_change: now,
+            },
+          );
+        }
+      }
+    }
+
+    // Anything no longer in the table has left.
+    for tracked in self.neighbors.values_mut() {
+      if tracked.online && !snapshot.contains(&tracked.mac_addr) {
+        debug!(
+          "device.mac":% = tracked.mac_addr, "event.kind" = "left";
+          "{} left {}", tracked.mac_addr, tracked.iface
+        );
+        tracked.online = false;
+        tracked.last_state_change = now;
+      }
+    }
+    self.evict();
+  }
+
+  /// Returns the tracked history of a single device.
+  pub fn get(&self, mac_addr: &MacAddr) -> Option<&TrackedNeighbor> {
+    self.neighbors.get(mac_addr)
+  }
+
+  /// Iterates every device seen so far.
+  pub fn neighbors(&self) -> impl Iterator<Item = &TrackedNeighbor> {
+    self.neighbors.values()
+  }
+}
@@ -0,0 +1,241 @@
+use crate::counters;
+use crate::device::Device;
+use crate::neighbors::{CommandRunner, MacAddr, SystemRunner};
+use anyhow::Result;
+use log::{debug, warn};
+use std::str::FromStr;
+use std::time::Duration;
+
+/*
+  `iw dev` lists the wireless interfaces as:
+
+    phy#0
+      Interface phy0-ap0
+        ifindex 12
+        type AP
+
+  And `iw dev <iface> station dump` the stations associated to each:
+
+    Station dc:a6:32:57:46:d6 (on phy0-ap0)
+      inactive time:	1230 ms
+      rx bytes:	123456
+      tx bytes:	654321
+      signal:  	-52 [-54, -56] dBm
+      tx bitrate:	144.4 MBit/s MCS 15 short GI
+      rx bitrate:	130.0 MBit/s MCS 15
+      connected time:	3600 seconds
+*/
+
+/// A client associated to one of the router's access points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Station {
+  pub mac_addr: MacAddr,
+  /// Wireless interface the station is associated to.
+  pub iface: String,
+  /// Signal strength, in dBm.
+  pub signal_dbm: Option<i32>,
+  /// Time since the station was last heard from.
+  pub inactive: Option<Duration>,
+  /// Time since the station associated.
+  pub connected: Option<Duration>,
+  pub rx_bytes: Option<u64>,
+  pub tx_bytes: Option<u64>,
+  /// Transmit bitrate, in kbit/s.
+  pub tx_bitrate_kbps: Option<u64>,
+  /// Receive bitrate, in kbit/s.
+  pub rx_bitrate_kbps: Option<u64>,
+}
+
+impl Station {
+  fn new(mac_addr: MacAddr, iface: &str) -> Self {
+    Station {
+      mac_addr,
+      iface: iface.to_string(),
+      signal_dbm: None,
+      inactive: None,
+      connected: None,
+      rx_bytes: None,
+      tx_bytes: None,
+      tx_bitrate_kbps: None,
+      rx_bitrate_kbps: None,
+    }
+  }
+}
+
+/// Parses a bitrate such as "144.4 MBit/s MCS 15" into kbit/s.
+fn parse_bitrate_kbps(value: &str) -> Option<u64> {
+  let mbps: f64 = value.split_whitespace().next()?.parse().ok()?;
+  Some((mbps * 1000.0).round() as u64)
+}
+
+/// Parses the leading integer of a value such as "-52 [-54, -56] dBm".
+fn parse_leading<T: FromStr>(value: &str) -> Option<T> {
+  value.split_whitespace().next()?.parse().ok()
+}
+
+///
+/// Parses the wireless interfaces out of `iw dev` output.
+///
+/// Args:
+///  - stdout: Output of `iw dev`.
+///
+/// Returns:
+///  The interface names.
+///
+pub fn parse_iw_dev_output(stdout: &str) -> Vec<String> {
+  stdout
+    .lines()
+    .filter_map(|v| v.trim().strip_prefix("Interface "))
+    .map(|v| v.trim().to_string())
+    .collect()
+}
+
+///
+/// Parses the stations out of `iw dev <iface> station dump` output.
+///
+/// ```
+/// use openwrt_netmon::wireless::parse_station_dump;
+/// use std::time::Duration;
+///
+/// let stations = parse_station_dump("phy0-ap0", "\
+/// Station dc:a6:32:57:46:d6 (on phy0-ap0)
+/// \tinactive time:\t1230 ms
+/// \tsignal:  \t-52 [-54, -56] dBm
+/// \ttx bitrate:\t144.4 MBit/s MCS 15 short GI
+/// \tconnected time:\t3600 seconds
+/// ");
+/// assert_eq!(stations.len(), 1);
+/// assert_eq!(stations[0].signal_dbm, Some(-52));
+/// assert_eq!(stations[0].inactive, Some(Duration::from_millis(1230)));
+/// assert_eq!(stations[0].tx_bitrate_kbps, Some(144_400));
+/// ```
+///
+/// Args:
+///  - iface: Interface the dump was taken on.
+///  - stdout: Output of the command.
+///
+/// Returns:
+///  The stations, skipping the ones that failed to parse.
+///
+pub fn parse_station_dump(iface: &str, stdout: &str) -> Vec<Station> {
+  let mut stations: Vec<Station> = Vec::new();
+
+  for line in stdout.lines() {
+    if let Some(rest) = line.strip_prefix("Station ") {
+      let mac_str = rest.split_whitespace().next().unwrap_or_default();
+      match MacAddr::from_str(mac_str) {
+        Ok(mac_addr) => stations.push(Station::new(mac_addr, iface)),
+        Err(e) => {
+          warn!("Skipping station '{}': {}", mac_str, e);
+          counters::record_parse_error();
+        }
+      }
+      continue;
+    }
+
+    let (Some(station), Some((key, value))) = (stations.last_mut(), line.split_once(':')) else {
+      continue;
+    };
+    let value = value.trim();
+    match key.trim() {
+      "signal" => station.signal_dbm = parse_leading(value),
+      "inactive time" => station.inactive = parse_leading(value).map(Duration::from_millis),
+      "connected time" => station.connected = parse_leading(value).map(Duration::from_secs),
+      "rx bytes" => station.rx_bytes = parse_leading(value),
+      "tx bytes" => station.tx_bytes = parse_leading(value),
+      "tx bitrate" => station.tx_bitrate_kbps = parse_bitrate_kbps(value),
+      "rx bitrate" => station.rx_bitrate_kbps = parse_bitrate_kbps(value),
+      _ => {}
+    }
+  }
+
+  stations
+}
+
+///
+/// Collects the stations of every wireless interface with `iw`.
+///
+/// Returns:
+///  Result of the stations, empty on routers without wireless interfaces.
+///
+pub fn get_stations() -> Result<Vec<Station>> {
+  get_stations_with(&SystemRunner::new())
+}
+
+///
+/// Collects the stations of every wireless interface, running `iw` through
+/// the given runner.
+///
+/// Args:
+///  - runner: Runs the `iw` commands.
+///
+/// Returns:
+///  Result of the stations, empty on routers without wireless interfaces.
+///
+pub fn get_stations_with(runner: &dyn CommandRunner) -> Result<Vec<Station>> {
+  let ifaces = parse_iw_dev_output(&runner.run_args("iw", &["dev"])?);
+
+  let mut stations = Vec::new();
+  for iface in &ifaces {
+    match runner.run_args("iw", &["dev", iface, "station", "dump"]) {
+      Ok(stdout) => stations.extend(parse_station_dump(iface, &stdout)),
+      Err(err) => debug!("Skipping stations of {}: {}", iface, err),
+    }
+  }
+  Ok(stations)
+}
+
+///
+/// Collects the stations of every wireless interface with `iw`, without
+/// blocking the runtime.
+///
+/// Args:
+///  - timeout: Longest time each `iw` command may run for.
+///
+/// Returns:
+///  Result of the stations, empty on routers without wireless interfaces.
+///
+#[cfg(feature = "daemon")]
+pub async fn get_stations_async(timeout: Duration) -> Result<Vec<Station>> {
+  use crate::neighbors::run_command_async;
+
+  let ifaces = parse_iw_dev_output(&run_command_async("iw", &["dev"], timeout).await?);
+
+  // Dump every interface concurrently, then collect them in order.
+  let dumps: Vec<_> = ifaces
+    .into_iter()
+    .map(|iface| {
+      tokio::spawn(async move {
+        let stdout = run_command_async("iw", &["dev", &iface, "station", "dump"], timeout).await;
+        (iface, stdout)
+      })
+    })
+    .collect();
+
+  let mut stations = Vec::new();
+  for dump in dumps {
+    match dump.await {
+      Ok((iface, Ok(stdout))) => stations.extend(parse_station_dump(&iface, &stdout)),
+      Ok((iface, Err(err))) => debug!("Skipping stations of {}: {}", iface, err),
+      Err(err) => warn!("Station dump task failed: {}", err),
+    }
+  }
+  Ok(stations)
+}
+
+///
+/// Attaches each device's station, if it's a wireless client.
+///
+/// Args:
+///  - devices: Devices to attach stations to.
+///  - stations: Stations of every wireless interface.
+///
+pub fn join_stations(devices: &mut [Device], stations: &[Station]) {
+  for device in devices.iter_mut() {
+    device.station = stations
+      .iter()
+      .find(|v| v.mac_addr == device.mac_addr)
+      .cloned();
+  }
+}